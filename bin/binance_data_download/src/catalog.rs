@@ -0,0 +1,345 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    path::Path,
+};
+
+use anyhow::Context;
+use chrono::{Datelike, NaiveDate};
+use serde_json::{json, Value};
+
+// The days of one product of a symbol, e.g. BTCUSDT trades
+#[derive(Debug, Default)]
+struct Dataset {
+    // days of an intact daily file or an intact monthly file
+    present: BTreeSet<NaiveDate>,
+    // (file name, problem) of the files which can not be read
+    corrupted: Vec<(String, String)>,
+}
+
+// why the zip at path can not be read, None when it can
+fn problem(path: &Path) -> Option<String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Some(e.to_string()),
+    };
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(e) => return Some(format!("corrupted zip, {e}")),
+    };
+    if archive.len() != 1 {
+        return Some(format!("zip holds {} files, expected 1", archive.len()));
+    }
+    archive
+        .by_index(0)
+        .err()
+        .map(|e| format!("corrupted zip, {e}"))
+}
+
+// the days of a file named after a day or a month
+fn file_days(stem: &str) -> Option<Vec<NaiveDate>> {
+    if let Ok(date) = NaiveDate::parse_from_str(stem, "%Y-%m-%d") {
+        return Some(vec![date]);
+    }
+    let first = NaiveDate::parse_from_str(&format!("{stem}-01"), "%Y-%m-%d").ok()?;
+    Some(
+        first
+            .iter_days()
+            .take_while(|date| date.month() == first.month())
+            .collect(),
+    )
+}
+
+// symbol -> product directory -> dataset, of the zip files below root_path/future_um
+fn scan(root_path: &Path) -> Result<BTreeMap<String, BTreeMap<String, Dataset>>, anyhow::Error> {
+    let market_path = root_path.join("future_um");
+    let mut catalog = BTreeMap::new();
+    let dirs = |path: &Path| -> Result<Vec<(String, std::path::PathBuf)>, anyhow::Error> {
+        let mut dirs = vec![];
+        for entry in
+            std::fs::read_dir(path).with_context(|| format!("failed to read {}", path.display()))?
+        {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push((
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                ));
+            }
+        }
+        Ok(dirs)
+    };
+    for (symbol, symbol_path) in dirs(&market_path)? {
+        let products: &mut BTreeMap<String, Dataset> = catalog.entry(symbol).or_default();
+        for (product, product_path) in dirs(&symbol_path)? {
+            let mut dataset = Dataset::default();
+            for entry in std::fs::read_dir(&product_path)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "zip") {
+                    continue;
+                }
+                let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+                let Some(days) = file_days(&stem) else {
+                    continue;
+                };
+                match problem(&path) {
+                    Some(problem) => dataset.corrupted.push((
+                        path.file_name().unwrap().to_string_lossy().to_string(),
+                        problem,
+                    )),
+                    None => dataset.present.extend(days),
+                }
+            }
+            dataset.corrupted.sort();
+            // parquet directories and the like hold no zip files
+            if !dataset.present.is_empty() || !dataset.corrupted.is_empty() {
+                products.insert(product, dataset);
+            }
+        }
+    }
+    catalog.retain(|_, products| !products.is_empty());
+    Ok(catalog)
+}
+
+// the first and the last of consecutive days
+type DateRange = (NaiveDate, NaiveDate);
+
+// consecutive days as (first, last)
+fn date_ranges<'a>(dates: impl IntoIterator<Item = &'a NaiveDate>) -> Vec<DateRange> {
+    let mut ranges: Vec<DateRange> = vec![];
+    for &date in dates {
+        match ranges.last_mut() {
+            Some((_, last)) if last.succ_opt() == Some(date) => *last = date,
+            _ => ranges.push((date, date)),
+        }
+    }
+    ranges
+}
+
+fn ranges_str(ranges: &[(NaiveDate, NaiveDate)]) -> String {
+    if ranges.is_empty() {
+        return "-".to_string();
+    }
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}..{last}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn ranges_json(ranges: &[(NaiveDate, NaiveDate)]) -> Value {
+    ranges
+        .iter()
+        .map(|(first, last)| json!([first.to_string(), last.to_string()]))
+        .collect()
+}
+
+// the days of the dataset (present, missing), the missing ones of date_range or else the gaps
+// between the first and the last day present
+fn coverage(
+    dataset: &Dataset,
+    date_range: Option<&[NaiveDate]>,
+) -> (Vec<DateRange>, Vec<DateRange>) {
+    let expected: Vec<NaiveDate> = match date_range {
+        Some(dates) => dates.to_vec(),
+        None => match (dataset.present.first(), dataset.present.last()) {
+            (Some(first), Some(last)) => {
+                first.iter_days().take_while(|date| date <= last).collect()
+            }
+            _ => vec![],
+        },
+    };
+    let present = date_ranges(
+        dataset
+            .present
+            .iter()
+            .filter(|date| date_range.is_none_or(|dates| dates.contains(date))),
+    );
+    let missing = date_ranges(
+        expected
+            .iter()
+            .filter(|date| !dataset.present.contains(date)),
+    );
+    (present, missing)
+}
+
+// the days of date_range with both trades and bookticker present
+fn backtestable(
+    products: &BTreeMap<String, Dataset>,
+    date_range: Option<&[NaiveDate]>,
+) -> Vec<DateRange> {
+    match (products.get("trades"), products.get("bookticker")) {
+        (Some(trades), Some(bookticker)) => date_ranges(
+            trades
+                .present
+                .intersection(&bookticker.present)
+                .filter(|date| date_range.is_none_or(|dates| dates.contains(date))),
+        ),
+        _ => vec![],
+    }
+}
+
+// Lists the datasets below root_path, per symbol and product the days present, missing and
+// the corrupted files. Missing days are the ones of date_range, or the gaps between the first
+// and the last day present without one. The days with both trades and bookticker are the
+// backtestable ones.
+pub fn process_catalog_command(
+    root_path: &Path,
+    date_range: Option<&[NaiveDate]>,
+    as_json: bool,
+) -> Result<(), anyhow::Error> {
+    let catalog = scan(root_path)?;
+    let mut symbols_json = serde_json::Map::new();
+    for (symbol, products) in &catalog {
+        if !as_json {
+            println!("{symbol}");
+        }
+        let mut products_json = serde_json::Map::new();
+        for (product, dataset) in products {
+            let (present, missing) = coverage(dataset, date_range);
+            if as_json {
+                products_json.insert(
+                    product.clone(),
+                    json!({
+                        "present": ranges_json(&present),
+                        "missing": ranges_json(&missing),
+                        "corrupted": dataset
+                            .corrupted
+                            .iter()
+                            .map(|(file, problem)| json!({"file": file, "problem": problem}))
+                            .collect::<Vec<_>>(),
+                    }),
+                );
+                continue;
+            }
+            println!("  {product}");
+            println!("    present: {}", ranges_str(&present));
+            println!("    missing: {}", ranges_str(&missing));
+            for (file, problem) in &dataset.corrupted {
+                println!("    corrupted: {file}: {problem}");
+            }
+        }
+        let backtestable = backtestable(products, date_range);
+        if as_json {
+            symbols_json.insert(
+                symbol.clone(),
+                json!({
+                    "products": products_json,
+                    "backtestable": ranges_json(&backtestable),
+                }),
+            );
+        } else {
+            println!("  backtestable: {}", ranges_str(&backtestable));
+        }
+    }
+    if as_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&Value::Object(symbols_json))?
+        );
+    } else if catalog.is_empty() {
+        println!(
+            "no datasets below {}",
+            root_path.join("future_um").display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn write_zip(path: &Path, files: usize) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for i in 0..files {
+            zip.start_file(format!("{i}.csv"), zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"1,2,3\n").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn range(first: &str, last: &str) -> (NaiveDate, NaiveDate) {
+        (date(first), date(last))
+    }
+
+    #[test]
+    fn test_file_days() {
+        assert_eq!(file_days("2024-02-03"), Some(vec![date("2024-02-03")]));
+        let february = file_days("2024-02").unwrap();
+        assert_eq!(february.len(), 29);
+        assert_eq!(february.last(), Some(&date("2024-02-29")));
+        assert_eq!(file_days("latest"), None);
+    }
+
+    #[test]
+    fn test_catalog_coverage() {
+        let root = std::env::temp_dir().join(format!("catalog_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let symbol = root.join("future_um").join("BTCUSDT");
+        for day in ["2024-01-30", "2024-02-02", "2024-02-03"] {
+            write_zip(&symbol.join("trades").join(format!("{day}.zip")), 1);
+        }
+        write_zip(&symbol.join("bookticker").join("2024-02.zip"), 1);
+        write_zip(&symbol.join("bookticker").join("2024-01-31.zip"), 2);
+        std::fs::write(symbol.join("bookticker").join("2024-01-30.zip"), b"PK").unwrap();
+        // no zip files, left out
+        std::fs::create_dir_all(symbol.join("parquet")).unwrap();
+        std::fs::create_dir_all(root.join("future_um").join("ETHUSDT").join("trades")).unwrap();
+
+        let catalog = scan(&root).unwrap();
+        assert_eq!(catalog.keys().collect::<Vec<_>>(), vec!["BTCUSDT"]);
+        let products = &catalog["BTCUSDT"];
+        assert_eq!(
+            products.keys().collect::<Vec<_>>(),
+            vec!["bookticker", "trades"]
+        );
+
+        let (present, missing) = coverage(&products["trades"], None);
+        assert_eq!(
+            present,
+            vec![
+                range("2024-01-30", "2024-01-30"),
+                range("2024-02-02", "2024-02-03")
+            ]
+        );
+        assert_eq!(missing, vec![range("2024-01-31", "2024-02-01")]);
+
+        let bookticker = &products["bookticker"];
+        let corrupted: Vec<&str> = bookticker
+            .corrupted
+            .iter()
+            .map(|(file, _)| file.as_str())
+            .collect();
+        assert_eq!(corrupted, vec!["2024-01-30.zip", "2024-01-31.zip"]);
+        assert_eq!(bookticker.corrupted[1].1, "zip holds 2 files, expected 1");
+        let (present, missing) = coverage(bookticker, None);
+        assert_eq!(present, vec![range("2024-02-01", "2024-02-29")]);
+        assert!(missing.is_empty());
+
+        // the requested days missing from the ones present
+        let dates: Vec<NaiveDate> = date("2024-01-29").iter_days().take(6).collect();
+        let (present, missing) = coverage(bookticker, Some(&dates));
+        assert_eq!(present, vec![range("2024-02-01", "2024-02-03")]);
+        assert_eq!(missing, vec![range("2024-01-29", "2024-01-31")]);
+        assert_eq!(
+            backtestable(products, Some(&dates)),
+            vec![range("2024-02-02", "2024-02-03")]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -10,7 +10,7 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::Client;
 use tokio::{fs::File, io::AsyncWriteExt, sync::Semaphore};
 
-use crate::get_url::{self, BinanceBizType, DataProductName};
+use crate::get_url::{self, BinanceBizType, DataProductName, Granularity};
 
 #[derive(Debug)]
 pub struct DownloadTask {
@@ -63,40 +63,37 @@ impl DownloadTask {
     }
 }
 
+// a file per period, the monthly files are named after the month, e.g. trades/2024-01.zip
 fn generate_future_download_tasks(
     date_range: &[NaiveDate],
     symbol: &str,
     root_path: &Path,
+    products: &[DataProductName],
+    granularity: Granularity,
 ) -> Vec<DownloadTask> {
-    date_range
-        .iter()
-        .flat_map(|date| {
-            let date_str = date.format("%Y-%m-%d").to_string();
-            let trade_url = get_url::get_data_url(
-                symbol,
-                BinanceBizType::FutureUm,
-                DataProductName::Trades,
-                &date_str,
-            );
-            let bookticker_url = get_url::get_data_url(
-                symbol,
-                BinanceBizType::FutureUm,
-                DataProductName::BookTicker,
-                &date_str,
-            );
-            println!("trade_url: {}", trade_url);
-            println!("bookticker_url: {}", bookticker_url);
-            [
-                DownloadTask {
-                    uri: trade_url,
-                    path: root_path.join(format!("future_um/{}/trades/{}.zip", symbol, date_str)),
-                },
+    granularity
+        .periods(date_range)
+        .into_iter()
+        .flat_map(|date_str| {
+            products.iter().map(move |product| {
+                let url = get_url::get_data_url(
+                    symbol,
+                    BinanceBizType::FutureUm,
+                    granularity,
+                    product.clone(),
+                    &date_str,
+                );
+                println!("{}_url: {}", product.dir_name(), url);
                 DownloadTask {
-                    uri: bookticker_url,
-                    path: root_path
-                        .join(format!("future_um/{}/bookticker/{}.zip", symbol, date_str)),
-                },
-            ]
+                    uri: url,
+                    path: root_path.join(format!(
+                        "future_um/{}/{}/{}.zip",
+                        symbol,
+                        product.dir_name(),
+                        date_str
+                    )),
+                }
+            })
         })
         .collect()
 }
@@ -106,10 +103,13 @@ pub async fn process_download_command(
     symbol: &str,
     root_path: &Path,
     max_task: usize,
+    products: &[DataProductName],
+    granularity: Granularity,
 ) {
     let mp = Arc::new(MultiProgress::new());
     let max_task_semaphore = Arc::new(Semaphore::new(max_task));
-    let tasks = generate_future_download_tasks(date_range, symbol, root_path);
+    let tasks =
+        generate_future_download_tasks(date_range, symbol, root_path, products, granularity);
     let handles = tasks
         .into_iter()
         .filter(|task| task.need_download())
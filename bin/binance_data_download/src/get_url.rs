@@ -1,3 +1,5 @@
+use chrono::NaiveDate;
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum BinanceBizType {
     #[allow(dead_code)]
@@ -6,18 +8,76 @@ pub enum BinanceBizType {
 }
 
 impl BinanceBizType {
-    pub fn base_url(&self) -> &'static str {
+    pub fn base_url(&self, granularity: Granularity) -> String {
+        let market = match self {
+            BinanceBizType::Spot => "spot",
+            BinanceBizType::FutureUm => "futures/um",
+        };
+        format!(
+            "https://data.binance.vision/data/{}/{}",
+            market,
+            granularity.to_str()
+        )
+    }
+}
+
+// Binance publishes a zip per day and per month, the monthly ones are far faster to fetch
+// for long ranges
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Granularity {
+    #[default]
+    Daily,
+    Monthly,
+}
+
+impl Granularity {
+    fn to_str(self) -> &'static str {
         match self {
-            BinanceBizType::Spot => "https://data.binance.vision/data/spot/daily",
-            BinanceBizType::FutureUm => "https://data.binance.vision/data/futures/um/daily",
+            Granularity::Daily => "daily",
+            Granularity::Monthly => "monthly",
+        }
+    }
+
+    // the period of the file holding date, e.g. 2024-01-31 or 2024-01
+    pub fn period(self, date: &NaiveDate) -> String {
+        match self {
+            Granularity::Daily => date.format("%Y-%m-%d").to_string(),
+            Granularity::Monthly => date.format("%Y-%m").to_string(),
+        }
+    }
+
+    // the periods of the files covering the dates, in order
+    pub fn periods(self, dates: &[NaiveDate]) -> Vec<String> {
+        let mut periods: Vec<String> = dates.iter().map(|date| self.period(date)).collect();
+        periods.dedup();
+        periods
+    }
+}
+
+impl std::str::FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "daily" => Ok(Granularity::Daily),
+            "monthly" => Ok(Granularity::Monthly),
+            _ => Err(format!(
+                "unknown granularity {s}, expected daily or monthly"
+            )),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum DataProductName {
     Trades,
     BookTicker,
+    AggTrades,
+    // candles of the interval, e.g. 1m or 1h
+    Klines(String),
+    // candles of the mark price and of the index price of the futures, of the interval
+    MarkPriceKlines(String),
+    IndexPriceKlines(String),
 }
 
 impl Default for DataProductName {
@@ -31,6 +91,59 @@ impl DataProductName {
         match self {
             DataProductName::Trades => "trades",
             DataProductName::BookTicker => "bookTicker",
+            DataProductName::AggTrades => "aggTrades",
+            DataProductName::Klines(_) => "klines",
+            DataProductName::MarkPriceKlines(_) => "markPriceKlines",
+            DataProductName::IndexPriceKlines(_) => "indexPriceKlines",
+        }
+    }
+
+    // directory of the files below the symbol directory, where sim looks for them
+    pub fn dir_name(&self) -> String {
+        match self {
+            DataProductName::Trades => "trades".to_string(),
+            DataProductName::BookTicker => "bookticker".to_string(),
+            DataProductName::AggTrades => "agg_trades".to_string(),
+            DataProductName::Klines(interval) => format!("klines_{}", interval),
+            DataProductName::MarkPriceKlines(interval) => {
+                format!("mark_price_klines_{}", interval)
+            }
+            DataProductName::IndexPriceKlines(interval) => {
+                format!("index_price_klines_{}", interval)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for DataProductName {
+    type Err = String;
+
+    // trades, bookticker, agg-trades, klines-<interval>, mark-price-klines-<interval> or
+    // index-price-klines-<interval>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        let interval = |prefix: &str| {
+            s.strip_prefix(prefix)
+                .filter(|interval| !interval.is_empty())
+                .map(str::to_string)
+        };
+        match s.as_str() {
+            "trades" => Ok(DataProductName::Trades),
+            "bookticker" => Ok(DataProductName::BookTicker),
+            "agg-trades" | "aggtrades" => Ok(DataProductName::AggTrades),
+            _ => {
+                if let Some(interval) = interval("klines-") {
+                    Ok(DataProductName::Klines(interval))
+                } else if let Some(interval) = interval("mark-price-klines-") {
+                    Ok(DataProductName::MarkPriceKlines(interval))
+                } else if let Some(interval) = interval("index-price-klines-") {
+                    Ok(DataProductName::IndexPriceKlines(interval))
+                } else {
+                    Err(format!(
+                        "unknown data product {s}, expected trades, bookticker, agg-trades, klines-<interval>, mark-price-klines-<interval> or index-price-klines-<interval>"
+                    ))
+                }
+            }
         }
     }
 }
@@ -38,12 +151,74 @@ impl DataProductName {
 pub fn get_data_url(
     symbol: &str,
     biz_type: BinanceBizType,
+    granularity: Granularity,
     product_name: DataProductName,
     date_str: &str,
 ) -> String {
-    let base_url = biz_type.base_url();
+    let base_url = biz_type.base_url(granularity);
     let product_name_str = product_name.to_str();
-    let file_name = format!("{}-{}-{}.zip", symbol, product_name_str, date_str);
+    match &product_name {
+        // klines are in a directory per interval and named after it
+        DataProductName::Klines(interval)
+        | DataProductName::MarkPriceKlines(interval)
+        | DataProductName::IndexPriceKlines(interval) => format!(
+            "{}/{}/{}/{}/{}-{}-{}.zip",
+            base_url, product_name_str, symbol, interval, symbol, interval, date_str
+        ),
+        _ => {
+            let file_name = format!("{}-{}-{}.zip", symbol, product_name_str, date_str);
+            format!("{}/{}/{}/{}", base_url, product_name_str, symbol, file_name)
+        }
+    }
+}
 
-    format!("{}/{}/{}/{}", base_url, product_name_str, symbol, file_name)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_url() {
+        assert_eq!(
+            get_data_url(
+                "BTCUSDT",
+                BinanceBizType::FutureUm,
+                Granularity::Daily,
+                DataProductName::BookTicker,
+                "2024-01-31"
+            ),
+            "https://data.binance.vision/data/futures/um/daily/bookTicker/BTCUSDT/BTCUSDT-bookTicker-2024-01-31.zip"
+        );
+    }
+
+    #[test]
+    fn test_monthly_url() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let period = Granularity::Monthly.period(&date);
+        assert_eq!(
+            get_data_url(
+                "BTCUSDT",
+                BinanceBizType::FutureUm,
+                Granularity::Monthly,
+                DataProductName::Trades,
+                &period
+            ),
+            "https://data.binance.vision/data/futures/um/monthly/trades/BTCUSDT/BTCUSDT-trades-2024-01.zip"
+        );
+        assert_eq!(
+            get_data_url(
+                "BTCUSDT",
+                BinanceBizType::FutureUm,
+                Granularity::Monthly,
+                DataProductName::Klines("1m".to_string()),
+                &period
+            ),
+            "https://data.binance.vision/data/futures/um/monthly/klines/BTCUSDT/1m/BTCUSDT-1m-2024-01.zip"
+        );
+        // a file per month for the days of a range
+        let dates: Vec<NaiveDate> = date.iter_days().take(30).collect();
+        assert_eq!(
+            Granularity::Monthly.periods(&dates),
+            vec!["2024-01", "2024-02"]
+        );
+    }
 }
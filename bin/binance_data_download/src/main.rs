@@ -1,10 +1,15 @@
+mod catalog;
 mod download_task;
 mod get_url;
 mod make_parquet;
+mod resample;
+use catalog::process_catalog_command;
 use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
 pub use download_task::*;
+use get_url::{DataProductName, Granularity};
 use make_parquet::process_make_parquet_command;
+use resample::process_resample_command;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -15,15 +20,25 @@ struct BinanceDownloadCliArgs {
     #[clap(long, short = 's', default_value = "BTCUSDT")]
     symbol: String,
 
+    // required by download and make-parquet, catalog lists every day without them
     #[clap(long, short = 'a')]
-    start_date: String,
+    start_date: Option<String>,
 
     #[clap(long, short = 'b')]
-    end_date: String,
+    end_date: Option<String>,
 
     #[clap(long, short = 'm', default_value = "3")]
     max_task: usize,
 
+    // comma separated: trades, bookticker, agg-trades or klines-<interval> like klines-1m, and
+    // mark-price-klines-<interval> or index-price-klines-<interval> for the futures
+    #[clap(long, value_delimiter = ',', default_value = "trades,bookticker")]
+    products: Vec<DataProductName>,
+
+    // daily or monthly files, the months covering the dates are fetched as a whole
+    #[clap(long, default_value = "daily")]
+    granularity: Granularity,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -32,44 +47,94 @@ struct BinanceDownloadCliArgs {
 enum Commands {
     Download {},
     MakeParquet {},
+    // bars and bookticker snapshots of the make-parquet files, for coarse runs
+    Resample {
+        // bar length, e.g. 100ms, 1s or 1m
+        #[clap(long, default_value = "1s")]
+        interval: String,
+    },
+    // list the symbols, products and days present, missing or corrupted below the path
+    Catalog {
+        // print JSON instead of the listing
+        #[clap(long, action)]
+        json: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let cli = BinanceDownloadCliArgs::parse();
 
-    let start_date = {
-        let d = NaiveDate::parse_from_str(&cli.start_date, "%Y%m%d");
-        if d.is_err() {
-            panic!("Invalid start date");
-        }
-        d.unwrap()
-    };
+    let date_range = match (&cli.start_date, &cli.end_date) {
+        (Some(start_date), Some(end_date)) => {
+            let start_date = {
+                let d = NaiveDate::parse_from_str(start_date, "%Y%m%d");
+                if d.is_err() {
+                    panic!("Invalid start date");
+                }
+                d.unwrap()
+            };
 
-    let end_date = {
-        let d = NaiveDate::parse_from_str(&cli.end_date, "%Y%m%d");
-        if d.is_err() {
-            panic!("Invalid end date");
-        }
-        d.unwrap()
-    };
+            let end_date = {
+                let d = NaiveDate::parse_from_str(end_date, "%Y%m%d");
+                if d.is_err() {
+                    panic!("Invalid end date");
+                }
+                d.unwrap()
+            };
 
-    let date_range = {
-        let mut dates = vec![];
-        let mut date = start_date;
-        while date <= end_date {
-            dates.push(date);
-            date = date.succ_opt().unwrap();
+            let mut dates = vec![];
+            let mut date = start_date;
+            while date <= end_date {
+                dates.push(date);
+                date = date.succ_opt().unwrap();
+            }
+            Some(dates)
         }
-        dates
+        (None, None) => None,
+        _ => panic!("--start-date and --end-date go together"),
+    };
+    let required_date_range = || {
+        date_range
+            .clone()
+            .expect("--start-date and --end-date are required")
     };
 
     match cli.command {
         Commands::Download {} => {
-            process_download_command(&date_range, &cli.symbol, &cli.path, cli.max_task).await
+            process_download_command(
+                &required_date_range(),
+                &cli.symbol,
+                &cli.path,
+                cli.max_task,
+                &cli.products,
+                cli.granularity,
+            )
+            .await
         }
         Commands::MakeParquet {} => {
-            process_make_parquet_command(&date_range, &cli.symbol, &cli.path, cli.max_task).await
+            process_make_parquet_command(
+                &required_date_range(),
+                &cli.symbol,
+                &cli.path,
+                cli.max_task,
+                cli.granularity,
+            )
+            .await
+        }
+        Commands::Resample { interval } => {
+            process_resample_command(
+                &required_date_range(),
+                &cli.symbol,
+                &cli.path,
+                cli.granularity,
+                &interval,
+            )
+            .await
+        }
+        Commands::Catalog { json } => {
+            process_catalog_command(&cli.path, date_range.as_deref(), json)
+                .expect("failed to list the datasets")
         }
     }
 }
@@ -6,6 +6,8 @@ use std::{
 
 use anyhow::Context;
 use chrono::NaiveDate;
+
+use crate::get_url::Granularity;
 use polars::io::{
     csv::CsvReader,
     parquet::{ParquetReader, ParquetWriter},
@@ -86,11 +88,12 @@ fn generate_make_parquet_task(
     date_range: &[NaiveDate],
     symbol: &str,
     root_path: &Path,
+    granularity: Granularity,
 ) -> Vec<MakeParquetTask> {
-    date_range
-        .iter()
-        .flat_map(|date| {
-            let date_str = date.format("%Y-%m-%d").to_string();
+    granularity
+        .periods(date_range)
+        .into_iter()
+        .flat_map(|date_str| {
             [
                 MakeParquetTask {
                     csv_zip_path: root_path
@@ -118,6 +121,7 @@ pub async fn process_make_parquet_command(
     symbol: &str,
     root_path: &Path,
     max_task: usize,
+    granularity: Granularity,
 ) {
     // set env POLARS_MAX_THREADS to max_task if max_task > 0
     if max_task > 0 {
@@ -125,7 +129,7 @@ pub async fn process_make_parquet_command(
         std::env::set_var("POLARS_MAX_THREADS", max_task.to_string());
     }
 
-    let tasks = generate_make_parquet_task(date_range, symbol, root_path);
+    let tasks = generate_make_parquet_task(date_range, symbol, root_path, granularity);
     for task in tasks {
         if !task.parquet_file_missing_or_corrupted() {
             println!("parquet file already existed: {:?}", task.parquet_path);
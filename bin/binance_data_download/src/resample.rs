@@ -0,0 +1,299 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{create_dir_all, File},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use chrono::NaiveDate;
+use polars::prelude::{DataFrame, DataType, ParquetReader, SerReader};
+
+use crate::get_url::Granularity;
+
+const KLINE_HEADER: &str = "open_time,open,high,low,close,volume,close_time,quote_volume,count,taker_buy_volume,taker_buy_quote_volume";
+const BOOKTICKER_HEADER: &str =
+    "update_id,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty,transaction_time,event_time";
+
+// milliseconds of an interval such as 100ms, 1s or 1m
+fn parse_interval(interval: &str) -> Result<u64, anyhow::Error> {
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("interval {interval} has no unit, expected e.g. 100ms or 1s"))?;
+    let (count, unit) = interval.split_at(split);
+    let count: u64 = count
+        .parse()
+        .with_context(|| format!("invalid interval {interval}"))?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => bail!("unknown unit of interval {interval}, expected ms, s, m or h"),
+    };
+    if count == 0 {
+        bail!("interval {interval} is empty");
+    }
+    Ok(count * unit_ms)
+}
+
+// the first of names which is a column of dataframe, as f64
+fn f64_column(dataframe: &DataFrame, names: &[&str]) -> Result<Vec<f64>, anyhow::Error> {
+    let column = column(dataframe, names)?.cast(&DataType::Float64)?;
+    Ok(column
+        .f64()?
+        .into_iter()
+        .map(|v| v.unwrap_or(0.0))
+        .collect())
+}
+
+fn i64_column(dataframe: &DataFrame, names: &[&str]) -> Result<Vec<i64>, anyhow::Error> {
+    let column = column(dataframe, names)?.cast(&DataType::Int64)?;
+    Ok(column.i64()?.into_iter().map(|v| v.unwrap_or(0)).collect())
+}
+
+fn column<'a>(
+    dataframe: &'a DataFrame,
+    names: &[&str],
+) -> Result<&'a polars::prelude::Series, anyhow::Error> {
+    names
+        .iter()
+        .find_map(|name| dataframe.column(name).ok())
+        .with_context(|| format!("no column {}", names.join(" or ")))
+}
+
+fn read_parquet(path: &Path) -> Result<DataFrame, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(ParquetReader::new(file).finish()?)
+}
+
+// trades of an interval, the columns of the klines files
+struct Bar {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    quote_volume: f64,
+    count: u64,
+    taker_buy_volume: f64,
+    taker_buy_quote_volume: f64,
+}
+
+impl Bar {
+    fn new(price: f64) -> Self {
+        Bar {
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0.0,
+            quote_volume: 0.0,
+            count: 0,
+            taker_buy_volume: 0.0,
+            taker_buy_quote_volume: 0.0,
+        }
+    }
+
+    fn add(&mut self, price: f64, qty: f64, is_buyer_maker: bool) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += qty;
+        self.quote_volume += price * qty;
+        self.count += 1;
+        if !is_buyer_maker {
+            self.taker_buy_volume += qty;
+            self.taker_buy_quote_volume += price * qty;
+        }
+    }
+}
+
+// Bars of the trades in the klines layout, one per interval with trades
+fn trade_bars(trades: &DataFrame, interval_ms: u64) -> Result<String, anyhow::Error> {
+    let price = f64_column(trades, &["price"])?;
+    let qty = f64_column(trades, &["qty", "quantity"])?;
+    let time = i64_column(trades, &["time", "transact_time"])?;
+    let is_buyer_maker = column(trades, &["is_buyer_maker"])?.bool()?.clone();
+    let mut bars: BTreeMap<u64, Bar> = BTreeMap::new();
+    for (i, is_buyer_maker) in is_buyer_maker.into_iter().enumerate() {
+        bars.entry(time[i] as u64 / interval_ms)
+            .or_insert_with(|| Bar::new(price[i]))
+            .add(price[i], qty[i], is_buyer_maker.unwrap_or(true));
+    }
+    let mut csv = format!("{KLINE_HEADER}\n");
+    for (bucket, bar) in bars {
+        let open_time = bucket * interval_ms;
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            open_time,
+            bar.open,
+            bar.high,
+            bar.low,
+            bar.close,
+            bar.volume,
+            open_time + interval_ms - 1,
+            bar.quote_volume,
+            bar.count,
+            bar.taker_buy_volume,
+            bar.taker_buy_quote_volume
+        )?;
+    }
+    Ok(csv)
+}
+
+// The last bookticker of every interval with an update
+fn bookticker_snapshots(
+    booktickers: &DataFrame,
+    interval_ms: u64,
+) -> Result<String, anyhow::Error> {
+    let update_id = i64_column(booktickers, &["update_id"])?;
+    let best_bid_price = f64_column(booktickers, &["best_bid_price"])?;
+    let best_bid_qty = f64_column(booktickers, &["best_bid_qty"])?;
+    let best_ask_price = f64_column(booktickers, &["best_ask_price"])?;
+    let best_ask_qty = f64_column(booktickers, &["best_ask_qty"])?;
+    let transaction_time = i64_column(booktickers, &["transaction_time"])?;
+    let event_time = i64_column(booktickers, &["event_time"])?;
+    let mut last_of_interval: BTreeMap<u64, usize> = BTreeMap::new();
+    for (i, time) in event_time.iter().enumerate() {
+        last_of_interval.insert(*time as u64 / interval_ms, i);
+    }
+    let mut csv = format!("{BOOKTICKER_HEADER}\n");
+    for i in last_of_interval.into_values() {
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{}",
+            update_id[i],
+            best_bid_price[i],
+            best_bid_qty[i],
+            best_ask_price[i],
+            best_ask_qty[i],
+            transaction_time[i],
+            event_time[i]
+        )?;
+    }
+    Ok(csv)
+}
+
+// the zip of a single csv file, the layout of the Binance files the republisher reads
+fn write_csv_zip(path: &Path, file_name: &str, csv: &str) -> Result<(), anyhow::Error> {
+    create_dir_all(path.parent().with_context(|| "File path no parent")?)?;
+    let mut zip = zip::ZipWriter::new(File::create(path)?);
+    zip.start_file(file_name, zip::write::SimpleFileOptions::default())?;
+    zip.write_all(csv.as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+// Turns the trades and booktickers parquet of make-parquet into bars of interval, in the klines
+// layout below resampled_klines_<interval>, apart from the downloaded klines_<interval>, and the
+// last bookticker of every interval below resampled_bookticker_<interval>. sim replays them with --trade-data resampled-<interval>, a coarse run
+// on a fraction of the ticks.
+pub async fn process_resample_command(
+    date_range: &[NaiveDate],
+    symbol: &str,
+    root_path: &Path,
+    granularity: Granularity,
+    interval: &str,
+) {
+    let interval_ms = match parse_interval(interval) {
+        Ok(interval_ms) => interval_ms,
+        Err(e) => panic!("{:#}", e),
+    };
+    let symbol_path = root_path.join("future_um").join(symbol);
+    for period in granularity.periods(date_range) {
+        let trades_path = symbol_path.join(format!("trades_pq/{}.parquet", period));
+        let bars_path = symbol_path.join(format!("resampled_klines_{}/{}.zip", interval, period));
+        let result = read_parquet(&trades_path)
+            .and_then(|trades| trade_bars(&trades, interval_ms))
+            .and_then(|csv| {
+                let file_name = format!("{}-{}-{}.csv", symbol, interval, period);
+                write_csv_zip(&bars_path, &file_name, &csv)
+            });
+        match result {
+            Ok(()) => println!("resampled trades: {:?}", bars_path),
+            Err(e) => eprintln!("failed to resample {:?}: {:#}", trades_path, e),
+        }
+
+        let booktickers_path = symbol_path.join(format!("bookticker_pq/{}.parquet", period));
+        let snapshots_path =
+            symbol_path.join(format!("resampled_bookticker_{}/{}.zip", interval, period));
+        let result = read_parquet(&booktickers_path)
+            .and_then(|booktickers| bookticker_snapshots(&booktickers, interval_ms))
+            .and_then(|csv| {
+                let file_name = format!("{}-bookTicker-{}.csv", symbol, period);
+                write_csv_zip(&snapshots_path, &file_name, &csv)
+            });
+        match result {
+            Ok(()) => println!("resampled booktickers: {:?}", snapshots_path),
+            Err(e) => eprintln!("failed to resample {:?}: {:#}", booktickers_path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::df;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("100ms").unwrap(), 100);
+        assert_eq!(parse_interval("1s").unwrap(), 1000);
+        assert_eq!(parse_interval("5m").unwrap(), 300_000);
+        assert_eq!(parse_interval("1h").unwrap(), 3_600_000);
+        assert!(parse_interval("1").is_err());
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("1d").is_err());
+    }
+
+    #[test]
+    fn test_trade_bars() {
+        let trades = df!(
+            "price" => [100.0, 102.0, 99.0, 101.0],
+            "qty" => [1.0, 2.0, 1.0, 3.0],
+            "time" => [1000i64, 1200, 1900, 3500],
+            "is_buyer_maker" => [true, false, true, false],
+        )
+        .unwrap();
+        let csv = trade_bars(&trades, 1000).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        // no bar of the second without trades
+        assert_eq!(
+            lines,
+            vec![
+                KLINE_HEADER,
+                "1000,100,102,99,99,4,1999,403,3,2,204",
+                "3000,101,101,101,101,3,3999,303,1,3,303",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bookticker_snapshots() {
+        let booktickers = df!(
+            "update_id" => [1i64, 2, 3],
+            "best_bid_price" => [99.0, 98.0, 97.0],
+            "best_bid_qty" => [1.0, 2.0, 3.0],
+            "best_ask_price" => [101.0, 102.0, 103.0],
+            "best_ask_qty" => [4.0, 5.0, 6.0],
+            "transaction_time" => [1000i64, 1500, 2100],
+            "event_time" => [1001i64, 1501, 2101],
+        )
+        .unwrap();
+        let csv = bookticker_snapshots(&booktickers, 1000).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        // the last update of each second
+        assert_eq!(
+            lines,
+            vec![
+                BOOKTICKER_HEADER,
+                "2,98,2,102,5,1500,1501",
+                "3,97,3,103,6,2100,2101",
+            ]
+        );
+    }
+}
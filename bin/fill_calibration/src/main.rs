@@ -0,0 +1,133 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use polars::prelude::{DataFrame, DataType, ParquetReader, SerReader};
+use pure_market_maker::{
+    fill_intensity::{bucket_quotes, FillIntensityCalibration},
+    MM_ORDER_EXPIRE_MILLSECONDS,
+};
+
+// Estimates the fill intensity of quotes by their distance from the fair price, the k of the
+// Avellaneda–Stoikov model, from the quote.parquet and trade.parquet written to the results
+// directory by sim --debug-log, and writes a calibration file for the simulator
+// (sim --as-calibration).
+//
+// The fills have no time in the recording, so a quote is taken to rest for its lifetime and
+// its fill probability over it gives the intensity.
+#[derive(Parser, Debug)]
+#[command(version, about = "Fill intensity calibration", long_about = None)]
+struct CliArgs {
+    // results directories with a quote.parquet and a trade.parquet
+    #[clap(long, short = 'i', required = true)]
+    input: Vec<PathBuf>,
+
+    #[clap(long, short = 'o', default_value = "fill_calibration.csv")]
+    output: PathBuf,
+
+    #[clap(long, default_value_t = 10)]
+    buckets: usize,
+
+    // how long a quote rests before the strategy cancels it
+    #[clap(long, default_value_t = MM_ORDER_EXPIRE_MILLSECONDS)]
+    quote_lifetime_ms: u64,
+}
+
+fn read_parquet(path: &Path) -> Result<DataFrame, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    ParquetReader::new(file)
+        .finish()
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn f64_column(dataframe: &DataFrame, name: &str) -> Result<Vec<f64>, anyhow::Error> {
+    let column = dataframe.column(name)?.cast(&DataType::Float64)?;
+    Ok(column
+        .f64()?
+        .into_iter()
+        .map(|v| v.unwrap_or(0.0))
+        .collect())
+}
+
+fn str_column(dataframe: &DataFrame, name: &str) -> Result<Vec<String>, anyhow::Error> {
+    let column = dataframe.column(name)?.cast(&DataType::String)?;
+    Ok(column
+        .str()?
+        .into_iter()
+        .map(|v| v.unwrap_or_default().to_string())
+        .collect())
+}
+
+// (depth, filled) of each quote in dir
+fn read_quotes(dir: &Path, quotes: &mut Vec<(f64, bool)>) -> Result<(), anyhow::Error> {
+    let trades = read_parquet(&dir.join("trade.parquet"))?;
+    let filled_ids: HashSet<String> = str_column(&trades, "order_id")?
+        .into_iter()
+        .zip(f64_column(&trades, "filled")?)
+        .filter(|(_, filled)| *filled > 0.0)
+        .map(|(order_id, _)| order_id)
+        .collect();
+
+    let quote_df = read_parquet(&dir.join("quote.parquet"))?;
+    let is_bid: Vec<bool> = quote_df
+        .column("is_bid")?
+        .bool()?
+        .into_iter()
+        .map(|v| v.unwrap_or_default())
+        .collect();
+    let rows = str_column(&quote_df, "id")?
+        .into_iter()
+        .zip(f64_column(&quote_df, "price")?)
+        .zip(f64_column(&quote_df, "fair_price")?)
+        .zip(is_bid);
+    for (((id, price), fair_price), is_bid) in rows {
+        let depth = if is_bid {
+            fair_price - price
+        } else {
+            price - fair_price
+        };
+        quotes.push((depth, filled_ids.contains(&id)));
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = CliArgs::parse();
+
+    let mut quotes = vec![];
+    for dir in &cli.input {
+        read_quotes(dir, &mut quotes)?;
+    }
+    if quotes.is_empty() {
+        bail!("no quotes in input");
+    }
+    let lifetime_secs = cli.quote_lifetime_ms as f64 / 1000.0;
+    let buckets = bucket_quotes(&quotes, cli.buckets);
+
+    println!("--- Fills by Depth ---");
+    println!(
+        "{:>12}{:>10}{:>10}{:>14}",
+        "depth", "quotes", "fills", "intensity/s"
+    );
+    for bucket in &buckets {
+        let intensity = bucket
+            .intensity(lifetime_secs)
+            .map_or("-".to_string(), |i| format!("{:.4}", i));
+        println!(
+            "{:>12.4}{:>10}{:>10}{:>14}",
+            bucket.depth, bucket.quotes, bucket.fills, intensity
+        );
+    }
+
+    let calibration = FillIntensityCalibration::fit(&buckets, lifetime_secs)?;
+    calibration.save(&cli.output)?;
+    println!("--- Fill Intensity ---");
+    println!("A: {:.6} fills/s", calibration.a);
+    println!("k: {:.6}", calibration.k);
+    println!("Calibration written to {}", cli.output.display());
+    Ok(())
+}
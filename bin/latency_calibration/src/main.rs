@@ -0,0 +1,101 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use market_agent::latency::{percentile_of_sorted, LatencyProfile};
+
+// Estimates the latency between the exchange and this machine from a live feed recording and
+// writes a latency profile for the simulator (sim --latency-profile).
+//
+// Each input line is `event_time_ms,receive_time_ms`: the exchange timestamp of a websocket
+// message and the local clock when it was received. A header line is skipped.
+#[derive(Parser, Debug)]
+#[command(version, about = "Latency profile calibration", long_about = None)]
+struct CliArgs {
+    #[clap(long, short = 'i', required = true)]
+    input: Vec<PathBuf>,
+
+    #[clap(long, short = 'o', default_value = "latency_profile.csv")]
+    output: PathBuf,
+
+    // local clock minus exchange clock, measured elsewhere (e.g. ntp)
+    #[clap(long, default_value_t = 0.0)]
+    clock_offset_ms: f64,
+
+    // align the clocks assuming the fastest message took this long,
+    // overrides clock_offset_ms
+    #[clap(long)]
+    min_latency_ms: Option<f64>,
+}
+
+fn read_deltas(path: &Path, deltas: &mut Vec<f64>) -> Result<(), anyhow::Error> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields = line.split_once(',').and_then(|(event, receive)| {
+            Some((event.parse::<f64>().ok()?, receive.parse::<f64>().ok()?))
+        });
+        match fields {
+            Some((event_time_ms, receive_time_ms)) => deltas.push(receive_time_ms - event_time_ms),
+            // header
+            None if i == 0 => {}
+            None => bail!(
+                "{}:{}: expect event_time_ms,receive_time_ms",
+                path.display(),
+                i + 1
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = CliArgs::parse();
+
+    let mut deltas = vec![];
+    for path in &cli.input {
+        read_deltas(path, &mut deltas)?;
+    }
+    if deltas.is_empty() {
+        bail!("no samples in input");
+    }
+    deltas.sort_by(|a, b| a.total_cmp(b));
+
+    let clock_offset_ms = match cli.min_latency_ms {
+        Some(min_latency_ms) => deltas[0] - min_latency_ms,
+        None => cli.clock_offset_ms,
+    };
+    let latencies: Vec<f64> = deltas.iter().map(|d| d - clock_offset_ms).collect();
+    let negative = latencies.iter().filter(|l| **l < 0.0).count();
+    if negative > 0 {
+        // the clocks are not aligned, these can not be used
+        println!(
+            "Dropped {} samples with negative latency, check the clock offset",
+            negative
+        );
+    }
+    let latencies: Vec<f64> = latencies.into_iter().filter(|l| *l >= 0.0).collect();
+    let profile = LatencyProfile::from_samples(&latencies)?;
+    profile.save(&cli.output)?;
+
+    println!("--- Latency ---");
+    println!("Samples: {}", latencies.len());
+    println!("Clock offset: {:.3} ms", clock_offset_ms);
+    println!(
+        "Mean: {:.3} ms",
+        latencies.iter().sum::<f64>() / latencies.len() as f64
+    );
+    for p in [50.0, 90.0, 99.0, 100.0] {
+        println!("p{}: {:.3} ms", p, percentile_of_sorted(&latencies, p));
+    }
+    println!("Profile written to {}", cli.output.display());
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use chrono::NaiveDate;
+use market_agent::results::RunResults;
+use polars::{
+    io::parquet::ParquetWriter,
+    prelude::{DataFrame, NamedFrom, Series},
+};
+
+use crate::data_check::{self, TradeData};
+
+const SUMMARY_FILE: &str = "summary.parquet";
+const TICK_CACHE_DIR: &str = "tick_cache";
+
+// flags the batch sets for each run
+const RUN_FLAGS: &[&str] = &[
+    "--symbol",
+    "--date",
+    "-d",
+    "--start-date",
+    "--end-date",
+    "--path",
+    "-p",
+    "--results-dir",
+    "--run-label",
+    "--runs-root",
+    "--vis",
+    "-g",
+    "--vis-export",
+];
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct BatchArgs {
+    // comma separated
+    #[clap(long, value_delimiter = ',', required = true)]
+    symbols: Vec<String>,
+
+    // YYYY-MM-DD, both inclusive
+    #[clap(long)]
+    start_date: String,
+
+    #[clap(long)]
+    end_date: String,
+
+    // results of each run are written to <out>/<symbol>/<date>
+    #[clap(long, short = 'o')]
+    out: PathBuf,
+
+    // runs in parallel
+    #[clap(long, short = 'j', default_value_t = 1)]
+    jobs: usize,
+
+    // run again the pairs which already have results instead of skipping them
+    #[clap(long, action)]
+    rerun: bool,
+}
+
+struct Run {
+    symbol: String,
+    date: String,
+    dir: PathBuf,
+}
+
+pub(crate) fn date_range(start_date: &str, end_date: &str) -> Result<Vec<String>, anyhow::Error> {
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("invalid date {}, expect YYYY-MM-DD", date))
+    };
+    let (mut date, end_date) = (parse(start_date)?, parse(end_date)?);
+    let mut dates = vec![];
+    while date <= end_date {
+        dates.push(date.format("%Y-%m-%d").to_string());
+        date = date.succ_opt().unwrap();
+    }
+    Ok(dates)
+}
+
+// the sim flags given before the subcommand, passed on to every run. run_flags are set by
+// the subcommand for each run and must not be given.
+pub(crate) fn forwarded_args(
+    subcommand: &str,
+    run_flags: &[&str],
+) -> Result<Vec<OsString>, anyhow::Error> {
+    let args: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .take_while(|arg| arg != subcommand)
+        .collect();
+    for arg in &args {
+        let arg = arg.to_string_lossy();
+        let flag = arg.split('=').next().unwrap_or_default();
+        if run_flags.contains(&flag) {
+            bail!("{} is set by the {} for each run", flag, subcommand);
+        }
+    }
+    Ok(args)
+}
+
+// runs sim as a child process writing its results to dir, output goes to dir/sim.log
+pub(crate) fn run_child(args: &[OsString], dir: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let log_path = dir.join("sim.log");
+    let log = File::create(&log_path)
+        .with_context(|| format!("failed to create {}", log_path.display()))?;
+    let status = Command::new(std::env::current_exe()?)
+        .args(args)
+        .arg("--results-dir")
+        .arg(dir)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .status()?;
+    if !status.success() {
+        bail!("{}, see {}", status, log_path.display());
+    }
+    if !RunResults::exists(dir) {
+        bail!("no results written, see {}", log_path.display());
+    }
+    Ok(())
+}
+
+fn run_one(run: &Run, args: &[OsString]) -> Result<(), anyhow::Error> {
+    let mut args = args.to_vec();
+    args.extend([
+        "--symbol".into(),
+        run.symbol.clone().into(),
+        "--date".into(),
+        run.date.clone().into(),
+    ]);
+    run_child(&args, &run.dir)
+}
+
+// every (symbol, date) pair of the batch
+fn runs(batch: &BatchArgs, dates: &[String]) -> Vec<Run> {
+    batch
+        .symbols
+        .iter()
+        .flat_map(|symbol| {
+            dates.iter().map(move |date| Run {
+                symbol: symbol.clone(),
+                date: date.clone(),
+                dir: batch.out.join(symbol).join(date),
+            })
+        })
+        .collect()
+}
+
+// one row per run with its stats, runs without results are left out
+fn merge_results(runs: &[Run], path: &Path) -> Result<usize, anyhow::Error> {
+    let mut results = vec![];
+    for run in runs.iter().filter(|run| RunResults::exists(&run.dir)) {
+        results.push((run, RunResults::load(&run.dir)?));
+    }
+    let metrics: BTreeSet<&String> = results.iter().flat_map(|(_, r)| r.stats.keys()).collect();
+
+    let mut columns = vec![
+        Series::new(
+            "symbol",
+            results
+                .iter()
+                .map(|(run, _)| run.symbol.as_str())
+                .collect::<Vec<_>>(),
+        ),
+        Series::new(
+            "date",
+            results
+                .iter()
+                .map(|(run, _)| run.date.as_str())
+                .collect::<Vec<_>>(),
+        ),
+    ];
+    for metric in metrics {
+        columns.push(Series::new(
+            metric,
+            results
+                .iter()
+                .map(|(_, r)| r.stats.get(metric).copied())
+                .collect::<Vec<_>>(),
+        ));
+    }
+    let mut summary = DataFrame::new(columns)?;
+    let mut file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    ParquetWriter::new(&mut file).finish(&mut summary)?;
+    Ok(results.len())
+}
+
+// Runs every (symbol, date) pair as a child sim process, `jobs` at a time. Runs share the
+// tick cache, so data parsed once is reused by later batches. Pairs with results are
+// skipped, so an interrupted batch is resumed by running it again. The stats of all runs
+// are merged into <out>/summary.parquet. The data of every pair is checked before the first
+// run, the missing days downloaded first when download_missing is set.
+pub(crate) fn run_batch(
+    batch: &BatchArgs,
+    tick_cache_dir: Option<&Path>,
+    root_path: &Path,
+    trade_data: &TradeData,
+    download_missing: bool,
+) -> Result<(), anyhow::Error> {
+    let mut args = forwarded_args("batch", RUN_FLAGS)?;
+    if tick_cache_dir.is_none() {
+        args.push("--tick-cache-dir".into());
+        args.push(batch.out.join(TICK_CACHE_DIR).into());
+    }
+    if !args.iter().any(|arg| arg == "--no-progress") {
+        args.push("--no-progress".into());
+    }
+
+    let dates = date_range(&batch.start_date, &batch.end_date)?;
+    data_check::ensure_dated_inputs(
+        root_path,
+        &batch.symbols,
+        &dates,
+        trade_data,
+        download_missing,
+    )?;
+    let runs = runs(batch, &dates);
+    let pending: Vec<&Run> = runs
+        .iter()
+        .filter(|run| batch.rerun || !RunResults::exists(&run.dir))
+        .collect();
+    println!(
+        "Batch: {} runs, {} already done, {} jobs",
+        runs.len(),
+        runs.len() - pending.len(),
+        batch.jobs
+    );
+
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let failed = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..batch.jobs.max(1) {
+            scope.spawn(|| {
+                while let Some(run) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let started_at = Instant::now();
+                    let result = run_one(run, &args);
+                    let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                    match result {
+                        Ok(()) => println!(
+                            "[{}/{}] {} {} done in {:.1}s",
+                            finished,
+                            pending.len(),
+                            run.symbol,
+                            run.date,
+                            started_at.elapsed().as_secs_f64()
+                        ),
+                        Err(e) => {
+                            println!(
+                                "[{}/{}] {} {} failed: {:#}",
+                                finished,
+                                pending.len(),
+                                run.symbol,
+                                run.date,
+                                e
+                            );
+                            failed.lock().unwrap().push(run);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let summary_path = batch.out.join(SUMMARY_FILE);
+    let merged = merge_results(&runs, &summary_path)?;
+    println!("Merged {} runs into {}", merged, summary_path.display());
+
+    let failed = failed.into_inner().unwrap();
+    if !failed.is_empty() {
+        bail!(
+            "{} runs failed, run the batch again to retry them",
+            failed.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        batch: BatchArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<BatchArgs, clap::Error> {
+        Cli::try_parse_from(std::iter::once("batch").chain(args.iter().copied()))
+            .map(|cli| cli.batch)
+    }
+
+    #[test]
+    fn test_batch_args() {
+        let batch = parse(&[
+            "--symbols",
+            "BTCUSDT,ETHUSDT",
+            "--start-date",
+            "2024-02-28",
+            "--end-date",
+            "2024-03-01",
+            "-o",
+            "out",
+            "-j",
+            "4",
+        ])
+        .unwrap();
+        assert_eq!(batch.symbols, vec!["BTCUSDT", "ETHUSDT"]);
+        assert_eq!(batch.jobs, 4);
+        assert!(!batch.rerun);
+        // the symbols and the out directory are required
+        assert!(parse(&[
+            "--start-date",
+            "2024-01-01",
+            "--end-date",
+            "2024-01-01",
+            "-o",
+            "out"
+        ])
+        .is_err());
+        assert!(parse(&["--symbols", "BTCUSDT", "--start-date", "2024-01-01"]).is_err());
+    }
+
+    #[test]
+    fn test_date_range() {
+        assert_eq!(
+            date_range("2024-02-28", "2024-03-01").unwrap(),
+            vec!["2024-02-28", "2024-02-29", "2024-03-01"]
+        );
+        assert!(date_range("2024-03-01", "2024-02-28").unwrap().is_empty());
+        assert!(date_range("2024-02-30", "2024-03-01").is_err());
+        assert!(date_range("20240301", "2024-03-01").is_err());
+    }
+
+    #[test]
+    fn test_runs_of_every_symbol_and_date() {
+        let batch = parse(&[
+            "--symbols",
+            "BTCUSDT,ETHUSDT",
+            "--start-date",
+            "2024-01-01",
+            "--end-date",
+            "2024-01-03",
+            "-o",
+            "out",
+        ])
+        .unwrap();
+        let dates = date_range(&batch.start_date, &batch.end_date).unwrap();
+        let runs = runs(&batch, &dates);
+        assert_eq!(runs.len(), 6);
+        let pairs: Vec<(&str, &str)> = runs
+            .iter()
+            .map(|run| (run.symbol.as_str(), run.date.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("BTCUSDT", "2024-01-01"),
+                ("BTCUSDT", "2024-01-02"),
+                ("BTCUSDT", "2024-01-03"),
+                ("ETHUSDT", "2024-01-01"),
+                ("ETHUSDT", "2024-01-02"),
+                ("ETHUSDT", "2024-01-03"),
+            ]
+        );
+        assert_eq!(runs[4].dir, Path::new("out/ETHUSDT/2024-01-02"));
+    }
+}
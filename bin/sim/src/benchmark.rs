@@ -0,0 +1,110 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime},
+};
+
+use binance_republisher::binance_republisher::BinanceRepublisherBuilder;
+use simulation::engine::SimulationEngineBuilder;
+use upstair_type::module::{
+    Module, ModuleBuilder, ModuleComms, ModuleCommsBuilder, ReadTopicHandle,
+};
+
+// Reads the market data and does nothing with it, so the run measures the reader and the
+// engine alone
+struct NullConsumer {
+    market_data_topic: ReadTopicHandle,
+    ticks: Arc<AtomicU64>,
+}
+
+impl Module for NullConsumer {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        let mut ticks = 0;
+        while comms.receive_shared(&self.market_data_topic).is_some() {
+            ticks += 1;
+        }
+        self.ticks.fetch_add(ticks, Ordering::Relaxed);
+        false
+    }
+
+    fn one_iteration(&mut self, _comms: &mut dyn ModuleComms) {}
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+struct NullConsumerBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    ticks: Arc<AtomicU64>,
+}
+
+impl ModuleBuilder for NullConsumerBuilder {
+    fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        self.market_data_topic = comms.subscribe_topic(&market_data_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(NullConsumer {
+            market_data_topic: self.market_data_topic.unwrap(),
+            ticks: self.ticks,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "null_consumer"
+    }
+}
+
+// Republishes the files to a null consumer and reports the throughput, to track the speed of
+// the reader and the engine over time
+pub(crate) fn run_benchmark(
+    republisher: BinanceRepublisherBuilder,
+    paths: &[PathBuf],
+) -> Result<(), anyhow::Error> {
+    let input_bytes: u64 = paths
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let ticks = Arc::new(AtomicU64::new(0));
+
+    let started_at = Instant::now();
+    let mut engine = SimulationEngineBuilder::default()
+        .add_module(NullConsumerBuilder {
+            market_data_topic: None,
+            ticks: ticks.clone(),
+        })
+        .add_module(republisher)
+        .build();
+    engine.run();
+    let elapsed = started_at.elapsed().as_secs_f64();
+    let failures = engine.failures();
+    if !failures.is_empty() {
+        anyhow::bail!("the benchmark ended early\n{}", failures.join("\n"));
+    }
+
+    let ticks = ticks.load(Ordering::Relaxed);
+    println!("--- Benchmark ---");
+    println!("Ticks: {}", ticks);
+    println!("Elapsed: {:.3} s", elapsed);
+    println!("Throughput: {:.0} ticks/sec", ticks as f64 / elapsed);
+    println!(
+        "Input: {:.2} MB, {:.2} MB/sec",
+        input_bytes as f64 / 1024.0 / 1024.0,
+        input_bytes as f64 / 1024.0 / 1024.0 / elapsed
+    );
+    println!("--- Engine Profile ---");
+    println!("{}", engine.profile());
+    Ok(())
+}
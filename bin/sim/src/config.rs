@@ -0,0 +1,221 @@
+use std::{ffi::OsString, path::Path, str::FromStr};
+
+use anyhow::{bail, Context};
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+// written to the results directory, it can be passed back with --config to repeat the run
+const RESOLVED_CONFIG_FILE: &str = "config.toml";
+
+// flags which are not part of a run
+const IGNORED_ARGS: &[&str] = &["config", "help", "version"];
+
+// An asset and the balance the account starts with, ASSET=AMOUNT
+#[derive(Debug, Clone)]
+pub(crate) struct InitialBalance {
+    pub asset: String,
+    pub amount: f64,
+}
+
+impl FromStr for InitialBalance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (asset, amount) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid initial balance {s}, expected ASSET=AMOUNT"))?;
+        let amount = amount
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid amount in {s}: {e}"))?;
+        Ok(InitialBalance {
+            asset: asset.trim().to_string(),
+            amount,
+        })
+    }
+}
+
+// The arguments with the values of the config file added for every flag not given on the
+// command line. The keys of the file are the long flags in snake_case, e.g. fee_rate for
+// --fee-rate, and may be grouped in tables such as [strategy]. A table named after a flag
+// is a list of KEY=VALUE, e.g. [initial_balance] USDT = 50000.0
+pub(crate) fn args_with_config(
+    command: &Command,
+    matches: &ArgMatches,
+    args: Vec<OsString>,
+    config: &Table,
+) -> Result<Vec<OsString>, anyhow::Error> {
+    let mut config_args = vec![];
+    for (key, value) in flatten(config)? {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .filter(|arg| arg.get_long().is_some() && !IGNORED_ARGS.contains(&key.as_str()))
+        else {
+            bail!("unknown config key {key}");
+        };
+        if matches.value_source(&key) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let flag = format!("--{}", arg.get_long().unwrap());
+        if !arg.get_action().takes_values() {
+            match value {
+                Value::Boolean(true) => config_args.push(flag.into()),
+                Value::Boolean(false) => {}
+                _ => bail!("config key {key} is a flag, expected true or false"),
+            }
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            config_args.push(flag.clone().into());
+            config_args.push(scalar(&key, value)?.into());
+        }
+    }
+    // top level flags come before a subcommand, so they go right after the program name
+    let mut args = args.into_iter();
+    Ok(args
+        .next()
+        .into_iter()
+        .chain(config_args)
+        .chain(args)
+        .collect())
+}
+
+pub(crate) fn load(path: &Path) -> Result<Table, anyhow::Error> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))
+}
+
+// (key, value) of the flags in config, the tables not named after a flag taken apart
+fn flatten(config: &Table) -> Result<Vec<(String, Value)>, anyhow::Error> {
+    let mut entries = vec![];
+    for (key, value) in config {
+        match value {
+            // a section, unless it is the KEY=VALUE list of a flag
+            Value::Table(table) if !is_pair_list(key) => entries.extend(flatten(table)?),
+            Value::Table(table) => {
+                let pairs = table
+                    .iter()
+                    .map(|(k, v)| Ok(Value::String(format!("{k}={}", scalar(k, v.clone())?))))
+                    .collect::<Result<_, anyhow::Error>>()?;
+                entries.push((key.clone(), Value::Array(pairs)));
+            }
+            value => entries.push((key.clone(), value.clone())),
+        }
+    }
+    Ok(entries)
+}
+
+fn is_pair_list(key: &str) -> bool {
+    key == "initial_balance"
+}
+
+fn scalar(key: &str, value: Value) -> Result<String, anyhow::Error> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        value => bail!("unsupported value {value} of config key {key}"),
+    }
+}
+
+// Every flag of the run given on the command line or in the config file with the value it
+// resolved to. The defaults are left out, passed back with --config they would count as
+// given and trip the flags they require, e.g. --hedge-ratio needs --hedge-band
+pub(crate) fn resolved(command: &Command, matches: &ArgMatches) -> Table {
+    let mut config = Table::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if arg.get_long().is_none() || IGNORED_ARGS.contains(&id) {
+            continue;
+        }
+        if matches.value_source(id) == Some(ValueSource::DefaultValue) {
+            continue;
+        }
+        let Some(raw) = matches.get_raw(id) else {
+            continue;
+        };
+        let values: Vec<Value> = raw.map(|value| typed(&value.to_string_lossy())).collect();
+        let value = match arg.get_action() {
+            ArgAction::Append => Value::Array(values),
+            _ => match values.into_iter().next() {
+                Some(value) => value,
+                None => continue,
+            },
+        };
+        config.insert(id.to_string(), value);
+    }
+    config
+}
+
+// numbers and booleans as such, so the file reads like one written by hand
+fn typed(value: &str) -> Value {
+    if let Ok(b) = value.parse::<bool>() {
+        Value::Boolean(b)
+    } else if let Ok(i) = value.parse::<i64>() {
+        Value::Integer(i)
+    } else if let Ok(f) = value.parse::<f64>() {
+        Value::Float(f)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+pub(crate) fn save(config: &Table, dir: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(RESOLVED_CONFIG_FILE);
+    std::fs::write(&path, toml::to_string(config)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::CliArgs;
+
+    // the config resolved from args, saved and passed back with --config
+    fn round_trip(args: &[&str]) -> (Table, Table) {
+        let command = CliArgs::command();
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        let config = resolved(&command, &matches);
+        let dir = std::env::temp_dir().join(format!("sim_config_{}", std::process::id()));
+        save(&config, &dir).unwrap();
+        let loaded = load(&dir.join(RESOLVED_CONFIG_FILE));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let args: Vec<OsString> = vec!["sim".into()];
+        let matches = command.clone().try_get_matches_from(&args).unwrap();
+        let args = args_with_config(&command, &matches, args, &loaded.unwrap()).unwrap();
+        let matches = command.clone().try_get_matches_from(&args).unwrap();
+        (config, resolved(&command, &matches))
+    }
+
+    #[test]
+    fn test_resolved_config_round_trip() {
+        // the defaults of --hedge-ratio and --strategy-plugin-config require flags not given
+        let (config, reparsed) = round_trip(&["sim"]);
+        assert!(config.is_empty());
+        assert_eq!(reparsed, config);
+
+        let (config, reparsed) = round_trip(&[
+            "sim",
+            "--fee-rate",
+            "0.0002",
+            "--hedge-band",
+            "0.5",
+            "--initial-balance",
+            "USDT=50000",
+        ]);
+        assert_eq!(config.get("fee_rate"), Some(&Value::Float(0.0002)));
+        assert!(!config.contains_key("hedge_ratio"));
+        assert_eq!(reparsed, config);
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use binance_republisher::capture;
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ConvertArgs {
+    // csv or zip files of Binance, those of --symbol and --date (--end-date) given before
+    // `convert` when none
+    files: Vec<PathBuf>,
+
+    // convert again the files which have a capture
+    #[clap(long, action)]
+    force: bool,
+}
+
+// the zip or csv file a capture was converted from
+fn source_of(capture: &Path) -> Result<PathBuf, anyhow::Error> {
+    ["zip", "csv"]
+        .into_iter()
+        .map(|extension| capture.with_extension(extension))
+        .find(|source| source.exists())
+        .with_context(|| format!("no zip or csv file to convert {} from", capture.display()))
+}
+
+// Writes the capture of each file next to it, e.g. trades/2024-01-01.cap, which the runs
+// replay in place of the file. dated are the files of the run flags, a converted one given by
+// its capture.
+pub(crate) fn run_convert(args: &ConvertArgs, dated: Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    let files = if args.files.is_empty() {
+        dated
+    } else {
+        args.files.clone()
+    };
+    if files.is_empty() {
+        bail!("no files to convert, give them or --symbol and --date");
+    }
+    for file in files {
+        let (source, dest) = if capture::is_capture(&file) {
+            (source_of(&file)?, file)
+        } else {
+            (file.clone(), file.with_extension(capture::EXTENSION))
+        };
+        if dest.exists() && !args.force {
+            println!(
+                "{} is converted already, pass --force to convert it again",
+                source.display()
+            );
+            continue;
+        }
+        let started_at = Instant::now();
+        let stats = capture::convert(&source, &dest)?;
+        println!(
+            "Converted {} to {}: {} lines in {:.1}s",
+            source.display(),
+            dest.display(),
+            stats.lines,
+            started_at.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
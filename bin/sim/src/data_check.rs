@@ -0,0 +1,526 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use binance_republisher::capture;
+use chrono::NaiveDate;
+
+const DOWNLOADER: &str = "binance_data_download";
+// the downloader writes below <path>/future_um
+const DOWNLOAD_MARKET: &str = "future_um";
+// YYYY-MM of a YYYY-MM-DD date, the name of the monthly files
+const MONTH_LEN: usize = 7;
+
+// The trades replayed next to the booktickers, the aggregated ones and klines are far smaller
+// when the single trades do not matter. Resampled are the bars and bookticker snapshots of
+// binance_data_download resample, the booktickers coarse as well.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum TradeData {
+    #[default]
+    Trades,
+    AggTrades,
+    Klines(String),
+    Resampled(String),
+}
+
+impl TradeData {
+    // the directories of the trade and the bookticker files in the layout of
+    // binance_data_download
+    fn dir_names(&self) -> [String; 2] {
+        match self {
+            TradeData::Trades => ["trades".to_string(), "bookticker".to_string()],
+            TradeData::AggTrades => ["agg_trades".to_string(), "bookticker".to_string()],
+            TradeData::Klines(interval) => [format!("klines_{interval}"), "bookticker".to_string()],
+            TradeData::Resampled(interval) => [
+                format!("resampled_klines_{interval}"),
+                format!("resampled_bookticker_{interval}"),
+            ],
+        }
+    }
+
+    // the --products of binance_data_download, None for the files it does not download
+    fn products(&self) -> Option<String> {
+        match self {
+            TradeData::Trades => Some("trades,bookticker".to_string()),
+            TradeData::AggTrades => Some("agg-trades,bookticker".to_string()),
+            TradeData::Klines(interval) => Some(format!("klines-{interval},bookticker")),
+            TradeData::Resampled(_) => None,
+        }
+    }
+}
+
+impl FromStr for TradeData {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        match s.as_str() {
+            "trades" => Ok(TradeData::Trades),
+            "agg-trades" | "aggtrades" => Ok(TradeData::AggTrades),
+            _ => match (s.strip_prefix("klines-"), s.strip_prefix("resampled-")) {
+                (Some(interval), _) if !interval.is_empty() => {
+                    Ok(TradeData::Klines(interval.to_string()))
+                }
+                (_, Some(interval)) if !interval.is_empty() => {
+                    Ok(TradeData::Resampled(interval.to_string()))
+                }
+                _ => Err(format!(
+                    "unknown trade data {s}, expected trades, agg-trades, klines-<interval> or resampled-<interval>"
+                )),
+            },
+        }
+    }
+}
+
+// the capture of path when it was converted, else path
+fn converted(path: PathBuf) -> PathBuf {
+    let capture = path.with_extension(capture::EXTENSION);
+    if capture.exists() {
+        capture
+    } else {
+        path
+    }
+}
+
+// The trade and bookticker files of a symbol on a date, in the layout of binance_data_download.
+// The monthly file holding the date is taken when the daily one was not downloaded, and the
+// capture of a file when it was converted.
+pub(crate) fn dated_paths(
+    root_path: &Path,
+    symbol: &str,
+    date: &str,
+    trade_data: &TradeData,
+) -> [PathBuf; 2] {
+    trade_data
+        .dir_names()
+        .map(|kind| dated_path(root_path, symbol, date, &kind))
+}
+
+// the file of a symbol on a date in the directory of kind, see dated_paths
+fn dated_path(root_path: &Path, symbol: &str, date: &str, kind: &str) -> PathBuf {
+    let dir = root_path.join(symbol).join(kind);
+    let daily = converted(dir.join(format!("{date}.zip")));
+    match date.get(..MONTH_LEN) {
+        Some(month) if !daily.exists() => {
+            let monthly = converted(dir.join(format!("{month}.zip")));
+            if monthly.exists() {
+                monthly
+            } else {
+                daily
+            }
+        }
+        _ => daily,
+    }
+}
+
+// The mark and index price candles of interval of a symbol on the dates, a monthly file once
+// for all of its days
+pub(crate) fn mark_price_inputs(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    interval: &str,
+) -> Vec<PathBuf> {
+    let kinds = [
+        format!("mark_price_klines_{interval}"),
+        format!("index_price_klines_{interval}"),
+    ];
+    kind_inputs(root_path, symbol, dates, &kinds)
+}
+
+// The klines of interval of a symbol on the dates, without the booktickers of
+// TradeData::Klines, a monthly file once for all of its days
+pub(crate) fn kline_inputs(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    interval: &str,
+) -> Vec<PathBuf> {
+    kind_inputs(root_path, symbol, dates, &[format!("klines_{interval}")])
+}
+
+// the files of the directories of kinds on the dates, a monthly file once
+fn kind_inputs(root_path: &Path, symbol: &str, dates: &[String], kinds: &[String]) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    dates
+        .iter()
+        .flat_map(|date| {
+            kinds
+                .iter()
+                .map(|kind| dated_path(root_path, symbol, date, kind))
+        })
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+// The files of the dates in order, a monthly file once for all of its days
+pub(crate) fn dated_inputs(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    trade_data: &TradeData,
+) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    dates
+        .iter()
+        .flat_map(|date| dated_paths(root_path, symbol, date, trade_data))
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+// whether path is a monthly file, which holds more days than the ones replayed
+pub(crate) fn is_monthly(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| NaiveDate::parse_from_str(&format!("{stem}-01"), "%Y-%m-%d").is_ok())
+}
+
+// from the start of the first date to the end of the last one
+pub(crate) fn dates_time_range(dates: &[String]) -> Result<Range<SystemTime>, anyhow::Error> {
+    let day_start = |date: &String| -> Result<SystemTime, anyhow::Error> {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("invalid date {}, expect YYYY-MM-DD", date))?;
+        let secs = date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        Ok(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    };
+    let (Some(first), Some(last)) = (dates.first(), dates.last()) else {
+        bail!("no dates");
+    };
+    Ok(day_start(first)?..day_start(last)? + Duration::from_secs(24 * 60 * 60))
+}
+
+// why path can not be replayed, None when it can. A zip has to hold the one csv file the
+// republisher reads, a download cut short has no central directory and fails to open.
+fn problem(path: &Path) -> Option<String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Some(e.to_string()),
+    };
+    if capture::is_capture(path) {
+        return capture::read_header(path).err().map(|e| format!("{:#}", e));
+    }
+    if path.extension().is_none_or(|ext| ext != "zip") {
+        return None;
+    }
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(e) => return Some(format!("corrupted zip, {e}")),
+    };
+    if archive.len() != 1 {
+        return Some(format!("zip holds {} files, expected 1", archive.len()));
+    }
+    archive
+        .by_index(0)
+        .err()
+        .map(|e| format!("corrupted zip, {e}"))
+}
+
+// (path, problem) of the files which can not be replayed
+pub(crate) fn missing_inputs(paths: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    paths
+        .iter()
+        .filter_map(|path| problem(path).map(|problem| (path.clone(), problem)))
+        .collect()
+}
+
+// Downloads the trade and bookticker files of symbol from start_date to end_date
+pub(crate) fn download(
+    root_path: &Path,
+    symbol: &str,
+    start_date: &str,
+    end_date: &str,
+    trade_data: &TradeData,
+) -> Result<(), anyhow::Error> {
+    let Some(products) = trade_data.products() else {
+        bail!(
+            "{} makes resampled data with resample, it can not be downloaded",
+            DOWNLOADER
+        );
+    };
+    run_downloader(root_path, symbol, start_date, end_date, &products)
+}
+
+// Runs binance_data_download, found next to the sim binary, for the products of symbol from
+// start_date to end_date. Files already downloaded and intact are skipped by it.
+fn run_downloader(
+    root_path: &Path,
+    symbol: &str,
+    start_date: &str,
+    end_date: &str,
+    products: &str,
+) -> Result<(), anyhow::Error> {
+    if root_path
+        .file_name()
+        .is_none_or(|name| name != DOWNLOAD_MARKET)
+    {
+        bail!(
+            "only data under a {} root path can be downloaded, not {}",
+            DOWNLOAD_MARKET,
+            root_path.display()
+        );
+    }
+    let downloader = std::env::current_exe()?
+        .with_file_name(format!("{DOWNLOADER}{}", std::env::consts::EXE_SUFFIX));
+    if !downloader.exists() {
+        bail!(
+            "{} not found, build it with cargo build --release --bin {}",
+            downloader.display(),
+            DOWNLOADER
+        );
+    }
+    let compact = |date: &str| -> Result<String, anyhow::Error> {
+        Ok(NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("invalid date {}, expect YYYY-MM-DD", date))?
+            .format("%Y%m%d")
+            .to_string())
+    };
+    println!("Downloading {} from {} to {}", symbol, start_date, end_date);
+    let status = Command::new(&downloader)
+        .arg("--path")
+        .arg(root_path.parent().unwrap_or(Path::new("")))
+        .args(["--symbol", symbol])
+        .args(["--start-date", &compact(start_date)?])
+        .args(["--end-date", &compact(end_date)?])
+        .args(["--products", products])
+        .arg("download")
+        .status()
+        .with_context(|| format!("failed to run {}", downloader.display()))?;
+    if !status.success() {
+        bail!("{} {}", DOWNLOADER, status);
+    }
+    Ok(())
+}
+
+// Checks the files of every symbol and date under root_path before anything runs, the days
+// with missing files downloaded first when download_missing is set. Fails listing what is
+// still missing rather than halfway through a run or a batch.
+pub(crate) fn ensure_dated_inputs(
+    root_path: &Path,
+    symbols: &[String],
+    dates: &[String],
+    trade_data: &TradeData,
+    download_missing: bool,
+) -> Result<(), anyhow::Error> {
+    let missing_dates = |symbol: &str| -> Vec<&String> {
+        dates
+            .iter()
+            .filter(|date| {
+                !missing_inputs(&dated_paths(root_path, symbol, date, trade_data)).is_empty()
+            })
+            .collect()
+    };
+    if download_missing {
+        for symbol in symbols {
+            let missing = missing_dates(symbol);
+            if let (Some(first), Some(last)) = (missing.first(), missing.last()) {
+                if let Err(e) = download(root_path, symbol, first, last, trade_data) {
+                    eprintln!("failed to download the missing data: {:#}", e);
+                }
+            }
+        }
+    }
+    let paths: Vec<PathBuf> = symbols
+        .iter()
+        .flat_map(|symbol| dated_inputs(root_path, symbol, dates, trade_data))
+        .collect();
+    ensure_inputs(&paths)
+}
+
+// The mark and index price candles of interval of symbol on the dates, checked and downloaded
+// as ensure_dated_inputs does
+pub(crate) fn ensure_mark_price_inputs(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    interval: &str,
+    download_missing: bool,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    ensure_downloaded(
+        root_path,
+        symbol,
+        dates,
+        &format!("mark-price-klines-{interval},index-price-klines-{interval}"),
+        download_missing,
+        |dates| mark_price_inputs(root_path, symbol, dates, interval),
+    )
+}
+
+// The klines of interval of symbol on the dates, checked and downloaded as
+// ensure_dated_inputs does
+pub(crate) fn ensure_kline_inputs(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    interval: &str,
+    download_missing: bool,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    ensure_downloaded(
+        root_path,
+        symbol,
+        dates,
+        &format!("klines-{interval}"),
+        download_missing,
+        |dates| kline_inputs(root_path, symbol, dates, interval),
+    )
+}
+
+// the inputs of the dates, the products of the days with missing ones downloaded first when
+// download_missing is set
+fn ensure_downloaded(
+    root_path: &Path,
+    symbol: &str,
+    dates: &[String],
+    products: &str,
+    download_missing: bool,
+    inputs: impl Fn(&[String]) -> Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let missing: Vec<&String> = dates
+        .iter()
+        .filter(|date| !missing_inputs(&inputs(std::slice::from_ref(date))).is_empty())
+        .collect();
+    if let (true, Some(first), Some(last)) = (download_missing, missing.first(), missing.last()) {
+        if let Err(e) = run_downloader(root_path, symbol, first, last, products) {
+            eprintln!("failed to download the missing data: {:#}", e);
+        }
+    }
+    let paths = inputs(dates);
+    ensure_inputs(&paths)?;
+    Ok(paths)
+}
+
+// Fails listing the files of paths which can not be replayed, if any
+pub(crate) fn ensure_inputs(paths: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let missing = missing_inputs(paths);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let listing: String = missing
+        .iter()
+        .map(|(path, problem)| format!("\n  {}: {}", path.display(), problem))
+        .collect();
+    bail!(
+        "{} of {} input files can not be replayed:{}\ndownload them with {DOWNLOADER} or pass \
+         --download-missing",
+        missing.len(),
+        paths.len(),
+        listing
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // a zip of the files, each holding a line of csv
+    fn write_zip(path: &Path, files: &[&str]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for file in files {
+            zip.start_file(*file, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"1,2,3\n").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn dates(dates: &[&str]) -> Vec<String> {
+        dates.iter().map(|date| date.to_string()).collect()
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| {
+                let kind = path
+                    .parent()
+                    .unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy();
+                format!("{}/{}", kind, path.file_name().unwrap().to_string_lossy())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dated_inputs_in_order_and_once() {
+        let root = std::env::temp_dir().join(format!("data_check_inputs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for kind in ["trades", "bookticker"] {
+            let dir = root.join("BTCUSDT").join(kind);
+            write_zip(&dir.join("2024-01-31.zip"), &["a.csv"]);
+            write_zip(&dir.join("2024-02.zip"), &["a.csv"]);
+        }
+        let days = dates(&["2024-01-31", "2024-02-01", "2024-02-02"]);
+        let paths = dated_inputs(&root, "BTCUSDT", &days, &TradeData::Trades);
+        // the monthly file holds both days of february
+        assert_eq!(
+            names(&paths),
+            vec![
+                "trades/2024-01-31.zip",
+                "bookticker/2024-01-31.zip",
+                "trades/2024-02.zip",
+                "bookticker/2024-02.zip",
+            ]
+        );
+        assert!(!is_monthly(&paths[0]));
+        assert!(is_monthly(&paths[2]));
+        assert!(ensure_inputs(&paths).is_ok());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_gaps_and_broken_files_are_listed() {
+        let root = std::env::temp_dir().join(format!("data_check_gaps_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("BTCUSDT").join("agg_trades");
+        let bookticker = root.join("BTCUSDT").join("bookticker");
+        for date in ["2024-01-01", "2024-01-03"] {
+            write_zip(&dir.join(format!("{date}.zip")), &["a.csv"]);
+            write_zip(&bookticker.join(format!("{date}.zip")), &["a.csv"]);
+        }
+        // a download cut short, and a zip of two files
+        std::fs::write(bookticker.join("2024-01-04.zip"), b"PK\x03\x04").unwrap();
+        write_zip(&dir.join("2024-01-04.zip"), &["a.csv", "b.csv"]);
+
+        let days = dates(&["2024-01-01", "2024-01-02", "2024-01-03", "2024-01-04"]);
+        let paths = dated_inputs(&root, "BTCUSDT", &days, &TradeData::AggTrades);
+        let missing = missing_inputs(&paths);
+        let missing_paths: Vec<PathBuf> = missing.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            names(&missing_paths),
+            vec![
+                "agg_trades/2024-01-02.zip",
+                "bookticker/2024-01-02.zip",
+                "agg_trades/2024-01-04.zip",
+                "bookticker/2024-01-04.zip",
+            ]
+        );
+        assert_eq!(missing[2].1, "zip holds 2 files, expected 1");
+        assert!(missing[3].1.starts_with("corrupted zip"));
+        let e = ensure_inputs(&paths).unwrap_err().to_string();
+        assert!(e.starts_with("4 of 8 input files can not be replayed"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_dates_time_range() {
+        let range = dates_time_range(&dates(&["2024-01-01", "2024-01-02"])).unwrap();
+        assert_eq!(range.start, UNIX_EPOCH + Duration::from_secs(1704067200));
+        assert_eq!(
+            range.end,
+            range.start + Duration::from_secs(2 * 24 * 60 * 60)
+        );
+        assert!(dates_time_range(&[]).is_err());
+        assert!(dates_time_range(&dates(&["2024-13-01"])).is_err());
+    }
+}
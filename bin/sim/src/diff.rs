@@ -0,0 +1,168 @@
+use std::{fmt::Write as _, path::Path};
+
+use market_agent::results::{first_diverging_fill, stat_deltas, Fill, RunResults};
+
+fn fill_brief(fill: Option<&Fill>) -> String {
+    match fill {
+        Some(fill) => format!(
+            "time_ms={} order_id={} {} price={} qty={}",
+            fill.time_ms,
+            fill.order_id,
+            if fill.is_buy { "buy" } else { "sell" },
+            fill.price,
+            fill.quantity
+        ),
+        None => "-".to_string(),
+    }
+}
+
+fn value_brief(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.6}", v))
+}
+
+// compare the results directories of two runs written with --results-dir
+pub(crate) fn diff_runs(run_a: &Path, run_b: &Path) -> Result<(), anyhow::Error> {
+    let a = RunResults::load(run_a)?;
+    let b = RunResults::load(run_b)?;
+    print!("{}", diff_report(&a, &b)?);
+    Ok(())
+}
+
+// the fills, equity points and metrics the runs differ in
+fn diff_report(a: &RunResults, b: &RunResults) -> Result<String, std::fmt::Error> {
+    let mut out = String::new();
+    writeln!(out, "--- Fills ---")?;
+    writeln!(
+        out,
+        "a: {} fills, b: {} fills",
+        a.fills.len(),
+        b.fills.len()
+    )?;
+    match first_diverging_fill(a, b) {
+        Some((i, fill_a, fill_b)) => {
+            writeln!(out, "First diverging fill #{}", i)?;
+            writeln!(out, "a: {}", fill_brief(fill_a))?;
+            writeln!(out, "b: {}", fill_brief(fill_b))?;
+        }
+        None => writeln!(out, "Fills are identical")?,
+    }
+
+    writeln!(out, "--- Equity ---")?;
+    writeln!(
+        out,
+        "a: {} points, b: {} points",
+        a.equity.len(),
+        b.equity.len()
+    )?;
+    let first_diverging_equity = a
+        .equity
+        .iter()
+        .zip(b.equity.iter())
+        .position(|(x, y)| x.0 != y.0 || (x.1 - y.1).abs() > 1e-9);
+    match first_diverging_equity {
+        Some(i) => writeln!(
+            out,
+            "First diverging point #{}: a={:?} b={:?}",
+            i, a.equity[i], b.equity[i]
+        )?,
+        None if a.equity.len() == b.equity.len() => writeln!(out, "Equity curves are identical")?,
+        None => writeln!(out, "Equity curves are identical up to the shorter one")?,
+    }
+
+    writeln!(out, "--- Stats ---")?;
+    writeln!(
+        out,
+        "{:<28} {:>20} {:>20} {:>20}",
+        "metric", "a", "b", "b - a"
+    )?;
+    let mut unchanged = 0;
+    for (metric, value_a, value_b) in stat_deltas(a, b) {
+        let delta = match (value_a, value_b) {
+            (Some(x), Some(y)) => Some(y - x),
+            _ => None,
+        };
+        if delta == Some(0.0) {
+            unchanged += 1;
+            continue;
+        }
+        writeln!(
+            out,
+            "{:<28} {:>20} {:>20} {:>20}",
+            metric,
+            value_brief(value_a),
+            value_brief(value_b),
+            value_brief(delta)
+        )?;
+    }
+    writeln!(out, "{} metrics unchanged", unchanged)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(time_ms: u64, price: f64) -> Fill {
+        Fill {
+            time_ms,
+            order_id: "1".into(),
+            is_buy: true,
+            price,
+            quantity: 0.5,
+            fee: 0.0,
+            tag: String::new(),
+            symbol: "BTCUSDT".into(),
+            is_maker: true,
+            inventory: 0.5,
+            mid: price,
+        }
+    }
+
+    fn results(fills: Vec<Fill>, equity: Vec<(u64, f64)>, stats: &[(&str, f64)]) -> RunResults {
+        RunResults {
+            fills,
+            equity,
+            stats: stats.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_report() {
+        let a = results(
+            vec![fill(1000, 100.0), fill(2000, 101.0)],
+            vec![(1000, 10.0), (2000, 11.0)],
+            &[("profit", 1.0), ("fill_count", 2.0), ("max_drawdown", 0.5)],
+        );
+        let b = results(
+            vec![fill(1000, 100.0), fill(2000, 102.0)],
+            vec![(1000, 10.0), (2000, 12.0), (3000, 12.5)],
+            &[("profit", 2.5), ("fill_count", 2.0), ("sharpe", 1.2)],
+        );
+        let report = diff_report(&a, &b).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines.contains(&"a: 2 fills, b: 2 fills"));
+        assert!(lines.contains(&"First diverging fill #1"));
+        assert!(lines.contains(&"First diverging point #1: a=(2000, 11.0) b=(2000, 12.0)"));
+        let stat = |metric: &str| {
+            lines
+                .iter()
+                .find(|line| line.split_whitespace().next() == Some(metric))
+                .map(|line| line.split_whitespace().skip(1).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            stat("profit").unwrap(),
+            vec!["1.000000", "2.500000", "1.500000"]
+        );
+        // a metric of one run only has no delta
+        assert_eq!(stat("max_drawdown").unwrap(), vec!["0.500000", "-", "-"]);
+        assert_eq!(stat("sharpe").unwrap(), vec!["-", "1.200000", "-"]);
+        assert_eq!(stat("fill_count"), None);
+        assert!(lines.contains(&"1 metrics unchanged"));
+
+        let report = diff_report(&a, &a).unwrap();
+        assert!(report.contains("Fills are identical"));
+        assert!(report.contains("Equity curves are identical\n"));
+        assert!(report.contains("3 metrics unchanged"));
+    }
+}
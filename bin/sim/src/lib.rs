@@ -0,0 +1,1556 @@
+mod batch;
+mod benchmark;
+mod config;
+mod convert;
+mod data_check;
+mod diff;
+mod results_db;
+mod robustness;
+mod variant;
+mod walk_forward;
+
+use anyhow::{bail, Context};
+use audit::debug_sink::DebugSinkBuilder;
+use audit::order_audit::OrderAuditBuilder;
+use binance_republisher::binance_republisher::BinanceRepublisherBuilder;
+use binance_republisher::tick_cache::TickCache;
+use binance_republisher::validation::ValidationConfig;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::InitialBalance;
+use data_check::TradeData;
+use indicators::{indicators::IndicatorPublisherBuilder, order_flow::OrderFlowPublisherBuilder};
+use market_agent::fill_probability::FillProbability;
+use market_agent::latency::{LatencyModel, LatencyProfile};
+use market_agent::market_agent::{MarketAgentBuilder, SelfTradePrevention};
+use market_agent::rate_limit::RateLimit;
+use market_agent::slippage::SlippageModel;
+use metrics::metrics::MetricsBuilder;
+use pure_market_maker::{
+    avellaneda_stoikov::AvellanedaStoikovParams,
+    fill_intensity::FillIntensityCalibration,
+    quote_sizing::QuoteSizing,
+    vol_estimator::{VolEstimator, VolGapHandling},
+    AmmStrategy, DegradedDataResponse, FairPriceSource, InventoryLimits, PricingModel,
+    QuoteAnchoring, QuoteTolerance, VolPriceSource,
+};
+use risk_guard::liquidator::{LiquidationLimits, LiquidatorBuilder};
+use risk_guard::risk_guard::{RiskGuardBuilder, RiskLimits};
+use simple_backtest::simple_backtest::SimpleBacktest;
+use simulation::clock_audit::ClockAudit;
+use simulation::engine::SimulationEngineBuilder;
+use simulation::fault_injection::{FaultInjection, TopicFaults};
+use simulation::namespace::NamespacedBuilder;
+use std::{
+    ffi::OsString,
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use stepper::grpc_bridge::GrpcBridge;
+use stepper::plugin::{PluginRegistry, StrategyPlugin};
+use stepper::session::{SessionCalendar, SessionWindow};
+use stepper::stepper::{DecisionTrigger, ReconcileConfig, StepperBuilder};
+use symbol_info::{SymbolInfo, SymbolInfoManager};
+use synthetic_feed::scenario::{Scenario, ScenarioBuilder, StressEvent};
+use synthetic_feed::synthetic_feed::{PriceProcess, SyntheticFeedBuilder, SyntheticFeedConfig};
+use synthetic_feed::time_compression::TimeCompressionBuilder;
+use taker_hedger::taker_hedger::TakerHedgerBuilder;
+use tracing::{error, info, subscriber::SetGlobalDefaultError};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::{
+    filter::{filter_fn, EnvFilter, LevelFilter},
+    layer::SubscriberExt,
+    Layer,
+};
+use upstair_type::module::ModuleBuilder;
+use upstair_type::run_output::RunOutput;
+use upstair_type::time::PlaybackControl;
+use variant::Variant;
+use vis::vis_module::{VisLink, VisModuleBuilder, VisWindow};
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // compare the results directories of two runs
+    Diff { run_a: PathBuf, run_b: PathBuf },
+    // run every (symbol, date) pair of a range with the other flags given before `batch`
+    Batch(batch::BatchArgs),
+    // run repeatedly with perturbed fees, latencies and fills, reporting the share of
+    // profitable runs
+    Robustness(robustness::RobustnessArgs),
+    // list, show and compare the runs stored with --results-db
+    Results(results_db::ResultsArgs),
+    // choose the parameters on a window of days and evaluate them on the days after it,
+    // rolling forward over a date range
+    WalkForward(walk_forward::WalkForwardArgs),
+    // write binary captures of the csv files, replayed without parsing
+    Convert(convert::ConvertArgs),
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Upstair simulation", long_about = None)]
+#[command(group(ArgGroup::new("output").args(["results_dir", "run_label"])))]
+#[command(group(ArgGroup::new("plugin").args(["strategy", "strategy_plugin"]).multiple(true)))]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // TOML file with the values of the flags not given on the command line, see config.rs
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    // every flag with the value it resolved to, written to the results directory
+    #[clap(skip)]
+    resolved_config: toml::Table,
+
+    // the command line the flags were parsed from, with the ones of the config file
+    #[clap(skip)]
+    args: Vec<OsString>,
+
+    #[clap(long, short = 'p')]
+    path: Vec<PathBuf>,
+
+    #[clap(long, default_value = "BTCUSDT")]
+    symbol: Option<String>,
+
+    #[clap(long, short = 'v', default_value_t = tracing::Level::ERROR)]
+    log_level: tracing::Level,
+
+    // the levels of some modules over --log-level, by the crate or module path the log is in,
+    // e.g. market_agent=debug,stepper=trace
+    #[clap(long, value_parser = log_directives)]
+    log: Option<String>,
+
+    #[clap(long, action)]
+    no_progress: bool,
+
+    #[clap(long, short = 'g', action)]
+    vis: bool,
+
+    // republish the market data to a null consumer and report the ticks/sec and MB/sec of
+    // the reader and the engine, without the strategy and the market
+    #[clap(long, action, conflicts_with = "vis")]
+    benchmark: bool,
+
+    // step the strategy on the candles of --trade-data klines-<interval> instead of in the
+    // engine, filling the quotes a candle trades through, for fast first passes over the
+    // parameters
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = ["vis", "benchmark", "synthetic", "plugin", "grpc_strategy", "variant"]
+    )]
+    simple_backtest: bool,
+
+    // run the vis module without its window and write its data to Parquet files in vis of
+    // the results directory
+    #[clap(long, action, conflicts_with = "vis", requires = "output")]
+    vis_export: bool,
+
+    // run the vis module without its window and serve a dashboard and a JSON API on this
+    // address, e.g. 127.0.0.1:8080, to watch a simulation on a headless server
+    #[clap(long, conflicts_with = "vis")]
+    vis_web: Option<SocketAddr>,
+
+    // serve Prometheus metrics of the run on /metrics of this address, e.g. 127.0.0.1:9100
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    // publish the volatility, book imbalance, momentum and order flow indicators of the symbol
+    // on the signals topic, served with --metrics-addr
+    #[clap(long, action)]
+    signals: bool,
+
+    // cap the simulation at N times real time, the vis window can change it and pause
+    #[clap(long)]
+    speed: Option<f64>,
+
+    // first day to replay, --start-date with --end-date
+    #[clap(long, short = 'd', alias = "start-date")]
+    date: Option<String>,
+
+    // replay every day from --date to this one, both inclusive, in one continuous run
+    #[clap(long, requires = "date")]
+    end_date: Option<String>,
+
+    #[clap(long, short = 'r', default_value = "data/future_um")]
+    root_path: PathBuf,
+
+    // run binance_data_download for the days whose files are missing or corrupted
+    #[clap(long, action)]
+    download_missing: bool,
+
+    // the trades replayed under the root path, trades, agg-trades, klines-<interval> or
+    // resampled-<interval> for the bars and bookticker snapshots of the downloader resample
+    #[clap(long, default_value = "trades")]
+    trade_data: TradeData,
+
+    // the interval of the mark and index price candles replayed next to the trades, e.g. 1m,
+    // for the mark price and basis of the futures
+    #[clap(long)]
+    mark_price_klines: Option<String>,
+
+    // replay a synthetic market instead of the data, gbm or ou (Ornstein-Uhlenbeck), for the
+    // days of --date to --end-date or 2024-01-01
+    #[clap(long)]
+    synthetic: Option<PriceProcess>,
+
+    // annualized volatility of the synthetic price
+    #[clap(long, default_value_t = 0.5)]
+    synthetic_volatility: f64,
+
+    #[clap(long, default_value_t = 40000.0)]
+    synthetic_initial_price: f64,
+
+    #[clap(long, default_value_t = 0)]
+    synthetic_seed: u64,
+
+    // stress the market data, timed from the first tick, e.g.
+    // crash:at_secs=3600,drop=0.1,over_secs=30,recover_secs=300,
+    // spread:at_secs=3600,for_secs=600,factor=5 or gap:at_secs=3600,for_secs=300
+    #[clap(long)]
+    scenario: Vec<StressEvent>,
+
+    // replay the market data this many times denser, e.g. 10 for ten times the ticks per
+    // second, in the same order
+    #[clap(long)]
+    time_compression: Option<f64>,
+
+    // quote with the full Avellaneda–Stoikov model
+    #[clap(long, action)]
+    avellaneda_stoikov: bool,
+
+    #[clap(long, default_value_t = 0.1)]
+    as_gamma: f64,
+
+    // estimated from recent trades if not provided
+    #[clap(long)]
+    as_k: Option<f64>,
+
+    // k of a fill_calibration file, as_k takes precedence
+    #[clap(long)]
+    as_calibration: Option<PathBuf>,
+
+    #[clap(long, default_value_t = 24 * 60 * 60)]
+    as_horizon_secs: u64,
+
+    // wap, mid, microprice or mark. The microprice is computed by an order flow module added
+    // for it, the mark price needs --mark-price-klines
+    #[clap(long, default_value = "wap")]
+    fair_price_source: FairPriceSource,
+
+    // wap, mid or trade
+    #[clap(long, default_value = "wap")]
+    vol_price_source: VolPriceSource,
+
+    // stdev, ewma, parkinson, garman-klass or bipower
+    #[clap(long, default_value = "stdev")]
+    vol_estimator: VolEstimator,
+
+    // ignore, reset or rescale the price change across a gap of more than --vol-gap-secs in
+    // the vol samples
+    #[clap(long, default_value = "ignore")]
+    vol_gap: VolGapHandling,
+
+    #[clap(long, default_value_t = 60)]
+    vol_gap_secs: u64,
+
+    // no quotes until the vol samples span the window of the estimate, again after a reset
+    #[clap(long, action)]
+    vol_warm_up: bool,
+
+    // model, touch or improve: quote the model prices as they are, never better than the
+    // best bid/ask, or at most one price tick inside them
+    #[clap(long, default_value = "touch")]
+    quote_anchoring: QuoteAnchoring,
+
+    #[clap(long, default_value_t = 0.1)]
+    price_tick: f64,
+
+    // keep open quotes until the desired price moves further than this,
+    // quotes are replaced every round if not provided
+    #[clap(long)]
+    quote_price_tolerance: Option<f64>,
+
+    #[clap(long, default_value_t = 0.0)]
+    quote_qty_tolerance: f64,
+
+    // how large the quotes are, fixed:quantity=<q>, headroom:fraction=<f> of the room to the
+    // inventory limits or the balances, inverse-vol:quantity=<q>,vol=<reference vol> or
+    // notional:quantity=<q>,max=<notional>
+    #[clap(long, default_value = "fixed:quantity=0.01")]
+    quote_sizing: QuoteSizing,
+
+    // quote sizes are snapped down to whole lots of this base asset quantity, a side under one
+    // lot is not quoted
+    #[clap(long, default_value_t = 0.001)]
+    lot_size: f64,
+
+    // stop quoting the side adding risk once inventory away from target is over the limit,
+    // in base asset quantity
+    #[clap(long)]
+    max_long_inventory: Option<f64>,
+
+    #[clap(long)]
+    max_short_inventory: Option<f64>,
+
+    // cross the spread to bring inventory back within the limits
+    #[clap(long, action)]
+    reduce_inventory: bool,
+
+    // quote only inside these UTC windows, e.g. 06:00-22:00, always if none is given
+    #[clap(long)]
+    session_window: Vec<SessionWindow>,
+
+    // no quotes inside these UTC windows, e.g. 23:55-00:05 around a funding timestamp
+    #[clap(long)]
+    session_blackout: Vec<SessionWindow>,
+
+    // flag stale books and trade gaps and drop outlier trades before republishing
+    #[clap(long, action)]
+    validate_data: bool,
+
+    // the book is stale once no book ticker arrived for this long
+    #[clap(long, default_value_t = 5000)]
+    stale_book_ms: u64,
+
+    // trades further than this fraction from the last trade price are outliers
+    #[clap(long, default_value_t = 0.05)]
+    max_trade_jump: f64,
+
+    // ignore, widen or pull: what the strategy does while the data is flagged
+    #[clap(long, default_value = "ignore")]
+    degraded_data: DegradedDataResponse,
+
+    // abort when a data file has more unparseable lines than this
+    #[clap(long)]
+    max_parse_errors: Option<u64>,
+
+    // hold ticks back this long to sort in the ones out of time order, e.g. a trade file
+    // running behind the bookticker file, later ones are published at the time before them
+    #[clap(long)]
+    reorder_window_ms: Option<u64>,
+
+    // publish the trades within this many ms of each other together, at the time of the last
+    // one, which delays them by up to the window for fewer wake ups of the modules
+    #[clap(long)]
+    trade_batch_ms: Option<u64>,
+
+    // halt trading once equity falls this fraction below its peak
+    #[clap(long)]
+    max_drawdown: Option<f64>,
+
+    // halt trading once this much quote asset is lost within a minute
+    #[clap(long)]
+    max_loss_per_minute: Option<f64>,
+
+    // halt trading once this fraction of the last 100 orders are rejected
+    #[clap(long)]
+    max_reject_rate: Option<f64>,
+
+    // sell at market once the base asset balance is this far above its initial balance
+    #[clap(long)]
+    liquidate_long_over: Option<f64>,
+
+    // buy at market once the base asset balance is this far below its initial balance
+    #[clap(long)]
+    liquidate_short_over: Option<f64>,
+
+    // close the position at market and halt trading once this much quote asset is lost
+    #[clap(long)]
+    liquidate_loss_over: Option<f64>,
+
+    // take the inventory of the strategy off at market once the base asset balance is this far
+    // from its initial balance, a taker next to the maker on the same account
+    #[clap(long)]
+    hedge_band: Option<f64>,
+
+    // base asset the hedger trades for each base asset of inventory, a fraction hedges part
+    // of it
+    #[clap(long, default_value_t = 1.0, requires = "hedge_band", value_parser = positive)]
+    hedge_ratio: f64,
+
+    // hedge on this correlated symbol instead, its data under the root path is replayed as
+    // well, e.g. ETHUSDT with --hedge-ratio 20 and an initial ETH balance to sell
+    #[clap(long, requires = "hedge_band")]
+    hedge_symbol: Option<String>,
+
+    // wait this long after a hedge before sending the next one
+    #[clap(long, requires = "hedge_band")]
+    hedge_cooldown_ms: Option<u64>,
+
+    // follow the book of this symbol too, for strategies that quote off a correlated market,
+    // its data under the root path is replayed as well, may be given more than once
+    #[clap(long)]
+    reference_symbol: Vec<String>,
+
+    // terminate the simulation when trading is halted
+    #[clap(long, action)]
+    halt_terminates: bool,
+
+    // at the end of the run the strategies cancel their orders, and the simulation goes on
+    // this long for them to be processed before the final report
+    #[clap(long)]
+    shutdown_grace_ms: Option<u64>,
+
+    // also take the book for the inventory away from target at the end of the run, with a
+    // shutdown grace of 1000ms unless given
+    #[clap(long, action)]
+    flatten_on_shutdown: bool,
+
+    // check that the modules publish at the time of the engine, strict panics at the first
+    // message out of time and lenient counts them
+    #[clap(long)]
+    clock_audit: Option<ClockAudit>,
+
+    // write the runs of the modules, with their sync and one_iteration, to a chrome trace file
+    // of this path, to open in chrome://tracing or ui.perfetto.dev
+    #[clap(long)]
+    trace_chrome: Option<PathBuf>,
+
+    // delay orders by latencies drawn from a profile written by latency_calibration
+    #[clap(long)]
+    latency_profile: Option<PathBuf>,
+
+    // cancels draw from --latency-profile unless given
+    #[clap(long)]
+    cancel_latency_profile: Option<PathBuf>,
+
+    // delay order results and account updates back to the strategy, immediate if not given
+    #[clap(long)]
+    ack_latency_profile: Option<PathBuf>,
+
+    #[clap(long, default_value_t = 0)]
+    latency_seed: u64,
+
+    // multiplies the latencies drawn from the profiles
+    #[clap(long, default_value_t = 1.0)]
+    latency_scale: f64,
+
+    // ASSET=AMOUNT the account starts with, repeated for each asset,
+    // 50000 of the quote asset and 1 of the base asset if not given
+    #[clap(long)]
+    initial_balance: Vec<InitialBalance>,
+
+    // fee rate of the symbol, charged on every fill
+    #[clap(long, default_value_t = 0.0)]
+    fee_rate: f64,
+
+    // chance a trade crossing a resting order fills it
+    #[clap(long, default_value_t = 1.0, value_parser = probability)]
+    fill_probability: f64,
+
+    #[clap(long, default_value_t = 0)]
+    fill_seed: u64,
+
+    // re-issue cancels for orders not acknowledged or cancelled within this time
+    #[clap(long)]
+    reconcile_timeout_ms: Option<u64>,
+
+    // give up an unanswered order after this many re-issued cancels
+    #[clap(long, default_value_t = 3)]
+    reconcile_max_retries: u32,
+
+    // minimum simulated time between two decisions of the strategy
+    #[clap(long, default_value_t = 100)]
+    decision_interval_ms: u64,
+
+    // decide after every book ticker update instead of every --decision-interval-ms
+    #[clap(long, action)]
+    decide_on_book_ticker: bool,
+
+    // decide with the strategy plugin of this name instead of the market maker, one of the
+    // shared libraries in --plugin-dir, see crates/stepper/plugin/maker_strategy.h for their
+    // C ABI
+    #[clap(long)]
+    strategy: Option<String>,
+
+    // the directory the strategy plugins are discovered in
+    #[clap(long, default_value = "plugins")]
+    plugin_dir: PathBuf,
+
+    // take the strategy plugins from this shared library instead of --plugin-dir, its only
+    // strategy unless --strategy chooses one
+    #[clap(long)]
+    strategy_plugin: Option<PathBuf>,
+
+    // the config string the plugin creates its strategy from
+    #[clap(long, default_value = "", requires = "plugin")]
+    strategy_plugin_config: String,
+
+    // decide with the strategy an external process serves over gRPC instead of the market
+    // maker, e.g. http://127.0.0.1:50051, see crates/stepper/proto/strategy_bridge.proto
+    #[clap(long, conflicts_with = "plugin")]
+    grpc_strategy: Option<String>,
+
+    // apply the actions of the gRPC strategy this long after its decision instead of the wall
+    // time of the call, for runs that repeat
+    #[clap(long, requires = "grpc_strategy")]
+    grpc_latency_ms: Option<u64>,
+
+    // deliver the market data to the strategy this feed latency after it is published and
+    // panic when a decision reads data newer than that, to check a backtest does not peek
+    #[clap(long)]
+    look_ahead_check_ms: Option<u64>,
+
+    // unreliable transport for a topic, e.g. order:drop=0.01,duplicate=0.01,delay=0.1,max_delay_ms=200
+    #[clap(long)]
+    fault: Vec<TopicFaults>,
+
+    #[clap(long, default_value_t = 0)]
+    fault_seed: u64,
+
+    // none, cancel-newest, cancel-oldest or reject, applied when our own orders would match
+    #[clap(long, default_value = "none")]
+    self_trade_prevention: SelfTradePrevention,
+
+    // none, fixed:<bps>, depth:<bps per level> or impact:<bps>, how far taker fills execute
+    // from the touch
+    #[clap(long, default_value = "none")]
+    slippage: SlippageModel,
+
+    // run another strategy with its own account on the same market data, with some flags
+    // changed, e.g. wide:quote_price_tolerance=2,reduce_inventory. Repeat to compare more,
+    // each writes its results to variant-<name> in the results directory
+    #[clap(long)]
+    variant: Vec<Variant>,
+
+    // write fills, equity curve and stats of the run to this directory
+    #[clap(long)]
+    results_dir: Option<PathBuf>,
+
+    // write the results to a directory of their own under the runs root instead, named after
+    // the UTC time the run started and the label, e.g. runs/20240101T093000-wide
+    #[clap(long)]
+    run_label: Option<String>,
+
+    #[clap(long, default_value = "runs")]
+    runs_root: PathBuf,
+
+    // store the config, metrics and files of the run in this SQLite database, `sim results`
+    // lists and compares them
+    #[clap(long, requires = "output")]
+    results_db: Option<PathBuf>,
+
+    // snapshot orders, balances and strategy variables to the results directory every this
+    // many simulated seconds
+    #[clap(long, requires = "output")]
+    state_history_secs: Option<u64>,
+
+    // send the account balances and their mark-to-market equity every this many simulated
+    // seconds, the equity curve is sampled then too
+    #[clap(long)]
+    account_snapshot_secs: Option<u64>,
+
+    // reject the order requests of an account over this many in a window, e.g. 300/10s
+    #[clap(long)]
+    order_rate_limit: Option<RateLimit>,
+
+    // reject the cancel requests of an account over this many in a window, e.g. 100/1s
+    #[clap(long)]
+    cancel_rate_limit: Option<RateLimit>,
+
+    // send the orders and cancels of each decision in batches of up to 5, answered by one
+    // message each
+    #[clap(long, action)]
+    batch_orders: bool,
+
+    // place the quotes as good til date orders the exchange expires after their round, instead
+    // of canceling them
+    #[clap(long, action)]
+    exchange_expiry: bool,
+
+    // write every order request, ack, fill, cancel and reject with its latencies to the
+    // results directory
+    #[clap(long, action, requires = "output")]
+    order_audit: bool,
+
+    // write the vol updates, quotes and fills of the strategy to the results directory
+    #[clap(long, action, requires = "output")]
+    debug_log: bool,
+
+    // keep parsed market data here and reuse it in later runs
+    #[clap(long)]
+    tick_cache_dir: Option<PathBuf>,
+
+    // comma separated column names of trade files without a header row
+    #[clap(long)]
+    trade_columns: Option<String>,
+
+    // comma separated column names of bookticker files without a header row
+    #[clap(long)]
+    bookticker_columns: Option<String>,
+}
+
+// the flags of the command line, completed by the config file when one is given
+fn parse_cli() -> CliArgs {
+    let command = CliArgs::command();
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let mut matches = command.clone().get_matches_from(&args);
+    if let Some(path) = matches.get_one::<PathBuf>("config") {
+        let config = config::load(path).expect("failed to load config");
+        args = config::args_with_config(&command, &matches, args, &config).expect("invalid config");
+        matches = command.clone().get_matches_from(&args);
+    }
+    let mut cli = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.resolved_config = config::resolved(&command, &matches);
+    cli.args = args;
+    cli
+}
+
+// runs the command line of the process, the sim binary
+pub fn run_cli() {
+    let cli = parse_cli();
+    match &cli.command {
+        Some(Command::Diff { run_a, run_b }) => {
+            diff::diff_runs(run_a, run_b).expect("failed to diff runs");
+            return;
+        }
+        Some(Command::Batch(args)) => {
+            let result = batch::run_batch(
+                args,
+                cli.tick_cache_dir.as_deref(),
+                &cli.root_path,
+                &cli.trade_data,
+                cli.download_missing,
+            )
+            .context("batch failed");
+            exit_on_error(result);
+            return;
+        }
+        Some(Command::Robustness(args)) => {
+            robustness::run_robustness(args).expect("robustness scoring failed");
+            return;
+        }
+        Some(Command::Results(args)) => {
+            results_db::query_results(args).expect("failed to query results");
+            return;
+        }
+        Some(Command::WalkForward(args)) => {
+            walk_forward::run_walk_forward(args).expect("walk-forward failed");
+            return;
+        }
+        Some(Command::Convert(args)) => {
+            let dated = match (&cli.symbol, &cli.date) {
+                (Some(symbol), Some(_)) => data_check::dated_inputs(
+                    &cli.root_path,
+                    symbol,
+                    &exit_on_error(replay_dates(&cli)),
+                    &cli.trade_data,
+                ),
+                _ => vec![],
+            };
+            convert::run_convert(args, dated).expect("conversion failed");
+            return;
+        }
+        None => {}
+    }
+    println!("{:?}", cli);
+
+    let _trace_guard = set_tracing_subscriber(
+        cli.log_level,
+        cli.log.as_deref(),
+        cli.trace_chrome.as_deref(),
+    )
+    .expect("setting default subscriber failed");
+
+    if cli.benchmark {
+        let symbol: &'static str = cli.symbol.clone().expect("symbol is not provided").leak();
+        let paths = exit_on_error(republish_paths(&cli, symbol));
+        let republisher = exit_on_error(republisher_builder(&cli, symbol, &paths));
+        exit_on_error(benchmark::run_benchmark(republisher, &paths));
+        return;
+    }
+
+    if cli.speed.is_some_and(|speed| speed <= 0.0) {
+        panic!("--speed must be positive");
+    }
+    // the vis window controls the playback even when the speed is not capped
+    let playback = (cli.speed.is_some() || cli.vis).then(|| PlaybackControl::with_speed(cli.speed));
+    if cli.vis {
+        // the window takes the main thread, the only one macOS runs it on
+        let (window, link) = VisWindow::new(playback.clone());
+        let simulation = std::thread::spawn(move || run_simulation(cli, playback, Some(link)));
+        window.run();
+        exit_on_error(simulation.join().expect("simulation panicked"));
+    } else {
+        exit_on_error(run_simulation(cli, playback, None));
+    }
+}
+
+// the value of a result of the command line, which exits with its error instead of the one
+// run_backtest returns
+fn exit_on_error<T>(result: Result<T, anyhow::Error>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{:#}", e);
+        std::process::exit(1)
+    })
+}
+
+// a fill probability, checked as the market agent and the simple backtest take it
+fn probability(s: &str) -> Result<f64, String> {
+    let probability = s
+        .parse::<f64>()
+        .map_err(|_| format!("invalid probability {s}, expected a number from 0 to 1"))?;
+    FillProbability::new(probability, 0).map(|p| p.probability())
+}
+
+// a ratio or a scale, above zero
+fn positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 => Ok(value),
+        _ => Err(format!("invalid value {s}, expected a number above 0")),
+    }
+}
+
+fn log_directives(s: &str) -> Result<String, String> {
+    EnvFilter::builder()
+        .parse(s)
+        .map(|_| s.to_string())
+        .map_err(|e| format!("invalid log directives {s}: {e}"))
+}
+
+// logs of the level, or of the level of the directives for their modules, and the spans of the
+// module runs written to trace_file when given. The trace is complete once the guard returned
+// is dropped
+fn set_tracing_subscriber(
+    level: tracing::Level,
+    directives: Option<&str>,
+    trace_file: Option<&Path>,
+) -> Result<Option<FlushGuard>, SetGlobalDefaultError> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(level).into())
+        .parse_lossy(directives.unwrap_or_default());
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_file(true)
+        .with_line_number(true)
+        .with_target(false)
+        .with_filter(filter);
+    let (chrome, guard) = match trace_file {
+        Some(path) => {
+            let (chrome, guard) = ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            let chrome = chrome.with_filter(filter_fn(|metadata| metadata.is_span()));
+            (Some(chrome), Some(guard))
+        }
+        None => (None, None),
+    };
+    let subscriber = tracing_subscriber::registry().with(fmt).with(chrome);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(guard)
+}
+
+// Runs the simulation of the flags in config in this process, keyed as in a config file, e.g.
+// {fee_rate = 0.0002, initial_balance = {USDT = 50000.0}}, and returns the directory its
+// results were written to. The config gives results_dir or run_label for it.
+pub fn run_backtest(config: &toml::Table) -> Result<PathBuf, anyhow::Error> {
+    let command = CliArgs::command();
+    let args: Vec<OsString> = vec!["sim".into()];
+    let matches = command.clone().try_get_matches_from(&args)?;
+    let args = config::args_with_config(&command, &matches, args, config)?;
+    let matches = command.clone().try_get_matches_from(&args)?;
+    let mut cli = CliArgs::from_arg_matches(&matches)?;
+    if cli.command.is_some() || cli.vis || cli.benchmark || cli.speed.is_some() {
+        bail!("a backtest runs without subcommands, the vis window, a speed or the benchmark");
+    }
+    if cli.results_dir.is_none() && cli.run_label.is_none() {
+        bail!("a backtest needs results_dir or run_label to write its results to");
+    }
+    cli.resolved_config = config::resolved(&command, &matches);
+    cli.args = args;
+    // the subscriber of the first backtest of the process logs the later ones
+    let _trace_guard = set_tracing_subscriber(
+        cli.log_level,
+        cli.log.as_deref(),
+        cli.trace_chrome.as_deref(),
+    )
+    .ok()
+    .flatten();
+    let output = run_simulation(cli, None, None)?.context("the backtest wrote no results")?;
+    Ok(output.dir().to_path_buf())
+}
+
+// the directory the results of the run were written to, if any
+fn run_simulation(
+    cli: CliArgs,
+    playback: Option<PlaybackControl>,
+    window: Option<VisLink>,
+) -> Result<Option<RunOutput>, anyhow::Error> {
+    // Init symbol
+    let symbol: &'static str = cli.symbol.clone().context("symbol is not provided")?.leak();
+    let symbol_info_manager = symbols(&cli, symbol)?;
+    // the directory every module writes its results to
+    let output = match (&cli.results_dir, &cli.run_label) {
+        (Some(dir), _) => Some(RunOutput::new(dir)),
+        (None, Some(label)) => Some(
+            RunOutput::timestamped(&cli.runs_root, label, SystemTime::now())
+                .context("failed to create the run directory")?,
+        ),
+        (None, None) => None,
+    };
+    if let Some(output) = &output {
+        if let Err(e) = config::save(&cli.resolved_config, output.dir()) {
+            error!("failed to write the run config: {:#}", e);
+        }
+    }
+    if cli.simple_backtest {
+        run_simple_backtest(&cli, symbol, &symbol_info_manager, output.as_ref())?;
+        if let Some(output) = &output {
+            record_run(&cli, output);
+        }
+        return Ok(output);
+    }
+    // the flags and results directory of each variant
+    let variants: Vec<(&Variant, CliArgs, Option<PathBuf>)> = cli
+        .variant
+        .iter()
+        .map(|variant| {
+            let variant_cli = variant.cli(&cli.args)?;
+            let dir = output
+                .as_ref()
+                .map(|output| output.namespaced(&variant.name).dir().to_path_buf());
+            if let Some(dir) = &dir {
+                if let Err(e) = config::save(&variant_cli.resolved_config, dir) {
+                    error!(
+                        "failed to write the config of variant {}: {:#}",
+                        variant.name, e
+                    );
+                }
+            }
+            Ok((variant, variant_cli, dir))
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+
+    let mut engine = SimulationEngineBuilder::default();
+    if let Some(output) = &output {
+        engine = engine.with_run_output(output.clone());
+    }
+    if cli.shutdown_grace_ms.is_some() || cli.flatten_on_shutdown {
+        engine = engine
+            .with_shutdown_phase(Duration::from_millis(cli.shutdown_grace_ms.unwrap_or(1000)));
+    }
+    if let Some(playback) = &playback {
+        engine = engine.with_playback(playback.clone());
+    }
+    if let Some(audit) = cli.clock_audit {
+        engine = engine.with_clock_audit(audit);
+    }
+    if let Some(range) = progress_range(&cli)? {
+        engine = engine.with_progress(range.start, range.end);
+    }
+    if !cli.fault.is_empty() {
+        let fault_injection = cli
+            .fault
+            .iter()
+            .fold(FaultInjection::new(cli.fault_seed), |f, faults| {
+                f.with_topic(faults.clone())
+            });
+        engine = engine.with_fault_injection(fault_injection);
+    }
+    add_strategy(&mut engine, &cli, symbol, &symbol_info_manager, None)?;
+    // each variant trades in the namespace of its name. Modules due at the same time run in an
+    // order of their own, a variant with no flags changed may quote a round apart from the base
+    let variant_managers: Vec<SymbolInfoManager> = variants
+        .iter()
+        .map(|(_, variant_cli, _)| symbols(variant_cli, symbol))
+        .collect::<Result<_, anyhow::Error>>()?;
+    for ((variant, variant_cli, _), manager) in variants.iter().zip(&variant_managers) {
+        add_strategy(
+            &mut engine,
+            variant_cli,
+            symbol,
+            manager,
+            Some(&variant.name),
+        )?;
+    }
+
+    let mut feed: Box<dyn ModuleBuilder> = match cli.synthetic {
+        Some(process) => Box::new(synthetic_feed_builder(&cli, symbol, process)?),
+        None => {
+            let republish_path = republish_paths(&cli, symbol)?;
+            Box::new(republisher_builder(&cli, symbol, &republish_path)?)
+        }
+    };
+    if !cli.scenario.is_empty() {
+        let scenario = cli
+            .scenario
+            .iter()
+            .fold(Scenario::default(), |s, event| s.with_event(event.clone()));
+        feed = Box::new(ScenarioBuilder::new(feed, scenario));
+    }
+    // the scenario is timed in the time of the data
+    if let Some(factor) = cli.time_compression {
+        feed = Box::new(TimeCompressionBuilder::new(feed, factor));
+    }
+    engine.add_module_dyn(feed);
+    // the symbols the hedgers trade replay next to the one of the strategy
+    let managers = std::iter::once(&symbol_info_manager).chain(&variant_managers);
+    for other in other_symbols(managers, symbol) {
+        if cli.synthetic.is_some() || !cli.path.is_empty() {
+            bail!("{other} is replayed from the root path, it takes no --synthetic or --path");
+        }
+        let paths = republish_paths(&cli, other)?;
+        let mut feed: Box<dyn ModuleBuilder> = Box::new(republisher_builder(&cli, other, &paths)?);
+        if let Some(factor) = cli.time_compression {
+            feed = Box::new(TimeCompressionBuilder::new(feed, factor));
+        }
+        engine.add_module_dyn(feed);
+    }
+
+    add_strategy_guards(&mut engine, &cli, symbol, &symbol_info_manager, None);
+    for ((variant, variant_cli, _), manager) in variants.iter().zip(&variant_managers) {
+        add_strategy_guards(
+            &mut engine,
+            variant_cli,
+            symbol,
+            manager,
+            Some(&variant.name),
+        );
+    }
+
+    if cli.signals {
+        engine = engine.add_module(IndicatorPublisherBuilder::new(symbol));
+    }
+    // the strategies quoting around the microprice read it from the signals topic
+    let microprice = std::iter::once(&cli)
+        .chain(variants.iter().map(|(_, variant_cli, _)| variant_cli))
+        .any(|cli| cli.fair_price_source == FairPriceSource::Microprice);
+    if cli.signals || microprice {
+        engine = engine.add_module(OrderFlowPublisherBuilder::new(symbol));
+    }
+    if let Some(addr) = cli.metrics_addr {
+        engine = engine.add_module(
+            MetricsBuilder::new(symbol, addr).with_symbol_info_manager(symbol_info_manager.clone()),
+        );
+    }
+
+    if cli.vis || cli.vis_export || cli.vis_web.is_some() {
+        let symbol_info = &symbol_info_manager.symbol_info[symbol];
+        let mut vis = initial_balances(&cli, symbol_info).iter().fold(
+            VisModuleBuilder::default().with_symbol_info_manager(symbol_info_manager.clone()),
+            |b, (asset, balance)| b.with_initial_balance(asset, *balance),
+        );
+        for (variant, variant_cli, _) in &variants {
+            vis = vis.with_variant(
+                variant.name.clone().leak(),
+                &initial_balances(variant_cli, symbol_info),
+            );
+        }
+        if let Some(playback) = &playback {
+            vis = vis.with_playback(playback.clone());
+        }
+        if cli.vis_export {
+            vis = vis.with_export();
+        }
+        if let Some(addr) = cli.vis_web {
+            vis = vis.with_web(addr);
+        }
+        if let Some(link) = window {
+            vis = vis.with_window(link);
+        }
+        engine = engine.add_module(vis);
+    }
+
+    let mut engine = engine.build();
+    info!("engine start");
+    engine.run();
+
+    let profile = engine.profile();
+    println!("--- Engine Profile ---");
+    println!("{}", profile);
+    let failures = engine.failures();
+    if !failures.is_empty() {
+        bail!("the run ended early\n{}", failures.join("\n"));
+    }
+    let Some(output) = output else {
+        return Ok(None);
+    };
+    if let Err(e) = profile.save(output.dir()) {
+        error!("failed to write engine profile: {}", e);
+    }
+    if !variants.is_empty() {
+        let dir = output.dir();
+        let dirs: Vec<(&str, &Path)> = std::iter::once((variant::BASE_VARIANT, dir))
+            .chain(
+                variants
+                    .iter()
+                    .filter_map(|(variant, _, dir)| Some((variant.name.as_str(), dir.as_deref()?))),
+            )
+            .collect();
+        if let Err(e) = variant::compare_variants(dir, &dirs) {
+            error!("failed to compare the variants: {:#}", e);
+        }
+    }
+    record_run(&cli, &output);
+    Ok(Some(output))
+}
+
+// stores the results of the run in the --results-db, if any
+fn record_run(cli: &CliArgs, output: &RunOutput) {
+    if let Some(db) = &cli.results_db {
+        match results_db::record_run(db, output.dir(), &cli.resolved_config) {
+            Ok(id) => println!("Stored as run {} in {}", id, db.display()),
+            Err(e) => error!("failed to store the run in {}: {:#}", db.display(), e),
+        }
+    }
+}
+
+// The coarse backtest of --simple-backtest, the market maker stepped on the candles of the
+// dates or of the files given, see SimpleBacktest
+fn run_simple_backtest(
+    cli: &CliArgs,
+    symbol: &'static str,
+    symbol_info_manager: &SymbolInfoManager,
+    output: Option<&RunOutput>,
+) -> Result<(), anyhow::Error> {
+    let paths = if cli.path.is_empty() {
+        let TradeData::Klines(interval) = &cli.trade_data else {
+            bail!("--simple-backtest steps on the candles of --trade-data klines-<interval>");
+        };
+        data_check::ensure_kline_inputs(
+            &cli.root_path,
+            symbol,
+            &replay_dates(cli)?,
+            interval,
+            cli.download_missing,
+        )?
+    } else {
+        data_check::ensure_inputs(&cli.path)?;
+        cli.path.clone()
+    };
+    println!("Simple backtest data path: {:?}", paths);
+    let mut reader = BinanceRepublisherBuilder::new(symbol)
+        .set_show_progress(!cli.no_progress && !cli.path.is_empty());
+    if let Some(max_parse_errors) = cli.max_parse_errors {
+        reader = reader.with_max_parse_errors(max_parse_errors);
+    }
+    if let Some(dir) = &cli.tick_cache_dir {
+        reader = reader.with_tick_cache(TickCache::new(dir));
+    }
+    // a monthly file holds the other days of the month as well
+    if cli.path.is_empty() && paths.iter().any(|path| data_check::is_monthly(path)) {
+        let range = data_check::dates_time_range(&replay_dates(cli)?)?;
+        reader = reader.with_time_range(range.start, range.end);
+    }
+    let klines = with_files(reader, &paths)?
+        .read_klines()
+        .context("failed to read the klines")?;
+    if klines.is_empty() {
+        bail!("no klines to step on in {:?}", paths);
+    }
+
+    let strategy = AmmStrategy::new(symbol, symbol_info_manager.clone())
+        .with_pricing_model(pricing_model(cli)?)
+        .with_fair_price_source(cli.fair_price_source)
+        .with_vol_price_source(cli.vol_price_source)
+        .with_vol_estimator(cli.vol_estimator)
+        .with_vol_gap_handling(cli.vol_gap, Duration::from_secs(cli.vol_gap_secs))
+        .with_vol_warm_up(cli.vol_warm_up)
+        .with_quote_anchoring(cli.quote_anchoring)
+        .with_price_tick(cli.price_tick)
+        .with_quote_sizing(cli.quote_sizing)
+        .with_lot_size(cli.lot_size)
+        .with_quote_tolerance(quote_tolerance(cli))
+        .with_exchange_expiry(cli.exchange_expiry)
+        .with_inventory_limits(inventory_limits(cli))
+        .with_degraded_data_response(cli.degraded_data);
+    let backtest = initial_balances(cli, &symbol_info_manager.symbol_info[symbol])
+        .iter()
+        .fold(SimpleBacktest::new(strategy), |b, (asset, balance)| {
+            b.with_balance(asset, *balance)
+        })
+        .with_fill_probability(
+            FillProbability::new(cli.fill_probability, cli.fill_seed)
+                .map_err(anyhow::Error::msg)?,
+        );
+    let results = backtest.run(klines);
+
+    println!("--- Simple Backtest ---");
+    for stat in [
+        "candle_num",
+        "order_num",
+        "fill_count",
+        "fee",
+        "initial_equity",
+        "final_equity",
+        "profit",
+        "max_drawdown",
+    ] {
+        println!("{}: {}", stat, results.stats[stat]);
+    }
+    if let Some(output) = output {
+        match results.save(output.dir()) {
+            Ok(_) => println!("Results written to {}", output.dir().display()),
+            Err(e) => error!("failed to write results: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+// the quote assets a symbol may end with, the rest of it is the base asset
+const QUOTE_ASSETS: [&str; 10] = [
+    "USDT", "USDC", "FDUSD", "TUSD", "BUSD", "BTC", "ETH", "BNB", "EUR", "TRY",
+];
+
+// the base and the quote asset of symbol
+fn assets(symbol: &'static str) -> Result<(&'static str, &'static str), anyhow::Error> {
+    QUOTE_ASSETS
+        .iter()
+        .filter(|quote| symbol.len() > quote.len() && symbol.ends_with(*quote))
+        .max_by_key(|quote| quote.len())
+        .map(|quote| symbol.split_at(symbol.len() - quote.len()))
+        .with_context(|| format!("{symbol} ends with none of the quote assets {QUOTE_ASSETS:?}"))
+}
+
+// symbol, the symbol hedged on and the referenced symbols, charged the fee rate
+fn symbols(cli: &CliArgs, symbol: &'static str) -> Result<SymbolInfoManager, anyhow::Error> {
+    let others = cli.hedge_symbol.iter().chain(&cli.reference_symbol);
+    let mut manager = SymbolInfoManager::default();
+    for symbol in std::iter::once(symbol).chain(others.map(|other| &*other.clone().leak())) {
+        if manager.symbol_info.contains_key(symbol) {
+            continue;
+        }
+        let (base_asset, quote_asset) = assets(symbol)?;
+        manager = manager.with_symbol_config(symbol, base_asset, quote_asset, cli.fee_rate);
+    }
+    Ok(manager)
+}
+
+// the symbols besides symbol whose market data the strategies of the managers need
+fn other_symbols<'a>(
+    managers: impl Iterator<Item = &'a SymbolInfoManager>,
+    symbol: &str,
+) -> Vec<&'static str> {
+    let mut symbols: Vec<&'static str> = managers
+        .flat_map(|manager| manager.symbol_info.keys().copied())
+        .filter(|other| *other != symbol)
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+    symbols
+}
+
+// the name the manager holds for symbol
+fn interned(manager: &SymbolInfoManager, symbol: &str) -> &'static str {
+    manager
+        .symbol_info
+        .get_key_value(symbol)
+        .map(|(symbol, _)| *symbol)
+        .expect("the symbol is in the manager")
+}
+
+// the balances the account starts with
+fn initial_balances(cli: &CliArgs, symbol_info: &SymbolInfo) -> Vec<(&'static str, f64)> {
+    if cli.initial_balance.is_empty() {
+        vec![
+            (symbol_info.quote_asset, 50000.0),
+            (symbol_info.base_asset, 1.0),
+        ]
+    } else {
+        cli.initial_balance
+            .iter()
+            .map(|b| (&*b.asset.clone().leak(), b.amount))
+            .collect()
+    }
+}
+
+// the module run in the namespace, or with the topics of every other module without one
+fn add_in_namespace(
+    engine: &mut SimulationEngineBuilder,
+    module: Box<dyn ModuleBuilder>,
+    namespace: Option<&str>,
+) {
+    match namespace {
+        Some(namespace) => {
+            engine.add_module_dyn(Box::new(NamespacedBuilder::new(module, namespace)))
+        }
+        None => engine.add_module_dyn(module),
+    }
+}
+
+// the strategy, its hedger and the market agent filling their orders
+fn add_strategy(
+    engine: &mut SimulationEngineBuilder,
+    cli: &CliArgs,
+    symbol: &'static str,
+    symbol_info_manager: &SymbolInfoManager,
+    namespace: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let reconcile = cli.reconcile_timeout_ms.map(|timeout_ms| ReconcileConfig {
+        timeout: Duration::from_millis(timeout_ms),
+        max_retries: cli.reconcile_max_retries,
+    });
+
+    let session = cli
+        .session_window
+        .iter()
+        .fold(SessionCalendar::default(), |c, w| c.with_window(*w));
+    let session = cli
+        .session_blackout
+        .iter()
+        .fold(session, |c, b| c.with_blackout(*b));
+
+    let symbol_info = &symbol_info_manager.symbol_info[symbol];
+    let mut market_agent = initial_balances(cli, symbol_info).iter().fold(
+        MarketAgentBuilder::default().with_symbol_info_manager(symbol_info_manager.clone()),
+        |b, (asset, balance)| b.with_initial_balance(*asset, *balance),
+    );
+    market_agent = market_agent
+        .with_self_trade_prevention(cli.self_trade_prevention)
+        .with_slippage_model(cli.slippage);
+    if cli.fill_probability < 1.0 {
+        let fill_probability = FillProbability::new(cli.fill_probability, cli.fill_seed)
+            .map_err(anyhow::Error::msg)?;
+        market_agent = market_agent.with_fill_probability(fill_probability);
+    }
+    if let Some(secs) = cli.account_snapshot_secs {
+        market_agent = market_agent.with_account_snapshot_interval(Duration::from_secs(secs));
+    }
+    if let Some(limit) = cli.order_rate_limit {
+        market_agent = market_agent.with_order_rate_limit(limit);
+    }
+    if let Some(limit) = cli.cancel_rate_limit {
+        market_agent = market_agent.with_cancel_rate_limit(limit);
+    }
+    let load_profile = |path: &PathBuf| {
+        LatencyProfile::load(path)
+            .with_context(|| format!("invalid latency profile {}", path.display()))
+    };
+    let place_profile = match &cli.latency_profile {
+        Some(path) => Some(load_profile(path)?),
+        // places are immediate when only cancel or ack latencies are given
+        None if cli.cancel_latency_profile.is_some() || cli.ack_latency_profile.is_some() => {
+            Some(LatencyProfile::parse("0,0\n100,0").unwrap())
+        }
+        None => None,
+    };
+    if let Some(profile) = place_profile {
+        let mut model = LatencyModel::new(profile, cli.latency_seed).with_scale(cli.latency_scale);
+        if let Some(path) = &cli.cancel_latency_profile {
+            model = model.with_cancel_profile(load_profile(path)?);
+        }
+        if let Some(path) = &cli.ack_latency_profile {
+            model = model.with_ack_profile(load_profile(path)?);
+        }
+        market_agent = market_agent.with_latency_model(model);
+    }
+
+    let mut stepper = StepperBuilder::new(symbol)
+        .with_symbol_info_manager(symbol_info_manager.clone())
+        .with_pricing_model(pricing_model(cli)?)
+        .with_fair_price_source(cli.fair_price_source)
+        .with_vol_price_source(cli.vol_price_source)
+        .with_vol_estimator(cli.vol_estimator)
+        .with_vol_gap_handling(cli.vol_gap, Duration::from_secs(cli.vol_gap_secs))
+        .with_vol_warm_up(cli.vol_warm_up)
+        .with_quote_anchoring(cli.quote_anchoring)
+        .with_price_tick(cli.price_tick)
+        .with_quote_sizing(cli.quote_sizing)
+        .with_lot_size(cli.lot_size)
+        .with_quote_tolerance(quote_tolerance(cli))
+        .with_exchange_expiry(cli.exchange_expiry)
+        .with_inventory_limits(inventory_limits(cli))
+        .with_degraded_data_response(cli.degraded_data)
+        .with_reconcile(reconcile)
+        .with_session(session)
+        .with_flatten_on_shutdown(cli.flatten_on_shutdown)
+        .with_debug_log(cli.debug_log)
+        .with_batch_orders(cli.batch_orders)
+        .with_decision_trigger(if cli.decide_on_book_ticker {
+            DecisionTrigger::BookTicker
+        } else {
+            DecisionTrigger::Interval(Duration::from_millis(cli.decision_interval_ms))
+        });
+    if let Some(secs) = cli.state_history_secs {
+        stepper = stepper.with_state_history(Duration::from_secs(secs));
+    }
+    if let Some(plugin) = strategy_plugin(cli)? {
+        stepper = stepper.with_plugin(plugin.create(&cli.strategy_plugin_config)?);
+    }
+    if let Some(endpoint) = &cli.grpc_strategy {
+        let latency = cli.grpc_latency_ms.map(Duration::from_millis);
+        stepper = stepper.with_grpc_bridge(GrpcBridge::connect(endpoint, latency)?);
+    }
+    if let Some(latency) = cli.look_ahead_check_ms {
+        stepper = stepper.with_look_ahead_check(Duration::from_millis(latency));
+    }
+    for reference in &cli.reference_symbol {
+        stepper = stepper.with_reference_symbol(interned(symbol_info_manager, reference));
+    }
+    add_in_namespace(engine, Box::new(stepper), namespace);
+    if let Some(band) = cli.hedge_band {
+        let mut hedger = TakerHedgerBuilder::new(symbol)
+            .with_symbol_info_manager(symbol_info_manager.clone())
+            .with_band(band)
+            .with_hedge_ratio(cli.hedge_ratio)
+            .with_cooldown(Duration::from_millis(cli.hedge_cooldown_ms.unwrap_or(0)));
+        if let Some(hedge_symbol) = &cli.hedge_symbol {
+            hedger = hedger.with_hedge_symbol(interned(symbol_info_manager, hedge_symbol));
+        }
+        add_in_namespace(engine, Box::new(hedger), namespace);
+    }
+    add_in_namespace(engine, Box::new(market_agent), namespace);
+    Ok(())
+}
+
+fn pricing_model(cli: &CliArgs) -> Result<PricingModel, anyhow::Error> {
+    if !cli.avellaneda_stoikov {
+        return Ok(PricingModel::Simple);
+    }
+    let calibrated_k = match (cli.as_k, &cli.as_calibration) {
+        (None, Some(path)) => Some(
+            FillIntensityCalibration::load(path)
+                .with_context(|| format!("invalid fill calibration {}", path.display()))?
+                .k,
+        ),
+        _ => None,
+    };
+    Ok(PricingModel::AvellanedaStoikov(AvellanedaStoikovParams {
+        gamma: cli.as_gamma,
+        k: cli.as_k.or(calibrated_k),
+        horizon_ms: cli.as_horizon_secs * 1000,
+    }))
+}
+
+fn quote_tolerance(cli: &CliArgs) -> Option<QuoteTolerance> {
+    cli.quote_price_tolerance.map(|price| QuoteTolerance {
+        price,
+        quantity: cli.quote_qty_tolerance,
+    })
+}
+
+fn inventory_limits(cli: &CliArgs) -> Option<InventoryLimits> {
+    (cli.max_long_inventory.is_some() || cli.max_short_inventory.is_some()).then(|| {
+        InventoryLimits {
+            max_long: cli.max_long_inventory.unwrap_or(f64::INFINITY),
+            max_short: cli.max_short_inventory.unwrap_or(f64::INFINITY),
+            reduce_aggressively: cli.reduce_inventory,
+        }
+    })
+}
+
+// the strategy plugin chosen by --strategy and --strategy-plugin, if any
+fn strategy_plugin(cli: &CliArgs) -> Result<Option<StrategyPlugin>, anyhow::Error> {
+    let registry = match &cli.strategy_plugin {
+        Some(path) => PluginRegistry::default().with_library(path),
+        None if cli.strategy.is_some() => PluginRegistry::discover(&cli.plugin_dir),
+        None => return Ok(None),
+    }
+    .context("failed to load the strategy plugins")?;
+    let names = registry.names();
+    let name = match (&cli.strategy, names.as_slice()) {
+        (Some(name), _) => name.as_str(),
+        (None, [name]) => name,
+        (None, names) => bail!(
+            "the strategy plugin has strategies {}, choose one with --strategy",
+            names.join(", ")
+        ),
+    };
+    let Some(plugin) = registry.get(name) else {
+        bail!("no strategy plugin {}, found: {}", name, names.join(", "));
+    };
+    Ok(Some(plugin.clone()))
+}
+
+// the risk limits, liquidation and order audit of the strategy, those enabled by the flags
+fn add_strategy_guards(
+    engine: &mut SimulationEngineBuilder,
+    cli: &CliArgs,
+    symbol: &'static str,
+    symbol_info_manager: &SymbolInfoManager,
+    namespace: Option<&str>,
+) {
+    let risk_limits = RiskLimits {
+        max_drawdown: cli.max_drawdown,
+        max_loss_per_minute: cli.max_loss_per_minute,
+        max_reject_rate: cli.max_reject_rate,
+    };
+    if risk_limits.max_drawdown.is_some()
+        || risk_limits.max_loss_per_minute.is_some()
+        || risk_limits.max_reject_rate.is_some()
+    {
+        let risk_guard = RiskGuardBuilder::new(symbol)
+            .with_symbol_info_manager(symbol_info_manager.clone())
+            .with_limits(risk_limits)
+            .with_terminate_on_halt(cli.halt_terminates);
+        add_in_namespace(engine, Box::new(risk_guard), namespace);
+    }
+
+    let liquidation_limits = LiquidationLimits {
+        max_long: cli.liquidate_long_over,
+        max_short: cli.liquidate_short_over,
+        max_loss: cli.liquidate_loss_over,
+    };
+    if liquidation_limits.max_long.is_some()
+        || liquidation_limits.max_short.is_some()
+        || liquidation_limits.max_loss.is_some()
+    {
+        let liquidator = LiquidatorBuilder::new(symbol)
+            .with_symbol_info_manager(symbol_info_manager.clone())
+            .with_limits(liquidation_limits);
+        add_in_namespace(engine, Box::new(liquidator), namespace);
+    }
+
+    if cli.order_audit {
+        add_in_namespace(engine, Box::new(OrderAuditBuilder::default()), namespace);
+    }
+    if cli.debug_log {
+        add_in_namespace(engine, Box::new(DebugSinkBuilder::default()), namespace);
+    }
+}
+
+// the dates replayed under the root path, --date to --end-date
+fn replay_dates(cli: &CliArgs) -> Result<Vec<String>, anyhow::Error> {
+    let date = cli.date.as_ref().context("date is not provided")?;
+    match &cli.end_date {
+        Some(end_date) => batch::date_range(date, end_date).context("invalid date range"),
+        None => Ok(vec![date.clone()]),
+    }
+}
+
+// the time range the engine runs through, none for the files given by --path, which show the
+// progress of their reads instead
+fn progress_range(cli: &CliArgs) -> Result<Option<Range<SystemTime>>, anyhow::Error> {
+    if cli.no_progress || (cli.synthetic.is_none() && !cli.path.is_empty()) {
+        return Ok(None);
+    }
+    let dates = match cli.date {
+        Some(_) => replay_dates(cli)?,
+        None => vec!["2024-01-01".to_string()],
+    };
+    let range = data_check::dates_time_range(&dates)?;
+    // a compressed run replays the dates factor times faster
+    let factor = cli.time_compression.unwrap_or(1.0);
+    let len = range.end.duration_since(range.start).unwrap_or_default();
+    Ok(Some(range.start..range.start + len.div_f64(factor)))
+}
+
+fn synthetic_feed_builder(
+    cli: &CliArgs,
+    symbol: &'static str,
+    process: PriceProcess,
+) -> Result<SyntheticFeedBuilder, anyhow::Error> {
+    let dates = match cli.date {
+        Some(_) => replay_dates(cli)?,
+        None => vec!["2024-01-01".to_string()],
+    };
+    let range = data_check::dates_time_range(&dates)?;
+    Ok(SyntheticFeedBuilder::new(symbol)
+        .with_config(SyntheticFeedConfig {
+            process,
+            initial_price: cli.synthetic_initial_price,
+            volatility: cli.synthetic_volatility,
+            price_tick: cli.price_tick,
+            seed: cli.synthetic_seed,
+            ..Default::default()
+        })
+        .with_time_range(range.start, range.end))
+}
+
+// the files given, or the trades and booktickers of the date under the root path and the mark
+// price candles asked for
+fn republish_paths(cli: &CliArgs, symbol: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let paths = if cli.path.is_empty() {
+        let dates = replay_dates(cli)?;
+        data_check::ensure_dated_inputs(
+            &cli.root_path,
+            &[symbol.to_string()],
+            &dates,
+            &cli.trade_data,
+            cli.download_missing,
+        )?;
+        let mut paths = data_check::dated_inputs(&cli.root_path, symbol, &dates, &cli.trade_data);
+        if let Some(interval) = &cli.mark_price_klines {
+            paths.extend(data_check::ensure_mark_price_inputs(
+                &cli.root_path,
+                symbol,
+                &dates,
+                interval,
+                cli.download_missing,
+            )?);
+        }
+        paths
+    } else {
+        data_check::ensure_inputs(&cli.path)?;
+        cli.path.clone()
+    };
+    println!("Republish data path: {:?}", paths);
+    if paths.is_empty() {
+        bail!("path is not provided");
+    }
+    Ok(paths)
+}
+
+fn republisher_builder(
+    cli: &CliArgs,
+    symbol: &'static str,
+    paths: &[PathBuf],
+) -> Result<BinanceRepublisherBuilder, anyhow::Error> {
+    // the engine shows the progress of a run over dates, see progress_range
+    let mut republisher = BinanceRepublisherBuilder::new(symbol)
+        .set_show_progress(!cli.no_progress && !cli.path.is_empty());
+    if let Some(max_parse_errors) = cli.max_parse_errors {
+        republisher = republisher.with_max_parse_errors(max_parse_errors);
+    }
+    if let Some(window) = cli.reorder_window_ms {
+        republisher = republisher.with_reorder_window(Duration::from_millis(window));
+    }
+    if let Some(window) = cli.trade_batch_ms {
+        republisher = republisher.with_trade_batch_window(Duration::from_millis(window));
+    }
+    if let Some(columns) = &cli.trade_columns {
+        republisher = republisher
+            .with_trade_tick_columns(columns)
+            .context("invalid trade columns")?;
+    }
+    if let Some(columns) = &cli.bookticker_columns {
+        republisher = republisher
+            .with_bookticker_columns(columns)
+            .context("invalid bookticker columns")?;
+    }
+    if let Some(dir) = &cli.tick_cache_dir {
+        republisher = republisher.with_tick_cache(TickCache::new(dir));
+    }
+    if cli.validate_data {
+        republisher = republisher.with_validation(ValidationConfig {
+            stale_book_after: Duration::from_millis(cli.stale_book_ms),
+            max_trade_jump: cli.max_trade_jump,
+        });
+    }
+    // a monthly file holds the other days of the month as well
+    if cli.path.is_empty() && paths.iter().any(|path| data_check::is_monthly(path)) {
+        let range = data_check::dates_time_range(&replay_dates(cli)?)?;
+        republisher = republisher.with_time_range(range.start, range.end);
+    }
+    with_files(republisher, paths)
+}
+
+// the republisher reading the files of paths
+fn with_files(
+    republisher: BinanceRepublisherBuilder,
+    paths: &[PathBuf],
+) -> Result<BinanceRepublisherBuilder, anyhow::Error> {
+    paths.iter().try_fold(republisher, |b, path| {
+        let path = path
+            .to_str()
+            .with_context(|| format!("invalid path {}", path.display()))?;
+        b.with_file(path)
+            .with_context(|| format!("failed to open {}", path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assets_of_symbol() {
+        assert_eq!(assets("BTCUSDT").unwrap(), ("BTC", "USDT"));
+        assert_eq!(assets("ETHBTC").unwrap(), ("ETH", "BTC"));
+        assert_eq!(assets("BTCFDUSD").unwrap(), ("BTC", "FDUSD"));
+        assert!(assets("BTCXYZ").is_err());
+        assert!(assets("USDT").is_err());
+    }
+}
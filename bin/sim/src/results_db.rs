@@ -0,0 +1,433 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use market_agent::results::RunResults;
+use rusqlite::{params, Connection, OptionalExtension};
+use toml::{Table, Value};
+
+// the metrics listed next to every run
+const LIST_METRICS: &[&str] = &["profit", "max_drawdown", "fill_count"];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    results_dir TEXT NOT NULL,
+    symbol TEXT,
+    date TEXT,
+    config TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS run_config (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    key TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS run_metrics (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS run_artifacts (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    name TEXT NOT NULL,
+    path TEXT NOT NULL
+);
+";
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ResultsArgs {
+    // the database written with --results-db
+    db: PathBuf,
+
+    #[command(subcommand)]
+    query: Option<ResultsQuery>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ResultsQuery {
+    // the runs stored, the latest first
+    List {
+        // a metric such as profit, the runs with the highest first
+        #[clap(long)]
+        sort_by: Option<String>,
+
+        #[clap(long)]
+        symbol: Option<String>,
+
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+    },
+    // the config, metrics and artifacts of a run
+    Show {
+        id: i64,
+    },
+    // the config keys two runs differ in and their metrics side by side
+    Compare {
+        run_a: i64,
+        run_b: i64,
+    },
+}
+
+fn open(path: &Path) -> Result<Connection, anyhow::Error> {
+    let conn =
+        Connection::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    // the runs of a parallel batch insert at the same time
+    conn.busy_timeout(Duration::from_secs(30))?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+fn config_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Stores a run written to results_dir with the config it resolved to, its metrics and the
+// paths of the files it wrote. Returns the id of the run.
+pub(crate) fn record_run(
+    db: &Path,
+    results_dir: &Path,
+    config: &Table,
+) -> Result<i64, anyhow::Error> {
+    let results = RunResults::load(results_dir)?;
+    let results_dir = std::fs::canonicalize(results_dir).unwrap_or(results_dir.to_path_buf());
+    let mut conn = open(db)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO runs (created_at, results_dir, symbol, date, config) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            results_dir.to_string_lossy(),
+            config.get("symbol").map(config_value),
+            config.get("date").map(config_value),
+            toml::to_string(config)?,
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    for (key, value) in config {
+        tx.execute(
+            "INSERT INTO run_config (run_id, key, value) VALUES (?1, ?2, ?3)",
+            params![id, key, config_value(value)],
+        )?;
+    }
+    for (name, value) in &results.stats {
+        tx.execute(
+            "INSERT INTO run_metrics (run_id, name, value) VALUES (?1, ?2, ?3)",
+            params![id, name, value],
+        )?;
+    }
+    let mut artifacts = vec![];
+    for entry in std::fs::read_dir(&results_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            artifacts.push((
+                entry.file_name().to_string_lossy().to_string(),
+                entry.path(),
+            ));
+        }
+    }
+    artifacts.sort();
+    for (name, path) in artifacts {
+        tx.execute(
+            "INSERT INTO run_artifacts (run_id, name, path) VALUES (?1, ?2, ?3)",
+            params![id, name, path.to_string_lossy()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(id)
+}
+
+fn metric(conn: &Connection, id: i64, name: &str) -> Result<Option<f64>, anyhow::Error> {
+    Ok(conn
+        .query_row(
+            "SELECT value FROM run_metrics WHERE run_id = ?1 AND name = ?2",
+            params![id, name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn pairs(conn: &Connection, sql: &str, id: i64) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn metrics(conn: &Connection, id: i64) -> Result<Vec<(String, f64)>, anyhow::Error> {
+    let mut stmt =
+        conn.prepare("SELECT name, value FROM run_metrics WHERE run_id = ?1 ORDER BY name")?;
+    let rows = stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn config(conn: &Connection, id: i64) -> Result<Vec<(String, String)>, anyhow::Error> {
+    pairs(
+        conn,
+        "SELECT key, value FROM run_config WHERE run_id = ?1 ORDER BY key",
+        id,
+    )
+}
+
+fn ensure_run(conn: &Connection, id: i64) -> Result<(), anyhow::Error> {
+    let found: Option<i64> = conn
+        .query_row("SELECT id FROM runs WHERE id = ?1", params![id], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    if found.is_none() {
+        bail!("no run {id}");
+    }
+    Ok(())
+}
+
+fn value_brief(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.6}", v))
+}
+
+// a run as listed
+#[derive(Debug)]
+struct RunRow {
+    id: i64,
+    created_at: String,
+    symbol: Option<String>,
+    date: Option<String>,
+    results_dir: String,
+}
+
+// the runs of symbol, by the sort_by metric or the latest first
+fn runs(
+    conn: &Connection,
+    sort_by: Option<&str>,
+    symbol: Option<&str>,
+    limit: usize,
+) -> Result<Vec<RunRow>, anyhow::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT runs.id, runs.created_at, runs.symbol, runs.date, runs.results_dir FROM runs
+         LEFT JOIN run_metrics ON run_metrics.run_id = runs.id AND run_metrics.name = ?1
+         WHERE ?2 IS NULL OR runs.symbol = ?2
+         ORDER BY CASE WHEN ?1 IS NULL THEN runs.id ELSE run_metrics.value END DESC
+         LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(params![sort_by, symbol, limit as i64], |row| {
+        Ok(RunRow {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            symbol: row.get(2)?,
+            date: row.get(3)?,
+            results_dir: row.get(4)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn list(
+    conn: &Connection,
+    sort_by: Option<&str>,
+    symbol: Option<&str>,
+    limit: usize,
+) -> Result<(), anyhow::Error> {
+    let rows = runs(conn, sort_by, symbol, limit)?;
+    let mut columns: Vec<&str> = LIST_METRICS.to_vec();
+    if let Some(sort_by) = sort_by.filter(|m| !LIST_METRICS.contains(m)) {
+        columns.push(sort_by);
+    }
+    print!(
+        "{:>5} {:<20} {:<10} {:<10}",
+        "id", "created_at", "symbol", "date"
+    );
+    for column in &columns {
+        print!(" {:>16}", column);
+    }
+    println!(" results_dir");
+    for row in rows {
+        print!(
+            "{:>5} {:<20} {:<10} {:<10}",
+            row.id,
+            row.created_at,
+            row.symbol.unwrap_or_default(),
+            row.date.unwrap_or_default()
+        );
+        for column in &columns {
+            print!(" {:>16}", value_brief(metric(conn, row.id, column)?));
+        }
+        println!(" {}", row.results_dir);
+    }
+    Ok(())
+}
+
+fn show(conn: &Connection, id: i64) -> Result<(), anyhow::Error> {
+    ensure_run(conn, id)?;
+    let (created_at, results_dir): (String, String) = conn.query_row(
+        "SELECT created_at, results_dir FROM runs WHERE id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    println!("Run {id} at {created_at}, {results_dir}");
+    println!("--- Config ---");
+    for (key, value) in config(conn, id)? {
+        println!("{key} = {value}");
+    }
+    println!("--- Metrics ---");
+    for (name, value) in metrics(conn, id)? {
+        println!("{:<28} {:>20}", name, value_brief(Some(value)));
+    }
+    println!("--- Artifacts ---");
+    let artifacts = pairs(
+        conn,
+        "SELECT name, path FROM run_artifacts WHERE run_id = ?1 ORDER BY name",
+        id,
+    )?;
+    for (_, path) in artifacts {
+        println!("{path}");
+    }
+    Ok(())
+}
+
+fn compare(conn: &Connection, run_a: i64, run_b: i64) -> Result<(), anyhow::Error> {
+    ensure_run(conn, run_a)?;
+    ensure_run(conn, run_b)?;
+    println!("--- Config ---");
+    let config_a = config(conn, run_a)?;
+    let config_b = config(conn, run_b)?;
+    let mut keys: Vec<&String> = config_a.iter().chain(&config_b).map(|(k, _)| k).collect();
+    keys.sort();
+    keys.dedup();
+    let value = |config: &[(String, String)], key: &String| {
+        config
+            .iter()
+            .find(|(k, _)| k == key)
+            .map_or("-".to_string(), |(_, v)| v.clone())
+    };
+    let mut differs = false;
+    for key in keys {
+        let (a, b) = (value(&config_a, key), value(&config_b, key));
+        if a != b {
+            println!("{key}: a = {a}, b = {b}");
+            differs = true;
+        }
+    }
+    if !differs {
+        println!("Configs are identical");
+    }
+
+    println!("--- Metrics ---");
+    println!("{:<28} {:>20} {:>20} {:>20}", "metric", "a", "b", "b - a");
+    let metrics_a = metrics(conn, run_a)?;
+    let metrics_b = metrics(conn, run_b)?;
+    let mut names: Vec<&String> = metrics_a.iter().chain(&metrics_b).map(|(n, _)| n).collect();
+    names.sort();
+    names.dedup();
+    let value = |metrics: &[(String, f64)], name: &String| {
+        metrics.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    };
+    for name in names {
+        let (a, b) = (value(&metrics_a, name), value(&metrics_b, name));
+        let delta = match (a, b) {
+            (Some(a), Some(b)) => Some(b - a),
+            _ => None,
+        };
+        println!(
+            "{:<28} {:>20} {:>20} {:>20}",
+            name,
+            value_brief(a),
+            value_brief(b),
+            value_brief(delta)
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn query_results(args: &ResultsArgs) -> Result<(), anyhow::Error> {
+    if !args.db.exists() {
+        bail!("no results database {}", args.db.display());
+    }
+    let conn = open(&args.db)?;
+    match &args.query {
+        None => list(&conn, None, None, 20),
+        Some(ResultsQuery::List {
+            sort_by,
+            symbol,
+            limit,
+        }) => list(&conn, sort_by.as_deref(), symbol.as_deref(), *limit),
+        Some(ResultsQuery::Show { id }) => show(&conn, *id),
+        Some(ResultsQuery::Compare { run_a, run_b }) => compare(&conn, *run_a, *run_b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a results directory with the stats
+    fn write_run(dir: &Path, stats: &[(&str, f64)]) {
+        let results = RunResults {
+            stats: stats.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            ..Default::default()
+        };
+        std::fs::create_dir_all(dir).unwrap();
+        results.save(dir).unwrap();
+    }
+
+    fn run_config(symbol: &str, spread: f64) -> Table {
+        let mut config = Table::new();
+        config.insert("symbol".into(), Value::String(symbol.into()));
+        config.insert("date".into(), Value::String("2024-01-01".into()));
+        config.insert("spread".into(), Value::Float(spread));
+        config
+    }
+
+    #[test]
+    fn test_record_and_query_runs() {
+        let root = std::env::temp_dir().join(format!("results_db_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let db = root.join("runs.db");
+        let (dir_a, dir_b, dir_c) = (root.join("a"), root.join("b"), root.join("c"));
+        write_run(&dir_a, &[("profit", 1.0), ("fill_count", 3.0)]);
+        write_run(&dir_b, &[("profit", 5.0), ("fill_count", 7.0)]);
+        write_run(&dir_c, &[("profit", 3.0)]);
+        let a = record_run(&db, &dir_a, &run_config("BTCUSDT", 0.1)).unwrap();
+        let b = record_run(&db, &dir_b, &run_config("BTCUSDT", 0.2)).unwrap();
+        let c = record_run(&db, &dir_c, &run_config("ETHUSDT", 0.1)).unwrap();
+
+        let conn = open(&db).unwrap();
+        let ids = |rows: Vec<RunRow>| rows.iter().map(|row| row.id).collect::<Vec<_>>();
+        // the latest first, by a metric, of a symbol and limited
+        assert_eq!(ids(runs(&conn, None, None, 20).unwrap()), vec![c, b, a]);
+        assert_eq!(
+            ids(runs(&conn, Some("profit"), None, 20).unwrap()),
+            vec![b, c, a]
+        );
+        assert_eq!(
+            ids(runs(&conn, None, Some("BTCUSDT"), 20).unwrap()),
+            vec![b, a]
+        );
+        assert_eq!(ids(runs(&conn, Some("profit"), None, 1).unwrap()), vec![b]);
+        let row = &runs(&conn, None, Some("ETHUSDT"), 20).unwrap()[0];
+        assert_eq!(row.date.as_deref(), Some("2024-01-01"));
+
+        assert_eq!(metric(&conn, b, "fill_count").unwrap(), Some(7.0));
+        assert_eq!(metric(&conn, c, "fill_count").unwrap(), None);
+        assert_eq!(
+            config(&conn, b).unwrap(),
+            vec![
+                ("date".to_string(), "2024-01-01".to_string()),
+                ("spread".to_string(), "0.2".to_string()),
+                ("symbol".to_string(), "BTCUSDT".to_string()),
+            ]
+        );
+        let artifacts = pairs(
+            &conn,
+            "SELECT name, path FROM run_artifacts WHERE run_id = ?1 ORDER BY name",
+            a,
+        )
+        .unwrap();
+        assert!(artifacts.iter().any(|(name, _)| name == "stats.csv"));
+        assert!(ensure_run(&conn, 99).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
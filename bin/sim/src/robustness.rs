@@ -0,0 +1,347 @@
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{bail, Context};
+use market_agent::results::RunResults;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::batch::{forwarded_args, run_child};
+
+const ROBUSTNESS_FILE: &str = "robustness.csv";
+
+// Uniform range a parameter is drawn from, given as min:max
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PerturbRange {
+    min: f64,
+    max: f64,
+}
+
+impl PerturbRange {
+    // a range of probabilities, within 0:1
+    fn probabilities(s: &str) -> Result<Self, String> {
+        let range: PerturbRange = s.parse()?;
+        if range.min < 0.0 || range.max > 1.0 {
+            return Err(format!(
+                "invalid range {s}, expected probabilities min:max within 0:1"
+            ));
+        }
+        Ok(range)
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> f64 {
+        if self.min == self.max {
+            return self.min;
+        }
+        rng.gen_range(self.min..=self.max)
+    }
+}
+
+impl FromStr for PerturbRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid range {s}, expected min:max");
+        let (min, max) = s.split_once(':').ok_or_else(invalid)?;
+        let min: f64 = min.trim().parse().map_err(|_| invalid())?;
+        let max: f64 = max.trim().parse().map_err(|_| invalid())?;
+        if !min.is_finite() || !max.is_finite() || min > max {
+            return Err(invalid());
+        }
+        Ok(PerturbRange { min, max })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct RobustnessArgs {
+    // number of perturbed runs
+    #[clap(long, default_value_t = 20)]
+    runs: usize,
+
+    // the parameters and the latency and fill draws of run i are seeded with seed + i
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+
+    // min:max, e.g. 0:0.0004
+    #[clap(long)]
+    fee_rate: Option<PerturbRange>,
+
+    // min:max multiplier of the latencies of --latency-profile
+    #[clap(long)]
+    latency_scale: Option<PerturbRange>,
+
+    // min:max chance a trade crossing a resting order fills it
+    #[clap(long, value_parser = PerturbRange::probabilities)]
+    fill_probability: Option<PerturbRange>,
+
+    // results of run i are written to <out>/run-<i>
+    #[clap(long, short = 'o')]
+    out: PathBuf,
+
+    // runs in parallel
+    #[clap(long, short = 'j', default_value_t = 1)]
+    jobs: usize,
+}
+
+// parameters of one perturbed world
+#[derive(Debug, Clone, PartialEq)]
+struct Perturbation {
+    seed: u64,
+    fee_rate: Option<f64>,
+    latency_scale: Option<f64>,
+    fill_probability: Option<f64>,
+}
+
+impl Perturbation {
+    fn draw(robustness: &RobustnessArgs, run: usize) -> Self {
+        let seed = robustness.seed.wrapping_add(run as u64);
+        let mut rng = StdRng::seed_from_u64(seed);
+        Perturbation {
+            seed,
+            fee_rate: robustness.fee_rate.map(|r| r.sample(&mut rng)),
+            latency_scale: robustness.latency_scale.map(|r| r.sample(&mut rng)),
+            fill_probability: robustness.fill_probability.map(|r| r.sample(&mut rng)),
+        }
+    }
+
+    fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "--latency-seed".into(),
+            self.seed.to_string().into(),
+            "--fill-seed".into(),
+            self.seed.to_string().into(),
+        ];
+        for (flag, value) in [
+            ("--fee-rate", self.fee_rate),
+            ("--latency-scale", self.latency_scale),
+            ("--fill-probability", self.fill_probability),
+        ] {
+            if let Some(value) = value {
+                args.push(flag.into());
+                args.push(value.to_string().into());
+            }
+        }
+        args
+    }
+}
+
+fn format_param(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.6}", v))
+}
+
+fn run_profit(args: &[OsString], dir: &Path) -> Result<f64, anyhow::Error> {
+    run_child(args, dir)?;
+    let results = RunResults::load(dir)?;
+    results
+        .stats
+        .get("profit")
+        .copied()
+        .with_context(|| format!("no profit in the results of {}", dir.display()))
+}
+
+// share of the runs with a positive profit, failed runs count as unprofitable
+fn robustness_score(profits: &[Option<f64>]) -> f64 {
+    if profits.is_empty() {
+        return 0.0;
+    }
+    let profitable = profits
+        .iter()
+        .filter(|p| p.is_some_and(|p| p > 0.0))
+        .count();
+    profitable as f64 / profits.len() as f64
+}
+
+// Runs the sim `runs` times with fees, latencies and fill probability drawn from the given
+// ranges, and reports the share of the perturbed worlds in which the strategy stays
+// profitable as its robustness.
+pub(crate) fn run_robustness(robustness: &RobustnessArgs) -> Result<(), anyhow::Error> {
+    let mut run_flags = vec![
+        "--results-dir",
+        "--run-label",
+        "--runs-root",
+        "--vis",
+        "-g",
+        "--vis-export",
+        "--latency-seed",
+        "--fill-seed",
+    ];
+    for (flag, range) in [
+        ("--fee-rate", robustness.fee_rate),
+        ("--latency-scale", robustness.latency_scale),
+        ("--fill-probability", robustness.fill_probability),
+    ] {
+        if range.is_some() {
+            run_flags.push(flag);
+        }
+    }
+    let mut args = forwarded_args("robustness", &run_flags)?;
+    if robustness.latency_scale.is_some() && !args.iter().any(|arg| arg == "--latency-profile") {
+        bail!("--latency-scale needs --latency-profile to scale");
+    }
+    if !args.iter().any(|arg| arg == "--no-progress") {
+        args.push("--no-progress".into());
+    }
+
+    let perturbations: Vec<Perturbation> = (0..robustness.runs)
+        .map(|run| Perturbation::draw(robustness, run))
+        .collect();
+    println!(
+        "Robustness: {} perturbed runs, {} jobs",
+        perturbations.len(),
+        robustness.jobs
+    );
+
+    let next = AtomicUsize::new(0);
+    let profits = Mutex::new(vec![None; perturbations.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..robustness.jobs.max(1) {
+            scope.spawn(|| loop {
+                let run = next.fetch_add(1, Ordering::Relaxed);
+                let Some(perturbation) = perturbations.get(run) else {
+                    break;
+                };
+                let dir = robustness.out.join(format!("run-{:03}", run));
+                let mut run_args = args.clone();
+                run_args.extend(perturbation.args());
+                let profit = run_profit(&run_args, &dir);
+                match &profit {
+                    Ok(profit) => println!("run {} profit {:.4}", run, profit),
+                    Err(e) => println!("run {} failed: {:#}", run, e),
+                }
+                profits.lock().unwrap()[run] = profit.ok();
+            });
+        }
+    });
+    let profits = profits.into_inner().unwrap();
+
+    let mut csv = String::from("run,seed,fee_rate,latency_scale,fill_probability,profit\n");
+    println!(
+        "{:>5} {:>12} {:>14} {:>14} {:>17} {:>14}",
+        "run", "seed", "fee_rate", "latency_scale", "fill_probability", "profit"
+    );
+    for (run, (perturbation, profit)) in perturbations.iter().zip(profits.iter()).enumerate() {
+        println!(
+            "{:>5} {:>12} {:>14} {:>14} {:>17} {:>14}",
+            run,
+            perturbation.seed,
+            format_param(perturbation.fee_rate),
+            format_param(perturbation.latency_scale),
+            format_param(perturbation.fill_probability),
+            format_param(*profit)
+        );
+        writeln!(
+            csv,
+            "{},{},{},{},{},{}",
+            run,
+            perturbation.seed,
+            perturbation
+                .fee_rate
+                .map_or(String::new(), |v| v.to_string()),
+            perturbation
+                .latency_scale
+                .map_or(String::new(), |v| v.to_string()),
+            perturbation
+                .fill_probability
+                .map_or(String::new(), |v| v.to_string()),
+            profit.map_or(String::new(), |v| v.to_string())
+        )?;
+    }
+    let path = robustness.out.join(ROBUSTNESS_FILE);
+    std::fs::create_dir_all(&robustness.out)?;
+    std::fs::write(&path, csv).with_context(|| format!("failed to write {}", path.display()))?;
+
+    let failed = profits.iter().filter(|p| p.is_none()).count();
+    println!(
+        "Robustness: {:.1}% of {} perturbed runs profitable{}",
+        robustness_score(&profits) * 100.0,
+        profits.len(),
+        if failed > 0 {
+            format!(", {} failed runs counted as unprofitable", failed)
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robustness(seed: u64) -> RobustnessArgs {
+        RobustnessArgs {
+            runs: 3,
+            seed,
+            fee_rate: Some("0:0.0004".parse().unwrap()),
+            latency_scale: None,
+            fill_probability: Some(PerturbRange::probabilities("0.5:0.5").unwrap()),
+            out: PathBuf::from("out"),
+            jobs: 1,
+        }
+    }
+
+    #[test]
+    fn test_perturb_range() {
+        assert_eq!(
+            "0.5 : 2".parse::<PerturbRange>(),
+            Ok(PerturbRange { min: 0.5, max: 2.0 })
+        );
+        assert!("2:0.5".parse::<PerturbRange>().is_err());
+        assert!("0.5".parse::<PerturbRange>().is_err());
+        assert!("0:inf".parse::<PerturbRange>().is_err());
+        assert!(PerturbRange::probabilities("0.5:1.5").is_err());
+
+        let range: PerturbRange = "1:2".parse().unwrap();
+        let draws = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..10).map(|_| range.sample(&mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+        assert!(draws(7).iter().all(|v| (1.0..=2.0).contains(v)));
+    }
+
+    #[test]
+    fn test_perturbations_are_seeded() {
+        let args = robustness(42);
+        let draws: Vec<Perturbation> = (0..args.runs)
+            .map(|run| Perturbation::draw(&args, run))
+            .collect();
+        assert_eq!(draws[2], Perturbation::draw(&robustness(42), 2));
+        // run i draws with seed + i
+        assert_eq!(draws[1], Perturbation::draw(&robustness(43), 0));
+        assert_ne!(draws[0].fee_rate, draws[1].fee_rate);
+        for (run, draw) in draws.iter().enumerate() {
+            assert_eq!(draw.seed, 42 + run as u64);
+            assert!((0.0..=0.0004).contains(&draw.fee_rate.unwrap()));
+            assert_eq!(draw.latency_scale, None);
+            assert_eq!(draw.fill_probability, Some(0.5));
+        }
+        let args: Vec<String> = draws[0]
+            .args()
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(args[..4], ["--latency-seed", "42", "--fill-seed", "42"]);
+        assert_eq!(args[4], "--fee-rate");
+        assert_eq!(args[6..], ["--fill-probability", "0.5"]);
+    }
+
+    #[test]
+    fn test_robustness_score() {
+        assert_eq!(robustness_score(&[]), 0.0);
+        // a failed run counts as unprofitable
+        assert_eq!(
+            robustness_score(&[Some(1.0), Some(-1.0), None, Some(0.5)]),
+            0.5
+        );
+        assert_eq!(robustness_score(&[Some(0.0)]), 0.0);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, ffi::OsString, fmt::Write as _, path::Path, str::FromStr};
+
+use anyhow::Context;
+use clap::{CommandFactory, FromArgMatches};
+use market_agent::results::RunResults;
+
+use crate::{config, CliArgs};
+
+// the side by side equity curves of the strategies, written to the results directory
+const VARIANTS_EQUITY_FILE: &str = "variants_equity.csv";
+
+// the name of the strategy of the run flags in the comparison
+pub(crate) const BASE_VARIANT: &str = "base";
+
+// flags shared by every strategy of the run
+const RUN_FLAGS: &[&str] = &[
+    "config",
+    "variant",
+    "symbol",
+    "date",
+    "end_date",
+    "results_dir",
+    "run_label",
+    "runs_root",
+    "results_db",
+];
+
+// A strategy run next to the one of the flags on the same market data, with some of the flags
+// changed, given as name:flag=value,flag,... with the flags in snake_case, e.g.
+// wide:quote_price_tolerance=2,reduce_inventory
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Variant {
+    pub(crate) name: String,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, flags) = s.split_once(':').unwrap_or((s, ""));
+        if name.is_empty()
+            || name == BASE_VARIANT
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "invalid variant {s}, expected name:flag=value,flag,... with a name of letters, \
+                 digits, _ and - other than {BASE_VARIANT}"
+            ));
+        }
+        let mut variant = Variant {
+            name: name.to_string(),
+            flags: vec![],
+        };
+        for flag in flags.split(',').filter(|f| !f.is_empty()) {
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.trim(), Some(value.trim().to_string())),
+                None => (flag.trim(), None),
+            };
+            if RUN_FLAGS.contains(&flag) {
+                return Err(format!("{flag} is shared by every variant of the run"));
+            }
+            variant.flags.push((flag.to_string(), value));
+        }
+        Ok(variant)
+    }
+}
+
+impl Variant {
+    // the flags of the run with the ones of the variant in place of theirs
+    pub(crate) fn cli(&self, args: &[OsString]) -> Result<CliArgs, anyhow::Error> {
+        let mut args = args.to_vec();
+        for (flag, value) in &self.flags {
+            args.push(format!("--{}", flag.replace('_', "-")).into());
+            args.extend(value.iter().map(OsString::from));
+        }
+        let command = CliArgs::command().args_override_self(true);
+        let matches = command
+            .clone()
+            .try_get_matches_from(&args)
+            .with_context(|| format!("invalid flags of variant {}", self.name))?;
+        let mut cli = CliArgs::from_arg_matches(&matches)?;
+        cli.resolved_config = config::resolved(&command, &matches);
+        Ok(cli)
+    }
+}
+
+// Prints the stats of the strategies side by side and writes their equity curves to one file,
+// each carried forward to the points of the others. dirs are the results directories by name.
+pub(crate) fn compare_variants(
+    results_dir: &Path,
+    dirs: &[(&str, &Path)],
+) -> Result<(), anyhow::Error> {
+    let results = dirs
+        .iter()
+        .map(|(_, dir)| RunResults::load(dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!("--- Variants ---");
+    print!("{:<28}", "metric");
+    for (name, _) in dirs {
+        print!(" {:>20}", name);
+    }
+    println!();
+    let mut metrics: Vec<&String> = results.iter().flat_map(|r| r.stats.keys()).collect();
+    metrics.sort();
+    metrics.dedup();
+    for metric in metrics {
+        print!("{:<28}", metric);
+        for result in &results {
+            print!(
+                " {:>20}",
+                result
+                    .stats
+                    .get(metric)
+                    .map_or("-".to_string(), |v| format!("{:.6}", v))
+            );
+        }
+        println!();
+    }
+
+    let mut points: BTreeMap<u64, Vec<Option<f64>>> = BTreeMap::new();
+    for (i, result) in results.iter().enumerate() {
+        for (time_ms, equity) in &result.equity {
+            points.entry(*time_ms).or_insert(vec![None; results.len()])[i] = Some(*equity);
+        }
+    }
+    let mut csv = String::from("time_ms");
+    for (name, _) in dirs {
+        write!(csv, ",{}", name)?;
+    }
+    csv.push('\n');
+    let mut last = vec![None; results.len()];
+    for (time_ms, equities) in points {
+        write!(csv, "{}", time_ms)?;
+        for (last, equity) in last.iter_mut().zip(equities) {
+            *last = equity.or(*last);
+            write!(
+                csv,
+                ",{}",
+                last.map_or(String::new(), |v: f64| v.to_string())
+            )?;
+        }
+        csv.push('\n');
+    }
+    let path = results_dir.join(VARIANTS_EQUITY_FILE);
+    std::fs::write(&path, csv).with_context(|| format!("failed to write {}", path.display()))?;
+    println!("Equity curves written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("sim")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_variant_spec() {
+        let variant: Variant = "wide:quote_price_tolerance=2, reduce_inventory"
+            .parse()
+            .unwrap();
+        assert_eq!(variant.name, "wide");
+        assert_eq!(
+            variant.flags,
+            vec![
+                ("quote_price_tolerance".to_string(), Some("2".to_string())),
+                ("reduce_inventory".to_string(), None),
+            ]
+        );
+        // a variant of the run flags only
+        let same: Variant = "same".parse().unwrap();
+        assert!(same.flags.is_empty());
+
+        assert!("".parse::<Variant>().is_err());
+        assert!(":fee_rate=0.1".parse::<Variant>().is_err());
+        assert!("base:fee_rate=0.1".parse::<Variant>().is_err());
+        assert!("a b:fee_rate=0.1".parse::<Variant>().is_err());
+        assert!("other:symbol=ETHUSDT".parse::<Variant>().is_err());
+    }
+
+    #[test]
+    fn test_variant_overrides_the_run_flags() {
+        let run = args(&["--symbol", "BTCUSDT", "--quote-price-tolerance", "1"]);
+        let variant: Variant = "wide:quote_price_tolerance=2,reduce_inventory,fee_rate=0.001"
+            .parse()
+            .unwrap();
+        let cli = variant.cli(&run).unwrap();
+        assert_eq!(cli.quote_price_tolerance, Some(2.0));
+        assert!(cli.reduce_inventory);
+        assert_eq!(cli.fee_rate, 0.001);
+        assert_eq!(cli.symbol.as_deref(), Some("BTCUSDT"));
+    }
+
+    #[test]
+    fn test_invalid_overrides() {
+        let run = args(&["--symbol", "BTCUSDT"]);
+        for spec in [
+            "typo:quote_price_tolerence=2",
+            "nan:quote_price_tolerance=wide",
+            "missing:quote_price_tolerance",
+            "extra:reduce_inventory=yes",
+        ] {
+            let variant: Variant = spec.parse().unwrap();
+            let e = variant.cli(&run).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("invalid flags of variant {}", variant.name)
+            );
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{bail, Context};
+use market_agent::results::RunResults;
+
+use crate::batch::{date_range, forwarded_args, run_child};
+
+const WALK_FORWARD_FILE: &str = "walk_forward.csv";
+
+// A sim flag and the values tried for it, given as name=value,value,... with the name of the
+// flag in snake_case, e.g. quote_price_tolerance=0.5,1,2
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ParamGrid {
+    name: String,
+    values: Vec<String>,
+}
+
+impl FromStr for ParamGrid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid parameter {s}, expected name=value,value,...");
+        let (name, values) = s.split_once('=').ok_or_else(invalid)?;
+        let values: Vec<String> = values
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        if name.trim().is_empty() || values.is_empty() {
+            return Err(invalid());
+        }
+        Ok(ParamGrid {
+            name: name.trim().to_string(),
+            values,
+        })
+    }
+}
+
+impl ParamGrid {
+    fn flag(&self) -> String {
+        format!("--{}", self.name.replace('_', "-"))
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct WalkForwardArgs {
+    // YYYY-MM-DD, both inclusive
+    #[clap(long)]
+    start_date: String,
+
+    #[clap(long)]
+    end_date: String,
+
+    // days the parameters are chosen on
+    #[clap(long, default_value_t = 5)]
+    train_days: usize,
+
+    // days following the training window the chosen parameters are evaluated on, the windows
+    // roll forward by this many days
+    #[clap(long, default_value_t = 1)]
+    test_days: usize,
+
+    // every combination of the values is tried on each training window
+    #[clap(long, required = true)]
+    param: Vec<ParamGrid>,
+
+    // the stat the parameters are chosen by, the highest wins
+    #[clap(long, default_value = "profit")]
+    objective: String,
+
+    // results are written to <out>/window-<i>/train-<j> and <out>/window-<i>/test
+    #[clap(long, short = 'o')]
+    out: PathBuf,
+
+    // runs in parallel
+    #[clap(long, short = 'j', default_value_t = 1)]
+    jobs: usize,
+}
+
+// A training window and the out of sample window after it
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    train: (String, String),
+    test: (String, String),
+}
+
+fn windows(dates: &[String], train_days: usize, test_days: usize) -> Vec<Window> {
+    let mut windows = vec![];
+    let mut start = 0;
+    while start + train_days + test_days <= dates.len() {
+        let test_start = start + train_days;
+        windows.push(Window {
+            train: (dates[start].clone(), dates[test_start - 1].clone()),
+            test: (
+                dates[test_start].clone(),
+                dates[test_start + test_days - 1].clone(),
+            ),
+        });
+        start += test_days;
+    }
+    windows
+}
+
+// every combination of the values of the grids, as (flag, value) pairs
+fn combinations(grids: &[ParamGrid]) -> Vec<Vec<(String, String)>> {
+    grids.iter().fold(vec![vec![]], |combinations, grid| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                grid.values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((grid.flag(), value.clone()));
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+fn combination_brief(combination: &[(String, String)]) -> String {
+    combination
+        .iter()
+        .map(|(flag, value)| {
+            format!(
+                "{}={}",
+                flag.trim_start_matches("--").replace('-', "_"),
+                value
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A child run over a date range, results already in its directory are reused
+struct Task {
+    dir: PathBuf,
+    dates: (String, String),
+    combination: Vec<(String, String)>,
+}
+
+fn run_task(task: &Task, args: &[OsString], objective: &str) -> Result<f64, anyhow::Error> {
+    if !RunResults::exists(&task.dir) {
+        let mut args = args.to_vec();
+        args.extend([
+            "--date".into(),
+            task.dates.0.clone().into(),
+            "--end-date".into(),
+            task.dates.1.clone().into(),
+        ]);
+        for (flag, value) in &task.combination {
+            args.push(flag.into());
+            args.push(value.into());
+        }
+        run_child(&args, &task.dir)?;
+    }
+    objective_value(&task.dir, objective)
+}
+
+fn objective_value(dir: &Path, objective: &str) -> Result<f64, anyhow::Error> {
+    RunResults::load(dir)?
+        .stats
+        .get(objective)
+        .copied()
+        .with_context(|| format!("no {} in the results of {}", objective, dir.display()))
+}
+
+// the objective of every task, None for the failed ones
+fn run_tasks(tasks: &[Task], args: &[OsString], walk: &WalkForwardArgs) -> Vec<Option<f64>> {
+    let next = AtomicUsize::new(0);
+    let values = Mutex::new(vec![None; tasks.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..walk.jobs.max(1) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(task) = tasks.get(i) else {
+                    break;
+                };
+                let value = run_task(task, args, &walk.objective);
+                match &value {
+                    Ok(value) => println!(
+                        "{} {}..{} {} {:.4}",
+                        task.dir.display(),
+                        task.dates.0,
+                        task.dates.1,
+                        walk.objective,
+                        value
+                    ),
+                    Err(e) => println!("{} failed: {:#}", task.dir.display(), e),
+                }
+                values.lock().unwrap()[i] = value.ok();
+            });
+        }
+    });
+    values.into_inner().unwrap()
+}
+
+fn format_value(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{:.4}", v))
+}
+
+// Optimizes the parameters on a window of train_days and evaluates the best combination on
+// the test_days after it, rolling forward by test_days over the date range. Reports the
+// objective per day in and out of sample, the drop from one to the other is how much of the
+// in-sample result was fitted to the days it was chosen on.
+pub(crate) fn run_walk_forward(walk: &WalkForwardArgs) -> Result<(), anyhow::Error> {
+    let mut run_flags = vec![
+        "--date",
+        "-d",
+        "--start-date",
+        "--end-date",
+        "--results-dir",
+        "--run-label",
+        "--runs-root",
+        "--vis",
+        "-g",
+        "--vis-export",
+    ];
+    let param_flags: Vec<String> = walk.param.iter().map(ParamGrid::flag).collect();
+    run_flags.extend(param_flags.iter().map(String::as_str));
+    let mut args = forwarded_args("walk-forward", &run_flags)?;
+    if !args.iter().any(|arg| arg == "--no-progress") {
+        args.push("--no-progress".into());
+    }
+
+    if walk.train_days == 0 || walk.test_days == 0 {
+        bail!("--train-days and --test-days must be positive");
+    }
+    let dates = date_range(&walk.start_date, &walk.end_date)?;
+    let windows = windows(&dates, walk.train_days, walk.test_days);
+    if windows.is_empty() {
+        bail!(
+            "{} days from {} to {} hold no window of {} training and {} test days",
+            dates.len(),
+            walk.start_date,
+            walk.end_date,
+            walk.train_days,
+            walk.test_days
+        );
+    }
+    let combinations = combinations(&walk.param);
+    println!(
+        "Walk-forward: {} windows, {} parameter combinations, {} jobs",
+        windows.len(),
+        combinations.len(),
+        walk.jobs
+    );
+
+    let window_dir = |i: usize| walk.out.join(format!("window-{:03}", i));
+    let train_tasks: Vec<Task> = windows
+        .iter()
+        .enumerate()
+        .flat_map(|(i, window)| {
+            combinations
+                .iter()
+                .enumerate()
+                .map(move |(j, combination)| Task {
+                    dir: window_dir(i).join(format!("train-{:03}", j)),
+                    dates: window.train.clone(),
+                    combination: combination.clone(),
+                })
+        })
+        .collect();
+    let train_values = run_tasks(&train_tasks, &args, walk);
+
+    // the best combination of each window and its value, none when every run failed
+    let best: Vec<Option<(usize, f64)>> = train_values
+        .chunks(combinations.len())
+        .map(|values| {
+            values
+                .iter()
+                .enumerate()
+                .filter_map(|(j, value)| value.map(|value| (j, value)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+        })
+        .collect();
+    let test_tasks: Vec<Task> = windows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, window)| {
+            best[i].map(|(j, _)| Task {
+                dir: window_dir(i).join("test"),
+                dates: window.test.clone(),
+                combination: combinations[j].clone(),
+            })
+        })
+        .collect();
+    let mut test_values = run_tasks(&test_tasks, &args, walk).into_iter();
+
+    let mut csv = format!(
+        "window,train_start,train_end,test_start,test_end,params,in_sample_{0},\
+         in_sample_{0}_per_day,out_of_sample_{0},out_of_sample_{0}_per_day\n",
+        walk.objective
+    );
+    println!(
+        "{:>6} {:<23} {:<23} {:>14} {:>14}  params",
+        "window", "train", "test", "in/day", "out/day"
+    );
+    let (mut in_total, mut out_total, mut evaluated) = (0.0, 0.0, 0);
+    for (i, window) in windows.iter().enumerate() {
+        let in_sample = best[i].map(|(_, value)| value);
+        let out_of_sample = match best[i] {
+            Some(_) => test_values.next().flatten(),
+            None => None,
+        };
+        let params = best[i].map_or("-".to_string(), |(j, _)| {
+            combination_brief(&combinations[j])
+        });
+        let in_per_day = in_sample.map(|v| v / walk.train_days as f64);
+        let out_per_day = out_of_sample.map(|v| v / walk.test_days as f64);
+        if let (Some(in_per_day), Some(out_per_day)) = (in_per_day, out_per_day) {
+            in_total += in_per_day;
+            out_total += out_per_day;
+            evaluated += 1;
+        }
+        println!(
+            "{:>6} {:<23} {:<23} {:>14} {:>14}  {}",
+            i,
+            format!("{}..{}", window.train.0, window.train.1),
+            format!("{}..{}", window.test.0, window.test.1),
+            format_value(in_per_day),
+            format_value(out_per_day),
+            params
+        );
+        let field = |value: Option<f64>| value.map_or(String::new(), |v| v.to_string());
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{}",
+            i,
+            window.train.0,
+            window.train.1,
+            window.test.0,
+            window.test.1,
+            params,
+            field(in_sample),
+            field(in_per_day),
+            field(out_of_sample),
+            field(out_per_day)
+        )?;
+    }
+    std::fs::create_dir_all(&walk.out)?;
+    let path = walk.out.join(WALK_FORWARD_FILE);
+    std::fs::write(&path, csv).with_context(|| format!("failed to write {}", path.display()))?;
+
+    if evaluated == 0 {
+        bail!("no window was evaluated out of sample");
+    }
+    let (in_mean, out_mean) = (in_total / evaluated as f64, out_total / evaluated as f64);
+    println!(
+        "Walk-forward: {} per day {:.4} in sample, {:.4} out of sample over {} windows, \
+         degradation {:.4}{}",
+        walk.objective,
+        in_mean,
+        out_mean,
+        evaluated,
+        in_mean - out_mean,
+        if in_mean > 0.0 {
+            format!(", efficiency {:.1}%", out_mean / in_mean * 100.0)
+        } else {
+            String::new()
+        }
+    );
+    if evaluated < windows.len() {
+        bail!(
+            "{} windows failed, run again to retry them",
+            windows.len() - evaluated
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days(n: usize) -> Vec<String> {
+        (1..=n).map(|d| format!("2024-01-{:02}", d)).collect()
+    }
+
+    fn window(train: (usize, usize), test: (usize, usize)) -> Window {
+        let day = |d: usize| format!("2024-01-{:02}", d);
+        Window {
+            train: (day(train.0), day(train.1)),
+            test: (day(test.0), day(test.1)),
+        }
+    }
+
+    #[test]
+    fn test_windows_roll_by_test_days() {
+        assert_eq!(
+            windows(&days(7), 3, 2),
+            vec![window((1, 3), (4, 5)), window((3, 5), (6, 7))]
+        );
+        assert_eq!(
+            windows(&days(5), 3, 1),
+            vec![window((1, 3), (4, 4)), window((2, 4), (5, 5)),]
+        );
+    }
+
+    #[test]
+    fn test_partly_covered_last_window_is_dropped() {
+        // the third window would test on days 8 and 9, only 8 is in the range
+        assert_eq!(
+            windows(&days(8), 3, 2),
+            vec![window((1, 3), (4, 5)), window((3, 5), (6, 7))]
+        );
+        // no window fits when the range is shorter than one
+        assert!(windows(&days(4), 3, 2).is_empty());
+    }
+
+    #[test]
+    fn test_param_grid() {
+        let grid: ParamGrid = "quote_price_tolerance=0.5, 1,,2".parse().unwrap();
+        assert_eq!(grid.flag(), "--quote-price-tolerance");
+        assert_eq!(grid.values, vec!["0.5", "1", "2"]);
+        assert!("quote_price_tolerance".parse::<ParamGrid>().is_err());
+        assert!("=1,2".parse::<ParamGrid>().is_err());
+        assert!("spread=".parse::<ParamGrid>().is_err());
+
+        let grids = vec![
+            "a=1,2".parse::<ParamGrid>().unwrap(),
+            "b_c=x".parse::<ParamGrid>().unwrap(),
+        ];
+        let combinations = combinations(&grids);
+        assert_eq!(combinations.len(), 2);
+        assert_eq!(combination_brief(&combinations[0]), "a=1 b_c=x");
+        assert_eq!(combination_brief(&combinations[1]), "a=2 b_c=x");
+    }
+}
@@ -0,0 +1,190 @@
+mod alloc_counter;
+mod synthetic;
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use alloc_counter::{AllocStats, CountingAllocator};
+use binance_republisher::{binance_republisher::BinanceRepublisherBuilder, capture};
+use clap::Parser;
+use grid_strategy::grid_strategy::GridStrategyBuilder;
+use market_agent::{
+    latency::{LatencyModel, LatencyProfile},
+    market_agent::MarketAgentBuilder,
+};
+use simulation::engine::SimulationEngineBuilder;
+use stepper::stepper::StepperBuilder;
+use symbol_info::SymbolInfoManager;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// 2023-12-01 00:00:00 UTC
+const DAY_START_MS: u64 = 1701388800000;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Upstair simulation benchmark", long_about = None)]
+struct CliArgs {
+    // number of trades and booktickers in the synthetic day
+    #[clap(long, short = 'n', default_value_t = 2_000_000)]
+    ticks: u64,
+
+    #[clap(long, short = 's', default_value_t = 42)]
+    seed: u64,
+
+    #[clap(long, short = 'p', default_value = "data/bench")]
+    path: PathBuf,
+
+    // constant order latency, 0 for none. Orders in flight keep the market agent scheduled
+    // while ticks wake it
+    #[clap(long, default_value_t = 0.0)]
+    latency_ms: f64,
+
+    // every wake-up of a module is queued, to compare against coalescing
+    #[clap(long)]
+    no_coalesce: bool,
+
+    // bench the matching with the static grid strategy instead of the stepper
+    #[clap(long)]
+    grid: bool,
+
+    // also run the grid strategy on an account of its own, to bench two strategies sharing
+    // the market
+    #[clap(long, conflicts_with = "grid")]
+    shared_market: bool,
+
+    // replay the synthetic day from its csv files and from their captures with no strategy,
+    // to compare the readers
+    #[clap(long, conflicts_with_all = ["grid", "shared_market"])]
+    replay: bool,
+}
+
+// the time the republisher and the engine alone take to replay paths
+fn replay(symbol: &'static str, paths: &[&Path]) -> Duration {
+    let mut republisher = BinanceRepublisherBuilder::new(symbol);
+    for path in paths {
+        republisher = republisher
+            .with_file(path.to_str().unwrap())
+            .expect("failed to open synthetic data");
+    }
+    let started_at = Instant::now();
+    let mut engine = SimulationEngineBuilder::default()
+        .add_module(republisher)
+        .build();
+    engine.run();
+    started_at.elapsed()
+}
+
+fn bench_replay(symbol: &'static str, day: &synthetic::SyntheticDay) {
+    let csv = [day.trades_path.as_path(), day.bookticker_path.as_path()];
+    let captures: Vec<PathBuf> = csv
+        .iter()
+        .map(|path| {
+            let dest = path.with_extension(capture::EXTENSION);
+            capture::convert(path, &dest).expect("failed to convert synthetic data");
+            dest
+        })
+        .collect();
+    let captures: Vec<&Path> = captures.iter().map(PathBuf::as_path).collect();
+
+    let num_ticks = day.num_ticks() as f64;
+    let csv = replay(symbol, &csv);
+    let captures = replay(symbol, &captures);
+    println!("--- Replay ---");
+    for (name, elapsed) in [("CSV", csv), ("Capture", captures)] {
+        println!(
+            "{}: {:.3} s, {:.0} ticks/sec",
+            name,
+            elapsed.as_secs_f64(),
+            num_ticks / elapsed.as_secs_f64()
+        );
+    }
+    println!(
+        "Speedup: {:.1}x",
+        csv.as_secs_f64() / captures.as_secs_f64()
+    );
+}
+
+fn main() {
+    let cli = CliArgs::parse();
+    println!("{:?}", cli);
+
+    let symbol = "BTCUSDT";
+    let day = synthetic::generate_day(&cli.path, symbol, DAY_START_MS, cli.ticks, cli.seed)
+        .expect("failed to generate synthetic data");
+    println!(
+        "Synthetic day: {} trades, {} booktickers",
+        day.num_trades, day.num_booktickers
+    );
+    if cli.replay {
+        bench_replay(symbol, &day);
+        return;
+    }
+
+    let symbol_info_manager = SymbolInfoManager::default()
+        .with_symbol_config("BTCUSDT", "BTC", "USDT", /*fee rate*/ 0.0000);
+
+    let mut market_agent = MarketAgentBuilder::default()
+        .with_symbol_info_manager(symbol_info_manager.clone())
+        .with_initial_balance("USDT", 50000.0)
+        .with_initial_balance("BTC", 1.0);
+    if cli.latency_ms > 0.0 {
+        let profile = LatencyProfile::from_samples(&[cli.latency_ms]).expect("latency profile");
+        market_agent = market_agent.with_latency_model(LatencyModel::new(profile, cli.seed));
+    }
+    if cli.shared_market {
+        market_agent = market_agent
+            .with_owner_initial_balance("grid", "USDT", 50000.0)
+            .with_owner_initial_balance("grid", "BTC", 1.0);
+    }
+
+    let started_at = Instant::now();
+    let alloc_before = AllocStats::now();
+    let republisher = BinanceRepublisherBuilder::new(symbol)
+        .with_file(day.trades_path.to_str().unwrap())
+        .and_then(|b| b.with_file(day.bookticker_path.to_str().unwrap()))
+        .expect("failed to open synthetic data");
+    let mut engine = SimulationEngineBuilder::default().with_wakeup_coalescing(!cli.no_coalesce);
+    engine = if cli.grid {
+        engine.add_module(GridStrategyBuilder::new(symbol))
+    } else {
+        engine.add_module(
+            StepperBuilder::new(symbol).with_symbol_info_manager(symbol_info_manager.clone()),
+        )
+    };
+    if cli.shared_market {
+        engine = engine.add_module(GridStrategyBuilder::new(symbol).with_owner("grid"));
+    }
+    let mut engine = engine
+        .add_module(market_agent)
+        .add_module(republisher)
+        .build();
+    engine.run();
+    let elapsed = started_at.elapsed();
+    let alloc = AllocStats::now().since(&alloc_before);
+    let profile = engine.profile();
+
+    let num_ticks = day.num_ticks();
+    println!("--- Bench ---");
+    println!("Ticks: {}", num_ticks);
+    println!("Elapsed: {:.3} s", elapsed.as_secs_f64());
+    println!(
+        "Throughput: {:.0} ticks/sec",
+        num_ticks as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "Wake-ups: {} ({} coalesced)",
+        profile.wakeups, profile.coalesced_wakeups
+    );
+    println!("Allocations: {}", alloc.allocations);
+    println!(
+        "Allocations/tick: {:.2}",
+        alloc.allocations as f64 / num_ticks as f64
+    );
+    println!(
+        "Allocated: {:.2} MB",
+        alloc.allocated_bytes as f64 / 1024.0 / 1024.0
+    );
+}
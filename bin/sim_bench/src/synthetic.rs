@@ -0,0 +1,116 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+const TICK_SIZE: f64 = 0.1;
+
+// xorshift64, so the same seed always generates the same day
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub struct SyntheticDay {
+    pub trades_path: PathBuf,
+    pub bookticker_path: PathBuf,
+    pub num_trades: u64,
+    pub num_booktickers: u64,
+}
+
+impl SyntheticDay {
+    pub fn num_ticks(&self) -> u64 {
+        self.num_trades + self.num_booktickers
+    }
+}
+
+// Writes one day of trades and booktickers in binance csv format.
+// Mid price follows a random walk and trades print at the touch.
+pub fn generate_day(
+    dir: &Path,
+    symbol: &str,
+    start_ms: u64,
+    num_ticks: u64,
+    seed: u64,
+) -> Result<SyntheticDay, anyhow::Error> {
+    create_dir_all(dir)?;
+    let trades_path = dir.join(format!("{symbol}-trades-synthetic.csv"));
+    let bookticker_path = dir.join(format!("{symbol}-bookTicker-synthetic.csv"));
+    let mut trades = BufWriter::new(File::create(&trades_path)?);
+    let mut booktickers = BufWriter::new(File::create(&bookticker_path)?);
+    writeln!(trades, "id,price,qty,quote_qty,time,is_buyer_maker")?;
+    writeln!(
+        booktickers,
+        "update_id,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty,transaction_time,event_time"
+    )?;
+
+    let mut rng = Rng::new(seed);
+    let mut bid = 40000.0;
+    let interval_ms = (DAY_MS / num_ticks.max(1)).max(1);
+    let (mut num_trades, mut num_booktickers) = (0, 0);
+    for i in 0..num_ticks {
+        let time = start_ms + i * interval_ms;
+        if rng.next_f64() < 0.4 {
+            let is_buyer_maker = rng.next_f64() < 0.5;
+            let price = if is_buyer_maker { bid } else { bid + TICK_SIZE };
+            let qty = 0.001 * (1 + rng.next_u64() % 500) as f64;
+            writeln!(
+                trades,
+                "{},{:.1},{:.3},{:.4},{},{}",
+                num_trades,
+                price,
+                qty,
+                price * qty,
+                time,
+                is_buyer_maker
+            )?;
+            num_trades += 1;
+        } else {
+            match rng.next_u64() % 3 {
+                0 => bid -= TICK_SIZE,
+                1 => bid += TICK_SIZE,
+                _ => {}
+            }
+            let bid_qty = 0.001 * (1 + rng.next_u64() % 5000) as f64;
+            let ask_qty = 0.001 * (1 + rng.next_u64() % 5000) as f64;
+            writeln!(
+                booktickers,
+                "{},{:.1},{:.3},{:.1},{:.3},{},{}",
+                num_booktickers,
+                bid,
+                bid_qty,
+                bid + TICK_SIZE,
+                ask_qty,
+                time,
+                time
+            )?;
+            num_booktickers += 1;
+        }
+    }
+    trades.flush()?;
+    booktickers.flush()?;
+    Ok(SyntheticDay {
+        trades_path,
+        bookticker_path,
+        num_trades,
+        num_booktickers,
+    })
+}
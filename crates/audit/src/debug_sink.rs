@@ -0,0 +1,211 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use polars::df;
+use tracing::error;
+use upstair_type::{
+    debug_log::{DebugRecord, QuoteDebug},
+    module::{Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle},
+    run_output::RunOutput,
+    Payload,
+};
+use util::parquet::write_parquet;
+
+const VOL_FILE: &str = "vol.parquet";
+const QUOTE_FILE: &str = "quote.parquet";
+const TRADE_FILE: &str = "trade.parquet";
+
+// The debug records of the strategies, by kind
+#[derive(Default)]
+pub struct DebugRecords {
+    vols: Vec<(&'static str, u64, f64)>,
+    quotes: Vec<(&'static str, QuoteDebug)>,
+    fills: Vec<(&'static str, String, f64)>,
+}
+
+impl DebugRecords {
+    pub fn on_message(&mut self, payload: &Payload) {
+        let Payload::DebugLog(log) = payload else {
+            return;
+        };
+        match &log.record {
+            DebugRecord::Vol { time_ms, vol } => self.vols.push((log.symbol, *time_ms, *vol)),
+            DebugRecord::Quote(quote) => self.quotes.push((log.symbol, quote.clone())),
+            DebugRecord::Fill { order_id, filled } => {
+                self.fills.push((log.symbol, order_id.clone(), *filled))
+            }
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let v = &self.vols;
+        write_parquet(
+            dir,
+            VOL_FILE,
+            df!(
+                "time" => v.iter().map(|(_, t, _)| *t).collect::<Vec<_>>(),
+                "symbol" => v.iter().map(|(s, _, _)| *s).collect::<Vec<_>>(),
+                "vol" => v.iter().map(|(_, _, vol)| *vol).collect::<Vec<_>>(),
+            )?,
+        )?;
+        let q = &self.quotes;
+        write_parquet(
+            dir,
+            QUOTE_FILE,
+            df!(
+                "time" => q.iter().map(|(_, q)| q.time_ms).collect::<Vec<_>>(),
+                "symbol" => q.iter().map(|(s, _)| *s).collect::<Vec<_>>(),
+                "id" => q.iter().map(|(_, q)| q.order_id.as_str()).collect::<Vec<_>>(),
+                "price" => q.iter().map(|(_, q)| q.price).collect::<Vec<_>>(),
+                "qty" => q.iter().map(|(_, q)| q.qty).collect::<Vec<_>>(),
+                "fair_price" => q.iter().map(|(_, q)| q.fair_price).collect::<Vec<_>>(),
+                "is_bid" => q.iter().map(|(_, q)| q.is_bid).collect::<Vec<_>>(),
+                "best_bid_price" => q.iter().map(|(_, q)| q.best_bid_price).collect::<Vec<_>>(),
+                "best_bid_qty" => q.iter().map(|(_, q)| q.best_bid_qty).collect::<Vec<_>>(),
+                "best_ask_price" => q.iter().map(|(_, q)| q.best_ask_price).collect::<Vec<_>>(),
+                "best_ask_qty" => q.iter().map(|(_, q)| q.best_ask_qty).collect::<Vec<_>>(),
+            )?,
+        )?;
+        let f = &self.fills;
+        write_parquet(
+            dir,
+            TRADE_FILE,
+            df!(
+                "symbol" => f.iter().map(|(s, _, _)| *s).collect::<Vec<_>>(),
+                "order_id" => f.iter().map(|(_, id, _)| id.as_str()).collect::<Vec<_>>(),
+                "filled" => f.iter().map(|(_, _, filled)| *filled).collect::<Vec<_>>(),
+            )?,
+        )?;
+        Ok(())
+    }
+}
+
+struct DebugSink {
+    debug_log_topic: ReadTopicHandle,
+
+    records: DebugRecords,
+    dir: PathBuf,
+}
+
+impl Module for DebugSink {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.debug_log_topic) {
+            self.records.on_message(&msg.payload);
+        }
+        false
+    }
+
+    fn one_iteration(&mut self, _comms: &mut dyn ModuleComms) {}
+
+    fn terminate(&mut self) {
+        match self.records.save(&self.dir) {
+            Ok(()) => println!("Debug log written to {}", self.dir.display()),
+            Err(e) => error!("failed to write the debug log: {:#}", e),
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Writes the records of the debug_log topic to vol.parquet, quote.parquet and trade.parquet
+// in the run output on terminate, so parallel runs keep their own
+#[derive(Default)]
+pub struct DebugSinkBuilder {
+    debug_log_topic: Option<ReadTopicHandle>,
+
+    dir: Option<PathBuf>,
+}
+
+impl ModuleBuilder for DebugSinkBuilder {
+    fn init_output(&mut self, output: &RunOutput) {
+        self.dir = Some(output.dir().to_path_buf());
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let debug_log_topic = comms.get_topic("debug_log");
+        self.debug_log_topic = comms.subscribe_topic(&debug_log_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(DebugSink {
+            debug_log_topic: self.debug_log_topic.unwrap(),
+            records: DebugRecords::default(),
+            dir: self
+                .dir
+                .expect("the debug sink needs the run output of the engine"),
+        })
+    }
+
+    fn name(&self) -> &str {
+        "debug_sink"
+    }
+
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::Observer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::{io::SerReader, prelude::ParquetReader};
+    use upstair_type::debug_log::DebugLog;
+
+    use super::*;
+
+    #[test]
+    fn test_debug_records() {
+        let log = |record| {
+            Payload::DebugLog(DebugLog {
+                symbol: "BTCUSDT",
+                record,
+            })
+        };
+        let mut records = DebugRecords::default();
+        records.on_message(&log(DebugRecord::Vol {
+            time_ms: 1000,
+            vol: 0.5,
+        }));
+        for (order_id, is_bid) in [("B0", true), ("S0", false)] {
+            records.on_message(&log(DebugRecord::Quote(QuoteDebug {
+                time_ms: 1000,
+                order_id: order_id.to_string(),
+                is_bid,
+                price: 100.0,
+                qty: 0.01,
+                ..Default::default()
+            })));
+        }
+        records.on_message(&log(DebugRecord::Fill {
+            order_id: "B0".to_string(),
+            filled: 0.01,
+        }));
+        // not a debug record
+        records.on_message(&Payload::TradingHalt(upstair_type::control::TradingHalt {
+            reason: "test".to_string(),
+        }));
+
+        let dir = std::env::temp_dir().join(format!("debug_sink_{}", std::process::id()));
+        records.save(&dir).unwrap();
+        let rows = |name: &str| {
+            let file = std::fs::File::open(dir.join(name)).unwrap();
+            ParquetReader::new(file).finish().unwrap().height()
+        };
+        assert_eq!(rows(VOL_FILE), 1);
+        assert_eq!(rows(QUOTE_FILE), 2);
+        assert_eq!(rows(TRADE_FILE), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+pub mod debug_sink;
+pub mod order_audit;
@@ -0,0 +1,615 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use polars::df;
+use tracing::error;
+use upstair_type::{
+    module::{Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle},
+    order::{CancelOrderRequest, CancelReject, OrderRequest, OrderResult, OrderStatus, TradeSide},
+    run_output::RunOutput,
+    time::to_ms,
+    Message, Payload,
+};
+use util::parquet::write_parquet;
+
+const ORDER_AUDIT_FILE: &str = "order_audit.parquet";
+
+// A step in the life of an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderEvent {
+    Requested,
+    CancelRequested,
+    // the exchange did not cancel the order, it rests or was already gone
+    CancelRejected,
+    Acked,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    Rejected,
+    Expired,
+}
+
+impl OrderEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OrderEvent::Requested => "requested",
+            OrderEvent::CancelRequested => "cancel_requested",
+            OrderEvent::CancelRejected => "cancel_rejected",
+            OrderEvent::Acked => "acked",
+            OrderEvent::PartiallyFilled => "partially_filled",
+            OrderEvent::Filled => "filled",
+            OrderEvent::Canceled => "canceled",
+            OrderEvent::Rejected => "rejected",
+            OrderEvent::Expired => "expired",
+        }
+    }
+
+    fn ends_order(&self) -> bool {
+        matches!(
+            self,
+            OrderEvent::Filled | OrderEvent::Canceled | OrderEvent::Rejected | OrderEvent::Expired
+        )
+    }
+}
+
+// An order between its request and its end
+struct OrderLife {
+    requested_at: SystemTime,
+    last_event_at: SystemTime,
+    is_buy: bool,
+    price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AuditRow {
+    // when the event was seen, the strategy sees it at the same time
+    time_ms: u64,
+    // when the exchange sent it, None for requests
+    exchange_time_ms: Option<u64>,
+    symbol: &'static str,
+    order_id: Arc<str>,
+    event: OrderEvent,
+    // None for cancel requests of orders requested before the audit started
+    is_buy: Option<bool>,
+    price: Option<f64>,
+    // requested quantity for requests, filled quantity for fills, 0 otherwise
+    quantity: f64,
+    // of the book of the symbol when the event was seen
+    mid: Option<f64>,
+    // None for orders requested before the audit started
+    since_request_ms: Option<u64>,
+    since_previous_ms: Option<u64>,
+    // why the exchange rejected the order or its cancel
+    reason: Option<&'static str>,
+}
+
+// Every transition of every order, with the latencies between them
+#[derive(Default)]
+pub struct OrderAuditLog {
+    orders: HashMap<Arc<str>, OrderLife>,
+    mid_by_symbol: HashMap<&'static str, f64>,
+    rows: Vec<AuditRow>,
+}
+
+impl OrderAuditLog {
+    pub fn on_message(&mut self, message: &Message, now: SystemTime) {
+        match &message.payload {
+            Payload::BinanceBookTicker(ticker) => {
+                let mid = (ticker.best_bid_price + ticker.best_ask_price) / 2.0;
+                self.mid_by_symbol.insert(ticker.symbol, mid);
+            }
+            Payload::OrderRequest(req) => self.on_order_request(req, now),
+            Payload::CancelOrderRequest(req) => self.on_cancel_request(req, now),
+            Payload::CancelReject(reject) => self.on_cancel_reject(reject, now),
+            Payload::OrderResult(result) => self.on_order_result(result, now),
+            Payload::BatchOrderRequest(batch) => {
+                for req in &batch.orders {
+                    self.on_order_request(req, now);
+                }
+            }
+            Payload::BatchCancelRequest(batch) => {
+                for req in &batch.cancels {
+                    self.on_cancel_request(req, now);
+                }
+            }
+            Payload::OrderResultBatch(batch) => {
+                for result in &batch.results {
+                    self.on_order_result(result, now);
+                }
+                for reject in &batch.cancel_rejects {
+                    self.on_cancel_reject(reject, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_order_request(&mut self, req: &OrderRequest, now: SystemTime) {
+        self.orders.insert(
+            req.client_order_id.clone(),
+            OrderLife {
+                requested_at: now,
+                last_event_at: now,
+                is_buy: req.side == TradeSide::Buy,
+                price: req.price,
+            },
+        );
+        self.record(
+            now,
+            None,
+            req.symbol,
+            &req.client_order_id,
+            OrderEvent::Requested,
+            (Some(req.side == TradeSide::Buy), Some(req.price)),
+            req.quantity,
+        );
+    }
+
+    fn on_cancel_request(&mut self, req: &CancelOrderRequest, now: SystemTime) {
+        let order = self
+            .orders
+            .get(&req.client_order_id)
+            .map(|o| (Some(o.is_buy), Some(o.price)));
+        self.record(
+            now,
+            None,
+            req.symbol,
+            &req.client_order_id,
+            OrderEvent::CancelRequested,
+            order.unwrap_or_default(),
+            0.0,
+        );
+    }
+
+    fn on_cancel_reject(&mut self, reject: &CancelReject, now: SystemTime) {
+        let order = self
+            .orders
+            .get(&reject.client_order_id)
+            .map(|o| (Some(o.is_buy), Some(o.price)));
+        self.record(
+            now,
+            Some(to_ms(reject.at)),
+            reject.symbol,
+            &reject.client_order_id,
+            OrderEvent::CancelRejected,
+            order.unwrap_or_default(),
+            0.0,
+        );
+        self.set_reason(reject.reason.as_str());
+    }
+
+    fn on_order_result(&mut self, result: &OrderResult, now: SystemTime) {
+        let event = match result.status {
+            OrderStatus::New => OrderEvent::Acked,
+            OrderStatus::PartiallyFilled => OrderEvent::PartiallyFilled,
+            OrderStatus::Filled => OrderEvent::Filled,
+            OrderStatus::Canceled => OrderEvent::Canceled,
+            OrderStatus::Rejected | OrderStatus::RateLimited => OrderEvent::Rejected,
+            OrderStatus::Expired | OrderStatus::ExpiredInMatch => OrderEvent::Expired,
+        };
+        self.record(
+            now,
+            Some(to_ms(result.at)),
+            result.symbol,
+            &result.client_order_id,
+            event,
+            (Some(result.is_buy), Some(result.price)),
+            result.filled_quantity,
+        );
+        if let Some(reason) = result.reject_reason {
+            self.set_reason(reason.as_str());
+        }
+        if event.ends_order() {
+            self.orders.remove(&result.client_order_id);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &mut self,
+        now: SystemTime,
+        exchange_time_ms: Option<u64>,
+        symbol: &'static str,
+        order_id: &Arc<str>,
+        event: OrderEvent,
+        (is_buy, price): (Option<bool>, Option<f64>),
+        quantity: f64,
+    ) {
+        let since = |at: SystemTime| now.duration_since(at).unwrap_or_default().as_millis() as u64;
+        let order = self.orders.get_mut(order_id);
+        let (since_request_ms, since_previous_ms) = order.map_or((None, None), |order| {
+            let since_previous = since(order.last_event_at);
+            order.last_event_at = now;
+            (Some(since(order.requested_at)), Some(since_previous))
+        });
+        self.rows.push(AuditRow {
+            time_ms: to_ms(now),
+            exchange_time_ms,
+            symbol,
+            order_id: order_id.clone(),
+            event,
+            is_buy,
+            price,
+            quantity,
+            mid: self.mid_by_symbol.get(symbol).copied(),
+            since_request_ms,
+            since_previous_ms,
+            reason: None,
+        });
+    }
+
+    // of the row recorded last
+    fn set_reason(&mut self, reason: &'static str) {
+        if let Some(row) = self.rows.last_mut() {
+            row.reason = Some(reason);
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let r = &self.rows;
+        write_parquet(
+            dir,
+            ORDER_AUDIT_FILE,
+            df!(
+                "time" => r.iter().map(|r| r.time_ms).collect::<Vec<_>>(),
+                "exchange_time" => r.iter().map(|r| r.exchange_time_ms).collect::<Vec<_>>(),
+                "symbol" => r.iter().map(|r| r.symbol).collect::<Vec<_>>(),
+                "order_id" => r.iter().map(|r| r.order_id.as_ref()).collect::<Vec<_>>(),
+                "event" => r.iter().map(|r| r.event.as_str()).collect::<Vec<_>>(),
+                "is_buy" => r.iter().map(|r| r.is_buy).collect::<Vec<_>>(),
+                "price" => r.iter().map(|r| r.price).collect::<Vec<_>>(),
+                "quantity" => r.iter().map(|r| r.quantity).collect::<Vec<_>>(),
+                "mid" => r.iter().map(|r| r.mid).collect::<Vec<_>>(),
+                "since_request_ms" => r.iter().map(|r| r.since_request_ms).collect::<Vec<_>>(),
+                "since_previous_ms" => r.iter().map(|r| r.since_previous_ms).collect::<Vec<_>>(),
+                "reason" => r.iter().map(|r| r.reason).collect::<Vec<_>>(),
+            )?,
+        )?;
+        Ok(())
+    }
+}
+
+struct OrderAudit {
+    market_data_topic: ReadTopicHandle,
+    order_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+
+    log: OrderAuditLog,
+    dir: PathBuf,
+}
+
+impl Module for OrderAudit {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        let now = comms.time();
+        // the book first, the orders of this moment were sent against it
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            self.log.on_message(&msg, now);
+        }
+        while let Some(msg) = comms.receive_shared(&self.order_topic) {
+            self.log.on_message(&msg, now);
+        }
+        while let Some(msg) = comms.receive_shared(&self.order_result_topic) {
+            self.log.on_message(&msg, now);
+        }
+        false
+    }
+
+    fn one_iteration(&mut self, _comms: &mut dyn ModuleComms) {}
+
+    fn terminate(&mut self) {
+        match self.log.save(&self.dir) {
+            Ok(()) => println!(
+                "Order audit written to {}",
+                self.dir.join(ORDER_AUDIT_FILE).display()
+            ),
+            Err(e) => error!("failed to write the order audit: {:#}", e),
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    // woken by every message to see it when the strategy does
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Writes every order request, cancel request and order result to order_audit.parquet in the
+// run output on terminate, for ack latency, time in book and fill ratio by distance to mid
+// offline
+#[derive(Default)]
+pub struct OrderAuditBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    order_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+
+    dir: Option<PathBuf>,
+}
+
+impl ModuleBuilder for OrderAuditBuilder {
+    fn init_output(&mut self, output: &RunOutput) {
+        self.dir = Some(output.dir().to_path_buf());
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let order_topic = comms.get_topic("order");
+        let order_result_topic = comms.get_topic("order_result");
+
+        self.market_data_topic = comms.subscribe_topic(&market_data_topic).into();
+        self.order_topic = comms.subscribe_topic(&order_topic).into();
+        self.order_result_topic = comms.subscribe_topic(&order_result_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(OrderAudit {
+            market_data_topic: self.market_data_topic.unwrap(),
+            order_topic: self.order_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            log: OrderAuditLog::default(),
+            dir: self
+                .dir
+                .expect("the order audit needs the run output of the engine"),
+        })
+    }
+
+    fn name(&self) -> &str {
+        "order_audit"
+    }
+
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::Observer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use polars::{io::SerReader, prelude::ParquetReader};
+    use upstair_type::{
+        data::market::BinanceBookTicker,
+        order::{
+            BatchCancelRequest, BatchOrderRequest, CancelRejectReason, OrderResultBatch,
+            RejectReason, TimeInForce, TradeType,
+        },
+        MessageHeader,
+    };
+
+    use super::*;
+
+    fn at_ms(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn message(payload: Payload) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: UNIX_EPOCH,
+            },
+            payload,
+        }
+    }
+
+    fn request(order_id: &str) -> Message {
+        message(Payload::OrderRequest(OrderRequest {
+            symbol: "BTCUSDT",
+            side: TradeSide::Buy,
+            price: 99.0,
+            quantity: 1.0,
+            trade_type: TradeType::LimitMaker,
+            time_in_force: TimeInForce::GoodTilCancelled,
+            client_order_id: order_id.into(),
+            cancel_order_id: None,
+            owner: None,
+        }))
+    }
+
+    fn result(order_id: &str, at: u64, filled_quantity: f64, status: OrderStatus) -> Message {
+        message(Payload::OrderResult(OrderResult {
+            symbol: "BTCUSDT",
+            at: at_ms(at),
+            client_order_id: order_id.into(),
+            filled_quantity,
+            price: 99.0,
+            is_buy: true,
+            status,
+            seq: 0,
+            owner: None,
+            reject_reason: None,
+        }))
+    }
+
+    #[test]
+    fn test_order_lifecycle() {
+        let mut log = OrderAuditLog::default();
+        let book = message(Payload::BinanceBookTicker(BinanceBookTicker {
+            update_id: 0,
+            best_bid_price: 99.0,
+            best_bid_qty: 1.0,
+            best_ask_price: 101.0,
+            best_ask_qty: 1.0,
+            transaction_time: 0,
+            event_time: 0,
+            symbol: "BTCUSDT",
+        }));
+        log.on_message(&book, at_ms(0));
+        log.on_message(&request("B0"), at_ms(0));
+        // acked by the exchange at 5ms, seen at 10ms
+        log.on_message(&result("B0", 5, 0.0, OrderStatus::New), at_ms(10));
+        log.on_message(
+            &result("B0", 995, 0.4, OrderStatus::PartiallyFilled),
+            at_ms(1000),
+        );
+        log.on_message(&result("B0", 1995, 0.6, OrderStatus::Filled), at_ms(2000));
+        log.on_message(&request("B1"), at_ms(2000));
+        log.on_message(
+            &message(Payload::CancelOrderRequest(CancelOrderRequest {
+                symbol: "BTCUSDT",
+                client_order_id: "B1".into(),
+                owner: None,
+            })),
+            at_ms(2500),
+        );
+        log.on_message(&result("B1", 2505, 0.0, OrderStatus::Canceled), at_ms(2510));
+        // requested before the audit started
+        log.on_message(&result("B2", 3000, 0.0, OrderStatus::Canceled), at_ms(3000));
+
+        let events: Vec<_> = log.rows.iter().map(|r| r.event.as_str()).collect();
+        assert_eq!(
+            events,
+            [
+                "requested",
+                "acked",
+                "partially_filled",
+                "filled",
+                "requested",
+                "cancel_requested",
+                "canceled",
+                "canceled",
+            ]
+        );
+        let ack = &log.rows[1];
+        assert_eq!(ack.exchange_time_ms, Some(5));
+        assert_eq!(ack.since_request_ms, Some(10));
+        assert_eq!(log.rows[0].mid, Some(100.0));
+        assert_eq!(log.rows[3].since_request_ms, Some(2000));
+        assert_eq!(log.rows[3].since_previous_ms, Some(1000));
+        assert_eq!(log.rows[5].price, Some(99.0));
+        assert_eq!(log.rows[7].since_request_ms, None);
+        // ended orders are forgotten
+        assert!(log.orders.is_empty());
+
+        let dir = std::env::temp_dir().join(format!("order_audit_{}", std::process::id()));
+        log.save(&dir).unwrap();
+        let file = std::fs::File::open(dir.join(ORDER_AUDIT_FILE)).unwrap();
+        assert_eq!(ParquetReader::new(file).finish().unwrap().height(), 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reject_reasons() {
+        let mut log = OrderAuditLog::default();
+        log.on_message(&request("B0"), at_ms(0));
+        let mut rejected = result("B0", 5, 0.0, OrderStatus::Rejected);
+        if let Payload::OrderResult(result) = &mut rejected.payload {
+            result.reject_reason = Some(RejectReason::PostOnlyCross);
+        }
+        log.on_message(&rejected, at_ms(10));
+        log.on_message(
+            &message(Payload::CancelReject(CancelReject {
+                symbol: "BTCUSDT",
+                at: at_ms(15),
+                client_order_id: "B0".into(),
+                reason: CancelRejectReason::UnknownOrder,
+                owner: None,
+            })),
+            at_ms(20),
+        );
+
+        let rows: Vec<_> = log.rows.iter().map(|r| (r.event, r.reason)).collect();
+        assert_eq!(
+            rows,
+            [
+                (OrderEvent::Requested, None),
+                (OrderEvent::Rejected, Some("post_only_cross")),
+                (OrderEvent::CancelRejected, Some("unknown_order")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut log = OrderAuditLog::default();
+        let orders = ["B0", "B1"].map(|id| match request(id).payload {
+            Payload::OrderRequest(req) => req,
+            _ => unreachable!(),
+        });
+        log.on_message(
+            &message(Payload::BatchOrderRequest(BatchOrderRequest {
+                symbol: "BTCUSDT",
+                orders: orders.to_vec(),
+                owner: None,
+            })),
+            at_ms(0),
+        );
+        let results = ["B0", "B1"].map(|id| match result(id, 5, 0.0, OrderStatus::New).payload {
+            Payload::OrderResult(result) => result,
+            _ => unreachable!(),
+        });
+        log.on_message(
+            &message(Payload::OrderResultBatch(OrderResultBatch {
+                symbol: "BTCUSDT",
+                results: results.to_vec(),
+                cancel_rejects: vec![],
+                owner: None,
+            })),
+            at_ms(10),
+        );
+        log.on_message(
+            &message(Payload::BatchCancelRequest(BatchCancelRequest {
+                symbol: "BTCUSDT",
+                cancels: ["B0", "B2"]
+                    .map(|id| CancelOrderRequest {
+                        symbol: "BTCUSDT",
+                        client_order_id: id.into(),
+                        owner: None,
+                    })
+                    .to_vec(),
+                owner: None,
+            })),
+            at_ms(20),
+        );
+        let canceled = match result("B0", 25, 0.0, OrderStatus::Canceled).payload {
+            Payload::OrderResult(result) => result,
+            _ => unreachable!(),
+        };
+        log.on_message(
+            &message(Payload::OrderResultBatch(OrderResultBatch {
+                symbol: "BTCUSDT",
+                results: vec![canceled],
+                cancel_rejects: vec![CancelReject {
+                    symbol: "BTCUSDT",
+                    at: at_ms(25),
+                    client_order_id: "B2".into(),
+                    reason: CancelRejectReason::UnknownOrder,
+                    owner: None,
+                }],
+                owner: None,
+            })),
+            at_ms(30),
+        );
+
+        let rows: Vec<_> = log
+            .rows
+            .iter()
+            .map(|r| (r.order_id.as_ref(), r.event.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("B0", "requested"),
+                ("B1", "requested"),
+                ("B0", "acked"),
+                ("B1", "acked"),
+                ("B0", "cancel_requested"),
+                ("B2", "cancel_requested"),
+                ("B0", "canceled"),
+                ("B2", "cancel_rejected"),
+            ]
+        );
+        assert_eq!(log.rows[2].since_request_ms, Some(10));
+        assert_eq!(log.rows[7].reason, Some("unknown_order"));
+    }
+}
@@ -4,20 +4,78 @@ use std::{
     fs::File,
     io::{BufRead, BufReader},
     iter::Peekable,
+    ops::Range,
     path::{Path, PathBuf},
-    sync::mpsc::{self, sync_channel, Receiver},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
     thread,
-    time::{Duration, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use upstair_type::{
+    aggregate::{BinanceAggTrade, BinanceKline, MarkPrice},
+    control::{DataQuality, DayRoll},
     data::market::{BinanceBookTicker, BinanceTradeTick},
-    module::{Module, ModuleBuilder, WriteTopicHandle},
+    module::{Module, ModuleBuilder, ModulePriority, WriteTopicHandle},
     Message, Payload,
 };
 
+use crate::capture::{self, CaptureRecord, CaptureTicks};
+use crate::csv_columns::{is_header, split_csv_line, CsvColumnMapping, CsvField, MAX_CSV_FIELDS};
+use crate::mark_price::{IndexPriceKline, MarkPriceKline, MarkPriceStream};
+use crate::tick_cache::{CacheRecord, CacheWriter, TickCache};
+use crate::time_order::ReorderBuffer;
+use crate::validation::{MarketDataValidator, ValidationConfig};
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
-use tracing::info;
+use tracing::{error, info, warn};
+
+// Line accounting of a republished csv file
+#[derive(Debug, Clone, Default)]
+pub struct CsvParseStats {
+    pub path: PathBuf,
+    pub lines: u64,
+    pub header_skipped: bool,
+    pub parse_errors: u64,
+    pub first_error: Option<String>,
+    // read from the tick cache instead of parsed
+    pub cached: bool,
+    // a capture of sim convert, read without parsing
+    pub capture: bool,
+}
+
+impl std::fmt::Display for CsvParseStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}: {} lines, {} parse errors",
+            self.path, self.lines, self.parse_errors
+        )?;
+        if self.header_skipped {
+            write!(f, ", header skipped")?;
+        }
+        if self.cached {
+            write!(f, ", from tick cache")?;
+        }
+        if self.capture {
+            write!(f, ", from capture")?;
+        }
+        if let Some(first_error) = &self.first_error {
+            write!(f, ", first error at {}", first_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_summary(stats: &[CsvParseStats]) -> String {
+    stats
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
 #[derive(Debug, Default)]
 enum PeekingTick {
@@ -25,14 +83,72 @@ enum PeekingTick {
     None,
     TradeTick(BinanceTradeTick),
     BookTicker(BinanceBookTicker),
+    AggTrade(BinanceAggTrade),
+    Kline(BinanceKline),
+    MarkPrice(MarkPrice),
+}
+
+// The ticks of a kind, parsed by a reader thread, or decoded here when all of its files are
+// captures, which skips the channel as well
+enum TickStream<T: CaptureRecord> {
+    Reader(mpsc::IntoIter<T>),
+    Capture(std::iter::Flatten<std::vec::IntoIter<CaptureTicks<T>>>),
+}
+
+impl<T: CaptureRecord> Iterator for TickStream<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            TickStream::Reader(rx) => rx.next(),
+            TickStream::Capture(ticks) => ticks.next(),
+        }
+    }
 }
 
 pub struct BinanceRepublisher {
     write_market_data_handle: WriteTopicHandle,
-    trade_tick_peekable_iter: Peekable<mpsc::IntoIter<BinanceTradeTick>>,
-    bookticker_peekable_iter: Peekable<mpsc::IntoIter<BinanceBookTicker>>,
+    trade_tick_peekable_iter: Peekable<TickStream<BinanceTradeTick>>,
+    bookticker_peekable_iter: Peekable<TickStream<BinanceBookTicker>>,
+    agg_trade_peekable_iter: Peekable<TickStream<BinanceAggTrade>>,
+    kline_peekable_iter: Peekable<TickStream<BinanceKline>>,
+    mark_price_peekable_iter:
+        Peekable<MarkPriceStream<TickStream<MarkPriceKline>, TickStream<IndexPriceKline>>>,
     peeking_tick: PeekingTick,
     peeking_tick_time: std::time::SystemTime,
+    // filled by the csv reader threads once a file is read
+    parse_stats: Arc<Mutex<Vec<CsvParseStats>>>,
+    parse_aborted: Arc<AtomicBool>,
+    // UTC day of the last published tick, counted from the epoch
+    day: Option<u64>,
+    validator: Option<MarketDataValidator>,
+    // ticks outside are skipped, e.g. the other days of a monthly file
+    time_range: Option<Range<SystemTime>>,
+    reorder: ReorderBuffer<PeekingTick>,
+    // trades are gathered for up to the window and published together, none publishes each
+    trade_batch_window: Option<Duration>,
+    trade_batch: Vec<BinanceTradeTick>,
+    // the times of the first and the last trade of the batch
+    trade_batch_times: (SystemTime, SystemTime),
+}
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+// the day roll to publish before a tick at time, none for the first day
+fn day_roll(day: &mut Option<u64>, time: SystemTime) -> Option<DayRoll> {
+    let tick_day = day_of(time);
+    let last_day = day.replace(tick_day)?;
+    (tick_day > last_day).then(|| DayRoll {
+        day_start: UNIX_EPOCH + Duration::from_secs(tick_day * DAY_SECS),
+    })
+}
+
+// the UTC day of time, counted from the epoch
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / DAY_SECS
 }
 
 impl Module for BinanceRepublisher {
@@ -41,6 +157,12 @@ impl Module for BinanceRepublisher {
     }
 
     fn one_iteration(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        // the readers stop on too many parse errors, which ends the data early: stop the run
+        // and fail it, see failure
+        if self.parse_aborted.load(Ordering::Relaxed) {
+            comms.request_terminate();
+            return;
+        }
         let now = comms.time();
         loop {
             if self.peeking_tick_time > now {
@@ -49,19 +171,49 @@ impl Module for BinanceRepublisher {
             let payload = match std::mem::take(&mut self.peeking_tick) {
                 PeekingTick::TradeTick(tick) => Payload::BinanceTradeTick(tick),
                 PeekingTick::BookTicker(tick) => Payload::BinanceBookTicker(tick),
+                PeekingTick::AggTrade(trade) => Payload::BinanceAggTrade(trade),
+                PeekingTick::Kline(kline) => Payload::BinanceKline(kline),
+                PeekingTick::MarkPrice(mark) => Payload::MarkPrice(mark),
                 PeekingTick::None => break,
             };
-            comms.publish(
-                &self.write_market_data_handle,
-                Message {
-                    header: upstair_type::MessageHeader {
-                        commit_at: self.peeking_tick_time,
+            if let Some(roll) = day_roll(&mut self.day, self.peeking_tick_time) {
+                comms.publish(
+                    &self.write_market_data_handle,
+                    Message {
+                        header: upstair_type::MessageHeader {
+                            commit_at: self.peeking_tick_time,
+                        },
+                        payload: Payload::DayRoll(roll),
                     },
-                    payload,
-                },
-            );
+                );
+            }
+            if self.validate(comms, &payload) {
+                match payload {
+                    Payload::BinanceTradeTick(tick) if self.trade_batch_window.is_some() => {
+                        if self.trade_batch.is_empty() {
+                            self.trade_batch_times.0 = self.peeking_tick_time;
+                        }
+                        self.trade_batch_times.1 = self.peeking_tick_time;
+                        self.trade_batch.push(tick);
+                    }
+                    payload => comms.publish(
+                        &self.write_market_data_handle,
+                        Message {
+                            header: upstair_type::MessageHeader {
+                                commit_at: self.peeking_tick_time,
+                            },
+                            payload,
+                        },
+                    ),
+                }
+            }
             self.next_tick();
-            if matches!(self.peeking_tick, PeekingTick::None) {
+            if !self.joins_trade_batch() {
+                self.publish_trade_batch(comms);
+            }
+            if self.parse_aborted.load(Ordering::Relaxed)
+                || matches!(self.peeking_tick, PeekingTick::None)
+            {
                 comms.request_terminate();
                 return;
             }
@@ -82,43 +234,162 @@ impl Module for BinanceRepublisher {
     fn wake_on_message(&self) -> bool {
         false
     }
+
+    fn terminate(&mut self) {
+        let stats = self.parse_stats.lock().unwrap();
+        if !stats.is_empty() {
+            println!("Republished csv files:\n{}", parse_summary(&stats));
+        }
+        let order = self.reorder.stats;
+        if order.out_of_order > 0 {
+            println!(
+                "Time order: {} ticks out of order, {} late by up to {} ms published at the time before them",
+                order.out_of_order, order.late, order.max_lag_ms
+            );
+        }
+        if let Some(validator) = &self.validator {
+            let stats = validator.stats;
+            println!(
+                "Market data validation: {} stale books, {} trade gaps, {} outliers filtered",
+                stats.stale_books, stats.gaps, stats.outliers
+            );
+        }
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.parse_aborted.load(Ordering::Relaxed).then(|| {
+            format!(
+                "too many csv parse errors, abort republishing\n{}",
+                parse_summary(&self.parse_stats.lock().unwrap())
+            )
+        })
+    }
 }
 
 impl BinanceRepublisher {
-    fn next_tick(&mut self) -> bool {
-        match (
-            self.trade_tick_peekable_iter.peek(),
-            self.bookticker_peekable_iter.peek(),
-        ) {
-            (None, None) => {
-                info!("no more tick to read");
-                self.peeking_tick = PeekingTick::None;
-                false
+    // runs the validation pass on a tick about to be published, publishing the quality flags
+    // when they change. Returns false when the tick is to be dropped.
+    fn validate(
+        &mut self,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+        payload: &Payload,
+    ) -> bool {
+        let Some(validator) = &mut self.validator else {
+            return true;
+        };
+        let time = self.peeking_tick_time;
+        let (symbol, valid) = match payload {
+            Payload::BinanceTradeTick(tick) => (tick.symbol, validator.check_trade(tick, time)),
+            Payload::BinanceAggTrade(trade) => (
+                trade.symbol,
+                validator.check_trade(&trade.to_trade_tick(), time),
+            ),
+            Payload::BinanceBookTicker(ticker) => {
+                validator.check_book(time);
+                (ticker.symbol, true)
             }
-            (None, Some(bookticker)) => {
-                self.peeking_tick = PeekingTick::BookTicker(bookticker.clone());
-                self.peeking_tick_time = UNIX_EPOCH + Duration::from_millis(bookticker.event_time);
-                self.bookticker_peekable_iter.next();
-                true
+            _ => return true,
+        };
+        if let Some(flags) = validator.changed_flags() {
+            if flags.is_degraded() {
+                warn!(
+                    "market data of {} degraded at {:?}: {:?}",
+                    symbol, time, flags
+                );
             }
-            (Some(trade_tick), None) => {
-                self.peeking_tick = PeekingTick::TradeTick(trade_tick.clone());
-                self.peeking_tick_time = UNIX_EPOCH + Duration::from_millis(trade_tick.time);
-                self.trade_tick_peekable_iter.next();
-                true
+            // the flags are about the ticks from here on
+            self.publish_trade_batch(comms);
+            comms.publish(
+                &self.write_market_data_handle,
+                Message {
+                    header: upstair_type::MessageHeader { commit_at: time },
+                    payload: Payload::DataQuality(DataQuality { symbol, flags }),
+                },
+            );
+        }
+        valid
+    }
+
+    // whether the tick peeked is a trade to add to the batch pending, one within its window
+    // and on its day
+    fn joins_trade_batch(&self) -> bool {
+        let (Some(window), false) = (self.trade_batch_window, self.trade_batch.is_empty()) else {
+            return false;
+        };
+        matches!(self.peeking_tick, PeekingTick::TradeTick(_))
+            && self.peeking_tick_time <= self.trade_batch_times.0 + window
+            && day_of(self.peeking_tick_time) == day_of(self.trade_batch_times.1)
+    }
+
+    // publishes the trades pending at the time of the iteration: the one of the last trade,
+    // or a later one when a change of the quality flags cuts the batch short
+    fn publish_trade_batch(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        if self.trade_batch.is_empty() {
+            return;
+        }
+        comms.publish(
+            &self.write_market_data_handle,
+            Message {
+                header: upstair_type::MessageHeader {
+                    commit_at: comms.time(),
+                },
+                payload: Payload::TradeTickBatch(std::mem::take(&mut self.trade_batch)),
+            },
+        );
+    }
+
+    // the stream with the earliest tick and its time, a bookticker first on equal times
+    fn earliest_stream(&mut self) -> Option<(u64, usize)> {
+        let times = [
+            self.bookticker_peekable_iter.peek().map(|t| t.event_time),
+            self.trade_tick_peekable_iter.peek().map(|t| t.time),
+            self.agg_trade_peekable_iter.peek().map(|t| t.time),
+            self.kline_peekable_iter.peek().map(|k| k.close_time),
+            self.mark_price_peekable_iter.peek().map(|m| m.time),
+        ];
+        times
+            .iter()
+            .enumerate()
+            .filter_map(|(stream, time)| time.map(|time| (time, stream)))
+            .min()
+    }
+
+    fn next_tick(&mut self) -> bool {
+        loop {
+            while self.reorder.wants_more() {
+                let Some((time, stream)) = self.earliest_stream() else {
+                    break;
+                };
+                let tick = match stream {
+                    0 => PeekingTick::BookTicker(self.bookticker_peekable_iter.next().unwrap()),
+                    1 => PeekingTick::TradeTick(self.trade_tick_peekable_iter.next().unwrap()),
+                    2 => PeekingTick::AggTrade(self.agg_trade_peekable_iter.next().unwrap()),
+                    3 => PeekingTick::Kline(self.kline_peekable_iter.next().unwrap()),
+                    _ => PeekingTick::MarkPrice(self.mark_price_peekable_iter.next().unwrap()),
+                };
+                self.reorder.push(time, tick);
             }
-            (Some(trade_tick), Some(bookticker)) => {
-                if trade_tick.time < bookticker.event_time {
-                    self.peeking_tick = PeekingTick::TradeTick(trade_tick.clone());
-                    self.peeking_tick_time = UNIX_EPOCH + Duration::from_millis(trade_tick.time);
-                    self.trade_tick_peekable_iter.next();
-                } else {
-                    self.peeking_tick = PeekingTick::BookTicker(bookticker.clone());
-                    self.peeking_tick_time =
-                        UNIX_EPOCH + Duration::from_millis(bookticker.event_time);
-                    self.bookticker_peekable_iter.next();
+            let Some((time, tick)) = self.reorder.pop() else {
+                info!("no more tick to read");
+                self.peeking_tick = PeekingTick::None;
+                return false;
+            };
+            let tick_time = UNIX_EPOCH + Duration::from_millis(time);
+            if let Some(range) = &self.time_range {
+                if tick_time >= range.end {
+                    info!("no more tick in the time range");
+                    self.peeking_tick = PeekingTick::None;
+                    return false;
                 }
-                true
+            }
+            self.peeking_tick_time = tick_time;
+            self.peeking_tick = tick;
+            if self
+                .time_range
+                .as_ref()
+                .is_none_or(|range| tick_time >= range.start)
+            {
+                return true;
             }
         }
     }
@@ -129,6 +400,16 @@ pub struct BinanceRepublisherBuilder {
     write_target_topic_handle: Option<WriteTopicHandle>,
     files: Vec<(File, PathBuf)>,
     show_progress: bool,
+    // abort once a file has more unparseable lines than this
+    max_parse_errors: Option<u64>,
+    // column layout of files without a header row
+    trade_tick_columns: CsvColumnMapping,
+    bookticker_columns: CsvColumnMapping,
+    tick_cache: Option<TickCache>,
+    validation: Option<ValidationConfig>,
+    time_range: Option<Range<SystemTime>>,
+    reorder_window: Duration,
+    trade_batch_window: Option<Duration>,
 }
 
 impl BinanceRepublisherBuilder {
@@ -138,6 +419,14 @@ impl BinanceRepublisherBuilder {
             write_target_topic_handle: None,
             files: vec![],
             show_progress: false,
+            max_parse_errors: None,
+            trade_tick_columns: CsvColumnMapping::identity(BinanceTradeTick::FIELDS),
+            bookticker_columns: CsvColumnMapping::identity(BinanceBookTicker::FIELDS),
+            tick_cache: None,
+            validation: None,
+            time_range: None,
+            reorder_window: Duration::ZERO,
+            trade_batch_window: None,
         }
     }
 
@@ -151,6 +440,96 @@ impl BinanceRepublisherBuilder {
         self.show_progress = show_progress;
         self
     }
+
+    pub fn with_max_parse_errors(mut self, max_parse_errors: u64) -> Self {
+        self.max_parse_errors = Some(max_parse_errors);
+        self
+    }
+
+    // columns are the comma separated column names, e.g. agg_trade_id,price,quantity,...
+    pub fn with_trade_tick_columns(mut self, columns: &str) -> Result<Self, anyhow::Error> {
+        self.trade_tick_columns = CsvColumnMapping::from_header(columns, BinanceTradeTick::FIELDS)?;
+        Ok(self)
+    }
+
+    pub fn with_bookticker_columns(mut self, columns: &str) -> Result<Self, anyhow::Error> {
+        self.bookticker_columns =
+            CsvColumnMapping::from_header(columns, BinanceBookTicker::FIELDS)?;
+        Ok(self)
+    }
+
+    // read parsed ticks from the cache and add the files parsed
+    pub fn with_tick_cache(mut self, tick_cache: TickCache) -> Self {
+        self.tick_cache = Some(tick_cache);
+        self
+    }
+
+    // flag stale books and trade gaps and drop outlier trades, the flags are published as
+    // DataQuality messages
+    pub fn with_validation(mut self, config: ValidationConfig) -> Self {
+        self.validation = Some(config);
+        self
+    }
+
+    // republish only the ticks from start until end, for files covering more than the run
+    // such as the monthly files of Binance
+    pub fn with_time_range(mut self, start: SystemTime, end: SystemTime) -> Self {
+        self.time_range = Some(start..end);
+        self
+    }
+
+    // hold ticks back for up to window to sort in the ones read out of order
+    pub fn with_reorder_window(mut self, window: Duration) -> Self {
+        self.reorder_window = window;
+        self
+    }
+
+    // publish the consecutive trades within window of the first one as one TradeTickBatch, at
+    // the time of the last one, which wakes the modules reading the market data once for them
+    pub fn with_trade_batch_window(mut self, window: Duration) -> Self {
+        self.trade_batch_window = Some(window);
+        self
+    }
+
+    // the klines of the files closing in the time range, read without the engine for the
+    // candle backtest of simple_backtest
+    pub fn read_klines(self) -> Result<Vec<BinanceKline>, anyhow::Error> {
+        let parse_stats = Arc::new(Mutex::new(vec![]));
+        let parse_aborted = Arc::new(AtomicBool::new(false));
+        // the paths of the mark and index price candles contain klines as well
+        let (kline_files, _): (Vec<_>, Vec<_>) = self.files.into_iter().partition(|(_, path)| {
+            holds::<BinanceKline>(path)
+                && !holds::<MarkPriceKline>(path)
+                && !holds::<IndexPriceKline>(path)
+        });
+        if kline_files.is_empty() {
+            anyhow::bail!("no klines files to read");
+        }
+        let klines = Self::tick_stream::<BinanceKline>(
+            kline_files,
+            self.symbol,
+            self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            CsvColumnMapping::identity(BinanceKline::FIELDS),
+            self.tick_cache,
+        )
+        .filter(|kline| {
+            let close = UNIX_EPOCH + Duration::from_millis(kline.close_time);
+            self.time_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&close))
+        })
+        .collect();
+        if parse_aborted.load(Ordering::Relaxed) {
+            anyhow::bail!(
+                "too many csv parse errors, abort reading klines\n{}",
+                parse_summary(&parse_stats.lock().unwrap())
+            );
+        }
+        Ok(klines)
+    }
 }
 
 impl ModuleBuilder for BinanceRepublisherBuilder {
@@ -158,46 +537,222 @@ impl ModuleBuilder for BinanceRepublisherBuilder {
         "binance_republisher"
     }
 
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::DataFeed
+    }
+
     fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
         let target_topic = comms.get_topic("market_data");
         self.write_target_topic_handle = comms.publish_topic(&target_topic).into();
     }
 
     fn build(self: Box<BinanceRepublisherBuilder>) -> Box<dyn Module> {
+        Box::new(self.build_republisher())
+    }
+}
+
+impl BinanceRepublisherBuilder {
+    // the files of each kind are read one after the other in the order given, daily files
+    // are chained by giving them day by day, the trades and booktickers merged by time
+    fn build_republisher(self) -> BinanceRepublisher {
         let write_target_topic_handle = self.write_target_topic_handle.clone().unwrap();
         let files = self.files;
+        let parse_stats = Arc::new(Mutex::new(vec![]));
+        let parse_aborted = Arc::new(AtomicBool::new(false));
+        // mark and index price candles before klines, their paths contain klines as well
+        let (mark_price_files, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|(_, path)| holds::<MarkPriceKline>(path));
+        let mark_price_rx = Self::tick_stream::<MarkPriceKline>(
+            mark_price_files,
+            self.symbol,
+            self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            CsvColumnMapping::identity(MarkPriceKline::FIELDS),
+            self.tick_cache.clone(),
+        );
+        let (index_price_files, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|(_, path)| holds::<IndexPriceKline>(path));
+        let index_price_rx = Self::tick_stream::<IndexPriceKline>(
+            index_price_files,
+            self.symbol,
+            self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            CsvColumnMapping::identity(IndexPriceKline::FIELDS),
+            self.tick_cache.clone(),
+        );
+        // aggregate trades before trades, their paths may contain trades as well
+        let (agg_trade_files, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|(_, path)| holds::<BinanceAggTrade>(path));
+        let agg_trade_rx = Self::tick_stream::<BinanceAggTrade>(
+            agg_trade_files,
+            self.symbol,
+            self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            CsvColumnMapping::identity(BinanceAggTrade::FIELDS),
+            self.tick_cache.clone(),
+        );
+        let (kline_files, files): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|(_, path)| holds::<BinanceKline>(path));
+        let kline_rx = Self::tick_stream::<BinanceKline>(
+            kline_files,
+            self.symbol,
+            self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            CsvColumnMapping::identity(BinanceKline::FIELDS),
+            self.tick_cache.clone(),
+        );
         let (trade_tick_files, files): (Vec<_>, Vec<_>) = files
             .into_iter()
-            .partition(|(_, path)| BinanceTradeTick::file_name_matched(path));
-        let tick_rx = Self::spawn_csv_reader::<BinanceTradeTick>(
+            .partition(|(_, path)| holds::<BinanceTradeTick>(path));
+        let tick_rx = Self::tick_stream::<BinanceTradeTick>(
             trade_tick_files,
             self.symbol,
             self.show_progress,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            self.trade_tick_columns,
+            self.tick_cache.clone(),
         );
         let (bookticker_files, _): (Vec<_>, Vec<_>) = files
             .into_iter()
-            .partition(|(_, path)| BinanceBookTicker::file_name_matched(path));
-        let bookticker_rx =
-            Self::spawn_csv_reader::<BinanceBookTicker>(bookticker_files, self.symbol, false);
-        Box::new(BinanceRepublisher {
+            .partition(|(_, path)| holds::<BinanceBookTicker>(path));
+        let bookticker_rx = Self::tick_stream::<BinanceBookTicker>(
+            bookticker_files,
+            self.symbol,
+            false,
+            self.max_parse_errors,
+            parse_stats.clone(),
+            parse_aborted.clone(),
+            self.bookticker_columns,
+            self.tick_cache,
+        );
+        BinanceRepublisher {
             write_market_data_handle: write_target_topic_handle,
             peeking_tick_time: std::time::SystemTime::UNIX_EPOCH, // this will be set in start when buffering data
-            trade_tick_peekable_iter: tick_rx.into_iter().peekable(),
-            bookticker_peekable_iter: bookticker_rx.into_iter().peekable(),
+            trade_tick_peekable_iter: tick_rx.peekable(),
+            bookticker_peekable_iter: bookticker_rx.peekable(),
+            agg_trade_peekable_iter: agg_trade_rx.peekable(),
+            kline_peekable_iter: kline_rx.peekable(),
+            mark_price_peekable_iter: MarkPriceStream::new(mark_price_rx, index_price_rx)
+                .peekable(),
             peeking_tick: PeekingTick::None,
-        })
+            parse_stats,
+            parse_aborted,
+            day: None,
+            validator: self.validation.map(MarketDataValidator::new),
+            time_range: self.time_range,
+            reorder: ReorderBuffer::new(self.reorder_window),
+            trade_batch_window: self.trade_batch_window,
+            trade_batch: vec![],
+            trade_batch_times: (UNIX_EPOCH, UNIX_EPOCH),
+        }
     }
-}
 
-impl BinanceRepublisherBuilder {
-    fn spawn_csv_reader<T: ParseFromCsvFile + Send + 'static>(
+    // the ticks of files, decoded here when they are all captures
+    #[allow(clippy::too_many_arguments)]
+    fn tick_stream<T: ParseFromCsvFile + CaptureRecord + Send + 'static>(
+        files: Vec<(File, PathBuf)>,
+        symbol: &'static str,
+        show_progress: bool,
+        max_parse_errors: Option<u64>,
+        parse_stats: Arc<Mutex<Vec<CsvParseStats>>>,
+        parse_aborted: Arc<AtomicBool>,
+        columns: CsvColumnMapping,
+        tick_cache: Option<TickCache>,
+    ) -> TickStream<T> {
+        if files.is_empty() || !files.iter().all(|(_, path)| capture::is_capture(path)) {
+            return TickStream::Reader(
+                Self::spawn_csv_reader(
+                    files,
+                    symbol,
+                    show_progress,
+                    max_parse_errors,
+                    parse_stats,
+                    parse_aborted,
+                    columns,
+                    tick_cache,
+                )
+                .into_iter(),
+            );
+        }
+        let captures = files
+            .iter()
+            .map(|(_, path)| {
+                let ticks = open_capture::<T>(path, symbol);
+                parse_stats.lock().unwrap().push(CsvParseStats {
+                    path: path.clone(),
+                    lines: ticks.records(),
+                    capture: true,
+                    ..Default::default()
+                });
+                ticks
+            })
+            .collect::<Vec<_>>();
+        TickStream::Capture(captures.into_iter().flatten())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_csv_reader<T: ParseFromCsvFile + CaptureRecord + Send + 'static>(
         files: Vec<(File, PathBuf)>,
         symbol: &'static str,
         show_progress: bool,
+        max_parse_errors: Option<u64>,
+        parse_stats: Arc<Mutex<Vec<CsvParseStats>>>,
+        parse_aborted: Arc<AtomicBool>,
+        columns: CsvColumnMapping,
+        tick_cache: Option<TickCache>,
     ) -> Receiver<T> {
         let (tx, rx) = sync_channel(1024);
         thread::spawn(move || {
-            files.iter().for_each(|(file, file_path_buf)| {
+            for (file, file_path_buf) in files.iter() {
+                if capture::is_capture(file_path_buf) {
+                    let ticks = open_capture::<T>(file_path_buf, symbol);
+                    parse_stats.lock().unwrap().push(CsvParseStats {
+                        path: file_path_buf.clone(),
+                        lines: ticks.records(),
+                        capture: true,
+                        ..Default::default()
+                    });
+                    for tick in ticks {
+                        if tx.send(tick).is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+                let cached = tick_cache
+                    .as_ref()
+                    .and_then(|cache| cache.open::<T>(file_path_buf, &columns));
+                if let Some(cached) = cached {
+                    let mut stats = CsvParseStats {
+                        path: file_path_buf.clone(),
+                        cached: true,
+                        ..Default::default()
+                    };
+                    let result = cached.send(symbol, &tx, &mut stats);
+                    parse_stats.lock().unwrap().push(stats);
+                    if result == ReadCsvResult::ChannelClosed {
+                        return;
+                    }
+                    continue;
+                }
+                let mut cache_writer = tick_cache
+                    .as_ref()
+                    .and_then(|cache| cache.writer(file_path_buf, &columns));
+
                 // setup progress bar
                 let progress_bar = ProgressBar::new(file.metadata().unwrap().len());
                 progress_bar.set_style(
@@ -220,48 +775,144 @@ impl BinanceRepublisherBuilder {
                     progress_bar.set_draw_target(ProgressDrawTarget::hidden());
                 }
                 let file = progress_bar.wrap_read(file);
+                let mut stats = CsvParseStats {
+                    path: file_path_buf.clone(),
+                    ..Default::default()
+                };
                 let is_zip = file_path_buf.extension().map_or(false, |ext| ext == "zip");
-                if is_zip {
-                    let mut zip_file = zip::read::ZipArchive::new(file).unwrap_or_else(|e| panic!("failed to open zip file {:?}. error={:?}", file_path_buf, e));
+                let result = if is_zip {
+                    let mut zip_file = zip::read::ZipArchive::new(file).unwrap_or_else(|e| {
+                        panic!("failed to open zip file {:?}. error={:?}", file_path_buf, e)
+                    });
                     if zip_file.len() != 1 {
-                        panic!("zip file should contain only one file, but found {} files, file={:?}", zip_file.len(), file_path_buf);
+                        panic!(
+                            "zip file should contain only one file, but found {} files, file={:?}",
+                            zip_file.len(),
+                            file_path_buf
+                        );
                     }
                     let csv_file = zip_file.by_index(0).expect("failed to read zip file");
-                    let lines = BufReader::new(csv_file).lines();
-                    for l in lines {
-                        match l {
-                            Ok(line) =>  {
-                                let parsed = T::parse_csv_line(&line, symbol);
-                                if parsed.is_ok() && tx.send(parsed.unwrap()).is_err() {
-                                // channel closed stop reading
-                                    return;
-                                }
-                            },
-                            Err(_) => continue,
-                        }
+                    read_csv_lines(
+                        BufReader::new(csv_file),
+                        symbol,
+                        &columns,
+                        &tx,
+                        &mut stats,
+                        max_parse_errors,
+                        cache_writer.as_mut(),
+                    )
+                } else {
+                    read_csv_lines(
+                        BufReader::new(file),
+                        symbol,
+                        &columns,
+                        &tx,
+                        &mut stats,
+                        max_parse_errors,
+                        cache_writer.as_mut(),
+                    )
+                };
+                parse_stats.lock().unwrap().push(stats.clone());
+                // files with errors are parsed again next time, so they are reported again
+                if result == ReadCsvResult::Done && stats.parse_errors == 0 {
+                    if let Some(cache_writer) = cache_writer {
+                        cache_writer.finish();
                     }
                 }
-                else {
-                    let lines = BufReader::new(file).lines();
-                    for l in lines {
-                        match l {
-                            Ok(line) =>  {
-                                let parsed = T::parse_csv_line(&line, symbol);
-                                if parsed.is_ok() && tx.send(parsed.unwrap()).is_err() {
-                                // channel closed stop reading
-                                    return;
-                                }
-                            },
-                            Err(_) => continue,
-                        }
+                match result {
+                    ReadCsvResult::Done => {}
+                    // channel closed stop reading
+                    ReadCsvResult::ChannelClosed => return,
+                    ReadCsvResult::TooManyErrors => {
+                        error!("too many parse errors, stop reading. {}", stats);
+                        parse_aborted.store(true, Ordering::Relaxed);
+                        return;
                     }
                 }
-            })
+            }
         });
         rx
     }
 }
 
+fn open_capture<T: CaptureRecord>(path: &Path, symbol: &'static str) -> CaptureTicks<T> {
+    CaptureTicks::open(path, symbol)
+        .unwrap_or_else(|e| panic!("failed to open capture {:?}. error={:#}", path, e))
+}
+
+// whether the file at path holds ticks of T, told by the header of a capture or else the
+// file name
+fn holds<T: ParseFromCsvFile + CaptureRecord>(path: &Path) -> bool {
+    if capture::is_capture(path) {
+        capture::read_header(path).is_ok_and(|header| header.kind == T::KIND)
+    } else {
+        T::file_name_matched(path)
+    }
+}
+
+// Where parsed ticks go, put returns false once it takes no more
+pub(crate) trait TickSink<T> {
+    fn put(&mut self, tick: T) -> bool;
+}
+
+impl<T> TickSink<T> for &SyncSender<T> {
+    fn put(&mut self, tick: T) -> bool {
+        self.send(tick).is_ok()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ReadCsvResult {
+    Done,
+    ChannelClosed,
+    TooManyErrors,
+}
+
+pub(crate) fn read_csv_lines<T: ParseFromCsvFile + CacheRecord>(
+    reader: impl BufRead,
+    symbol: &'static str,
+    columns: &CsvColumnMapping,
+    mut sink: impl TickSink<T>,
+    stats: &mut CsvParseStats,
+    max_parse_errors: Option<u64>,
+    mut cache_writer: Option<&mut CacheWriter>,
+) -> ReadCsvResult {
+    let mut columns = columns.clone();
+    for l in reader.lines() {
+        stats.lines += 1;
+        let parsed = l.map_err(anyhow::Error::from).and_then(|line| {
+            // newer binance files start with a header row, which takes over the column layout
+            if stats.lines == 1 && is_header(&line) {
+                stats.header_skipped = true;
+                columns = CsvColumnMapping::from_header(&line, T::FIELDS)?;
+                return Ok(None);
+            }
+            T::parse_csv_line(&line, &columns, symbol).map(Some)
+        });
+        match parsed {
+            Ok(Some(parsed)) => {
+                if let Some(cache_writer) = cache_writer.as_deref_mut() {
+                    cache_writer.push(&parsed);
+                }
+                if !sink.put(parsed) {
+                    return ReadCsvResult::ChannelClosed;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                stats.parse_errors += 1;
+                if stats.first_error.is_none() {
+                    stats.first_error = Some(format!("line {}: {:#}", stats.lines, e));
+                }
+                if max_parse_errors.is_some_and(|max| stats.parse_errors > max) {
+                    return ReadCsvResult::TooManyErrors;
+                }
+            }
+        }
+    }
+    ReadCsvResult::Done
+}
+
 fn make_binance_tick(
     id: Option<&str>,
     price: Option<&str>,
@@ -271,25 +922,34 @@ fn make_binance_tick(
     is_buyer_maker: Option<&str>,
     symbol: &'static str,
 ) -> Result<BinanceTradeTick, anyhow::Error> {
+    let price: f64 = price
+        .with_context(|| "no price")?
+        .parse()
+        .with_context(|| "failed to parse price")?;
+    let qty: f64 = qty
+        .with_context(|| "no qty")?
+        .parse()
+        .with_context(|| "failed to parse qty")?;
+    // aggregated trades have no quote quantity
+    let base_qty = match base_qty {
+        Some(base_qty) => base_qty
+            .parse()
+            .with_context(|| "failed to parse base_qty")?,
+        None => price * qty,
+    };
     // parse bool
     Ok(BinanceTradeTick {
-        id: id.unwrap().parse().with_context(|| "failed to parse id")?,
-        price: price
-            .with_context(|| "no price")?
+        id: id
+            .with_context(|| "no id")?
             .parse()
-            .with_context(|| "failed to parse price")?,
-        qty: qty
-            .with_context(|| "no qty")?
-            .parse()
-            .with_context(|| "failed to parse qty")?,
-        base_qty: base_qty
-            .with_context(|| "no base_qty")?
-            .parse()
-            .with_context(|| "failed to parse base_qty")?,
+            .with_context(|| "failed to parse id")?,
+        price,
+        qty,
+        base_qty,
         time: time
             .with_context(|| "no time")?
             .parse()
-            .with_context(|| "failed to parse base_qty")?,
+            .with_context(|| "failed to parse time")?,
         is_buyer_maker: is_buyer_maker
             .with_context(|| "no is_buyer_maker")?
             .to_lowercase()
@@ -298,21 +958,62 @@ fn make_binance_tick(
     })
 }
 
-trait ParseFromCsvFile: Sized {
-    fn parse_csv_line(s: &str, symbol: &'static str) -> Result<Self, anyhow::Error>;
+pub(crate) trait ParseFromCsvFile: Sized {
+    // fields in the order of the layout of files without a header row
+    const FIELDS: &'static [CsvField];
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error>;
     fn file_name_matched(pathbuf: &Path) -> bool;
 }
 
 impl ParseFromCsvFile for BinanceTradeTick {
-    fn parse_csv_line(s: &str, symbol: &'static str) -> Result<Self, anyhow::Error> {
-        let mut fields = s.split(',');
-        let id = fields.next();
-        let price = fields.next();
-        let qty = fields.next();
-        let base_qty = fields.next();
-        let time = fields.next();
-        let is_buyer_maker = fields.next();
-        make_binance_tick(id, price, qty, base_qty, time, is_buyer_maker, symbol)
+    const FIELDS: &'static [CsvField] = &[
+        CsvField {
+            names: &["id", "trade_id", "agg_trade_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["price"],
+            required: true,
+        },
+        CsvField {
+            names: &["qty", "quantity"],
+            required: true,
+        },
+        CsvField {
+            names: &["base_qty", "quote_qty"],
+            required: false,
+        },
+        CsvField {
+            names: &["time", "transact_time"],
+            required: true,
+        },
+        CsvField {
+            names: &["is_buyer_maker"],
+            required: true,
+        },
+    ];
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        let mut buf = [""; MAX_CSV_FIELDS];
+        let len = split_csv_line(s, &mut buf);
+        let fields = &buf[..len];
+        make_binance_tick(
+            columns.get(fields, 0),
+            columns.get(fields, 1),
+            columns.get(fields, 2),
+            columns.get(fields, 3),
+            columns.get(fields, 4),
+            columns.get(fields, 5),
+            symbol,
+        )
     }
 
     fn file_name_matched(pathbuf: &Path) -> bool {
@@ -330,34 +1031,71 @@ impl ParseFromCsvFile for BinanceTradeTick {
 }
 
 impl ParseFromCsvFile for BinanceBookTicker {
-    fn parse_csv_line(s: &str, symbol: &'static str) -> Result<Self, anyhow::Error> {
-        let mut fields = s.split(',');
-        let update_id = fields
-            .next()
+    const FIELDS: &'static [CsvField] = &[
+        CsvField {
+            names: &["update_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["best_bid_price"],
+            required: true,
+        },
+        CsvField {
+            names: &["best_bid_qty"],
+            required: true,
+        },
+        CsvField {
+            names: &["best_ask_price"],
+            required: true,
+        },
+        CsvField {
+            names: &["best_ask_qty"],
+            required: true,
+        },
+        CsvField {
+            names: &["transaction_time"],
+            required: true,
+        },
+        CsvField {
+            names: &["event_time"],
+            required: true,
+        },
+    ];
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        let mut buf = [""; MAX_CSV_FIELDS];
+        let len = split_csv_line(s, &mut buf);
+        let fields = &buf[..len];
+        let update_id = columns
+            .get(fields, 0)
             .with_context(|| "failed to parse update_id")?
             .parse()?;
-        let best_bid_price = fields
-            .next()
+        let best_bid_price = columns
+            .get(fields, 1)
             .with_context(|| "failed to parse best_bid_price")?
             .parse()?;
-        let best_bid_qty = fields
-            .next()
+        let best_bid_qty = columns
+            .get(fields, 2)
             .with_context(|| "failed to parse best_bid_qty")?
             .parse()?;
-        let best_ask_price = fields
-            .next()
+        let best_ask_price = columns
+            .get(fields, 3)
             .with_context(|| "failed to parse best_ask_price")?
             .parse()?;
-        let best_ask_qty = fields
-            .next()
+        let best_ask_qty = columns
+            .get(fields, 4)
             .with_context(|| "failed to parse best_ask_qty")?
             .parse()?;
-        let transaction_time = fields
-            .next()
+        let transaction_time = columns
+            .get(fields, 5)
             .with_context(|| "failed to parse transaction_time")?
             .parse()?;
-        let event_time = fields
-            .next()
+        let event_time = columns
+            .get(fields, 6)
             .with_context(|| "failed to parse event_time")?
             .parse()?;
 
@@ -386,3 +1124,696 @@ impl ParseFromCsvFile for BinanceBookTicker {
         }
     }
 }
+
+// the field at index of the layout, parsed
+fn parse_field<T>(
+    fields: &[&str],
+    columns: &CsvColumnMapping,
+    index: usize,
+    name: &str,
+) -> Result<T, anyhow::Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    columns
+        .get(fields, index)
+        .with_context(|| format!("no {}", name))?
+        .parse()
+        .with_context(|| format!("failed to parse {}", name))
+}
+
+impl ParseFromCsvFile for BinanceAggTrade {
+    const FIELDS: &'static [CsvField] = &[
+        CsvField {
+            names: &["agg_trade_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["price"],
+            required: true,
+        },
+        CsvField {
+            names: &["quantity", "qty"],
+            required: true,
+        },
+        CsvField {
+            names: &["first_trade_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["last_trade_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["transact_time", "time"],
+            required: true,
+        },
+        CsvField {
+            names: &["is_buyer_maker"],
+            required: true,
+        },
+    ];
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        let mut buf = [""; MAX_CSV_FIELDS];
+        let len = split_csv_line(s, &mut buf);
+        let fields = &buf[..len];
+        Ok(BinanceAggTrade {
+            agg_trade_id: parse_field(fields, columns, 0, "agg_trade_id")?,
+            price: parse_field(fields, columns, 1, "price")?,
+            qty: parse_field(fields, columns, 2, "quantity")?,
+            first_trade_id: parse_field(fields, columns, 3, "first_trade_id")?,
+            last_trade_id: parse_field(fields, columns, 4, "last_trade_id")?,
+            time: parse_field(fields, columns, 5, "transact_time")?,
+            is_buyer_maker: columns
+                .get(fields, 6)
+                .with_context(|| "no is_buyer_maker")?
+                .to_lowercase()
+                == "true",
+            symbol,
+        })
+    }
+
+    // agg_trades directory of binance_data_download or an aggTrades file of Binance
+    fn file_name_matched(pathbuf: &Path) -> bool {
+        let path = pathbuf.to_str().unwrap();
+        path.contains("agg_trades") || path.contains("aggTrades")
+    }
+}
+
+impl ParseFromCsvFile for BinanceKline {
+    const FIELDS: &'static [CsvField] = &[
+        CsvField {
+            names: &["open_time"],
+            required: true,
+        },
+        CsvField {
+            names: &["open"],
+            required: true,
+        },
+        CsvField {
+            names: &["high"],
+            required: true,
+        },
+        CsvField {
+            names: &["low"],
+            required: true,
+        },
+        CsvField {
+            names: &["close"],
+            required: true,
+        },
+        CsvField {
+            names: &["volume"],
+            required: true,
+        },
+        CsvField {
+            names: &["close_time"],
+            required: true,
+        },
+        CsvField {
+            names: &["quote_volume"],
+            required: true,
+        },
+        CsvField {
+            names: &["count"],
+            required: true,
+        },
+        CsvField {
+            names: &["taker_buy_volume"],
+            required: true,
+        },
+        CsvField {
+            names: &["taker_buy_quote_volume"],
+            required: true,
+        },
+    ];
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        let mut buf = [""; MAX_CSV_FIELDS];
+        let len = split_csv_line(s, &mut buf);
+        let fields = &buf[..len];
+        Ok(BinanceKline {
+            open_time: parse_field(fields, columns, 0, "open_time")?,
+            open: parse_field(fields, columns, 1, "open")?,
+            high: parse_field(fields, columns, 2, "high")?,
+            low: parse_field(fields, columns, 3, "low")?,
+            close: parse_field(fields, columns, 4, "close")?,
+            volume: parse_field(fields, columns, 5, "volume")?,
+            close_time: parse_field(fields, columns, 6, "close_time")?,
+            quote_volume: parse_field(fields, columns, 7, "quote_volume")?,
+            count: parse_field(fields, columns, 8, "count")?,
+            taker_buy_volume: parse_field(fields, columns, 9, "taker_buy_volume")?,
+            taker_buy_quote_volume: parse_field(fields, columns, 10, "taker_buy_quote_volume")?,
+            symbol,
+        })
+    }
+
+    // klines_<interval> directory of binance_data_download, the kline files of Binance are
+    // named after the interval only
+    fn file_name_matched(pathbuf: &Path) -> bool {
+        pathbuf.to_str().unwrap().contains("klines")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const TRADES_CSV: &str = "id,price,qty,quote_qty,time,is_buyer_maker
+1,100.0,1.0,100.0,1000,true
+2,not_a_price,1.0,100.0,1001,true
+3,100.5,2.0,201.0,1002,false
+4,100.5,2.0
+";
+
+    #[test]
+    fn test_day_roll() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut day = None;
+        assert!(day_roll(&mut day, at(DAY_SECS + 10)).is_none());
+        assert!(day_roll(&mut day, at(2 * DAY_SECS - 1)).is_none());
+        let roll = day_roll(&mut day, at(2 * DAY_SECS + 5)).unwrap();
+        assert_eq!(roll.day_start, at(2 * DAY_SECS));
+        assert!(day_roll(&mut day, at(2 * DAY_SECS + 6)).is_none());
+        // a gap of days rolls once
+        let roll = day_roll(&mut day, at(5 * DAY_SECS)).unwrap();
+        assert_eq!(roll.day_start, at(5 * DAY_SECS));
+    }
+
+    #[test]
+    fn test_read_csv_lines_counts_parse_errors() {
+        let (tx, rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        let result = read_csv_lines::<BinanceTradeTick>(
+            Cursor::new(TRADES_CSV),
+            "BTCUSDT",
+            &CsvColumnMapping::identity(BinanceTradeTick::FIELDS),
+            &tx,
+            &mut stats,
+            None,
+            None,
+        );
+        drop(tx);
+        assert_eq!(result, ReadCsvResult::Done);
+        assert_eq!(
+            rx.iter().map(|tick| tick.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(stats.lines, 5);
+        assert!(stats.header_skipped);
+        assert_eq!(stats.parse_errors, 2);
+        assert!(stats.first_error.unwrap().starts_with("line 3:"));
+    }
+
+    #[test]
+    fn test_read_csv_lines_aborts_over_threshold() {
+        let (tx, _rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        let result = read_csv_lines::<BinanceTradeTick>(
+            Cursor::new(TRADES_CSV),
+            "BTCUSDT",
+            &CsvColumnMapping::identity(BinanceTradeTick::FIELDS),
+            &tx,
+            &mut stats,
+            Some(1),
+            None,
+        );
+        assert_eq!(result, ReadCsvResult::TooManyErrors);
+        assert_eq!(stats.lines, 5);
+        assert_eq!(stats.parse_errors, 2);
+    }
+
+    fn read_trades(
+        csv: &str,
+        columns: &CsvColumnMapping,
+    ) -> (Vec<BinanceTradeTick>, CsvParseStats) {
+        let (tx, rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        read_csv_lines::<BinanceTradeTick>(
+            Cursor::new(csv.to_string()),
+            "BTCUSDT",
+            columns,
+            &tx,
+            &mut stats,
+            None,
+            None,
+        );
+        drop(tx);
+        (rx.iter().collect(), stats)
+    }
+
+    #[test]
+    fn test_read_csv_lines_maps_header_columns() {
+        // aggregated trades, no quote quantity
+        let csv =
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker
+7,100.0,2.0,10,12,1000,true
+";
+        let (ticks, stats) =
+            read_trades(csv, &CsvColumnMapping::identity(BinanceTradeTick::FIELDS));
+        assert!(stats.header_skipped);
+        assert_eq!(stats.parse_errors, 0);
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(ticks[0].id, 7);
+        assert_eq!(ticks[0].qty, 2.0);
+        assert_eq!(ticks[0].base_qty, 200.0);
+        assert_eq!(ticks[0].time, 1000);
+        assert!(ticks[0].is_buyer_maker);
+    }
+
+    #[test]
+    fn test_read_csv_lines_configured_columns() {
+        // headerless file with a configured layout
+        let csv = "7,100.0,2.0,10,12,1000,false\n";
+        let columns = CsvColumnMapping::from_header(
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker",
+            BinanceTradeTick::FIELDS,
+        )
+        .unwrap();
+        let (ticks, stats) = read_trades(csv, &columns);
+        assert!(!stats.header_skipped);
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(ticks[0].time, 1000);
+
+        // bad header is reported
+        let (ticks, stats) = read_trades(
+            "id,price\n1,100.0\n",
+            &CsvColumnMapping::identity(BinanceTradeTick::FIELDS),
+        );
+        assert!(ticks.is_empty());
+        assert_eq!(stats.parse_errors, 2);
+        assert!(stats.first_error.unwrap().starts_with("line 1:"));
+    }
+
+    #[test]
+    fn test_read_csv_lines_agg_trades_and_klines() {
+        let csv =
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker
+7,100.0,2.0,10,12,1000,true
+";
+        let (tx, rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        read_csv_lines::<BinanceAggTrade>(
+            Cursor::new(csv),
+            "BTCUSDT",
+            &CsvColumnMapping::identity(BinanceAggTrade::FIELDS),
+            &tx,
+            &mut stats,
+            None,
+            None,
+        );
+        drop(tx);
+        let trades: Vec<_> = rx.iter().collect();
+        assert_eq!(stats.parse_errors, 0);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(
+            (trades[0].first_trade_id, trades[0].last_trade_id),
+            (10, 12)
+        );
+        assert_eq!(trades[0].to_trade_tick().base_qty, 200.0);
+
+        let csv = "open_time,open,high,low,close,volume,close_time,quote_volume,count,taker_buy_volume,taker_buy_quote_volume,ignore
+0,100.0,101.0,99.0,100.5,10.0,59999,1003.0,42,4.0,401.0,0
+";
+        let (tx, rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        read_csv_lines::<BinanceKline>(
+            Cursor::new(csv),
+            "BTCUSDT",
+            &CsvColumnMapping::identity(BinanceKline::FIELDS),
+            &tx,
+            &mut stats,
+            None,
+            None,
+        );
+        drop(tx);
+        let klines: Vec<_> = rx.iter().collect();
+        assert_eq!(stats.parse_errors, 0);
+        assert_eq!(klines.len(), 1);
+        assert_eq!((klines[0].low, klines[0].high), (99.0, 101.0));
+        assert_eq!(klines[0].close_time, 59999);
+        assert_eq!(klines[0].count, 42);
+
+        assert!(BinanceAggTrade::file_name_matched(Path::new(
+            "future_um/BTCUSDT/agg_trades/2024-01-01.zip"
+        )));
+        assert!(BinanceKline::file_name_matched(Path::new(
+            "future_um/BTCUSDT/klines_1m/2024-01-01.zip"
+        )));
+        assert!(!BinanceKline::file_name_matched(Path::new(
+            "future_um/BTCUSDT/trades/2024-01-01.zip"
+        )));
+    }
+
+    #[test]
+    fn test_multi_day_files_interleave() {
+        let dir = std::env::temp_dir().join(format!("republisher_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // 2024-01-02 00:00 UTC
+        let midnight = 1704153600000u64;
+        let files = [
+            (
+                "BTCUSDT-trades-2024-01-01.csv",
+                [midnight - 2000, midnight - 500],
+            ),
+            ("BTCUSDT-trades-2024-01-02.csv", [midnight, midnight + 1500]),
+            (
+                "BTCUSDT-bookTicker-2024-01-01.csv",
+                [midnight - 1000, midnight - 1],
+            ),
+            (
+                "BTCUSDT-bookTicker-2024-01-02.csv",
+                [midnight + 500, midnight + 2000],
+            ),
+        ];
+        let mut builder = BinanceRepublisherBuilder::new("BTCUSDT");
+        for (name, times) in files {
+            let rows: String = times
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    if name.contains("trades") {
+                        format!("{i},100.0,1.0,100.0,{t},true\n")
+                    } else {
+                        format!("{i},99.9,1.0,100.1,1.0,{t},{t}\n")
+                    }
+                })
+                .collect();
+            let path = dir.join(name);
+            std::fs::write(&path, rows).unwrap();
+            builder = builder.with_file(path.to_str().unwrap()).unwrap();
+        }
+        builder.write_target_topic_handle = Some(WriteTopicHandle { slot: 0 });
+
+        let mut republisher = builder.build_republisher();
+        republisher.start();
+        let mut ticks = vec![];
+        while let Some(at) = republisher.next_iteration_start_at() {
+            let is_trade = matches!(republisher.peeking_tick, PeekingTick::TradeTick(_));
+            ticks.push((
+                at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+                is_trade,
+            ));
+            republisher.next_tick();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // one continuous stream across the day boundary, trades and booktickers merged
+        assert_eq!(
+            ticks,
+            vec![
+                (midnight - 2000, true),
+                (midnight - 1000, false),
+                (midnight - 500, true),
+                (midnight - 1, false),
+                (midnight, true),
+                (midnight + 500, false),
+                (midnight + 1500, true),
+                (midnight + 2000, false),
+            ]
+        );
+        let mut day = None;
+        let rolls = ticks
+            .iter()
+            .filter_map(|(t, _)| day_roll(&mut day, UNIX_EPOCH + Duration::from_millis(*t)))
+            .count();
+        assert_eq!(rolls, 1);
+    }
+
+    #[test]
+    fn test_time_range_skips_other_days() {
+        let dir = std::env::temp_dir().join(format!("republisher_range_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // 2024-01-02 00:00 UTC, a monthly file holds the days around it
+        let midnight = 1704153600000u64;
+        let day = DAY_SECS * 1000;
+        let rows: String = [midnight - 1, midnight, midnight + day - 1, midnight + day]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("{i},100.0,1.0,100.0,{t},true\n"))
+            .collect();
+        let path = dir.join("BTCUSDT-trades-2024-01.csv");
+        std::fs::write(&path, rows).unwrap();
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+        let mut builder = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(path.to_str().unwrap())
+            .unwrap()
+            .with_time_range(at(midnight), at(midnight + day));
+        builder.write_target_topic_handle = Some(WriteTopicHandle { slot: 0 });
+
+        let mut republisher = builder.build_republisher();
+        republisher.start();
+        let mut ticks = vec![];
+        while let Some(at) = republisher.next_iteration_start_at() {
+            ticks.push(at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64);
+            republisher.next_tick();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ticks, vec![midnight, midnight + day - 1]);
+    }
+
+    #[test]
+    fn test_read_klines() {
+        let dir = std::env::temp_dir().join(format!("republisher_candles_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rows: String = [59_999u64, 119_999, 179_999]
+            .iter()
+            .map(|t| {
+                format!(
+                    "{},100.0,101.0,99.0,100.5,10.0,{t},1003.0,42,4.0,401.0,0\n",
+                    t - 59_999
+                )
+            })
+            .collect();
+        let klines = dir.join("BTCUSDT-klines-1m-2024-01-01.csv");
+        std::fs::write(&klines, rows).unwrap();
+        let trades = dir.join("BTCUSDT-trades-2024-01-01.csv");
+        std::fs::write(&trades, "0,100.0,1.0,100.0,0,true\n").unwrap();
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+        let read = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(klines.to_str().unwrap())
+            .unwrap()
+            .with_file(trades.to_str().unwrap())
+            .unwrap()
+            .with_time_range(at(60_000), at(180_000))
+            .read_klines();
+        let no_klines = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(trades.to_str().unwrap())
+            .unwrap()
+            .read_klines();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let close_times: Vec<_> = read.unwrap().iter().map(|k| k.close_time).collect();
+        assert_eq!(close_times, vec![119_999, 179_999]);
+        assert!(no_klines.is_err());
+    }
+
+    // the messages published, at the time set. Like the strict clock audit, nothing is to be
+    // published before it
+    struct RecordingComms {
+        time: SystemTime,
+        published: Vec<Message>,
+        terminate_requested: bool,
+    }
+
+    impl upstair_type::module::ModuleComms for RecordingComms {
+        fn time(&self) -> SystemTime {
+            self.time
+        }
+
+        fn receive_shared(
+            &mut self,
+            _: &upstair_type::module::ReadTopicHandle,
+        ) -> Option<Arc<Message>> {
+            None
+        }
+
+        fn publish(&mut self, _: &WriteTopicHandle, message: Message) {
+            assert!(message.header.commit_at >= self.time, "{message:?}");
+            self.published.push(message);
+        }
+
+        fn request_terminate(&mut self) {
+            self.terminate_requested = true;
+        }
+    }
+
+    #[test]
+    fn test_parse_errors_fail_the_run() {
+        let dir = std::env::temp_dir().join(format!("republisher_abort_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let trades_path = dir.join("BTCUSDT-trades-2024-01-01.csv");
+        std::fs::write(&trades_path, TRADES_CSV).unwrap();
+        let mut builder = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(trades_path.to_str().unwrap())
+            .unwrap()
+            .with_max_parse_errors(1);
+        builder.write_target_topic_handle = Some(WriteTopicHandle { slot: 0 });
+
+        let mut republisher = builder.build_republisher();
+        republisher.start();
+        let mut comms = RecordingComms {
+            time: UNIX_EPOCH,
+            published: vec![],
+            terminate_requested: false,
+        };
+        while let Some(at) = republisher.next_iteration_start_at() {
+            comms.time = at;
+            republisher.one_iteration(&mut comms);
+            if comms.terminate_requested {
+                break;
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the readers stop early, the run ends on the ticks read so far and fails
+        assert!(comms.terminate_requested);
+        let failure = republisher.failure().unwrap();
+        assert!(
+            failure.starts_with("too many csv parse errors"),
+            "{failure}"
+        );
+    }
+
+    #[test]
+    fn test_trade_batches() {
+        let dir = std::env::temp_dir().join(format!("republisher_batch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let trades: String = [1000, 1001, 1003, 1010, 1020, 1022]
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("{i},100.0,1.0,100.0,{t},true\n"))
+            .collect();
+        let trades_path = dir.join("BTCUSDT-trades-2024-01-01.csv");
+        std::fs::write(&trades_path, trades).unwrap();
+        let book_path = dir.join("BTCUSDT-bookTicker-2024-01-01.csv");
+        std::fs::write(&book_path, "0,99.9,1.0,100.1,1.0,1002,1002\n").unwrap();
+        let mut builder = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(trades_path.to_str().unwrap())
+            .unwrap()
+            .with_file(book_path.to_str().unwrap())
+            .unwrap()
+            .with_trade_batch_window(Duration::from_millis(5));
+        builder.write_target_topic_handle = Some(WriteTopicHandle { slot: 0 });
+
+        let mut republisher = builder.build_republisher();
+        republisher.start();
+        let mut comms = RecordingComms {
+            time: UNIX_EPOCH,
+            published: vec![],
+            terminate_requested: false,
+        };
+        while let Some(at) = republisher.next_iteration_start_at() {
+            comms.time = at;
+            republisher.one_iteration(&mut comms);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let published: Vec<_> = comms
+            .published
+            .iter()
+            .map(|message| {
+                let at = message
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                let times = match &message.payload {
+                    Payload::TradeTickBatch(ticks) => ticks.iter().map(|t| t.time).collect(),
+                    Payload::BinanceBookTicker(ticker) => vec![ticker.event_time],
+                    _ => unreachable!(),
+                };
+                (at, times)
+            })
+            .collect();
+        // a bookticker cuts a batch, a batch holds the trades within the window of its first
+        assert_eq!(
+            published,
+            vec![
+                (1001, vec![1000, 1001]),
+                (1002, vec![1002]),
+                (1003, vec![1003]),
+                (1010, vec![1010]),
+                (1022, vec![1020, 1022]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trade_batches_cut_by_quality_flags() {
+        let dir = std::env::temp_dir().join(format!("republisher_flags_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // trade 2 is missing, the gap is flagged at trade 3 and cleared at trade 4, both within
+        // the window of the batch
+        let trades: String = [(0, 1000), (1, 1001), (3, 1003), (4, 1004)]
+            .iter()
+            .map(|(id, t)| format!("{id},100.0,1.0,100.0,{t},true\n"))
+            .collect();
+        let trades_path = dir.join("BTCUSDT-trades-2024-01-01.csv");
+        std::fs::write(&trades_path, trades).unwrap();
+        let mut builder = BinanceRepublisherBuilder::new("BTCUSDT")
+            .with_file(trades_path.to_str().unwrap())
+            .unwrap()
+            .with_trade_batch_window(Duration::from_millis(5))
+            .with_validation(ValidationConfig::default());
+        builder.write_target_topic_handle = Some(WriteTopicHandle { slot: 0 });
+
+        let mut republisher = builder.build_republisher();
+        republisher.start();
+        let mut comms = RecordingComms {
+            time: UNIX_EPOCH,
+            published: vec![],
+            terminate_requested: false,
+        };
+        while let Some(at) = republisher.next_iteration_start_at() {
+            comms.time = at;
+            republisher.one_iteration(&mut comms);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let published: Vec<_> = comms
+            .published
+            .iter()
+            .map(|message| {
+                let at = message
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                let published = match &message.payload {
+                    Payload::TradeTickBatch(ticks) => {
+                        format!("{:?}", ticks.iter().map(|t| t.id).collect::<Vec<_>>())
+                    }
+                    Payload::DataQuality(quality) => format!("gap {}", quality.flags.gap_detected),
+                    _ => unreachable!(),
+                };
+                (at, published)
+            })
+            .collect();
+        // the trades before a change of the flags go out ahead of it, at the time it happens
+        assert_eq!(
+            published,
+            [
+                (1003, "[0, 1]"),
+                (1003, "gap true"),
+                (1004, "[3]"),
+                (1004, "gap false"),
+                (1004, "[4]"),
+            ]
+            .map(|(at, published)| (at, published.to_string()))
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use memmap2::Mmap;
+use upstair_type::{
+    aggregate::{BinanceAggTrade, BinanceKline},
+    data::market::{BinanceBookTicker, BinanceTradeTick},
+};
+
+use crate::{
+    binance_republisher::{read_csv_lines, CsvParseStats, ParseFromCsvFile, TickSink},
+    csv_columns::CsvColumnMapping,
+    mark_price::{IndexPriceKline, MarkPriceKline},
+    tick_cache::CacheRecord,
+};
+
+// Itch-style captures of the ticks of a csv file of Binance, replayed without parsing: a
+// header and the ticks as fixed size little endian records, the ones of the tick cache, read
+// from a memory map. Written by sim convert next to the file, e.g. trades/2024-01-01.cap for
+// trades/2024-01-01.zip.
+//
+// header: magic UPCAP001, the kind of the ticks (u8), 7 reserved bytes, the record count (u64)
+
+pub const EXTENSION: &str = "cap";
+
+const MAGIC: &[u8; 8] = b"UPCAP001";
+const HEADER_LEN: usize = 24;
+
+// A tick of a kind of capture
+pub(crate) trait CaptureRecord: CacheRecord {
+    const KIND: u8;
+}
+
+impl CaptureRecord for BinanceTradeTick {
+    const KIND: u8 = 0;
+}
+
+impl CaptureRecord for BinanceBookTicker {
+    const KIND: u8 = 1;
+}
+
+impl CaptureRecord for BinanceAggTrade {
+    const KIND: u8 = 2;
+}
+
+impl CaptureRecord for BinanceKline {
+    const KIND: u8 = 3;
+}
+
+fn record_size(kind: u8) -> Option<usize> {
+    match kind {
+        BinanceTradeTick::KIND => Some(BinanceTradeTick::SIZE),
+        BinanceBookTicker::KIND => Some(BinanceBookTicker::SIZE),
+        BinanceAggTrade::KIND => Some(BinanceAggTrade::SIZE),
+        BinanceKline::KIND => Some(BinanceKline::SIZE),
+        MarkPriceKline::KIND => Some(MarkPriceKline::SIZE),
+        IndexPriceKline::KIND => Some(IndexPriceKline::SIZE),
+        _ => None,
+    }
+}
+
+pub fn is_capture(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == EXTENSION)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureHeader {
+    pub kind: u8,
+    pub records: u64,
+}
+
+impl CaptureHeader {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8] = self.kind;
+        bytes[16..].copy_from_slice(&self.records.to_le_bytes());
+        bytes
+    }
+
+    // the header of a capture of len bytes, checked against it
+    fn decode(bytes: &[u8], len: u64) -> Result<CaptureHeader, anyhow::Error> {
+        if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+            bail!("not a capture");
+        }
+        let header = CaptureHeader {
+            kind: bytes[8],
+            records: u64::from_le_bytes(bytes[16..HEADER_LEN].try_into().unwrap()),
+        };
+        let Some(size) = record_size(header.kind) else {
+            bail!("unknown kind of ticks {}", header.kind);
+        };
+        if HEADER_LEN as u64 + header.records * size as u64 != len {
+            bail!(
+                "{} bytes do not hold the {} records of the header, the capture is cut short",
+                len,
+                header.records
+            );
+        }
+        Ok(header)
+    }
+}
+
+// the header of the capture at path, checked against the file
+pub fn read_header(path: &Path) -> Result<CaptureHeader, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let mut bytes = [0u8; HEADER_LEN];
+    BufReader::new(file)
+        .read_exact(&mut bytes)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| CaptureHeader::decode(&bytes, len))
+        .with_context(|| format!("invalid capture {}", path.display()))
+}
+
+// The ticks of a capture, decoded from its memory map
+pub(crate) struct CaptureTicks<T> {
+    mmap: Mmap,
+    offset: usize,
+    records: u64,
+    symbol: &'static str,
+    _record: PhantomData<T>,
+}
+
+impl<T: CaptureRecord> CaptureTicks<T> {
+    pub(crate) fn open(path: &Path, symbol: &'static str) -> Result<Self, anyhow::Error> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        // SAFETY: captures are written once under a temporary name and not changed after
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("failed to map {}", path.display()))?;
+        let header = CaptureHeader::decode(&mmap, mmap.len() as u64)
+            .with_context(|| format!("invalid capture {}", path.display()))?;
+        if header.kind != T::KIND {
+            bail!(
+                "{} holds ticks of kind {}, expected {}",
+                path.display(),
+                header.kind,
+                T::KIND
+            );
+        }
+        Ok(CaptureTicks {
+            mmap,
+            offset: HEADER_LEN,
+            records: header.records,
+            symbol,
+            _record: PhantomData,
+        })
+    }
+
+    pub(crate) fn records(&self) -> u64 {
+        self.records
+    }
+}
+
+impl<T: CaptureRecord> Iterator for CaptureTicks<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let record = self.mmap.get(self.offset..self.offset + T::SIZE)?;
+        self.offset += T::SIZE;
+        Some(T::decode(record, self.symbol))
+    }
+}
+
+// Writes a capture to a temporary file renamed once finished, a conversion cut short leaves
+// no capture behind
+pub(crate) struct CaptureWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: BufWriter<File>,
+    header: CaptureHeader,
+    buf: Vec<u8>,
+    // the first failed write, the ticks after it are not taken
+    error: Option<std::io::Error>,
+}
+
+impl CaptureWriter {
+    pub(crate) fn create<T: CaptureRecord>(path: &Path) -> Result<Self, anyhow::Error> {
+        let tmp_path = path.with_extension(format!("{}.tmp.{}", EXTENSION, std::process::id()));
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let header = CaptureHeader {
+            kind: T::KIND,
+            records: 0,
+        };
+        let mut writer = BufWriter::new(file);
+        // the record count is written once known
+        writer.write_all(&header.encode())?;
+        Ok(CaptureWriter {
+            path: path.to_path_buf(),
+            tmp_path,
+            writer,
+            header,
+            buf: Vec::with_capacity(128),
+            error: None,
+        })
+    }
+
+    pub(crate) fn finish(mut self) -> Result<u64, anyhow::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e).with_context(|| format!("failed to write {}", self.tmp_path.display()));
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&self.header.encode())?;
+        self.writer.flush()?;
+        std::fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        Ok(self.header.records)
+    }
+}
+
+impl<T: CaptureRecord> TickSink<T> for &mut CaptureWriter {
+    fn put(&mut self, tick: T) -> bool {
+        self.buf.clear();
+        tick.encode(&mut self.buf);
+        if let Err(e) = self.writer.write_all(&self.buf) {
+            self.error = Some(e);
+            return false;
+        }
+        self.header.records += 1;
+        true
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        // no-op once renamed
+        let _ = std::fs::remove_file(&self.tmp_path);
+    }
+}
+
+fn convert_ticks<T: ParseFromCsvFile + CaptureRecord>(
+    source: &Path,
+    dest: &Path,
+) -> Result<CsvParseStats, anyhow::Error> {
+    let file =
+        File::open(source).with_context(|| format!("failed to open {}", source.display()))?;
+    let mut writer = CaptureWriter::create::<T>(dest)?;
+    let mut stats = CsvParseStats {
+        path: source.to_path_buf(),
+        ..Default::default()
+    };
+    // the layout of files without a header row is the one of Binance
+    let columns = CsvColumnMapping::identity(T::FIELDS);
+    // the symbol is given when the capture is replayed
+    if source.extension().is_some_and(|ext| ext == "zip") {
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("failed to open zip file {}", source.display()))?;
+        if archive.len() != 1 {
+            bail!(
+                "{} holds {} files, expected 1",
+                source.display(),
+                archive.len()
+            );
+        }
+        let csv_file = archive.by_index(0)?;
+        read_csv_lines::<T>(
+            std::io::BufReader::new(csv_file),
+            "",
+            &columns,
+            &mut writer,
+            &mut stats,
+            None,
+            None,
+        );
+    } else {
+        read_csv_lines::<T>(
+            std::io::BufReader::new(file),
+            "",
+            &columns,
+            &mut writer,
+            &mut stats,
+            None,
+            None,
+        );
+    }
+    // a capture replays every line of its file
+    if stats.parse_errors > 0 {
+        bail!(
+            "{} has {} lines which do not parse, first at {}",
+            source.display(),
+            stats.parse_errors,
+            stats.first_error.as_deref().unwrap_or_default()
+        );
+    }
+    writer.finish()?;
+    Ok(stats)
+}
+
+// Converts the csv or zip file of Binance at source to a capture at dest, the kind of its
+// ticks told by the file name as when it is republished
+pub fn convert(source: &Path, dest: &Path) -> Result<CsvParseStats, anyhow::Error> {
+    // aggregate trades before trades and mark and index price candles before klines, their
+    // paths contain the others as well
+    if BinanceAggTrade::file_name_matched(source) {
+        convert_ticks::<BinanceAggTrade>(source, dest)
+    } else if MarkPriceKline::file_name_matched(source) {
+        convert_ticks::<MarkPriceKline>(source, dest)
+    } else if IndexPriceKline::file_name_matched(source) {
+        convert_ticks::<IndexPriceKline>(source, dest)
+    } else if BinanceKline::file_name_matched(source) {
+        convert_ticks::<BinanceKline>(source, dest)
+    } else if BinanceTradeTick::file_name_matched(source) {
+        convert_ticks::<BinanceTradeTick>(source, dest)
+    } else if BinanceBookTicker::file_name_matched(source) {
+        convert_ticks::<BinanceBookTicker>(source, dest)
+    } else {
+        bail!("unknown kind of ticks in {}", source.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_and_replay() {
+        let dir = std::env::temp_dir().join(format!("capture_test_{}", std::process::id()));
+        let source = dir.join("BTCUSDT-bookTicker-2024-01-01.csv");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            &source,
+            "update_id,best_bid_price,best_bid_qty,best_ask_price,best_ask_qty,transaction_time,event_time
+1,100.0,1.5,100.1,2.5,1000,1001
+2,100.1,0.5,100.2,1.0,2000,2002
+",
+        )
+        .unwrap();
+        let dest = source.with_extension(EXTENSION);
+        let stats = convert(&source, &dest).unwrap();
+        assert!(stats.header_skipped);
+        assert_eq!(
+            read_header(&dest).unwrap(),
+            CaptureHeader {
+                kind: BinanceBookTicker::KIND,
+                records: 2
+            }
+        );
+
+        let ticks: Vec<BinanceBookTicker> = CaptureTicks::open(&dest, "BTCUSDT").unwrap().collect();
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[1].update_id, 2);
+        assert_eq!(ticks[1].best_ask_qty, 1.0);
+        assert_eq!(ticks[1].event_time, 2002);
+        assert_eq!(ticks[1].symbol, "BTCUSDT");
+        // the kind is checked
+        assert!(CaptureTicks::<BinanceTradeTick>::open(&dest, "BTCUSDT").is_err());
+
+        // cut short
+        let bytes = std::fs::read(&dest).unwrap();
+        std::fs::write(&dest, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_header(&dest).is_err());
+        assert!(CaptureTicks::<BinanceBookTicker>::open(&dest, "BTCUSDT").is_err());
+
+        // lines which do not parse leave no capture
+        std::fs::write(&source, "1,100.0,1.5,100.1,2.5,1000,1001\nbroken\n").unwrap();
+        std::fs::remove_file(&dest).unwrap();
+        assert!(convert(&source, &dest).is_err());
+        assert!(!dest.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
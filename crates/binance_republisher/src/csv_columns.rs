@@ -0,0 +1,136 @@
+use anyhow::bail;
+
+// Lines with more columns than this have the rest ignored
+pub(crate) const MAX_CSV_FIELDS: usize = 16;
+
+// A field of a parsed record.
+// The first name is the canonical one, the rest are header names used across binance archives.
+// Names are compared ignoring case and underscores, so quoteQty matches quote_qty.
+#[derive(Debug)]
+pub struct CsvField {
+    pub names: &'static [&'static str],
+    pub required: bool,
+}
+
+// Column index of each field, in the order of the record's fields
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CsvColumnMapping {
+    columns: Vec<Option<usize>>,
+}
+
+fn normalize(name: &str) -> String {
+    name.trim()
+        .chars()
+        .filter(|c| *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl CsvColumnMapping {
+    // fields are laid out in order, the layout of the original binance archives
+    pub fn identity(fields: &[CsvField]) -> Self {
+        CsvColumnMapping {
+            columns: (0..fields.len()).map(Some).collect(),
+        }
+    }
+
+    // header is the comma separated column names of a file
+    pub fn from_header(header: &str, fields: &[CsvField]) -> Result<Self, anyhow::Error> {
+        let header = header.split(',').map(normalize).collect::<Vec<_>>();
+        let mut columns = Vec::with_capacity(fields.len());
+        for field in fields {
+            let column = header
+                .iter()
+                .position(|column| field.names.iter().any(|name| normalize(name) == *column));
+            if column.is_none() && field.required {
+                bail!("no column for {} in header {:?}", field.names[0], header);
+            }
+            if column.is_some_and(|c| c >= MAX_CSV_FIELDS) {
+                bail!(
+                    "column of {} is out of {MAX_CSV_FIELDS} columns",
+                    field.names[0]
+                );
+            }
+            columns.push(column);
+        }
+        Ok(CsvColumnMapping { columns })
+    }
+
+    // value of the field_index-th field of a line split by split_csv_line
+    pub(crate) fn get<'a>(&self, line: &[&'a str], field_index: usize) -> Option<&'a str> {
+        self.columns[field_index].and_then(|column| line.get(column).copied())
+    }
+}
+
+// files starting with a non numeric column carry a header row
+pub(crate) fn is_header(line: &str) -> bool {
+    line.split(',')
+        .next()
+        .is_some_and(|first| first.trim().parse::<f64>().is_err())
+}
+
+pub(crate) fn split_csv_line<'a>(line: &'a str, buf: &mut [&'a str; MAX_CSV_FIELDS]) -> usize {
+    let mut len = 0;
+    for (slot, field) in buf.iter_mut().zip(line.split(',')) {
+        *slot = field;
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[CsvField] = &[
+        CsvField {
+            names: &["id", "agg_trade_id"],
+            required: true,
+        },
+        CsvField {
+            names: &["qty", "quantity"],
+            required: true,
+        },
+        CsvField {
+            names: &["quote_qty"],
+            required: false,
+        },
+    ];
+
+    #[test]
+    fn test_from_header() {
+        let mapping = CsvColumnMapping::from_header("id,price,qty,quoteQty", FIELDS).unwrap();
+        assert_eq!(mapping.columns, vec![Some(0), Some(2), Some(3)]);
+
+        let mapping = CsvColumnMapping::from_header(
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id",
+            FIELDS,
+        )
+        .unwrap();
+        assert_eq!(mapping.columns, vec![Some(0), Some(2), None]);
+
+        assert!(CsvColumnMapping::from_header("id,price", FIELDS).is_err());
+    }
+
+    #[test]
+    fn test_get_field() {
+        let mapping = CsvColumnMapping::from_header("price,qty,id", FIELDS).unwrap();
+        let mut buf = [""; MAX_CSV_FIELDS];
+        let len = split_csv_line("100.5,2,42", &mut buf);
+        let line = &buf[..len];
+        assert_eq!(mapping.get(line, 0), Some("42"));
+        assert_eq!(mapping.get(line, 1), Some("2"));
+        assert_eq!(mapping.get(line, 2), None);
+        assert_eq!(
+            CsvColumnMapping::identity(FIELDS).get(line, 0),
+            Some("100.5")
+        );
+        assert_eq!(CsvColumnMapping::identity(FIELDS).get(&line[..1], 1), None);
+    }
+
+    #[test]
+    fn test_is_header() {
+        assert!(is_header("id,price,qty"));
+        assert!(!is_header("1,100.5,2"));
+    }
+}
@@ -1 +1,7 @@
 pub mod binance_republisher;
+pub mod capture;
+pub mod csv_columns;
+pub mod mark_price;
+pub mod tick_cache;
+pub mod time_order;
+pub mod validation;
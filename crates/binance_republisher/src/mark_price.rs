@@ -0,0 +1,189 @@
+use std::{iter::Peekable, path::Path};
+
+use upstair_type::aggregate::{BinanceKline, MarkPrice};
+
+use crate::{
+    binance_republisher::ParseFromCsvFile,
+    capture::CaptureRecord,
+    csv_columns::{CsvColumnMapping, CsvField},
+    tick_cache::CacheRecord,
+};
+
+// A candle of the markPriceKlines files of Binance, laid out as the klines with no volume.
+// Told apart from the klines by its directory only, so its files are picked out first.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MarkPriceKline(pub(crate) BinanceKline);
+
+// A candle of the indexPriceKlines files of Binance, the price of the spot markets the mark
+// price is anchored to
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexPriceKline(pub(crate) BinanceKline);
+
+impl ParseFromCsvFile for MarkPriceKline {
+    const FIELDS: &'static [CsvField] = BinanceKline::FIELDS;
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        BinanceKline::parse_csv_line(s, columns, symbol).map(MarkPriceKline)
+    }
+
+    // mark_price_klines_<interval> directory of binance_data_download or a markPriceKlines
+    // file of Binance
+    fn file_name_matched(pathbuf: &Path) -> bool {
+        let path = pathbuf.to_str().unwrap();
+        path.contains("mark_price_klines") || path.contains("markPriceKlines")
+    }
+}
+
+impl ParseFromCsvFile for IndexPriceKline {
+    const FIELDS: &'static [CsvField] = BinanceKline::FIELDS;
+
+    fn parse_csv_line(
+        s: &str,
+        columns: &CsvColumnMapping,
+        symbol: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        BinanceKline::parse_csv_line(s, columns, symbol).map(IndexPriceKline)
+    }
+
+    // index_price_klines_<interval> directory of binance_data_download or an
+    // indexPriceKlines file of Binance
+    fn file_name_matched(pathbuf: &Path) -> bool {
+        let path = pathbuf.to_str().unwrap();
+        path.contains("index_price_klines") || path.contains("indexPriceKlines")
+    }
+}
+
+impl CacheRecord for MarkPriceKline {
+    const SIZE: usize = BinanceKline::SIZE;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf)
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        MarkPriceKline(BinanceKline::decode(bytes, symbol))
+    }
+}
+
+impl CacheRecord for IndexPriceKline {
+    const SIZE: usize = BinanceKline::SIZE;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.0.encode(buf)
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        IndexPriceKline(BinanceKline::decode(bytes, symbol))
+    }
+}
+
+impl CaptureRecord for MarkPriceKline {
+    const KIND: u8 = 4;
+}
+
+impl CaptureRecord for IndexPriceKline {
+    const KIND: u8 = 5;
+}
+
+// The mark prices at the close of the mark price candles, each with the index price of the
+// last index candle up to it. Index candles without mark price candles give nothing.
+pub(crate) struct MarkPriceStream<M, I: Iterator> {
+    marks: M,
+    indexes: Peekable<I>,
+    index_price: Option<f64>,
+}
+
+impl<M, I> MarkPriceStream<M, I>
+where
+    M: Iterator<Item = MarkPriceKline>,
+    I: Iterator<Item = IndexPriceKline>,
+{
+    pub(crate) fn new(marks: M, indexes: I) -> Self {
+        MarkPriceStream {
+            marks,
+            indexes: indexes.peekable(),
+            index_price: None,
+        }
+    }
+}
+
+impl<M, I> Iterator for MarkPriceStream<M, I>
+where
+    M: Iterator<Item = MarkPriceKline>,
+    I: Iterator<Item = IndexPriceKline>,
+{
+    type Item = MarkPrice;
+
+    fn next(&mut self) -> Option<MarkPrice> {
+        let MarkPriceKline(mark) = self.marks.next()?;
+        while let Some(IndexPriceKline(index)) = self
+            .indexes
+            .next_if(|IndexPriceKline(index)| index.close_time <= mark.close_time)
+        {
+            self.index_price = Some(index.close);
+        }
+        Some(MarkPrice {
+            mark_price: mark.close,
+            index_price: self.index_price,
+            time: mark.close_time,
+            symbol: mark.symbol,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kline(close_time: u64, close: f64) -> BinanceKline {
+        BinanceKline {
+            open_time: close_time + 1 - 60_000,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 0.0,
+            close_time,
+            quote_volume: 0.0,
+            count: 0,
+            taker_buy_volume: 0.0,
+            taker_buy_quote_volume: 0.0,
+            symbol: "BTCUSDT",
+        }
+    }
+
+    #[test]
+    fn test_mark_price_stream() {
+        let marks = [(59_999, 100.0), (119_999, 101.0), (179_999, 102.0)]
+            .map(|(time, close)| MarkPriceKline(kline(time, close)));
+        // the index candle of the second minute is missing
+        let indexes = [(59_999, 99.5), (179_999, 101.0), (239_999, 103.0)]
+            .map(|(time, close)| IndexPriceKline(kline(time, close)));
+        let prices: Vec<_> = MarkPriceStream::new(marks.into_iter(), indexes.into_iter())
+            .map(|mark| (mark.time, mark.mark_price, mark.basis()))
+            .collect();
+        assert_eq!(
+            prices,
+            vec![
+                (59_999, 100.0, Some(0.5)),
+                (119_999, 101.0, Some(1.5)),
+                (179_999, 102.0, Some(1.0)),
+            ]
+        );
+
+        let marks = [MarkPriceKline(kline(59_999, 100.0))];
+        let mut stream = MarkPriceStream::new(marks.into_iter(), std::iter::empty());
+        assert_eq!(stream.next().unwrap().index_price, None);
+        assert!(stream.next().is_none());
+        assert!(MarkPriceKline::file_name_matched(Path::new(
+            "future_um/BTCUSDT/mark_price_klines_1m/2024-01-01.zip"
+        )));
+        assert!(!IndexPriceKline::file_name_matched(Path::new(
+            "future_um/BTCUSDT/mark_price_klines_1m/2024-01-01.zip"
+        )));
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::SyncSender,
+    },
+    time::UNIX_EPOCH,
+};
+
+use tracing::warn;
+use upstair_type::{
+    aggregate::{BinanceAggTrade, BinanceKline},
+    data::market::{BinanceBookTicker, BinanceTradeTick},
+};
+
+use crate::{
+    binance_republisher::{CsvParseStats, ReadCsvResult},
+    csv_columns::CsvColumnMapping,
+};
+
+const MAGIC: &[u8; 8] = b"UPTICK01";
+
+// unique suffix of the temporary files written by this process
+static TMP_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+// A parsed tick stored as a fixed size little endian record
+pub(crate) trait CacheRecord: Sized {
+    const SIZE: usize;
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self;
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn f64_at(bytes: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl CacheRecord for BinanceTradeTick {
+    const SIZE: usize = 41;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.qty.to_le_bytes());
+        buf.extend_from_slice(&self.base_qty.to_le_bytes());
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        buf.push(self.is_buyer_maker as u8);
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        BinanceTradeTick {
+            id: u64_at(bytes, 0),
+            price: f64_at(bytes, 8),
+            qty: f64_at(bytes, 16),
+            base_qty: f64_at(bytes, 24),
+            time: u64_at(bytes, 32),
+            is_buyer_maker: bytes[40] != 0,
+            symbol,
+        }
+    }
+}
+
+impl CacheRecord for BinanceBookTicker {
+    const SIZE: usize = 56;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.update_id.to_le_bytes());
+        buf.extend_from_slice(&self.best_bid_price.to_le_bytes());
+        buf.extend_from_slice(&self.best_bid_qty.to_le_bytes());
+        buf.extend_from_slice(&self.best_ask_price.to_le_bytes());
+        buf.extend_from_slice(&self.best_ask_qty.to_le_bytes());
+        buf.extend_from_slice(&self.transaction_time.to_le_bytes());
+        buf.extend_from_slice(&self.event_time.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        BinanceBookTicker {
+            update_id: u64_at(bytes, 0),
+            best_bid_price: f64_at(bytes, 8),
+            best_bid_qty: f64_at(bytes, 16),
+            best_ask_price: f64_at(bytes, 24),
+            best_ask_qty: f64_at(bytes, 32),
+            transaction_time: u64_at(bytes, 40),
+            event_time: u64_at(bytes, 48),
+            symbol,
+        }
+    }
+}
+
+impl CacheRecord for BinanceAggTrade {
+    const SIZE: usize = 49;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.agg_trade_id.to_le_bytes());
+        buf.extend_from_slice(&self.price.to_le_bytes());
+        buf.extend_from_slice(&self.qty.to_le_bytes());
+        buf.extend_from_slice(&self.first_trade_id.to_le_bytes());
+        buf.extend_from_slice(&self.last_trade_id.to_le_bytes());
+        buf.extend_from_slice(&self.time.to_le_bytes());
+        buf.push(self.is_buyer_maker as u8);
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        BinanceAggTrade {
+            agg_trade_id: u64_at(bytes, 0),
+            price: f64_at(bytes, 8),
+            qty: f64_at(bytes, 16),
+            first_trade_id: u64_at(bytes, 24),
+            last_trade_id: u64_at(bytes, 32),
+            time: u64_at(bytes, 40),
+            is_buyer_maker: bytes[48] != 0,
+            symbol,
+        }
+    }
+}
+
+impl CacheRecord for BinanceKline {
+    const SIZE: usize = 88;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.open_time.to_le_bytes());
+        buf.extend_from_slice(&self.open.to_le_bytes());
+        buf.extend_from_slice(&self.high.to_le_bytes());
+        buf.extend_from_slice(&self.low.to_le_bytes());
+        buf.extend_from_slice(&self.close.to_le_bytes());
+        buf.extend_from_slice(&self.volume.to_le_bytes());
+        buf.extend_from_slice(&self.close_time.to_le_bytes());
+        buf.extend_from_slice(&self.quote_volume.to_le_bytes());
+        buf.extend_from_slice(&self.count.to_le_bytes());
+        buf.extend_from_slice(&self.taker_buy_volume.to_le_bytes());
+        buf.extend_from_slice(&self.taker_buy_quote_volume.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8], symbol: &'static str) -> Self {
+        BinanceKline {
+            open_time: u64_at(bytes, 0),
+            open: f64_at(bytes, 8),
+            high: f64_at(bytes, 16),
+            low: f64_at(bytes, 24),
+            close: f64_at(bytes, 32),
+            volume: f64_at(bytes, 40),
+            close_time: u64_at(bytes, 48),
+            quote_volume: f64_at(bytes, 56),
+            count: u64_at(bytes, 64),
+            taker_buy_volume: f64_at(bytes, 72),
+            taker_buy_quote_volume: f64_at(bytes, 80),
+            symbol,
+        }
+    }
+}
+
+// Parsed ticks of csv files kept on disk, so runs over the same data (other workers of a
+// batch, a resumed batch) skip the csv parsing. A cache file is written to a temporary
+// name and renamed once complete, so concurrent processes can share a directory.
+#[derive(Debug, Clone)]
+pub struct TickCache {
+    dir: PathBuf,
+}
+
+impl TickCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        TickCache { dir: dir.into() }
+    }
+
+    // keyed by the source file path, size and mtime and the column layout,
+    // a changed file is parsed again
+    fn cache_path(&self, source: &Path, columns: &CsvColumnMapping) -> Option<PathBuf> {
+        let metadata = std::fs::metadata(source).ok()?;
+        let mut hasher = DefaultHasher::new();
+        std::fs::canonicalize(source).ok()?.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        columns.hash(&mut hasher);
+        // e.g. trades-2024-01-01 for data/future_um/BTCUSDT/trades/2024-01-01.zip
+        let parent = source
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|p| p.to_str())
+            .unwrap_or_default();
+        let stem = source.file_stem()?.to_str()?;
+        Some(self.dir.join(format!(
+            "{}-{}-{:016x}.ticks",
+            parent,
+            stem,
+            hasher.finish()
+        )))
+    }
+
+    pub(crate) fn open<T: CacheRecord>(
+        &self,
+        source: &Path,
+        columns: &CsvColumnMapping,
+    ) -> Option<CachedTicks> {
+        let path = self.cache_path(source, columns)?;
+        let file = File::open(&path).ok()?;
+        let len = file.metadata().ok()?.len() as usize;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).ok()?;
+        let records = (len - MAGIC.len()) / T::SIZE;
+        if &magic != MAGIC || records * T::SIZE != len - MAGIC.len() {
+            warn!("ignore invalid tick cache {}", path.display());
+            return None;
+        }
+        Some(CachedTicks { reader, records })
+    }
+
+    pub(crate) fn writer(&self, source: &Path, columns: &CsvColumnMapping) -> Option<CacheWriter> {
+        let path = self.cache_path(source, columns)?;
+        let tmp_path = path.with_extension(format!(
+            "tmp.{}.{}",
+            std::process::id(),
+            TMP_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = std::fs::create_dir_all(&self.dir).and_then(|_| File::create(&tmp_path));
+        match file {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                writer.write_all(MAGIC).ok()?;
+                Some(CacheWriter {
+                    path,
+                    tmp_path,
+                    writer: Some(writer),
+                    buf: Vec::with_capacity(64),
+                })
+            }
+            Err(e) => {
+                warn!("failed to create tick cache {}: {}", tmp_path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+pub(crate) struct CachedTicks {
+    reader: BufReader<File>,
+    records: usize,
+}
+
+impl CachedTicks {
+    pub(crate) fn send<T: CacheRecord>(
+        mut self,
+        symbol: &'static str,
+        tx: &SyncSender<T>,
+        stats: &mut CsvParseStats,
+    ) -> ReadCsvResult {
+        let mut buf = vec![0u8; T::SIZE];
+        for _ in 0..self.records {
+            if let Err(e) = self.reader.read_exact(&mut buf) {
+                stats.parse_errors += 1;
+                stats.first_error = Some(format!("tick cache: {}", e));
+                return ReadCsvResult::Done;
+            }
+            stats.lines += 1;
+            if tx.send(T::decode(&buf, symbol)).is_err() {
+                return ReadCsvResult::ChannelClosed;
+            }
+        }
+        ReadCsvResult::Done
+    }
+}
+
+// Removes its temporary file unless finished
+pub(crate) struct CacheWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    // None once a write failed
+    writer: Option<BufWriter<File>>,
+    buf: Vec<u8>,
+}
+
+impl CacheWriter {
+    pub(crate) fn push<T: CacheRecord>(&mut self, record: &T) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        self.buf.clear();
+        record.encode(&mut self.buf);
+        if let Err(e) = writer.write_all(&self.buf) {
+            warn!(
+                "failed to write tick cache {}: {}",
+                self.tmp_path.display(),
+                e
+            );
+            self.writer = None;
+        }
+    }
+
+    pub(crate) fn finish(mut self) {
+        let Some(writer) = self.writer.take() else {
+            return;
+        };
+        let result = writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|_| std::fs::rename(&self.tmp_path, &self.path));
+        if let Err(e) = result {
+            warn!("failed to write tick cache {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // no-op once renamed
+        let _ = std::fs::remove_file(&self.tmp_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+    use crate::csv_columns::CsvField;
+
+    #[test]
+    fn test_tick_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("tick_cache_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("BTCUSDT-trades-2024-01-01.csv");
+        std::fs::write(&source, "1,100.0,1.0,100.0,1000,true\n").unwrap();
+        let columns = CsvColumnMapping::identity(&[]);
+        let cache = TickCache::new(dir.join("cache"));
+        assert!(cache.open::<BinanceTradeTick>(&source, &columns).is_none());
+
+        let ticks: Vec<BinanceTradeTick> = (0..3)
+            .map(|i| BinanceTradeTick {
+                id: i,
+                price: 100.0 + i as f64 * 0.5,
+                qty: 1.25,
+                base_qty: 125.0,
+                time: 1000 + i,
+                is_buyer_maker: i % 2 == 0,
+                symbol: "BTCUSDT",
+            })
+            .collect();
+        let mut writer = cache.writer(&source, &columns).unwrap();
+        ticks.iter().for_each(|tick| writer.push(tick));
+        // unfinished writes are not visible
+        assert!(cache.open::<BinanceTradeTick>(&source, &columns).is_none());
+        writer.finish();
+
+        let (tx, rx) = sync_channel(16);
+        let mut stats = CsvParseStats::default();
+        let result = cache
+            .open::<BinanceTradeTick>(&source, &columns)
+            .unwrap()
+            .send("BTCUSDT", &tx, &mut stats);
+        drop(tx);
+        assert_eq!(result, ReadCsvResult::Done);
+        assert_eq!(stats.lines, 3);
+        let cached: Vec<BinanceTradeTick> = rx.iter().collect();
+        assert_eq!(
+            cached
+                .iter()
+                .map(|t| (t.id, t.price, t.time, t.is_buyer_maker))
+                .collect::<Vec<_>>(),
+            ticks
+                .iter()
+                .map(|t| (t.id, t.price, t.time, t.is_buyer_maker))
+                .collect::<Vec<_>>()
+        );
+
+        // a different column layout is another entry
+        let columns = CsvColumnMapping::identity(&[CsvField {
+            names: &["id"],
+            required: true,
+        }]);
+        assert!(cache.open::<BinanceTradeTick>(&source, &columns).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
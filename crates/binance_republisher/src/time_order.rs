@@ -0,0 +1,154 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeOrderStats {
+    // ticks earlier than a tick read before them
+    pub out_of_order: u64,
+    // out of order ticks behind a published tick, published at its time
+    pub late: u64,
+    // the most one of them was behind
+    pub max_lag_ms: u64,
+}
+
+// a tick with the order it was read in, so ticks of equal time keep it
+struct Pending<T> {
+    time: u64,
+    seq: u64,
+    tick: T,
+}
+
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl<T> Eq for Pending<T> {}
+
+impl<T> PartialOrd for Pending<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Pending<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+// Holds the ticks read back for up to window and releases them in time order, so a file
+// running behind the others is sorted in rather than sent back in time. Ticks later than the
+// window are released at the time of the last released tick, the engine never sees time go
+// backwards. Without a window ticks are released as read and only counted.
+pub(crate) struct ReorderBuffer<T> {
+    window_ms: u64,
+    pending: BinaryHeap<Reverse<Pending<T>>>,
+    seq: u64,
+    // the latest time read, and the time the last tick was released at
+    newest: Option<u64>,
+    released: Option<u64>,
+    pub stats: TimeOrderStats,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(window: Duration) -> Self {
+        ReorderBuffer {
+            window_ms: window.as_millis() as u64,
+            pending: BinaryHeap::new(),
+            seq: 0,
+            newest: None,
+            released: None,
+            stats: TimeOrderStats::default(),
+        }
+    }
+
+    // whether ticks have to be read before the earliest one can be released
+    pub fn wants_more(&self) -> bool {
+        match (self.pending.peek(), self.newest) {
+            (Some(Reverse(earliest)), Some(newest)) => newest < earliest.time + self.window_ms,
+            _ => true,
+        }
+    }
+
+    pub fn push(&mut self, time: u64, tick: T) {
+        match self.newest {
+            Some(newest) if time < newest => self.stats.out_of_order += 1,
+            _ => self.newest = Some(time),
+        }
+        self.seq += 1;
+        self.pending.push(Reverse(Pending {
+            time,
+            seq: self.seq,
+            tick,
+        }));
+    }
+
+    // the earliest tick and the time to publish it at
+    pub fn pop(&mut self) -> Option<(u64, T)> {
+        let Reverse(pending) = self.pending.pop()?;
+        let time = match self.released {
+            Some(released) if pending.time < released => {
+                self.stats.late += 1;
+                self.stats.max_lag_ms = self.stats.max_lag_ms.max(released - pending.time);
+                released
+            }
+            _ => pending.time,
+        };
+        self.released = Some(time);
+        Some((time, pending.tick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(buffer: &mut ReorderBuffer<u32>, ticks: &[(u64, u32)]) -> Vec<(u64, u32)> {
+        let mut ticks = ticks.iter();
+        let mut released = vec![];
+        loop {
+            while buffer.wants_more() {
+                let Some(&(time, tick)) = ticks.next() else {
+                    break;
+                };
+                buffer.push(time, tick);
+            }
+            let Some(tick) = buffer.pop() else {
+                return released;
+            };
+            released.push(tick);
+        }
+    }
+
+    #[test]
+    fn test_without_window_counts_and_holds_time() {
+        let mut buffer = ReorderBuffer::new(Duration::ZERO);
+        let released = drain(&mut buffer, &[(10, 0), (10, 1), (5, 2), (20, 3)]);
+        // ticks of equal time keep their order, the late one does not go back in time
+        assert_eq!(released, vec![(10, 0), (10, 1), (10, 2), (20, 3)]);
+        assert_eq!(
+            buffer.stats,
+            TimeOrderStats {
+                out_of_order: 1,
+                late: 1,
+                max_lag_ms: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_window_sorts_in() {
+        let mut buffer = ReorderBuffer::new(Duration::from_millis(10));
+        let released = drain(&mut buffer, &[(10, 0), (18, 1), (12, 2), (40, 3), (15, 4)]);
+        // 12 is sorted in before 18, 15 is read after 18 was released
+        assert_eq!(released, vec![(10, 0), (12, 2), (18, 1), (18, 4), (40, 3)]);
+        assert_eq!(buffer.stats.out_of_order, 2);
+        assert_eq!(buffer.stats.late, 1);
+        assert_eq!(buffer.stats.max_lag_ms, 3);
+    }
+}
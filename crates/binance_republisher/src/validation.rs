@@ -0,0 +1,173 @@
+use std::time::{Duration, SystemTime};
+
+use upstair_type::{control::DataQualityFlags, data::market::BinanceTradeTick};
+
+// a price level this many outliers in a row report is taken as a real move
+const MAX_CONSECUTIVE_OUTLIERS: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ValidationConfig {
+    // the book is stale once no book ticker arrived for this long
+    pub stale_book_after: Duration,
+    // trades further than this fraction from the last trade price are outliers
+    pub max_trade_jump: f64,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            stale_book_after: Duration::from_secs(5),
+            max_trade_jump: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidationStats {
+    pub stale_books: u64,
+    pub gaps: u64,
+    pub outliers: u64,
+}
+
+// Checks the ticks of a symbol in the order they are republished and keeps the data quality
+// flags strategies see
+#[derive(Debug)]
+pub struct MarketDataValidator {
+    config: ValidationConfig,
+    last_book_at: Option<SystemTime>,
+    last_trade_id: Option<u64>,
+    last_price: Option<f64>,
+    consecutive_outliers: u32,
+    flags: DataQualityFlags,
+    // the flags last returned by changed_flags
+    published: DataQualityFlags,
+    pub stats: ValidationStats,
+}
+
+impl MarketDataValidator {
+    pub fn new(config: ValidationConfig) -> Self {
+        MarketDataValidator {
+            config,
+            last_book_at: None,
+            last_trade_id: None,
+            last_price: None,
+            consecutive_outliers: 0,
+            flags: DataQualityFlags::default(),
+            published: DataQualityFlags::default(),
+            stats: ValidationStats::default(),
+        }
+    }
+
+    pub fn check_book(&mut self, time: SystemTime) {
+        self.last_book_at = Some(time);
+        self.update_stale_book(time);
+    }
+
+    // returns false when the trade is an outlier and should be dropped
+    pub fn check_trade(&mut self, tick: &BinanceTradeTick, time: SystemTime) -> bool {
+        self.update_stale_book(time);
+
+        if let Some(last_id) = self.last_trade_id {
+            let gap = tick.id > last_id + 1;
+            if gap && !self.flags.gap_detected {
+                self.stats.gaps += 1;
+            }
+            self.flags.gap_detected = gap;
+        }
+        self.last_trade_id = Some(self.last_trade_id.map_or(tick.id, |id| id.max(tick.id)));
+
+        let outlier = self.last_price.is_some_and(|last_price| {
+            (tick.price / last_price - 1.0).abs() > self.config.max_trade_jump
+        });
+        if outlier && self.consecutive_outliers + 1 < MAX_CONSECUTIVE_OUTLIERS {
+            self.consecutive_outliers += 1;
+            self.stats.outliers += 1;
+            self.flags.outlier_filtered = true;
+            return false;
+        }
+        self.consecutive_outliers = 0;
+        self.last_price = Some(tick.price);
+        self.flags.outlier_filtered = false;
+        true
+    }
+
+    // the flags when they changed since the last call
+    pub fn changed_flags(&mut self) -> Option<DataQualityFlags> {
+        if self.flags == self.published {
+            return None;
+        }
+        self.published = self.flags;
+        Some(self.flags)
+    }
+
+    fn update_stale_book(&mut self, time: SystemTime) {
+        // nothing to compare against before the first book ticker
+        let stale = self.last_book_at.is_some_and(|at| {
+            time.duration_since(at).unwrap_or_default() > self.config.stale_book_after
+        });
+        if stale && !self.flags.stale_book {
+            self.stats.stale_books += 1;
+        }
+        self.flags.stale_book = stale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn trade(id: u64, price: f64) -> BinanceTradeTick {
+        BinanceTradeTick {
+            id,
+            price,
+            qty: 1.0,
+            base_qty: price,
+            time: 0,
+            is_buyer_maker: false,
+            symbol: "BTCUSDT",
+        }
+    }
+
+    #[test]
+    fn test_quality_flags() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut validator = MarketDataValidator::new(ValidationConfig::default());
+        validator.check_book(at(0));
+        assert!(validator.check_trade(&trade(1, 100.0), at(1)));
+        assert_eq!(validator.changed_flags(), None);
+
+        // trade 2 is lost
+        assert!(validator.check_trade(&trade(3, 100.0), at(1)));
+        let flags = validator.changed_flags().unwrap();
+        assert!(flags.gap_detected && !flags.stale_book);
+        assert!(validator.check_trade(&trade(4, 100.0), at(2)));
+        assert!(!validator.changed_flags().unwrap().is_degraded());
+
+        // no book ticker for more than 5s
+        assert!(validator.check_trade(&trade(5, 100.0), at(6)));
+        assert!(validator.changed_flags().unwrap().stale_book);
+        validator.check_book(at(7));
+        assert!(!validator.changed_flags().unwrap().stale_book);
+
+        assert!(!validator.check_trade(&trade(6, 120.0), at(7)));
+        assert!(validator.changed_flags().unwrap().outlier_filtered);
+        assert!(validator.check_trade(&trade(7, 101.0), at(7)));
+        assert!(!validator.changed_flags().unwrap().is_degraded());
+
+        // the price moved for real
+        assert!(!validator.check_trade(&trade(8, 120.0), at(7)));
+        assert!(!validator.check_trade(&trade(9, 120.0), at(7)));
+        assert!(validator.check_trade(&trade(10, 120.0), at(7)));
+        assert!(validator.check_trade(&trade(11, 120.0), at(7)));
+        assert_eq!(
+            validator.stats,
+            ValidationStats {
+                stale_books: 1,
+                gaps: 1,
+                outliers: 3,
+            }
+        );
+    }
+}
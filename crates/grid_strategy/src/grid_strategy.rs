@@ -0,0 +1,378 @@
+use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
+
+use tracing::{debug, warn};
+use upstair_type::{
+    module::{
+        and_filter, owner_filter, symbol_filter, Module, ModuleBuilder, ModuleComms,
+        ReadTopicHandle, WriteTopicHandle,
+    },
+    order::{
+        CancelOrderRequest, OrderRequest, OrderResult, OrderStatus, TimeInForce, TradeSide,
+        TradeType,
+    },
+    Message, MessageHeader, Payload,
+};
+
+// The levels of a static grid, a step apart outward from the reference price
+#[derive(Debug, Clone)]
+pub struct GridConfig {
+    // orders on each side of the reference
+    pub levels: usize,
+    pub step: f64,
+    pub quantity: f64,
+    pub price_tick: f64,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        GridConfig {
+            levels: 5,
+            step: 10.0,
+            quantity: 0.001,
+            price_tick: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridOrder {
+    pub side: TradeSide,
+    pub price: f64,
+}
+
+impl GridConfig {
+    fn round(&self, price: f64) -> f64 {
+        (price / self.price_tick).round() * self.price_tick
+    }
+
+    // a buy below and a sell above the reference on every level
+    pub fn initial_orders(&self, reference: f64) -> Vec<GridOrder> {
+        let reference = self.round(reference);
+        (1..=self.levels)
+            .flat_map(|level| {
+                let offset = level as f64 * self.step;
+                [
+                    GridOrder {
+                        side: TradeSide::Buy,
+                        price: self.round(reference - offset),
+                    },
+                    GridOrder {
+                        side: TradeSide::Sell,
+                        price: self.round(reference + offset),
+                    },
+                ]
+            })
+            .filter(|order| order.price > 0.0)
+            .collect()
+    }
+
+    // a filled buy is sold a step above, a filled sell bought back a step below
+    pub fn counter_order(&self, filled: &GridOrder) -> GridOrder {
+        match filled.side {
+            TradeSide::Buy => GridOrder {
+                side: TradeSide::Sell,
+                price: self.round(filled.price + self.step),
+            },
+            TradeSide::Sell => GridOrder {
+                side: TradeSide::Buy,
+                price: self.round(filled.price - self.step),
+            },
+        }
+    }
+}
+
+struct GridStrategy {
+    market_data_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+    order_topic: WriteTopicHandle,
+
+    symbol: &'static str,
+    owner: Option<&'static str>,
+    config: GridConfig,
+    // the first trade price unless given
+    reference: Option<f64>,
+    grid_placed: bool,
+    open_orders: BTreeMap<Arc<str>, GridOrder>,
+    // counter orders of the fills, placed on the next iteration
+    to_place: Vec<GridOrder>,
+    order_seq: u64,
+    // (buys, sells) filled
+    filled: (u64, u64),
+    // set on shutdown, no orders are placed from then on
+    shut_down: bool,
+}
+
+impl GridStrategy {
+    fn on_order_result(&mut self, result: &OrderResult) {
+        match result.status {
+            OrderStatus::New | OrderStatus::PartiallyFilled => {}
+            OrderStatus::Filled => {
+                let Some(order) = self.open_orders.remove(&result.client_order_id) else {
+                    return;
+                };
+                match order.side {
+                    TradeSide::Buy => self.filled.0 += 1,
+                    TradeSide::Sell => self.filled.1 += 1,
+                }
+                self.to_place.push(self.config.counter_order(&order));
+            }
+            _ => {
+                // the level stays empty
+                if self.open_orders.remove(&result.client_order_id).is_some() && !self.shut_down {
+                    warn!(
+                        "grid order {} ended {:?}",
+                        result.client_order_id, result.status
+                    );
+                }
+            }
+        }
+    }
+
+    fn place(&mut self, order: GridOrder, now: SystemTime, comms: &mut dyn ModuleComms) {
+        self.order_seq += 1;
+        // the ids of the strategies sharing a market differ by their owners
+        let client_order_id: Arc<str> = Arc::from(match self.owner {
+            Some(owner) => format!("G{}@{}", self.order_seq, owner),
+            None => format!("G{}", self.order_seq),
+        });
+        debug!(
+            "grid {:?} at {} order_id={}",
+            order.side, order.price, client_order_id
+        );
+        comms.publish(
+            &self.order_topic,
+            Message {
+                header: MessageHeader { commit_at: now },
+                payload: Payload::OrderRequest(OrderRequest {
+                    symbol: self.symbol,
+                    side: order.side.clone(),
+                    price: order.price,
+                    quantity: self.config.quantity,
+                    trade_type: TradeType::Limit,
+                    time_in_force: TimeInForce::GoodTilCancelled,
+                    client_order_id: client_order_id.clone(),
+                    cancel_order_id: None,
+                    owner: self.owner,
+                }),
+            },
+        );
+        self.open_orders.insert(client_order_id, order);
+    }
+}
+
+impl Module for GridStrategy {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            if self.reference.is_none() {
+                self.reference = msg.payload.trade_price();
+            }
+        }
+        while let Some(msg) = comms.receive(&self.order_result_topic) {
+            for result in msg.payload.order_results() {
+                self.on_order_result(result);
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        if self.shut_down {
+            return;
+        }
+        let now = comms.time();
+        if let (false, Some(reference)) = (self.grid_placed, self.reference) {
+            self.grid_placed = true;
+            for order in self.config.initial_orders(reference) {
+                self.place(order, now, comms);
+            }
+        }
+        for order in std::mem::take(&mut self.to_place) {
+            self.place(order, now, comms);
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+
+    fn on_shutdown(&mut self, comms: &mut dyn ModuleComms) {
+        self.shut_down = true;
+        for client_order_id in self.open_orders.keys() {
+            comms.publish(
+                &self.order_topic,
+                Message {
+                    header: MessageHeader {
+                        commit_at: comms.time(),
+                    },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol: self.symbol,
+                        client_order_id: client_order_id.clone(),
+                        owner: self.owner,
+                    }),
+                },
+            );
+        }
+    }
+
+    fn terminate(&mut self) {
+        println!("--- Grid ---");
+        println!(
+            "Filled: {} buys, {} sells, {} round trips of {} each",
+            self.filled.0,
+            self.filled.1,
+            self.filled.0.min(self.filled.1),
+            self.config.step * self.config.quantity
+        );
+        println!("Open: {}", self.open_orders.len());
+    }
+}
+
+// Places a static grid of limit orders around a reference price and answers every fill with
+// the order a step away on the other side, a reference strategy which is easy to check the
+// matching of by hand
+pub struct GridStrategyBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+    order_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    owner: Option<&'static str>,
+    config: GridConfig,
+    reference: Option<f64>,
+}
+
+impl GridStrategyBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        GridStrategyBuilder {
+            market_data_topic: None,
+            order_result_topic: None,
+            order_topic: None,
+            symbol,
+            owner: None,
+            config: GridConfig::default(),
+            reference: None,
+        }
+    }
+
+    pub fn with_config(mut self, config: GridConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // center the grid on price instead of the first trade
+    pub fn with_reference_price(mut self, price: f64) -> Self {
+        self.reference = Some(price);
+        self
+    }
+
+    // trade the account the market agent keeps for owner instead of the shared one
+    pub fn with_owner(mut self, owner: &'static str) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+}
+
+impl ModuleBuilder for GridStrategyBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let order_result_topic = comms.get_topic("order_result");
+        let order_topic = comms.get_topic("order");
+
+        // only the ticks of our symbol
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(self.symbol))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(
+                &order_result_topic,
+                and_filter(symbol_filter(self.symbol), owner_filter(self.owner)),
+            )
+            .into();
+        self.order_topic = comms.publish_topic(&order_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(GridStrategy {
+            market_data_topic: self.market_data_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            order_topic: self.order_topic.unwrap(),
+            symbol: self.symbol,
+            owner: self.owner,
+            config: self.config,
+            reference: self.reference,
+            grid_placed: false,
+            open_orders: BTreeMap::new(),
+            to_place: vec![],
+            order_seq: 0,
+            filled: (0, 0),
+            shut_down: false,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "grid_strategy"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GridConfig {
+        GridConfig {
+            levels: 2,
+            step: 10.0,
+            quantity: 0.01,
+            price_tick: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_initial_orders() {
+        let prices: Vec<(TradeSide, f64)> = config()
+            .initial_orders(100.2)
+            .into_iter()
+            .map(|order| (order.side, order.price))
+            .collect();
+        assert_eq!(
+            prices,
+            vec![
+                (TradeSide::Buy, 90.0),
+                (TradeSide::Sell, 110.0),
+                (TradeSide::Buy, 80.0),
+                (TradeSide::Sell, 120.0),
+            ]
+        );
+        // no level at or below zero
+        assert_eq!(config().initial_orders(15.0).len(), 3);
+    }
+
+    #[test]
+    fn test_counter_order() {
+        let config = config();
+        let sell = config.counter_order(&GridOrder {
+            side: TradeSide::Buy,
+            price: 90.0,
+        });
+        assert_eq!(
+            sell,
+            GridOrder {
+                side: TradeSide::Sell,
+                price: 100.0,
+            }
+        );
+        assert_eq!(
+            config.counter_order(&sell),
+            GridOrder {
+                side: TradeSide::Buy,
+                price: 90.0,
+            }
+        );
+    }
+}
@@ -0,0 +1 @@
+pub mod grid_strategy;
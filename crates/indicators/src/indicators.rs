@@ -0,0 +1,294 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::debug;
+use upstair_type::{
+    module::{
+        symbol_filter, Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle,
+        WriteTopicHandle,
+    },
+    signal::{SignalKind, SignalUpdate},
+    Message, MessageHeader, Payload,
+};
+
+// Stdev of the trade price changes sampled once per sample interval, over the last samples.
+// The same estimate the AmmStrategy quotes from.
+#[derive(Debug)]
+pub struct SampledVolatility {
+    sample_ms: u64,
+    samples: usize,
+    diffs: VecDeque<f64>,
+    // (sample slot, price) of the last sample
+    last_sample: Option<(u64, f64)>,
+}
+
+impl SampledVolatility {
+    pub fn new(samples: usize, sample_interval: Duration) -> Self {
+        assert!(samples > 0 && !sample_interval.is_zero());
+        SampledVolatility {
+            sample_ms: sample_interval.as_millis() as u64,
+            samples,
+            diffs: VecDeque::with_capacity(samples + 1),
+            last_sample: None,
+        }
+    }
+
+    pub fn on_trade(&mut self, time_ms: u64, price: f64) {
+        let slot = time_ms / self.sample_ms;
+        match self.last_sample {
+            Some((last_slot, last_price)) if slot > last_slot => {
+                self.diffs.push_back(price - last_price);
+                if self.diffs.len() > self.samples {
+                    self.diffs.pop_front();
+                }
+                self.last_sample = Some((slot, price));
+            }
+            Some(_) => {}
+            None => self.last_sample = Some((slot, price)),
+        }
+    }
+
+    // None until a price change is sampled
+    pub fn value(&self) -> Option<f64> {
+        if self.diffs.is_empty() {
+            return None;
+        }
+        let n = self.diffs.len() as f64;
+        let mean = self.diffs.iter().sum::<f64>() / n;
+        let variance = self.diffs.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / n;
+        Some(variance.sqrt())
+    }
+}
+
+// Relative trade price change over a time window
+#[derive(Debug)]
+pub struct Momentum {
+    window_ms: u64,
+    // (time, price) of the trades in the window and the last one before it
+    prices: VecDeque<(u64, f64)>,
+}
+
+impl Momentum {
+    pub fn new(window: Duration) -> Self {
+        Momentum {
+            window_ms: window.as_millis() as u64,
+            prices: VecDeque::new(),
+        }
+    }
+
+    pub fn on_trade(&mut self, time_ms: u64, price: f64) {
+        self.prices.push_back((time_ms, price));
+        let window_start = time_ms.saturating_sub(self.window_ms);
+        while self.prices.len() > 1 && self.prices[1].0 <= window_start {
+            self.prices.pop_front();
+        }
+    }
+
+    // None until the trades span the window
+    pub fn value(&self) -> Option<f64> {
+        let (first_time, first_price) = *self.prices.front()?;
+        let (last_time, last_price) = *self.prices.back()?;
+        if last_time - first_time < self.window_ms || first_price <= 0.0 {
+            return None;
+        }
+        Some(last_price / first_price - 1.0)
+    }
+}
+
+// (bid qty - ask qty) / (bid qty + ask qty) of the best levels, None for an empty book
+pub fn book_imbalance(bid_qty: f64, ask_qty: f64) -> Option<f64> {
+    let total = bid_qty + ask_qty;
+    if total <= 0.0 {
+        return None;
+    }
+    Some((bid_qty - ask_qty) / total)
+}
+
+struct IndicatorPublisher {
+    market_data_topic: ReadTopicHandle,
+    signals_topic: WriteTopicHandle,
+
+    symbol: &'static str,
+    interval: Duration,
+    volatility: SampledVolatility,
+    momentum: Momentum,
+    imbalance: Option<f64>,
+    next_publish_at: SystemTime,
+}
+
+impl Module for IndicatorPublisher {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            if let Payload::BinanceBookTicker(ticker) = &msg.payload {
+                self.imbalance = book_imbalance(ticker.best_bid_qty, ticker.best_ask_qty);
+            } else if let Some(price) = msg.payload.trade_price() {
+                let time_ms = msg
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                self.volatility.on_trade(time_ms, price);
+                self.momentum.on_trade(time_ms, price);
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let now = comms.time();
+        if now < self.next_publish_at {
+            return;
+        }
+        let signals = [
+            (SignalKind::Volatility, self.volatility.value()),
+            (SignalKind::Imbalance, self.imbalance),
+            (SignalKind::Momentum, self.momentum.value()),
+        ];
+        for (kind, value) in signals {
+            let Some(value) = value else {
+                continue;
+            };
+            debug!("{} {} {}", self.symbol, kind.as_str(), value);
+            comms.publish(
+                &self.signals_topic,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::SignalUpdate(SignalUpdate {
+                        symbol: self.symbol,
+                        kind,
+                        value,
+                    }),
+                },
+            );
+        }
+        self.next_publish_at = now + self.interval;
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Computes the volatility, book imbalance and momentum of a symbol from its ticks and
+// publishes them on the signals topic at most once per interval, for any strategy to consume
+pub struct IndicatorPublisherBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    signals_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    interval: Duration,
+    // (samples, sample interval)
+    volatility_window: (usize, Duration),
+    momentum_window: Duration,
+}
+
+impl IndicatorPublisherBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        IndicatorPublisherBuilder {
+            market_data_topic: None,
+            signals_topic: None,
+            symbol,
+            interval: Duration::from_secs(1),
+            volatility_window: (60, Duration::from_secs(1)),
+            momentum_window: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_volatility_window(mut self, samples: usize, sample_interval: Duration) -> Self {
+        self.volatility_window = (samples, sample_interval);
+        self
+    }
+
+    pub fn with_momentum_window(mut self, window: Duration) -> Self {
+        self.momentum_window = window;
+        self
+    }
+}
+
+impl ModuleBuilder for IndicatorPublisherBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let signals_topic = comms.get_topic("signals");
+
+        // only the ticks of our symbol
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(self.symbol))
+            .into();
+        self.signals_topic = comms.publish_topic(&signals_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        let (samples, sample_interval) = self.volatility_window;
+        Box::new(IndicatorPublisher {
+            market_data_topic: self.market_data_topic.unwrap(),
+            signals_topic: self.signals_topic.unwrap(),
+            symbol: self.symbol,
+            interval: self.interval,
+            volatility: SampledVolatility::new(samples, sample_interval),
+            momentum: Momentum::new(self.momentum_window),
+            imbalance: None,
+            next_publish_at: UNIX_EPOCH,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "indicators"
+    }
+
+    // derived from the market data, before the strategies reading it
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::DataFeed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_volatility() {
+        let mut volatility = SampledVolatility::new(2, Duration::from_secs(1));
+        volatility.on_trade(0, 100.0);
+        // same slot, not sampled
+        volatility.on_trade(500, 200.0);
+        assert_eq!(volatility.value(), None);
+        volatility.on_trade(1000, 101.0);
+        assert_eq!(volatility.value(), Some(0.0));
+        volatility.on_trade(2000, 104.0);
+        // diffs 1 and 3
+        assert_eq!(volatility.value(), Some(1.0));
+        volatility.on_trade(3000, 107.0);
+        // 1 left the window, diffs 3 and 3
+        assert_eq!(volatility.value(), Some(0.0));
+    }
+
+    #[test]
+    fn test_momentum_and_imbalance() {
+        let mut momentum = Momentum::new(Duration::from_secs(10));
+        momentum.on_trade(0, 100.0);
+        momentum.on_trade(5_000, 150.0);
+        assert_eq!(momentum.value(), None);
+        momentum.on_trade(10_000, 110.0);
+        assert!((momentum.value().unwrap() - 0.1).abs() < 1e-12);
+        // the price at the window start is the latest one at or before it
+        momentum.on_trade(16_000, 120.0);
+        assert!((momentum.value().unwrap() - (120.0 / 150.0 - 1.0)).abs() < 1e-12);
+
+        assert_eq!(book_imbalance(3.0, 1.0), Some(0.5));
+        assert_eq!(book_imbalance(0.0, 0.0), None);
+    }
+}
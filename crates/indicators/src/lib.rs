@@ -0,0 +1,2 @@
+pub mod indicators;
+pub mod order_flow;
@@ -0,0 +1,414 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::debug;
+use upstair_type::{
+    data::market::BinanceBookTicker,
+    module::{
+        symbol_filter, Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle,
+        WriteTopicHandle,
+    },
+    signal::{SignalKind, SignalUpdate},
+    Message, MessageHeader, Payload,
+};
+
+// buckets of bid qty / (bid qty + ask qty) the microprice adjustment is learned per
+const IMBALANCE_BUCKETS: usize = 10;
+
+// Order-flow imbalance of Cont, Kukanov and Stoikov summed over a time window: the quantity
+// added at the best bid and taken from the best ask, minus the opposite, in base asset
+#[derive(Debug)]
+pub struct OrderFlowImbalance {
+    window_ms: u64,
+    last_book: Option<BinanceBookTicker>,
+    // (time, flow) of the book updates in the window
+    flows: VecDeque<(u64, f64)>,
+    sum: f64,
+}
+
+impl OrderFlowImbalance {
+    pub fn new(window: Duration) -> Self {
+        OrderFlowImbalance {
+            window_ms: window.as_millis() as u64,
+            last_book: None,
+            flows: VecDeque::new(),
+            sum: 0.0,
+        }
+    }
+
+    pub fn on_book(&mut self, time_ms: u64, book: &BinanceBookTicker) {
+        if let Some(last) = &self.last_book {
+            let mut flow = 0.0;
+            if book.best_bid_price >= last.best_bid_price {
+                flow += book.best_bid_qty;
+            }
+            if book.best_bid_price <= last.best_bid_price {
+                flow -= last.best_bid_qty;
+            }
+            if book.best_ask_price <= last.best_ask_price {
+                flow -= book.best_ask_qty;
+            }
+            if book.best_ask_price >= last.best_ask_price {
+                flow += last.best_ask_qty;
+            }
+            self.flows.push_back((time_ms, flow));
+            self.sum += flow;
+        }
+        self.last_book = Some(*book);
+        let window_start = time_ms.saturating_sub(self.window_ms);
+        while let Some(&(time, flow)) = self.flows.front() {
+            if time > window_start {
+                break;
+            }
+            self.flows.pop_front();
+            self.sum -= flow;
+        }
+    }
+
+    // None before the second book update
+    pub fn value(&self) -> Option<f64> {
+        self.last_book.as_ref()?;
+        // the running sum drifts from the float errors of the removals
+        if self.flows.is_empty() {
+            return Some(0.0);
+        }
+        Some(self.sum)
+    }
+}
+
+// Lag one autocorrelation of the signs of the last trades, +1 for a buying taker and -1 for a
+// selling one. Positive while the takers keep trading the same side.
+#[derive(Debug)]
+pub struct TradeSignAutocorrelation {
+    trades: usize,
+    signs: VecDeque<f64>,
+}
+
+impl TradeSignAutocorrelation {
+    pub fn new(trades: usize) -> Self {
+        assert!(trades > 1);
+        TradeSignAutocorrelation {
+            trades,
+            signs: VecDeque::with_capacity(trades + 1),
+        }
+    }
+
+    pub fn on_trade(&mut self, is_buyer_maker: bool) {
+        self.signs
+            .push_back(if is_buyer_maker { -1.0 } else { 1.0 });
+        if self.signs.len() > self.trades {
+            self.signs.pop_front();
+        }
+    }
+
+    // None until the window is full and while all the trades are on one side
+    pub fn value(&self) -> Option<f64> {
+        if self.signs.len() < self.trades {
+            return None;
+        }
+        let n = self.signs.len() as f64;
+        let mean = self.signs.iter().sum::<f64>() / n;
+        let variance = 1.0 - mean * mean;
+        if variance <= f64::EPSILON {
+            return None;
+        }
+        let (lagged, current) = (
+            self.signs.range(..self.signs.len() - 1),
+            self.signs.range(1..),
+        );
+        let covariance = lagged
+            .zip(current)
+            .map(|(a, b)| (a - mean) * (b - mean))
+            .sum::<f64>()
+            / (n - 1.0);
+        Some(covariance / variance)
+    }
+}
+
+// Micro-price of Stoikov: the mid price plus the spread times the mid price change that
+// followed the same book imbalance so far, learned from the book updates. The weighted mid
+// price until an imbalance bucket has seen a mid price change.
+#[derive(Debug)]
+pub struct Microprice {
+    // (sum of mid price change / spread, observations) per imbalance bucket
+    adjustments: [(f64, u64); IMBALANCE_BUCKETS],
+    // (sum of 1 / spread, observations) per bucket of the updates since the mid price changed
+    pending: [(f64, u64); IMBALANCE_BUCKETS],
+    last_mid: Option<f64>,
+    value: Option<f64>,
+}
+
+impl Default for Microprice {
+    fn default() -> Self {
+        Microprice {
+            adjustments: [(0.0, 0); IMBALANCE_BUCKETS],
+            pending: [(0.0, 0); IMBALANCE_BUCKETS],
+            last_mid: None,
+            value: None,
+        }
+    }
+}
+
+impl Microprice {
+    pub fn on_book(&mut self, book: &BinanceBookTicker) {
+        let spread = book.best_ask_price - book.best_bid_price;
+        let total_qty = book.best_bid_qty + book.best_ask_qty;
+        if spread <= 0.0 || total_qty <= 0.0 {
+            return;
+        }
+        let mid = (book.best_bid_price + book.best_ask_price) / 2.0;
+        if let Some(last_mid) = self.last_mid.filter(|last_mid| *last_mid != mid) {
+            let change = mid - last_mid;
+            for (adjustment, pending) in self.adjustments.iter_mut().zip(self.pending.iter_mut()) {
+                adjustment.0 += change * pending.0;
+                adjustment.1 += pending.1;
+                *pending = (0.0, 0);
+            }
+        }
+        self.last_mid = Some(mid);
+
+        let imbalance = book.best_bid_qty / total_qty;
+        let bucket = ((imbalance * IMBALANCE_BUCKETS as f64) as usize).min(IMBALANCE_BUCKETS - 1);
+        self.pending[bucket].0 += 1.0 / spread;
+        self.pending[bucket].1 += 1;
+        self.value = Some(match self.adjustments[bucket] {
+            (sum, count) if count > 0 => mid + spread * sum / count as f64,
+            _ => {
+                (book.best_ask_price * book.best_bid_qty + book.best_bid_price * book.best_ask_qty)
+                    / total_qty
+            }
+        });
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+struct OrderFlowPublisher {
+    market_data_topic: ReadTopicHandle,
+    signals_topic: WriteTopicHandle,
+
+    symbol: &'static str,
+    interval: Duration,
+    order_flow_imbalance: OrderFlowImbalance,
+    trade_sign_autocorrelation: TradeSignAutocorrelation,
+    microprice: Microprice,
+    next_publish_at: SystemTime,
+}
+
+impl Module for OrderFlowPublisher {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            match &msg.payload {
+                Payload::BinanceBookTicker(ticker) => {
+                    let time_ms = msg
+                        .header
+                        .commit_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64;
+                    self.order_flow_imbalance.on_book(time_ms, ticker);
+                    self.microprice.on_book(ticker);
+                }
+                Payload::BinanceTradeTick(tick) => self
+                    .trade_sign_autocorrelation
+                    .on_trade(tick.is_buyer_maker),
+                Payload::TradeTickBatch(ticks) => ticks.iter().for_each(|tick| {
+                    self.trade_sign_autocorrelation
+                        .on_trade(tick.is_buyer_maker)
+                }),
+                Payload::BinanceAggTrade(trade) => self
+                    .trade_sign_autocorrelation
+                    .on_trade(trade.is_buyer_maker),
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let now = comms.time();
+        if now < self.next_publish_at {
+            return;
+        }
+        let signals = [
+            (
+                SignalKind::OrderFlowImbalance,
+                self.order_flow_imbalance.value(),
+            ),
+            (
+                SignalKind::TradeSignAutocorrelation,
+                self.trade_sign_autocorrelation.value(),
+            ),
+            (SignalKind::Microprice, self.microprice.value()),
+        ];
+        for (kind, value) in signals {
+            let Some(value) = value else {
+                continue;
+            };
+            debug!("{} {} {}", self.symbol, kind.as_str(), value);
+            comms.publish(
+                &self.signals_topic,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::SignalUpdate(SignalUpdate {
+                        symbol: self.symbol,
+                        kind,
+                        value,
+                    }),
+                },
+            );
+        }
+        self.next_publish_at = now + self.interval;
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Computes the order-flow imbalance, trade sign autocorrelation and micro-price of a symbol
+// from its book tickers and trades and publishes them on the signals topic at most once per
+// interval. The interval is short by default as strategies can quote around the micro-price.
+pub struct OrderFlowPublisherBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    signals_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    interval: Duration,
+    imbalance_window: Duration,
+    autocorrelation_trades: usize,
+}
+
+impl OrderFlowPublisherBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        OrderFlowPublisherBuilder {
+            market_data_topic: None,
+            signals_topic: None,
+            symbol,
+            interval: Duration::from_millis(100),
+            imbalance_window: Duration::from_secs(10),
+            autocorrelation_trades: 100,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_imbalance_window(mut self, window: Duration) -> Self {
+        self.imbalance_window = window;
+        self
+    }
+
+    pub fn with_autocorrelation_trades(mut self, trades: usize) -> Self {
+        self.autocorrelation_trades = trades;
+        self
+    }
+}
+
+impl ModuleBuilder for OrderFlowPublisherBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let signals_topic = comms.get_topic("signals");
+
+        // only the ticks of our symbol
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(self.symbol))
+            .into();
+        self.signals_topic = comms.publish_topic(&signals_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(OrderFlowPublisher {
+            market_data_topic: self.market_data_topic.unwrap(),
+            signals_topic: self.signals_topic.unwrap(),
+            symbol: self.symbol,
+            interval: self.interval,
+            order_flow_imbalance: OrderFlowImbalance::new(self.imbalance_window),
+            trade_sign_autocorrelation: TradeSignAutocorrelation::new(self.autocorrelation_trades),
+            microprice: Microprice::default(),
+            next_publish_at: UNIX_EPOCH,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "order_flow"
+    }
+
+    // derived from the market data, before the strategies reading it
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::DataFeed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(bid: (f64, f64), ask: (f64, f64)) -> BinanceBookTicker {
+        BinanceBookTicker {
+            best_bid_price: bid.0,
+            best_bid_qty: bid.1,
+            best_ask_price: ask.0,
+            best_ask_qty: ask.1,
+            symbol: "BTCUSDT",
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_order_flow_imbalance() {
+        let mut ofi = OrderFlowImbalance::new(Duration::from_secs(10));
+        ofi.on_book(0, &book((100.0, 1.0), (101.0, 1.0)));
+        assert_eq!(ofi.value(), Some(0.0));
+        // 2 added at the bid
+        ofi.on_book(1_000, &book((100.0, 3.0), (101.0, 1.0)));
+        assert_eq!(ofi.value(), Some(2.0));
+        // the ask is lifted, its 1 is gone
+        ofi.on_book(2_000, &book((100.0, 3.0), (102.0, 4.0)));
+        assert_eq!(ofi.value(), Some(3.0));
+        // the first flow leaves the window
+        ofi.on_book(11_000, &book((100.0, 3.0), (102.0, 4.0)));
+        assert_eq!(ofi.value(), Some(1.0));
+    }
+
+    #[test]
+    fn test_trade_sign_autocorrelation() {
+        let mut autocorrelation = TradeSignAutocorrelation::new(4);
+        for is_buyer_maker in [false, false, false] {
+            autocorrelation.on_trade(is_buyer_maker);
+        }
+        assert_eq!(autocorrelation.value(), None);
+        // all buys
+        autocorrelation.on_trade(false);
+        assert_eq!(autocorrelation.value(), None);
+        // alternating sides
+        for is_buyer_maker in [true, false, true, false] {
+            autocorrelation.on_trade(is_buyer_maker);
+        }
+        assert_eq!(autocorrelation.value(), Some(-1.0));
+    }
+
+    #[test]
+    fn test_microprice() {
+        let mut microprice = Microprice::default();
+        // no mid price change seen yet, the weighted mid price
+        microprice.on_book(&book((100.0, 3.0), (101.0, 1.0)));
+        assert_eq!(microprice.value(), Some(100.75));
+        // the mid moved up a whole spread after a bid heavy book
+        microprice.on_book(&book((101.0, 1.0), (102.0, 1.0)));
+        microprice.on_book(&book((101.0, 3.0), (102.0, 1.0)));
+        assert_eq!(microprice.value(), Some(102.5));
+    }
+}
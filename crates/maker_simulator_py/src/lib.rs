@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+
+use market_agent::results::RunResults;
+use pyo3::{
+    exceptions::{PyRuntimeError, PyTypeError},
+    prelude::*,
+    types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple},
+};
+use toml::{Table, Value};
+
+// Runs backtests from Python, e.g. a notebook sweeping a parameter:
+//
+//   import maker_simulator
+//   results = maker_simulator.run_backtest({
+//       "date": "2024-01-01",
+//       "run_label": "tight",
+//       "quote_price_tolerance": 0.5,
+//       "initial_balance": {"USDT": 50000.0, "BTC": 1.0},
+//   })
+//   results["stats"]["profit"], results["fills"].to_pandas()
+//
+// The keys of the config are the keys of a sim config file, the long flags in snake_case.
+// The results are the ones written to the results directory, the stats as a dict and the
+// fills, equity curve and days as pyarrow tables.
+
+fn to_toml(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    // a bool is an int too
+    if let Ok(value) = value.downcast::<PyBool>() {
+        Ok(Value::Boolean(value.is_true()))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(Value::Integer(value.extract()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(Value::Float(value.extract()?))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(Value::String(value.extract()?))
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        Ok(Value::Table(to_table(dict)?))
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        let values = value
+            .iter()?
+            .map(|value| to_toml(&value?))
+            .collect::<PyResult<_>>()?;
+        Ok(Value::Array(values))
+    } else if value.hasattr("__fspath__")? {
+        // a pathlib.Path
+        Ok(Value::String(value.call_method0("__fspath__")?.extract()?))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "unsupported config value {}, expected bool, int, float, str, path, list or dict",
+            value.repr()?
+        )))
+    }
+}
+
+fn to_table(dict: &Bound<'_, PyDict>) -> PyResult<Table> {
+    let mut table = Table::new();
+    for (key, value) in dict {
+        table.insert(key.extract()?, to_toml(&value)?);
+    }
+    Ok(table)
+}
+
+fn results_dict<'py>(
+    py: Python<'py>,
+    dir: PathBuf,
+    results: RunResults,
+) -> PyResult<Bound<'py, PyDict>> {
+    let pyarrow = py.import_bound("pyarrow")?;
+    let table = |columns: Bound<'py, PyDict>| pyarrow.call_method1("table", (columns,));
+
+    let fills = &results.fills;
+    let fill_columns = PyDict::new_bound(py);
+    fill_columns.set_item(
+        "time_ms",
+        fills.iter().map(|f| f.time_ms).collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item(
+        "order_id",
+        fills
+            .iter()
+            .map(|f| f.order_id.as_str())
+            .collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item(
+        "side",
+        fills
+            .iter()
+            .map(|f| if f.is_buy { "buy" } else { "sell" })
+            .collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item("price", fills.iter().map(|f| f.price).collect::<Vec<_>>())?;
+    fill_columns.set_item(
+        "quantity",
+        fills.iter().map(|f| f.quantity).collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item("fee", fills.iter().map(|f| f.fee).collect::<Vec<_>>())?;
+    fill_columns.set_item(
+        "tag",
+        fills.iter().map(|f| f.tag.as_str()).collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item(
+        "symbol",
+        fills.iter().map(|f| f.symbol.as_str()).collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item(
+        "liquidity",
+        fills
+            .iter()
+            .map(|f| if f.is_maker { "maker" } else { "taker" })
+            .collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item(
+        "inventory",
+        fills.iter().map(|f| f.inventory).collect::<Vec<_>>(),
+    )?;
+    fill_columns.set_item("mid", fills.iter().map(|f| f.mid).collect::<Vec<_>>())?;
+
+    let equity_columns = PyDict::new_bound(py);
+    equity_columns.set_item(
+        "time_ms",
+        results.equity.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+    )?;
+    equity_columns.set_item(
+        "equity",
+        results.equity.iter().map(|(_, e)| *e).collect::<Vec<_>>(),
+    )?;
+
+    let daily = &results.daily;
+    let daily_columns = PyDict::new_bound(py);
+    daily_columns.set_item(
+        "day_start_ms",
+        daily.iter().map(|d| d.day_start_ms).collect::<Vec<_>>(),
+    )?;
+    daily_columns.set_item("fills", daily.iter().map(|d| d.fills).collect::<Vec<_>>())?;
+    daily_columns.set_item("volume", daily.iter().map(|d| d.volume).collect::<Vec<_>>())?;
+    daily_columns.set_item("fees", daily.iter().map(|d| d.fees).collect::<Vec<_>>())?;
+    daily_columns.set_item("equity", daily.iter().map(|d| d.equity).collect::<Vec<_>>())?;
+    daily_columns.set_item("profit", daily.iter().map(|d| d.profit).collect::<Vec<_>>())?;
+
+    let dict = PyDict::new_bound(py);
+    dict.set_item("results_dir", dir)?;
+    dict.set_item("stats", results.stats)?;
+    dict.set_item("fills", table(fill_columns)?)?;
+    dict.set_item("equity", table(equity_columns)?)?;
+    dict.set_item("daily", table(daily_columns)?)?;
+    Ok(dict)
+}
+
+// the results of a run written to results_dir earlier, by the sim or run_backtest
+#[pyfunction]
+fn load_results(py: Python<'_>, results_dir: PathBuf) -> PyResult<Bound<'_, PyDict>> {
+    let results =
+        RunResults::load(&results_dir).map_err(|e| PyRuntimeError::new_err(format!("{:#}", e)))?;
+    results_dict(py, results_dir, results)
+}
+
+// runs the backtest of the config in this process, without the GIL, and returns its results
+#[pyfunction]
+fn run_backtest<'py>(py: Python<'py>, config: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyDict>> {
+    let config = to_table(config)?;
+    let results_dir = py
+        .allow_threads(|| sim::run_backtest(&config))
+        .map_err(|e| PyRuntimeError::new_err(format!("{:#}", e)))?;
+    load_results(py, results_dir)
+}
+
+#[pymodule]
+fn maker_simulator(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(run_backtest, m)?)?;
+    m.add_function(wrap_pyfunction!(load_results, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_to_toml() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let config = py
+                .eval_bound(
+                    "{'date': '2024-01-01', 'reduce_inventory': True, 'decision_interval_ms': 500, \
+                     'fee_rate': 0.0002, 'variant': ['wide:quote_price_tolerance=2'], \
+                     'initial_balance': {'USDT': 50000.0}}",
+                    None,
+                    None,
+                )
+                .unwrap();
+            let table = to_table(config.downcast().unwrap()).unwrap();
+            let expected: Table = toml::from_str(
+                r#"
+                date = "2024-01-01"
+                reduce_inventory = true
+                decision_interval_ms = 500
+                fee_rate = 0.0002
+                variant = ["wide:quote_price_tolerance=2"]
+                initial_balance = { USDT = 50000.0 }
+                "#,
+            )
+            .unwrap();
+            assert_eq!(table, expected);
+
+            let config = py.eval_bound("{'date': object()}", None, None).unwrap();
+            assert!(to_table(config.downcast().unwrap()).is_err());
+        });
+    }
+
+    #[test]
+    fn test_run_backtest_missing_data() {
+        let dir = std::env::temp_dir().join(format!("maker_simulator_py_{}", std::process::id()));
+        let mut config = Table::new();
+        config.insert("date".into(), "2024-01-01".into());
+        config.insert("no_progress".into(), true.into());
+        let root_path = dir.join("future_um");
+        config.insert("root_path".into(), root_path.to_str().unwrap().into());
+        let results_dir = dir.join("results");
+        config.insert("results_dir".into(), results_dir.to_str().unwrap().into());
+        // an error for the notebook to raise, the process goes on
+        let e = sim::run_backtest(&config).unwrap_err();
+        assert!(
+            format!("{:#}", e).contains("can not be replayed"),
+            "{:#}",
+            e
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,59 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// The chance a resting order crossed by the market fills, drawn from a seeded rng for
+// reproducible runs. A probability of 1 fills every crossed order without drawing.
+#[derive(Debug, Clone)]
+pub struct FillProbability {
+    probability: f64,
+    rng: StdRng,
+}
+
+impl FillProbability {
+    pub fn new(probability: f64, seed: u64) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(format!(
+                "invalid fill probability {probability}, expected a number from 0 to 1"
+            ));
+        }
+        Ok(FillProbability {
+            probability,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+
+    // whether the next crossed order fills
+    pub fn fills(&mut self) -> bool {
+        self.probability >= 1.0 || self.rng.gen_bool(self.probability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_probability() {
+        for probability in [-0.1, 1.5, f64::NAN] {
+            assert!(
+                FillProbability::new(probability, 0).is_err(),
+                "{probability}"
+            );
+        }
+        let mut all = FillProbability::new(1.0, 0).unwrap();
+        assert!((0..100).all(|_| all.fills()));
+        let mut none = FillProbability::new(0.0, 0).unwrap();
+        assert!(!(0..100).any(|_| none.fills()));
+
+        let draws = |seed| {
+            let mut half = FillProbability::new(0.5, seed).unwrap();
+            (0..100).map(|_| half.fills()).collect::<Vec<_>>()
+        };
+        let filled = draws(7).iter().filter(|fills| **fills).count();
+        assert!((30..70).contains(&filled), "{filled}");
+        assert_eq!(draws(7), draws(7));
+    }
+}
@@ -0,0 +1,272 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Percentiles stored in a latency profile
+pub const PROFILE_PERCENTILES: &[f64] = &[
+    0.0, 1.0, 5.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0, 95.0, 99.0, 99.9, 100.0,
+];
+
+// Empirical latency distribution given as (percentile, latency in ms) points.
+// Stored as text, one `percentile,latency_ms` pair per line, `#` starts a comment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyProfile {
+    points: Vec<(f64, f64)>,
+}
+
+impl LatencyProfile {
+    // latency_ms does not need to be sorted
+    pub fn from_samples(latency_ms: &[f64]) -> Result<Self, anyhow::Error> {
+        if latency_ms.is_empty() {
+            bail!("no latency samples");
+        }
+        let mut sorted = latency_ms.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let points = PROFILE_PERCENTILES
+            .iter()
+            .map(|p| (*p, percentile_of_sorted(&sorted, *p)))
+            .collect();
+        Ok(LatencyProfile { points })
+    }
+
+    pub fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        let mut points: Vec<(f64, f64)> = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((percentile, latency_ms)) = line.split_once(',') else {
+                bail!("line {}: expect percentile,latency_ms", i + 1);
+            };
+            let percentile: f64 = percentile
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid percentile", i + 1))?;
+            let latency_ms: f64 = latency_ms
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid latency", i + 1))?;
+            if !(0.0..=100.0).contains(&percentile) || latency_ms < 0.0 {
+                bail!("line {}: out of range", i + 1);
+            }
+            if let Some((last_percentile, last_latency_ms)) = points.last() {
+                if percentile <= *last_percentile || latency_ms < *last_latency_ms {
+                    bail!("line {}: points must be increasing", i + 1);
+                }
+            }
+            points.push((percentile, latency_ms));
+        }
+        match (points.first(), points.last()) {
+            (Some((first, _)), Some((last, _))) if *first == 0.0 && *last == 100.0 => {}
+            _ => bail!("profile must range from percentile 0 to 100"),
+        }
+        Ok(LatencyProfile { points })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read latency profile {}", path.display()))?;
+        Self::parse(&s).with_context(|| format!("invalid latency profile {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("failed to write latency profile {}", path.display()))
+    }
+
+    // latency in ms at percentile, interpolated between the profile points
+    pub fn latency_ms_at(&self, percentile: f64) -> f64 {
+        let i = self.points.partition_point(|(p, _)| *p < percentile);
+        if i == 0 {
+            return self.points[0].1;
+        }
+        if i == self.points.len() {
+            return self.points[i - 1].1;
+        }
+        let (p0, l0) = self.points[i - 1];
+        let (p1, l1) = self.points[i];
+        l0 + (l1 - l0) * (percentile - p0) / (p1 - p0)
+    }
+}
+
+impl std::fmt::Display for LatencyProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# percentile,latency_ms")?;
+        for (percentile, latency_ms) in &self.points {
+            writeln!(f, "{},{:.3}", percentile, latency_ms)?;
+        }
+        Ok(())
+    }
+}
+
+// nearest rank percentile, p in [0, 100]
+pub fn percentile_of_sorted(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+// Legs of an order's round trip with their own latency distribution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyChannel {
+    // new orders to the exchange
+    Place,
+    // cancels to the exchange, venues often handle them faster or slower than new orders
+    Cancel,
+    // order results and account updates back from the exchange
+    Ack,
+}
+
+#[derive(Debug)]
+struct ChannelLatency {
+    profile: LatencyProfile,
+    rng: StdRng,
+}
+
+impl ChannelLatency {
+    fn new(profile: LatencyProfile, seed: u64) -> Self {
+        ChannelLatency {
+            profile,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn sample(&mut self) -> Duration {
+        let percentile = self.rng.gen_range(0.0..100.0);
+        Duration::from_secs_f64(self.profile.latency_ms_at(percentile) / 1000.0)
+    }
+}
+
+// Draws latencies from a profile per channel, seeded so a simulation is reproducible.
+// Each channel draws from its own generator, setting one channel doesn't change the others.
+#[derive(Debug)]
+pub struct LatencyModel {
+    place: ChannelLatency,
+    cancel: ChannelLatency,
+    // acks are immediate unless set
+    ack: Option<ChannelLatency>,
+    seed: u64,
+    // multiplies every sampled latency
+    scale: f64,
+}
+
+impl LatencyModel {
+    // places and cancels both draw from profile
+    pub fn new(profile: LatencyProfile, seed: u64) -> Self {
+        LatencyModel {
+            place: ChannelLatency::new(profile.clone(), seed),
+            cancel: ChannelLatency::new(profile, seed.wrapping_add(1)),
+            ack: None,
+            seed,
+            scale: 1.0,
+        }
+    }
+
+    pub fn with_cancel_profile(mut self, profile: LatencyProfile) -> Self {
+        self.cancel = ChannelLatency::new(profile, self.seed.wrapping_add(1));
+        self
+    }
+
+    pub fn with_ack_profile(mut self, profile: LatencyProfile) -> Self {
+        self.ack = Some(ChannelLatency::new(profile, self.seed.wrapping_add(2)));
+        self
+    }
+
+    // slower or faster venues than the profiles were measured on
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn has_ack_latency(&self) -> bool {
+        self.ack.is_some()
+    }
+
+    pub fn sample(&mut self, channel: LatencyChannel) -> Duration {
+        let latency = match channel {
+            LatencyChannel::Place => self.place.sample(),
+            LatencyChannel::Cancel => self.cancel.sample(),
+            LatencyChannel::Ack => self.ack.as_mut().map_or(Duration::ZERO, |ack| ack.sample()),
+        };
+        latency.mul_f64(self.scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_from_samples() {
+        let samples: Vec<f64> = (0..=100).rev().map(|x| x as f64).collect();
+        let profile = LatencyProfile::from_samples(&samples).unwrap();
+        assert_eq!(profile.latency_ms_at(0.0), 0.0);
+        assert_eq!(profile.latency_ms_at(50.0), 50.0);
+        assert_eq!(profile.latency_ms_at(100.0), 100.0);
+        // interpolated between the 99 and 99.9 percentiles
+        assert!((profile.latency_ms_at(99.45) - 99.5).abs() < 1e-9);
+
+        assert!(LatencyProfile::from_samples(&[]).is_err());
+    }
+
+    #[test]
+    fn test_profile_round_trip() {
+        let profile = LatencyProfile::from_samples(&[3.0, 5.5, 8.25, 40.0]).unwrap();
+        assert_eq!(
+            LatencyProfile::parse(&profile.to_string()).unwrap(),
+            profile
+        );
+
+        assert!(LatencyProfile::parse("0,1\n100,2").is_ok());
+        assert!(LatencyProfile::parse("0,1\n50,2").is_err());
+        assert!(LatencyProfile::parse("0,3\n100,2").is_err());
+        assert!(LatencyProfile::parse("0,1\n100").is_err());
+    }
+
+    #[test]
+    fn test_latency_model_sample() {
+        let profile = LatencyProfile::parse("0,10\n100,20").unwrap();
+        let mut model = LatencyModel::new(profile.clone(), 7);
+        let samples: Vec<Duration> = (0..100)
+            .map(|_| model.sample(LatencyChannel::Place))
+            .collect();
+        assert!(samples
+            .iter()
+            .all(|d| *d >= Duration::from_millis(10) && *d <= Duration::from_millis(20)));
+
+        // same seed, same latencies
+        let mut model = LatencyModel::new(profile.clone(), 7);
+        assert!(samples
+            .iter()
+            .all(|d| *d == model.sample(LatencyChannel::Place)));
+
+        let mut model = LatencyModel::new(profile, 7).with_scale(2.0);
+        assert!(samples.iter().all(|d| {
+            (model.sample(LatencyChannel::Place).as_secs_f64() - d.as_secs_f64() * 2.0).abs() < 1e-9
+        }));
+    }
+
+    #[test]
+    fn test_latency_channels() {
+        let place = LatencyProfile::parse("0,10\n100,20").unwrap();
+        let cancel = LatencyProfile::parse("0,1\n100,2").unwrap();
+        let mut model = LatencyModel::new(place.clone(), 7);
+        assert!(!model.has_ack_latency());
+        assert_eq!(model.sample(LatencyChannel::Ack), Duration::ZERO);
+        let places: Vec<Duration> = (0..10)
+            .map(|_| model.sample(LatencyChannel::Place))
+            .collect();
+
+        let mut model = LatencyModel::new(place, 7)
+            .with_cancel_profile(cancel)
+            .with_ack_profile(LatencyProfile::parse("0,5\n100,5").unwrap());
+        for place in places {
+            let cancel = model.sample(LatencyChannel::Cancel);
+            assert!(cancel >= Duration::from_millis(1) && cancel <= Duration::from_millis(2));
+            assert_eq!(model.sample(LatencyChannel::Ack), Duration::from_millis(5));
+            // drawing other channels doesn't change the place latencies
+            assert_eq!(model.sample(LatencyChannel::Place), place);
+        }
+    }
+}
@@ -1,3 +1,9 @@
+pub mod fill_probability;
+pub mod latency;
 pub mod market_agent;
 mod market_stats;
+mod markout;
+pub mod rate_limit;
+pub mod results;
 mod simple_market;
+pub mod slippage;
@@ -1,13 +1,112 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::PathBuf,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{market_stats::MarketStats, simple_market};
+use crate::{
+    fill_probability::FillProbability,
+    latency::{LatencyChannel, LatencyModel},
+    market_stats::MarketStats,
+    markout::MarkoutTracker,
+    rate_limit::{RateLimit, RateLimiter},
+    results::{DailyResult, Fill, RunResults},
+    simple_market,
+    slippage::SlippageModel,
+};
 use account::account::{Account, AssetBalance};
-use symbol_info::{calc_trade_result, SymbolInfoManager};
+use symbol_info::{calc_trade_result, SymbolInfo, SymbolInfoManager};
 use tracing::{debug, error, trace};
-use upstair_type::module::{Module, ModuleBuilder, ReadTopicHandle, WriteTopicHandle};
+use upstair_type::{
+    module::{Module, ModuleBuilder, ModulePriority, ReadTopicHandle, WriteTopicHandle},
+    order::{CancelRejectReason, RejectReason, LIQUIDATION_ORDER_PREFIX},
+    run_output::RunOutput,
+};
+
+pub use crate::simple_market::SelfTradePrevention;
+
+// number of fully filled order ids kept to tell cancels which lost the race
+const RECENTLY_FILLED_CAPACITY: usize = 1000;
+
+// Messages back to the strategy, delayed by the ack latency of the latency model
+#[derive(Default)]
+struct AckOutbox {
+    // (arrive_at, topic, message) ordered by arrival time
+    pending: VecDeque<(SystemTime, WriteTopicHandle, upstair_type::Message)>,
+    // sequence number of the last message sent per owner and symbol
+    last_seq: HashMap<(Option<&'static str>, &'static str), u64>,
+}
+
+impl AckOutbox {
+    fn send(
+        &mut self,
+        topic: &WriteTopicHandle,
+        mut message: upstair_type::Message,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+        latency_model: Option<&mut LatencyModel>,
+    ) {
+        // the results and balance updates of a symbol are numbered in the order they are sent,
+        // a snapshot carries the number of the last message it includes. Each owner has its own
+        // numbers, it only receives its own messages.
+        let owner = message.payload.owner();
+        match &mut message.payload {
+            upstair_type::Payload::OrderResult(result) => {
+                result.seq = self.next_seq(owner, result.symbol)
+            }
+            upstair_type::Payload::OrderResultBatch(batch) => {
+                for result in &mut batch.results {
+                    result.seq = self.next_seq(owner, result.symbol)
+                }
+            }
+            upstair_type::Payload::AccountUpdate(update) => {
+                if let Some(symbol) = update.symbol {
+                    update.seq = self.next_seq(owner, symbol);
+                }
+            }
+            upstair_type::Payload::ResyncSnapshot(snapshot) => {
+                snapshot.seq = self
+                    .last_seq
+                    .get(&(owner, snapshot.symbol))
+                    .copied()
+                    .unwrap_or(0)
+            }
+            _ => {}
+        }
+        let Some(model) = latency_model.filter(|model| model.has_ack_latency()) else {
+            comms.publish(topic, message);
+            return;
+        };
+        // acks arrive in the order they are sent
+        let arrive_at = (comms.time() + model.sample(LatencyChannel::Ack))
+            .max(self.pending.back().map_or(UNIX_EPOCH, |(t, _, _)| *t));
+        self.pending.push_back((arrive_at, topic.clone(), message));
+    }
+
+    // publish the acks which arrived by now
+    fn flush(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|(t, _, _)| *t <= comms.time())
+        {
+            let (_, topic, mut message) = self.pending.pop_front().unwrap();
+            // subscribers are woken by the publish time
+            message.header.commit_at = comms.time();
+            comms.publish(&topic, message);
+        }
+    }
+
+    fn next_arrival_at(&self) -> Option<SystemTime> {
+        self.pending.front().map(|(t, _, _)| *t)
+    }
+
+    fn next_seq(&mut self, owner: Option<&'static str>, symbol: &'static str) -> u64 {
+        let seq = self.last_seq.entry((owner, symbol)).or_default();
+        *seq += 1;
+        *seq
+    }
+}
 
 struct MarketAgent {
     market_data_topic: ReadTopicHandle,
@@ -17,15 +116,51 @@ struct MarketAgent {
 
     market_by_symbol: std::collections::HashMap<&'static str, simple_market::SimpleMarket>,
 
+    // the account of the orders without an owner, the results are of this one
     account: Account,
+    // the accounts of the strategies placing orders with an owner, apart from the one above
+    owner_accounts: BTreeMap<&'static str, Account>,
+    // the owner of each open order placed with one
+    order_owners: HashMap<Arc<str>, &'static str>,
     fee_account: Account,
     symobl_info_manager: SymbolInfoManager,
 
+    // the order flow of the orders without an owner and the events of the agent
     stats: MarketStats,
+    // the order flow of the strategies with their own account, apart from the one above
+    owner_stats: BTreeMap<&'static str, MarketStats>,
+    markouts: MarkoutTracker,
 
     initial_balance: Vec<(String, f64)>,
+    owner_initial_balance: BTreeMap<&'static str, Vec<(String, f64)>>,
 
     last_account_summary_send_time: SystemTime,
+    // the summaries and equity snapshots are sent on the first iteration, then on the
+    // boundaries of the interval
+    account_snapshot_interval: Option<Duration>,
+    next_account_snapshot_at: Option<SystemTime>,
+
+    // order requests are delivered to the exchange after a sampled latency
+    latency_model: Option<LatencyModel>,
+    // requests on the way to the exchange, ordered by arrival time
+    inflight_requests: VecDeque<(SystemTime, LatencyChannel, upstair_type::Message)>,
+    acks: AckOutbox,
+    // fully filled orders, a cancel arriving for them lost the race against the fill
+    recently_filled: VecDeque<Arc<str>>,
+
+    // fills and equity are recorded and written here on terminate
+    results_dir: Option<PathBuf>,
+    results: RunResults,
+    // (start ms, fills before it, equity at its start) of the day the daily results record
+    day: Option<(u64, usize, f64)>,
+
+    self_trade_prevention: SelfTradePrevention,
+    slippage: SlippageModel,
+    // of a crossed resting order, each market draws from a copy seeded alike
+    fill_probability: Option<FillProbability>,
+    // requests over the limits are rejected
+    order_rate_limit: Option<RateLimiter>,
+    cancel_rate_limit: Option<RateLimiter>,
 }
 
 impl Module for MarketAgent {
@@ -35,45 +170,140 @@ impl Module for MarketAgent {
             let account = self.account.get_or_create(asset.clone().leak());
             account.add_balance(*balance);
         }
+        for (owner, balances) in &self.owner_initial_balance {
+            let account = self.owner_accounts.entry(owner).or_default();
+            for (asset, balance) in balances {
+                account
+                    .get_or_create(asset.clone().leak())
+                    .add_balance(*balance);
+            }
+        }
     }
 
     fn sync(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) -> bool {
-        while let Some(msg) = comms.receive(&self.market_data_topic) {
-            self.ingest_market_trade_data(msg);
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            self.ingest_market_trade_data(&msg);
         }
+        self.acks.flush(comms);
         while let Some(msg) = comms.receive(&self.order_topic) {
+            match &mut self.latency_model {
+                Some(model) => {
+                    let channel = match msg.payload {
+                        upstair_type::Payload::CancelOrderRequest(_)
+                        | upstair_type::Payload::BatchCancelRequest(_) => LatencyChannel::Cancel,
+                        _ => LatencyChannel::Place,
+                    };
+                    // requests of a channel arrive in the order they are sent, a cancel may
+                    // overtake the order it cancels
+                    let arrive_at = (comms.time() + model.sample(channel)).max(
+                        self.inflight_requests
+                            .iter()
+                            .rev()
+                            .find(|(_, c, _)| *c == channel)
+                            .map_or(UNIX_EPOCH, |(t, _, _)| *t),
+                    );
+                    let i = self
+                        .inflight_requests
+                        .partition_point(|(t, _, _)| *t <= arrive_at);
+                    self.inflight_requests.insert(i, (arrive_at, channel, msg));
+                }
+                None => self.ingest_order_request(msg, comms),
+            }
+        }
+        while self
+            .inflight_requests
+            .front()
+            .is_some_and(|(t, _, _)| *t <= comms.time())
+        {
+            let (_, _, msg) = self.inflight_requests.pop_front().unwrap();
             self.ingest_order_request(msg, comms);
         }
         true
     }
 
     fn one_iteration(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        let now_ms = comms
+            .time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let num_fills = self.results.fills.len();
         for (symbol, market) in &mut self.market_by_symbol {
             for e in market.try_match_market().iter() {
                 let is_buy = e.side == upstair_type::order::TradeSide::Buy;
-                // update stats
-                self.stats
-                    .on_order_filled(e.quantity, e.quantity * e.price, is_buy);
 
                 // deduce locked balance
                 let symbol_info = self.symobl_info_manager.get(symbol).unwrap_or_else(|| {
                     panic!("symbol {} is not supported", symbol);
                 });
                 let r = calc_trade_result(symbol_info, e.price, e.quantity, is_buy);
+                let is_fully_filled = e.reamin_qty_to_fill <= 0.0;
+                let owner = if is_fully_filled {
+                    self.order_owners.remove(&e.order_id)
+                } else {
+                    self.order_owners.get(&e.order_id).copied()
+                };
+                let account = account_of(&mut self.account, &mut self.owner_accounts, owner);
+                // update stats
+                let stats = stats_of(&mut self.stats, &mut self.owner_stats, owner);
+                stats.on_order_filled(e.quantity, e.quantity * e.price, is_buy);
 
                 // deduct fees
                 self.fee_account
                     .get_or_create(r.fee_asset)
                     .add_balance(r.fee_qty);
-                self.account
-                    .get_or_create(r.pay_asset)
-                    .consume_locked(r.pay_qty);
-                self.account
-                    .get_or_create(r.recv_asset)
-                    .add_balance(r.recv_qty);
+                let pay_asset_balance = account.get_or_create(r.pay_asset);
+                if is_buy {
+                    // stop market orders fill away from the price they were locked at
+                    let locked_qty = e.locked_price * e.quantity;
+                    pay_asset_balance.consume_locked(locked_qty);
+                    pay_asset_balance.deduce_balance(r.pay_qty - locked_qty);
+                } else {
+                    pay_asset_balance.consume_locked(r.pay_qty);
+                }
+                account.get_or_create(r.recv_asset).add_balance(r.recv_qty);
                 if e.quantity <= 0.0 {
                     panic!("quantity should be positive");
                 }
+                let is_liquidation = e.order_id.starts_with(LIQUIDATION_ORDER_PREFIX);
+                if is_liquidation {
+                    stats.on_liquidation_fill(e.quantity * e.price);
+                }
+                let mid = market.mid_price();
+                if owner.is_none() {
+                    // the fee is in the asset received
+                    let fee = if r.fee_asset == symbol_info.quote_asset {
+                        r.fee_qty
+                    } else {
+                        r.fee_qty * e.price
+                    };
+                    self.stats
+                        .on_attributed_fill(symbol, is_buy, e.price, e.quantity, mid, fee);
+                    self.markouts.on_fill(symbol, now_ms, is_buy, e.price, mid);
+                }
+                if self.results_dir.is_some() && owner.is_none() {
+                    let inventory = account
+                        .asset_to_balance
+                        .get(symbol_info.base_asset)
+                        .map_or(0.0, |b| b.balance);
+                    self.results.fills.push(Fill {
+                        time_ms: now_ms,
+                        order_id: e.order_id.to_string(),
+                        is_buy,
+                        price: e.price,
+                        quantity: e.quantity,
+                        fee: r.fee_qty,
+                        tag: if is_liquidation {
+                            "liquidation".to_string()
+                        } else {
+                            String::new()
+                        },
+                        symbol: symbol.to_string(),
+                        is_maker: e.is_maker,
+                        inventory,
+                        mid,
+                    });
+                }
 
                 trace!(
                     "-----\nFill {:?} order_id={} price={} qty={}\n{}",
@@ -81,11 +311,16 @@ impl Module for MarketAgent {
                     e.order_id,
                     e.price,
                     e.quantity,
-                    account_brief(&self.account)
+                    account_brief(account)
                 );
 
-                let is_fully_filled = e.reamin_qty_to_fill <= 0.0;
-                comms.publish(
+                if is_fully_filled {
+                    if self.recently_filled.len() == RECENTLY_FILLED_CAPACITY {
+                        self.recently_filled.pop_front();
+                    }
+                    self.recently_filled.push_back(e.order_id.clone());
+                }
+                self.acks.send(
                     &self.order_result_topic,
                     upstair_type::Message {
                         header: upstair_type::MessageHeader {
@@ -104,12 +339,17 @@ impl Module for MarketAgent {
                                 } else {
                                     upstair_type::order::OrderStatus::PartiallyFilled
                                 },
+                                seq: 0,
+                                owner,
+                                reject_reason: None,
                             },
                         ),
                     },
+                    comms,
+                    self.latency_model.as_mut(),
                 );
                 // update touch asset
-                comms.publish(
+                self.acks.send(
                     &self.account_topic,
                     upstair_type::Message {
                         header: upstair_type::MessageHeader {
@@ -117,38 +357,113 @@ impl Module for MarketAgent {
                         },
                         payload: upstair_type::Payload::AccountUpdate(
                             Self::make_account_update_for_asset(
-                                &self.account,
+                                account_of(&mut self.account, &mut self.owner_accounts, owner),
+                                owner,
+                                symbol,
                                 &[r.pay_asset, r.recv_asset],
                             ),
                         ),
                     },
+                    comms,
+                    self.latency_model.as_mut(),
                 );
             }
         }
 
-        // send account summary every 10 seconds
+        // take profit orders opened on trigger may expire by self trade prevention
+        let symbols_with_expired_orders: Vec<&'static str> = self
+            .market_by_symbol
+            .iter()
+            .filter(|(_, market)| !market.expired_orders.is_empty())
+            .map(|(symbol, _)| *symbol)
+            .collect();
+        for symbol in symbols_with_expired_orders {
+            self.report_expired_orders(symbol, comms);
+        }
+        // good til date orders expire at their deadline, after the trades up to it
+        self.expire_good_til_date_orders(comms);
+
+        if self.results.fills.len() > num_fills {
+            let equity = self.usdt_value(&self.account);
+            self.results.equity.push((now_ms, equity));
+        }
+
+        // send account summary every 1000 seconds, or on every snapshot boundary with the
+        // equity of the accounts
         let now = comms.time();
-        if now
-            .duration_since(self.last_account_summary_send_time)
-            .unwrap_or_default()
-            .as_secs()
-            > 1000
-        {
+        let summary_due = match (
+            self.account_snapshot_interval,
+            self.next_account_snapshot_at,
+        ) {
+            (Some(_), Some(at)) => now >= at,
+            (Some(_), None) => true,
+            (None, _) => {
+                now.duration_since(self.last_account_summary_send_time)
+                    .unwrap_or_default()
+                    .as_secs()
+                    > 1000
+            }
+        };
+        if summary_due {
             self.last_account_summary_send_time = now;
-            comms.publish(
-                &self.account_topic,
-                upstair_type::Message {
-                    header: upstair_type::MessageHeader { commit_at: now },
-                    payload: upstair_type::Payload::AccountUpdate(Self::make_account_update(
-                        &self.account,
-                    )),
-                },
-            );
+            let accounts: Vec<(Option<&'static str>, &Account)> =
+                std::iter::once((None, &self.account))
+                    .chain(self.owner_accounts.iter().map(|(o, a)| (Some(*o), a)))
+                    .collect();
+            for (owner, account) in &accounts {
+                self.acks.send(
+                    &self.account_topic,
+                    upstair_type::Message {
+                        header: upstair_type::MessageHeader { commit_at: now },
+                        payload: upstair_type::Payload::AccountUpdate(Self::make_account_update(
+                            account, *owner,
+                        )),
+                    },
+                    comms,
+                    self.latency_model.as_mut(),
+                );
+            }
+            if let Some(interval) = self.account_snapshot_interval {
+                self.next_account_snapshot_at = Some(next_snapshot_at(now, interval));
+                let equities: Vec<(Option<&'static str>, f64)> = accounts
+                    .iter()
+                    .map(|(owner, account)| (*owner, self.usdt_value(account)))
+                    .collect();
+                for (owner, equity) in equities {
+                    self.acks.send(
+                        &self.account_topic,
+                        upstair_type::Message {
+                            header: upstair_type::MessageHeader { commit_at: now },
+                            payload: upstair_type::Payload::EquitySnapshot(
+                                upstair_type::account::EquitySnapshot { equity, owner },
+                            ),
+                        },
+                        comms,
+                        self.latency_model.as_mut(),
+                    );
+                    // the equity curve is sampled on the boundaries too, besides on the fills
+                    if owner.is_none() && self.results.equity.last() != Some(&(now_ms, equity)) {
+                        self.results.equity.push((now_ms, equity));
+                    }
+                }
+            }
         }
     }
 
     fn next_iteration_start_at(&self) -> Option<std::time::SystemTime> {
-        None
+        let next_request_at = self.inflight_requests.front().map(|(t, _, _)| *t);
+        [
+            next_request_at,
+            self.acks.next_arrival_at(),
+            self.next_account_snapshot_at,
+            self.market_by_symbol
+                .values()
+                .filter_map(|market| market.next_expiry())
+                .min(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
     }
 
     fn wake_on_message(&self) -> bool {
@@ -165,24 +480,6 @@ impl Module for MarketAgent {
             println!("{}: {}", symbol, market.last_trade_price);
         }
 
-        // given account, compute total usdt value
-        let calc_usdt_value_fn = |account: &Account| -> f64 {
-            let mut total_usdt_value = 0.0;
-            for (asset, balance) in &account.asset_to_balance {
-                if asset == &"USDT" {
-                    total_usdt_value += balance.balance;
-                } else {
-                    let symbol: String = format!("{}USDT", asset);
-                    let market = self.market_by_symbol.get(&symbol.as_str());
-                    if market.is_none() {
-                        error!("symbol {} is not valued", symbol);
-                        continue;
-                    }
-                    total_usdt_value += balance.balance * market.unwrap().last_trade_price;
-                }
-            }
-            total_usdt_value
-        };
         // print inital equity
         let mut total_inital_value = 0.0;
         println!("--- Initial Equity ---");
@@ -209,16 +506,15 @@ impl Module for MarketAgent {
         for (asset, balance) in &self.account.asset_to_balance {
             println!("{}: {} ({} locked)", asset, balance.balance, balance.locked);
         }
-        println!("Total Usdt Value: {}", calc_usdt_value_fn(&self.account));
+        let total_value = self.usdt_value(&self.account);
+        println!("Total Usdt Value: {}", total_value);
         // print all fee balance
         println!("--- Fee ---");
         for (asset, balance) in &self.fee_account.asset_to_balance {
             println!("{}: {}", asset, balance.balance);
         }
-        println!(
-            "Total Usdt Value: {}",
-            calc_usdt_value_fn(&self.fee_account)
-        );
+        let fee_value = self.usdt_value(&self.fee_account);
+        println!("Total Usdt Value: {}", fee_value);
         // print all profilts
         println!("--- Profits ---");
         let mut total_profit_in_usdt = 0.0;
@@ -257,6 +553,120 @@ impl Module for MarketAgent {
                 * 100.0
                 * 100.0
         );
+
+        let attribution = self.stats.pnl_attribution(|symbol| {
+            self.market_by_symbol
+                .get(symbol)
+                .map(|market| market.last_trade_price)
+        });
+        println!("--- PnL Attribution ---");
+        println!("Spread Capture: {:.4}", attribution.spread_capture);
+        println!("Inventory Drift: {:.4}", attribution.inventory_drift);
+        println!("Fees: {:.4}", attribution.fees);
+        println!("Total: {:.4}", attribution.total());
+        println!("--- Markouts (bps) ---");
+        print!("{}", self.markouts.summary());
+
+        // the strategies with their own account
+        let mut owner_stats = vec![];
+        for (owner, account) in &self.owner_accounts {
+            if let Some(stats) = self.owner_stats.get(owner) {
+                println!("--- Stats of {} ---", owner);
+                println!("{}", stats.summary());
+                owner_stats.extend(
+                    stats
+                        .metrics()
+                        .into_iter()
+                        .map(|(name, value)| (format!("{}.{}", name, owner), value)),
+                );
+            }
+            println!("--- Equity of {} ---", owner);
+            for (asset, balance) in &account.asset_to_balance {
+                println!("{}: {} ({} locked)", asset, balance.balance, balance.locked);
+            }
+            let initial_value: f64 = self
+                .owner_initial_balance
+                .get(owner)
+                .into_iter()
+                .flatten()
+                .filter_map(|(asset, balance)| self.usdt_price(asset).map(|p| balance * p))
+                .sum();
+            let value = self.usdt_value(account);
+            println!("Initial Usdt Value: {}", initial_value);
+            println!("Total Usdt Value: {}", value);
+            println!("Profit: {}", value - initial_value);
+            owner_stats.extend([
+                (format!("initial_equity.{}", owner), initial_value),
+                (format!("final_equity.{}", owner), value),
+                (format!("profit.{}", owner), value - initial_value),
+            ]);
+        }
+
+        self.end_day();
+        if let Some(results_dir) = &self.results_dir {
+            let max_drawdown = self.results.max_drawdown();
+            let fill_count = self.results.fills.len() as f64;
+            self.results.stats.extend(self.stats.metrics());
+            self.results.stats.extend([
+                ("initial_equity".to_string(), total_inital_value),
+                ("final_equity".to_string(), total_value),
+                ("profit".to_string(), total_profit_in_usdt),
+                ("fee".to_string(), fee_value),
+                ("fill_count".to_string(), fill_count),
+                ("max_drawdown".to_string(), max_drawdown),
+                ("pnl_spread_capture".to_string(), attribution.spread_capture),
+                (
+                    "pnl_inventory_drift".to_string(),
+                    attribution.inventory_drift,
+                ),
+                ("pnl_fees".to_string(), attribution.fees),
+            ]);
+            self.results.stats.extend(self.markouts.metrics());
+            self.results.stats.extend(owner_stats);
+            match self.results.save(results_dir) {
+                Ok(_) => println!("Results written to {}", results_dir.display()),
+                Err(e) => error!("failed to write results: {:#}", e),
+            }
+        }
+    }
+}
+
+// asset and amount locked by the unfilled part of order
+fn locked_balance(
+    symbol_info: &SymbolInfo,
+    order: &simple_market::LimitOrder,
+) -> (&'static str, f64) {
+    if order.side == upstair_type::order::TradeSide::Buy {
+        (
+            symbol_info.quote_asset,
+            order.price * (order.quantity - order.filled),
+        )
+    } else {
+        (symbol_info.base_asset, order.quantity - order.filled)
+    }
+}
+
+// the account of owner, the one of the orders without an owner for None
+fn account_of<'a>(
+    account: &'a mut Account,
+    owner_accounts: &'a mut BTreeMap<&'static str, Account>,
+    owner: Option<&'static str>,
+) -> &'a mut Account {
+    match owner {
+        Some(owner) => owner_accounts.entry(owner).or_default(),
+        None => account,
+    }
+}
+
+// the order flow stats of owner, the ones of the orders without an owner for None
+fn stats_of<'a>(
+    stats: &'a mut MarketStats,
+    owner_stats: &'a mut BTreeMap<&'static str, MarketStats>,
+    owner: Option<&'static str>,
+) -> &'a mut MarketStats {
+    match owner {
+        Some(owner) => owner_stats.entry(owner).or_default(),
+        None => stats,
     }
 }
 
@@ -282,25 +692,175 @@ fn account_brief(account: &Account) -> String {
 }
 
 impl MarketAgent {
-    fn ingest_market_trade_data(&mut self, data: upstair_type::Message) {
-        match data.payload {
+    // total value of account in usdt at the last trade prices
+    fn usdt_value(&self, account: &Account) -> f64 {
+        let mut total_usdt_value = 0.0;
+        for (asset, balance) in &account.asset_to_balance {
+            if asset == &"USDT" {
+                total_usdt_value += balance.balance;
+            } else {
+                let symbol: String = format!("{}USDT", asset);
+                let market = self.market_by_symbol.get(&symbol.as_str());
+                if market.is_none() {
+                    error!("symbol {} is not valued", symbol);
+                    continue;
+                }
+                total_usdt_value += balance.balance * market.unwrap().last_trade_price;
+            }
+        }
+        total_usdt_value
+    }
+
+    // usdt per unit of asset at the last trade price, None when it has no usdt market
+    fn usdt_price(&self, asset: &str) -> Option<f64> {
+        if asset == "USDT" {
+            return Some(1.0);
+        }
+        self.market_by_symbol
+            .get(format!("{}USDT", asset).as_str())
+            .map(|market| market.last_trade_price)
+    }
+
+    fn market_mut(&mut self, symbol: &'static str) -> &mut simple_market::SimpleMarket {
+        self.market_by_symbol.entry(symbol).or_insert_with(|| {
+            let market = simple_market::SimpleMarket::new()
+                .with_self_trade_prevention(self.self_trade_prevention)
+                .with_slippage(self.slippage);
+            match &self.fill_probability {
+                Some(fill_probability) => market.with_fill_probability(fill_probability.clone()),
+                None => market,
+            }
+        })
+    }
+
+    // the day starts once its equity can be valued
+    fn start_day(&mut self, at: SystemTime) {
+        if self.results_dir.is_none() {
+            return;
+        }
+        let at_ms = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let equity = self.usdt_value(&self.account);
+        self.day = Some((at_ms, self.results.fills.len(), equity));
+    }
+
+    fn end_day(&mut self) {
+        let Some((day_start_ms, first_fill, start_equity)) = self.day.take() else {
+            return;
+        };
+        let fills = &self.results.fills[first_fill..];
+        let equity = self.usdt_value(&self.account);
+        self.results.daily.push(DailyResult {
+            day_start_ms,
+            fills: fills.len() as u64,
+            volume: fills.iter().map(|f| f.price * f.quantity).sum(),
+            fees: fills.iter().map(|f| f.fee).sum(),
+            equity,
+            profit: equity - start_equity,
+        });
+    }
+
+    fn ingest_market_trade_data(&mut self, data: &upstair_type::Message) {
+        match &data.payload {
             upstair_type::Payload::BinanceTradeTick(tick) => {
-                let market = self
-                    .market_by_symbol
-                    .entry(tick.symbol)
-                    .or_insert_with(simple_market::SimpleMarket::new);
-                market.add_market_trade(simple_market::MarketTrade {
-                    price: tick.price,
-                    quantity: tick.qty,
-                    trade_at: SystemTime::UNIX_EPOCH + Duration::from_millis(tick.time),
-                    is_buyer_maker: tick.is_buyer_maker,
-                });
+                self.market_mut(tick.symbol)
+                    .add_market_trade(simple_market::MarketTrade {
+                        price: tick.price,
+                        quantity: tick.qty,
+                        trade_at: SystemTime::UNIX_EPOCH + Duration::from_millis(tick.time),
+                        is_buyer_maker: tick.is_buyer_maker,
+                    });
+                if self.day.is_none() && self.results.daily.is_empty() {
+                    self.start_day(data.header.commit_at);
+                }
             }
-            upstair_type::Payload::BinanceBookTicker(_) => {}
+            upstair_type::Payload::TradeTickBatch(ticks) => {
+                for tick in ticks {
+                    self.market_mut(tick.symbol)
+                        .add_market_trade(simple_market::MarketTrade {
+                            price: tick.price,
+                            quantity: tick.qty,
+                            trade_at: SystemTime::UNIX_EPOCH + Duration::from_millis(tick.time),
+                            is_buyer_maker: tick.is_buyer_maker,
+                        });
+                }
+                if self.day.is_none() && self.results.daily.is_empty() {
+                    self.start_day(data.header.commit_at);
+                }
+            }
+            upstair_type::Payload::BinanceAggTrade(trade) => {
+                self.market_mut(trade.symbol)
+                    .add_market_trade(simple_market::MarketTrade {
+                        price: trade.price,
+                        quantity: trade.qty,
+                        trade_at: SystemTime::UNIX_EPOCH + Duration::from_millis(trade.time),
+                        is_buyer_maker: trade.is_buyer_maker,
+                    });
+                if self.day.is_none() && self.results.daily.is_empty() {
+                    self.start_day(data.header.commit_at);
+                }
+            }
+            upstair_type::Payload::BinanceKline(kline) => {
+                // a candle as a sell at its low and a buy at its high, half of its volume each,
+                // in the order a rising or falling candle would have traded them
+                let trade_at = SystemTime::UNIX_EPOCH + Duration::from_millis(kline.close_time);
+                let low = simple_market::MarketTrade {
+                    price: kline.low,
+                    quantity: kline.volume / 2.0,
+                    trade_at,
+                    is_buyer_maker: true,
+                };
+                let high = simple_market::MarketTrade {
+                    price: kline.high,
+                    quantity: kline.volume / 2.0,
+                    trade_at,
+                    is_buyer_maker: false,
+                };
+                let trades = if kline.close >= kline.open {
+                    [low, high]
+                } else {
+                    [high, low]
+                };
+                let market = self.market_mut(kline.symbol);
+                for trade in trades {
+                    market.add_market_trade(trade);
+                }
+                if self.day.is_none() && self.results.daily.is_empty() {
+                    self.start_day(data.header.commit_at);
+                }
+            }
+            upstair_type::Payload::DayRoll(roll) => {
+                self.end_day();
+                self.start_day(roll.day_start);
+            }
+            upstair_type::Payload::BinanceBookTicker(ticker) => {
+                self.market_mut(ticker.symbol).update_book(
+                    (ticker.best_bid_price, ticker.best_bid_qty),
+                    (ticker.best_ask_price, ticker.best_ask_qty),
+                );
+            }
+            upstair_type::Payload::DataQuality(_) => {}
+            // the fills are matched on the trades, the mark price is for the strategies
+            upstair_type::Payload::MarkPrice(_) => {}
             _ => {
                 error!("ingest_market_data: data is not expected");
             }
         }
+        let symbol_market = data
+            .payload
+            .symbol()
+            .and_then(|symbol| Some((symbol, self.market_by_symbol.get(symbol)?)));
+        if let Some((symbol, market)) = symbol_market {
+            let time_ms = data
+                .header
+                .commit_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            self.markouts.on_mid(symbol, time_ms, market.mid_price());
+        }
     }
 
     fn ingest_order_request(
@@ -311,92 +871,70 @@ impl MarketAgent {
         trace!("{:?}", data.payload);
         match data.payload {
             upstair_type::Payload::OrderRequest(req) => {
-                if req.price <= 0.0 {
-                    error!("price must be positive");
-                    return;
-                }
                 let symbol = req.symbol;
-                let side = req.side.clone();
-                let client_order_id = req.client_order_id.clone();
-                let price = req.price;
-                match self.process_order_request(req, data.header) {
-                    Ok(_) => {
-                        comms.publish(
-                            &self.order_result_topic,
-                            upstair_type::Message {
-                                header: upstair_type::MessageHeader {
-                                    commit_at: comms.time(),
-                                },
-                                payload: upstair_type::Payload::OrderResult(
-                                    upstair_type::order::OrderResult {
-                                        symbol,
-                                        at: comms.time(),
-                                        client_order_id,
-                                        filled_quantity: 0.0,
-                                        price,
-                                        is_buy: side == upstair_type::order::TradeSide::Buy,
-                                        status: upstair_type::order::OrderStatus::New,
-                                    },
-                                ),
-                            },
-                        );
-                    }
-                    Err(_) => {
-                        comms.publish(
-                            &self.order_result_topic,
-                            upstair_type::Message {
-                                header: upstair_type::MessageHeader {
-                                    commit_at: comms.time(),
-                                },
-                                payload: upstair_type::Payload::OrderResult(
-                                    upstair_type::order::OrderResult {
-                                        symbol,
-                                        at: comms.time(),
-                                        client_order_id,
-                                        filled_quantity: 0.0,
-                                        price,
-                                        is_buy: side == upstair_type::order::TradeSide::Buy,
-                                        status: upstair_type::order::OrderStatus::Rejected,
-                                    },
-                                ),
-                            },
-                        );
-                        self.stats
-                            .on_event(format!("order_fail_{:?}_{}", side, symbol).as_str());
-                    }
-                }
+                let owner = req.owner;
+                let result = self.place_order(req, data.header, comms);
+                self.send_order_result(upstair_type::Payload::OrderResult(result), comms);
+                self.report_expired_orders(symbol, comms);
+                self.send_rate_limit_usage(symbol, owner, comms);
             }
             upstair_type::Payload::CancelOrderRequest(cancel_req) => {
                 let symbol = cancel_req.symbol;
-                let client_order_id = cancel_req.client_order_id.clone();
-
-                match self.process_cancel_order_request(cancel_req) {
-                    Ok(_) => {
-                        comms.publish(
-                            &self.order_result_topic,
-                            upstair_type::Message {
-                                header: upstair_type::MessageHeader {
-                                    commit_at: comms.time(),
-                                },
-                                payload: upstair_type::Payload::OrderResult(
-                                    upstair_type::order::OrderResult {
-                                        symbol,
-                                        at: comms.time(),
-                                        client_order_id,
-                                        status: upstair_type::order::OrderStatus::Canceled,
-                                        filled_quantity: 0.0,
-                                        price: 0.0,
-                                        is_buy: false,
-                                    },
-                                ),
-                            },
-                        );
-                    }
-                    Err(e) => {
-                        debug!("ingest_order_request: {}", e);
-                        self.stats.on_event("cancel_order_fail");
+                let requested_by = cancel_req.owner;
+                let payload = match self.cancel_order(cancel_req, comms) {
+                    Ok(result) => upstair_type::Payload::OrderResult(result),
+                    Err(reject) => upstair_type::Payload::CancelReject(reject),
+                };
+                self.send_order_result(payload, comms);
+                self.send_rate_limit_usage(symbol, requested_by, comms);
+            }
+            upstair_type::Payload::BatchOrderRequest(batch) => {
+                self.stats.on_event("batch_order");
+                let results = batch
+                    .orders
+                    .into_iter()
+                    .map(|req| self.place_order(req, data.header.clone(), comms))
+                    .collect();
+                self.send_order_result(
+                    upstair_type::Payload::OrderResultBatch(
+                        upstair_type::order::OrderResultBatch {
+                            symbol: batch.symbol,
+                            results,
+                            cancel_rejects: vec![],
+                            owner: batch.owner,
+                        },
+                    ),
+                    comms,
+                );
+                self.report_expired_orders(batch.symbol, comms);
+                self.send_rate_limit_usage(batch.symbol, batch.owner, comms);
+            }
+            upstair_type::Payload::BatchCancelRequest(batch) => {
+                self.stats.on_event("batch_cancel");
+                let mut results = vec![];
+                let mut cancel_rejects = vec![];
+                for cancel_req in batch.cancels {
+                    match self.cancel_order(cancel_req, comms) {
+                        Ok(result) => results.push(result),
+                        Err(reject) => cancel_rejects.push(reject),
                     }
                 }
+                self.send_order_result(
+                    upstair_type::Payload::OrderResultBatch(
+                        upstair_type::order::OrderResultBatch {
+                            symbol: batch.symbol,
+                            results,
+                            cancel_rejects,
+                            owner: batch.owner,
+                        },
+                    ),
+                    comms,
+                );
+                self.send_rate_limit_usage(batch.symbol, batch.owner, comms);
+            }
+            upstair_type::Payload::ResyncRequest(req) => {
+                self.stats.on_event("resync");
+                self.send_resync_snapshot(req.symbol, req.owner, comms);
             }
             _ => {
                 error!("ingest_market_data: data is not expected");
@@ -404,13 +942,132 @@ impl MarketAgent {
         }
     }
 
+    // places an order of the strategy, the New or Rejected result answering it
+    fn place_order(
+        &mut self,
+        req: upstair_type::order::OrderRequest,
+        header: upstair_type::MessageHeader,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) -> upstair_type::order::OrderResult {
+        let symbol = req.symbol;
+        let side = req.side.clone();
+        let client_order_id = req.client_order_id.clone();
+        let price = req.price;
+        let owner = req.owner;
+        let limited = self
+            .order_rate_limit
+            .as_mut()
+            .is_some_and(|limiter| !limiter.try_acquire(owner, comms.time()));
+        let processed = if limited {
+            self.stats.on_event("order_rate_limited");
+            Err(RejectReason::RateLimited)
+        } else {
+            self.process_order_request(req, header, comms.time())
+        };
+        let (status, reject_reason) = match processed {
+            Ok(_) => {
+                if let Some(owner) = owner {
+                    self.order_owners.insert(client_order_id.clone(), owner);
+                }
+                (upstair_type::order::OrderStatus::New, None)
+            }
+            Err(reason) => {
+                debug!("order {} rejected: {:?}", client_order_id, reason);
+                self.stats
+                    .on_event(format!("order_fail_{:?}_{}", side, symbol).as_str());
+                let status = match reason {
+                    RejectReason::RateLimited => upstair_type::order::OrderStatus::RateLimited,
+                    _ => upstair_type::order::OrderStatus::Rejected,
+                };
+                (status, Some(reason))
+            }
+        };
+        upstair_type::order::OrderResult {
+            symbol,
+            at: comms.time(),
+            client_order_id,
+            filled_quantity: 0.0,
+            price,
+            is_buy: side == upstair_type::order::TradeSide::Buy,
+            status,
+            seq: 0,
+            owner,
+            reject_reason,
+        }
+    }
+
+    // cancels an order of the strategy, the Canceled result or the reject answering it
+    fn cancel_order(
+        &mut self,
+        cancel_req: upstair_type::order::CancelOrderRequest,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) -> Result<upstair_type::order::OrderResult, upstair_type::order::CancelReject> {
+        let symbol = cancel_req.symbol;
+        let client_order_id = cancel_req.client_order_id.clone();
+        let requested_by = cancel_req.owner;
+        let limited = self
+            .cancel_rate_limit
+            .as_mut()
+            .is_some_and(|limiter| !limiter.try_acquire(requested_by, comms.time()));
+        let processed = if limited {
+            self.stats.on_event("cancel_rate_limited");
+            Err(CancelRejectReason::RateLimited)
+        } else {
+            self.process_cancel_order_request(cancel_req)
+        };
+        match processed {
+            Ok(owner) => Ok(upstair_type::order::OrderResult {
+                symbol,
+                at: comms.time(),
+                client_order_id,
+                status: upstair_type::order::OrderStatus::Canceled,
+                filled_quantity: 0.0,
+                price: 0.0,
+                is_buy: false,
+                seq: 0,
+                owner,
+                reject_reason: None,
+            }),
+            Err(reason) => {
+                debug!("cancel of {} rejected: {:?}", client_order_id, reason);
+                self.stats.on_event("cancel_order_fail");
+                Err(upstair_type::order::CancelReject {
+                    symbol,
+                    at: comms.time(),
+                    client_order_id,
+                    reason,
+                    owner: requested_by,
+                })
+            }
+        }
+    }
+
+    fn send_order_result(
+        &mut self,
+        payload: upstair_type::Payload,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) {
+        self.acks.send(
+            &self.order_result_topic,
+            upstair_type::Message {
+                header: upstair_type::MessageHeader {
+                    commit_at: comms.time(),
+                },
+                payload,
+            },
+            comms,
+            self.latency_model.as_mut(),
+        );
+    }
+
     fn process_order_request(
         &mut self,
         req: upstair_type::order::OrderRequest,
         header: upstair_type::MessageHeader,
-    ) -> anyhow::Result<()> {
+        now: SystemTime,
+    ) -> Result<(), RejectReason> {
         // update stats
-        self.stats.on_order_submiited(
+        stats_of(&mut self.stats, &mut self.owner_stats, req.owner).on_order_submiited(
             req.quantity,
             req.side == upstair_type::order::TradeSide::Buy,
         );
@@ -418,16 +1075,51 @@ impl MarketAgent {
         let symbol_info = self
             .symobl_info_manager
             .get(req.symbol)
-            .ok_or_else(|| anyhow::anyhow!("symbol {} is not supported", req.symbol))?;
+            .ok_or(RejectReason::UnknownSymbol)?;
+        let is_market = matches!(req.trade_type, upstair_type::order::TradeType::Market);
+        if !is_market && Self::locked_price(&req) <= 0.0 {
+            return Err(RejectReason::BadPrice);
+        }
+        if req.quantity <= 0.0 {
+            return Err(RejectReason::FilterViolation);
+        }
+        let expire_at = match req.time_in_force {
+            upstair_type::order::TimeInForce::GoodTilDate(at) => Some(at),
+            _ => None,
+        };
+        // the deadline is checked when the request reaches the exchange, not when it was sent
+        if expire_at.is_some_and(|at| at <= now) {
+            return Err(RejectReason::FilterViolation);
+        }
+        if matches!(req.trade_type, upstair_type::order::TradeType::LimitMaker)
+            && self
+                .market_by_symbol
+                .get(req.symbol)
+                .is_some_and(|market| market.would_take(&req.side, req.price))
+        {
+            self.stats.on_event("post_only_rejected");
+            return Err(RejectReason::PostOnlyCross);
+        }
+        // market orders take the book at once, the balance is locked at the fill price
+        let taker_price = match req.trade_type {
+            upstair_type::order::TradeType::Market => Some(
+                self.market_by_symbol
+                    .get(req.symbol)
+                    .and_then(|market| market.taker_fill_price(&req.side, req.quantity))
+                    .ok_or(RejectReason::NoBook)?,
+            ),
+            _ => None,
+        };
         // determine paying asset and amount
+        let locked_price = taker_price.unwrap_or_else(|| Self::locked_price(&req));
         let (pay_asset, pay_amt) = if req.side == upstair_type::order::TradeSide::Buy {
-            (symbol_info.quote_asset, req.price * req.quantity)
+            (symbol_info.quote_asset, locked_price * req.quantity)
         } else {
             (symbol_info.base_asset, req.quantity)
         };
-        let pay_asset_balance = self.account.get_or_create(pay_asset);
-        if !pay_asset_balance.try_lock_balance(pay_amt) {
-            return Err(anyhow::anyhow!("insufficient balance"));
+        let account = account_of(&mut self.account, &mut self.owner_accounts, req.owner);
+        if !account.get_or_create(pay_asset).try_lock_balance(pay_amt) {
+            return Err(RejectReason::InsufficientBalance);
         }
         trace!(
             "-----\n{:?} client_id={} price={} qty={}\n{}",
@@ -435,57 +1127,202 @@ impl MarketAgent {
             req.client_order_id,
             req.price,
             req.quantity,
-            account_brief(&self.account)
+            account_brief(account)
         );
         let market = self
             .market_by_symbol
             .get_mut(req.symbol)
-            .ok_or_else(|| anyhow::anyhow!("symbol {} has no market", req.symbol))?;
-        market.add_order(simple_market::LimitOrder {
+            .ok_or(RejectReason::UnknownSymbol)?;
+        let order = simple_market::LimitOrder {
             submit_at: header.commit_at,
             side: req.side,
             order_id: req.client_order_id,
-            price: req.price,
+            price: locked_price,
             quantity: req.quantity,
             filled: 0.0,
-        });
+            expire_at,
+            owner: req.owner,
+        };
+        let trigger = match req.trade_type {
+            upstair_type::order::TradeType::StopMarket { trigger_price } => {
+                Some((simple_market::TriggerKind::StopMarket, trigger_price))
+            }
+            upstair_type::order::TradeType::TakeProfit { trigger_price } => {
+                Some((simple_market::TriggerKind::TakeProfit, trigger_price))
+            }
+            _ => None,
+        };
+        if taker_price.is_some() {
+            self.stats.on_event("taker_fill");
+            market.add_taker_fill(order);
+            return Ok(());
+        }
+        match trigger {
+            Some((kind, trigger_price)) => market.add_trigger_order(simple_market::TriggerOrder {
+                kind,
+                trigger_price,
+                order,
+            }),
+            None if market.self_trade_prevention() == SelfTradePrevention::Reject
+                && market.would_self_trade(&order) =>
+            {
+                account_of(&mut self.account, &mut self.owner_accounts, req.owner)
+                    .get_or_create(pay_asset)
+                    .unlock_balance(pay_amt);
+                self.stats.on_event("self_trade_prevented");
+                return Err(RejectReason::SelfTrade);
+            }
+            None => market.add_order(order),
+        }
         Ok(())
     }
 
+    // unlock balance of orders removed by self trade prevention and report them expired
+    fn report_expired_orders(
+        &mut self,
+        symbol: &'static str,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) {
+        let Some(market) = self.market_by_symbol.get_mut(symbol) else {
+            return;
+        };
+        let expired: Vec<simple_market::LimitOrder> = market.expired_orders.drain(..).collect();
+        for order in expired {
+            self.stats.on_event("self_trade_prevented");
+            debug!(
+                "self trade prevented, expire {:?} order_id={} price={}",
+                order.side, order.order_id, order.price
+            );
+            self.send_expired(
+                symbol,
+                order,
+                upstair_type::order::OrderStatus::ExpiredInMatch,
+                comms,
+            );
+        }
+    }
+
+    fn expire_good_til_date_orders(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        let now = comms.time();
+        let expired: Vec<(&'static str, simple_market::LimitOrder)> = self
+            .market_by_symbol
+            .iter_mut()
+            .flat_map(|(symbol, market)| {
+                market
+                    .expire_orders(now)
+                    .into_iter()
+                    .map(|order| (*symbol, order))
+            })
+            .collect();
+        for (symbol, order) in expired {
+            self.stats.on_event("good_til_date_expired");
+            debug!(
+                "deadline passed, expire {:?} order_id={} price={}",
+                order.side, order.order_id, order.price
+            );
+            self.send_expired(
+                symbol,
+                order,
+                upstair_type::order::OrderStatus::Expired,
+                comms,
+            );
+        }
+    }
+
+    // unlock the balance of an order removed by the exchange and report it with status
+    fn send_expired(
+        &mut self,
+        symbol: &'static str,
+        order: simple_market::LimitOrder,
+        status: upstair_type::order::OrderStatus,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) {
+        let symbol_info = self.symobl_info_manager.get(symbol).unwrap_or_else(|| {
+            panic!("symbol {} is not supported", symbol);
+        });
+        let (locked_asset, locked_amt) = locked_balance(symbol_info, &order);
+        let owner = self.order_owners.remove(&order.order_id);
+        account_of(&mut self.account, &mut self.owner_accounts, owner)
+            .get_or_create(locked_asset)
+            .unlock_balance(locked_amt);
+        self.send_order_result(
+            upstair_type::Payload::OrderResult(upstair_type::order::OrderResult {
+                symbol,
+                at: comms.time(),
+                client_order_id: order.order_id,
+                filled_quantity: 0.0,
+                price: order.price,
+                is_buy: order.side == upstair_type::order::TradeSide::Buy,
+                status,
+                seq: 0,
+                owner,
+                reject_reason: None,
+            }),
+            comms,
+        );
+    }
+
+    // stop market orders have no price, the balance is locked at the trigger price
+    fn locked_price(req: &upstair_type::order::OrderRequest) -> f64 {
+        match req.trade_type {
+            upstair_type::order::TradeType::StopMarket { trigger_price } => trigger_price,
+            _ => req.price,
+        }
+    }
+
+    // the owner of the order canceled, or why it was not
     fn process_cancel_order_request(
         &mut self,
         cancel_req: upstair_type::order::CancelOrderRequest,
-    ) -> anyhow::Result<()> {
+    ) -> Result<Option<&'static str>, CancelRejectReason> {
         // update stats
-        self.stats.on_order_cancel();
+        stats_of(&mut self.stats, &mut self.owner_stats, cancel_req.owner).on_order_cancel();
 
         let symbol_info = self
             .symobl_info_manager
             .get(cancel_req.symbol)
-            .ok_or_else(|| anyhow::anyhow!("symbol {} is not supported", cancel_req.symbol))?;
+            .ok_or(CancelRejectReason::UnknownSymbol)?;
         let market = self
             .market_by_symbol
             .get_mut(cancel_req.symbol)
-            .ok_or_else(|| anyhow::anyhow!("symbol {} has no market", cancel_req.symbol))?;
+            .ok_or(CancelRejectReason::UnknownSymbol)?;
 
         // determine paying asset and amount
         let order = market.get_order(&cancel_req.client_order_id);
         if order.is_none() {
-            return Err(anyhow::anyhow!(
-                "order {} not found",
-                cancel_req.client_order_id
-            ));
+            let id = &cancel_req.client_order_id;
+            if self
+                .inflight_requests
+                .iter()
+                .any(|(_, _, msg)| match &msg.payload {
+                    upstair_type::Payload::OrderRequest(req) => req.client_order_id == *id,
+                    upstair_type::Payload::BatchOrderRequest(batch) => {
+                        batch.orders.iter().any(|req| req.client_order_id == *id)
+                    }
+                    _ => false,
+                })
+            {
+                self.stats.on_event("cancel_race_before_place");
+                return Err(CancelRejectReason::NotYetPlaced);
+            } else if self.recently_filled.contains(id) {
+                self.stats.on_event("cancel_race_lost_to_fill");
+                return Err(CancelRejectReason::AlreadyFilled);
+            }
+            return Err(CancelRejectReason::UnknownOrder);
         };
         let order = order.unwrap();
-        let (locked_asset, locked_amt) = if order.side == upstair_type::order::TradeSide::Buy {
-            (
-                symbol_info.quote_asset,
-                order.price * (order.quantity - order.filled),
-            )
-        } else {
-            (symbol_info.base_asset, order.quantity - order.filled)
-        };
-        self.account
+        // owners cancel their own orders only
+        if self.order_owners.get(&cancel_req.client_order_id).copied() != cancel_req.owner {
+            self.stats.on_event("cancel_not_owner");
+            return Err(CancelRejectReason::NotOwner);
+        }
+        if order.filled > 0.0 {
+            self.stats.on_event("cancel_race_partially_filled");
+        }
+        let (locked_asset, locked_amt) = locked_balance(symbol_info, order);
+        let owner = self.order_owners.remove(&cancel_req.client_order_id);
+        let account = account_of(&mut self.account, &mut self.owner_accounts, owner);
+        account
             .get_or_create(locked_asset)
             .unlock_balance(locked_amt);
         trace!(
@@ -495,14 +1332,102 @@ impl MarketAgent {
             order.price,
             order.quantity,
             order.filled,
-            account_brief(&self.account)
+            account_brief(account)
         );
 
         market.cancel_order(&cancel_req.client_order_id);
-        Ok(())
+        Ok(owner)
+    }
+
+    // the requests of owner in the rate limit windows, after each of its requests when the
+    // exchange limits them
+    fn send_rate_limit_usage(
+        &mut self,
+        symbol: &'static str,
+        owner: Option<&'static str>,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) {
+        if self.order_rate_limit.is_none() && self.cancel_rate_limit.is_none() {
+            return;
+        }
+        let now = comms.time();
+        let usage = |limiter: &mut Option<RateLimiter>| {
+            limiter
+                .as_mut()
+                .map(|limiter| (limiter.usage(owner, now), limiter.limit()))
+        };
+        let orders = usage(&mut self.order_rate_limit);
+        let cancels = usage(&mut self.cancel_rate_limit);
+        self.acks.send(
+            &self.order_result_topic,
+            upstair_type::Message {
+                header: upstair_type::MessageHeader { commit_at: now },
+                payload: upstair_type::Payload::RateLimitUsage(
+                    upstair_type::order::RateLimitUsage {
+                        symbol,
+                        at: now,
+                        orders,
+                        cancels,
+                        owner,
+                    },
+                ),
+            },
+            comms,
+            self.latency_model.as_mut(),
+        );
+    }
+
+    // open orders of symbol and all balances of owner, for a strategy which missed messages
+    fn send_resync_snapshot(
+        &mut self,
+        symbol: &'static str,
+        owner: Option<&'static str>,
+        comms: &mut dyn upstair_type::module::ModuleComms,
+    ) {
+        let open_orders = self
+            .market_by_symbol
+            .get(symbol)
+            .map(|market| {
+                market
+                    .orders()
+                    .filter(|order| self.order_owners.get(&order.order_id).copied() == owner)
+                    .map(|order| upstair_type::order::OpenOrder {
+                        client_order_id: order.order_id.clone(),
+                        price: order.price,
+                        quantity: order.quantity,
+                        filled: order.filled,
+                        is_buy: order.side == upstair_type::order::TradeSide::Buy,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.acks.send(
+            &self.order_result_topic,
+            upstair_type::Message {
+                header: upstair_type::MessageHeader {
+                    commit_at: comms.time(),
+                },
+                payload: upstair_type::Payload::ResyncSnapshot(
+                    upstair_type::order::ResyncSnapshot {
+                        symbol,
+                        seq: 0,
+                        open_orders,
+                        account: Self::make_account_update(
+                            account_of(&mut self.account, &mut self.owner_accounts, owner),
+                            owner,
+                        ),
+                    },
+                ),
+            },
+            comms,
+            self.latency_model.as_mut(),
+        );
     }
 
-    fn make_account_update(account: &Account) -> upstair_type::account::AccountUpdate {
+    fn make_account_update(
+        account: &Account,
+        owner: Option<&'static str>,
+    ) -> upstair_type::account::AccountUpdate {
         upstair_type::account::AccountUpdate {
             updates: account
                 .asset_to_balance
@@ -517,10 +1442,15 @@ impl MarketAgent {
                     )
                 })
                 .collect(),
+            symbol: None,
+            seq: 0,
+            owner,
         }
     }
     fn make_account_update_for_asset(
         account: &Account,
+        owner: Option<&'static str>,
+        symbol: &'static str,
         asset: &[&'static str],
     ) -> upstair_type::account::AccountUpdate {
         upstair_type::account::AccountUpdate {
@@ -543,10 +1473,23 @@ impl MarketAgent {
                     )
                 })
                 .collect(),
+            symbol: Some(symbol),
+            seq: 0,
+            owner,
         }
     }
 }
 
+// the first boundary of interval since the epoch after time
+fn next_snapshot_at(time: SystemTime, interval: Duration) -> SystemTime {
+    let interval_ms = interval.as_millis().max(1);
+    let time_ms = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    UNIX_EPOCH + Duration::from_millis(((time_ms / interval_ms + 1) * interval_ms) as u64)
+}
+
 #[derive(Default)]
 pub struct MarketAgentBuilder {
     market_data_topic: Option<ReadTopicHandle>,
@@ -556,6 +1499,15 @@ pub struct MarketAgentBuilder {
 
     symobl_info_manager: Option<SymbolInfoManager>,
     intial_balance: HashMap<String, f64>,
+    owner_initial_balance: BTreeMap<&'static str, HashMap<String, f64>>,
+    latency_model: Option<LatencyModel>,
+    results_dir: Option<PathBuf>,
+    self_trade_prevention: SelfTradePrevention,
+    slippage: SlippageModel,
+    fill_probability: Option<FillProbability>,
+    account_snapshot_interval: Option<Duration>,
+    order_rate_limit: Option<RateLimit>,
+    cancel_rate_limit: Option<RateLimit>,
 }
 
 impl MarketAgentBuilder {
@@ -565,14 +1517,76 @@ impl MarketAgentBuilder {
         self
     }
 
+    // add balance to the account of the orders of owner
+    pub fn with_owner_initial_balance(
+        mut self,
+        owner: &'static str,
+        asset: impl Into<String>,
+        balance: f64,
+    ) -> Self {
+        self.owner_initial_balance
+            .entry(owner)
+            .or_default()
+            .insert(asset.into(), balance);
+        self
+    }
+
     // set symbol info manager
     pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
         self.symobl_info_manager = Some(manager);
         self
     }
+
+    // delay order and cancel requests by latencies drawn from the model
+    pub fn with_latency_model(mut self, model: LatencyModel) -> Self {
+        self.latency_model = Some(model);
+        self
+    }
+
+    pub fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = mode;
+        self
+    }
+
+    // price taker fills (market and triggered stop market orders) away from the touch
+    pub fn with_slippage_model(mut self, slippage: SlippageModel) -> Self {
+        self.slippage = slippage;
+        self
+    }
+
+    // fill resting orders crossed by a trade with this probability
+    pub fn with_fill_probability(mut self, fill_probability: FillProbability) -> Self {
+        self.fill_probability = Some(fill_probability);
+        self
+    }
+
+    // send the account summaries every interval of engine time instead of every 1000 seconds,
+    // each followed by an equity snapshot of the account at the last trade prices
+    pub fn with_account_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.account_snapshot_interval = Some(interval);
+        self
+    }
+
+    // reject the order requests of an account over the limit, e.g. 300 in 10 seconds. Each
+    // request is answered by the usage of the limits as well
+    pub fn with_order_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.order_rate_limit = Some(limit);
+        self
+    }
+
+    // reject the cancel requests of an account over the limit, the orders rest
+    pub fn with_cancel_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.cancel_rate_limit = Some(limit);
+        self
+    }
 }
 
 impl ModuleBuilder for MarketAgentBuilder {
+    // writes fills, equity curve and stats of the run to the output
+    fn init_output(&mut self, output: &RunOutput) {
+        self.results_dir = Some(output.dir().to_path_buf());
+    }
+
     fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
         let market_data_topic = comms.get_topic("market_data");
         let order_topic = comms.get_topic("order");
@@ -589,6 +1603,10 @@ impl ModuleBuilder for MarketAgentBuilder {
         "market_agent"
     }
 
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::Exchange
+    }
+
     fn build(self: Box<Self>) -> Box<dyn Module> {
         Box::new(MarketAgent {
             market_data_topic: self.market_data_topic.unwrap(),
@@ -597,11 +1615,589 @@ impl ModuleBuilder for MarketAgentBuilder {
             account_topic: self.account_topic.unwrap(),
             market_by_symbol: std::collections::HashMap::new(),
             account: Account::default(),
+            owner_accounts: BTreeMap::new(),
+            order_owners: HashMap::new(),
             symobl_info_manager: self.symobl_info_manager.unwrap(),
             fee_account: Account::default(),
             stats: MarketStats::default(),
+            owner_stats: BTreeMap::new(),
+            markouts: MarkoutTracker::default(),
             initial_balance: self.intial_balance.into_iter().collect(),
+            owner_initial_balance: self
+                .owner_initial_balance
+                .into_iter()
+                .map(|(owner, balances)| (owner, balances.into_iter().collect()))
+                .collect(),
             last_account_summary_send_time: UNIX_EPOCH,
+            account_snapshot_interval: self.account_snapshot_interval,
+            next_account_snapshot_at: None,
+            latency_model: self.latency_model,
+            inflight_requests: VecDeque::new(),
+            acks: AckOutbox::default(),
+            recently_filled: VecDeque::new(),
+            results_dir: self.results_dir,
+            results: RunResults::default(),
+            day: None,
+            self_trade_prevention: self.self_trade_prevention,
+            slippage: self.slippage,
+            fill_probability: self.fill_probability,
+            order_rate_limit: self.order_rate_limit.map(RateLimiter::new),
+            cancel_rate_limit: self.cancel_rate_limit.map(RateLimiter::new),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use simulation::simulation::SimulationCommsSystem;
+    use upstair_type::{
+        data::market::{BinanceBookTicker, BinanceTradeTick},
+        module::{CommsSystem, ModuleComms, ModuleCommsBuilder},
+        order::{
+            BatchCancelRequest, BatchOrderRequest, CancelOrderRequest, OrderRequest, OrderResult,
+            OrderStatus, TimeInForce, TradeSide, TradeType,
+        },
+        Message, MessageHeader, Payload,
+    };
+
+    use super::*;
+    use crate::latency::LatencyProfile;
+
+    // a market agent driven by hand through the topics a strategy sees, BTCUSDT without fees
+    struct Harness {
+        system: SimulationCommsSystem,
+        agent: Box<dyn Module>,
+        agent_comms: Box<dyn ModuleComms>,
+        comms: Box<dyn ModuleComms>,
+        market_data: WriteTopicHandle,
+        order: WriteTopicHandle,
+        order_result: ReadTopicHandle,
+        account: ReadTopicHandle,
+    }
+
+    impl Harness {
+        fn new(builder: MarketAgentBuilder) -> Self {
+            let system = SimulationCommsSystem::default();
+            let mut builder = Box::new(builder.with_symbol_info_manager(
+                SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+            ));
+            let mut agent_comms = system.new_builder("market_agent");
+            builder.init_comm(&mut agent_comms);
+            let mut comms = system.new_builder("strategy");
+            let market_data = comms.get_topic("market_data");
+            let order = comms.get_topic("order");
+            let order_result = comms.get_topic("order_result");
+            let account = comms.get_topic("account");
+            let market_data = comms.publish_topic(&market_data);
+            let order = comms.publish_topic(&order);
+            let order_result = comms.subscribe_topic(&order_result);
+            let account = comms.subscribe_topic(&account);
+            let mut agent = builder.build();
+            agent.start();
+            Harness {
+                system,
+                agent,
+                agent_comms: agent_comms.build(),
+                comms: comms.build(),
+                market_data,
+                order,
+                order_result,
+                account,
+            }
+        }
+
+        fn at(&mut self, ms: u64) -> &mut Self {
+            self.system
+                .time_provider
+                .set_time(UNIX_EPOCH + Duration::from_millis(ms));
+            self
+        }
+
+        fn message(&self, payload: Payload) -> Message {
+            Message {
+                header: MessageHeader {
+                    commit_at: self.comms.time(),
+                },
+                payload,
+            }
+        }
+
+        fn step(&mut self) -> &mut Self {
+            self.agent.sync(self.agent_comms.as_mut());
+            self.agent.one_iteration(self.agent_comms.as_mut());
+            self
+        }
+
+        fn market_data(&mut self, payload: Payload) -> &mut Self {
+            let message = self.message(payload);
+            self.comms.publish(&self.market_data, message);
+            self.step()
+        }
+
+        fn book(&mut self, bid: f64, ask: f64) -> &mut Self {
+            let ticker = BinanceBookTicker {
+                best_bid_price: bid,
+                best_bid_qty: 1.0,
+                best_ask_price: ask,
+                best_ask_qty: 1.0,
+                symbol: "BTCUSDT",
+                ..Default::default()
+            };
+            self.market_data(Payload::BinanceBookTicker(ticker))
+        }
+
+        // a trade of a seller taking the bids, or of a buyer taking the asks
+        fn trade(&mut self, price: f64, qty: f64, is_buyer_maker: bool) -> &mut Self {
+            let tick = BinanceTradeTick {
+                price,
+                qty,
+                time: self
+                    .comms
+                    .time()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64,
+                is_buyer_maker,
+                symbol: "BTCUSDT",
+                ..Default::default()
+            };
+            self.market_data(Payload::BinanceTradeTick(tick))
+        }
+
+        fn send(&mut self, payload: Payload) -> &mut Self {
+            let message = self.message(payload);
+            self.comms.publish(&self.order, message);
+            self.step()
+        }
+
+        fn results(&mut self) -> Vec<Payload> {
+            std::iter::from_fn(|| self.comms.receive(&self.order_result))
+                .map(|msg| msg.payload)
+                .collect()
+        }
+
+        // the balances of the fills in the account updates, by owner
+        fn fill_updates(&mut self) -> Vec<(Option<&'static str>, &'static str, f64)> {
+            std::iter::from_fn(|| self.comms.receive(&self.account))
+                .filter_map(|msg| match msg.payload {
+                    Payload::AccountUpdate(update) if update.symbol.is_some() => Some(update),
+                    _ => None,
+                })
+                .flat_map(|update| {
+                    update
+                        .updates
+                        .into_iter()
+                        .map(move |(asset, balance)| (update.owner, asset, balance.balance))
+                })
+                .collect()
+        }
+    }
+
+    fn limit(
+        client_order_id: &str,
+        side: TradeSide,
+        price: f64,
+        quantity: f64,
+        owner: Option<&'static str>,
+    ) -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT",
+            side,
+            price,
+            quantity,
+            trade_type: TradeType::Limit,
+            time_in_force: TimeInForce::GoodTilCancelled,
+            client_order_id: Arc::from(client_order_id),
+            cancel_order_id: None,
+            owner,
+        }
+    }
+
+    fn cancel(client_order_id: &str, owner: Option<&'static str>) -> CancelOrderRequest {
+        CancelOrderRequest {
+            symbol: "BTCUSDT",
+            client_order_id: Arc::from(client_order_id),
+            owner,
+        }
+    }
+
+    fn order_result(payload: &Payload) -> &OrderResult {
+        match payload {
+            Payload::OrderResult(result) => result,
+            payload => panic!("not an order result: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn test_owners_trade_their_own_accounts() {
+        let mut harness = Harness::new(
+            MarketAgentBuilder::default()
+                .with_self_trade_prevention(SelfTradePrevention::Reject)
+                .with_owner_initial_balance("a", "USDT", 1000.0)
+                .with_owner_initial_balance("b", "USDT", 1000.0)
+                .with_owner_initial_balance("b", "BTC", 1.0),
+        );
+        harness.at(1).book(99.0, 101.0);
+
+        // b can not pay with the balance of a, the sell of b crossing the buy of a is no self
+        // trade
+        harness
+            .send(Payload::OrderRequest(limit(
+                "A1",
+                TradeSide::Buy,
+                100.0,
+                1.0,
+                Some("a"),
+            )))
+            .send(Payload::OrderRequest(limit(
+                "B1",
+                TradeSide::Buy,
+                100.0,
+                15.0,
+                Some("b"),
+            )))
+            .send(Payload::OrderRequest(limit(
+                "B2",
+                TradeSide::Sell,
+                100.0,
+                1.0,
+                Some("b"),
+            )));
+        let results = harness.results();
+        let statuses: Vec<_> = results
+            .iter()
+            .map(order_result)
+            .map(|r| {
+                (
+                    &*r.client_order_id,
+                    r.owner,
+                    r.status.clone(),
+                    r.reject_reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("A1", Some("a"), OrderStatus::New, None),
+                (
+                    "B1",
+                    Some("b"),
+                    OrderStatus::Rejected,
+                    Some(RejectReason::InsufficientBalance)
+                ),
+                ("B2", Some("b"), OrderStatus::New, None),
+            ]
+        );
+
+        // a seller takes the buy of a, only the account of a changes
+        harness.at(2).trade(99.0, 1.0, true);
+        let fill = harness.results();
+        assert_eq!(order_result(&fill[0]).owner, Some("a"));
+        assert_eq!(order_result(&fill[0]).status, OrderStatus::Filled);
+        let mut updates = harness.fill_updates();
+        updates.sort_by(|a, b| a.1.cmp(b.1));
+        assert_eq!(
+            updates,
+            vec![(Some("a"), "BTC", 1.0), (Some("a"), "USDT", 900.0)]
+        );
+
+        // a cancel of b answers b
+        harness.send(Payload::CancelOrderRequest(cancel("B2", Some("b"))));
+        let canceled = harness.results();
+        assert_eq!(order_result(&canceled[0]).owner, Some("b"));
+        assert_eq!(order_result(&canceled[0]).status, OrderStatus::Canceled);
+    }
+
+    #[test]
+    fn test_owners_cancel_their_own_orders() {
+        let mut harness = Harness::new(
+            MarketAgentBuilder::default()
+                .with_owner_initial_balance("a", "USDT", 1000.0)
+                .with_owner_initial_balance("b", "USDT", 1000.0),
+        );
+        harness.at(1).book(99.0, 101.0);
+        harness.send(Payload::OrderRequest(limit(
+            "A1",
+            TradeSide::Buy,
+            100.0,
+            1.0,
+            Some("a"),
+        )));
+        assert_eq!(order_result(&harness.results()[0]).status, OrderStatus::New);
+
+        // neither b nor the account without an owner take the order of a
+        for owner in [Some("b"), None] {
+            harness.send(Payload::CancelOrderRequest(cancel("A1", owner)));
+            let results = harness.results();
+            let Payload::CancelReject(reject) = &results[0] else {
+                panic!("not a cancel reject: {:?}", results[0]);
+            };
+            assert_eq!(
+                (reject.owner, reject.reason),
+                (owner, CancelRejectReason::NotOwner)
+            );
+        }
+        assert!(harness.fill_updates().is_empty());
+
+        // the order still rests for a
+        harness.send(Payload::CancelOrderRequest(cancel("A1", Some("a"))));
+        let canceled = harness.results();
+        assert_eq!(order_result(&canceled[0]).owner, Some("a"));
+        assert_eq!(order_result(&canceled[0]).status, OrderStatus::Canceled);
+    }
+
+    #[test]
+    fn test_batches_answered_in_order() {
+        let mut harness =
+            Harness::new(MarketAgentBuilder::default().with_initial_balance("USDT", 1000.0));
+        harness.at(1).book(99.0, 101.0);
+
+        // placed in order, the second one pays with the balance the first locked
+        harness.send(Payload::BatchOrderRequest(BatchOrderRequest {
+            symbol: "BTCUSDT",
+            orders: vec![
+                limit("B1", TradeSide::Buy, 98.0, 5.0, None),
+                limit("B2", TradeSide::Buy, 97.0, 6.0, None),
+                limit("B3", TradeSide::Buy, 96.0, 1.0, None),
+            ],
+            owner: None,
+        }));
+        let results = harness.results();
+        assert_eq!(results.len(), 1);
+        let Payload::OrderResultBatch(batch) = &results[0] else {
+            panic!("not a batch: {:?}", results[0]);
+        };
+        let answers: Vec<_> = batch
+            .results
+            .iter()
+            .map(|r| (&*r.client_order_id, r.status.clone(), r.seq))
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                ("B1", OrderStatus::New, 1),
+                ("B2", OrderStatus::Rejected, 2),
+                ("B3", OrderStatus::New, 3),
+            ]
+        );
+        assert!(batch.cancel_rejects.is_empty());
+
+        // the rejected cancels are listed apart from the results
+        harness.send(Payload::BatchCancelRequest(BatchCancelRequest {
+            symbol: "BTCUSDT",
+            cancels: vec![cancel("B1", None), cancel("B2", None), cancel("B3", None)],
+            owner: None,
+        }));
+        let results = harness.results();
+        assert_eq!(results.len(), 1);
+        let Payload::OrderResultBatch(batch) = &results[0] else {
+            panic!("not a batch: {:?}", results[0]);
+        };
+        let answers: Vec<_> = batch
+            .results
+            .iter()
+            .map(|r| (&*r.client_order_id, r.status.clone(), r.seq))
+            .collect();
+        assert_eq!(
+            answers,
+            vec![
+                ("B1", OrderStatus::Canceled, 4),
+                ("B3", OrderStatus::Canceled, 5),
+            ]
+        );
+        let rejects: Vec<_> = batch
+            .cancel_rejects
+            .iter()
+            .map(|r| (&*r.client_order_id, r.reason))
+            .collect();
+        assert_eq!(rejects, vec![("B2", CancelRejectReason::UnknownOrder)]);
+    }
+
+    fn cancel_reject(payload: &Payload) -> CancelRejectReason {
+        match payload {
+            Payload::CancelReject(reject) => reject.reason,
+            payload => panic!("not a cancel reject: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn test_cancel_rejects() {
+        let mut harness = Harness::new(
+            MarketAgentBuilder::default()
+                .with_initial_balance("USDT", 1000.0)
+                .with_latency_model(
+                    LatencyModel::new(LatencyProfile::from_samples(&[10.0]).unwrap(), 1)
+                        .with_cancel_profile(LatencyProfile::from_samples(&[0.0]).unwrap()),
+                ),
+        );
+        harness.at(1).book(99.0, 101.0);
+
+        // no order of the id
+        harness.send(Payload::CancelOrderRequest(cancel("X", None)));
+        assert_eq!(
+            cancel_reject(&harness.results()[0]),
+            CancelRejectReason::UnknownOrder
+        );
+
+        // the cancel overtakes the order it cancels
+        harness
+            .send(Payload::OrderRequest(limit(
+                "B1",
+                TradeSide::Buy,
+                100.0,
+                1.0,
+                None,
+            )))
+            .at(2)
+            .send(Payload::CancelOrderRequest(cancel("B1", None)));
+        assert_eq!(
+            cancel_reject(&harness.results()[0]),
+            CancelRejectReason::NotYetPlaced
+        );
+
+        // the order arrives and a seller fills it before the cancel
+        harness.at(11).step();
+        assert_eq!(order_result(&harness.results()[0]).status, OrderStatus::New);
+        harness.at(12).trade(99.0, 1.0, true);
+        assert_eq!(
+            order_result(&harness.results()[0]).status,
+            OrderStatus::Filled
+        );
+        harness.send(Payload::CancelOrderRequest(cancel("B1", None)));
+        assert_eq!(
+            cancel_reject(&harness.results()[0]),
+            CancelRejectReason::AlreadyFilled
+        );
+    }
+
+    #[test]
+    fn test_reject_reasons() {
+        let mut harness =
+            Harness::new(MarketAgentBuilder::default().with_initial_balance("USDT", 1000.0));
+        harness.at(1).book(99.0, 101.0);
+
+        // a post only buy at the best ask would take it
+        let mut maker = limit("M1", TradeSide::Buy, 101.0, 1.0, None);
+        maker.trade_type = TradeType::LimitMaker;
+        harness.send(Payload::OrderRequest(maker));
+        let results = harness.results();
+        assert_eq!(order_result(&results[0]).status, OrderStatus::Rejected);
+        assert_eq!(
+            order_result(&results[0]).reject_reason,
+            Some(RejectReason::PostOnlyCross)
+        );
+
+        // 11 at 98 is more than the 1000 USDT
+        harness.send(Payload::OrderRequest(limit(
+            "B1",
+            TradeSide::Buy,
+            98.0,
+            11.0,
+            None,
+        )));
+        let results = harness.results();
+        assert_eq!(order_result(&results[0]).status, OrderStatus::Rejected);
+        assert_eq!(
+            order_result(&results[0]).reject_reason,
+            Some(RejectReason::InsufficientBalance)
+        );
+
+        // below the ask the post only order rests
+        let mut maker = limit("M2", TradeSide::Buy, 100.0, 1.0, None);
+        maker.trade_type = TradeType::LimitMaker;
+        harness.send(Payload::OrderRequest(maker));
+        assert_eq!(order_result(&harness.results()[0]).status, OrderStatus::New);
+    }
+
+    #[test]
+    fn test_good_til_date_expiry() {
+        let mut harness =
+            Harness::new(MarketAgentBuilder::default().with_initial_balance("USDT", 1000.0));
+        harness.at(1).book(99.0, 101.0);
+
+        // all of the balance is locked by an order good until 50 ms
+        let mut order = limit("B1", TradeSide::Buy, 100.0, 10.0, None);
+        order.time_in_force = TimeInForce::GoodTilDate(UNIX_EPOCH + Duration::from_millis(50));
+        harness.send(Payload::OrderRequest(order));
+        assert_eq!(order_result(&harness.results()[0]).status, OrderStatus::New);
+        harness.at(49).step();
+        assert!(harness.results().is_empty());
+
+        // expired at its deadline, the balance it locked is free again
+        harness.at(50).step();
+        let results = harness.results();
+        assert_eq!(order_result(&results[0]).client_order_id.as_ref(), "B1");
+        assert_eq!(order_result(&results[0]).status, OrderStatus::Expired);
+        harness.send(Payload::OrderRequest(limit(
+            "B2",
+            TradeSide::Buy,
+            100.0,
+            10.0,
+            None,
+        )));
+        assert_eq!(order_result(&harness.results()[0]).status, OrderStatus::New);
+    }
+
+    #[test]
+    fn test_good_til_date_past_on_arrival() {
+        let mut harness = Harness::new(
+            MarketAgentBuilder::default()
+                .with_initial_balance("USDT", 1000.0)
+                .with_latency_model(LatencyModel::new(
+                    LatencyProfile::from_samples(&[10.0]).unwrap(),
+                    1,
+                )),
+        );
+        harness.at(1).book(99.0, 101.0);
+
+        // sent before its deadline, it reaches the exchange after it
+        let mut order = limit("B1", TradeSide::Buy, 100.0, 1.0, None);
+        order.time_in_force = TimeInForce::GoodTilDate(UNIX_EPOCH + Duration::from_millis(5));
+        harness.send(Payload::OrderRequest(order));
+        harness.at(11).step();
+        let results = harness.results();
+        assert_eq!(order_result(&results[0]).status, OrderStatus::Rejected);
+        assert_eq!(
+            order_result(&results[0]).reject_reason,
+            Some(RejectReason::FilterViolation)
+        );
+    }
+
+    #[test]
+    fn test_order_rate_limit() {
+        let mut harness = Harness::new(
+            MarketAgentBuilder::default()
+                .with_initial_balance("USDT", 1000.0)
+                .with_order_rate_limit("1/10ms".parse().unwrap()),
+        );
+        harness.at(1).book(99.0, 101.0);
+        let place = |harness: &mut Harness, id: &str| {
+            harness.send(Payload::OrderRequest(limit(
+                id,
+                TradeSide::Buy,
+                98.0,
+                1.0,
+                None,
+            )));
+            let results = harness.results();
+            let result = results
+                .iter()
+                .find(|payload| matches!(payload, Payload::OrderResult(_)))
+                .unwrap();
+            (
+                order_result(result).status.clone(),
+                order_result(result).reject_reason,
+            )
+        };
+
+        assert_eq!(place(&mut harness, "B1"), (OrderStatus::New, None));
+        // the second order of the window is rate limited, not rejected
+        assert_eq!(
+            place(&mut harness, "B2"),
+            (OrderStatus::RateLimited, Some(RejectReason::RateLimited))
+        );
+        harness.at(11);
+        assert_eq!(place(&mut harness, "B3"), (OrderStatus::New, None));
+    }
+}
@@ -1,4 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+// The PnL of the fills of the account split the usual market making way, in quote asset:
+// the spread captured against the mid at each fill, the move of the mid from the fills to the
+// last trade price over the inventory they leave, and the fees
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct PnlAttribution {
+    pub(crate) spread_capture: f64,
+    pub(crate) inventory_drift: f64,
+    pub(crate) fees: f64,
+}
+
+impl PnlAttribution {
+    pub(crate) fn total(&self) -> f64 {
+        self.spread_capture + self.inventory_drift - self.fees
+    }
+}
 
 #[derive(Default, Debug)]
 pub(crate) struct MarketStats {
@@ -10,6 +26,14 @@ pub(crate) struct MarketStats {
     total_filled_sell_quantity: f64,
     total_filled_buy_vol: f64,
     total_filled_sell_vol: f64,
+    // fills of orders sent to enforce hard risk limits
+    liquidation_fill_num: u64,
+    liquidation_vol: f64,
+    // the fills of the account attributed, see PnlAttribution
+    spread_capture: f64,
+    attributed_fees: f64,
+    // (base quantity bought less sold, the same weighted by the mid at the fills) per symbol
+    attributed_positions: BTreeMap<&'static str, (f64, f64)>,
 
     event_count: HashMap<String, u64>,
 }
@@ -38,6 +62,49 @@ impl MarketStats {
         }
     }
 
+    pub(crate) fn on_liquidation_fill(&mut self, vol: f64) {
+        self.liquidation_fill_num += 1;
+        self.liquidation_vol += vol;
+    }
+
+    // fee in quote asset
+    pub(crate) fn on_attributed_fill(
+        &mut self,
+        symbol: &'static str,
+        is_buy: bool,
+        price: f64,
+        quantity: f64,
+        mid: f64,
+        fee: f64,
+    ) {
+        let signed_quantity = if is_buy { quantity } else { -quantity };
+        self.spread_capture += (mid - price) * signed_quantity;
+        self.attributed_fees += fee;
+        let position = self.attributed_positions.entry(symbol).or_default();
+        position.0 += signed_quantity;
+        position.1 += signed_quantity * mid;
+    }
+
+    // the inventory is marked at the last trade price of its symbol, those without one are
+    // left out
+    pub(crate) fn pnl_attribution(
+        &self,
+        last_price: impl Fn(&'static str) -> Option<f64>,
+    ) -> PnlAttribution {
+        let inventory_drift = self
+            .attributed_positions
+            .iter()
+            .filter_map(|(symbol, (quantity, mid_value))| {
+                last_price(symbol).map(|price| quantity * price - mid_value)
+            })
+            .sum();
+        PnlAttribution {
+            spread_capture: self.spread_capture,
+            inventory_drift,
+            fees: self.attributed_fees,
+        }
+    }
+
     pub(crate) fn on_event(&mut self, event: &str) {
         let count = self.event_count.entry(event.to_string()).or_insert(0);
         *count += 1;
@@ -56,6 +123,7 @@ impl MarketStats {
             Order Sell Quantity: {:.5}\n\
             Filled Buy Quantity/Vol: {:.5}/{:.2}\n\
             Filled Sell Quantity/Vol: {:.5}/{:.2}\n\
+            Liquidation Fills/Vol: {}/{:.2}\n\
             {}",
             self.total_order_num,
             self.total_order_cancel_num,
@@ -65,10 +133,50 @@ impl MarketStats {
             self.total_filled_buy_vol,
             self.total_filled_sell_quantity,
             self.total_filled_sell_vol,
+            self.liquidation_fill_num,
+            self.liquidation_vol,
             event_summary
         )
     }
 
+    // numeric stats written to the results of a run, events are prefixed with event.
+    pub(crate) fn metrics(&self) -> Vec<(String, f64)> {
+        let mut metrics = vec![
+            ("order_num".to_string(), self.total_order_num as f64),
+            (
+                "order_cancel_num".to_string(),
+                self.total_order_cancel_num as f64,
+            ),
+            (
+                "order_buy_quantity".to_string(),
+                self.total_order_buy_quantity,
+            ),
+            (
+                "order_sell_quantity".to_string(),
+                self.total_order_sell_quantity,
+            ),
+            (
+                "filled_buy_quantity".to_string(),
+                self.total_filled_buy_quantity,
+            ),
+            ("filled_buy_vol".to_string(), self.total_filled_buy_vol),
+            (
+                "filled_sell_quantity".to_string(),
+                self.total_filled_sell_quantity,
+            ),
+            ("filled_sell_vol".to_string(), self.total_filled_sell_vol),
+            (
+                "liquidation_fill_num".to_string(),
+                self.liquidation_fill_num as f64,
+            ),
+            ("liquidation_vol".to_string(), self.liquidation_vol),
+        ];
+        for (event, count) in &self.event_count {
+            metrics.push((format!("event.{}", event), *count as f64));
+        }
+        metrics
+    }
+
     pub(crate) fn total_filled_sell_vol(&self) -> f64 {
         self.total_filled_sell_vol
     }
@@ -77,3 +185,26 @@ impl MarketStats {
         self.total_filled_buy_vol
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pnl_attribution() {
+        let mut stats = MarketStats::default();
+        // bought 2 at 99 with the mid at 100, sold 1 at 102 with the mid at 101
+        stats.on_attributed_fill("BTCUSDT", true, 99.0, 2.0, 100.0, 0.2);
+        stats.on_attributed_fill("BTCUSDT", false, 102.0, 1.0, 101.0, 0.1);
+        let attribution = stats.pnl_attribution(|_| Some(105.0));
+        assert_eq!(attribution.spread_capture, 3.0);
+        // 2 held from 100 and -1 from 101 marked at 105
+        assert_eq!(attribution.inventory_drift, 6.0);
+        assert!((attribution.fees - 0.3).abs() < 1e-12);
+        // the cash flows marked at the last price
+        let cash = -2.0 * 99.0 + 102.0 + 105.0 - 0.3;
+        assert!((attribution.total() - cash).abs() < 1e-9);
+
+        assert_eq!(stats.pnl_attribution(|_| None).inventory_drift, 0.0);
+    }
+}
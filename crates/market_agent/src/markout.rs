@@ -0,0 +1,179 @@
+use std::fmt::Write as _;
+
+// how long after a fill the mid is compared to its price
+const HORIZONS_MS: [u64; 3] = [1_000, 5_000, 30_000];
+// upper bounds of the distance of the fill price from the mid in bps, on the side of the
+// order, negative through the mid. The last bucket is unbounded.
+const DISTANCE_BOUNDS_BPS: [f64; 4] = [0.0, 1.0, 2.0, 5.0];
+const DISTANCE_LABELS: [&str; 5] = ["<0", "0-1", "1-2", "2-5", ">=5"];
+
+struct PendingFill {
+    symbol: &'static str,
+    time_ms: u64,
+    is_buy: bool,
+    price: f64,
+    bucket: usize,
+    // index of the next horizon to take the markout at
+    next_horizon: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct MarkoutSum {
+    sum: [f64; HORIZONS_MS.len()],
+    count: [u64; HORIZONS_MS.len()],
+}
+
+impl MarkoutSum {
+    fn add(&mut self, horizon: usize, markout: f64) {
+        self.sum[horizon] += markout;
+        self.count[horizon] += 1;
+    }
+
+    fn average(&self, horizon: usize) -> Option<f64> {
+        (self.count[horizon] > 0).then(|| self.sum[horizon] / self.count[horizon] as f64)
+    }
+}
+
+// How the mid moves after the fills, in bps of the fill price, positive when it moves in
+// favor of the fill. A market maker being picked off sees its fills marked out negative.
+// The mid at a horizon is the first one seen at or after it, fills whose horizon the run
+// does not reach are left out of it.
+#[derive(Default)]
+pub(crate) struct MarkoutTracker {
+    pending: Vec<PendingFill>,
+    // buy, sell
+    by_side: [MarkoutSum; 2],
+    by_distance: [MarkoutSum; DISTANCE_LABELS.len()],
+}
+
+fn side_sign(is_buy: bool) -> f64 {
+    if is_buy {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+impl MarkoutTracker {
+    pub(crate) fn on_fill(
+        &mut self,
+        symbol: &'static str,
+        time_ms: u64,
+        is_buy: bool,
+        price: f64,
+        mid: f64,
+    ) {
+        if mid <= 0.0 || price <= 0.0 {
+            return;
+        }
+        let distance_bps = side_sign(is_buy) * (mid - price) / mid * 10_000.0;
+        let bucket = DISTANCE_BOUNDS_BPS
+            .iter()
+            .position(|bound| distance_bps < *bound)
+            .unwrap_or(DISTANCE_BOUNDS_BPS.len());
+        self.pending.push(PendingFill {
+            symbol,
+            time_ms,
+            is_buy,
+            price,
+            bucket,
+            next_horizon: 0,
+        });
+    }
+
+    pub(crate) fn on_mid(&mut self, symbol: &'static str, time_ms: u64, mid: f64) {
+        if mid <= 0.0 {
+            return;
+        }
+        for fill in self.pending.iter_mut().filter(|f| f.symbol == symbol) {
+            while fill.next_horizon < HORIZONS_MS.len()
+                && time_ms >= fill.time_ms + HORIZONS_MS[fill.next_horizon]
+            {
+                let markout = side_sign(fill.is_buy) * (mid - fill.price) / fill.price * 10_000.0;
+                let side = if fill.is_buy { 0 } else { 1 };
+                self.by_side[side].add(fill.next_horizon, markout);
+                self.by_distance[fill.bucket].add(fill.next_horizon, markout);
+                fill.next_horizon += 1;
+            }
+        }
+        self.pending
+            .retain(|fill| fill.next_horizon < HORIZONS_MS.len());
+    }
+
+    fn rows(&self) -> impl Iterator<Item = (String, &MarkoutSum)> {
+        ["buy", "sell"]
+            .iter()
+            .map(|side| side.to_string())
+            .zip(&self.by_side)
+            .chain(
+                DISTANCE_LABELS
+                    .iter()
+                    .map(|label| format!("{label}bps"))
+                    .zip(&self.by_distance),
+            )
+    }
+
+    pub(crate) fn summary(&self) -> String {
+        let mut out = format!("{:<10}", "");
+        for horizon_ms in HORIZONS_MS {
+            let _ = write!(out, "{:>10}", format!("{}s", horizon_ms / 1000));
+        }
+        let _ = writeln!(out, "{:>10}", "fills");
+        for (label, sum) in self.rows() {
+            let _ = write!(out, "{:<10}", label);
+            for horizon in 0..HORIZONS_MS.len() {
+                match sum.average(horizon) {
+                    Some(average) => write!(out, "{:>10.3}", average),
+                    None => write!(out, "{:>10}", "-"),
+                }
+                .unwrap();
+            }
+            let _ = writeln!(out, "{:>10}", sum.count[0]);
+        }
+        out
+    }
+
+    // average markouts written to the results, e.g. markout.5s.sell or markout.5s.0-1bps
+    pub(crate) fn metrics(&self) -> Vec<(String, f64)> {
+        let mut metrics = vec![];
+        for (label, sum) in self.rows() {
+            for (horizon, horizon_ms) in HORIZONS_MS.iter().enumerate() {
+                if let Some(average) = sum.average(horizon) {
+                    metrics.push((format!("markout.{}s.{}", horizon_ms / 1000, label), average));
+                }
+            }
+        }
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markouts() {
+        let mut tracker = MarkoutTracker::default();
+        // a buy 1.5bps below the mid, the mid then falls through it and recovers
+        tracker.on_fill("BTCUSDT", 0, true, 10_000.0, 10_001.5);
+        // a sell through the mid
+        tracker.on_fill("BTCUSDT", 0, false, 10_000.0, 10_001.0);
+        tracker.on_mid("ETHUSDT", 2_000, 100.0);
+        tracker.on_mid("BTCUSDT", 500, 10_000.0);
+        assert!(tracker.metrics().is_empty());
+        // taken at 1s and 5s at once
+        tracker.on_mid("BTCUSDT", 6_000, 9_990.0);
+        tracker.on_mid("BTCUSDT", 40_000, 10_010.0);
+        assert!(tracker.pending.is_empty());
+
+        let metrics: std::collections::BTreeMap<String, f64> =
+            tracker.metrics().into_iter().collect();
+        assert!((metrics["markout.1s.buy"] + 10.0).abs() < 1e-9);
+        assert!((metrics["markout.5s.sell"] - 10.0).abs() < 1e-9);
+        assert!((metrics["markout.30s.buy"] - 10.0).abs() < 1e-9);
+        assert!((metrics["markout.30s.1-2bps"] - 10.0).abs() < 1e-9);
+        assert!((metrics["markout.30s.<0bps"] + 10.0).abs() < 1e-9);
+        assert!(!metrics.contains_key("markout.1s.0-1bps"));
+        assert!(tracker.summary().contains("sell"));
+    }
+}
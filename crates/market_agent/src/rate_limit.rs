@@ -0,0 +1,127 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+// At most count requests of an account in any window of engine time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub count: u32,
+    pub window: Duration,
+}
+
+// <count>/<window> with the window in ms, s or m, e.g. 300/10s
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid rate limit {s}, expected <count>/<window>, e.g. 300/10s");
+        let (count, window) = s.split_once('/').ok_or_else(invalid)?;
+        let count = count.trim().parse::<u32>().map_err(|_| invalid())?;
+        let window = window.trim().to_lowercase();
+        let (value, unit) = window
+            .find(|c: char| !c.is_ascii_digit())
+            .map(|i| window.split_at(i))
+            .ok_or_else(invalid)?;
+        let value = value.parse::<u64>().map_err(|_| invalid())?;
+        let window = match unit {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value * 60),
+            _ => return Err(invalid()),
+        };
+        if count == 0 || window.is_zero() {
+            return Err(invalid());
+        }
+        Ok(RateLimit { count, window })
+    }
+}
+
+// The requests each account made in the last window of its limit
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    requests: HashMap<Option<&'static str>, VecDeque<SystemTime>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            requests: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> u32 {
+        self.limit.count
+    }
+
+    // the requests of owner in the window up to now
+    pub(crate) fn usage(&mut self, owner: Option<&'static str>, now: SystemTime) -> u32 {
+        let requests = self.requests.entry(owner).or_default();
+        while requests
+            .front()
+            .is_some_and(|at| *at + self.limit.window <= now)
+        {
+            requests.pop_front();
+        }
+        requests.len() as u32
+    }
+
+    // counts a request of owner at now, false without counting it when the limit is used up
+    pub(crate) fn try_acquire(&mut self, owner: Option<&'static str>, now: SystemTime) -> bool {
+        if self.usage(owner, now) >= self.limit.count {
+            return false;
+        }
+        self.requests.entry(owner).or_default().push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "300/10s".parse::<RateLimit>(),
+            Ok(RateLimit {
+                count: 300,
+                window: Duration::from_secs(10),
+            })
+        );
+        assert_eq!(
+            "50/100MS".parse::<RateLimit>(),
+            Ok(RateLimit {
+                count: 50,
+                window: Duration::from_millis(100),
+            })
+        );
+        assert_eq!(
+            "1200/1m".parse::<RateLimit>().unwrap().window,
+            Duration::from_secs(60)
+        );
+        for s in ["300", "300/10", "0/1s", "300/0s", "x/1s", "300/10h"] {
+            assert!(s.parse::<RateLimit>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+        let mut limiter = RateLimiter::new("2/1s".parse().unwrap());
+        assert!(limiter.try_acquire(None, at(0)));
+        assert!(limiter.try_acquire(None, at(500)));
+        assert!(!limiter.try_acquire(None, at(900)));
+        // each account has its own window
+        assert!(limiter.try_acquire(Some("a"), at(900)));
+        assert_eq!(limiter.usage(None, at(900)), 2);
+        // the first request leaves the window
+        assert!(limiter.try_acquire(None, at(1000)));
+        assert_eq!(limiter.usage(None, at(1000)), 2);
+        assert_eq!(limiter.usage(None, at(2000)), 0);
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::BTreeMap, fmt::Write as _, path::Path};
+
+use anyhow::{bail, Context};
+
+const FILLS_FILE: &str = "fills.csv";
+const EQUITY_FILE: &str = "equity.csv";
+const STATS_FILE: &str = "stats.csv";
+const DAILY_FILE: &str = "daily.csv";
+
+// prices and quantities closer than this are the same
+const FILL_EPSILON: f64 = 1e-9;
+
+// One fill of the blotter, enough to attribute the PnL outside the simulator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub time_ms: u64,
+    pub order_id: String,
+    pub is_buy: bool,
+    pub price: f64,
+    pub quantity: f64,
+    pub fee: f64,
+    // what sent the order when not the strategy, e.g. liquidation, empty otherwise
+    pub tag: String,
+    pub symbol: String,
+    // filled resting on the book, rather than taking liquidity
+    pub is_maker: bool,
+    // base asset balance after the fill
+    pub inventory: f64,
+    // mid price of the book at the fill
+    pub mid: f64,
+}
+
+impl Fill {
+    fn matches(&self, other: &Fill) -> bool {
+        self.time_ms == other.time_ms
+            && self.order_id == other.order_id
+            && self.is_buy == other.is_buy
+            && (self.price - other.price).abs() <= FILL_EPSILON
+            && (self.quantity - other.quantity).abs() <= FILL_EPSILON
+    }
+}
+
+// One day of a run, cut at the day rolls of the market data
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyResult {
+    // midnight the day starts at, or the first market data of the run
+    pub day_start_ms: u64,
+    pub fills: u64,
+    // in quote asset
+    pub volume: f64,
+    pub fees: f64,
+    // at the end of the day, and its change over the day
+    pub equity: f64,
+    pub profit: f64,
+}
+
+// Results of one run, stored in a directory as fills.csv, equity.csv, daily.csv and stats.csv
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunResults {
+    pub fills: Vec<Fill>,
+    // (time_ms, equity in quote asset)
+    pub equity: Vec<(u64, f64)>,
+    pub daily: Vec<DailyResult>,
+    pub stats: BTreeMap<String, f64>,
+}
+
+fn read_csv(dir: &Path, file: &str) -> Result<Vec<Vec<String>>, anyhow::Error> {
+    let path = dir.join(file);
+    let s = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    // skip header
+    Ok(s.lines()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split(',').map(String::from).collect())
+        .collect())
+}
+
+fn parse_field<T: std::str::FromStr>(
+    row: &[String],
+    i: usize,
+    file: &str,
+    line: usize,
+) -> Result<T, anyhow::Error> {
+    let Some(value) = row.get(i).and_then(|v| v.parse().ok()) else {
+        bail!("{}:{}: invalid column {}", file, line + 2, i + 1);
+    };
+    Ok(value)
+}
+
+impl RunResults {
+    pub fn save(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let mut fills = String::from(
+            "time_ms,order_id,side,price,quantity,fee,tag,symbol,liquidity,inventory,mid\n",
+        );
+        for fill in &self.fills {
+            writeln!(
+                fills,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                fill.time_ms,
+                fill.order_id,
+                if fill.is_buy { "buy" } else { "sell" },
+                fill.price,
+                fill.quantity,
+                fill.fee,
+                fill.tag,
+                fill.symbol,
+                if fill.is_maker { "maker" } else { "taker" },
+                fill.inventory,
+                fill.mid
+            )?;
+        }
+        let mut equity = String::from("time_ms,equity\n");
+        for (time_ms, value) in &self.equity {
+            writeln!(equity, "{},{}", time_ms, value)?;
+        }
+        let mut daily = String::from("day_start_ms,fills,volume,fees,equity,profit\n");
+        for day in &self.daily {
+            writeln!(
+                daily,
+                "{},{},{},{},{},{}",
+                day.day_start_ms, day.fills, day.volume, day.fees, day.equity, day.profit
+            )?;
+        }
+        let mut stats = String::from("metric,value\n");
+        for (metric, value) in &self.stats {
+            writeln!(stats, "{},{}", metric, value)?;
+        }
+        for (file, content) in [
+            (FILLS_FILE, fills),
+            (EQUITY_FILE, equity),
+            (DAILY_FILE, daily),
+            (STATS_FILE, stats),
+        ] {
+            let path = dir.join(file);
+            std::fs::write(&path, content)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    // stats.csv is written last, so a directory holding it has a finished run
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(STATS_FILE).is_file()
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, anyhow::Error> {
+        let mut results = RunResults::default();
+        for (line, row) in read_csv(dir, FILLS_FILE)?.iter().enumerate() {
+            let is_buy = match row.get(2).map(String::as_str) {
+                Some("buy") => true,
+                Some("sell") => false,
+                _ => bail!("{}:{}: invalid side", FILLS_FILE, line + 2),
+            };
+            // results written before the ledger recorded the liquidity count as maker fills
+            let is_maker = match row.get(8).map(String::as_str) {
+                Some("maker") | None => true,
+                Some("taker") => false,
+                _ => bail!("{}:{}: invalid liquidity", FILLS_FILE, line + 2),
+            };
+            results.fills.push(Fill {
+                time_ms: parse_field(row, 0, FILLS_FILE, line)?,
+                order_id: parse_field(row, 1, FILLS_FILE, line)?,
+                is_buy,
+                price: parse_field(row, 3, FILLS_FILE, line)?,
+                quantity: parse_field(row, 4, FILLS_FILE, line)?,
+                fee: parse_field(row, 5, FILLS_FILE, line)?,
+                // results written before fills were tagged have no tag column
+                tag: row.get(6).cloned().unwrap_or_default(),
+                symbol: row.get(7).cloned().unwrap_or_default(),
+                is_maker,
+                inventory: match row.get(9) {
+                    Some(_) => parse_field(row, 9, FILLS_FILE, line)?,
+                    None => 0.0,
+                },
+                // results written before the mid was recorded have none
+                mid: match row.get(10) {
+                    Some(_) => parse_field(row, 10, FILLS_FILE, line)?,
+                    None => 0.0,
+                },
+            });
+        }
+        for (line, row) in read_csv(dir, EQUITY_FILE)?.iter().enumerate() {
+            results.equity.push((
+                parse_field(row, 0, EQUITY_FILE, line)?,
+                parse_field(row, 1, EQUITY_FILE, line)?,
+            ));
+        }
+        // results written before days were reported have no daily.csv
+        if dir.join(DAILY_FILE).is_file() {
+            for (line, row) in read_csv(dir, DAILY_FILE)?.iter().enumerate() {
+                results.daily.push(DailyResult {
+                    day_start_ms: parse_field(row, 0, DAILY_FILE, line)?,
+                    fills: parse_field(row, 1, DAILY_FILE, line)?,
+                    volume: parse_field(row, 2, DAILY_FILE, line)?,
+                    fees: parse_field(row, 3, DAILY_FILE, line)?,
+                    equity: parse_field(row, 4, DAILY_FILE, line)?,
+                    profit: parse_field(row, 5, DAILY_FILE, line)?,
+                });
+            }
+        }
+        for (line, row) in read_csv(dir, STATS_FILE)?.iter().enumerate() {
+            results.stats.insert(
+                parse_field(row, 0, STATS_FILE, line)?,
+                parse_field(row, 1, STATS_FILE, line)?,
+            );
+        }
+        Ok(results)
+    }
+
+    // largest fall of the equity curve from its running peak, in quote asset
+    pub fn max_drawdown(&self) -> f64 {
+        let mut peak = f64::MIN;
+        let mut max_drawdown = 0.0_f64;
+        for (_, equity) in &self.equity {
+            peak = peak.max(*equity);
+            max_drawdown = max_drawdown.max(peak - equity);
+        }
+        max_drawdown
+    }
+}
+
+// index of the first fill that differs, with the fills of both runs at that index
+pub fn first_diverging_fill<'a>(
+    a: &'a RunResults,
+    b: &'a RunResults,
+) -> Option<(usize, Option<&'a Fill>, Option<&'a Fill>)> {
+    let len = a.fills.len().max(b.fills.len());
+    (0..len).find_map(|i| {
+        let (fill_a, fill_b) = (a.fills.get(i), b.fills.get(i));
+        match (fill_a, fill_b) {
+            (Some(x), Some(y)) if x.matches(y) => None,
+            _ => Some((i, fill_a, fill_b)),
+        }
+    })
+}
+
+// (metric, value of a, value of b) of every metric in either run, in metric order
+pub fn stat_deltas(a: &RunResults, b: &RunResults) -> Vec<(String, Option<f64>, Option<f64>)> {
+    let mut metrics: Vec<&String> = a.stats.keys().chain(b.stats.keys()).collect();
+    metrics.sort();
+    metrics.dedup();
+    metrics
+        .into_iter()
+        .map(|metric| {
+            (
+                metric.clone(),
+                a.stats.get(metric).copied(),
+                b.stats.get(metric).copied(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(time_ms: u64, order_id: &str, price: f64) -> Fill {
+        Fill {
+            time_ms,
+            order_id: order_id.into(),
+            is_buy: true,
+            price,
+            quantity: 0.5,
+            fee: 0.01,
+            tag: String::new(),
+            symbol: "BTCUSDT".into(),
+            is_maker: true,
+            inventory: 1.5,
+            mid: price + 0.05,
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let dir = std::env::temp_dir().join(format!("results_test_{}", std::process::id()));
+        let results = RunResults {
+            fills: vec![
+                fill(1, "B0", 100.25),
+                fill(2, "S1", 101.0),
+                Fill {
+                    tag: "liquidation".into(),
+                    is_maker: false,
+                    ..fill(3, "L1", 99.5)
+                },
+            ],
+            equity: vec![(1, 1000.0), (2, 1000.5)],
+            daily: vec![DailyResult {
+                day_start_ms: 0,
+                fills: 3,
+                volume: 150.375,
+                fees: 0.25,
+                equity: 1000.5,
+                profit: 0.5,
+            }],
+            stats: BTreeMap::from([("fill_count".to_string(), 2.0)]),
+        };
+        results.save(&dir).unwrap();
+        let loaded = RunResults::load(&dir).unwrap();
+        assert_eq!(loaded, results);
+
+        // written before daily results and before the fills recorded symbol and liquidity
+        std::fs::remove_file(dir.join(DAILY_FILE)).unwrap();
+        std::fs::write(
+            dir.join(FILLS_FILE),
+            "time_ms,order_id,side,price,quantity,fee,tag\n1,B0,buy,100.25,0.5,0.01,\n",
+        )
+        .unwrap();
+        let loaded = RunResults::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(loaded.daily.is_empty());
+        assert!(loaded.fills[0].is_maker && loaded.fills[0].symbol.is_empty());
+    }
+
+    #[test]
+    fn test_first_diverging_fill() {
+        let a = RunResults {
+            fills: vec![fill(1, "B0", 100.0), fill(2, "B1", 99.0)],
+            ..Default::default()
+        };
+        let mut b = a.clone();
+        assert!(first_diverging_fill(&a, &b).is_none());
+
+        b.fills[1].price = 99.5;
+        let (i, fill_a, fill_b) = first_diverging_fill(&a, &b).unwrap();
+        assert_eq!(i, 1);
+        assert_eq!(fill_a.unwrap().price, 99.0);
+        assert_eq!(fill_b.unwrap().price, 99.5);
+
+        b.fills = vec![fill(1, "B0", 100.0)];
+        let (i, fill_a, fill_b) = first_diverging_fill(&a, &b).unwrap();
+        assert_eq!(i, 1);
+        assert!(fill_a.is_some() && fill_b.is_none());
+    }
+
+    #[test]
+    fn test_stat_deltas_and_drawdown() {
+        let a = RunResults {
+            equity: vec![(1, 100.0), (2, 120.0), (3, 90.0), (4, 130.0)],
+            stats: BTreeMap::from([("profit".to_string(), 1.0), ("fills".to_string(), 3.0)]),
+            ..Default::default()
+        };
+        let b = RunResults {
+            stats: BTreeMap::from([("profit".to_string(), 2.0), ("fees".to_string(), 0.5)]),
+            ..Default::default()
+        };
+        assert_eq!(
+            stat_deltas(&a, &b),
+            vec![
+                ("fees".to_string(), None, Some(0.5)),
+                ("fills".to_string(), Some(3.0), None),
+                ("profit".to_string(), Some(1.0), Some(2.0)),
+            ]
+        );
+        assert_eq!(a.max_drawdown(), 30.0);
+        assert_eq!(b.max_drawdown(), 0.0);
+    }
+}
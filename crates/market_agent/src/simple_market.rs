@@ -1,8 +1,19 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use tracing::warn;
 use upstair_type::order::TradeSide;
 
+use crate::fill_probability::FillProbability;
+use crate::slippage::{SlippageModel, TakerContext};
+
+// market volume of this window is the recent volume of the volume impact slippage
+const RECENT_VOLUME_WINDOW: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub(crate) struct LimitOrder {
     pub(crate) price: f64,
@@ -11,6 +22,72 @@ pub(crate) struct LimitOrder {
     pub(crate) submit_at: std::time::SystemTime,
     pub(crate) side: TradeSide,
     pub(crate) order_id: Arc<str>,
+    // the deadline of a good til date order
+    pub(crate) expire_at: Option<SystemTime>,
+    // the strategy the order was placed for, only its own orders are a self trade
+    pub(crate) owner: Option<&'static str>,
+}
+
+// What happens when a new order would match our own resting orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    // orders of the same account match each other
+    #[default]
+    None,
+    // the new order expires
+    CancelNewest,
+    // the resting orders it would match expire
+    CancelOldest,
+    // the new order is rejected on submission
+    Reject,
+}
+
+impl FromStr for SelfTradePrevention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "cancel-newest" => Ok(Self::CancelNewest),
+            "cancel-oldest" => Ok(Self::CancelOldest),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!(
+                "unknown self trade prevention {s}, expected none, cancel-newest, cancel-oldest or reject"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TriggerKind {
+    // sell when price falls to the trigger, buy when it rises to it
+    StopMarket,
+    // sell when price rises to the trigger, buy when it falls to it
+    TakeProfit,
+}
+
+// Order waiting for the last trade price to reach trigger_price.
+// A triggered stop market order fills at the triggering trade price,
+// a triggered take profit order rests as a limit order.
+#[derive(Debug)]
+pub(crate) struct TriggerOrder {
+    pub(crate) kind: TriggerKind,
+    pub(crate) trigger_price: f64,
+    pub(crate) order: LimitOrder,
+}
+
+impl TriggerOrder {
+    fn is_triggered_by(&self, price: f64) -> bool {
+        let on_rise = match self.kind {
+            TriggerKind::StopMarket => self.order.side == TradeSide::Buy,
+            TriggerKind::TakeProfit => self.order.side == TradeSide::Sell,
+        };
+        if on_rise {
+            price >= self.trigger_price
+        } else {
+            price <= self.trigger_price
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,8 +100,27 @@ pub(crate) struct MarketTrade {
 
 pub(crate) struct SimpleMarket {
     pub(crate) open_orders: Vec<LimitOrder>,
+    pub(crate) trigger_orders: Vec<TriggerOrder>,
+    // triggered stop market orders, taking the quantity of the trades from their trigger on
+    // until filled
+    pub(crate) triggered_orders: Vec<LimitOrder>,
+    // orders removed by self trade prevention, to be reported as expired
+    pub(crate) expired_orders: Vec<LimitOrder>,
+    self_trade_prevention: SelfTradePrevention,
     market_trade_buf: Vec<MarketTrade>,
     pub(crate) last_trade_price: f64,
+    slippage: SlippageModel,
+    // (price, quantity) of the last book ticker, 0 until one is received
+    best_bid: (f64, f64),
+    best_ask: (f64, f64),
+    // (trade_at, quantity) of the trades within RECENT_VOLUME_WINDOW
+    recent_trades: VecDeque<(SystemTime, f64)>,
+    recent_volume: f64,
+    // fills of taker orders, reported by the next try_match_market
+    taker_events: Vec<MarketEvent>,
+    // chance a trade crossing a resting order fills it, the trade passes it by otherwise.
+    // None fills every crossed order.
+    fill_probability: Option<FillProbability>,
 }
 
 #[derive(Debug)]
@@ -33,20 +129,134 @@ pub(crate) struct MarketEvent {
     pub(crate) price: f64,
     pub(crate) quantity: f64,
     pub(crate) reamin_qty_to_fill: f64,
+    // price the order's balance was locked at, differs from price for stop market fills
+    pub(crate) locked_price: f64,
     #[allow(dead_code)]
     pub(crate) event_at: std::time::SystemTime,
     pub(crate) order_id: Arc<str>,
+    // filled resting on the book, rather than taking liquidity
+    pub(crate) is_maker: bool,
 }
 
 impl SimpleMarket {
     pub(crate) fn new() -> Self {
         Self {
             open_orders: vec![],
+            trigger_orders: vec![],
+            triggered_orders: vec![],
+            expired_orders: vec![],
+            self_trade_prevention: SelfTradePrevention::default(),
             market_trade_buf: vec![],
             last_trade_price: 0.0,
+            slippage: SlippageModel::default(),
+            best_bid: (0.0, 0.0),
+            best_ask: (0.0, 0.0),
+            recent_trades: VecDeque::new(),
+            recent_volume: 0.0,
+            taker_events: vec![],
+            fill_probability: None,
+        }
+    }
+
+    pub(crate) fn with_slippage(mut self, slippage: SlippageModel) -> Self {
+        self.slippage = slippage;
+        self
+    }
+
+    pub(crate) fn with_fill_probability(mut self, fill_probability: FillProbability) -> Self {
+        self.fill_probability = Some(fill_probability);
+        self
+    }
+
+    pub(crate) fn update_book(&mut self, best_bid: (f64, f64), best_ask: (f64, f64)) {
+        self.best_bid = best_bid;
+        self.best_ask = best_ask;
+    }
+
+    // mid of the last book ticker, the last trade price until one is received
+    pub(crate) fn mid_price(&self) -> f64 {
+        if self.best_bid.0 > 0.0 && self.best_ask.0 > 0.0 {
+            (self.best_bid.0 + self.best_ask.0) / 2.0
+        } else {
+            self.last_trade_price
+        }
+    }
+
+    // touch_price is the price the taker fill would be at without slippage
+    fn taker_context(&self, side: &TradeSide, touch_price: f64) -> TakerContext {
+        let (_, touch_quantity) = match side {
+            TradeSide::Buy => self.best_ask,
+            TradeSide::Sell => self.best_bid,
+        };
+        TakerContext {
+            touch_price,
+            touch_quantity,
+            recent_volume: self.recent_volume,
+        }
+    }
+
+    // price a taker order fills at against the book, None until the book is known
+    pub(crate) fn taker_fill_price(&self, side: &TradeSide, quantity: f64) -> Option<f64> {
+        let (touch_price, _) = match side {
+            TradeSide::Buy => self.best_ask,
+            TradeSide::Sell => self.best_bid,
+        };
+        if touch_price <= 0.0 {
+            return None;
+        }
+        let ctx = self.taker_context(side, touch_price);
+        Some(self.slippage.fill_price(side, quantity, &ctx))
+    }
+
+    // an order of side at price would fill against the book at once, false until the book is
+    // known
+    pub(crate) fn would_take(&self, side: &TradeSide, price: f64) -> bool {
+        match side {
+            TradeSide::Buy => self.best_ask.0 > 0.0 && price >= self.best_ask.0,
+            TradeSide::Sell => self.best_bid.0 > 0.0 && price <= self.best_bid.0,
+        }
+    }
+
+    // fill order at its price in full, with a price from taker_fill_price
+    pub(crate) fn add_taker_fill(&mut self, order: LimitOrder) {
+        self.taker_events.push(MarketEvent {
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity - order.filled,
+            reamin_qty_to_fill: 0.0,
+            locked_price: order.price,
+            event_at: order.submit_at,
+            order_id: order.order_id,
+            is_maker: false,
+        });
+    }
+
+    pub(crate) fn with_self_trade_prevention(mut self, mode: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = mode;
+        self
+    }
+
+    pub(crate) fn self_trade_prevention(&self) -> SelfTradePrevention {
+        self.self_trade_prevention
+    }
+
+    // resting orders of the other side order would match
+    fn is_self_trade(order: &LimitOrder, resting: &LimitOrder) -> bool {
+        if order.owner != resting.owner {
+            return false;
+        }
+        match order.side {
+            TradeSide::Buy => resting.side == TradeSide::Sell && resting.price <= order.price,
+            TradeSide::Sell => resting.side == TradeSide::Buy && resting.price >= order.price,
         }
     }
 
+    pub(crate) fn would_self_trade(&self, order: &LimitOrder) -> bool {
+        self.open_orders
+            .iter()
+            .any(|resting| Self::is_self_trade(order, resting))
+    }
+
     pub(crate) fn add_order(&mut self, order: LimitOrder) {
         if order.quantity <= 0.0 {
             warn!("order rejected due to quantity <= 0.0 : {:?}", order);
@@ -57,6 +267,27 @@ impl SimpleMarket {
                 return;
             }
         }
+        match self.self_trade_prevention {
+            SelfTradePrevention::None => {}
+            // orders rejected on submission are checked by would_self_trade,
+            // triggered orders can only expire
+            SelfTradePrevention::CancelNewest | SelfTradePrevention::Reject => {
+                if self.would_self_trade(&order) {
+                    self.expired_orders.push(order);
+                    return;
+                }
+            }
+            SelfTradePrevention::CancelOldest => {
+                let mut i = 0;
+                while i < self.open_orders.len() {
+                    if Self::is_self_trade(&order, &self.open_orders[i]) {
+                        self.expired_orders.push(self.open_orders.remove(i));
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
         self.open_orders.push(order);
         self.open_orders.sort_by(|a, b| {
             if a.price == b.price {
@@ -67,31 +298,149 @@ impl SimpleMarket {
         });
     }
 
-    pub(crate) fn get_order(&self, order_id: &str) -> Option<&LimitOrder> {
+    pub(crate) fn add_trigger_order(&mut self, trigger_order: TriggerOrder) {
+        if trigger_order.order.quantity <= 0.0 {
+            warn!(
+                "order rejected due to quantity <= 0.0 : {:?}",
+                trigger_order
+            );
+            return;
+        }
+        if self.get_order(&trigger_order.order.order_id).is_some() {
+            return;
+        }
+        self.trigger_orders.push(trigger_order);
+    }
+
+    // open, waiting for trigger or triggered and not yet filled
+    pub(crate) fn orders(&self) -> impl Iterator<Item = &LimitOrder> {
         self.open_orders
             .iter()
-            .find(|o| o.order_id.as_ref() == order_id)
+            .chain(self.trigger_orders.iter().map(|t| &t.order))
+            .chain(self.triggered_orders.iter())
+    }
+
+    pub(crate) fn get_order(&self, order_id: &str) -> Option<&LimitOrder> {
+        self.orders().find(|o| o.order_id.as_ref() == order_id)
     }
 
     pub(crate) fn cancel_order(&mut self, order_id: &str) {
         self.open_orders.retain(|o| o.order_id.as_ref() != order_id);
+        self.trigger_orders
+            .retain(|t| t.order.order_id.as_ref() != order_id);
+        self.triggered_orders
+            .retain(|o| o.order_id.as_ref() != order_id);
+    }
+
+    // the earliest deadline of the good til date orders
+    pub(crate) fn next_expiry(&self) -> Option<SystemTime> {
+        self.orders().filter_map(|order| order.expire_at).min()
+    }
+
+    // remove the good til date orders whose deadline passed by now
+    pub(crate) fn expire_orders(&mut self, now: SystemTime) -> Vec<LimitOrder> {
+        let expires = |order: &LimitOrder| order.expire_at.is_some_and(|at| at <= now);
+        let mut expired = vec![];
+        let mut i = 0;
+        while i < self.open_orders.len() {
+            if expires(&self.open_orders[i]) {
+                expired.push(self.open_orders.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        let mut i = 0;
+        while i < self.trigger_orders.len() {
+            if expires(&self.trigger_orders[i].order) {
+                expired.push(self.trigger_orders.remove(i).order);
+            } else {
+                i += 1;
+            }
+        }
+        let mut i = 0;
+        while i < self.triggered_orders.len() {
+            if expires(&self.triggered_orders[i]) {
+                expired.push(self.triggered_orders.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        expired
+    }
+
+    // fill triggered stop market orders and open triggered take profit orders
+    fn fire_trigger_orders(&mut self, trade: &MarketTrade, events: &mut Vec<MarketEvent>) {
+        let mut i = 0;
+        while i < self.trigger_orders.len() {
+            if !self.trigger_orders[i].is_triggered_by(trade.price) {
+                i += 1;
+                continue;
+            }
+            let TriggerOrder { kind, order, .. } = self.trigger_orders.remove(i);
+            match kind {
+                TriggerKind::StopMarket => self.triggered_orders.push(order),
+                TriggerKind::TakeProfit => self.add_order(order),
+            }
+        }
+        // the market is no deeper than the trade, the rest is left to the trades that follow
+        let mut triggered = std::mem::take(&mut self.triggered_orders);
+        let mut remain_quantity = trade.quantity;
+        for order in triggered.iter_mut() {
+            if remain_quantity <= 0.0 {
+                break;
+            }
+            let fill_quantity = (order.quantity - order.filled).min(remain_quantity);
+            order.filled += fill_quantity;
+            remain_quantity -= fill_quantity;
+            events.push(MarketEvent {
+                price: self.slippage.fill_price(
+                    &order.side,
+                    fill_quantity,
+                    &self.taker_context(&order.side, trade.price),
+                ),
+                side: order.side.clone(),
+                quantity: fill_quantity,
+                reamin_qty_to_fill: order.quantity - order.filled,
+                locked_price: order.price,
+                event_at: trade.trade_at,
+                order_id: order.order_id.clone(),
+                is_maker: false,
+            });
+        }
+        triggered.retain(|o| o.filled < o.quantity);
+        self.triggered_orders = triggered;
     }
 
     pub(crate) fn add_market_trade(&mut self, trade: MarketTrade) {
         self.last_trade_price = trade.price;
+        self.recent_trades
+            .push_back((trade.trade_at, trade.quantity));
+        self.recent_volume += trade.quantity;
+        while let Some((trade_at, quantity)) = self.recent_trades.front() {
+            if *trade_at + RECENT_VOLUME_WINDOW > trade.trade_at {
+                break;
+            }
+            self.recent_volume -= quantity;
+            self.recent_trades.pop_front();
+        }
         self.market_trade_buf.push(trade);
     }
 
     pub(crate) fn try_match_market(&mut self) -> Vec<MarketEvent> {
-        let mut events = vec![];
-        for trade in self.market_trade_buf.drain(..) {
+        let mut events = std::mem::take(&mut self.taker_events);
+        // taken out so triggered orders can be added while matching, the buffer is kept
+        let mut trades = std::mem::take(&mut self.market_trade_buf);
+        let mut fill_probability = self.fill_probability.take();
+        let mut fills = || fill_probability.as_mut().is_none_or(FillProbability::fills);
+        for trade in trades.drain(..) {
+            self.fire_trigger_orders(&trade, &mut events);
             let mut remain_quantity = trade.quantity;
 
             if trade.is_buyer_maker {
                 // this is a active sell trade
                 // from order with highest price to lowest price
                 for order in self.open_orders.iter_mut().rev() {
-                    if order.side == TradeSide::Buy && order.price >= trade.price {
+                    if order.side == TradeSide::Buy && order.price >= trade.price && fills() {
                         let fill_quantity = (order.quantity - order.filled).min(remain_quantity);
                         order.filled += fill_quantity;
                         remain_quantity -= fill_quantity;
@@ -102,6 +451,8 @@ impl SimpleMarket {
                             order_id: order.order_id.clone(),
                             side: order.side.clone(),
                             reamin_qty_to_fill: order.quantity - order.filled,
+                            locked_price: order.price,
+                            is_maker: true,
                         });
                         if remain_quantity <= 0.0 {
                             break;
@@ -112,7 +463,7 @@ impl SimpleMarket {
                 // this is active buy trade
                 // from order with lowest price to highest price
                 for order in self.open_orders.iter_mut() {
-                    if order.side == TradeSide::Sell && order.price <= trade.price {
+                    if order.side == TradeSide::Sell && order.price <= trade.price && fills() {
                         let fill_quantity = (order.quantity - order.filled).min(remain_quantity);
                         order.filled += fill_quantity;
                         remain_quantity -= fill_quantity;
@@ -123,6 +474,8 @@ impl SimpleMarket {
                             order_id: order.order_id.clone(),
                             side: order.side.clone(),
                             reamin_qty_to_fill: order.quantity - order.filled,
+                            locked_price: order.price,
+                            is_maker: true,
                         });
                         if remain_quantity <= 0.0 {
                             break;
@@ -133,6 +486,8 @@ impl SimpleMarket {
             // remove filled order
             self.open_orders.retain(|o| o.filled < o.quantity);
         }
+        self.market_trade_buf = trades;
+        self.fill_probability = fill_probability;
         events
     }
 }
@@ -154,6 +509,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         let order_id: Arc<str> = Arc::from("B");
@@ -164,6 +521,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         assert_eq!(market.open_orders.len(), 2);
@@ -182,6 +541,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         let order = LimitOrder {
@@ -191,6 +552,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         assert_eq!(market.open_orders.len(), 1);
@@ -207,6 +570,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         market.cancel_order(&order_id);
@@ -237,6 +602,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         let trade = MarketTrade {
@@ -263,6 +630,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
 
@@ -274,6 +643,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
 
@@ -285,6 +656,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Sell,
             order_id: orde_id.clone(),
+            expire_at: None,
+            owner: None,
         };
 
         market.add_order(order);
@@ -316,6 +689,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         assert_eq!(market.open_orders.len(), 0);
@@ -332,6 +707,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         let order_id: Arc<str> = Arc::from("B");
@@ -342,6 +719,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         let order_id: Arc<str> = Arc::from("C");
@@ -352,6 +731,8 @@ mod tests {
             submit_at: std::time::SystemTime::now(),
             side: TradeSide::Buy,
             order_id: order_id.clone(),
+            expire_at: None,
+            owner: None,
         };
         market.add_order(order);
         assert_eq!(market.open_orders.len(), 3);
@@ -360,4 +741,358 @@ mod tests {
         assert_eq!(market.open_orders[2].price, 100.0);
         assert_eq!(market.open_orders[2].order_id.deref(), "B");
     }
+
+    #[test]
+    fn test_stop_market_order() {
+        let mut market = SimpleMarket::new();
+        let order_id: Arc<str> = Arc::from("S");
+        market.add_trigger_order(TriggerOrder {
+            kind: TriggerKind::StopMarket,
+            trigger_price: 95.0,
+            order: LimitOrder {
+                price: 95.0,
+                quantity: 2.0,
+                filled: 0.0,
+                submit_at: std::time::SystemTime::now(),
+                side: TradeSide::Sell,
+                order_id: order_id.clone(),
+                expire_at: None,
+                owner: None,
+            },
+        });
+        assert!(market.get_order(&order_id).is_some());
+
+        market.add_market_trade(MarketTrade {
+            price: 96.0,
+            quantity: 1.0,
+            trade_at: std::time::SystemTime::now(),
+            is_buyer_maker: true,
+        });
+        assert_eq!(market.try_match_market().len(), 0);
+
+        // gaps through the trigger, fills at the trade price
+        market.add_market_trade(MarketTrade {
+            price: 94.0,
+            quantity: 1.0,
+            trade_at: std::time::SystemTime::now(),
+            is_buyer_maker: true,
+        });
+        let events = market.try_match_market();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].price, 94.0);
+        assert_eq!(events[0].locked_price, 95.0);
+        // no more than the trade which triggered it
+        assert_eq!(events[0].quantity, 1.0);
+        assert_eq!(events[0].reamin_qty_to_fill, 1.0);
+        assert!(market.get_order(&order_id).is_some());
+
+        // the rest takes the trades that follow, back over the trigger or not
+        for (price, quantity) in [(96.0, 0.5), (97.0, 3.0)] {
+            market.add_market_trade(MarketTrade {
+                price,
+                quantity,
+                trade_at: std::time::SystemTime::now(),
+                is_buyer_maker: true,
+            });
+        }
+        let events = market.try_match_market();
+        let fills: Vec<_> = events
+            .iter()
+            .map(|e| (e.price, e.quantity, e.reamin_qty_to_fill))
+            .collect();
+        assert_eq!(fills, vec![(96.0, 0.5, 0.5), (97.0, 0.5, 0.0)]);
+        assert!(market.get_order(&order_id).is_none());
+    }
+
+    #[test]
+    fn test_would_take() {
+        let mut market = SimpleMarket::new();
+        assert!(!market.would_take(&TradeSide::Buy, 1000.0));
+        market.update_book((99.0, 5.0), (100.0, 5.0));
+        assert!(market.would_take(&TradeSide::Buy, 100.0));
+        assert!(!market.would_take(&TradeSide::Buy, 99.5));
+        assert!(market.would_take(&TradeSide::Sell, 99.0));
+        assert!(!market.would_take(&TradeSide::Sell, 99.5));
+    }
+
+    #[test]
+    fn test_taker_fill_with_slippage() {
+        let mut market = SimpleMarket::new().with_slippage(SlippageModel::FixedBps(10.0));
+        assert!(market.taker_fill_price(&TradeSide::Buy, 1.0).is_none());
+        market.update_book((99.0, 5.0), (100.0, 5.0));
+        let price = market.taker_fill_price(&TradeSide::Buy, 1.0).unwrap();
+        assert!((price - 100.1).abs() < 1e-9);
+        let price = market.taker_fill_price(&TradeSide::Sell, 1.0).unwrap();
+        assert!((price - 98.901).abs() < 1e-9);
+
+        market.add_taker_fill(LimitOrder {
+            price: 100.1,
+            quantity: 1.0,
+            filled: 0.0,
+            submit_at: std::time::SystemTime::now(),
+            side: TradeSide::Buy,
+            order_id: Arc::from("M"),
+            expire_at: None,
+            owner: None,
+        });
+        let events = market.try_match_market();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].price, 100.1);
+        assert_eq!(events[0].reamin_qty_to_fill, 0.0);
+        assert!(market.try_match_market().is_empty());
+
+        // triggered stop market orders slip from the trade price
+        market.add_trigger_order(TriggerOrder {
+            kind: TriggerKind::StopMarket,
+            trigger_price: 95.0,
+            order: LimitOrder {
+                price: 95.0,
+                quantity: 1.0,
+                filled: 0.0,
+                submit_at: std::time::SystemTime::now(),
+                side: TradeSide::Sell,
+                order_id: Arc::from("S"),
+                expire_at: None,
+                owner: None,
+            },
+        });
+        market.add_market_trade(MarketTrade {
+            price: 94.0,
+            quantity: 1.0,
+            trade_at: std::time::SystemTime::now(),
+            is_buyer_maker: true,
+        });
+        let events = market.try_match_market();
+        assert!((events[0].price - 93.906).abs() < 1e-9);
+        assert_eq!(events[0].locked_price, 95.0);
+    }
+
+    fn fill_probability(probability: f64, seed: u64) -> FillProbability {
+        FillProbability::new(probability, seed).unwrap()
+    }
+
+    #[test]
+    fn test_fill_probability() {
+        let fill_count = |market: SimpleMarket| {
+            let mut market = market;
+            (0..100)
+                .filter(|i| {
+                    market.add_order(LimitOrder {
+                        price: 100.0,
+                        quantity: 1.0,
+                        filled: 0.0,
+                        submit_at: std::time::SystemTime::now(),
+                        side: TradeSide::Buy,
+                        order_id: Arc::from(i.to_string()),
+                        expire_at: None,
+                        owner: None,
+                    });
+                    market.add_market_trade(MarketTrade {
+                        price: 99.0,
+                        quantity: 1.0,
+                        trade_at: std::time::SystemTime::now(),
+                        is_buyer_maker: true,
+                    });
+                    let filled = !market.try_match_market().is_empty();
+                    market.open_orders.clear();
+                    filled
+                })
+                .count()
+        };
+        assert_eq!(fill_count(SimpleMarket::new()), 100);
+        assert_eq!(
+            fill_count(SimpleMarket::new().with_fill_probability(fill_probability(1.0, 1))),
+            100
+        );
+        assert_eq!(
+            fill_count(SimpleMarket::new().with_fill_probability(fill_probability(0.0, 1))),
+            0
+        );
+        let half = fill_count(SimpleMarket::new().with_fill_probability(fill_probability(0.5, 1)));
+        assert!((30..70).contains(&half), "{half}");
+        // the same seed draws the same fills
+        assert_eq!(
+            fill_count(SimpleMarket::new().with_fill_probability(fill_probability(0.5, 1))),
+            half
+        );
+    }
+
+    #[test]
+    fn test_recent_volume_window() {
+        let mut market = SimpleMarket::new();
+        let start = std::time::SystemTime::UNIX_EPOCH;
+        for (secs, quantity) in [(0, 1.0), (30, 2.0), (61, 4.0)] {
+            market.add_market_trade(MarketTrade {
+                price: 100.0,
+                quantity,
+                trade_at: start + Duration::from_secs(secs),
+                is_buyer_maker: false,
+            });
+        }
+        assert_eq!(market.recent_volume, 6.0);
+        assert_eq!(market.recent_trades.len(), 2);
+    }
+
+    #[test]
+    fn test_take_profit_order() {
+        let mut market = SimpleMarket::new();
+        let order_id: Arc<str> = Arc::from("T");
+        market.add_trigger_order(TriggerOrder {
+            kind: TriggerKind::TakeProfit,
+            trigger_price: 105.0,
+            order: LimitOrder {
+                price: 104.0,
+                quantity: 2.0,
+                filled: 0.0,
+                submit_at: std::time::SystemTime::now(),
+                side: TradeSide::Sell,
+                order_id: order_id.clone(),
+                expire_at: None,
+                owner: None,
+            },
+        });
+        // a falling price does not trigger a sell take profit
+        market.add_market_trade(MarketTrade {
+            price: 90.0,
+            quantity: 1.0,
+            trade_at: std::time::SystemTime::now(),
+            is_buyer_maker: true,
+        });
+        assert_eq!(market.try_match_market().len(), 0);
+        assert_eq!(market.open_orders.len(), 0);
+
+        // triggered and filled as a limit order by the same trade
+        market.add_market_trade(MarketTrade {
+            price: 105.0,
+            quantity: 1.0,
+            trade_at: std::time::SystemTime::now(),
+            is_buyer_maker: false,
+        });
+        let events = market.try_match_market();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].price, 104.0);
+        assert_eq!(events[0].quantity, 1.0);
+        assert_eq!(market.trigger_orders.len(), 0);
+        assert_eq!(market.open_orders[0].filled, 1.0);
+
+        market.cancel_order(&order_id);
+        assert!(market.get_order(&order_id).is_none());
+    }
+
+    fn crossing_orders(mode: SelfTradePrevention) -> SimpleMarket {
+        let mut market = SimpleMarket::new().with_self_trade_prevention(mode);
+        for (order_id, side, price) in [
+            ("S0", TradeSide::Sell, 101.0),
+            ("S1", TradeSide::Sell, 103.0),
+            ("B0", TradeSide::Buy, 102.0),
+        ] {
+            market.add_order(LimitOrder {
+                price,
+                quantity: 1.0,
+                filled: 0.0,
+                submit_at: std::time::SystemTime::now(),
+                side,
+                order_id: Arc::from(order_id),
+                expire_at: None,
+                owner: None,
+            });
+        }
+        market
+    }
+
+    #[test]
+    fn test_self_trade_prevention() {
+        let market = crossing_orders(SelfTradePrevention::None);
+        assert_eq!(market.open_orders.len(), 3);
+        assert!(market.expired_orders.is_empty());
+
+        let market = crossing_orders(SelfTradePrevention::CancelNewest);
+        assert_eq!(market.open_orders.len(), 2);
+        assert_eq!(market.expired_orders.len(), 1);
+        assert_eq!(market.expired_orders[0].order_id.deref(), "B0");
+
+        let market = crossing_orders(SelfTradePrevention::CancelOldest);
+        assert_eq!(market.open_orders.len(), 2);
+        assert_eq!(market.expired_orders.len(), 1);
+        assert_eq!(market.expired_orders[0].order_id.deref(), "S0");
+        assert!(market.get_order("B0").is_some());
+        assert!(market.get_order("S1").is_some());
+
+        // orders added without the submission check expire like cancel newest
+        let market = crossing_orders(SelfTradePrevention::Reject);
+        assert_eq!(market.expired_orders.len(), 1);
+        let order = LimitOrder {
+            price: 100.0,
+            quantity: 1.0,
+            filled: 0.0,
+            submit_at: std::time::SystemTime::now(),
+            side: TradeSide::Buy,
+            order_id: Arc::from("B1"),
+            expire_at: None,
+            owner: None,
+        };
+        assert!(!market.would_self_trade(&order));
+        let order = LimitOrder {
+            price: 101.0,
+            ..order
+        };
+        assert!(market.would_self_trade(&order));
+        // the orders of another strategy trade with ours
+        let order = LimitOrder {
+            owner: Some("b"),
+            ..order
+        };
+        assert!(!market.would_self_trade(&order));
+
+        assert_eq!(
+            "cancel-oldest".parse::<SelfTradePrevention>(),
+            Ok(SelfTradePrevention::CancelOldest)
+        );
+        assert!("oldest".parse::<SelfTradePrevention>().is_err());
+    }
+
+    #[test]
+    fn test_expire_orders() {
+        let at = |ms| std::time::UNIX_EPOCH + Duration::from_millis(ms);
+        let mut market = SimpleMarket::new();
+        for (order_id, price, expire_at) in [("B0", 99.0, Some(at(100))), ("B1", 98.0, None)] {
+            market.add_order(LimitOrder {
+                price,
+                quantity: 1.0,
+                filled: 0.0,
+                submit_at: at(0),
+                side: TradeSide::Buy,
+                order_id: Arc::from(order_id),
+                expire_at,
+                owner: None,
+            });
+        }
+        market.add_trigger_order(TriggerOrder {
+            kind: TriggerKind::StopMarket,
+            trigger_price: 110.0,
+            order: LimitOrder {
+                price: 110.0,
+                quantity: 1.0,
+                filled: 0.0,
+                submit_at: at(0),
+                side: TradeSide::Buy,
+                order_id: Arc::from("T0"),
+                expire_at: Some(at(50)),
+                owner: None,
+            },
+        });
+        assert_eq!(market.next_expiry(), Some(at(50)));
+        assert!(market.expire_orders(at(49)).is_empty());
+
+        let expired = market.expire_orders(at(50));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order_id.deref(), "T0");
+        assert_eq!(market.next_expiry(), Some(at(100)));
+
+        let expired = market.expire_orders(at(200));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order_id.deref(), "B0");
+        assert_eq!(market.next_expiry(), None);
+        assert!(market.get_order("B1").is_some());
+    }
 }
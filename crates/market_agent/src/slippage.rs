@@ -0,0 +1,163 @@
+use std::str::FromStr;
+
+use upstair_type::order::TradeSide;
+
+// How far a taker fill executes from the touch price, see SlippageModel::fill_price
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SlippageModel {
+    // fills at the touch
+    #[default]
+    None,
+    // a constant cost in bps
+    FixedBps(f64),
+    // the displayed touch quantity fills at the touch, every further multiple of it fills one
+    // level of `bps_per_level` worse, the fill is at the average level
+    Depth {
+        bps_per_level: f64,
+    },
+    // `bps` times the square root of the quantity over the market volume of the last minute
+    VolumeImpact {
+        bps: f64,
+    },
+}
+
+// context of the market a taker order executes against
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TakerContext {
+    // best ask for buys, best bid for sells
+    pub(crate) touch_price: f64,
+    // quantity displayed at the touch, 0 when unknown
+    pub(crate) touch_quantity: f64,
+    pub(crate) recent_volume: f64,
+}
+
+impl SlippageModel {
+    fn slippage_bps(&self, quantity: f64, ctx: &TakerContext) -> f64 {
+        match *self {
+            SlippageModel::None => 0.0,
+            SlippageModel::FixedBps(bps) => bps,
+            SlippageModel::Depth { bps_per_level } => {
+                if ctx.touch_quantity <= 0.0 {
+                    return 0.0;
+                }
+                let levels = quantity / ctx.touch_quantity;
+                let full_levels = levels.floor();
+                // level k is filled for touch_quantity at k * bps_per_level, the rest at
+                // the next level
+                let level_sum =
+                    full_levels * (full_levels - 1.0) / 2.0 + (levels - full_levels) * full_levels;
+                bps_per_level * level_sum / levels
+            }
+            SlippageModel::VolumeImpact { bps } => {
+                if ctx.recent_volume <= 0.0 {
+                    return bps;
+                }
+                bps * (quantity / ctx.recent_volume).sqrt()
+            }
+        }
+    }
+
+    // price a taker order of quantity fills at, worse than the touch for either side
+    pub(crate) fn fill_price(&self, side: &TradeSide, quantity: f64, ctx: &TakerContext) -> f64 {
+        let slippage = self.slippage_bps(quantity, ctx) / 10000.0;
+        match side {
+            TradeSide::Buy => ctx.touch_price * (1.0 + slippage),
+            TradeSide::Sell => ctx.touch_price * (1.0 - slippage),
+        }
+    }
+}
+
+// none, fixed:<bps>, depth:<bps per level> or impact:<bps>
+impl FromStr for SlippageModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').unwrap_or((s, ""));
+        let bps = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|bps| *bps >= 0.0)
+                .ok_or_else(|| format!("invalid slippage {s}, expected {name}:<bps>"))
+        };
+        match name.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "fixed" => Ok(Self::FixedBps(bps()?)),
+            "depth" => Ok(Self::Depth {
+                bps_per_level: bps()?,
+            }),
+            "impact" => Ok(Self::VolumeImpact { bps: bps()? }),
+            _ => Err(format!(
+                "unknown slippage model {s}, expected none, fixed:<bps>, depth:<bps> or impact:<bps>"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(touch_quantity: f64, recent_volume: f64) -> TakerContext {
+        TakerContext {
+            touch_price: 100.0,
+            touch_quantity,
+            recent_volume,
+        }
+    }
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_fill_price() {
+        let buy = TradeSide::Buy;
+        assert_eq!(
+            SlippageModel::None.fill_price(&buy, 5.0, &ctx(1.0, 0.0)),
+            100.0
+        );
+        let fixed = SlippageModel::FixedBps(10.0);
+        assert_near(fixed.fill_price(&buy, 1.0, &ctx(1.0, 0.0)), 100.1);
+        assert_near(
+            fixed.fill_price(&TradeSide::Sell, 1.0, &ctx(1.0, 0.0)),
+            99.9,
+        );
+
+        let depth = SlippageModel::Depth {
+            bps_per_level: 10.0,
+        };
+        // within the touch quantity
+        assert_eq!(depth.fill_price(&buy, 0.5, &ctx(1.0, 0.0)), 100.0);
+        // half at the touch, half one level up
+        assert_near(depth.fill_price(&buy, 2.0, &ctx(1.0, 0.0)), 100.05);
+        // levels 0, 1, 2 and half of 3: (0 + 1 + 2 + 1.5) / 3.5 levels
+        assert_near(
+            depth.fill_price(&buy, 3.5, &ctx(1.0, 0.0)),
+            100.0 * (1.0 + 4.5 / 3.5 / 1000.0),
+        );
+        // touch quantity unknown
+        assert_eq!(depth.fill_price(&buy, 3.0, &ctx(0.0, 0.0)), 100.0);
+
+        let impact = SlippageModel::VolumeImpact { bps: 10.0 };
+        assert_near(impact.fill_price(&buy, 1.0, &ctx(0.0, 100.0)), 100.01);
+        assert_near(impact.fill_price(&buy, 1.0, &ctx(0.0, 0.0)), 100.1);
+    }
+
+    #[test]
+    fn test_parse_slippage_model() {
+        assert_eq!("none".parse(), Ok(SlippageModel::None));
+        assert_eq!("fixed:2.5".parse(), Ok(SlippageModel::FixedBps(2.5)));
+        assert_eq!(
+            "depth:1".parse(),
+            Ok(SlippageModel::Depth { bps_per_level: 1.0 })
+        );
+        assert_eq!(
+            "impact:20".parse(),
+            Ok(SlippageModel::VolumeImpact { bps: 20.0 })
+        );
+        assert!("fixed".parse::<SlippageModel>().is_err());
+        assert!("fixed:-1".parse::<SlippageModel>().is_err());
+        assert!("linear:1".parse::<SlippageModel>().is_err());
+    }
+}
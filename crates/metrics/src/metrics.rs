@@ -0,0 +1,523 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write as _,
+    net::SocketAddr,
+    ops::Add,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use symbol_info::SymbolInfoManager;
+use tracing::error;
+use upstair_type::{
+    module::{
+        and_filter, owner_filter, symbol_filter, Module, ModuleBuilder, ModuleComms,
+        ModulePriority, ReadTopicHandle,
+    },
+    order::OrderStatus,
+    signal::SignalKind,
+    Message, Payload,
+};
+use util::http::serve_get;
+
+// how often the served values are brought up to date, in the time of the engine
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// The counters and gauges of one symbol, rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct MetricsState {
+    base_asset: &'static str,
+    quote_asset: &'static str,
+    // engine time of the last update
+    time: Option<SystemTime>,
+    messages: BTreeMap<&'static str, u64>,
+    orders: u64,
+    cancels: u64,
+    rejects: u64,
+    // (count, quantity) of the buy and the sell fills
+    buy_fills: (u64, f64),
+    sell_fills: (u64, f64),
+    open_orders: HashSet<Arc<str>>,
+    balances: BTreeMap<&'static str, f64>,
+    last_price: Option<f64>,
+    // the first equity seen, the PnL is relative to it
+    initial_equity: Option<f64>,
+    halted: bool,
+    // the latest values on the signals topic
+    signals: BTreeMap<SignalKind, f64>,
+}
+
+impl MetricsState {
+    pub fn new(base_asset: &'static str, quote_asset: &'static str) -> Self {
+        MetricsState {
+            base_asset,
+            quote_asset,
+            ..Default::default()
+        }
+    }
+
+    pub fn on_message(&mut self, topic: &'static str, message: &Message) {
+        *self.messages.entry(topic).or_default() += 1;
+        match &message.payload {
+            Payload::BinanceTradeTick(_)
+            | Payload::TradeTickBatch(_)
+            | Payload::BinanceAggTrade(_)
+            | Payload::BinanceKline(_) => self.last_price = message.payload.trade_price(),
+            Payload::OrderRequest(_) => self.orders += 1,
+            Payload::CancelOrderRequest(_) => self.cancels += 1,
+            Payload::BatchOrderRequest(batch) => self.orders += batch.orders.len() as u64,
+            Payload::BatchCancelRequest(batch) => self.cancels += batch.cancels.len() as u64,
+            Payload::OrderResult(_) | Payload::OrderResultBatch(_) => {
+                for result in message.payload.order_results() {
+                    if result.filled_quantity > 0.0 {
+                        let fills = if result.is_buy {
+                            &mut self.buy_fills
+                        } else {
+                            &mut self.sell_fills
+                        };
+                        fills.0 += 1;
+                        fills.1 += result.filled_quantity;
+                    }
+                    match result.status {
+                        OrderStatus::New => {
+                            self.open_orders.insert(result.client_order_id.clone());
+                        }
+                        OrderStatus::PartiallyFilled => {}
+                        OrderStatus::Rejected | OrderStatus::RateLimited => {
+                            self.rejects += 1;
+                            self.open_orders.remove(&result.client_order_id);
+                        }
+                        OrderStatus::Filled
+                        | OrderStatus::Canceled
+                        | OrderStatus::Expired
+                        | OrderStatus::ExpiredInMatch => {
+                            self.open_orders.remove(&result.client_order_id);
+                        }
+                    }
+                }
+            }
+            Payload::AccountUpdate(update) => {
+                for (asset, balance) in update.updates.iter() {
+                    self.balances.insert(asset, balance.balance);
+                }
+            }
+            Payload::TradingHalt(_) => self.halted = true,
+            Payload::SignalUpdate(signal) => {
+                self.signals.insert(signal.kind, signal.value);
+            }
+            _ => {}
+        }
+        if self.initial_equity.is_none() {
+            self.initial_equity = self.equity();
+        }
+    }
+
+    // account value in quote asset, None until both balances and the price are known
+    fn equity(&self) -> Option<f64> {
+        let price = self.last_price?;
+        let base = self.balances.get(self.base_asset)?;
+        let quote = self.balances.get(self.quote_asset)?;
+        Some(base * price + quote)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "# HELP upstair_{name} {help}");
+            let _ = writeln!(out, "# TYPE upstair_{name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "upstair_{name}{labels} {value}");
+            }
+        };
+        let single = |value: Option<f64>| -> Vec<(String, f64)> {
+            value.map(|v| (String::new(), v)).into_iter().collect()
+        };
+        let by_side = |buy: f64, sell: f64| {
+            vec![
+                ("{side=\"buy\"}".to_string(), buy),
+                ("{side=\"sell\"}".to_string(), sell),
+            ]
+        };
+
+        let time = self.time.map(|t| {
+            t.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+        metric(
+            "time_seconds",
+            "gauge",
+            "Engine time of the last update",
+            &single(time),
+        );
+        let messages: Vec<_> = self
+            .messages
+            .iter()
+            .map(|(topic, count)| (format!("{{topic=\"{topic}\"}}"), *count as f64))
+            .collect();
+        metric(
+            "messages_total",
+            "counter",
+            "Messages received by topic",
+            &messages,
+        );
+        metric(
+            "orders_total",
+            "counter",
+            "Order requests",
+            &single(Some(self.orders as f64)),
+        );
+        metric(
+            "cancels_total",
+            "counter",
+            "Cancel requests",
+            &single(Some(self.cancels as f64)),
+        );
+        metric(
+            "rejects_total",
+            "counter",
+            "Rejected orders",
+            &single(Some(self.rejects as f64)),
+        );
+        metric(
+            "fills_total",
+            "counter",
+            "Fills by side",
+            &by_side(self.buy_fills.0 as f64, self.sell_fills.0 as f64),
+        );
+        metric(
+            "filled_quantity_total",
+            "counter",
+            "Filled base asset quantity by side",
+            &by_side(self.buy_fills.1, self.sell_fills.1),
+        );
+        metric(
+            "open_orders",
+            "gauge",
+            "Orders acknowledged and not ended",
+            &single(Some(self.open_orders.len() as f64)),
+        );
+        let balances: Vec<_> = self
+            .balances
+            .iter()
+            .map(|(asset, balance)| (format!("{{asset=\"{asset}\"}}"), *balance))
+            .collect();
+        metric("balance", "gauge", "Balance by asset", &balances);
+        metric(
+            "inventory",
+            "gauge",
+            "Base asset balance",
+            &single(self.balances.get(self.base_asset).copied()),
+        );
+        metric(
+            "last_price",
+            "gauge",
+            "Last trade price",
+            &single(self.last_price),
+        );
+        metric(
+            "equity",
+            "gauge",
+            "Account value in quote asset",
+            &single(self.equity()),
+        );
+        let pnl = self.equity().zip(self.initial_equity).map(|(e, i)| e - i);
+        metric(
+            "pnl",
+            "gauge",
+            "Equity change since the first update",
+            &single(pnl),
+        );
+        metric(
+            "trading_halted",
+            "gauge",
+            "1 once trading is halted",
+            &single(Some(self.halted as u8 as f64)),
+        );
+        let signals: Vec<_> = self
+            .signals
+            .iter()
+            .map(|(kind, value)| (format!("{{kind=\"{}\"}}", kind.as_str()), *value))
+            .collect();
+        metric(
+            "signal",
+            "gauge",
+            "Latest indicator value by kind",
+            &signals,
+        );
+        out
+    }
+}
+
+// Serves the metrics on /metrics of addr from a background thread. Returns the bound
+// address, port 0 picks a free one.
+pub fn serve(addr: SocketAddr, state: Arc<Mutex<MetricsState>>) -> std::io::Result<SocketAddr> {
+    serve_get("metrics", addr, move |target| match target {
+        "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            state.lock().unwrap().render(),
+        ),
+        _ => (
+            "404 Not Found",
+            "text/plain; version=0.0.4",
+            "metrics are on GET /metrics\n".to_string(),
+        ),
+    })
+}
+
+struct Metrics {
+    // (topic name, handle)
+    topics: Vec<(&'static str, ReadTopicHandle)>,
+    state: Arc<Mutex<MetricsState>>,
+    addr: SocketAddr,
+
+    wait_for_first_message: bool,
+    next_iteration_time: SystemTime,
+}
+
+impl Module for Metrics {
+    fn start(&mut self) {
+        match serve(self.addr, self.state.clone()) {
+            Ok(addr) => println!("Metrics at http://{}/metrics", addr),
+            Err(e) => error!("failed to serve the metrics on {}: {}", self.addr, e),
+        }
+    }
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        let mut state = self.state.lock().unwrap();
+        for (name, topic) in &self.topics {
+            while let Some(msg) = comms.receive_shared(topic) {
+                state.on_message(name, &msg);
+            }
+        }
+        state.time = Some(comms.time());
+        if self.wait_for_first_message {
+            self.wait_for_first_message = false;
+            self.next_iteration_time = comms.time().add(UPDATE_INTERVAL);
+            return false;
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        self.next_iteration_time = comms.time().add(UPDATE_INTERVAL);
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        if self.wait_for_first_message {
+            None
+        } else {
+            Some(self.next_iteration_time)
+        }
+    }
+
+    fn wake_on_message(&self) -> bool {
+        self.wait_for_first_message
+    }
+}
+
+// Exposes message rates, orders, fills, PnL and inventory of a symbol to Prometheus. Meant
+// for realtime runs, in a simulation the counters move at the speed of the replay.
+pub struct MetricsBuilder {
+    topics: Vec<(&'static str, ReadTopicHandle)>,
+
+    symbol: &'static str,
+    symbol_info_manager: Option<SymbolInfoManager>,
+    addr: SocketAddr,
+}
+
+impl MetricsBuilder {
+    pub fn new(symbol: &'static str, addr: SocketAddr) -> Self {
+        MetricsBuilder {
+            topics: vec![],
+            symbol,
+            symbol_info_manager: None,
+            addr,
+        }
+    }
+
+    pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
+        self.symbol_info_manager = Some(manager);
+        self
+    }
+}
+
+impl ModuleBuilder for MetricsBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        for name in [
+            "market_data",
+            "order",
+            "order_result",
+            "account",
+            "control",
+            "signals",
+        ] {
+            let topic = comms.get_topic(name);
+            // only the messages about our symbol and the account wide ones, of the account
+            // without an owner
+            let handle = comms.subscribe_topic_filtered(
+                &topic,
+                and_filter(symbol_filter(self.symbol), owner_filter(None)),
+            );
+            self.topics.push((name, handle));
+        }
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        let symbol_info_manager = self.symbol_info_manager.unwrap();
+        let symbol_info = symbol_info_manager
+            .get(self.symbol)
+            .expect("symbol in symbol info manager");
+        Box::new(Metrics {
+            topics: self.topics,
+            state: Arc::new(Mutex::new(MetricsState::new(
+                symbol_info.base_asset,
+                symbol_info.quote_asset,
+            ))),
+            addr: self.addr,
+            wait_for_first_message: true,
+            next_iteration_time: UNIX_EPOCH,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::Observer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use upstair_type::{
+        account::{AccountAssetUpdate, AccountUpdate},
+        data::market::BinanceTradeTick,
+        order::OrderResult,
+        signal::SignalUpdate,
+        MessageHeader,
+    };
+
+    use super::*;
+
+    fn message(payload: Payload) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: UNIX_EPOCH,
+            },
+            payload,
+        }
+    }
+
+    fn order_result(order_id: &str, filled_quantity: f64, status: OrderStatus) -> Message {
+        message(Payload::OrderResult(OrderResult {
+            symbol: "BTCUSDT",
+            at: UNIX_EPOCH,
+            client_order_id: Arc::from(order_id),
+            filled_quantity,
+            price: 100.0,
+            is_buy: true,
+            status,
+            seq: 0,
+            owner: None,
+            reject_reason: None,
+        }))
+    }
+
+    fn trade(price: f64) -> Message {
+        message(Payload::BinanceTradeTick(BinanceTradeTick {
+            id: 0,
+            price,
+            qty: 1.0,
+            base_qty: price,
+            time: 0,
+            is_buyer_maker: false,
+            symbol: "BTCUSDT",
+        }))
+    }
+
+    fn get(addr: SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut state = MetricsState::new("BTC", "USDT");
+        state.on_message(
+            "account",
+            &message(Payload::AccountUpdate(AccountUpdate {
+                updates: vec![
+                    (
+                        "BTC",
+                        AccountAssetUpdate {
+                            balance: 1.0,
+                            locked: 0.0,
+                        },
+                    ),
+                    (
+                        "USDT",
+                        AccountAssetUpdate {
+                            balance: 1000.0,
+                            locked: 0.0,
+                        },
+                    ),
+                ],
+                symbol: None,
+                seq: 0,
+                owner: None,
+            })),
+        );
+        state.on_message("market_data", &trade(100.0));
+        for result in [
+            order_result("B0", 0.0, OrderStatus::New),
+            order_result("B1", 0.0, OrderStatus::New),
+            order_result("B0", 0.4, OrderStatus::PartiallyFilled),
+            order_result("B0", 0.6, OrderStatus::Filled),
+            order_result("B2", 0.0, OrderStatus::Rejected),
+        ] {
+            state.on_message("order_result", &result);
+        }
+        state.on_message("market_data", &trade(110.0));
+        state.on_message(
+            "signals",
+            &message(Payload::SignalUpdate(SignalUpdate {
+                symbol: "BTCUSDT",
+                kind: SignalKind::Imbalance,
+                value: 0.25,
+            })),
+        );
+
+        let text = state.render();
+        assert!(text.contains("upstair_messages_total{topic=\"order_result\"} 5\n"));
+        assert!(text.contains("upstair_fills_total{side=\"buy\"} 2\n"));
+        assert!(text.contains("upstair_filled_quantity_total{side=\"buy\"} 1\n"));
+        assert!(text.contains("upstair_open_orders 1\n"));
+        assert!(text.contains("upstair_rejects_total 1\n"));
+        assert!(text.contains("upstair_inventory 1\n"));
+        // the fills are not in the balances until the account update
+        assert!(text.contains("upstair_pnl 10\n"));
+        assert!(text.contains("upstair_signal{kind=\"imbalance\"} 0.25\n"));
+        // no update yet
+        assert!(!text.contains("upstair_time_seconds"));
+
+        let addr = serve("127.0.0.1:0".parse().unwrap(), Arc::new(Mutex::new(state))).unwrap();
+        let response = get(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("# TYPE upstair_equity gauge\nupstair_equity 1110\n"));
+        assert!(get(addr, "/").starts_with("HTTP/1.1 404"));
+    }
+}
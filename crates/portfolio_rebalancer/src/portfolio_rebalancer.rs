@@ -0,0 +1,515 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use account::account::Account;
+use symbol_info::SymbolInfoManager;
+use tracing::{debug, warn};
+use upstair_type::{
+    account::AccountUpdate,
+    module::{
+        owner_filter, MessageFilter, Module, ModuleBuilder, ModuleComms, ReadTopicHandle,
+        WriteTopicHandle,
+    },
+    order::{
+        CancelOrderRequest, OrderRequest, OrderResult, OrderStatus, TimeInForce, TradeSide,
+        TradeType,
+    },
+    Message, MessageHeader, Payload,
+};
+
+// dust left over after a rebalance is not worth an order
+const MIN_ORDER_QUANTITY: f64 = 1e-6;
+
+// A limit order trading a symbol back to its target weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rebalance {
+    pub symbol: &'static str,
+    pub side: TradeSide,
+    pub price: f64,
+    pub quantity: f64,
+}
+
+// Tracks the balances of a portfolio against the weights of its equity each symbol is held at,
+// the rest of the equity stays in the quote asset the symbols share
+#[derive(Debug)]
+pub struct Allocation {
+    // (symbol, base asset, weight)
+    targets: Vec<(&'static str, &'static str, f64)>,
+    quote_asset: &'static str,
+    // fraction of the equity a symbol may drift from its target before it is traded back
+    band: f64,
+    account: Account,
+    // (bid, ask) of the last book ticker of each symbol
+    books: HashMap<&'static str, (f64, f64)>,
+    // the symbols without book tickers are priced at their last trade
+    last_prices: HashMap<&'static str, f64>,
+}
+
+impl Allocation {
+    pub fn new(
+        targets: &[(&'static str, f64)],
+        symbol_info_manager: &SymbolInfoManager,
+        band: f64,
+    ) -> Self {
+        let mut quote_asset = None;
+        let targets: Vec<_> = targets
+            .iter()
+            .map(|(symbol, weight)| {
+                let symbol_info = symbol_info_manager
+                    .get(symbol)
+                    .unwrap_or_else(|| panic!("symbol {} is not supported", symbol));
+                assert!(
+                    quote_asset.is_none_or(|asset| asset == symbol_info.quote_asset),
+                    "the symbols of a portfolio share their quote asset"
+                );
+                assert!(*weight >= 0.0, "weight of {} is negative", symbol);
+                quote_asset = Some(symbol_info.quote_asset);
+                (*symbol, symbol_info.base_asset, *weight)
+            })
+            .collect();
+        assert!(
+            targets.iter().map(|(_, _, weight)| weight).sum::<f64>() <= 1.0 + 1e-9,
+            "the weights add up to more than 1"
+        );
+        Allocation {
+            targets,
+            quote_asset: quote_asset.expect("a portfolio holds at least one symbol"),
+            band,
+            account: Account::default(),
+            books: HashMap::new(),
+            last_prices: HashMap::new(),
+        }
+    }
+
+    pub fn on_account_update(&mut self, update: &AccountUpdate) {
+        for (asset, updated_balance) in update.updates.iter() {
+            let entry = self.account.get_or_create(asset);
+            entry.balance = updated_balance.balance;
+            entry.locked = updated_balance.locked;
+        }
+    }
+
+    pub fn on_book(&mut self, symbol: &'static str, bid: f64, ask: f64) {
+        self.books.insert(symbol, (bid, ask));
+    }
+
+    pub fn on_trade_price(&mut self, symbol: &'static str, price: f64) {
+        self.last_prices.insert(symbol, price);
+    }
+
+    fn quote(&self, symbol: &'static str) -> Option<(f64, f64)> {
+        self.books
+            .get(symbol)
+            .copied()
+            .or_else(|| self.last_prices.get(symbol).map(|price| (*price, *price)))
+    }
+
+    fn balance(&self, asset: &str) -> f64 {
+        self.account
+            .asset_to_balance
+            .get(asset)
+            .map_or(0.0, |balance| balance.balance)
+    }
+
+    fn free(&self, asset: &str) -> f64 {
+        self.account
+            .asset_to_balance
+            .get(asset)
+            .map_or(0.0, |balance| balance.balance - balance.locked)
+    }
+
+    // in the quote asset at the mid prices, None until every symbol has a price
+    pub fn equity(&self) -> Option<f64> {
+        let mut equity = self.balance(self.quote_asset);
+        for (symbol, base_asset, _) in &self.targets {
+            let (bid, ask) = self.quote(symbol)?;
+            equity += self.balance(base_asset) * (bid + ask) / 2.0;
+        }
+        Some(equity)
+    }
+
+    // the orders bringing every symbol drifted out of the band back to its target, joining the
+    // best bid to buy and the best ask to sell. Only the free balance is traded, each buy is
+    // sized by the quote asset the ones before it left.
+    pub fn rebalances(&self) -> Vec<Rebalance> {
+        let Some(equity) = self.equity().filter(|equity| *equity > 0.0) else {
+            return vec![];
+        };
+        let mut free_quote = self.free(self.quote_asset);
+        let mut rebalances = vec![];
+        for (symbol, base_asset, weight) in &self.targets {
+            let (bid, ask) = self.quote(symbol).unwrap();
+            if bid <= 0.0 || ask <= 0.0 {
+                continue;
+            }
+            let drift = self.balance(base_asset) * (bid + ask) / 2.0 - weight * equity;
+            if drift.abs() <= self.band * equity {
+                continue;
+            }
+            let (side, price, quantity) = if drift > 0.0 {
+                let quantity = (drift / ask).min(self.free(base_asset));
+                (TradeSide::Sell, ask, quantity)
+            } else {
+                let quantity = (-drift / bid).min(free_quote / bid);
+                free_quote -= quantity * bid;
+                (TradeSide::Buy, bid, quantity)
+            };
+            if quantity < MIN_ORDER_QUANTITY {
+                continue;
+            }
+            rebalances.push(Rebalance {
+                symbol,
+                side,
+                price,
+                quantity,
+            });
+        }
+        rebalances
+    }
+}
+
+struct PortfolioRebalancer {
+    market_data_topic: ReadTopicHandle,
+    account_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+    order_topic: WriteTopicHandle,
+
+    allocation: Allocation,
+    interval: Duration,
+    owner: Option<&'static str>,
+    next_rebalance_at: SystemTime,
+    // the resting order of each symbol, repriced every interval until it is done
+    open_orders: BTreeMap<&'static str, Arc<str>>,
+    order_seq: u64,
+}
+
+impl PortfolioRebalancer {
+    fn on_order_result(&mut self, result: &OrderResult) {
+        if self.open_orders.get(result.symbol) != Some(&result.client_order_id) {
+            return;
+        }
+        match result.status {
+            OrderStatus::New | OrderStatus::PartiallyFilled => {}
+            OrderStatus::Rejected | OrderStatus::RateLimited => {
+                warn!("rebalance order {} rejected", result.client_order_id);
+                self.open_orders.remove(result.symbol);
+            }
+            _ => {
+                self.open_orders.remove(result.symbol);
+            }
+        }
+    }
+}
+
+impl Module for PortfolioRebalancer {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            match &msg.payload {
+                Payload::BinanceBookTicker(ticker) => self.allocation.on_book(
+                    ticker.symbol,
+                    ticker.best_bid_price,
+                    ticker.best_ask_price,
+                ),
+                payload => {
+                    if let (Some(symbol), Some(price)) = (payload.symbol(), payload.trade_price()) {
+                        self.allocation.on_trade_price(symbol, price);
+                    }
+                }
+            }
+        }
+        while let Some(msg) = comms.receive(&self.account_topic) {
+            if let Payload::AccountUpdate(update) = msg.payload {
+                self.allocation.on_account_update(&update);
+            }
+        }
+        while let Some(msg) = comms.receive(&self.order_result_topic) {
+            for result in msg.payload.order_results() {
+                self.on_order_result(result);
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let now = comms.time();
+        if now < self.next_rebalance_at || self.allocation.equity().is_none() {
+            return;
+        }
+        self.next_rebalance_at = now + self.interval;
+
+        // the orders left from the last rebalance are priced off the old book, the symbols are
+        // traded again once they are gone
+        for (symbol, client_order_id) in &self.open_orders {
+            comms.publish(
+                &self.order_topic,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol,
+                        client_order_id: client_order_id.clone(),
+                        owner: self.owner,
+                    }),
+                },
+            );
+        }
+        for rebalance in self.allocation.rebalances() {
+            if self.open_orders.contains_key(rebalance.symbol) {
+                continue;
+            }
+            self.order_seq += 1;
+            // the ids of the strategies sharing a market differ by their owners
+            let client_order_id: Arc<str> = Arc::from(match self.owner {
+                Some(owner) => format!("R{}@{}", self.order_seq, owner),
+                None => format!("R{}", self.order_seq),
+            });
+            debug!(
+                "rebalance {} {:?} {:.5} at {} order_id={}",
+                rebalance.symbol,
+                rebalance.side,
+                rebalance.quantity,
+                rebalance.price,
+                client_order_id
+            );
+            self.open_orders
+                .insert(rebalance.symbol, client_order_id.clone());
+            comms.publish(
+                &self.order_topic,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::OrderRequest(OrderRequest {
+                        symbol: rebalance.symbol,
+                        side: rebalance.side,
+                        price: rebalance.price,
+                        quantity: rebalance.quantity,
+                        trade_type: TradeType::Limit,
+                        time_in_force: TimeInForce::GoodTilCancelled,
+                        client_order_id,
+                        cancel_order_id: None,
+                        owner: self.owner,
+                    }),
+                },
+            );
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Holds every symbol at a target weight of the equity of the account with limit orders, a
+// reference strategy trading several markets of one quote asset at once
+pub struct PortfolioRebalancerBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    account_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+    order_topic: Option<WriteTopicHandle>,
+
+    targets: Vec<(&'static str, f64)>,
+    symbol_info_manager: Option<SymbolInfoManager>,
+    band: f64,
+    interval: Duration,
+    owner: Option<&'static str>,
+}
+
+impl PortfolioRebalancerBuilder {
+    // targets are (symbol, weight) pairs, the weights add up to at most 1
+    pub fn new(targets: Vec<(&'static str, f64)>) -> Self {
+        PortfolioRebalancerBuilder {
+            market_data_topic: None,
+            account_topic: None,
+            order_result_topic: None,
+            order_topic: None,
+            targets,
+            symbol_info_manager: None,
+            band: 0.01,
+            interval: Duration::from_secs(60),
+            owner: None,
+        }
+    }
+
+    pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
+        self.symbol_info_manager = Some(manager);
+        self
+    }
+
+    // fraction of the equity a symbol may drift from its target before it is traded back, 1%
+    // by default
+    pub fn with_band(mut self, band: f64) -> Self {
+        self.band = band;
+        self
+    }
+
+    // engine time between two rebalances, a minute by default
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // trade the account the market agent keeps for owner instead of the shared one
+    pub fn with_owner(mut self, owner: &'static str) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+}
+
+impl ModuleBuilder for PortfolioRebalancerBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let account_topic = comms.get_topic("account");
+        let order_result_topic = comms.get_topic("order_result");
+        let order_topic = comms.get_topic("order");
+
+        // only the ticks of our symbols
+        let symbols: Vec<&'static str> = self.targets.iter().map(|(symbol, _)| *symbol).collect();
+        let symbols_filter: MessageFilter = Arc::new(move |message: &Message| {
+            message
+                .payload
+                .symbol()
+                .is_none_or(|symbol| symbols.contains(&symbol))
+        });
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbols_filter)
+            .into();
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(self.owner))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(&order_result_topic, owner_filter(self.owner))
+            .into();
+        self.order_topic = comms.publish_topic(&order_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        Box::new(PortfolioRebalancer {
+            market_data_topic: self.market_data_topic.unwrap(),
+            account_topic: self.account_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            order_topic: self.order_topic.unwrap(),
+            allocation: Allocation::new(
+                &self.targets,
+                &self.symbol_info_manager.expect("symbol info manager"),
+                self.band,
+            ),
+            interval: self.interval,
+            owner: self.owner,
+            next_rebalance_at: SystemTime::UNIX_EPOCH,
+            open_orders: BTreeMap::new(),
+            order_seq: 0,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "portfolio_rebalancer"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use upstair_type::account::AccountAssetUpdate;
+
+    use super::*;
+
+    fn balances(balances: &[(&'static str, f64, f64)]) -> AccountUpdate {
+        AccountUpdate {
+            updates: balances
+                .iter()
+                .map(|(asset, balance, locked)| {
+                    (
+                        *asset,
+                        AccountAssetUpdate {
+                            balance: *balance,
+                            locked: *locked,
+                        },
+                    )
+                })
+                .collect(),
+            symbol: None,
+            seq: 0,
+            owner: None,
+        }
+    }
+
+    // half in BTC at 100, a quarter in ETH at 10
+    fn fixture_allocation() -> Allocation {
+        let manager = SymbolInfoManager::default()
+            .with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0)
+            .with_symbol_config("ETHUSDT", "ETH", "USDT", 0.0);
+        let mut allocation =
+            Allocation::new(&[("BTCUSDT", 0.5), ("ETHUSDT", 0.25)], &manager, 0.01);
+        allocation.on_book("BTCUSDT", 99.0, 101.0);
+        allocation.on_trade_price("ETHUSDT", 10.0);
+        allocation
+    }
+
+    #[test]
+    fn test_rebalance_from_cash() {
+        let mut allocation = fixture_allocation();
+        allocation.on_account_update(&balances(&[("USDT", 10000.0, 0.0)]));
+        assert_eq!(allocation.equity(), Some(10000.0));
+        let rebalances = allocation.rebalances();
+        assert_eq!(rebalances.len(), 2);
+        assert_eq!(rebalances[0].side, TradeSide::Buy);
+        assert_eq!(rebalances[0].price, 99.0);
+        assert!((rebalances[0].quantity - 5000.0 / 99.0).abs() < 1e-9);
+        assert_eq!(rebalances[1].symbol, "ETHUSDT");
+        assert!((rebalances[1].quantity - 250.0).abs() < 1e-9);
+
+        // the buys share the free quote asset
+        allocation.on_account_update(&balances(&[("USDT", 10000.0, 7000.0)]));
+        let rebalances = allocation.rebalances();
+        assert_eq!(rebalances.len(), 1);
+        assert!((rebalances[0].quantity - 3000.0 / 99.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_within_band() {
+        let mut allocation = fixture_allocation();
+        allocation.on_account_update(&balances(&[
+            ("USDT", 2500.0, 0.0),
+            ("BTC", 50.5, 0.0),
+            ("ETH", 250.0, 0.0),
+        ]));
+        assert!(allocation.rebalances().is_empty());
+    }
+
+    #[test]
+    fn test_sell_free_balance() {
+        let mut allocation = fixture_allocation();
+        // 80% in BTC, 60 of it locked by open orders
+        allocation.on_account_update(&balances(&[
+            ("USDT", 0.0, 0.0),
+            ("BTC", 80.0, 60.0),
+            ("ETH", 200.0, 0.0),
+        ]));
+        let rebalances = allocation.rebalances();
+        assert_eq!(
+            rebalances,
+            vec![Rebalance {
+                symbol: "BTCUSDT",
+                side: TradeSide::Sell,
+                price: 101.0,
+                quantity: 20.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_no_rebalance_without_prices() {
+        let manager =
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0);
+        let mut allocation = Allocation::new(&[("BTCUSDT", 0.5)], &manager, 0.01);
+        allocation.on_account_update(&balances(&[("USDT", 10000.0, 0.0)]));
+        assert_eq!(allocation.equity(), None);
+        assert!(allocation.rebalances().is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+// Parameters of the Avellaneda–Stoikov model.
+// The time horizon rolls over every `horizon_ms`, so (T - t) is the fraction of the current
+// horizon left, in (0, 1].
+#[derive(Debug, Clone)]
+pub struct AvellanedaStoikovParams {
+    pub gamma: f64,
+    // decay of order arrival intensity by depth. estimated from recent trades when None
+    pub k: Option<f64>,
+    pub horizon_ms: u64,
+}
+
+impl Default for AvellanedaStoikovParams {
+    fn default() -> Self {
+        Self {
+            gamma: 0.1,
+            k: None,
+            horizon_ms: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl AvellanedaStoikovParams {
+    pub fn time_left_fraction(&self, now_ms: u64) -> f64 {
+        let horizon_ms = self.horizon_ms.max(1);
+        let elapsed = now_ms % horizon_ms;
+        (horizon_ms - elapsed) as f64 / horizon_ms as f64
+    }
+
+    // r = s - q * gamma * sigma^2 * (T - t)
+    pub fn reservation_price(&self, fair_price: f64, q: f64, vol: f64, time_left: f64) -> f64 {
+        fair_price - q * self.gamma * vol * vol * time_left
+    }
+
+    // spread = gamma * sigma^2 * (T - t) + 2 / gamma * ln(1 + gamma / k)
+    pub fn optimal_spread(&self, k: f64, vol: f64, time_left: f64) -> f64 {
+        self.gamma * vol * vol * time_left + 2.0 / self.gamma * (1.0 + self.gamma / k).ln()
+    }
+}
+
+// Estimates k of the arrival intensity lambda(d) = A * exp(-k * d) from the depth of recent
+// trades, where depth is the distance from the fair price. For exponentially distributed
+// depths the maximum likelihood estimate of k is 1 / mean(depth).
+#[derive(Debug, Clone)]
+pub struct TradeIntensity {
+    depths: VecDeque<f64>,
+    depth_sum: f64,
+    capacity: usize,
+    min_samples: usize,
+}
+
+impl TradeIntensity {
+    pub fn new(capacity: usize, min_samples: usize) -> Self {
+        Self {
+            depths: VecDeque::with_capacity(capacity),
+            depth_sum: 0.0,
+            capacity,
+            min_samples: min_samples.min(capacity),
+        }
+    }
+
+    pub fn add_trade(&mut self, fair_price: f64, trade_price: f64) {
+        let depth = (trade_price - fair_price).abs();
+        if self.depths.len() == self.capacity {
+            self.depth_sum -= self.depths.pop_front().unwrap_or(0.0);
+        }
+        self.depths.push_back(depth);
+        self.depth_sum += depth;
+    }
+
+    pub fn k(&self) -> Option<f64> {
+        if self.depths.len() < self.min_samples || self.depth_sum <= 0.0 {
+            return None;
+        }
+        Some(self.depths.len() as f64 / self.depth_sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_left_fraction() {
+        let params = AvellanedaStoikovParams {
+            horizon_ms: 1000,
+            ..Default::default()
+        };
+        assert_eq!(params.time_left_fraction(0), 1.0);
+        assert_eq!(params.time_left_fraction(250), 0.75);
+        assert_eq!(params.time_left_fraction(1999), 0.001);
+    }
+
+    #[test]
+    fn test_reservation_price_skews_against_inventory() {
+        let params = AvellanedaStoikovParams {
+            gamma: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(params.reservation_price(100.0, 0.0, 2.0, 1.0), 100.0);
+        // long inventory pushes reservation price down
+        assert_eq!(params.reservation_price(100.0, 1.0, 2.0, 1.0), 98.0);
+        assert_eq!(params.reservation_price(100.0, -1.0, 2.0, 0.5), 101.0);
+    }
+
+    #[test]
+    fn test_optimal_spread_shrinks_with_time() {
+        let params = AvellanedaStoikovParams {
+            gamma: 0.5,
+            ..Default::default()
+        };
+        let early = params.optimal_spread(2.0, 2.0, 1.0);
+        let late = params.optimal_spread(2.0, 2.0, 0.0);
+        assert!((late - 4.0 * 1.25_f64.ln()).abs() < 1e-12);
+        assert!((early - late - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_trade_intensity() {
+        let mut intensity = TradeIntensity::new(4, 2);
+        intensity.add_trade(100.0, 100.5);
+        assert_eq!(intensity.k(), None);
+        intensity.add_trade(100.0, 99.5);
+        assert_eq!(intensity.k(), Some(2.0));
+        // oldest depths fall out of the window
+        intensity.add_trade(100.0, 102.0);
+        intensity.add_trade(100.0, 98.0);
+        intensity.add_trade(100.0, 102.0);
+        intensity.add_trade(100.0, 98.0);
+        assert_eq!(intensity.k(), Some(0.5));
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+// The quotes placed around one depth, the distance from the fair price on the side of the
+// quote, and how many of them were filled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthBucket {
+    pub depth: f64,
+    pub quotes: u64,
+    pub fills: u64,
+}
+
+impl DepthBucket {
+    // fills per second of a quote resting for lifetime_secs, from its fill probability
+    // 1 - exp(-lambda * lifetime). None without quotes or when all of them filled.
+    pub fn intensity(&self, lifetime_secs: f64) -> Option<f64> {
+        if self.quotes == 0 || self.fills >= self.quotes {
+            return None;
+        }
+        let fill_probability = self.fills as f64 / self.quotes as f64;
+        Some((1.0 / (1.0 - fill_probability)).ln() / lifetime_secs)
+    }
+}
+
+// Buckets of equal width from 0 to the 99th percentile of the depths, the deeper quotes in
+// the last one. Quotes through the fair price count at depth 0.
+pub fn bucket_quotes(quotes: &[(f64, bool)], count: usize) -> Vec<DepthBucket> {
+    if quotes.is_empty() || count == 0 {
+        return vec![];
+    }
+    let mut depths: Vec<f64> = quotes.iter().map(|(depth, _)| depth.max(0.0)).collect();
+    depths.sort_by(|a, b| a.total_cmp(b));
+    let max_depth = depths[((depths.len() - 1) as f64 * 0.99).round() as usize];
+    let width = if max_depth > 0.0 {
+        max_depth / count as f64
+    } else {
+        1.0
+    };
+    let mut buckets: Vec<DepthBucket> = (0..count)
+        .map(|i| DepthBucket {
+            depth: (i as f64 + 0.5) * width,
+            quotes: 0,
+            fills: 0,
+        })
+        .collect();
+    for (depth, filled) in quotes {
+        let i = ((depth.max(0.0) / width) as usize).min(count - 1);
+        buckets[i].quotes += 1;
+        buckets[i].fills += *filled as u64;
+    }
+    buckets
+}
+
+// Arrival intensity lambda(d) = A * exp(-k * d) of the fills of a quote at depth d, k being
+// the one of the Avellaneda–Stoikov model. Stored as text, `a,<A>` and `k,<k>` lines, `#`
+// starts a comment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FillIntensityCalibration {
+    pub a: f64,
+    pub k: f64,
+}
+
+impl FillIntensityCalibration {
+    // least squares fit of ln(lambda) on the depth, over the buckets with fills
+    pub fn fit(buckets: &[DepthBucket], lifetime_secs: f64) -> Result<Self, anyhow::Error> {
+        let points: Vec<(f64, f64)> = buckets
+            .iter()
+            .filter(|bucket| bucket.fills > 0)
+            .filter_map(|bucket| Some((bucket.depth, bucket.intensity(lifetime_secs)?.ln())))
+            .collect();
+        if points.len() < 2 {
+            bail!(
+                "need fills at 2 depths at least to fit, got {}",
+                points.len()
+            );
+        }
+        let n = points.len() as f64;
+        let mean_depth = points.iter().map(|(d, _)| d).sum::<f64>() / n;
+        let mean_ln = points.iter().map(|(_, l)| l).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|(d, l)| (d - mean_depth) * (l - mean_ln))
+            .sum();
+        let variance: f64 = points.iter().map(|(d, _)| (d - mean_depth).powi(2)).sum();
+        let k = -covariance / variance;
+        if !(k > 0.0 && k.is_finite()) {
+            bail!("fill intensity does not decay with the depth, k is {}", k);
+        }
+        Ok(FillIntensityCalibration {
+            a: (mean_ln + k * mean_depth).exp(),
+            k,
+        })
+    }
+
+    pub fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        let (mut a, mut k) = (None, None);
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(',') else {
+                bail!("line {}: expect key,value", i + 1);
+            };
+            let value: f64 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid value", i + 1))?;
+            if !(value > 0.0 && value.is_finite()) {
+                bail!("line {}: out of range", i + 1);
+            }
+            match key.trim() {
+                "a" => a = Some(value),
+                "k" => k = Some(value),
+                key => bail!("line {}: unknown key {}, expected a or k", i + 1, key),
+            }
+        }
+        match (a, k) {
+            (Some(a), Some(k)) => Ok(FillIntensityCalibration { a, k }),
+            _ => bail!("calibration must have a and k"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read fill calibration {}", path.display()))?;
+        Self::parse(&s).with_context(|| format!("invalid fill calibration {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("failed to write fill calibration {}", path.display()))
+    }
+}
+
+impl std::fmt::Display for FillIntensityCalibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# lambda(depth) = a * exp(-k * depth), fills per second")?;
+        writeln!(f, "a,{}", self.a)?;
+        writeln!(f, "k,{}", self.k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_fill_intensity() {
+        let lifetime_secs = 0.1;
+        // quotes filled with the probability of lambda(d) = 2 * exp(-0.5 * d)
+        let buckets: Vec<DepthBucket> = [0.5_f64, 1.5, 2.5, 3.5]
+            .iter()
+            .map(|depth| {
+                let probability = 1.0 - (-2.0 * (-0.5 * depth).exp() * lifetime_secs).exp();
+                DepthBucket {
+                    depth: *depth,
+                    quotes: 1_000_000,
+                    fills: (probability * 1_000_000.0).round() as u64,
+                }
+            })
+            .collect();
+        let calibration = FillIntensityCalibration::fit(&buckets, lifetime_secs).unwrap();
+        assert!((calibration.k - 0.5).abs() < 1e-3);
+        assert!((calibration.a - 2.0).abs() < 1e-2);
+
+        let parsed = FillIntensityCalibration::parse(&calibration.to_string()).unwrap();
+        assert_eq!(parsed, calibration);
+        assert!(FillIntensityCalibration::parse("k,0.5").is_err());
+        assert!(FillIntensityCalibration::parse("a,1\nk,-0.5").is_err());
+        // more fills deeper
+        let rising = [
+            DepthBucket {
+                depth: 0.5,
+                quotes: 10,
+                fills: 1,
+            },
+            DepthBucket {
+                depth: 1.5,
+                quotes: 10,
+                fills: 2,
+            },
+        ];
+        assert!(FillIntensityCalibration::fit(&rising, lifetime_secs).is_err());
+    }
+
+    #[test]
+    fn test_bucket_quotes() {
+        let mut quotes: Vec<(f64, bool)> =
+            (0..100).map(|i| (i as f64 / 10.0, i % 2 == 0)).collect();
+        // through the fair price, and an outlier past the 99th percentile
+        quotes.push((-1.0, true));
+        quotes.push((100.0, false));
+        let buckets = bucket_quotes(&quotes, 2);
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].depth, 2.475);
+        assert_eq!((buckets[0].quotes, buckets[0].fills), (51, 26));
+        assert_eq!((buckets[1].quotes, buckets[1].fills), (51, 25));
+        assert!(bucket_quotes(&[], 4).is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use stepper_world::{
+    order_tracker::{Order, OrderStatus},
+    StepperWorld,
+};
+use upstair_type::{control::DataQualityFlags, data::market::BinanceTradeTick, order::TradeSide};
+
+use crate::{Action, AmmStrategy};
+
+// What changes in the world of a strategy at one moment of a script
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedStep {
+    // since the start of the script
+    pub at: Duration,
+    // (bid, bid quantity, ask, ask quantity)
+    pub book: Option<(f64, f64, f64, f64)>,
+    // (price, quantity)
+    pub trades: Vec<(f64, f64)>,
+    // (order id, quantity filled in this step), fills at the order price
+    pub fills: Vec<(String, f64)>,
+    // the flags of the market data validation from this step on
+    pub data_quality: Option<DataQualityFlags>,
+}
+
+impl ScriptedStep {
+    pub fn at_ms(ms: u64) -> Self {
+        ScriptedStep {
+            at: Duration::from_millis(ms),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_book(mut self, bid: f64, bid_qty: f64, ask: f64, ask_qty: f64) -> Self {
+        self.book = Some((bid, bid_qty, ask, ask_qty));
+        self
+    }
+
+    pub fn with_trade(mut self, price: f64, quantity: f64) -> Self {
+        self.trades.push((price, quantity));
+        self
+    }
+
+    pub fn with_fill(mut self, order_id: &str, quantity: f64) -> Self {
+        self.fills.push((order_id.to_string(), quantity));
+        self
+    }
+
+    pub fn with_data_quality(mut self, flags: DataQualityFlags) -> Self {
+        self.data_quality = Some(flags);
+        self
+    }
+}
+
+// Runs a strategy against a scripted world, without the engine and the data files. The
+// exchange answers instantly: placed orders are open and cancelled orders are gone by the
+// next step, and fills move the balances without fees.
+pub struct StrategyHarness {
+    pub strategy: AmmStrategy,
+    pub world: StepperWorld,
+    trade_id: u64,
+}
+
+impl StrategyHarness {
+    pub fn new(strategy: AmmStrategy) -> Self {
+        StrategyHarness {
+            strategy,
+            world: StepperWorld {
+                now: UNIX_EPOCH,
+                ..Default::default()
+            },
+            trade_id: 0,
+        }
+    }
+
+    pub fn with_balance(mut self, asset: &'static str, balance: f64) -> Self {
+        self.world
+            .account
+            .asset_to_balance
+            .entry(asset)
+            .or_default()
+            .balance = balance;
+        self
+    }
+
+    // applies the step to the world and returns the actions of the strategy
+    pub fn step(&mut self, step: &ScriptedStep) -> Vec<Action> {
+        self.world.now = UNIX_EPOCH + step.at;
+        let now_ms = step.at.as_millis() as u64;
+
+        if let Some((bid, bid_qty, ask, ask_qty)) = step.book {
+            let book = self.world.markets.entry(self.strategy.symbol).or_default();
+            book.best_bid_price = bid;
+            book.best_bid_qty = bid_qty;
+            book.best_ask_price = ask;
+            book.best_ask_qty = ask_qty;
+            self.world.booker_tick_updated_at = self.world.now;
+            let wap = (ask * bid_qty + bid * ask_qty) / (ask_qty + bid_qty);
+            self.world.wap_buf.push((now_ms, wap));
+            self.world.mid_buf.push((now_ms, (bid + ask) / 2.0));
+        }
+        for (price, quantity) in &step.trades {
+            self.trade_id += 1;
+            self.world.latest_market_price = *price;
+            self.world.trade_buf.push(BinanceTradeTick {
+                id: self.trade_id,
+                price: *price,
+                qty: *quantity,
+                base_qty: price * quantity,
+                time: now_ms,
+                is_buyer_maker: false,
+                symbol: self.strategy.symbol,
+            });
+        }
+        if let Some(flags) = step.data_quality {
+            self.world.data_quality = flags;
+        }
+        for (order_id, quantity) in &step.fills {
+            self.fill(order_id, *quantity);
+        }
+        self.world.order_tracker.remove_terminated_orders();
+
+        self.strategy.run(&mut self.world);
+        self.world.trade_buf.clear();
+        self.world.wap_buf.clear();
+        self.world.mid_buf.clear();
+        self.world.filled_event_buf.clear();
+
+        self.strategy.debug_buf.clear();
+
+        let actions = std::mem::take(&mut self.strategy.actions);
+        for action in &actions {
+            match action {
+                Action::CancelOrder(cancel) => self
+                    .world
+                    .order_tracker
+                    .update_status(&cancel.order_id, OrderStatus::Canceled),
+                Action::PlaceOrder(place) => {
+                    self.world.order_tracker.upsert_order(Order {
+                        order_id: place.order_id.clone(),
+                        price: place.price,
+                        side: place.side.clone(),
+                        quantity: place.quantity,
+                        filled: 0.0,
+                        status: OrderStatus::Open,
+                        created_at: self.world.now,
+                    });
+                }
+            }
+        }
+        actions
+    }
+
+    // runs the steps in order and returns the actions of each
+    pub fn run(&mut self, script: &[ScriptedStep]) -> Vec<Vec<Action>> {
+        script.iter().map(|step| self.step(step)).collect()
+    }
+
+    fn fill(&mut self, order_id: &str, quantity: f64) {
+        let Some(order) = self.world.order_tracker.get_order(order_id) else {
+            panic!("scripted fill of unknown order {order_id}");
+        };
+        let filled = (order.filled + quantity).min(order.quantity);
+        let status = if filled >= order.quantity {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        let (base, quote) = match order.side {
+            TradeSide::Buy => (quantity, -quantity * order.price),
+            TradeSide::Sell => (-quantity, quantity * order.price),
+        };
+        let tracker = &mut self.world.order_tracker;
+        tracker.update_fill_quantity(order_id, filled);
+        tracker.update_status(order_id, status);
+        self.world
+            .filled_event_buf
+            .push((order_id.to_string(), filled));
+        for (asset, delta) in [
+            (self.strategy.base_asset, base),
+            (self.strategy.quote_asset, quote),
+        ] {
+            self.world
+                .account
+                .asset_to_balance
+                .entry(asset)
+                .or_default()
+                .balance += delta;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symbol_info::SymbolInfoManager;
+
+    use super::*;
+
+    fn fixture_harness() -> StrategyHarness {
+        let strategy = AmmStrategy::new(
+            "BTCUSDT",
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+        );
+        StrategyHarness::new(strategy)
+            .with_balance("BTC", 1.0)
+            .with_balance("USDT", 100.0)
+    }
+
+    fn placed(actions: &[Action]) -> Vec<&str> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::PlaceOrder(place) => Some(place.order_id.as_str()),
+                Action::CancelOrder(_) => None,
+            })
+            .collect()
+    }
+
+    fn cancelled(actions: &[Action]) -> Vec<&str> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::CancelOrder(cancel) => Some(cancel.order_id.as_str()),
+                Action::PlaceOrder(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scripted_quotes_and_fills() {
+        let mut harness = fixture_harness();
+        let actions = harness.run(&[
+            // no market data yet
+            ScriptedStep::at_ms(0),
+            ScriptedStep::at_ms(100)
+                .with_book(100.0, 1.0, 101.0, 1.0)
+                .with_trade(100.5, 0.1),
+            ScriptedStep::at_ms(200)
+                .with_book(100.0, 1.0, 101.0, 2.0)
+                .with_fill("B0", 0.004),
+            ScriptedStep::at_ms(300)
+                .with_book(100.0, 1.0, 101.0, 1.0)
+                .with_fill("B0", 0.006),
+        ]);
+        assert!(actions[0].is_empty());
+        assert_eq!(placed(&actions[1]), ["B0", "S0"]);
+        assert_eq!(placed(&actions[2]), ["B1", "S1"]);
+        // quotes expire after 100ms
+        assert!(cancelled(&actions[2]).is_empty());
+        assert_eq!(cancelled(&actions[3]), ["S0"]);
+
+        let world = &harness.world;
+        assert!(world.order_tracker.get_order("B0").is_none());
+        let btc = world.account.asset_to_balance.get("BTC").unwrap().balance;
+        assert!((btc - 1.01).abs() < 1e-12);
+        let usdt = world.account.asset_to_balance.get("USDT").unwrap().balance;
+        assert!(usdt < 100.0);
+    }
+}
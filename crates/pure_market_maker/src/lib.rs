@@ -1,20 +1,35 @@
+pub mod avellaneda_stoikov;
 mod duration_sampler;
+pub mod fill_intensity;
+pub mod harness;
+pub mod quote_sizing;
 mod time_volatility;
+pub mod vol_estimator;
 mod volatility;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use avellaneda_stoikov::{AvellanedaStoikovParams, TradeIntensity};
 
-use polars::{df, io::parquet::ParquetWriter};
-use time_volatility::TimeVolatility;
 use tracing::info;
-use upstair_type::order::TradeSide;
-use yata::{core::Method, helpers::Peekable};
+use upstair_type::{
+    debug_log::{DebugRecord, QuoteDebug},
+    order::{TimeInForce, TradeSide, TradeType},
+    signal::SignalKind,
+    strategy::StrategyDebug,
+};
 
 use stepper_world::{
     order_tracker::{Order, OrderStatus},
-    StepperWorld,
+    MarketSnapshot, StepperWorld,
 };
 
+use quote_sizing::{snap_to_lot, QuoteSizing, SizingContext};
 use symbol_info::SymbolInfoManager;
+use vol_estimator::{VolEstimator, VolGapHandling, VolTracker};
 
 #[derive(Debug)]
 pub struct CancelOrder {
@@ -29,6 +44,8 @@ pub struct PlaceOrderData {
     pub price: f64,
     pub side: TradeSide,
     pub quantity: f64,
+    pub trade_type: TradeType,
+    pub time_in_force: TimeInForce,
 }
 
 #[derive(Debug)]
@@ -37,33 +54,144 @@ pub enum Action {
     PlaceOrder(PlaceOrderData),
 }
 
-macro_rules! struct_to_dataframe {
-    ($input:expr, [$($field:ident),+]) => {
-        {
-            let len = $input.len().to_owned();
-            // Extract the field values into separate vectors
-            $(let mut $field = Vec::with_capacity(len);)*
-            for e in $input.into_iter() {
-                $($field.push(e.$field);)*
-            }
-            df! {
-                $(stringify!($field) => $field,)*
-            }
+// How reservation price and spread are derived from fair price, inventory and volatility
+#[derive(Debug, Clone, Default)]
+pub enum PricingModel {
+    // reservation price and spread scale linearly with volatility
+    #[default]
+    Simple,
+    // full Avellaneda–Stoikov model with time horizon and order arrival intensity
+    AvellanedaStoikov(AvellanedaStoikovParams),
+}
+
+// Price the quotes are centered around
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FairPriceSource {
+    // best bid/ask weighted by the opposite side quantity
+    #[default]
+    Wap,
+    Mid,
+    // the microprice on the signals topic, the wap until one is published
+    Microprice,
+    // the mark price of a futures symbol, the wap until one is published
+    Mark,
+}
+
+impl FromStr for FairPriceSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wap" => Ok(Self::Wap),
+            "mid" => Ok(Self::Mid),
+            "microprice" => Ok(Self::Microprice),
+            "mark" => Ok(Self::Mark),
+            _ => Err(format!(
+                "unknown fair price source {s}, expected wap, mid, microprice or mark"
+            )),
+        }
+    }
+}
+
+// Price series the volatility is estimated from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VolPriceSource {
+    #[default]
+    Wap,
+    Mid,
+    Trade,
+}
+
+impl FromStr for VolPriceSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wap" => Ok(Self::Wap),
+            "mid" => Ok(Self::Mid),
+            "trade" => Ok(Self::Trade),
+            _ => Err(format!(
+                "unknown vol price source {s}, expected wap, mid or trade"
+            )),
+        }
+    }
+}
+
+// How the model quote prices are placed relative to the displayed best bid/ask
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuoteAnchoring {
+    // model prices as they are, they may cross the book
+    Model,
+    // never better than the touch, wider model prices are kept
+    #[default]
+    Touch,
+    // at most one price tick inside the touch, joins it when the spread is a single tick
+    Improve,
+}
+
+impl FromStr for QuoteAnchoring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "model" => Ok(Self::Model),
+            "touch" => Ok(Self::Touch),
+            "improve" => Ok(Self::Improve),
+            _ => Err(format!(
+                "unknown quote anchoring {s}, expected model, touch or improve"
+            )),
         }
-    };
+    }
 }
 
-struct QuoteDebugLog {
-    time: i64,
-    price: f64,
-    qty: f64,
-    fair_price: f64,
-    is_bid: bool,
-    best_bid_price: f64,
-    best_bid_qty: f64,
-    best_ask_price: f64,
-    best_ask_qty: f64,
-    id: String,
+// What the strategy does while the market data validation flags the data as degraded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DegradedDataResponse {
+    // quote as if the data were fine
+    #[default]
+    Ignore,
+    // quote DEGRADED_SPREAD_MULTIPLIER times the model spread
+    Widen,
+    // cancel all quotes and place none until the data recovers
+    Pull,
+}
+
+impl FromStr for DegradedDataResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "widen" => Ok(Self::Widen),
+            "pull" => Ok(Self::Pull),
+            _ => Err(format!(
+                "unknown degraded data response {s}, expected ignore, widen or pull"
+            )),
+        }
+    }
+}
+
+// Open quotes are kept while the desired quote stays within the tolerance, otherwise they are
+// cancelled and replaced
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuoteTolerance {
+    pub price: f64,
+    pub quantity: f64,
+}
+
+// Bands of the inventory away from target, in base asset quantity
+#[derive(Debug, Clone, Copy)]
+pub struct InventoryLimits {
+    pub max_long: f64,
+    pub max_short: f64,
+    // cross the spread to bring the inventory back within the limits
+    pub reduce_aggressively: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InventoryCap {
+    Long,
+    Short,
 }
 
 pub struct AmmStrategy {
@@ -76,26 +204,59 @@ pub struct AmmStrategy {
     pub base_asset: &'static str,
     pub quote_asset: &'static str,
 
-    pub vol_tracker: Option<TimeVolatility>,
+    pub vol_tracker: Option<VolTracker>,
 
     pub gamma: f64,
 
-    pub ts_seq: Vec<i64>,
-    pub vol_seq: Vec<f64>,
-    quote_seq: Vec<QuoteDebugLog>,
-    fill_seq_order_id: Vec<String>,
-    fill_seq_qty: Vec<f64>,
+    pub pricing_model: PricingModel,
+    pub trade_intensity: TradeIntensity,
+    pub fair_price_source: FairPriceSource,
+    pub vol_price_source: VolPriceSource,
+    pub vol_estimator: VolEstimator,
+    // (handling, threshold) of the gaps in the vol samples
+    pub vol_gap: (VolGapHandling, Duration),
+    // no quotes until the vol samples span the window of the estimate
+    pub vol_warm_up: bool,
+    pub quote_anchoring: QuoteAnchoring,
+    pub price_tick: f64,
+    pub quote_sizing: QuoteSizing,
+    // quote sizes are whole lots of this quantity, as they are when 0
+    pub lot_size: f64,
+    // quotes expire every round when None
+    pub quote_tolerance: Option<QuoteTolerance>,
+    // the orders expiring every round are good til date orders the exchange expires, rather
+    // than canceled by the strategy
+    pub exchange_expiry: bool,
+    pub inventory_limits: Option<InventoryLimits>,
+    inventory_cap: Option<InventoryCap>,
+    pub degraded_data: DegradedDataResponse,
+    pub event_count: BTreeMap<&'static str, u64>,
+    // the events since the last day roll, see on_day_roll
+    pub day_event_count: BTreeMap<&'static str, u64>,
+
+    // records of the vol updates, quotes and fills, taken by the stepper and published on
+    // the debug_log topic when enabled
+    pub debug_log: bool,
+    pub debug_log_buf: Vec<DebugRecord>,
 
     pub uniq_quote_round: u64,
+    // a record of each quote round, taken by the stepper and published for the vis
+    pub debug_buf: Vec<StrategyDebug>,
 }
 
-fn convert_order_to_action(symbol: &'static str, order: Order) -> Action {
+fn convert_order_to_action(
+    symbol: &'static str,
+    order: Order,
+    time_in_force: TimeInForce,
+) -> Action {
     Action::PlaceOrder(PlaceOrderData {
         symbol,
         order_id: order.order_id,
         price: order.price,
         side: order.side,
         quantity: order.quantity,
+        trade_type: TradeType::Limit,
+        time_in_force,
     })
 }
 
@@ -107,7 +268,12 @@ fn inverse_lerp_with_clamp(v: f64, a: f64, b: f64) -> f64 {
     inverse_lerp(v.clamp(a, b), a, b)
 }
 
-const ENABLE_VOL_DEBUG: bool = true;
+pub const MM_ORDER_EXPIRE_MILLSECONDS: u64 = 100;
+// order id prefix of orders reducing inventory, they are never diffed against quotes
+const REDUCE_ORDER_PREFIX: &str = "R";
+// order id prefix of the market order flattening inventory on shutdown
+const FLATTEN_ORDER_PREFIX: &str = "F";
+const DEGRADED_SPREAD_MULTIPLIER: f64 = 2.0;
 
 impl AmmStrategy {
     pub fn new(symbol: &'static str, symbol_info_manager: SymbolInfoManager) -> AmmStrategy {
@@ -126,22 +292,191 @@ impl AmmStrategy {
             quote_asset,
             vol_tracker: None,
             gamma: 1.0,
-            ts_seq: vec![],
-            vol_seq: vec![],
-            quote_seq: vec![],
-            fill_seq_order_id: vec![],
-            fill_seq_qty: vec![],
+            pricing_model: PricingModel::default(),
+            trade_intensity: TradeIntensity::new(1000, 100),
+            fair_price_source: FairPriceSource::default(),
+            vol_price_source: VolPriceSource::default(),
+            vol_estimator: VolEstimator::default(),
+            vol_gap: (VolGapHandling::default(), Duration::from_secs(60)),
+            vol_warm_up: false,
+            quote_anchoring: QuoteAnchoring::default(),
+            price_tick: 0.1,
+            quote_sizing: QuoteSizing::default(),
+            lot_size: 0.001,
+            quote_tolerance: None,
+            exchange_expiry: false,
+            inventory_limits: None,
+            inventory_cap: None,
+            degraded_data: DegradedDataResponse::default(),
+            event_count: BTreeMap::new(),
+            day_event_count: BTreeMap::new(),
+            debug_log: false,
+            debug_log_buf: vec![],
             uniq_quote_round: 0,
+            debug_buf: vec![],
         }
     }
 
+    pub fn with_debug_log(mut self, debug_log: bool) -> Self {
+        self.debug_log = debug_log;
+        self
+    }
+
+    pub fn with_pricing_model(mut self, pricing_model: PricingModel) -> Self {
+        self.pricing_model = pricing_model;
+        self
+    }
+
+    pub fn with_fair_price_source(mut self, source: FairPriceSource) -> Self {
+        self.fair_price_source = source;
+        self
+    }
+
+    pub fn with_vol_price_source(mut self, source: VolPriceSource) -> Self {
+        self.vol_price_source = source;
+        self
+    }
+
+    pub fn with_vol_estimator(mut self, estimator: VolEstimator) -> Self {
+        self.vol_estimator = estimator;
+        self
+    }
+
+    pub fn with_vol_gap_handling(mut self, handling: VolGapHandling, gap: Duration) -> Self {
+        self.vol_gap = (handling, gap);
+        self
+    }
+
+    pub fn with_vol_warm_up(mut self, warm_up: bool) -> Self {
+        self.vol_warm_up = warm_up;
+        self
+    }
+
+    pub fn with_quote_anchoring(mut self, anchoring: QuoteAnchoring) -> Self {
+        self.quote_anchoring = anchoring;
+        self
+    }
+
+    pub fn with_price_tick(mut self, price_tick: f64) -> Self {
+        self.price_tick = price_tick;
+        self
+    }
+
+    pub fn with_quote_sizing(mut self, sizing: QuoteSizing) -> Self {
+        self.quote_sizing = sizing;
+        self
+    }
+
+    pub fn with_lot_size(mut self, lot_size: f64) -> Self {
+        self.lot_size = lot_size;
+        self
+    }
+
+    pub fn with_quote_tolerance(mut self, tolerance: Option<QuoteTolerance>) -> Self {
+        self.quote_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_exchange_expiry(mut self, exchange_expiry: bool) -> Self {
+        self.exchange_expiry = exchange_expiry;
+        self
+    }
+
+    pub fn with_inventory_limits(mut self, limits: Option<InventoryLimits>) -> Self {
+        self.inventory_limits = limits;
+        self
+    }
+
+    pub fn with_degraded_data_response(mut self, response: DegradedDataResponse) -> Self {
+        self.degraded_data = response;
+        self
+    }
+
+    fn on_event(&mut self, event: &'static str) {
+        *self.event_count.entry(event).or_insert(0) += 1;
+        *self.day_event_count.entry(event).or_insert(0) += 1;
+    }
+
+    // a new day starts in the market data, the events of the day ending are logged and the
+    // counting starts over
+    pub fn on_day_roll(&mut self) {
+        if !self.day_event_count.is_empty() {
+            let events: Vec<String> = self
+                .day_event_count
+                .iter()
+                .map(|(event, count)| format!("{}={}", event, count))
+                .collect();
+            tracing::info!("{} events of the day: {}", self.symbol, events.join(" "));
+        }
+        self.day_event_count.clear();
+    }
+
+    // the top of the book of our symbol, of the books the stepper keeps per symbol
+    fn book(&self, world: &StepperWorld) -> MarketSnapshot {
+        world.book(self.symbol)
+    }
+
     fn mid_price(&self, world: &StepperWorld) -> f64 {
-        (world.best_ask_price + world.best_bid_price) / 2.0
+        let book = self.book(world);
+        (book.best_ask_price + book.best_bid_price) / 2.0
     }
 
     fn wap_price(&self, world: &StepperWorld) -> f64 {
-        (world.best_ask_price * world.best_bid_qty + world.best_bid_price * world.best_ask_qty)
-            / (world.best_ask_qty + world.best_bid_qty)
+        let book = self.book(world);
+        (book.best_ask_price * book.best_bid_qty + book.best_bid_price * book.best_ask_qty)
+            / (book.best_ask_qty + book.best_bid_qty)
+    }
+
+    fn fair_price(&self, world: &StepperWorld) -> f64 {
+        match self.fair_price_source {
+            FairPriceSource::Wap => self.wap_price(world),
+            FairPriceSource::Mid => self.mid_price(world),
+            FairPriceSource::Microprice => match world.signals.get(&SignalKind::Microprice) {
+                Some(microprice) => *microprice,
+                None => self.wap_price(world),
+            },
+            FairPriceSource::Mark => world.mark_price.unwrap_or_else(|| self.wap_price(world)),
+        }
+    }
+
+    // (bid, ask) quote prices from the model prices
+    fn anchor_quotes(&self, world: &StepperWorld, model_bid: f64, model_ask: f64) -> (f64, f64) {
+        let book = self.book(world);
+        let (best_bid, best_ask) = (book.best_bid_price, book.best_ask_price);
+        match self.quote_anchoring {
+            QuoteAnchoring::Model => (model_bid, model_ask),
+            QuoteAnchoring::Touch => (model_bid.min(best_bid), model_ask.max(best_ask)),
+            QuoteAnchoring::Improve => {
+                let improves = best_ask - best_bid > self.price_tick * 1.5;
+                let (bid_limit, ask_limit) = if improves {
+                    (best_bid + self.price_tick, best_ask - self.price_tick)
+                } else {
+                    (best_bid, best_ask)
+                };
+                (model_bid.min(bid_limit), model_ask.max(ask_limit))
+            }
+        }
+    }
+
+    // A negative spread from the model, e.g. under extreme volatility, would send a bid above
+    // the ask. Crossing quotes are moved one tick apart around their middle, and quotes that
+    // are not numbers are not sent at all.
+    fn guard_quotes(&mut self, bid: f64, ask: f64) -> Option<(f64, f64)> {
+        if !bid.is_finite() || !ask.is_finite() {
+            tracing::warn!("skip invalid quotes bid={} ask={}", bid, ask);
+            self.on_event("invalid_quote_skipped");
+            return None;
+        }
+        if bid < ask {
+            return Some((bid, ask));
+        }
+        tracing::warn!("repair crossing quotes bid={:.3} ask={:.3}", bid, ask);
+        self.on_event("crossing_quote_repaired");
+        let middle = (bid + ask) / 2.0;
+        Some((
+            middle - self.price_tick / 2.0,
+            middle + self.price_tick / 2.0,
+        ))
     }
 
     fn calc_q(&self, world: &StepperWorld) -> f64 {
@@ -185,65 +520,303 @@ impl AmmStrategy {
         inventory_value / price
     }
 
+    // inventory away from target in base asset quantity
+    fn calc_q_base(&self, world: &StepperWorld) -> f64 {
+        self.calc_q(world) * self.calc_inventory_base(world)
+    }
+
+    fn update_trade_intensity(&mut self, world: &StepperWorld) {
+        let book = self.book(world);
+        if book.best_ask_price == 0.0 || book.best_bid_price == 0.0 {
+            return;
+        }
+        let fair_price = self.fair_price(world);
+        world.trade_buf.iter().for_each(|trade| {
+            self.trade_intensity.add_trade(fair_price, trade.price);
+        });
+    }
+
+    fn add_vol_sample(&mut self, time: u64, price: f64) {
+        match self.vol_tracker.as_mut() {
+            Some(vol_tracker) => {
+                vol_tracker.next(time, price);
+            }
+            None => {
+                let (handling, gap) = self.vol_gap;
+                self.vol_tracker = Some(VolTracker::new(
+                    self.vol_estimator,
+                    handling,
+                    gap,
+                    time,
+                    price,
+                ));
+            }
+        }
+    }
+
     fn update_vol(&mut self, world: &StepperWorld) {
-        const USE_WAP: bool = true;
-        if self.vol_tracker.is_none() {
-            if USE_WAP {
-                if world.wap_buf.is_empty() {
-                    return;
+        match self.vol_price_source {
+            VolPriceSource::Wap => {
+                for &(time, wap) in &world.wap_buf {
+                    self.add_vol_sample(time, wap);
                 }
-                self.vol_tracker = Some(
-                    TimeVolatility::new((60, 1000), &(world.wap_buf[0].0, world.wap_buf[0].1))
-                        .unwrap(),
-                );
-            } else {
-                if world.trade_buf.is_empty() {
-                    return;
+            }
+            VolPriceSource::Mid => {
+                for &(time, mid) in &world.mid_buf {
+                    self.add_vol_sample(time, mid);
+                }
+            }
+            VolPriceSource::Trade => {
+                for trade in &world.trade_buf {
+                    self.add_vol_sample(trade.time, trade.price);
                 }
-                self.vol_tracker = Some(
-                    TimeVolatility::new(
-                        (60, 1000),
-                        &(world.trade_buf[0].time, world.trade_buf[0].price),
-                    )
-                    .unwrap(),
-                );
             }
         }
-        if USE_WAP {
-            world.trade_buf.iter().for_each(|trade| {
-                self.vol_tracker
-                    .as_mut()
-                    .unwrap()
-                    .next(&(trade.time, trade.price));
+        if self.vol_tracker.is_none() {
+            return;
+        }
+
+        if self.debug_log {
+            self.debug_log_buf.push(DebugRecord::Vol {
+                time_ms: world.now.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+                vol: self.vol_tracker.as_ref().unwrap().value(),
             });
-        } else {
-            world.wap_buf.iter().for_each(|(time, price)| {
-                self.vol_tracker.as_mut().unwrap().next(&(*time, *price));
+        }
+    }
+
+    // keep one open order on the quote side if it is close enough, cancel the rest
+    fn diff_quote(
+        &mut self,
+        world: &StepperWorld,
+        side: &TradeSide,
+        quote: Option<Order>,
+        tolerance: QuoteTolerance,
+    ) {
+        let mut open_orders = world
+            .order_tracker
+            .live_orders(side)
+            .filter(|order| !order.order_id.starts_with(REDUCE_ORDER_PREFIX))
+            .collect::<Vec<_>>();
+        open_orders.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.order_id.cmp(&b.order_id))
+        });
+        let mut kept = quote.is_none();
+        for order in open_orders {
+            let within_tolerance = quote.as_ref().is_some_and(|quote| {
+                (order.price - quote.price).abs() <= tolerance.price
+                    && (order.remaining_quantity() - quote.quantity).abs() <= tolerance.quantity
             });
+            if within_tolerance && !kept {
+                kept = true;
+                continue;
+            }
+            self.actions.push(Action::CancelOrder(CancelOrder {
+                symbol: self.symbol,
+                order_id: order.order_id.clone(),
+            }));
         }
+        if let Some(quote) = quote.filter(|_| !kept) {
+            let time_in_force = self.time_in_force(&quote, world.now);
+            self.actions
+                .push(convert_order_to_action(self.symbol, quote, time_in_force));
+        }
+    }
 
-        if ENABLE_VOL_DEBUG {
-            self.ts_seq
-                .push(world.now.duration_since(UNIX_EPOCH).unwrap().as_millis() as i64);
-            self.vol_seq.push(self.vol_tracker.as_ref().unwrap().peek())
+    // placed as a good til date order, the quotes kept within the tolerance are not
+    fn expires_on_exchange(&self, order_id: &str) -> bool {
+        self.exchange_expiry
+            && (self.quote_tolerance.is_none() || order_id.starts_with(REDUCE_ORDER_PREFIX))
+    }
+
+    fn time_in_force(&self, order: &Order, now: SystemTime) -> TimeInForce {
+        if self.expires_on_exchange(&order.order_id) {
+            TimeInForce::GoodTilDate(now + Duration::from_millis(MM_ORDER_EXPIRE_MILLSECONDS))
+        } else {
+            TimeInForce::GoodTilCancelled
         }
     }
 
+    fn cancel_expired_orders(&mut self, world: &StepperWorld, filter: impl Fn(&Order) -> bool) {
+        let now = world.now;
+        for order in world.order_tracker.iter().filter(|order| filter(order)) {
+            if order.status == OrderStatus::CancelRequested
+                || self.expires_on_exchange(&order.order_id)
+            {
+                continue;
+            }
+            let order_exist_duration = now.duration_since(order.created_at);
+            if order_exist_duration.is_err() {
+                continue;
+            }
+            let order_exist_duration = order_exist_duration.unwrap();
+            if order_exist_duration.as_millis() as u64 > MM_ORDER_EXPIRE_MILLSECONDS {
+                self.actions.push(Action::CancelOrder(CancelOrder {
+                    symbol: self.symbol,
+                    order_id: order.order_id.clone(),
+                }));
+            }
+        }
+    }
+
+    fn cancel_all_orders(&mut self, world: &StepperWorld) {
+        for order in world.order_tracker.iter() {
+            if order.status == OrderStatus::CancelRequested {
+                continue;
+            }
+            self.actions.push(Action::CancelOrder(CancelOrder {
+                symbol: self.symbol,
+                order_id: order.order_id.clone(),
+            }));
+        }
+    }
+
+    // cancel every order, and with flatten take the book for the inventory away from target
+    pub fn shutdown(&mut self, world: &StepperWorld, flatten: bool) {
+        self.actions.clear();
+        self.cancel_all_orders(world);
+        let book = self.book(world);
+        // nothing to flatten before the initial position is known or without a book
+        if !flatten
+            || self.intial_position == 0.0
+            || book.best_bid_price == 0.0
+            || book.best_ask_price == 0.0
+        {
+            return;
+        }
+        let inventory = self.calc_q_base(world);
+        if inventory == 0.0 {
+            return;
+        }
+        let (side, price) = if inventory > 0.0 {
+            (TradeSide::Sell, book.best_bid_price)
+        } else {
+            (TradeSide::Buy, book.best_ask_price)
+        };
+        info!("flattening inventory={inventory:.5} on shutdown");
+        self.on_event("flattened_on_shutdown");
+        self.actions.push(Action::PlaceOrder(PlaceOrderData {
+            symbol: self.symbol,
+            order_id: format!("{FLATTEN_ORDER_PREFIX}{}", self.uniq_quote_round),
+            price,
+            side,
+            quantity: inventory.abs(),
+            trade_type: TradeType::Market,
+            time_in_force: TimeInForce::GoodTilCancelled,
+        }));
+    }
+
+    // inventory is away from target in base asset quantity
+    fn update_inventory_cap(&mut self, inventory: f64) -> Option<InventoryCap> {
+        let limits = self.inventory_limits?;
+        let cap = if inventory >= limits.max_long {
+            Some(InventoryCap::Long)
+        } else if inventory <= -limits.max_short {
+            Some(InventoryCap::Short)
+        } else {
+            None
+        };
+        if cap.is_some() && cap != self.inventory_cap {
+            let event = match cap {
+                Some(InventoryCap::Long) => "inventory_long_cap_hit",
+                _ => "inventory_short_cap_hit",
+            };
+            tracing::warn!("{event}: inventory={inventory:.5}");
+            self.on_event(event);
+        }
+        self.inventory_cap = cap;
+        cap
+    }
+
+    // (buy, sell) base asset quantity each side may add before its inventory limit, or before
+    // the balance it spends without one
+    fn sizing_headroom(&self, world: &StepperWorld, inventory: f64, price: f64) -> (f64, f64) {
+        let balance = |asset| {
+            world
+                .account
+                .asset_to_balance
+                .get(asset)
+                .map_or(0.0, |b| b.balance)
+        };
+        let limits = self.inventory_limits.unwrap_or(InventoryLimits {
+            max_long: f64::INFINITY,
+            max_short: f64::INFINITY,
+            reduce_aggressively: false,
+        });
+        let buy = if limits.max_long.is_finite() {
+            limits.max_long - inventory
+        } else {
+            balance(self.quote_asset) / price
+        };
+        let sell = if limits.max_short.is_finite() {
+            limits.max_short + inventory
+        } else {
+            balance(self.base_asset)
+        };
+        (buy, sell)
+    }
+
+    // order crossing the spread to bring inventory back within the limits
+    fn reduce_inventory_order(
+        &self,
+        world: &StepperWorld,
+        inventory: f64,
+        cap: Option<InventoryCap>,
+        uniq_token: u64,
+    ) -> Option<Order> {
+        let limits = self.inventory_limits.filter(|l| l.reduce_aggressively)?;
+        let book = self.book(world);
+        let (side, price, quantity) = match cap? {
+            InventoryCap::Long => (
+                TradeSide::Sell,
+                book.best_bid_price,
+                inventory - limits.max_long,
+            ),
+            InventoryCap::Short => (
+                TradeSide::Buy,
+                book.best_ask_price,
+                -inventory - limits.max_short,
+            ),
+        };
+        // the whole lots over the limit, less than a lot over is left as it is
+        let quantity = snap_to_lot(quantity, self.lot_size);
+        // wait for the previous one to be filled or expired
+        let reducing = world
+            .order_tracker
+            .live_orders(&side)
+            .any(|order| order.order_id.starts_with(REDUCE_ORDER_PREFIX));
+        if quantity <= 0.0 || reducing {
+            return None;
+        }
+        Some(Order {
+            order_id: format!("{REDUCE_ORDER_PREFIX}{uniq_token}"),
+            price,
+            side,
+            quantity,
+            filled: 0.0,
+            status: OrderStatus::Open,
+            created_at: world.now,
+        })
+    }
+
     fn vol(&self) -> f64 {
-        self.vol_tracker.as_ref().unwrap().peek()
+        self.vol_tracker.as_ref().unwrap().value()
     }
 
     // make_decision take world as input
     pub fn run(&mut self, world: &mut StepperWorld) {
         self.actions.clear();
         self.update_vol(world);
+        self.update_trade_intensity(world);
 
-        if ENABLE_VOL_DEBUG {
-            let filled_event_buf = std::mem::take(&mut world.filled_event_buf);
-            filled_event_buf.into_iter().for_each(|(order_id, filled)| {
-                self.fill_seq_order_id.push(order_id);
-                self.fill_seq_qty.push(filled);
-            });
+        if self.debug_log {
+            for (order_id, filled) in &world.filled_event_buf {
+                self.debug_log_buf.push(DebugRecord::Fill {
+                    order_id: order_id.clone(),
+                    filled: *filled,
+                });
+            }
         }
 
         if self.intial_position == 0.0 {
@@ -272,32 +845,66 @@ impl AmmStrategy {
                 );
             }
         }
-        if world.best_ask_price == 0.0
-            || world.best_bid_price == 0.0
+        let book = self.book(world);
+        if book.best_ask_price == 0.0
+            || book.best_bid_price == 0.0
             || world.latest_market_price == 0.0
             || self.vol_tracker.is_none()
         {
             info!("Wait for market data to be available.");
             return;
         }
+        // a reset across a gap in the data warms up again
+        if self.vol_warm_up && !self.vol_tracker.as_ref().is_some_and(|vol| vol.is_warm()) {
+            self.on_event("vol_warming_up");
+            self.cancel_all_orders(world);
+            return;
+        }
+        let degraded = world.data_quality.is_degraded();
+        if degraded && self.degraded_data == DegradedDataResponse::Pull {
+            self.on_event("quotes_pulled_on_degraded_data");
+            self.cancel_all_orders(world);
+            return;
+        }
 
-        const USE_WAP_AS_FAIR_PRICE: bool = true;
-        let fair_price = if USE_WAP_AS_FAIR_PRICE {
-            self.wap_price(world)
+        let fair_price = self.fair_price(world);
+        let vol = self.vol();
+        let (q, reservation_price, optimal_spread) = match &self.pricing_model {
+            PricingModel::Simple => {
+                let q = self.calc_q(world);
+                (q, fair_price - (q * self.gamma * vol), self.gamma * vol)
+            }
+            PricingModel::AvellanedaStoikov(params) => {
+                let k = match params.k.or_else(|| self.trade_intensity.k()) {
+                    Some(k) => k,
+                    None => {
+                        info!("Wait for trade intensity to be available.");
+                        return;
+                    }
+                };
+                let now_ms = world.now.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+                let time_left = params.time_left_fraction(now_ms);
+                let q = self.calc_q_base(world);
+                (
+                    q,
+                    params.reservation_price(fair_price, q, vol, time_left),
+                    params.optimal_spread(k, vol, time_left),
+                )
+            }
+        };
+        let optimal_spread = if degraded && self.degraded_data == DegradedDataResponse::Widen {
+            self.on_event("quotes_widened_on_degraded_data");
+            optimal_spread * DEGRADED_SPREAD_MULTIPLIER
         } else {
-            self.mid_price(world)
+            optimal_spread
         };
-        let q = self.calc_q(world);
-        let vol = self.vol();
-        let reservation_price = fair_price - (q * self.gamma * vol);
-        let optimal_spread = self.gamma * vol;
         tracing::trace!(
             "price={:.3} q={:.3} vol={:.3} res_price={:.3} spread={:.3} opt_spread={:.3}",
             fair_price,
             q,
             vol,
             reservation_price,
-            world.best_ask_price - world.best_bid_price,
+            book.best_ask_price - book.best_bid_price,
             optimal_spread
         );
 
@@ -314,9 +921,15 @@ impl AmmStrategy {
             self.intial_position
         );
 
-        // const MM_PRICE_SPREAD: f64 = 15.0;
-        const MM_QUANTITY: f64 = 0.01;
-        const MM_ORDER_EXPIRE_MILLSECONDS: u64 = 100;
+        let inventory = self.calc_q_base(world);
+        let (bid_quantity, ask_quantity) = self.quote_sizing.sizes(
+            &SizingContext {
+                fair_price,
+                vol,
+                headroom: self.sizing_headroom(world, inventory, fair_price),
+            },
+            self.lot_size,
+        );
         let now = world.now;
         let t_since_epoch = now
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -324,134 +937,686 @@ impl AmmStrategy {
             .as_millis();
         let uniq_token = self.uniq_quote_round;
         self.uniq_quote_round += 1;
+        let (bid_price, ask_price) = self.anchor_quotes(
+            world,
+            reservation_price - optimal_spread * 0.5,
+            reservation_price + optimal_spread * 0.5,
+        );
+        let Some((bid_price, ask_price)) = self.guard_quotes(bid_price, ask_price) else {
+            self.cancel_expired_orders(world, |_| true);
+            return;
+        };
         // make orders around latest price
         let (buy, sell) = (
             Order {
                 order_id: format!("B{}", uniq_token),
-                price: (reservation_price - optimal_spread * 0.5).min(world.best_bid_price),
+                price: bid_price,
                 side: TradeSide::Buy,
-                quantity: MM_QUANTITY,
+                quantity: bid_quantity,
                 filled: 0.0,
                 status: OrderStatus::Open,
                 created_at: now,
             },
             Order {
                 order_id: format!("S{}", uniq_token),
-                price: (reservation_price + optimal_spread * 0.5).max(world.best_ask_price),
+                price: ask_price,
                 side: TradeSide::Sell,
-                quantity: MM_QUANTITY,
+                quantity: ask_quantity,
                 filled: 0.0,
                 status: OrderStatus::Open,
                 created_at: now,
             },
         );
 
-        if ENABLE_VOL_DEBUG {
-            self.quote_seq.push(QuoteDebugLog {
-                time: t_since_epoch as i64,
-                price: buy.price,
-                qty: buy.quantity,
-                fair_price: self.mid_price(&world),
-                is_bid: true,
-                id: buy.order_id.clone(),
-                best_bid_price: world.best_bid_price,
-                best_bid_qty: world.best_bid_qty,
-                best_ask_price: world.best_ask_price,
-                best_ask_qty: world.best_ask_qty,
-            });
-            self.quote_seq.push(QuoteDebugLog {
-                time: t_since_epoch as i64,
-                price: sell.price,
-                qty: sell.quantity,
-                fair_price: self.mid_price(&world),
-                is_bid: false,
-                id: sell.order_id.clone(),
-                best_bid_price: world.best_bid_price,
-                best_bid_qty: world.best_bid_qty,
-                best_ask_price: world.best_ask_price,
-                best_ask_qty: world.best_ask_qty,
-            });
+        if self.debug_log {
+            let fair_price = self.mid_price(world);
+            for order in [&buy, &sell] {
+                self.debug_log_buf.push(DebugRecord::Quote(QuoteDebug {
+                    time_ms: t_since_epoch as u64,
+                    order_id: order.order_id.clone(),
+                    is_bid: order.side == TradeSide::Buy,
+                    price: order.price,
+                    qty: order.quantity,
+                    fair_price,
+                    best_bid_price: book.best_bid_price,
+                    best_bid_qty: book.best_bid_qty,
+                    best_ask_price: book.best_ask_price,
+                    best_ask_qty: book.best_ask_qty,
+                }));
+            }
         }
-
         tracing::trace!(
             "bid={:.3} ask={:.3} quote_bid={:.3} quote_ask={:.3}",
-            world.best_bid_price,
-            world.best_ask_price,
-            world.best_bid_price - buy.price,
-            sell.price - world.best_ask_price
+            book.best_bid_price,
+            book.best_ask_price,
+            book.best_bid_price - buy.price,
+            sell.price - book.best_ask_price
         );
 
+        // stop quoting the side adding risk once inventory is over the limits
+        let inventory_cap = self.update_inventory_cap(inventory);
+        let buy = (inventory_cap != Some(InventoryCap::Long)).then_some(buy);
+        let sell = (inventory_cap != Some(InventoryCap::Short)).then_some(sell);
+        // nor a side sized under one lot
+        let (buy, sell) = (
+            buy.filter(|order| order.quantity > 0.0),
+            sell.filter(|order| order.quantity > 0.0),
+        );
+        if bid_quantity <= 0.0 || ask_quantity <= 0.0 {
+            self.on_event("quote_size_under_lot");
+        }
+        self.debug_buf.push(StrategyDebug {
+            symbol: self.symbol,
+            vol,
+            fair_price,
+            reservation_price,
+            bid: bid_price,
+            ask: ask_price,
+            best_bid: book.best_bid_price,
+            best_ask: book.best_ask_price,
+            base_balance: base_asset_balance.balance,
+            initial_position: self.intial_position,
+            low_water_level,
+            high_water_level,
+            quoting_bid: buy.is_some(),
+            quoting_ask: sell.is_some(),
+        });
+        if let Some(order) =
+            self.reduce_inventory_order(world, inventory, inventory_cap, uniq_token)
+        {
+            let time_in_force = self.time_in_force(&order, world.now);
+            self.actions
+                .push(convert_order_to_action(self.symbol, order, time_in_force));
+        }
+
+        if let Some(tolerance) = self.quote_tolerance {
+            self.diff_quote(world, &TradeSide::Buy, buy, tolerance);
+            self.diff_quote(world, &TradeSide::Sell, sell, tolerance);
+            self.cancel_expired_orders(world, |order| {
+                order.order_id.starts_with(REDUCE_ORDER_PREFIX)
+            });
+            return;
+        }
+
         // put order
-        self.actions.push(convert_order_to_action(self.symbol, buy));
-        self.actions
-            .push(convert_order_to_action(self.symbol, sell));
+        for order in buy.into_iter().chain(sell) {
+            let time_in_force = self.time_in_force(&order, world.now);
+            self.actions
+                .push(convert_order_to_action(self.symbol, order, time_in_force));
+        }
 
         // clear expired orders
-        for order in world.order_tracker.iter() {
-            if order.status == OrderStatus::CancelRequested {
-                continue;
-            }
-            let order_exist_duration = now.duration_since(order.created_at);
-            if order_exist_duration.is_err() {
-                continue;
-            }
-            let order_exist_duration = order_exist_duration.unwrap();
-            if order_exist_duration.as_millis() as u64 > MM_ORDER_EXPIRE_MILLSECONDS {
-                self.actions.push(Action::CancelOrder(CancelOrder {
-                    symbol: self.symbol,
-                    order_id: order.order_id.clone(),
-                }));
+        self.cancel_expired_orders(world, |_| true);
+    }
+
+    pub fn terminate(&mut self) {
+        if !self.event_count.is_empty() {
+            println!("--- Strategy Events ---");
+            for (event, count) in &self.event_count {
+                println!("{}: {}", event, count);
             }
         }
     }
+}
 
-    pub fn terminate(&mut self) {
-        if ENABLE_VOL_DEBUG {
-            let debug_vol_file_path = "data/vol.parquet";
-            println!("DebugVol write to {debug_vol_file_path}");
-            let mut vol_df = df!(
-                "time" => std::mem::take(&mut self.ts_seq),
-                "vol" => std::mem::take(&mut self.vol_seq)
-            )
+#[cfg(test)]
+mod tests {
+    use upstair_type::data::market::BinanceTradeTick;
+
+    use super::*;
+
+    fn fixture_world() -> StepperWorld {
+        let mut world = StepperWorld::default();
+        world.markets.insert(
+            "BTCUSDT",
+            MarketSnapshot {
+                best_bid_price: 100.0,
+                best_bid_qty: 3.0,
+                best_ask_price: 101.0,
+                best_ask_qty: 1.0,
+                ..Default::default()
+            },
+        );
+        // wap moves every second, mid twice as much, while trades print at a constant price
+        for i in 0..10u64 {
+            world.wap_buf.push((i * 1000, 100.0 + (i % 2) as f64));
+            world.mid_buf.push((i * 1000, 100.0 + 2.0 * (i % 2) as f64));
+            world.trade_buf.push(BinanceTradeTick {
+                id: i,
+                price: 50.0,
+                qty: 1.0,
+                base_qty: 50.0,
+                time: i * 1000,
+                is_buyer_maker: false,
+                symbol: "BTCUSDT",
+            });
+        }
+        world
+    }
+
+    fn fixture_strategy() -> AmmStrategy {
+        AmmStrategy::new(
+            "BTCUSDT",
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+        )
+    }
+
+    #[test]
+    fn test_vol_from_wap() {
+        let mut strategy = fixture_strategy().with_vol_price_source(VolPriceSource::Wap);
+        strategy.update_vol(&fixture_world());
+        assert!(strategy.vol() > 0.0);
+    }
+
+    #[test]
+    fn test_vol_from_mid() {
+        let mut wap = fixture_strategy().with_vol_price_source(VolPriceSource::Wap);
+        wap.update_vol(&fixture_world());
+        let mut mid = fixture_strategy().with_vol_price_source(VolPriceSource::Mid);
+        mid.update_vol(&fixture_world());
+        assert!((mid.vol() - 2.0 * wap.vol()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_vol_from_trade() {
+        let mut strategy = fixture_strategy().with_vol_price_source(VolPriceSource::Trade);
+        strategy.update_vol(&fixture_world());
+        assert_eq!(strategy.vol(), 0.0);
+    }
+
+    #[test]
+    fn test_vol_waits_for_samples() {
+        let mut strategy = fixture_strategy().with_vol_price_source(VolPriceSource::Wap);
+        let mut world = fixture_world();
+        world.wap_buf.clear();
+        strategy.update_vol(&world);
+        assert!(strategy.vol_tracker.is_none());
+    }
+
+    #[test]
+    fn test_fair_price_source() {
+        let world = fixture_world();
+        let strategy = fixture_strategy().with_fair_price_source(FairPriceSource::Wap);
+        assert_eq!(strategy.fair_price(&world), 100.75);
+        let strategy = fixture_strategy().with_fair_price_source(FairPriceSource::Mid);
+        assert_eq!(strategy.fair_price(&world), 100.5);
+        let mut world = world;
+        let strategy = fixture_strategy().with_fair_price_source(FairPriceSource::Microprice);
+        assert_eq!(strategy.fair_price(&world), 100.75);
+        world.signals.insert(SignalKind::Microprice, 100.6);
+        assert_eq!(strategy.fair_price(&world), 100.6);
+        let strategy = fixture_strategy().with_fair_price_source(FairPriceSource::Mark);
+        assert_eq!(strategy.fair_price(&world), 100.75);
+        world.mark_price = Some(100.4);
+        assert_eq!(strategy.fair_price(&world), 100.4);
+    }
+
+    fn fixture_order(order_id: &str, side: TradeSide, price: f64, quantity: f64) -> Order {
+        Order {
+            order_id: order_id.into(),
+            price,
+            side,
+            quantity,
+            filled: 0.0,
+            status: OrderStatus::Open,
+            created_at: UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_diff_quote() {
+        let tolerance = QuoteTolerance {
+            price: 0.5,
+            quantity: 0.001,
+        };
+        let mut world = fixture_world();
+        world
+            .order_tracker
+            .upsert_order(fixture_order("B0", TradeSide::Buy, 99.0, 0.01));
+        world
+            .order_tracker
+            .upsert_order(fixture_order("S0", TradeSide::Sell, 102.0, 0.01));
+        let mut strategy = fixture_strategy();
+
+        // within tolerance, the open order is kept
+        strategy.diff_quote(
+            &world,
+            &TradeSide::Buy,
+            Some(fixture_order("B1", TradeSide::Buy, 99.4, 0.01)),
+            tolerance,
+        );
+        assert!(strategy.actions.is_empty());
+
+        // price moved too far, the open order is replaced
+        strategy.diff_quote(
+            &world,
+            &TradeSide::Sell,
+            Some(fixture_order("S1", TradeSide::Sell, 103.0, 0.01)),
+            tolerance,
+        );
+        assert_eq!(strategy.actions.len(), 2);
+        assert!(matches!(&strategy.actions[0], Action::CancelOrder(c) if c.order_id == "S0"));
+        assert!(matches!(&strategy.actions[1], Action::PlaceOrder(p) if p.order_id == "S1"));
+    }
+
+    #[test]
+    fn test_exchange_expiry() {
+        let mut world = fixture_world();
+        world.now = UNIX_EPOCH + Duration::from_secs(1);
+        world
+            .order_tracker
+            .upsert_order(fixture_order("B0", TradeSide::Buy, 99.0, 0.01));
+        let mut strategy = fixture_strategy();
+        strategy.cancel_expired_orders(&world, |_| true);
+        assert_eq!(strategy.actions.len(), 1);
+
+        // the exchange expires the order at its deadline
+        let mut strategy = fixture_strategy().with_exchange_expiry(true);
+        strategy.cancel_expired_orders(&world, |_| true);
+        assert!(strategy.actions.is_empty());
+        let quote = fixture_order("B1", TradeSide::Buy, 99.0, 0.01);
+        assert!(matches!(
+            strategy.time_in_force(&quote, world.now),
+            TimeInForce::GoodTilDate(at) if at == world.now + Duration::from_millis(100)
+        ));
+
+        // quotes kept within the tolerance stay until canceled, the reduce orders expire
+        let strategy = strategy.with_quote_tolerance(Some(QuoteTolerance::default()));
+        assert!(matches!(
+            strategy.time_in_force(&quote, world.now),
+            TimeInForce::GoodTilCancelled
+        ));
+        let reduce = fixture_order("R1", TradeSide::Buy, 99.0, 0.01);
+        assert!(matches!(
+            strategy.time_in_force(&reduce, world.now),
+            TimeInForce::GoodTilDate(_)
+        ));
+    }
+
+    #[test]
+    fn test_diff_quote_cancels_duplicates() {
+        let tolerance = QuoteTolerance {
+            price: 0.5,
+            quantity: 0.001,
+        };
+        let mut world = fixture_world();
+        let mut older = fixture_order("B0", TradeSide::Buy, 99.0, 0.01);
+        older.created_at = UNIX_EPOCH;
+        let mut newer = fixture_order("B1", TradeSide::Buy, 99.0, 0.01);
+        newer.created_at = UNIX_EPOCH + std::time::Duration::from_millis(100);
+        world.order_tracker.upsert_order(newer);
+        world.order_tracker.upsert_order(older);
+        let mut strategy = fixture_strategy();
+        strategy.diff_quote(
+            &world,
+            &TradeSide::Buy,
+            Some(fixture_order("B2", TradeSide::Buy, 99.0, 0.01)),
+            tolerance,
+        );
+        assert_eq!(strategy.actions.len(), 1);
+        assert!(matches!(&strategy.actions[0], Action::CancelOrder(c) if c.order_id == "B1"));
+
+        // side not quoted, open orders are cancelled
+        strategy.actions.clear();
+        strategy.diff_quote(&world, &TradeSide::Buy, None, tolerance);
+        assert_eq!(strategy.actions.len(), 2);
+    }
+
+    fn fixture_limits(reduce_aggressively: bool) -> InventoryLimits {
+        InventoryLimits {
+            max_long: 0.5,
+            max_short: 0.2,
+            reduce_aggressively,
+        }
+    }
+
+    #[test]
+    fn test_inventory_cap_event_on_crossing() {
+        let mut strategy = fixture_strategy().with_inventory_limits(Some(fixture_limits(false)));
+        assert_eq!(strategy.update_inventory_cap(0.1), None);
+        assert_eq!(strategy.update_inventory_cap(0.6), Some(InventoryCap::Long));
+        assert_eq!(strategy.update_inventory_cap(0.7), Some(InventoryCap::Long));
+        assert_eq!(
+            strategy.update_inventory_cap(-0.2),
+            Some(InventoryCap::Short)
+        );
+        assert_eq!(strategy.update_inventory_cap(0.0), None);
+        assert_eq!(strategy.update_inventory_cap(0.5), Some(InventoryCap::Long));
+        assert_eq!(strategy.event_count.get("inventory_long_cap_hit"), Some(&2));
+        assert_eq!(
+            strategy.event_count.get("inventory_short_cap_hit"),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn test_reduce_inventory_order() {
+        let mut world = fixture_world();
+        let strategy = fixture_strategy().with_inventory_limits(Some(fixture_limits(true)));
+        let order = strategy
+            .reduce_inventory_order(&world, 0.8, Some(InventoryCap::Long), 7)
             .unwrap();
-            let mut parquet_file = std::fs::File::create(debug_vol_file_path).unwrap();
-            ParquetWriter::new(&mut parquet_file)
-                .finish(&mut vol_df)
-                .unwrap();
-
-            let debug_quote_file_path = "data/quote.parquet";
-            let quote_seq = std::mem::take(&mut self.quote_seq);
-            let mut quote_seq_df = struct_to_dataframe!(
-                quote_seq,
-                [
-                    time,
-                    price,
-                    qty,
-                    fair_price,
-                    is_bid,
-                    id,
-                    best_bid_price,
-                    best_bid_qty,
-                    best_ask_price,
-                    best_ask_qty
-                ]
-            )
+        assert_eq!(order.order_id, "R7");
+        assert_eq!(order.side, TradeSide::Sell);
+        assert_eq!(order.price, world.book("BTCUSDT").best_bid_price);
+        assert!((order.quantity - 0.3).abs() < 1e-12);
+        let order = strategy
+            .reduce_inventory_order(&world, -0.5, Some(InventoryCap::Short), 8)
             .unwrap();
-            let mut parquet_file = std::fs::File::create(debug_quote_file_path).unwrap();
-            ParquetWriter::new(&mut parquet_file)
-                .finish(&mut quote_seq_df)
-                .unwrap();
-
-            let trade_file_path = "data/trade.parquet";
-            let mut trade_df = df!(
-                "order_id" => std::mem::take(&mut self.fill_seq_order_id),
-                "filled" => std::mem::take(&mut self.fill_seq_qty),
-            )
+        assert_eq!(order.side, TradeSide::Buy);
+        assert_eq!(order.price, world.book("BTCUSDT").best_ask_price);
+        assert!(strategy
+            .reduce_inventory_order(&world, 0.3, None, 9)
+            .is_none());
+        // snapped to the lot size of 0.001
+        let order = strategy
+            .reduce_inventory_order(&world, 0.8237, Some(InventoryCap::Long), 12)
             .unwrap();
-            let mut parquet_file = std::fs::File::create(trade_file_path).unwrap();
-            ParquetWriter::new(&mut parquet_file)
-                .finish(&mut trade_df)
-                .unwrap();
+        assert_eq!(order.quantity, 0.323);
+        assert!(strategy
+            .reduce_inventory_order(&world, 0.5004, Some(InventoryCap::Long), 13)
+            .is_none());
+
+        // one reducing order at a time
+        world
+            .order_tracker
+            .upsert_order(fixture_order("R7", TradeSide::Sell, 100.0, 0.3));
+        assert!(strategy
+            .reduce_inventory_order(&world, 0.8, Some(InventoryCap::Long), 10)
+            .is_none());
+
+        let strategy = fixture_strategy().with_inventory_limits(Some(fixture_limits(false)));
+        assert!(strategy
+            .reduce_inventory_order(&world, -0.5, Some(InventoryCap::Short), 11)
+            .is_none());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut world = fixture_world();
+        world
+            .order_tracker
+            .upsert_order(fixture_order("B0", TradeSide::Buy, 99.0, 0.01));
+        for (asset, balance) in [("BTC", 3.0), ("USDT", 100.5)] {
+            world
+                .account
+                .asset_to_balance
+                .entry(asset)
+                .or_default()
+                .balance = balance;
         }
+        let mut strategy = fixture_strategy();
+        // the initial position is not known yet
+        strategy.shutdown(&world, true);
+        assert_eq!(strategy.actions.len(), 1);
+
+        // 4 BTC worth, 1 over the target half
+        strategy.intial_position = 2.0;
+        strategy.shutdown(&world, false);
+        assert_eq!(strategy.actions.len(), 1);
+        strategy.shutdown(&world, true);
+        assert_eq!(strategy.actions.len(), 2);
+        assert!(matches!(&strategy.actions[0], Action::CancelOrder(c) if c.order_id == "B0"));
+        let Action::PlaceOrder(flatten) = &strategy.actions[1] else {
+            panic!("expected a flattening order");
+        };
+        assert_eq!(flatten.side, TradeSide::Sell);
+        assert_eq!(flatten.price, world.book("BTCUSDT").best_bid_price);
+        assert!((flatten.quantity - 1.0).abs() < 1e-12);
+        assert!(matches!(flatten.trade_type, TradeType::Market));
+    }
+
+    #[test]
+    fn test_anchor_quotes() {
+        let mut world = fixture_world();
+        let strategy = fixture_strategy().with_quote_anchoring(QuoteAnchoring::Model);
+        assert_eq!(strategy.anchor_quotes(&world, 100.5, 100.6), (100.5, 100.6));
+        let strategy = fixture_strategy().with_quote_anchoring(QuoteAnchoring::Touch);
+        assert_eq!(strategy.anchor_quotes(&world, 100.5, 100.6), (100.0, 101.0));
+        assert_eq!(strategy.anchor_quotes(&world, 99.0, 102.0), (99.0, 102.0));
+
+        let strategy = fixture_strategy()
+            .with_quote_anchoring(QuoteAnchoring::Improve)
+            .with_price_tick(0.1);
+        let (bid, ask) = strategy.anchor_quotes(&world, 100.5, 100.6);
+        assert!((bid - 100.1).abs() < 1e-9 && (ask - 100.9).abs() < 1e-9);
+        assert_eq!(strategy.anchor_quotes(&world, 99.0, 102.0), (99.0, 102.0));
+        // a single tick spread is joined
+        world.markets.get_mut("BTCUSDT").unwrap().best_ask_price = 100.1;
+        assert_eq!(strategy.anchor_quotes(&world, 100.5, 99.5), (100.0, 100.1));
+    }
+
+    #[test]
+    fn test_parse_price_source() {
+        assert_eq!("WAP".parse(), Ok(FairPriceSource::Wap));
+        assert_eq!("mid".parse(), Ok(FairPriceSource::Mid));
+        assert_eq!("Microprice".parse(), Ok(FairPriceSource::Microprice));
+        assert_eq!("mark".parse(), Ok(FairPriceSource::Mark));
+        assert!("last".parse::<FairPriceSource>().is_err());
+        assert_eq!("trade".parse(), Ok(VolPriceSource::Trade));
+        assert_eq!("mid".parse(), Ok(VolPriceSource::Mid));
+        assert!("last".parse::<VolPriceSource>().is_err());
+        assert_eq!("Improve".parse(), Ok(QuoteAnchoring::Improve));
+        assert!("join".parse::<QuoteAnchoring>().is_err());
+        assert_eq!("PULL".parse(), Ok(DegradedDataResponse::Pull));
+        assert!("stop".parse::<DegradedDataResponse>().is_err());
+    }
+
+    #[test]
+    fn test_guard_quotes() {
+        let mut strategy = fixture_strategy().with_price_tick(0.1);
+        assert_eq!(strategy.guard_quotes(100.0, 100.2), Some((100.0, 100.2)));
+        let (bid, ask) = strategy.guard_quotes(100.3, 100.1).unwrap();
+        assert!((bid - 100.15).abs() < 1e-9 && (ask - 100.25).abs() < 1e-9);
+        assert!(strategy
+            .guard_quotes(100.0, 100.0)
+            .is_some_and(|(b, a)| b < a));
+        assert_eq!(strategy.guard_quotes(f64::NAN, 100.0), None);
+        assert_eq!(strategy.guard_quotes(f64::NEG_INFINITY, 100.0), None);
+        assert_eq!(
+            strategy.event_count.get("crossing_quote_repaired"),
+            Some(&2)
+        );
+        assert_eq!(strategy.event_count.get("invalid_quote_skipped"), Some(&2));
+    }
+
+    #[test]
+    fn test_no_crossing_quotes_under_extreme_vol() {
+        use crate::harness::{ScriptedStep, StrategyHarness};
+
+        // a negative k gives a negative spread, the market swings by half its price
+        let strategy = fixture_strategy()
+            .with_quote_anchoring(QuoteAnchoring::Model)
+            .with_pricing_model(PricingModel::AvellanedaStoikov(AvellanedaStoikovParams {
+                gamma: 0.1,
+                k: Some(-0.5),
+                horizon_ms: 60 * 1000,
+            }));
+        let mut harness = StrategyHarness::new(strategy)
+            .with_balance("BTC", 1.0)
+            .with_balance("USDT", 100.0);
+        let script: Vec<_> = (1..40)
+            .map(|i| {
+                let price = if i % 2 == 0 { 100.0 } else { 150.0 };
+                ScriptedStep::at_ms(i * 100)
+                    .with_book(price, 1.0, price + 0.1, 1.0)
+                    .with_trade(price, 0.1)
+            })
+            .collect();
+        let mut quoted = 0;
+        for actions in harness.run(&script) {
+            let price = |side: TradeSide| {
+                actions.iter().find_map(|action| match action {
+                    Action::PlaceOrder(p) if p.side == side => Some(p.price),
+                    _ => None,
+                })
+            };
+            if let (Some(bid), Some(ask)) = (price(TradeSide::Buy), price(TradeSide::Sell)) {
+                assert!(bid < ask, "crossing quotes {bid} {ask}");
+                quoted += 1;
+            }
+        }
+        assert!(quoted > 0);
+        assert!(harness
+            .strategy
+            .event_count
+            .contains_key("crossing_quote_repaired"));
+    }
+
+    #[test]
+    fn test_vol_warm_up() {
+        use crate::harness::{ScriptedStep, StrategyHarness};
+
+        let mut harness = StrategyHarness::new(fixture_strategy().with_vol_warm_up(true))
+            .with_balance("BTC", 1.0)
+            .with_balance("USDT", 100.0);
+        let script: Vec<_> = (0..70)
+            .map(|i| {
+                let price = 100.0 + (i % 3) as f64;
+                ScriptedStep::at_ms(i * 1000)
+                    .with_book(price, 1.0, price + 0.1, 1.0)
+                    .with_trade(price, 0.1)
+            })
+            .collect();
+        let first_quote = harness.run(&script).iter().position(|actions| {
+            actions
+                .iter()
+                .any(|action| matches!(action, Action::PlaceOrder(_)))
+        });
+        // the samples span the window of 60s at the 61st second
+        assert_eq!(first_quote, Some(60));
+        assert_eq!(
+            harness.strategy.event_count.get("vol_warming_up"),
+            Some(&60)
+        );
+    }
+
+    #[test]
+    fn test_degraded_data_response() {
+        use crate::harness::{ScriptedStep, StrategyHarness};
+        use upstair_type::control::DataQualityFlags;
+
+        let stale = DataQualityFlags {
+            stale_book: true,
+            ..Default::default()
+        };
+        let quote_spread = |response: DegradedDataResponse, flags: DataQualityFlags| {
+            let strategy = fixture_strategy()
+                .with_quote_anchoring(QuoteAnchoring::Model)
+                .with_pricing_model(PricingModel::AvellanedaStoikov(AvellanedaStoikovParams {
+                    gamma: 0.1,
+                    k: Some(1.0),
+                    horizon_ms: 60 * 1000,
+                }))
+                .with_degraded_data_response(response);
+            let mut harness = StrategyHarness::new(strategy)
+                .with_balance("BTC", 1.0)
+                .with_balance("USDT", 100.0);
+            let actions = harness.step(
+                &ScriptedStep::at_ms(100)
+                    .with_book(100.0, 1.0, 101.0, 1.0)
+                    .with_trade(100.5, 0.1)
+                    .with_data_quality(flags),
+            );
+            let price = |side: TradeSide| {
+                actions.iter().find_map(|action| match action {
+                    Action::PlaceOrder(p) if p.side == side => Some(p.price),
+                    _ => None,
+                })
+            };
+            Some(price(TradeSide::Sell)? - price(TradeSide::Buy)?)
+        };
+        let spread =
+            quote_spread(DegradedDataResponse::Widen, DataQualityFlags::default()).unwrap();
+        assert_eq!(
+            quote_spread(DegradedDataResponse::Ignore, stale),
+            Some(spread)
+        );
+        let widened = quote_spread(DegradedDataResponse::Widen, stale).unwrap();
+        assert!((widened - spread * DEGRADED_SPREAD_MULTIPLIER).abs() < 1e-9);
+        assert_eq!(quote_spread(DegradedDataResponse::Pull, stale), None);
+
+        // open quotes are pulled at once and quoting resumes with the data
+        let strategy = fixture_strategy().with_degraded_data_response(DegradedDataResponse::Pull);
+        let mut harness = StrategyHarness::new(strategy)
+            .with_balance("BTC", 1.0)
+            .with_balance("USDT", 100.0);
+        let actions = harness.run(&[
+            ScriptedStep::at_ms(100)
+                .with_book(100.0, 1.0, 101.0, 1.0)
+                .with_trade(100.5, 0.1),
+            ScriptedStep::at_ms(150).with_data_quality(stale),
+            ScriptedStep::at_ms(300).with_data_quality(DataQualityFlags::default()),
+        ]);
+        assert_eq!(actions[0].len(), 2);
+        assert!(actions[1]
+            .iter()
+            .all(|action| matches!(action, Action::CancelOrder(_))));
+        assert_eq!(actions[1].len(), 2);
+        assert!(actions[2]
+            .iter()
+            .any(|action| matches!(action, Action::PlaceOrder(_))));
+        assert_eq!(
+            harness
+                .strategy
+                .event_count
+                .get("quotes_pulled_on_degraded_data"),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn test_quote_sizing() {
+        use crate::harness::{ScriptedStep, StrategyHarness};
+
+        // (buy, sell) quantities quoted at a wap and mid of 100.5
+        let quoted = |strategy: AmmStrategy| {
+            let mut harness = StrategyHarness::new(strategy)
+                .with_balance("BTC", 1.0)
+                .with_balance("USDT", 100.0);
+            let actions = harness.step(
+                &ScriptedStep::at_ms(100)
+                    .with_book(100.0, 1.0, 101.0, 1.0)
+                    .with_trade(100.5, 0.1),
+            );
+            let quantity = |side: TradeSide| {
+                actions.iter().find_map(|action| match action {
+                    Action::PlaceOrder(p) if p.side == side => Some(p.quantity),
+                    _ => None,
+                })
+            };
+            let quote_size_under_lot = harness.strategy.event_count.get("quote_size_under_lot");
+            (
+                quantity(TradeSide::Buy),
+                quantity(TradeSide::Sell),
+                quote_size_under_lot.copied(),
+            )
+        };
+        let near = |quantity: Option<f64>, expected: f64| {
+            assert!((quantity.unwrap() - expected).abs() < 1e-12, "{quantity:?}");
+        };
+
+        assert_eq!(quoted(fixture_strategy()), (Some(0.01), Some(0.01), None));
+        // half of the 0.995 BTC the USDT buys and of the 1 BTC held
+        let headroom = QuoteSizing::Headroom { fraction: 0.5 };
+        let (buy, sell, _) = quoted(fixture_strategy().with_quote_sizing(headroom));
+        near(buy, 0.497);
+        near(sell, 0.5);
+        // half of the room to the limits of an inventory on target
+        let (buy, sell, _) = quoted(
+            fixture_strategy()
+                .with_quote_sizing(headroom)
+                .with_inventory_limits(Some(fixture_limits(false))),
+        );
+        near(buy, 0.25);
+        near(sell, 0.1);
+        // the bid is under one lot
+        let (buy, sell, under_lot) = quoted(
+            fixture_strategy()
+                .with_quote_sizing(headroom)
+                .with_lot_size(0.5),
+        );
+        assert_eq!((buy, under_lot), (None, Some(1)));
+        near(sell, 0.5);
     }
 }
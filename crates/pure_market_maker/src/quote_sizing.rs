@@ -0,0 +1,256 @@
+use std::str::FromStr;
+
+const SIZING_FORMAT: &str = "fixed:quantity=<q>, headroom:fraction=<f>, \
+    inverse-vol:quantity=<q>,vol=<reference vol> or notional:quantity=<q>,max=<notional>";
+
+// the most an inverse-vol size grows over its quantity when the volatility is far below the
+// reference or zero
+const MAX_VOL_SCALE: f64 = 4.0;
+
+// How large the bid and ask quotes are, in base asset quantity before the lot size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteSizing {
+    // the same quantity every round
+    Fixed { quantity: f64 },
+    // a fraction of what each side may still add before the inventory limits, or before the
+    // balances without limits
+    Headroom { fraction: f64 },
+    // quantity at the reference volatility, smaller as the volatility rises. The reference
+    // is in the units of the estimate, the vol of the strategy debug
+    InverseVol { quantity: f64, reference_vol: f64 },
+    // quantity, less when its notional at the fair price is over max_notional
+    NotionalCap { quantity: f64, max_notional: f64 },
+}
+
+impl Default for QuoteSizing {
+    fn default() -> Self {
+        QuoteSizing::Fixed { quantity: 0.01 }
+    }
+}
+
+// what the sizes of a quote round depend on
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SizingContext {
+    pub(crate) fair_price: f64,
+    pub(crate) vol: f64,
+    // base asset quantity the (buy, sell) side may still add
+    pub(crate) headroom: (f64, f64),
+}
+
+impl QuoteSizing {
+    // (bid quantity, ask quantity) snapped down to the lot size, 0 for a side under one lot
+    pub(crate) fn sizes(&self, ctx: &SizingContext, lot_size: f64) -> (f64, f64) {
+        let (bid, ask) = match *self {
+            QuoteSizing::Fixed { quantity } => (quantity, quantity),
+            QuoteSizing::Headroom { fraction } => {
+                (ctx.headroom.0 * fraction, ctx.headroom.1 * fraction)
+            }
+            QuoteSizing::InverseVol {
+                quantity,
+                reference_vol,
+            } => {
+                let scale = if !ctx.vol.is_finite() {
+                    1.0
+                } else if ctx.vol > 0.0 {
+                    (reference_vol / ctx.vol).min(MAX_VOL_SCALE)
+                } else {
+                    MAX_VOL_SCALE
+                };
+                (quantity * scale, quantity * scale)
+            }
+            QuoteSizing::NotionalCap {
+                quantity,
+                max_notional,
+            } => {
+                let quantity = if ctx.fair_price > 0.0 {
+                    quantity.min(max_notional / ctx.fair_price)
+                } else {
+                    quantity
+                };
+                (quantity, quantity)
+            }
+        };
+        (snap_to_lot(bid, lot_size), snap_to_lot(ask, lot_size))
+    }
+}
+
+// the whole lots of quantity, quantity itself without a lot size
+pub(crate) fn snap_to_lot(quantity: f64, lot_size: f64) -> f64 {
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return 0.0;
+    }
+    if lot_size <= 0.0 {
+        return quantity;
+    }
+    // a quantity a rounding error under a whole number of lots keeps it. Dividing by the lots
+    // per unit gives 0.013 for 13 lots of 0.001, where multiplying gives 0.013000000000000001
+    let lots_per_unit = 1.0 / lot_size;
+    (quantity * lots_per_unit + 1e-9).floor() / lots_per_unit
+}
+
+// e.g. headroom:fraction=0.1 or notional:quantity=0.05,max=1000, see SIZING_FORMAT
+impl FromStr for QuoteSizing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, params)) = s.split_once(':') else {
+            return Err(format!(
+                "invalid quote sizing {s}, expected {SIZING_FORMAT}"
+            ));
+        };
+        let mut values = vec![];
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            let (name, value) = param.split_once('=').ok_or_else(|| {
+                format!("invalid quote sizing parameter {param}, expected name=value")
+            })?;
+            let value: f64 = value
+                .parse()
+                .map_err(|_| format!("invalid value of quote sizing parameter {name}: {value}"))?;
+            if value <= 0.0 {
+                return Err(format!("quote sizing parameter {name} must be positive"));
+            }
+            values.push((name, value));
+        }
+        let mut take = |name: &str| -> Result<f64, String> {
+            match values.iter().position(|(n, _)| *n == name) {
+                Some(i) => Ok(values.remove(i).1),
+                None => Err(format!("quote sizing {kind} needs {name}")),
+            }
+        };
+        let sizing = match kind.to_lowercase().as_str() {
+            "fixed" => QuoteSizing::Fixed {
+                quantity: take("quantity")?,
+            },
+            "headroom" => QuoteSizing::Headroom {
+                fraction: take("fraction")?,
+            },
+            "inverse-vol" => QuoteSizing::InverseVol {
+                quantity: take("quantity")?,
+                reference_vol: take("vol")?,
+            },
+            "notional" => QuoteSizing::NotionalCap {
+                quantity: take("quantity")?,
+                max_notional: take("max")?,
+            },
+            _ => {
+                return Err(format!(
+                    "unknown quote sizing {kind}, expected fixed, headroom, inverse-vol or notional"
+                ))
+            }
+        };
+        if let Some((name, _)) = values.first() {
+            return Err(format!("unknown parameter {name} of quote sizing {kind}"));
+        }
+        match sizing {
+            QuoteSizing::Headroom { fraction } if fraction > 1.0 => {
+                Err("fraction of a headroom sizing must be at most 1".to_string())
+            }
+            sizing => Ok(sizing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(vol: f64, headroom: (f64, f64)) -> SizingContext {
+        SizingContext {
+            fair_price: 40000.0,
+            vol,
+            headroom,
+        }
+    }
+
+    fn assert_near((bid, ask): (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (bid - expected.0).abs() < 1e-12 && (ask - expected.1).abs() < 1e-12,
+            "{:?} != {:?}",
+            (bid, ask),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_sizes() {
+        let lot = 0.001;
+        let fixed = QuoteSizing::default();
+        assert_eq!(fixed.sizes(&ctx(1.0, (0.0, 0.0)), lot), (0.01, 0.01));
+
+        let headroom = QuoteSizing::Headroom { fraction: 0.1 };
+        // 0.0456 and 0.0015 snapped down, a side under one lot is not quoted
+        assert_near(
+            headroom.sizes(&ctx(1.0, (0.456, 0.015)), lot),
+            (0.045, 0.001),
+        );
+        assert_near(headroom.sizes(&ctx(1.0, (0.5, 0.009)), lot), (0.05, 0.0));
+        assert_eq!(headroom.sizes(&ctx(1.0, (-0.2, 1.0)), lot).0, 0.0);
+
+        let inverse_vol = QuoteSizing::InverseVol {
+            quantity: 0.01,
+            reference_vol: 2.0,
+        };
+        assert_near(
+            inverse_vol.sizes(&ctx(4.0, (0.0, 0.0)), lot),
+            (0.005, 0.005),
+        );
+        assert_near(inverse_vol.sizes(&ctx(2.0, (0.0, 0.0)), lot), (0.01, 0.01));
+        // capped when the volatility collapses, the quantity while it is not a number
+        assert_near(inverse_vol.sizes(&ctx(0.1, (0.0, 0.0)), lot), (0.04, 0.04));
+        assert_near(inverse_vol.sizes(&ctx(0.0, (0.0, 0.0)), lot), (0.04, 0.04));
+        assert_near(
+            inverse_vol.sizes(&ctx(f64::NAN, (0.0, 0.0)), lot),
+            (0.01, 0.01),
+        );
+
+        let notional = QuoteSizing::NotionalCap {
+            quantity: 0.05,
+            max_notional: 1000.0,
+        };
+        // 1000 / 40000 = 0.025
+        assert_near(notional.sizes(&ctx(1.0, (0.0, 0.0)), lot), (0.025, 0.025));
+        // without a lot size the sizes are kept as they are
+        assert_near(notional.sizes(&ctx(1.0, (0.0, 0.0)), 0.0), (0.025, 0.025));
+        assert_eq!(
+            QuoteSizing::Fixed { quantity: 0.0139 }.sizes(&ctx(1.0, (0.0, 0.0)), lot),
+            (0.013, 0.013)
+        );
+        let fine = QuoteSizing::Fixed { quantity: 0.0123 };
+        assert_near(fine.sizes(&ctx(1.0, (0.0, 0.0)), 0.0), (0.0123, 0.0123));
+        assert_near(fine.sizes(&ctx(1.0, (0.0, 0.0)), lot), (0.012, 0.012));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "headroom:fraction=0.2".parse::<QuoteSizing>(),
+            Ok(QuoteSizing::Headroom { fraction: 0.2 })
+        );
+        assert_eq!(
+            "Inverse-Vol:quantity=0.01,vol=3".parse::<QuoteSizing>(),
+            Ok(QuoteSizing::InverseVol {
+                quantity: 0.01,
+                reference_vol: 3.0,
+            })
+        );
+        assert_eq!(
+            "notional:max=500,quantity=0.05".parse::<QuoteSizing>(),
+            Ok(QuoteSizing::NotionalCap {
+                quantity: 0.05,
+                max_notional: 500.0,
+            })
+        );
+        for s in [
+            "fixed",
+            "fixed:quantity=0",
+            "fixed:quantity=-1",
+            "fixed:quantity=0.01,extra=1",
+            "headroom:fraction=1.5",
+            "inverse-vol:quantity=0.01",
+            "notional:quantity=x,max=5",
+            "kelly:fraction=0.5",
+        ] {
+            assert!(s.parse::<QuoteSizing>().is_err(), "{s}");
+        }
+    }
+}
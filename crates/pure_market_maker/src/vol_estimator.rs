@@ -0,0 +1,441 @@
+use std::{
+    collections::VecDeque,
+    f64::consts::{LN_2, PI},
+    str::FromStr,
+    time::Duration,
+};
+
+use yata::{core::Method, helpers::Peekable};
+
+use crate::{duration_sampler::DurationSampler, time_volatility::TimeVolatility};
+
+// the estimates are of the price change over one sample interval, from about this many of them
+const VOL_SAMPLES: usize = 60;
+const VOL_SAMPLE_MS: u64 = 1000;
+
+// Stdev of the price change per sample interval in price units, estimated from (time in ms,
+// price) samples of the vol price series
+pub trait VolatilityEstimator {
+    fn next(&mut self, time_ms: u64, price: f64);
+
+    fn value(&self) -> f64;
+}
+
+impl VolatilityEstimator for TimeVolatility {
+    fn next(&mut self, time_ms: u64, price: f64) {
+        Method::next(self, &(time_ms, price));
+    }
+
+    fn value(&self) -> f64 {
+        self.peek()
+    }
+}
+
+// How the volatility the quotes scale with is estimated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VolEstimator {
+    // stdev of the sampled price changes over a fixed window
+    #[default]
+    Stdev,
+    // exponentially weighted variance of the sampled price changes, reacts faster to a change
+    // of regime
+    Ewma,
+    // from the high and low of the candles of the sample interval
+    Parkinson,
+    // from the open, high, low and close of the candles of the sample interval
+    GarmanKlass,
+    // from the products of consecutive absolute price changes, robust to single jumps
+    Bipower,
+}
+
+impl FromStr for VolEstimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stdev" => Ok(Self::Stdev),
+            "ewma" => Ok(Self::Ewma),
+            "parkinson" => Ok(Self::Parkinson),
+            "garman-klass" | "garman_klass" => Ok(Self::GarmanKlass),
+            "bipower" => Ok(Self::Bipower),
+            _ => Err(format!(
+                "unknown vol estimator {s}, expected stdev, ewma, parkinson, garman-klass or \
+                 bipower"
+            )),
+        }
+    }
+}
+
+impl VolEstimator {
+    // an estimator starting from its first sample
+    pub fn build(&self, time_ms: u64, price: f64) -> Box<dyn VolatilityEstimator> {
+        match self {
+            VolEstimator::Stdev => Box::new(
+                TimeVolatility::new((VOL_SAMPLES as u8, VOL_SAMPLE_MS), &(time_ms, price)).unwrap(),
+            ),
+            VolEstimator::Ewma => Box::new(EwmaVolatility {
+                sampler: DurationSampler::new(VOL_SAMPLE_MS, time_ms),
+                last_price: price,
+                alpha: 2.0 / (VOL_SAMPLES as f64 + 1.0),
+                variance: 0.0,
+            }),
+            VolEstimator::Parkinson | VolEstimator::GarmanKlass => Box::new(CandleVolatility {
+                garman_klass: *self == VolEstimator::GarmanKlass,
+                slot: time_ms / VOL_SAMPLE_MS,
+                candle: Candle::new(price),
+                variances: VecDeque::with_capacity(VOL_SAMPLES + 1),
+            }),
+            VolEstimator::Bipower => Box::new(BipowerVolatility {
+                sampler: DurationSampler::new(VOL_SAMPLE_MS, time_ms),
+                last_price: price,
+                abs_changes: VecDeque::with_capacity(VOL_SAMPLES + 2),
+            }),
+        }
+    }
+}
+
+// What is done with a price change across a gap in the samples longer than the gap threshold,
+// e.g. a missing minute or the night between two days of data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VolGapHandling {
+    // taken as the change over one sample interval
+    #[default]
+    Ignore,
+    // the estimate starts over from the first sample after the gap
+    Reset,
+    // scaled down to the change over one sample interval of a random walk
+    Rescale,
+}
+
+impl FromStr for VolGapHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "reset" => Ok(Self::Reset),
+            "rescale" => Ok(Self::Rescale),
+            _ => Err(format!(
+                "unknown vol gap handling {s}, expected ignore, reset or rescale"
+            )),
+        }
+    }
+}
+
+// An estimator with the gaps in its samples handled. Warm once its samples span the window of
+// the estimate, before that the estimate is low.
+pub struct VolTracker {
+    estimator: VolEstimator,
+    gap_handling: VolGapHandling,
+    gap_ms: u64,
+    inner: Box<dyn VolatilityEstimator>,
+    started_at: u64,
+    last_time: u64,
+    last_price: f64,
+    // subtracted from the prices given to the estimator, the part of the changes across gaps
+    // scaled away
+    offset: f64,
+}
+
+impl VolTracker {
+    pub fn new(
+        estimator: VolEstimator,
+        gap_handling: VolGapHandling,
+        gap: Duration,
+        time_ms: u64,
+        price: f64,
+    ) -> Self {
+        VolTracker {
+            estimator,
+            gap_handling,
+            gap_ms: gap.as_millis() as u64,
+            inner: estimator.build(time_ms, price),
+            started_at: time_ms,
+            last_time: time_ms,
+            last_price: price,
+            offset: 0.0,
+        }
+    }
+
+    pub fn next(&mut self, time_ms: u64, price: f64) {
+        let elapsed = time_ms.saturating_sub(self.last_time);
+        self.last_time = time_ms;
+        let change = price - self.last_price;
+        self.last_price = price;
+        if elapsed > self.gap_ms {
+            match self.gap_handling {
+                VolGapHandling::Ignore => {}
+                VolGapHandling::Reset => {
+                    self.inner = self.estimator.build(time_ms, price);
+                    self.started_at = time_ms;
+                    self.offset = 0.0;
+                    return;
+                }
+                VolGapHandling::Rescale => {
+                    let intervals = elapsed as f64 / VOL_SAMPLE_MS as f64;
+                    self.offset += change - change / intervals.sqrt();
+                }
+            }
+        }
+        self.inner.next(time_ms, price - self.offset);
+    }
+
+    pub fn value(&self) -> f64 {
+        self.inner.value()
+    }
+
+    pub fn is_warm(&self) -> bool {
+        // a sample older than the start, e.g. out of order around a reset, is not warm
+        self.last_time.saturating_sub(self.started_at) >= VOL_SAMPLES as u64 * VOL_SAMPLE_MS
+    }
+}
+
+struct EwmaVolatility {
+    sampler: DurationSampler,
+    last_price: f64,
+    alpha: f64,
+    variance: f64,
+}
+
+impl VolatilityEstimator for EwmaVolatility {
+    fn next(&mut self, time_ms: u64, price: f64) {
+        if self.sampler.sampled(time_ms) {
+            let change = price - self.last_price;
+            self.last_price = price;
+            self.variance = self.alpha * change * change + (1.0 - self.alpha) * self.variance;
+        }
+    }
+
+    fn value(&self) -> f64 {
+        self.variance.sqrt()
+    }
+}
+
+struct Candle {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+}
+
+impl Candle {
+    fn new(price: f64) -> Self {
+        Candle {
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+        }
+    }
+
+    fn update(&mut self, price: f64) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+    }
+}
+
+struct CandleVolatility {
+    garman_klass: bool,
+    slot: u64,
+    // the candle of the current sample interval, opened at the close of the previous one
+    candle: Candle,
+    // (log variance, close) of the last candles
+    variances: VecDeque<(f64, f64)>,
+}
+
+impl CandleVolatility {
+    fn log_variance(&self) -> f64 {
+        let Candle {
+            open,
+            high,
+            low,
+            close,
+        } = self.candle;
+        let range = (high / low).ln();
+        if self.garman_klass {
+            let change = (close / open).ln();
+            0.5 * range * range - (2.0 * LN_2 - 1.0) * change * change
+        } else {
+            range * range / (4.0 * LN_2)
+        }
+    }
+}
+
+impl VolatilityEstimator for CandleVolatility {
+    fn next(&mut self, time_ms: u64, price: f64) {
+        let slot = time_ms / VOL_SAMPLE_MS;
+        if slot > self.slot {
+            self.variances
+                .push_back((self.log_variance(), self.candle.close));
+            if self.variances.len() > VOL_SAMPLES {
+                self.variances.pop_front();
+            }
+            self.slot = slot;
+            self.candle = Candle::new(self.candle.close);
+        }
+        self.candle.update(price);
+    }
+
+    fn value(&self) -> f64 {
+        let Some((_, close)) = self.variances.back() else {
+            return 0.0;
+        };
+        let variance =
+            self.variances.iter().map(|(v, _)| v).sum::<f64>() / self.variances.len() as f64;
+        // the log estimate in price units at the last close
+        variance.max(0.0).sqrt() * close
+    }
+}
+
+struct BipowerVolatility {
+    sampler: DurationSampler,
+    last_price: f64,
+    abs_changes: VecDeque<f64>,
+}
+
+impl VolatilityEstimator for BipowerVolatility {
+    fn next(&mut self, time_ms: u64, price: f64) {
+        if self.sampler.sampled(time_ms) {
+            self.abs_changes.push_back((price - self.last_price).abs());
+            self.last_price = price;
+            // one more change than the products averaged
+            if self.abs_changes.len() > VOL_SAMPLES + 1 {
+                self.abs_changes.pop_front();
+            }
+        }
+    }
+
+    fn value(&self) -> f64 {
+        if self.abs_changes.len() < 2 {
+            return 0.0;
+        }
+        let products = self
+            .abs_changes
+            .iter()
+            .zip(self.abs_changes.iter().skip(1))
+            .map(|(a, b)| a * b);
+        let mean = products.sum::<f64>() / (self.abs_changes.len() - 1) as f64;
+        (PI / 2.0 * mean).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [VolEstimator; 5] = [
+        VolEstimator::Stdev,
+        VolEstimator::Ewma,
+        VolEstimator::Parkinson,
+        VolEstimator::GarmanKlass,
+        VolEstimator::Bipower,
+    ];
+
+    // a random walk of steps of step size every 10ms, the change over a second has a stdev of
+    // 10 steps
+    fn random_walk(seconds: u64, step: f64, seed: u64) -> Vec<(u64, f64)> {
+        let mut state = seed;
+        let mut price = 10000.0;
+        (0..seconds * 100)
+            .map(|i| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                price += if state >> 63 == 0 { step } else { -step };
+                (i * 10, price)
+            })
+            .collect()
+    }
+
+    fn estimate(estimator: VolEstimator, samples: &[(u64, f64)]) -> f64 {
+        let mut vol = estimator.build(samples[0].0, samples[0].1);
+        for (time, price) in &samples[1..] {
+            vol.next(*time, *price);
+        }
+        vol.value()
+    }
+
+    #[test]
+    fn test_estimators_agree_on_random_walk() {
+        let samples = random_walk(600, 1.0, 7);
+        for estimator in ALL {
+            let vol = estimate(estimator, &samples);
+            assert!(
+                (vol - 10.0).abs() < 3.0,
+                "{:?} estimated {} for 10",
+                estimator,
+                vol
+            );
+        }
+    }
+
+    #[test]
+    fn test_bipower_robust_to_jump() {
+        let mut samples = random_walk(600, 1.0, 11);
+        // a single jump of 200 half way through the last window
+        for sample in samples.iter_mut().skip(570 * 100) {
+            sample.1 += 200.0;
+        }
+        let stdev = estimate(VolEstimator::Stdev, &samples);
+        let bipower = estimate(VolEstimator::Bipower, &samples);
+        assert!(stdev > 20.0, "stdev {}", stdev);
+        assert!(bipower < 15.0, "bipower {}", bipower);
+    }
+
+    fn track(gap_handling: VolGapHandling, samples: &[(u64, f64)]) -> VolTracker {
+        let mut vol = VolTracker::new(
+            VolEstimator::Stdev,
+            gap_handling,
+            Duration::from_secs(60),
+            samples[0].0,
+            samples[0].1,
+        );
+        for (time, price) in &samples[1..] {
+            vol.next(*time, *price);
+        }
+        vol
+    }
+
+    #[test]
+    fn test_vol_gap_handling() {
+        let mut samples = random_walk(600, 1.0, 7);
+        // an hour without data, the price moved 300 meanwhile
+        let (end, price) = *samples.last().unwrap();
+        samples.extend(
+            random_walk(30, 1.0, 13)
+                .into_iter()
+                .map(|(time, p)| (end + 3_600_000 + time, price + 300.0 + p - 10000.0)),
+        );
+
+        let ignored = track(VolGapHandling::Ignore, &samples);
+        assert!(ignored.value() > 30.0, "ignored {}", ignored.value());
+        assert!(ignored.is_warm());
+
+        let rescaled = track(VolGapHandling::Rescale, &samples);
+        assert!(
+            (rescaled.value() - 10.0).abs() < 3.0,
+            "rescaled {}",
+            rescaled.value()
+        );
+        assert!(rescaled.is_warm());
+
+        // 30s since the reset
+        let reset = track(VolGapHandling::Reset, &samples);
+        assert!(!reset.is_warm());
+        assert!(reset.value() < 10.0, "reset {}", reset.value());
+        // nor from a sample before the start
+        let mut early = track(VolGapHandling::Reset, &[(1000, 100.0)]);
+        early.next(999, 100.0);
+        assert!(!early.is_warm());
+    }
+
+    #[test]
+    fn test_parse_vol_estimator() {
+        assert_eq!("EWMA".parse(), Ok(VolEstimator::Ewma));
+        assert_eq!("garman-klass".parse(), Ok(VolEstimator::GarmanKlass));
+        assert!("garch".parse::<VolEstimator>().is_err());
+        assert_eq!("Rescale".parse(), Ok(VolGapHandling::Rescale));
+        assert!("drop".parse::<VolGapHandling>().is_err());
+    }
+}
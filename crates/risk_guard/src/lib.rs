@@ -0,0 +1,2 @@
+pub mod liquidator;
+pub mod risk_guard;
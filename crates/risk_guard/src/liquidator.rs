@@ -0,0 +1,475 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use account::account::Account;
+use symbol_info::SymbolInfoManager;
+use tracing::{error, warn};
+use upstair_type::{
+    account::AccountUpdate,
+    control::TradingHalt,
+    module::{
+        and_filter, owner_filter, symbol_filter, Module, ModuleBuilder, ModuleComms,
+        ReadTopicHandle, WriteTopicHandle,
+    },
+    order::{
+        OrderRequest, OrderResult, OrderStatus, TimeInForce, TradeSide, TradeType,
+        LIQUIDATION_ORDER_PREFIX,
+    },
+    Message, MessageHeader, Payload,
+};
+
+// dust left over after a liquidation is not worth an order
+const MIN_ORDER_QUANTITY: f64 = 1e-6;
+// wait before sending another order once one is rejected
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Hard limits enforced by sending market orders, None disables the check
+#[derive(Debug, Clone, Default)]
+pub struct LiquidationLimits {
+    // base asset position away from the first known balance
+    pub max_long: Option<f64>,
+    pub max_short: Option<f64>,
+    // equity lost since the first known equity in quote asset, the position is closed and
+    // trading halted
+    pub max_loss: Option<f64>,
+}
+
+// An order bringing the position back inside the limits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liquidation {
+    pub side: TradeSide,
+    pub quantity: f64,
+    pub reason: String,
+}
+
+// Tracks the position and equity of one symbol against the liquidation limits
+#[derive(Debug)]
+pub struct LiquidationMonitor {
+    limits: LiquidationLimits,
+    base_asset: &'static str,
+    quote_asset: &'static str,
+    account: Account,
+    last_price: f64,
+    // the equity is valued at the mark price once one is known, as the exchange does, so a
+    // wick of the trades alone does not breach the loss limit
+    mark_price: Option<f64>,
+    // (base balance, equity) once both balances and a price are known
+    initial: Option<(f64, f64)>,
+    // set once the loss limit is breached, the position is closed from then on
+    loss_breach: Option<String>,
+}
+
+impl LiquidationMonitor {
+    pub fn new(
+        limits: LiquidationLimits,
+        base_asset: &'static str,
+        quote_asset: &'static str,
+    ) -> Self {
+        LiquidationMonitor {
+            limits,
+            base_asset,
+            quote_asset,
+            account: Account::default(),
+            last_price: 0.0,
+            mark_price: None,
+            initial: None,
+            loss_breach: None,
+        }
+    }
+
+    pub fn on_account_update(&mut self, update: &AccountUpdate) {
+        for (asset, updated_balance) in update.updates.iter() {
+            let entry = self.account.get_or_create(asset);
+            entry.balance = updated_balance.balance;
+            entry.locked = updated_balance.locked;
+        }
+    }
+
+    pub fn on_trade_price(&mut self, price: f64) {
+        self.last_price = price;
+    }
+
+    pub fn on_mark_price(&mut self, price: f64) {
+        self.mark_price = Some(price);
+    }
+
+    pub fn last_price(&self) -> f64 {
+        self.last_price
+    }
+
+    pub fn loss_breach(&self) -> Option<&str> {
+        self.loss_breach.as_deref()
+    }
+
+    // returns the order needed to get back inside the limits, if any
+    pub fn check(&mut self) -> Option<Liquidation> {
+        if self.last_price <= 0.0 {
+            return None;
+        }
+        let base = self.account.asset_to_balance.get(self.base_asset)?.clone();
+        let quote = self.account.asset_to_balance.get(self.quote_asset)?.clone();
+        let equity = base.balance * self.mark_price.unwrap_or(self.last_price) + quote.balance;
+        let (initial_base, initial_equity) = *self.initial.get_or_insert((base.balance, equity));
+        let position = base.balance - initial_base;
+
+        if let Some(max_loss) = self.limits.max_loss {
+            let loss = initial_equity - equity;
+            if self.loss_breach.is_none() && loss > max_loss {
+                self.loss_breach = Some(format!("loss {:.2} over {:.2}", loss, max_loss));
+            }
+        }
+        let (excess, reason) = if let Some(breach) = &self.loss_breach {
+            (
+                position,
+                format!("close position {:.5} after {}", position, breach),
+            )
+        } else if self.limits.max_long.is_some_and(|max| position > max) {
+            let max_long = self.limits.max_long.unwrap();
+            (
+                position - max_long,
+                format!("long position {:.5} over {:.5}", position, max_long),
+            )
+        } else if self.limits.max_short.is_some_and(|max| -position > max) {
+            let max_short = self.limits.max_short.unwrap();
+            (
+                position + max_short,
+                format!("short position {:.5} over {:.5}", -position, max_short),
+            )
+        } else {
+            return None;
+        };
+
+        // only the free balance can be traded, orders of the strategies lock the rest
+        let (side, quantity) = if excess > 0.0 {
+            (TradeSide::Sell, excess.min(base.balance - base.locked))
+        } else {
+            (
+                TradeSide::Buy,
+                (-excess).min((quote.balance - quote.locked) / self.last_price),
+            )
+        };
+        if quantity < MIN_ORDER_QUANTITY {
+            return None;
+        }
+        Some(Liquidation {
+            side,
+            quantity,
+            reason,
+        })
+    }
+}
+
+struct Liquidator {
+    market_data_topic: ReadTopicHandle,
+    account_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+    order_topic: WriteTopicHandle,
+    control_topic: WriteTopicHandle,
+
+    symbol: &'static str,
+    monitor: LiquidationMonitor,
+    // one liquidation order at a time, the next is sized by the account it left
+    pending_order: Option<Arc<str>>,
+    retry_at: Option<SystemTime>,
+    halted: bool,
+    order_seq: u64,
+}
+
+impl Liquidator {
+    fn on_order_result(&mut self, result: &OrderResult, now: SystemTime) {
+        if self.pending_order.as_ref() != Some(&result.client_order_id) {
+            return;
+        }
+        match result.status {
+            OrderStatus::New | OrderStatus::PartiallyFilled => {}
+            OrderStatus::Rejected | OrderStatus::RateLimited => {
+                error!("liquidation order {} rejected", result.client_order_id);
+                self.pending_order = None;
+                self.retry_at = Some(now + RETRY_INTERVAL);
+            }
+            _ => self.pending_order = None,
+        }
+    }
+}
+
+impl Module for Liquidator {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            if let Some(price) = msg.payload.trade_price() {
+                self.monitor.on_trade_price(price);
+            }
+            if let Payload::MarkPrice(mark) = &msg.payload {
+                self.monitor.on_mark_price(mark.mark_price);
+            }
+        }
+        while let Some(msg) = comms.receive(&self.account_topic) {
+            if let Payload::AccountUpdate(update) = msg.payload {
+                self.monitor.on_account_update(&update);
+            }
+        }
+        let now = comms.time();
+        while let Some(msg) = comms.receive(&self.order_result_topic) {
+            for result in msg.payload.order_results() {
+                self.on_order_result(result, now);
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let now = comms.time();
+        let liquidation = self.monitor.check();
+        if let (Some(breach), false) = (self.monitor.loss_breach(), self.halted) {
+            let reason = format!("liquidation on {}", breach);
+            error!("trading halted: {}", reason);
+            self.halted = true;
+            comms.publish(
+                &self.control_topic,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::TradingHalt(TradingHalt { reason }),
+                },
+            );
+        }
+        if self.pending_order.is_some() || self.retry_at.is_some_and(|t| now < t) {
+            return;
+        }
+        let Some(liquidation) = liquidation else {
+            return;
+        };
+        self.retry_at = None;
+        self.order_seq += 1;
+        let client_order_id: Arc<str> =
+            Arc::from(format!("{}{}", LIQUIDATION_ORDER_PREFIX, self.order_seq));
+        warn!(
+            "liquidate {:?} {:.5} order_id={}: {}",
+            liquidation.side, liquidation.quantity, client_order_id, liquidation.reason
+        );
+        self.pending_order = Some(client_order_id.clone());
+        comms.publish(
+            &self.order_topic,
+            Message {
+                header: MessageHeader { commit_at: now },
+                payload: Payload::OrderRequest(OrderRequest {
+                    symbol: self.symbol,
+                    side: liquidation.side,
+                    price: self.monitor.last_price(),
+                    quantity: liquidation.quantity,
+                    trade_type: TradeType::Market,
+                    time_in_force: TimeInForce::ImmediateOrCancelled,
+                    client_order_id,
+                    cancel_order_id: None,
+                    owner: None,
+                }),
+            },
+        );
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+pub struct LiquidatorBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    account_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+    order_topic: Option<WriteTopicHandle>,
+    control_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    symbol_info_manager: Option<SymbolInfoManager>,
+    limits: LiquidationLimits,
+}
+
+impl LiquidatorBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        LiquidatorBuilder {
+            market_data_topic: None,
+            account_topic: None,
+            order_result_topic: None,
+            order_topic: None,
+            control_topic: None,
+            symbol,
+            symbol_info_manager: None,
+            limits: LiquidationLimits::default(),
+        }
+    }
+
+    pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
+        self.symbol_info_manager = Some(manager);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LiquidationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl ModuleBuilder for LiquidatorBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let account_topic = comms.get_topic("account");
+        let order_result_topic = comms.get_topic("order_result");
+        let order_topic = comms.get_topic("order");
+        let control_topic = comms.get_topic("control");
+
+        // only the ticks of our symbol
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(self.symbol))
+            .into();
+        // the account without an owner, the strategies with one are not guarded
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(None))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(
+                &order_result_topic,
+                and_filter(symbol_filter(self.symbol), owner_filter(None)),
+            )
+            .into();
+        self.order_topic = comms.publish_topic(&order_topic).into();
+        self.control_topic = comms.publish_topic(&control_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        let symbol_info_manager = self.symbol_info_manager.unwrap();
+        let symbol_info = symbol_info_manager
+            .get(self.symbol)
+            .expect("symbol in symbol info manager");
+        Box::new(Liquidator {
+            market_data_topic: self.market_data_topic.unwrap(),
+            account_topic: self.account_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            order_topic: self.order_topic.unwrap(),
+            control_topic: self.control_topic.unwrap(),
+            symbol: self.symbol,
+            monitor: LiquidationMonitor::new(
+                self.limits,
+                symbol_info.base_asset,
+                symbol_info.quote_asset,
+            ),
+            pending_order: None,
+            retry_at: None,
+            halted: false,
+            order_seq: 0,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "liquidator"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use upstair_type::account::AccountAssetUpdate;
+
+    use super::*;
+
+    fn balances(base: (f64, f64), quote: (f64, f64)) -> AccountUpdate {
+        AccountUpdate {
+            updates: vec![
+                (
+                    "BTC",
+                    AccountAssetUpdate {
+                        balance: base.0,
+                        locked: base.1,
+                    },
+                ),
+                (
+                    "USDT",
+                    AccountAssetUpdate {
+                        balance: quote.0,
+                        locked: quote.1,
+                    },
+                ),
+            ],
+            symbol: None,
+            seq: 0,
+            owner: None,
+        }
+    }
+
+    fn fixture_monitor(limits: LiquidationLimits) -> LiquidationMonitor {
+        let mut monitor = LiquidationMonitor::new(limits, "BTC", "USDT");
+        monitor.on_account_update(&balances((1.0, 0.0), (1000.0, 0.0)));
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.check(), None);
+        monitor
+    }
+
+    #[test]
+    fn test_position_limits() {
+        let mut monitor = fixture_monitor(LiquidationLimits {
+            max_long: Some(0.5),
+            max_short: Some(0.25),
+            ..Default::default()
+        });
+        monitor.on_account_update(&balances((1.5, 0.0), (500.0, 0.0)));
+        assert_eq!(monitor.check(), None);
+
+        // long 0.75, half of the base balance is locked by open orders
+        monitor.on_account_update(&balances((1.75, 1.5), (250.0, 0.0)));
+        let liquidation = monitor.check().unwrap();
+        assert_eq!(liquidation.side, TradeSide::Sell);
+        assert_eq!(liquidation.quantity, 0.25);
+        monitor.on_account_update(&balances((1.75, 1.6), (250.0, 0.0)));
+        assert!((monitor.check().unwrap().quantity - 0.15).abs() < 1e-9);
+
+        // short 0.5
+        monitor.on_account_update(&balances((0.5, 0.0), (1500.0, 0.0)));
+        let liquidation = monitor.check().unwrap();
+        assert_eq!(liquidation.side, TradeSide::Buy);
+        assert_eq!(liquidation.quantity, 0.25);
+        assert!(liquidation.reason.starts_with("short position 0.50000"));
+    }
+
+    #[test]
+    fn test_loss_closes_position() {
+        let mut monitor = fixture_monitor(LiquidationLimits {
+            max_loss: Some(100.0),
+            ..Default::default()
+        });
+        monitor.on_account_update(&balances((1.2, 0.0), (800.0, 0.0)));
+        // equity 2000 -> 1880
+        monitor.on_trade_price(900.0);
+        let liquidation = monitor.check().unwrap();
+        assert_eq!(monitor.loss_breach(), Some("loss 120.00 over 100.00"));
+        assert_eq!(liquidation.side, TradeSide::Sell);
+        assert!((liquidation.quantity - 0.2).abs() < 1e-9);
+
+        // the position is closed even once the loss recovered
+        monitor.on_trade_price(1100.0);
+        assert!(monitor.check().is_some());
+        monitor.on_account_update(&balances((1.0, 0.0), (1020.0, 0.0)));
+        assert_eq!(monitor.check(), None);
+        assert!(monitor.loss_breach().is_some());
+    }
+
+    #[test]
+    fn test_loss_on_mark_price() {
+        let mut monitor = fixture_monitor(LiquidationLimits {
+            max_loss: Some(100.0),
+            ..Default::default()
+        });
+        monitor.on_mark_price(1000.0);
+        // a wick of the trades alone, the mark price holds
+        monitor.on_trade_price(850.0);
+        assert_eq!(monitor.check(), None);
+        // equity 2000 -> 1880 at the mark price
+        monitor.on_mark_price(880.0);
+        // flat, nothing to close
+        assert_eq!(monitor.check(), None);
+        assert_eq!(monitor.loss_breach(), Some("loss 120.00 over 100.00"));
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
+use account::account::Account;
+use symbol_info::SymbolInfoManager;
+use tracing::{error, info};
+use upstair_type::{
+    account::AccountUpdate,
+    control::TradingHalt,
+    module::{
+        and_filter, owner_filter, symbol_filter, Module, ModuleBuilder, ModuleComms,
+        ReadTopicHandle, WriteTopicHandle,
+    },
+    order::{OrderResult, OrderStatus},
+    Message, MessageHeader, Payload,
+};
+
+const LOSS_WINDOW: Duration = Duration::from_secs(60);
+// reject rate is only checked once this many orders are acknowledged
+const REJECT_RATE_WINDOW: usize = 100;
+
+// Thresholds that halt trading, None disables the check
+#[derive(Debug, Clone, Default)]
+pub struct RiskLimits {
+    // fraction of the peak equity
+    pub max_drawdown: Option<f64>,
+    // equity lost within the last minute, in quote asset
+    pub max_loss_per_minute: Option<f64>,
+    // fraction of rejected orders among the recently acknowledged ones
+    pub max_reject_rate: Option<f64>,
+}
+
+// Tracks equity and order acknowledgements of one symbol
+#[derive(Debug)]
+pub struct RiskMonitor {
+    limits: RiskLimits,
+    base_asset: &'static str,
+    quote_asset: &'static str,
+    account: Account,
+    last_price: f64,
+    peak_equity: f64,
+    equity_window: VecDeque<(SystemTime, f64)>,
+    // true for rejected orders
+    acks: VecDeque<bool>,
+}
+
+impl RiskMonitor {
+    pub fn new(limits: RiskLimits, base_asset: &'static str, quote_asset: &'static str) -> Self {
+        RiskMonitor {
+            limits,
+            base_asset,
+            quote_asset,
+            account: Account::default(),
+            last_price: 0.0,
+            peak_equity: 0.0,
+            equity_window: VecDeque::new(),
+            acks: VecDeque::with_capacity(REJECT_RATE_WINDOW),
+        }
+    }
+
+    pub fn on_account_update(&mut self, update: &AccountUpdate) {
+        for (asset, updated_balance) in update.updates.iter() {
+            let entry = self.account.get_or_create(asset);
+            entry.balance = updated_balance.balance;
+            entry.locked = updated_balance.locked;
+        }
+    }
+
+    pub fn on_trade_price(&mut self, price: f64) {
+        self.last_price = price;
+    }
+
+    pub fn on_order_result(&mut self, result: &OrderResult) {
+        let rejected = match result.status {
+            OrderStatus::New => false,
+            OrderStatus::Rejected | OrderStatus::RateLimited => true,
+            _ => return,
+        };
+        if self.acks.len() == REJECT_RATE_WINDOW {
+            self.acks.pop_front();
+        }
+        self.acks.push_back(rejected);
+    }
+
+    // account value in quote asset, None until both balance and price are known
+    pub fn equity(&self) -> Option<f64> {
+        if self.last_price <= 0.0 {
+            return None;
+        }
+        let base = self.account.asset_to_balance.get(self.base_asset)?;
+        let quote = self.account.asset_to_balance.get(self.quote_asset)?;
+        Some(base.balance * self.last_price + quote.balance)
+    }
+
+    fn reject_rate(&self) -> Option<f64> {
+        if self.acks.len() < REJECT_RATE_WINDOW {
+            return None;
+        }
+        let rejected = self.acks.iter().filter(|rejected| **rejected).count();
+        Some(rejected as f64 / self.acks.len() as f64)
+    }
+
+    // returns the reason if a threshold is breached
+    pub fn check(&mut self, now: SystemTime) -> Option<String> {
+        if let (Some(max_reject_rate), Some(reject_rate)) =
+            (self.limits.max_reject_rate, self.reject_rate())
+        {
+            if reject_rate > max_reject_rate {
+                return Some(format!(
+                    "reject rate {:.2}% over {:.2}%",
+                    reject_rate * 100.0,
+                    max_reject_rate * 100.0
+                ));
+            }
+        }
+
+        let equity = self.equity()?;
+        self.peak_equity = self.peak_equity.max(equity);
+        while self
+            .equity_window
+            .front()
+            .is_some_and(|(t, _)| now.duration_since(*t).unwrap_or_default() > LOSS_WINDOW)
+        {
+            self.equity_window.pop_front();
+        }
+        self.equity_window.push_back((now, equity));
+
+        if let Some(max_drawdown) = self.limits.max_drawdown {
+            let drawdown = (self.peak_equity - equity) / self.peak_equity;
+            if drawdown > max_drawdown {
+                return Some(format!(
+                    "drawdown {:.2}% over {:.2}%, equity={:.2} peak={:.2}",
+                    drawdown * 100.0,
+                    max_drawdown * 100.0,
+                    equity,
+                    self.peak_equity
+                ));
+            }
+        }
+        if let Some(max_loss_per_minute) = self.limits.max_loss_per_minute {
+            let minute_ago_equity = self.equity_window.front().map_or(equity, |(_, e)| *e);
+            let loss = minute_ago_equity - equity;
+            if loss > max_loss_per_minute {
+                return Some(format!(
+                    "lost {:.2} within a minute, over {:.2}",
+                    loss, max_loss_per_minute
+                ));
+            }
+        }
+        None
+    }
+}
+
+struct RiskGuard {
+    market_data_topic: ReadTopicHandle,
+    account_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+    control_topic: WriteTopicHandle,
+
+    monitor: RiskMonitor,
+    terminate_on_halt: bool,
+    halted: bool,
+}
+
+impl Module for RiskGuard {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            if let Some(price) = msg.payload.trade_price() {
+                self.monitor.on_trade_price(price);
+            }
+        }
+        while let Some(msg) = comms.receive(&self.account_topic) {
+            if let Payload::AccountUpdate(update) = msg.payload {
+                self.monitor.on_account_update(&update);
+            }
+        }
+        while let Some(msg) = comms.receive(&self.order_result_topic) {
+            for result in msg.payload.order_results() {
+                self.monitor.on_order_result(result);
+            }
+        }
+        !self.halted
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let Some(reason) = self.monitor.check(comms.time()) else {
+            return;
+        };
+        error!("trading halted: {}", reason);
+        self.halted = true;
+        comms.publish(
+            &self.control_topic,
+            Message {
+                header: MessageHeader {
+                    commit_at: comms.time(),
+                },
+                payload: Payload::TradingHalt(TradingHalt { reason }),
+            },
+        );
+        if self.terminate_on_halt {
+            info!("terminate simulation on trading halt");
+            comms.request_terminate();
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn wake_on_message(&self) -> bool {
+        !self.halted
+    }
+}
+
+pub struct RiskGuardBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    account_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+    control_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    symbol_info_manager: Option<SymbolInfoManager>,
+    limits: RiskLimits,
+    terminate_on_halt: bool,
+}
+
+impl RiskGuardBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        RiskGuardBuilder {
+            market_data_topic: None,
+            account_topic: None,
+            order_result_topic: None,
+            control_topic: None,
+            symbol,
+            symbol_info_manager: None,
+            limits: RiskLimits::default(),
+            terminate_on_halt: false,
+        }
+    }
+
+    pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
+        self.symbol_info_manager = Some(manager);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: RiskLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // stop the simulation instead of only halting the strategies
+    pub fn with_terminate_on_halt(mut self, terminate_on_halt: bool) -> Self {
+        self.terminate_on_halt = terminate_on_halt;
+        self
+    }
+}
+
+impl ModuleBuilder for RiskGuardBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let account_topic = comms.get_topic("account");
+        let order_result_topic = comms.get_topic("order_result");
+        let control_topic = comms.get_topic("control");
+
+        // only the ticks of our symbol
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(self.symbol))
+            .into();
+        // the account without an owner, the strategies with one are not guarded
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(None))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(
+                &order_result_topic,
+                and_filter(symbol_filter(self.symbol), owner_filter(None)),
+            )
+            .into();
+        self.control_topic = comms.publish_topic(&control_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        let symbol_info_manager = self.symbol_info_manager.unwrap();
+        let symbol_info = symbol_info_manager
+            .get(self.symbol)
+            .expect("symbol in symbol info manager");
+        Box::new(RiskGuard {
+            market_data_topic: self.market_data_topic.unwrap(),
+            account_topic: self.account_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            control_topic: self.control_topic.unwrap(),
+            monitor: RiskMonitor::new(self.limits, symbol_info.base_asset, symbol_info.quote_asset),
+            terminate_on_halt: self.terminate_on_halt,
+            halted: false,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "risk_guard"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::UNIX_EPOCH};
+
+    use upstair_type::account::AccountAssetUpdate;
+
+    use super::*;
+
+    fn fixture_monitor(limits: RiskLimits) -> RiskMonitor {
+        let mut monitor = RiskMonitor::new(limits, "BTC", "USDT");
+        monitor.on_account_update(&AccountUpdate {
+            updates: vec![
+                (
+                    "BTC",
+                    AccountAssetUpdate {
+                        balance: 1.0,
+                        locked: 0.0,
+                    },
+                ),
+                (
+                    "USDT",
+                    AccountAssetUpdate {
+                        balance: 1000.0,
+                        locked: 0.0,
+                    },
+                ),
+            ],
+            symbol: None,
+            seq: 0,
+            owner: None,
+        });
+        monitor
+    }
+
+    fn at_secs(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn order_result(status: OrderStatus) -> OrderResult {
+        OrderResult {
+            symbol: "BTCUSDT",
+            at: UNIX_EPOCH,
+            client_order_id: Arc::from("B0"),
+            filled_quantity: 0.0,
+            price: 100.0,
+            is_buy: true,
+            status,
+            seq: 0,
+            owner: None,
+            reject_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_no_check_without_price() {
+        let mut monitor = fixture_monitor(RiskLimits {
+            max_drawdown: Some(0.0),
+            ..Default::default()
+        });
+        assert_eq!(monitor.equity(), None);
+        assert_eq!(monitor.check(at_secs(0)), None);
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.equity(), Some(2000.0));
+    }
+
+    #[test]
+    fn test_drawdown() {
+        let mut monitor = fixture_monitor(RiskLimits {
+            max_drawdown: Some(0.1),
+            ..Default::default()
+        });
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.check(at_secs(0)), None);
+        // equity 2000 -> 1850, 7.5% drawdown
+        monitor.on_trade_price(850.0);
+        assert_eq!(monitor.check(at_secs(3600)), None);
+        // equity 2000 -> 1700, 15% drawdown
+        monitor.on_trade_price(700.0);
+        assert!(monitor
+            .check(at_secs(7200))
+            .unwrap()
+            .starts_with("drawdown"));
+    }
+
+    #[test]
+    fn test_loss_per_minute() {
+        let mut monitor = fixture_monitor(RiskLimits {
+            max_loss_per_minute: Some(100.0),
+            ..Default::default()
+        });
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.check(at_secs(0)), None);
+        // slow losses stay under the limit
+        monitor.on_trade_price(920.0);
+        assert_eq!(monitor.check(at_secs(61)), None);
+        monitor.on_trade_price(840.0);
+        assert_eq!(monitor.check(at_secs(122)), None);
+        monitor.on_trade_price(700.0);
+        assert!(monitor
+            .check(at_secs(150))
+            .unwrap()
+            .starts_with("lost 140.00"));
+    }
+
+    #[test]
+    fn test_reject_rate() {
+        let mut monitor = fixture_monitor(RiskLimits {
+            max_reject_rate: Some(0.2),
+            ..Default::default()
+        });
+        for i in 0..REJECT_RATE_WINDOW {
+            let status = if i % 4 == 0 {
+                OrderStatus::Rejected
+            } else {
+                OrderStatus::New
+            };
+            monitor.on_order_result(&order_result(status));
+            // fills are not acknowledgements
+            monitor.on_order_result(&order_result(OrderStatus::Filled));
+            if i + 1 < REJECT_RATE_WINDOW {
+                assert_eq!(monitor.check(at_secs(0)), None);
+            }
+        }
+        assert!(monitor
+            .check(at_secs(0))
+            .unwrap()
+            .starts_with("reject rate 25.00%"));
+    }
+}
@@ -0,0 +1,347 @@
+use market_agent::{
+    fill_probability::FillProbability,
+    results::{DailyResult, Fill, RunResults},
+};
+use pure_market_maker::{
+    harness::{ScriptedStep, StrategyHarness},
+    Action, AmmStrategy,
+};
+use stepper_world::order_tracker::OrderStatus;
+use symbol_info::calc_trade_result;
+use upstair_type::{aggregate::BinanceKline, order::TradeSide};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+// A coarse backtest stepping the strategy on candles instead of ticks, for first passes over
+// the parameters before the tick level simulation. At the close of each candle the strategy
+// sees a book one tick wide at the close and the volume of the candle as one trade. An order
+// resting through the next candle is filled in full at its price, with the fill probability,
+// when the range of the candle trades through it.
+pub struct SimpleBacktest {
+    harness: StrategyHarness,
+    fill_probability: Option<FillProbability>,
+    results: RunResults,
+    initial_equity: Option<f64>,
+    // (day start, first fill of the day, equity at the start)
+    day: Option<(u64, usize, f64)>,
+    // close of the last candle, the price the equity is valued at
+    last_close: f64,
+    order_num: u64,
+    candle_num: u64,
+    // in quote asset
+    fee_value: f64,
+}
+
+impl SimpleBacktest {
+    pub fn new(strategy: AmmStrategy) -> Self {
+        SimpleBacktest {
+            harness: StrategyHarness::new(strategy),
+            fill_probability: None,
+            results: RunResults::default(),
+            initial_equity: None,
+            day: None,
+            last_close: 0.0,
+            order_num: 0,
+            candle_num: 0,
+            fee_value: 0.0,
+        }
+    }
+
+    pub fn with_balance(mut self, asset: &'static str, balance: f64) -> Self {
+        self.harness = self.harness.with_balance(asset, balance);
+        self
+    }
+
+    // orders crossed by a candle fill with the probability, as in the market agent
+    pub fn with_fill_probability(mut self, fill_probability: FillProbability) -> Self {
+        self.fill_probability = Some(fill_probability);
+        self
+    }
+
+    // the candles must come in time order
+    pub fn run(mut self, klines: impl IntoIterator<Item = BinanceKline>) -> RunResults {
+        for kline in klines {
+            self.step(&kline);
+        }
+        self.finish()
+    }
+
+    pub fn step(&mut self, kline: &BinanceKline) {
+        let now_ms = kline.close_time;
+        if self
+            .day
+            .is_some_and(|(start, _, _)| start / DAY_MS != now_ms / DAY_MS)
+        {
+            self.end_day();
+            self.start_day(now_ms / DAY_MS * DAY_MS);
+        }
+        if self.initial_equity.is_none() {
+            let equity = self.equity(kline.close);
+            self.initial_equity = Some(equity);
+            self.day = Some((now_ms / DAY_MS * DAY_MS, 0, equity));
+        }
+
+        let fills = self.crossed_orders(kline);
+        let tick = self.harness.strategy.price_tick;
+        let bid = (kline.close / tick).floor() * tick;
+        let step = fills.iter().fold(
+            ScriptedStep::at_ms(now_ms)
+                .with_book(bid, 1.0, bid + tick, 1.0)
+                .with_trade(kline.close, kline.volume),
+            |step, (order_id, _, _, quantity)| step.with_fill(order_id, *quantity),
+        );
+        let actions = self.harness.step(&step);
+        self.order_num += actions
+            .iter()
+            .filter(|action| matches!(action, Action::PlaceOrder(_)))
+            .count() as u64;
+        for (order_id, is_buy, price, quantity) in fills {
+            self.record_fill(now_ms, order_id, is_buy, price, quantity, kline.close);
+        }
+        self.last_close = kline.close;
+        self.candle_num += 1;
+        let equity = self.equity(kline.close);
+        self.results.equity.push((now_ms, equity));
+    }
+
+    // the results with the stats of the run, the last day ended at the last candle
+    pub fn finish(mut self) -> RunResults {
+        self.end_day();
+        let initial_equity = self.initial_equity.unwrap_or_default();
+        let final_equity = self
+            .initial_equity
+            .map_or(0.0, |_| self.equity(self.last_close));
+        let max_drawdown = self.results.max_drawdown();
+        let filled = |is_buy: bool| {
+            self.results
+                .fills
+                .iter()
+                .filter(|fill| fill.is_buy == is_buy)
+                .fold((0.0, 0.0), |(quantity, vol), fill| {
+                    (quantity + fill.quantity, vol + fill.quantity * fill.price)
+                })
+        };
+        let (filled_buy_quantity, filled_buy_vol) = filled(true);
+        let (filled_sell_quantity, filled_sell_vol) = filled(false);
+        let stats = [
+            ("initial_equity", initial_equity),
+            ("final_equity", final_equity),
+            ("profit", final_equity - initial_equity),
+            ("fee", self.fee_value),
+            ("fill_count", self.results.fills.len() as f64),
+            ("max_drawdown", max_drawdown),
+            ("order_num", self.order_num as f64),
+            ("filled_buy_quantity", filled_buy_quantity),
+            ("filled_buy_vol", filled_buy_vol),
+            ("filled_sell_quantity", filled_sell_quantity),
+            ("filled_sell_vol", filled_sell_vol),
+            ("candle_num", self.candle_num as f64),
+        ];
+        self.results
+            .stats
+            .extend(stats.map(|(name, value)| (name.to_string(), value)));
+        self.results
+    }
+
+    // (order id, is buy, price, quantity) of the resting orders the candle trades through, in
+    // order id order for the draws of the fill probability to repeat
+    fn crossed_orders(&mut self, kline: &BinanceKline) -> Vec<(String, bool, f64, f64)> {
+        let mut orders: Vec<_> = self
+            .harness
+            .world
+            .order_tracker
+            .iter()
+            .filter(|order| {
+                matches!(
+                    order.status,
+                    OrderStatus::Open | OrderStatus::PartiallyFilled
+                )
+            })
+            .filter(|order| match order.side {
+                TradeSide::Buy => kline.low < order.price,
+                TradeSide::Sell => kline.high > order.price,
+            })
+            .map(|order| {
+                (
+                    order.order_id.clone(),
+                    order.side == TradeSide::Buy,
+                    order.price,
+                    order.remaining_quantity(),
+                )
+            })
+            .collect();
+        orders.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(fill_probability) = &mut self.fill_probability {
+            orders.retain(|_| fill_probability.fills());
+        }
+        orders
+    }
+
+    // takes the fee of a fill the harness applied, in the asset received
+    fn record_fill(
+        &mut self,
+        time_ms: u64,
+        order_id: String,
+        is_buy: bool,
+        price: f64,
+        quantity: f64,
+        mid: f64,
+    ) {
+        let strategy = &self.harness.strategy;
+        let symbol_info = strategy
+            .symbol_info_manager
+            .get(strategy.symbol)
+            .expect("symbol in symbol info manager");
+        let trade = calc_trade_result(symbol_info, price, quantity, is_buy);
+        let (symbol, base_asset) = (strategy.symbol, strategy.base_asset);
+        let account = &mut self.harness.world.account;
+        account
+            .get_or_create(trade.fee_asset)
+            .deduce_balance(trade.fee_qty);
+        self.fee_value += if trade.fee_asset == base_asset {
+            trade.fee_qty * price
+        } else {
+            trade.fee_qty
+        };
+        self.results.fills.push(Fill {
+            time_ms,
+            order_id,
+            is_buy,
+            price,
+            quantity,
+            fee: trade.fee_qty,
+            tag: String::new(),
+            symbol: symbol.to_string(),
+            is_maker: true,
+            inventory: account.get_or_create(base_asset).balance,
+            mid,
+        });
+    }
+
+    // in quote asset, the base asset at price
+    fn equity(&self, price: f64) -> f64 {
+        let strategy = &self.harness.strategy;
+        let balance = |asset| {
+            self.harness
+                .world
+                .account
+                .asset_to_balance
+                .get(asset)
+                .map_or(0.0, |b| b.balance)
+        };
+        balance(strategy.base_asset) * price + balance(strategy.quote_asset)
+    }
+
+    fn start_day(&mut self, day_start_ms: u64) {
+        let equity = self.equity(self.last_close);
+        self.day = Some((day_start_ms, self.results.fills.len(), equity));
+    }
+
+    fn end_day(&mut self) {
+        let Some((day_start_ms, first_fill, start_equity)) = self.day.take() else {
+            return;
+        };
+        let fills = &self.results.fills[first_fill..];
+        let equity = self.equity(self.last_close);
+        self.results.daily.push(DailyResult {
+            day_start_ms,
+            fills: fills.len() as u64,
+            volume: fills.iter().map(|f| f.price * f.quantity).sum(),
+            fees: fills.iter().map(|f| f.fee).sum(),
+            equity,
+            profit: equity - start_equity,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symbol_info::SymbolInfoManager;
+
+    use super::*;
+
+    fn kline(close_time: u64, low: f64, high: f64, close: f64) -> BinanceKline {
+        BinanceKline {
+            open_time: close_time + 1 - 60_000,
+            open: close,
+            high,
+            low,
+            close,
+            volume: 1.0,
+            close_time,
+            quote_volume: close,
+            count: 1,
+            taker_buy_volume: 0.5,
+            taker_buy_quote_volume: close / 2.0,
+            symbol: "BTCUSDT",
+        }
+    }
+
+    fn fixture_backtest(fee_rate: f64) -> SimpleBacktest {
+        let strategy = AmmStrategy::new(
+            "BTCUSDT",
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", fee_rate),
+        );
+        SimpleBacktest::new(strategy)
+            .with_balance("BTC", 1.0)
+            .with_balance("USDT", 100.0)
+    }
+
+    #[test]
+    fn test_fills_on_crossed_range() {
+        let mut backtest = fixture_backtest(0.001);
+        // quotes around 100.0-100.1 at the close of the first candle
+        backtest.step(&kline(59_999, 99.0, 101.0, 100.05));
+        let quotes: Vec<(bool, f64)> = backtest
+            .harness
+            .world
+            .order_tracker
+            .iter()
+            .map(|order| (order.side == TradeSide::Buy, order.price))
+            .collect();
+        let bid = quotes.iter().find(|(is_buy, _)| *is_buy).unwrap().1;
+        let ask = quotes.iter().find(|(is_buy, _)| !*is_buy).unwrap().1;
+        // trades down through the bid and not up to the ask
+        backtest.step(&kline(119_999, bid - 1.0, ask, bid));
+        let results = backtest.finish();
+
+        assert_eq!(results.fills.len(), 1);
+        let fill = &results.fills[0];
+        assert!(fill.is_buy);
+        assert_eq!(fill.price, bid);
+        assert_eq!(fill.time_ms, 119_999);
+        // the fee of a buy is in the base asset
+        assert!((fill.fee - fill.quantity * 0.001).abs() < 1e-12);
+        assert!((fill.inventory - (1.0 + fill.quantity * 0.999)).abs() < 1e-12);
+        assert_eq!(results.equity.len(), 2);
+        assert_eq!(results.stats["fill_count"], 1.0);
+        assert_eq!(results.stats["candle_num"], 2.0);
+        assert!(results.stats["order_num"] >= 4.0);
+        assert_eq!(results.daily.len(), 1);
+        assert_eq!(results.daily[0].day_start_ms, 0);
+    }
+
+    #[test]
+    fn test_fill_probability_and_days() {
+        let run = |probability: f64, seed: u64| {
+            let mut backtest = fixture_backtest(0.0)
+                .with_fill_probability(FillProbability::new(probability, seed).unwrap());
+            // every candle trades through both quotes, one a day
+            for day in 0..20 {
+                let close_time = day * DAY_MS + 59_999;
+                backtest.step(&kline(close_time, 90.0, 110.0, 100.0));
+            }
+            backtest.finish()
+        };
+        let all = run(1.0, 0);
+        // the quotes of the 19 candles before the last one
+        assert_eq!(all.fills.len(), 38);
+        assert_eq!(all.daily.len(), 20);
+        assert_eq!(all.daily[1].day_start_ms, DAY_MS);
+        assert_eq!(all.daily.iter().map(|day| day.fills).sum::<u64>(), 38);
+
+        let some = run(0.5, 7);
+        assert!(!some.fills.is_empty() && some.fills.len() < 38);
+        assert_eq!(some.fills, run(0.5, 7).fills);
+    }
+}
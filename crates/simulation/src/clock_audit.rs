@@ -0,0 +1,127 @@
+use std::{cell::Cell, rc::Rc, str::FromStr, sync::Arc, time::SystemTime};
+
+use tracing::error;
+use upstair_type::{
+    module::{ModuleComms, ReadTopicHandle, WriteTopicHandle},
+    Message,
+};
+
+// What the engine does with a message published at a time its module cannot be at: before
+// the time of the engine, which the subscribers would take for an older state, or after the
+// next scheduled event, which they would read ahead of the clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockAudit {
+    // panics at the first one, for the tests of new modules
+    Strict,
+    // logs the first one of each module and counts them, see ModuleProfile::clock_violations
+    Lenient,
+}
+
+impl FromStr for ClockAudit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!(
+                "unknown clock audit {s}, expected strict or lenient"
+            )),
+        }
+    }
+}
+
+// the time of the next scheduled event, set by the engine as it dispatches one
+pub(crate) type ClockHorizon = Rc<Cell<Option<SystemTime>>>;
+
+// Checks the time of the messages a module publishes against the clock of the engine
+pub(crate) struct ClockAuditComms {
+    inner: Box<dyn ModuleComms>,
+    audit: ClockAudit,
+    module: String,
+    horizon: ClockHorizon,
+    violations: Rc<Cell<u64>>,
+}
+
+impl ClockAuditComms {
+    pub(crate) fn wrap(
+        inner: Box<dyn ModuleComms>,
+        audit: ClockAudit,
+        module: &str,
+        horizon: ClockHorizon,
+        violations: Rc<Cell<u64>>,
+    ) -> Box<dyn ModuleComms> {
+        Box::new(ClockAuditComms {
+            inner,
+            audit,
+            module: module.into(),
+            horizon,
+            violations,
+        })
+    }
+
+    // why a message at commit_at is out of time, none when it is not
+    fn violation(&self, commit_at: SystemTime) -> Option<String> {
+        let now = self.inner.time();
+        let ms = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        };
+        if commit_at < now {
+            return Some(format!(
+                "{} ms before the engine time {} ms",
+                ms(commit_at),
+                ms(now)
+            ));
+        }
+        // the subscribers run at the time of the engine until the next event
+        let horizon = self.horizon.get().map_or(now, |next| next.max(now));
+        (commit_at > horizon).then(|| {
+            format!(
+                "{} ms after the next event at {} ms",
+                ms(commit_at),
+                ms(horizon)
+            )
+        })
+    }
+}
+
+impl ModuleComms for ClockAuditComms {
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        self.inner.receive_shared(topic)
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        if let Some(violation) = self.violation(message.header.commit_at) {
+            match self.audit {
+                ClockAudit::Strict => panic!(
+                    "module {} published a message at {}: {:?}",
+                    self.module, violation, message.payload
+                ),
+                ClockAudit::Lenient => {
+                    if self.violations.get() == 0 {
+                        error!(
+                            "module {} published a message at {}: {:?}",
+                            self.module, violation, message.payload
+                        );
+                    }
+                    self.violations.set(self.violations.get() + 1);
+                }
+            }
+        }
+        self.inner.publish(topic, message)
+    }
+
+    fn request_terminate(&mut self) {
+        self.inner.request_terminate()
+    }
+
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        self.inner.next_delivery_at()
+    }
+}
@@ -1,30 +1,70 @@
+use std::cell::Cell;
 use std::cmp::Reverse;
-use std::collections::hash_map::DefaultHasher;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, VecDeque};
 use std::fmt::Debug;
-use std::hash::{Hash, Hasher};
-use std::time::SystemTime;
-use std::vec;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use std::{thread, vec};
 
+use crossbeam::channel::RecvTimeoutError;
+use priority_queue::PriorityQueue;
+
+use crate::clock_audit::{ClockAudit, ClockAuditComms, ClockHorizon};
+use crate::fault_injection::FaultInjection;
+use crate::hooks::{EngineHooks, HookContext};
+use crate::playback::Pacer;
+use crate::profile::{traced_run, CountingModuleComms, EngineProfile, MessageCount, ModuleProfile};
+use crate::progress::ProgressReporter;
 use crate::simulation::{SimulationCommsSystem, SimulationModuleCommsBuilder};
-use upstair_type::module::{ModuleBuilder, ModuleComms, ModuleCommsBuilder, TopicId};
-use upstair_type::time::TimeProvider;
+use crate::threaded_module::ThreadedModule;
+use upstair_type::module::{
+    ModuleBuilder, ModuleComms, ModuleCommsBuilder, ModulePriority, ReadTopicHandle, TopicId,
+};
+use upstair_type::run_output::RunOutput;
+use upstair_type::time::{PlaybackControl, SystemTimeProvider, TimeProvider};
 use upstair_type::Message;
 use upstair_type::{
     module::{CommsSystem, Module, ModuleId},
     time::SimulationTime,
 };
 
-use tracing::debug;
+use tracing::{debug, error};
+
+// Simulation mode jumps the clock to the next scheduled event.
+// Realtime mode follows the wall clock and sleeps until the next scheduled event, or until an
+// EngineWaker ends the sleep. While a waker is alive it waits on with nothing scheduled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EngineMode {
+    #[default]
+    Simulation,
+    Realtime,
+}
+
+// Ends the sleep of a realtime engine from another thread, e.g. a feed receiving live data.
+// The engine then runs the modules whose next iteration is due.
+#[derive(Clone)]
+pub struct EngineWaker(crossbeam::channel::Sender<()>);
+
+impl EngineWaker {
+    pub fn wake(&self) {
+        // the engine is gone once the run ended
+        let _ = self.0.send(());
+    }
+}
 
 #[derive(Eq, PartialEq, Hash, Debug)]
 pub enum EngineEvent {
     Run(ModuleId),
 }
 
+// Events at the same time run by the priority of their module, then in the order the modules
+// are added, so a run does not depend on anything but its modules
 #[derive(Eq, PartialEq)]
 struct TimedEvent {
     time: SystemTime,
+    priority: ModulePriority,
     event: EngineEvent,
 }
 
@@ -36,25 +76,153 @@ impl PartialOrd for TimedEvent {
 
 impl Ord for TimedEvent {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self.time.cmp(&other.time) {
-            std::cmp::Ordering::Equal => {
-                // compare hash of event
-                let mut h1 = DefaultHasher::new();
-                let mut h2 = DefaultHasher::new();
-                self.event.hash(&mut h1);
-                other.event.hash(&mut h2);
-                h1.finish().cmp(&h2.finish())
+        let EngineEvent::Run(module_id) = &self.event;
+        let EngineEvent::Run(other_module_id) = &other.event;
+        (self.time, self.priority, module_id.slot).cmp(&(
+            other.time,
+            other.priority,
+            other_module_id.slot,
+        ))
+    }
+}
+
+// The pending module wake-ups. Modules woken by every message would otherwise pile up a run
+// per message-producing iteration, so with coalescing a module has at most one pending
+// wake-up: it is kept at the earliest requested time and the module reads every message
+// waiting for it when it runs.
+enum Events {
+    Queued(BinaryHeap<Reverse<TimedEvent>>),
+    Coalesced(PriorityQueue<ModuleId, Reverse<TimedEvent>>),
+}
+
+struct EventQueue {
+    events: Events,
+    // of each module by slot
+    priorities: Vec<ModulePriority>,
+    wakeups: u64,
+    coalesced: u64,
+}
+
+impl EventQueue {
+    fn new(coalesce: bool, priorities: Vec<ModulePriority>) -> Self {
+        EventQueue {
+            events: if coalesce {
+                Events::Coalesced(PriorityQueue::new())
+            } else {
+                Events::Queued(BinaryHeap::new())
+            },
+            priorities,
+            wakeups: 0,
+            coalesced: 0,
+        }
+    }
+
+    fn schedule(&mut self, module_id: ModuleId, time: SystemTime) {
+        self.wakeups += 1;
+        let event = TimedEvent {
+            time,
+            priority: self.priorities[module_id.slot],
+            event: EngineEvent::Run(module_id.clone()),
+        };
+        match &mut self.events {
+            Events::Queued(heap) => heap.push(Reverse(event)),
+            Events::Coalesced(queue) => {
+                if queue.push_increase(module_id, Reverse(event)).is_some() {
+                    self.coalesced += 1;
+                }
             }
-            other => other,
+        }
+    }
+
+    fn peek(&self) -> Option<&TimedEvent> {
+        match &self.events {
+            Events::Queued(heap) => heap.peek().map(|Reverse(event)| event),
+            Events::Coalesced(queue) => queue.peek().map(|(_, Reverse(event))| event),
+        }
+    }
+
+    fn pop(&mut self) -> Option<TimedEvent> {
+        match &mut self.events {
+            Events::Queued(heap) => heap.pop().map(|Reverse(event)| event),
+            Events::Coalesced(queue) => queue.pop().map(|(_, Reverse(event))| event),
         }
     }
 }
 
+enum ModuleExecution {
+    // runs on the engine thread
+    Inline(Box<dyn Module>),
+    // runs on its own thread, joined at the next barrier
+    Threaded(ThreadedModule),
+}
+
 struct SimulationModuleContext {
     pub(crate) id: ModuleId,
-    pub(crate) module: Box<dyn Module>,
+    pub(crate) execution: ModuleExecution,
     pub(crate) comms: Box<dyn ModuleComms>,
     pub(crate) name: String,
+    pub(crate) priority: ModulePriority,
+    pub(crate) num_read_topics: usize,
+    iterations: u64,
+    busy: Duration,
+    messages_in: MessageCount,
+    messages_out: MessageCount,
+    clock_violations: MessageCount,
+}
+
+impl SimulationModuleContext {
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        match &self.execution {
+            ModuleExecution::Inline(module) => module.next_iteration_start_at(),
+            ModuleExecution::Threaded(module) => module.next_iteration_start_at(),
+        }
+    }
+
+    fn failure(&self) -> Option<String> {
+        match &self.execution {
+            ModuleExecution::Inline(module) => module.failure(),
+            ModuleExecution::Threaded(module) => module.failure(),
+        }
+    }
+
+    // also wakes the module when a message held back by fault injection is due
+    fn next_wakeup_at(&self) -> Option<SystemTime> {
+        match (
+            self.next_iteration_start_at(),
+            self.comms.next_delivery_at(),
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn wake_on_message(&self) -> bool {
+        match &self.execution {
+            ModuleExecution::Inline(module) => module.wake_on_message(),
+            ModuleExecution::Threaded(module) => module.wake_on_message(),
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        match &self.execution {
+            ModuleExecution::Inline(_) => false,
+            ModuleExecution::Threaded(module) => module.is_pending(),
+        }
+    }
+}
+
+// drain everything a module can read, so it can be handed over to the module thread
+fn drain_inbox(comms: &mut dyn ModuleComms, num_read_topics: usize) -> Vec<VecDeque<Arc<Message>>> {
+    (0..num_read_topics)
+        .map(|slot| {
+            let handle = ReadTopicHandle { slot };
+            let mut messages = VecDeque::new();
+            while let Some(msg) = comms.receive_shared(&handle) {
+                messages.push_back(msg);
+            }
+            messages
+        })
+        .collect()
 }
 
 impl Debug for SimulationModuleContext {
@@ -66,26 +234,263 @@ impl Debug for SimulationModuleContext {
 }
 
 // Engine managee the system time and schedule the modules to run
+//
+// Threaded modules run concurrently with the other modules scheduled at the same time.
+// Before the clock moves forward the engine waits for them at a barrier and publishes their
+// outputs in module order, so a simulation gives the same result in both execution modes.
+// The modules scheduled at the same time run by ModulePriority, then in the order they are
+// added.
 pub struct SimulationEngine {
     comms_system: SimulationCommsSystem,
     simulation_time: SimulationTime,
     module_contexts: Vec<SimulationModuleContext>,
-    topic_readers: Vec<crossbeam::channel::Receiver<Message>>,
+    topic_readers: Vec<crossbeam::channel::Receiver<Arc<Message>>>,
+    mode: EngineMode,
+    wall_clock: SystemTimeProvider,
+    hooks: EngineHooks,
+    coalesce_wakeups: bool,
+    shutdown_grace: Option<Duration>,
+    // throttles, pauses and steps a simulation
+    pacer: Option<Pacer>,
+    // wall time of the last run
+    elapsed: Duration,
+    // (requested, coalesced) module wake-ups in the last run
+    wakeups: (u64, u64),
+    clock_horizon: ClockHorizon,
+    progress: Option<Range<SystemTime>>,
+    // signalled by the wakers, disconnected once they are all dropped
+    wake_signals: Option<crossbeam::channel::Receiver<()>>,
 }
 
 impl SimulationEngine {
+    pub fn mode(&self) -> EngineMode {
+        self.mode
+    }
+
+    // why the modules that ended the last run early failed it, empty when it finished
+    pub fn failures(&self) -> Vec<String> {
+        self.module_contexts
+            .iter()
+            .filter_map(|ctx| {
+                ctx.failure()
+                    .map(|failure| format!("module({}) failed: {failure}", ctx.name))
+            })
+            .collect()
+    }
+
+    // iterations, busy time and message counts of each module in the last run
+    pub fn profile(&self) -> EngineProfile {
+        EngineProfile {
+            modules: self
+                .module_contexts
+                .iter()
+                .map(|ctx| ModuleProfile {
+                    name: ctx.name.clone(),
+                    iterations: ctx.iterations,
+                    busy: ctx.busy,
+                    messages_in: ctx.messages_in.get(),
+                    messages_out: ctx.messages_out.get(),
+                    clock_violations: ctx.clock_violations.get(),
+                })
+                .collect(),
+            elapsed: self.elapsed,
+            wakeups: self.wakeups.0,
+            coalesced_wakeups: self.wakeups.1,
+        }
+    }
+
+    // returns the time the event is dispatched at
+    fn advance_time(&mut self, scheduled_at: SystemTime) -> SystemTime {
+        match self.mode {
+            EngineMode::Simulation => {
+                if let Some(pacer) = &mut self.pacer {
+                    pacer.wait(scheduled_at);
+                }
+                scheduled_at
+            }
+            EngineMode::Realtime => {
+                if let Ok(wait) = scheduled_at.duration_since(self.wall_clock.time()) {
+                    thread::sleep(wait);
+                }
+                self.wall_clock.time()
+            }
+        }
+    }
+
+    // sleeps until next, or with nothing next until a waker signals. True when a waker ended
+    // the sleep
+    fn wait_for_wakeup(&mut self, next: Option<SystemTime>) -> bool {
+        let Some(signals) = self.wake_signals.clone() else {
+            return false;
+        };
+        let woken = match next {
+            Some(next) => {
+                let wait = next
+                    .duration_since(self.wall_clock.time())
+                    .unwrap_or_default();
+                match signals.recv_timeout(wait) {
+                    Ok(()) => true,
+                    Err(RecvTimeoutError::Timeout) => false,
+                    // advance_time sleeps the rest
+                    Err(RecvTimeoutError::Disconnected) => {
+                        self.wake_signals = None;
+                        false
+                    }
+                }
+            }
+            // the run ends once the wakers are gone
+            None => signals.recv().is_ok(),
+        };
+        // one check of the modules covers the signals sent meanwhile
+        while signals.try_recv().is_ok() {}
+        woken
+    }
+
+    // schedule the modules whose next iteration is due after a waker ended the sleep
+    fn schedule_woken_modules(&self, q: &mut EventQueue) {
+        let now = self.wall_clock.time();
+        for ctx in &self.module_contexts {
+            if let Some(t) = ctx.next_wakeup_at().filter(|t| *t <= now) {
+                q.schedule(ctx.id.clone(), t);
+            }
+        }
+    }
+
+    // pass the messages published since the last call to the hooks, then end the iteration
+    // of the modules
+    fn run_hooks(&mut self, modules: &[ModuleId], time: SystemTime) {
+        let ctx = HookContext::new(time, &self.comms_system.is_world_running);
+        for reader in &self.topic_readers {
+            // drained even without hooks, the readers see every message
+            for message in reader.try_iter() {
+                self.hooks.message(&ctx, &message);
+            }
+        }
+        for module_id in modules {
+            self.hooks
+                .iteration_end(&ctx, &self.module_contexts[module_id.slot].name);
+        }
+    }
+
+    fn has_pending_modules(&self) -> bool {
+        self.module_contexts.iter().any(|ctx| ctx.is_pending())
+    }
+
+    // wait for all running threaded modules and publish their outputs in module order
+    fn join_threaded_modules(&mut self) -> Vec<ModuleId> {
+        let mut joined = vec![];
+        for ctx in &mut self.module_contexts {
+            let ModuleExecution::Threaded(module) = &mut ctx.execution else {
+                continue;
+            };
+            if !module.is_pending() {
+                continue;
+            }
+            let reply = module.join();
+            ctx.iterations += 1;
+            ctx.busy += reply.busy;
+            for (topic, message) in reply.outbox {
+                ctx.comms.publish(&topic, message);
+            }
+            if reply.terminate_requested {
+                ctx.comms.request_terminate();
+            }
+            joined.push(ctx.id.clone());
+        }
+        joined
+    }
+
+    // restart the stopped world and let every module wind down, their outputs are published
+    // in module order
+    fn shutdown_modules(&mut self, q: &mut EventQueue, time: SystemTime) {
+        let joined = self.join_threaded_modules();
+        self.run_hooks(&joined, time);
+        for module_id in joined {
+            self.schedule_next_iteration(q, module_id, time);
+        }
+        self.comms_system.is_world_running.set(true);
+        for ctx in &mut self.module_contexts {
+            debug!("shutdown module({})", ctx.name);
+            match &mut ctx.execution {
+                ModuleExecution::Inline(module) => module.on_shutdown(ctx.comms.as_mut()),
+                ModuleExecution::Threaded(module) => {
+                    let reply = module.shutdown(time);
+                    for (topic, message) in reply.outbox {
+                        ctx.comms.publish(&topic, message);
+                    }
+                }
+            }
+        }
+        self.run_hooks(&[], time);
+    }
+
+    fn schedule_next_iteration(&self, q: &mut EventQueue, module_id: ModuleId, time: SystemTime) {
+        let ctx = &self.module_contexts[module_id.slot];
+        // check next wakeup time
+        if let Some(next_iter_t) = ctx.next_wakeup_at() {
+            q.schedule(module_id, next_iter_t);
+
+            debug!(
+                "module {:?} finished. next_iter in {} ms",
+                ctx.name,
+                next_iter_t
+                    .duration_since(time)
+                    .unwrap_or_default()
+                    .as_millis()
+            );
+        } else {
+            debug!("module {:?} finished", ctx.name)
+        }
+    }
+
+    // wakeup module if topic is newer than last sync time
+    fn wake_subscribers(
+        &self,
+        q: &mut EventQueue,
+        module_last_sync_time: &mut [SystemTime],
+        topic_last_update_time: &[Rc<Cell<SystemTime>>],
+        module_subscribed_topics: &[Vec<TopicId>],
+    ) {
+        for module_slot in 0..module_subscribed_topics.len() {
+            let has_update_since_last_sync =
+                module_subscribed_topics[module_slot]
+                    .iter()
+                    .any(|topic_id| {
+                        let topic_slot = topic_id.slot;
+                        let topic_updated_at = &topic_last_update_time[topic_slot];
+                        let module_last_sync_time = &module_last_sync_time[module_slot];
+                        topic_updated_at.get() > *module_last_sync_time
+                    });
+            let ctx = &self.module_contexts[module_slot];
+            debug!(
+                "module {} has update: {} wake_on_message: {}",
+                ctx.name,
+                has_update_since_last_sync,
+                ctx.wake_on_message()
+            );
+            if has_update_since_last_sync && ctx.wake_on_message() {
+                let t = self.comms_system.time_provider.time();
+                q.schedule(ModuleId { slot: module_slot }, t);
+                module_last_sync_time[module_slot] = t;
+            }
+        }
+    }
+
     pub fn run(&mut self) {
-        let mut q = BinaryHeap::new();
+        let started_at = Instant::now();
+        let mut progress = self.progress.as_ref().map(ProgressReporter::new);
+        let mut q = EventQueue::new(
+            self.coalesce_wakeups,
+            self.module_contexts
+                .iter()
+                .map(|ctx| ctx.priority)
+                .collect(),
+        );
         // get module writing topics
         let mut module_last_sync_time = vec![SystemTime::UNIX_EPOCH; self.module_contexts.len()];
         let topic_last_update_time = self.comms_system.get_all_topic_update_time();
         let module_subscribed_topics = self.comms_system.get_module_subscribed_topics();
         let topic_name = self.comms_system.get_topic_name();
-        let module_name = self
-            .module_contexts
-            .iter()
-            .map(|ctx| ctx.name.to_string())
-            .collect::<Vec<_>>();
         assert_eq!(module_last_sync_time.len(), self.module_contexts.len());
         assert_eq!(module_subscribed_topics.len(), self.module_contexts.len());
         assert_eq!(topic_last_update_time.len(), self.topic_readers.len());
@@ -108,50 +513,113 @@ impl SimulationEngine {
         // call start for each modules
         for ctx in &mut self.module_contexts {
             debug!("start module({})", ctx.name);
-            ctx.module.start();
+            match &mut ctx.execution {
+                ModuleExecution::Inline(module) => module.start(),
+                ModuleExecution::Threaded(module) => module.start(),
+            }
         }
         // run modules with next iteration start time
         for (module_slot, ctx) in self.module_contexts.iter().enumerate() {
             let module_id = ModuleId { slot: module_slot };
-            if let Some(t) = ctx.module.next_iteration_start_at() {
-                let event = EngineEvent::Run(module_id);
-                let e = TimedEvent { time: t, event };
-                q.push(Reverse(e));
+            if let Some(t) = ctx.next_iteration_start_at() {
+                q.schedule(module_id, t);
             }
         }
         // start simulation
-        while let Some(Reverse(TimedEvent { time, event })) = q.pop() {
-            if !self.comms_system.is_world_running.get() {
-                break;
+        let mut dispatched_at = SystemTime::UNIX_EPOCH;
+        // the end of the shutdown phase once it started
+        let mut shutdown_until: Option<SystemTime> = None;
+        loop {
+            // barrier: threaded modules must finish before the clock moves forward,
+            // or before they are scheduled again
+            if self.has_pending_modules() {
+                let reached_barrier = match q.peek() {
+                    Some(TimedEvent {
+                        time,
+                        event: EngineEvent::Run(module_id),
+                        ..
+                    }) => {
+                        *time > dispatched_at || self.module_contexts[module_id.slot].is_pending()
+                    }
+                    None => true,
+                };
+                if reached_barrier {
+                    let time = self.comms_system.time_provider.time();
+                    let joined = self.join_threaded_modules();
+                    self.run_hooks(&joined, time);
+                    for module_id in joined {
+                        self.schedule_next_iteration(&mut q, module_id, time);
+                    }
+                    self.wake_subscribers(
+                        &mut q,
+                        &mut module_last_sync_time,
+                        &topic_last_update_time,
+                        &module_subscribed_topics,
+                    );
+                    continue;
+                }
+            }
+
+            let realtime = self.mode == EngineMode::Realtime
+                && self.comms_system.is_world_running.get()
+                && shutdown_until.is_none();
+            if realtime && self.wait_for_wakeup(q.peek().map(|next| next.time)) {
+                self.schedule_woken_modules(&mut q);
+                continue;
+            }
+            let ended = !self.comms_system.is_world_running.get()
+                || q.peek()
+                    .is_none_or(|next| shutdown_until.is_some_and(|until| next.time > until));
+            if ended {
+                match (self.shutdown_grace, shutdown_until) {
+                    (Some(grace), None) => {
+                        let time = self.comms_system.time_provider.time();
+                        debug!("shutdown phase until {:?} later", grace);
+                        shutdown_until = Some(time + grace);
+                        self.shutdown_modules(&mut q, time);
+                        self.wake_subscribers(
+                            &mut q,
+                            &mut module_last_sync_time,
+                            &topic_last_update_time,
+                            &module_subscribed_topics,
+                        );
+                        continue;
+                    }
+                    _ => break,
+                }
             }
+            let TimedEvent { time, event, .. } = q.pop().unwrap();
+            dispatched_at = time;
+            self.clock_horizon.set(q.peek().map(|next| next.time));
+            let time = self.advance_time(time);
             self.simulation_time.set_time(time);
+            if let Some(progress) = &mut progress {
+                progress.update(time);
+            }
             match event {
                 EngineEvent::Run(module_id) => {
                     let ctx = &mut self.module_contexts[module_id.slot];
                     debug!(
                         "run module({}) at {}",
                         ctx.name,
-                        time.elapsed().unwrap().as_millis()
+                        time.elapsed().unwrap_or_default().as_millis()
                     );
-                    if ctx.module.sync(ctx.comms.as_mut()) {
-                        ctx.module.one_iteration(ctx.comms.as_mut());
-                    }
-                    // check next wakeup time
-                    if let Some(next_iter_t) = ctx.module.next_iteration_start_at() {
-                        let event = EngineEvent::Run(module_id);
-                        q.push(Reverse(TimedEvent {
-                            time: next_iter_t,
-                            event,
-                        }));
-
-                        debug!(
-                            "module {:?} finished. next_iter in {} ms",
-                            ctx.name,
-                            next_iter_t.duration_since(time).unwrap().as_millis()
-                        );
-                    } else {
-                        debug!("module {:?} finished", ctx.name)
+                    match &mut ctx.execution {
+                        ModuleExecution::Inline(module) => {
+                            let iteration_started_at = Instant::now();
+                            traced_run(module.as_mut(), ctx.comms.as_mut(), &ctx.name, time);
+                            ctx.iterations += 1;
+                            ctx.busy += iteration_started_at.elapsed();
+                        }
+                        ModuleExecution::Threaded(module) => {
+                            let inbox = drain_inbox(ctx.comms.as_mut(), ctx.num_read_topics);
+                            module.dispatch(time, inbox);
+                            // outputs are published at the barrier
+                            continue;
+                        }
                     }
+                    self.run_hooks(std::slice::from_ref(&module_id), time);
+                    self.schedule_next_iteration(&mut q, module_id, time);
                     // print topic update time
                     for (i, t) in topic_last_update_time.iter().enumerate() {
                         if t.get()
@@ -165,48 +633,53 @@ impl SimulationEngine {
                         debug!(
                             "topic({}) updated at {} ms ago",
                             topic_name[i],
-                            time.duration_since(t.get()).unwrap().as_millis()
+                            time.duration_since(t.get()).unwrap_or_default().as_millis()
                         );
                     }
 
-                    // wakeup module if topic is newer than last sync time
-                    for module_slot in 0..module_subscribed_topics.len() {
-                        let has_update_since_last_sync = module_subscribed_topics[module_slot]
-                            .iter()
-                            .any(|topic_id| {
-                                let topic_slot = topic_id.slot;
-                                let topic_updated_at = &topic_last_update_time[topic_slot];
-                                let module_last_sync_time = &module_last_sync_time[module_slot];
-                                topic_updated_at.get() > *module_last_sync_time
-                            });
-                        debug!(
-                            "module {} has update: {} wake_on_message: {}",
-                            module_name[module_slot],
-                            has_update_since_last_sync,
-                            self.module_contexts[module_slot].module.wake_on_message()
-                        );
-                        if has_update_since_last_sync
-                            && self.module_contexts[module_slot].module.wake_on_message()
-                        {
-                            let event = EngineEvent::Run(ModuleId { slot: module_slot });
-                            let t = self.comms_system.time_provider.time();
-                            q.push(Reverse(TimedEvent { time: t, event }));
-                            module_last_sync_time[module_slot] = t;
-                        }
-                    }
+                    self.wake_subscribers(
+                        &mut q,
+                        &mut module_last_sync_time,
+                        &topic_last_update_time,
+                        &module_subscribed_topics,
+                    );
                 }
             }
         }
+        if let Some(progress) = &progress {
+            progress.finish();
+        }
+        let time = self.comms_system.time_provider.time();
+        self.run_hooks(&[], time);
+        self.hooks
+            .terminate(&HookContext::new(time, &self.comms_system.is_world_running));
         // terminate modules
         for ctx in &mut self.module_contexts {
-            ctx.module.terminate();
+            if ctx.clock_violations.get() > 0 {
+                error!(
+                    "clock audit: module {} published {} messages out of time",
+                    ctx.name,
+                    ctx.clock_violations.get()
+                );
+            }
+            match &mut ctx.execution {
+                ModuleExecution::Inline(module) => module.terminate(),
+                ModuleExecution::Threaded(module) => module.terminate(),
+            }
         }
+        self.elapsed = started_at.elapsed();
+        self.wakeups = (q.wakeups, q.coalesced);
     }
 }
 
+enum ModuleBuilderKind {
+    Inline(Box<dyn ModuleBuilder>),
+    Threaded(Box<dyn ModuleBuilder + Send>),
+}
+
 struct SimulationModuleBuilderContext {
     id: ModuleId,
-    builder: Box<dyn ModuleBuilder>,
+    builder: ModuleBuilderKind,
     comms_builder: SimulationModuleCommsBuilder,
 }
 
@@ -214,15 +687,122 @@ struct SimulationModuleBuilderContext {
 pub struct SimulationEngineBuilder {
     comms_sys: SimulationCommsSystem,
     module_builder_contexts: Vec<SimulationModuleBuilderContext>,
+    mode: EngineMode,
+    fault_injection: Option<FaultInjection>,
+    hooks: EngineHooks,
+    // on unless disabled
+    coalesce_wakeups: Option<bool>,
+    playback: Option<PlaybackControl>,
+    shutdown_grace: Option<Duration>,
+    run_output: Option<RunOutput>,
+    clock_audit: Option<ClockAudit>,
+    progress: Option<Range<SystemTime>>,
+    wake_channel: Option<(
+        crossbeam::channel::Sender<()>,
+        crossbeam::channel::Receiver<()>,
+    )>,
 }
 
 impl SimulationEngineBuilder {
+    pub fn with_mode(mut self, mode: EngineMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // a waker for the modules fed from other threads, see EngineWaker. The realtime engine
+    // runs on with nothing scheduled until every waker is dropped
+    pub fn waker(&mut self) -> EngineWaker {
+        let (sender, _) = self
+            .wake_channel
+            .get_or_insert_with(crossbeam::channel::unbounded);
+        EngineWaker(sender.clone())
+    }
+
+    // drop, duplicate and delay the messages modules receive
+    pub fn with_fault_injection(mut self, fault_injection: FaultInjection) -> Self {
+        self.fault_injection = Some(fault_injection);
+        self
+    }
+
+    // with coalescing, on by default, a module has at most one pending wake-up, see EventQueue
+    pub fn with_wakeup_coalescing(mut self, coalesce: bool) -> Self {
+        self.coalesce_wakeups = Some(coalesce);
+        self
+    }
+
+    // caps the speed of a simulation and lets it be paused, stepped and fast-forwarded
+    pub fn with_playback(mut self, playback: PlaybackControl) -> Self {
+        self.playback = Some(playback);
+        self
+    }
+
+    // once the world stops, e.g. at the end of the data, the modules get on_shutdown and run
+    // on for grace of engine time, so the orders they send then are still processed
+    pub fn with_shutdown_phase(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = Some(grace);
+        self
+    }
+
+    // creates the directory of the run and gives it to the modules added after, see
+    // ModuleBuilder::init_output
+    pub fn with_run_output(mut self, output: RunOutput) -> Self {
+        if let Err(e) = std::fs::create_dir_all(output.dir()) {
+            error!("failed to create {}: {}", output.dir().display(), e);
+        }
+        self.run_output = Some(output);
+        self
+    }
+
+    pub fn run_output(&self) -> Option<&RunOutput> {
+        self.run_output.as_ref()
+    }
+
+    // checks that the modules publish at the time of the engine, see ClockAudit
+    pub fn with_clock_audit(mut self, audit: ClockAudit) -> Self {
+        self.clock_audit = Some(audit);
+        self
+    }
+
+    // shows a progress bar of the run through the simulation time from start to end, the time
+    // range of its data
+    pub fn with_progress(mut self, start: SystemTime, end: SystemTime) -> Self {
+        self.progress = Some(start..end);
+        self
+    }
+
+    // callbacks on fills, orders, module iterations and termination
+    pub fn with_hooks(mut self, hooks: EngineHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
     pub fn add_module(mut self, module: impl ModuleBuilder + 'static) -> Self {
         self.add_module_dyn(Box::new(module));
         self
     }
 
-    pub fn add_module_dyn(&mut self, mut module_builder: Box<dyn ModuleBuilder>) {
+    pub fn add_module_dyn(&mut self, module_builder: Box<dyn ModuleBuilder>) {
+        self.add_builder(ModuleBuilderKind::Inline(module_builder));
+    }
+
+    // the module is built and run on its own thread
+    pub fn add_threaded_module(mut self, module: impl ModuleBuilder + Send + 'static) -> Self {
+        self.add_threaded_module_dyn(Box::new(module));
+        self
+    }
+
+    pub fn add_threaded_module_dyn(&mut self, module_builder: Box<dyn ModuleBuilder + Send>) {
+        self.add_builder(ModuleBuilderKind::Threaded(module_builder));
+    }
+
+    fn add_builder(&mut self, mut builder: ModuleBuilderKind) {
+        let module_builder: &mut dyn ModuleBuilder = match &mut builder {
+            ModuleBuilderKind::Inline(b) => b.as_mut(),
+            ModuleBuilderKind::Threaded(b) => b.as_mut(),
+        };
+        if let Some(output) = &self.run_output {
+            module_builder.init_output(output);
+        }
         let name = module_builder.name();
 
         let mut module_comm_builder = self.comms_sys.new_builder(name);
@@ -236,7 +816,7 @@ impl SimulationEngineBuilder {
         self.module_builder_contexts
             .push(SimulationModuleBuilderContext {
                 id: module_id,
-                builder: module_builder,
+                builder,
                 comms_builder: module_comm_builder,
             });
     }
@@ -250,6 +830,9 @@ impl SimulationEngineBuilder {
             topic_readers.push(self.comms_sys.get_topic_reader(&topic_id));
         }
 
+        let module_subscribed_topics = self.comms_sys.get_module_subscribed_topics();
+        let topic_name = self.comms_sys.get_topic_name();
+        let clock_horizon = ClockHorizon::default();
         // build all modules
         for SimulationModuleBuilderContext {
             id,
@@ -257,14 +840,51 @@ impl SimulationEngineBuilder {
             comms_builder,
         } in self.module_builder_contexts
         {
-            let name: String = builder.name().into();
-            let module = builder.build();
-            let comms = comms_builder.build();
+            let (name, priority, execution): (String, _, _) = match builder {
+                ModuleBuilderKind::Inline(builder) => (
+                    builder.name().into(),
+                    builder.priority(),
+                    ModuleExecution::Inline(builder.build()),
+                ),
+                ModuleBuilderKind::Threaded(builder) => {
+                    let name: String = builder.name().into();
+                    let priority = builder.priority();
+                    let module = ThreadedModule::spawn(&name, builder);
+                    (name, priority, ModuleExecution::Threaded(module))
+                }
+            };
+            let mut comms = comms_builder.build();
+            if let Some(fault_injection) = &self.fault_injection {
+                let read_topics = module_subscribed_topics[id.slot]
+                    .iter()
+                    .map(|topic_id| topic_name[topic_id.slot].clone())
+                    .collect::<Vec<_>>();
+                comms = fault_injection.wrap(comms, id.slot, &read_topics);
+            }
+            let clock_violations = MessageCount::default();
+            if let Some(audit) = self.clock_audit {
+                comms = ClockAuditComms::wrap(
+                    comms,
+                    audit,
+                    &name,
+                    clock_horizon.clone(),
+                    clock_violations.clone(),
+                );
+            }
+            let (comms, messages_in, messages_out) = CountingModuleComms::wrap(comms);
+            let num_read_topics = module_subscribed_topics[id.slot].len();
             ctxs.push(SimulationModuleContext {
                 id,
-                module,
+                execution,
                 comms,
                 name,
+                priority,
+                num_read_topics,
+                iterations: 0,
+                busy: Duration::ZERO,
+                messages_in,
+                messages_out,
+                clock_violations,
             });
         }
 
@@ -274,6 +894,867 @@ impl SimulationEngineBuilder {
             simulation_time,
             module_contexts: ctxs,
             topic_readers,
+            mode: self.mode,
+            wall_clock: SystemTimeProvider::default(),
+            hooks: self.hooks,
+            coalesce_wakeups: self.coalesce_wakeups.unwrap_or(true),
+            shutdown_grace: self.shutdown_grace,
+            pacer: self.playback.map(Pacer::new),
+            elapsed: Duration::ZERO,
+            wakeups: (0, 0),
+            clock_horizon,
+            progress: self.progress,
+            wake_signals: self.wake_channel.map(|(_, signals)| signals),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, sync::Arc, time::Duration};
+
+    use upstair_type::module::{ModuleCommsBuilder, WriteTopicHandle};
+    use upstair_type::order::CancelOrderRequest;
+    use upstair_type::{MessageHeader, Payload};
+
+    use super::*;
+    use crate::fault_injection::TopicFaults;
+
+    struct TickModule {
+        runs: Rc<RefCell<Vec<SystemTime>>>,
+        schedule: Vec<SystemTime>,
+    }
+
+    impl Module for TickModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            self.runs.borrow_mut().push(comms.time());
+            self.schedule.remove(0);
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.schedule.first().cloned()
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+    }
+
+    struct TickModuleBuilder {
+        runs: Rc<RefCell<Vec<SystemTime>>>,
+        schedule: Vec<SystemTime>,
+    }
+
+    impl ModuleBuilder for TickModuleBuilder {
+        fn init_comm(&mut self, _: &mut dyn ModuleCommsBuilder) {}
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(TickModule {
+                runs: self.runs,
+                schedule: self.schedule,
+            })
+        }
+
+        fn name(&self) -> &str {
+            "tick"
+        }
+    }
+
+    #[test]
+    fn test_simulation_mode_jumps_to_scheduled_time() {
+        let runs = Rc::new(RefCell::new(vec![]));
+        let schedule = vec![
+            SystemTime::UNIX_EPOCH + Duration::from_secs(10),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(3600),
+        ];
+        let mut engine = SimulationEngineBuilder::default()
+            .add_module(TickModuleBuilder {
+                runs: runs.clone(),
+                schedule: schedule.clone(),
+            })
+            .build();
+        assert_eq!(engine.mode(), EngineMode::Simulation);
+        engine.run();
+        assert_eq!(*runs.borrow(), schedule);
+    }
+
+    // logs its name on each run
+    struct NamedModuleBuilder {
+        name: &'static str,
+        priority: ModulePriority,
+        log: Rc<RefCell<Vec<&'static str>>>,
+        schedule: Vec<SystemTime>,
+    }
+
+    struct NamedModule {
+        name: &'static str,
+        log: Rc<RefCell<Vec<&'static str>>>,
+        schedule: Vec<SystemTime>,
+    }
+
+    impl Module for NamedModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, _: &mut dyn ModuleComms) {
+            self.log.borrow_mut().push(self.name);
+            self.schedule.remove(0);
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.schedule.first().cloned()
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+    }
+
+    impl ModuleBuilder for NamedModuleBuilder {
+        fn init_comm(&mut self, _: &mut dyn ModuleCommsBuilder) {}
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(NamedModule {
+                name: self.name,
+                log: self.log,
+                schedule: self.schedule,
+            })
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn priority(&self) -> ModulePriority {
+            self.priority
+        }
+    }
+
+    #[test]
+    fn test_same_time_runs_by_priority() {
+        let schedule = vec![
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+        ];
+        for coalesce in [false, true] {
+            let log = Rc::new(RefCell::new(vec![]));
+            let mut builder = SimulationEngineBuilder::default().with_wakeup_coalescing(coalesce);
+            for (name, priority) in [
+                ("strategy_a", ModulePriority::Strategy),
+                ("observer", ModulePriority::Observer),
+                ("exchange", ModulePriority::Exchange),
+                ("strategy_b", ModulePriority::Strategy),
+                ("feed", ModulePriority::DataFeed),
+            ] {
+                builder = builder.add_module(NamedModuleBuilder {
+                    name,
+                    priority,
+                    log: log.clone(),
+                    schedule: schedule.clone(),
+                });
+            }
+            builder.build().run();
+            // the strategies in the order they are added
+            let order = ["feed", "exchange", "strategy_a", "strategy_b", "observer"];
+            assert_eq!(*log.borrow(), [order, order].concat());
+        }
+    }
+
+    #[test]
+    fn test_hooks_end_iterations_and_terminate() {
+        let runs = Rc::new(RefCell::new(vec![]));
+        let iterations = Rc::new(RefCell::new(vec![]));
+        let terminated_at = Rc::new(Cell::new(None));
+        let schedule = (1..=3)
+            .map(|i| SystemTime::UNIX_EPOCH + Duration::from_secs(i))
+            .collect::<Vec<_>>();
+        let hooks = EngineHooks::default()
+            .on_iteration_end({
+                let iterations = iterations.clone();
+                move |ctx, module| {
+                    iterations.borrow_mut().push(module.to_string());
+                    // a guard stopping the run
+                    if iterations.borrow().len() == 2 {
+                        ctx.request_terminate();
+                    }
+                }
+            })
+            .on_terminate({
+                let terminated_at = terminated_at.clone();
+                move |ctx| terminated_at.set(Some(ctx.time()))
+            });
+        let mut engine = SimulationEngineBuilder::default()
+            .with_hooks(hooks)
+            .add_module(TickModuleBuilder {
+                runs: runs.clone(),
+                schedule: schedule.clone(),
+            })
+            .build();
+        engine.run();
+        assert_eq!(*runs.borrow(), schedule[..2]);
+        assert_eq!(*iterations.borrow(), vec!["tick", "tick"]);
+        assert_eq!(terminated_at.get(), Some(schedule[1]));
+    }
+
+    #[test]
+    fn test_realtime_mode_follows_wall_clock() {
+        let runs = Rc::new(RefCell::new(vec![]));
+        let started_at = SystemTime::now();
+        let schedule = vec![started_at, started_at + Duration::from_millis(50)];
+        let mut engine = SimulationEngineBuilder::default()
+            .with_mode(EngineMode::Realtime)
+            .add_module(TickModuleBuilder {
+                runs: runs.clone(),
+                schedule: schedule.clone(),
+            })
+            .build();
+        engine.run();
+        let runs = runs.borrow();
+        assert_eq!(runs.len(), 2);
+        assert!(runs[0] >= schedule[0]);
+        assert!(runs[1] >= schedule[1]);
+        assert!(SystemTime::now().duration_since(started_at).unwrap() >= Duration::from_millis(50));
+    }
+
+    // runs on the data another thread feeds it, or at its timer, and terminates the world
+    // once it received until items
+    struct FeedModule {
+        inbox: Arc<std::sync::Mutex<Vec<SystemTime>>>,
+        timer: Option<SystemTime>,
+        received: usize,
+        until: usize,
+    }
+
+    impl Module for FeedModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            self.received += self.inbox.lock().unwrap().drain(..).count();
+            if self.received >= self.until {
+                comms.request_terminate();
+            }
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            let arrived_at = self.inbox.lock().unwrap().first().cloned();
+            arrived_at.into_iter().chain(self.timer).min()
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+    }
+
+    struct FeedModuleBuilder(FeedModule);
+
+    impl ModuleBuilder for FeedModuleBuilder {
+        fn init_comm(&mut self, _: &mut dyn ModuleCommsBuilder) {}
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(self.0)
+        }
+
+        fn name(&self) -> &str {
+            "feed"
+        }
+    }
+
+    // a thread feeding the inbox an item every 20 ms, waking the engine after each
+    fn spawn_feed(
+        inbox: Arc<std::sync::Mutex<Vec<SystemTime>>>,
+        waker: EngineWaker,
+        items: usize,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for _ in 0..items {
+                thread::sleep(Duration::from_millis(20));
+                inbox.lock().unwrap().push(SystemTime::now());
+                waker.wake();
+            }
+        })
+    }
+
+    #[test]
+    fn test_realtime_waker_ends_sleep() {
+        let inbox = Arc::new(std::sync::Mutex::new(vec![]));
+        let started_at = SystemTime::now();
+        let mut builder = SimulationEngineBuilder::default().with_mode(EngineMode::Realtime);
+        let feed = spawn_feed(inbox.clone(), builder.waker(), 1);
+        let mut engine = builder
+            .add_module(FeedModuleBuilder(FeedModule {
+                inbox,
+                timer: Some(started_at + Duration::from_secs(60)),
+                received: 0,
+                until: 1,
+            }))
+            .build();
+        engine.run();
+        feed.join().unwrap();
+        // the data ended the sleep towards the timer
+        assert!(SystemTime::now().duration_since(started_at).unwrap() < Duration::from_secs(10));
+        assert_eq!(engine.profile().modules[0].iterations, 1);
+    }
+
+    #[test]
+    fn test_realtime_runs_on_with_nothing_scheduled() {
+        let inbox = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut builder = SimulationEngineBuilder::default().with_mode(EngineMode::Realtime);
+        let feed = spawn_feed(inbox.clone(), builder.waker(), 2);
+        let mut engine = builder
+            .add_module(FeedModuleBuilder(FeedModule {
+                inbox,
+                timer: None,
+                received: 0,
+                until: 2,
+            }))
+            .build();
+        engine.run();
+        feed.join().unwrap();
+        assert_eq!(engine.profile().modules[0].iterations, 2);
+    }
+
+    // publishes an increasing counter on its schedule, terminates the world when done
+    struct CounterModule {
+        write_handle: WriteTopicHandle,
+        schedule: Vec<SystemTime>,
+        counter: usize,
+    }
+
+    impl Module for CounterModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            self.counter += 1;
+            comms.publish(
+                &self.write_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: comms.time(),
+                    },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol: "BTCUSDT",
+                        client_order_id: Arc::from(self.counter.to_string()),
+                        owner: None,
+                    }),
+                },
+            );
+            self.schedule.remove(0);
+            if self.schedule.is_empty() {
+                comms.request_terminate();
+            }
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.schedule.first().cloned()
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+
+        fn on_shutdown(&mut self, comms: &mut dyn ModuleComms) {
+            comms.publish(
+                &self.write_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: comms.time(),
+                    },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol: "BTCUSDT",
+                        client_order_id: Arc::from("shutdown"),
+                        owner: None,
+                    }),
+                },
+            );
+        }
+    }
+
+    struct CounterModuleBuilder {
+        write_handle: Option<WriteTopicHandle>,
+        schedule: Vec<SystemTime>,
+    }
+
+    impl ModuleBuilder for CounterModuleBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let topic = comms.get_topic("order");
+            self.write_handle = comms.publish_topic(&topic).into();
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(CounterModule {
+                write_handle: self.write_handle.unwrap(),
+                schedule: self.schedule,
+                counter: 0,
+            })
+        }
+
+        fn name(&self) -> &str {
+            "counter"
+        }
+    }
+
+    // records received counters with the time they were received at
+    struct RecorderModule {
+        read_handle: ReadTopicHandle,
+        received: Rc<RefCell<Vec<(SystemTime, String)>>>,
+    }
+
+    impl Module for RecorderModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+            while let Some(msg) = comms.receive(&self.read_handle) {
+                if let Payload::CancelOrderRequest(req) = msg.payload {
+                    self.received
+                        .borrow_mut()
+                        .push((comms.time(), req.client_order_id.to_string()));
+                }
+            }
+            true
+        }
+
+        fn one_iteration(&mut self, _: &mut dyn ModuleComms) {}
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            None
+        }
+
+        fn wake_on_message(&self) -> bool {
+            true
+        }
+    }
+
+    struct RecorderModuleBuilder {
+        read_handle: Option<ReadTopicHandle>,
+        received: Rc<RefCell<Vec<(SystemTime, String)>>>,
+    }
+
+    impl ModuleBuilder for RecorderModuleBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let topic = comms.get_topic("order");
+            self.read_handle = comms.subscribe_topic(&topic).into();
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(RecorderModule {
+                read_handle: self.read_handle.unwrap(),
+                received: self.received,
+            })
+        }
+
+        fn name(&self) -> &str {
+            "recorder"
+        }
+    }
+
+    fn run_counter_and_recorder(
+        threaded: bool,
+        shutdown_grace: Option<Duration>,
+    ) -> Vec<(SystemTime, String)> {
+        let received = Rc::new(RefCell::new(vec![]));
+        let schedule = (1..=5)
+            .map(|i| SystemTime::UNIX_EPOCH + Duration::from_secs(i))
+            .collect::<Vec<_>>();
+        let counter = CounterModuleBuilder {
+            write_handle: None,
+            schedule,
+        };
+        let mut builder = SimulationEngineBuilder::default();
+        if let Some(grace) = shutdown_grace {
+            builder = builder.with_shutdown_phase(grace);
+        }
+        let builder = if threaded {
+            builder.add_threaded_module(counter)
+        } else {
+            builder.add_module(counter)
+        };
+        let mut engine = builder
+            .add_module(RecorderModuleBuilder {
+                read_handle: None,
+                received: received.clone(),
+            })
+            .build();
+        engine.run();
+        let received = received.borrow().clone();
+        received
+    }
+
+    // publishes on each run at the time stamp gives
+    struct StampModule {
+        write_handle: WriteTopicHandle,
+        schedule: Vec<SystemTime>,
+        stamp: fn(SystemTime) -> SystemTime,
+    }
+
+    impl Module for StampModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            comms.publish(
+                &self.write_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: (self.stamp)(comms.time()),
+                    },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol: "BTCUSDT",
+                        client_order_id: Arc::from("stamped"),
+                        owner: None,
+                    }),
+                },
+            );
+            self.schedule.remove(0);
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.schedule.first().cloned()
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+    }
+
+    struct StampModuleBuilder {
+        write_handle: Option<WriteTopicHandle>,
+        stamp: fn(SystemTime) -> SystemTime,
+    }
+
+    impl ModuleBuilder for StampModuleBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let topic = comms.get_topic("order");
+            self.write_handle = comms.publish_topic(&topic).into();
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(StampModule {
+                write_handle: self.write_handle.unwrap(),
+                schedule: vec![
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+                ],
+                stamp: self.stamp,
+            })
+        }
+
+        fn name(&self) -> &str {
+            "stamp"
+        }
+    }
+
+    // the clock violations of a stamp module run next to a module due at 4s
+    fn run_stamped(audit: ClockAudit, stamp: fn(SystemTime) -> SystemTime) -> u64 {
+        let mut engine = SimulationEngineBuilder::default()
+            .with_clock_audit(audit)
+            .add_module(StampModuleBuilder {
+                write_handle: None,
+                stamp,
+            })
+            .add_module(TickModuleBuilder {
+                runs: Rc::new(RefCell::new(vec![])),
+                schedule: vec![SystemTime::UNIX_EPOCH + Duration::from_secs(4)],
+            })
+            .build();
+        engine.run();
+        engine.profile().modules[0].clock_violations
+    }
+
+    #[test]
+    fn test_clock_audit() {
+        assert_eq!(run_stamped(ClockAudit::Lenient, |now| now), 0);
+        // up to the next event at 4s
+        assert_eq!(
+            run_stamped(ClockAudit::Lenient, |now| now + Duration::from_secs(2)),
+            0
+        );
+        assert_eq!(
+            run_stamped(ClockAudit::Lenient, |now| now + Duration::from_secs(3)),
+            1
+        );
+        assert_eq!(
+            run_stamped(ClockAudit::Lenient, |now| now - Duration::from_millis(1)),
+            2
+        );
+        assert_eq!("Strict".parse::<ClockAudit>(), Ok(ClockAudit::Strict));
+        assert!("loose".parse::<ClockAudit>().is_err());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "module stamp published a message at 4001 ms after the next event at 4000 ms"
+    )]
+    fn test_strict_clock_audit_panics() {
+        run_stamped(ClockAudit::Strict, |now| now + Duration::from_millis(3001));
+    }
+
+    #[test]
+    fn test_fault_injection_delays_delivery() {
+        let received = Rc::new(RefCell::new(vec![]));
+        let schedule = (1..=5)
+            .map(|i| SystemTime::UNIX_EPOCH + Duration::from_secs(i))
+            .collect::<Vec<_>>();
+        let max_delay = Duration::from_millis(500);
+        let mut engine = SimulationEngineBuilder::default()
+            .with_fault_injection(FaultInjection::new(3).with_topic(TopicFaults {
+                delay_rate: 1.0,
+                max_delay,
+                ..TopicFaults::new("order")
+            }))
+            .add_module(CounterModuleBuilder {
+                write_handle: None,
+                schedule,
+            })
+            .add_module(RecorderModuleBuilder {
+                read_handle: None,
+                received: received.clone(),
+            })
+            .build();
+        engine.run();
+        let received = received.borrow();
+        assert_eq!(received.len(), 4);
+        for (received_at, id) in received.iter() {
+            // the recorder is woken when the held message is due
+            let sent_at = SystemTime::UNIX_EPOCH + Duration::from_secs(id.parse().unwrap());
+            assert!(*received_at > sent_at && *received_at <= sent_at + max_delay);
+        }
+    }
+
+    #[test]
+    fn test_profile_counts_iterations_and_messages() {
+        for threaded in [false, true] {
+            let schedule = (1..=5)
+                .map(|i| SystemTime::UNIX_EPOCH + Duration::from_secs(i))
+                .collect::<Vec<_>>();
+            let counter = CounterModuleBuilder {
+                write_handle: None,
+                schedule,
+            };
+            let builder = if threaded {
+                SimulationEngineBuilder::default().add_threaded_module(counter)
+            } else {
+                SimulationEngineBuilder::default().add_module(counter)
+            };
+            let mut engine = builder
+                .add_module(RecorderModuleBuilder {
+                    read_handle: None,
+                    received: Rc::new(RefCell::new(vec![])),
+                })
+                .build();
+            engine.run();
+            let profile = engine.profile();
+            assert_eq!(profile.modules.len(), 2);
+            let (counter, recorder) = (&profile.modules[0], &profile.modules[1]);
+            assert_eq!(counter.name, "counter");
+            assert_eq!((counter.messages_in, counter.messages_out), (0, 5));
+            assert_eq!(recorder.messages_out, 0);
+            // the last counter terminates the run before the recorder sees it
+            assert_eq!(recorder.messages_in, 4);
+            assert_eq!(recorder.iterations, 4);
+            assert!(counter.iterations >= 4);
+            assert!(profile.elapsed >= counter.busy);
+        }
+    }
+
+    // ends the run at its first iteration, failing it
+    struct FailingModule {
+        failed: bool,
+    }
+
+    impl Module for FailingModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            self.failed = true;
+            comms.request_terminate();
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            (!self.failed).then_some(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+
+        fn failure(&self) -> Option<String> {
+            self.failed.then(|| "input cut short".to_string())
+        }
+    }
+
+    struct FailingModuleBuilder;
+
+    impl ModuleBuilder for FailingModuleBuilder {
+        fn init_comm(&mut self, _: &mut dyn ModuleCommsBuilder) {}
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(FailingModule { failed: false })
+        }
+
+        fn name(&self) -> &str {
+            "failing"
+        }
+    }
+
+    #[test]
+    fn test_module_failure_fails_the_run() {
+        for threaded in [false, true] {
+            let builder = if threaded {
+                SimulationEngineBuilder::default().add_threaded_module(FailingModuleBuilder)
+            } else {
+                SimulationEngineBuilder::default().add_module(FailingModuleBuilder)
+            };
+            let mut engine = builder
+                .add_module(TickModuleBuilder {
+                    runs: Rc::new(RefCell::new(vec![])),
+                    schedule: vec![SystemTime::UNIX_EPOCH + Duration::from_secs(2)],
+                })
+                .build();
+            assert!(engine.failures().is_empty());
+            engine.run();
+            assert_eq!(
+                engine.failures(),
+                vec!["module(failing) failed: input cut short".to_string()]
+            );
+        }
+    }
+
+    // woken by every message, and wants to run once more at `alarm`
+    struct AlarmModule {
+        read_handle: ReadTopicHandle,
+        alarm: Option<SystemTime>,
+        runs: Rc<RefCell<Vec<SystemTime>>>,
+    }
+
+    impl Module for AlarmModule {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+            while comms.receive_shared(&self.read_handle).is_some() {}
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            self.runs.borrow_mut().push(comms.time());
+            if self.alarm.is_some_and(|alarm| comms.time() >= alarm) {
+                self.alarm = None;
+            }
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.alarm
+        }
+
+        fn wake_on_message(&self) -> bool {
+            true
+        }
+    }
+
+    struct AlarmModuleBuilder {
+        read_handle: Option<ReadTopicHandle>,
+        alarm: SystemTime,
+        runs: Rc<RefCell<Vec<SystemTime>>>,
+    }
+
+    impl ModuleBuilder for AlarmModuleBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let topic = comms.get_topic("order");
+            self.read_handle = comms.subscribe_topic(&topic).into();
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(AlarmModule {
+                read_handle: self.read_handle.unwrap(),
+                alarm: Some(self.alarm),
+                runs: self.runs,
+            })
+        }
+
+        fn name(&self) -> &str {
+            "alarm"
+        }
+    }
+
+    #[test]
+    fn test_wakeup_coalescing() {
+        let alarm = SystemTime::UNIX_EPOCH + Duration::from_millis(10500);
+        for coalesce in [false, true] {
+            let runs = Rc::new(RefCell::new(vec![]));
+            let mut engine = SimulationEngineBuilder::default()
+                .with_wakeup_coalescing(coalesce)
+                .add_module(CounterModuleBuilder {
+                    write_handle: None,
+                    schedule: (1..=20)
+                        .map(|i| SystemTime::UNIX_EPOCH + Duration::from_secs(i))
+                        .collect(),
+                })
+                .add_module(AlarmModuleBuilder {
+                    read_handle: None,
+                    alarm,
+                    runs: runs.clone(),
+                })
+                .build();
+            engine.run();
+            let runs = runs.borrow();
+            let alarm_runs = runs.iter().filter(|t| **t == alarm).count();
+            let profile = engine.profile();
+            if coalesce {
+                // the alarm pending since the start absorbs the wake-ups of every iteration
+                assert_eq!(alarm_runs, 1);
+                assert!(profile.coalesced_wakeups > 0);
+            } else {
+                // each wake-up before the alarm scheduled the alarm once more
+                assert!(alarm_runs > 1);
+                assert_eq!(profile.coalesced_wakeups, 0);
+            }
+            // woken by every counter but the last, which terminates the run
+            assert_eq!(runs.iter().filter(|t| **t != alarm).count(), 19);
+        }
+    }
+
+    #[test]
+    fn test_threaded_module_matches_inline() {
+        let inline = run_counter_and_recorder(false, None);
+        let threaded = run_counter_and_recorder(true, None);
+        assert_eq!(inline.len(), 4);
+        assert_eq!(inline[0].0, SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        assert_eq!(inline[0].1, "1");
+        assert_eq!(threaded, inline);
+    }
+
+    #[test]
+    fn test_shutdown_phase() {
+        let end = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+        for threaded in [false, true] {
+            let received = run_counter_and_recorder(threaded, Some(Duration::from_secs(1)));
+            // the last counter and the one published on shutdown are still delivered
+            let ids: Vec<&str> = received.iter().map(|(_, id)| id.as_str()).collect();
+            assert_eq!(ids, vec!["1", "2", "3", "4", "5", "shutdown"]);
+            assert_eq!(received[5].0, end);
         }
     }
 }
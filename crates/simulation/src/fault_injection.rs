@@ -0,0 +1,344 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::debug;
+use upstair_type::{
+    module::{ModuleComms, ReadTopicHandle, WriteTopicHandle},
+    Message,
+};
+
+// Faults applied to the messages a module receives from a topic, each subscriber draws its
+// own faults. Rates are fractions of the messages in [0, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicFaults {
+    pub topic: String,
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+    // delayed messages are held back up to max_delay, which reorders them
+    pub delay_rate: f64,
+    pub max_delay: Duration,
+}
+
+impl TopicFaults {
+    pub fn new(topic: &str) -> Self {
+        TopicFaults {
+            topic: topic.into(),
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            delay_rate: 0.0,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+// topic:drop=0.01,duplicate=0.01,delay=0.1,max_delay_ms=200, omitted faults are off
+impl FromStr for TopicFaults {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((topic, faults)) = s.split_once(':') else {
+            return Err(format!(
+                "invalid fault {s}, expected topic:drop=0.01,duplicate=0.01,delay=0.1,max_delay_ms=200"
+            ));
+        };
+        let mut topic_faults = TopicFaults::new(topic);
+        for fault in faults.split(',').filter(|f| !f.is_empty()) {
+            let (name, value) = fault
+                .split_once('=')
+                .ok_or_else(|| format!("invalid fault {fault}, expected name=value"))?;
+            let value: f64 = value
+                .parse()
+                .map_err(|_| format!("invalid value of fault {name}: {value}"))?;
+            let rate = match name {
+                "drop" => &mut topic_faults.drop_rate,
+                "duplicate" => &mut topic_faults.duplicate_rate,
+                "delay" => &mut topic_faults.delay_rate,
+                "max_delay_ms" if value < 0.0 => {
+                    return Err("max_delay_ms must not be negative".to_string())
+                }
+                "max_delay_ms" => {
+                    topic_faults.max_delay = Duration::from_secs_f64(value / 1000.0);
+                    continue;
+                }
+                _ => return Err(format!("unknown fault {fault}")),
+            };
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("rate of fault {name} must be in [0, 1]"));
+            }
+            *rate = value;
+        }
+        Ok(topic_faults)
+    }
+}
+
+// Unreliable transport for robustness tests, see SimulationEngineBuilder::with_fault_injection
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjection {
+    topics: Vec<TopicFaults>,
+    seed: u64,
+}
+
+impl FaultInjection {
+    pub fn new(seed: u64) -> Self {
+        FaultInjection {
+            topics: vec![],
+            seed,
+        }
+    }
+
+    pub fn with_topic(mut self, faults: TopicFaults) -> Self {
+        self.topics.push(faults);
+        self
+    }
+
+    // read_topics are the topic names of the module's read slots
+    pub(crate) fn wrap(
+        &self,
+        comms: Box<dyn ModuleComms>,
+        module_slot: usize,
+        read_topics: &[String],
+    ) -> Box<dyn ModuleComms> {
+        let faults: Vec<Option<TopicFaults>> = read_topics
+            .iter()
+            .map(|topic| self.topics.iter().find(|f| f.topic == *topic).cloned())
+            .collect();
+        if faults.iter().all(Option::is_none) {
+            return comms;
+        }
+        Box::new(FaultyModuleComms {
+            inner: comms,
+            held: faults.iter().map(|_| vec![]).collect(),
+            faults,
+            // every module draws its own faults, the same for each run with the seed
+            rng: StdRng::seed_from_u64(self.seed.wrapping_add(module_slot as u64)),
+            seq: 0,
+        })
+    }
+}
+
+struct HeldMessage {
+    deliver_at: SystemTime,
+    // keeps the order of messages delivered at the same time
+    seq: u64,
+    message: Arc<Message>,
+}
+
+// Receives through the inner comms and applies the faults of each read topic
+struct FaultyModuleComms {
+    inner: Box<dyn ModuleComms>,
+    faults: Vec<Option<TopicFaults>>,
+    // messages of each read slot waiting for their delivery time
+    held: Vec<Vec<HeldMessage>>,
+    rng: StdRng,
+    seq: u64,
+}
+
+impl FaultyModuleComms {
+    fn hold(&mut self, slot: usize, deliver_at: SystemTime, message: Arc<Message>) {
+        self.seq += 1;
+        self.held[slot].push(HeldMessage {
+            deliver_at,
+            seq: self.seq,
+            message,
+        });
+    }
+}
+
+impl ModuleComms for FaultyModuleComms {
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        let Some(faults) = self.faults[topic.slot].clone() else {
+            return self.inner.receive_shared(topic);
+        };
+        let now = self.inner.time();
+        while let Some(message) = self.inner.receive_shared(topic) {
+            if self.rng.gen_bool(faults.drop_rate) {
+                debug!("fault injection: drop message of {}", faults.topic);
+                continue;
+            }
+            let copies = if self.rng.gen_bool(faults.duplicate_rate) {
+                debug!("fault injection: duplicate message of {}", faults.topic);
+                2
+            } else {
+                1
+            };
+            for _ in 0..copies {
+                let delay = if self.rng.gen_bool(faults.delay_rate) {
+                    faults.max_delay.mul_f64(self.rng.gen())
+                } else {
+                    Duration::ZERO
+                };
+                self.hold(topic.slot, now + delay, message.clone());
+            }
+        }
+        let held = &mut self.held[topic.slot];
+        let next = held
+            .iter()
+            .enumerate()
+            .filter(|(_, held)| held.deliver_at <= now)
+            .min_by_key(|(_, held)| (held.deliver_at, held.seq))
+            .map(|(i, _)| i)?;
+        Some(held.remove(next).message)
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        self.inner.publish(topic, message)
+    }
+
+    fn request_terminate(&mut self) {
+        self.inner.request_terminate()
+    }
+
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        self.held.iter().flatten().map(|held| held.deliver_at).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::VecDeque, rc::Rc};
+
+    use upstair_type::{order::CancelOrderRequest, MessageHeader, Payload};
+
+    use super::*;
+
+    // a single topic of queued messages
+    struct QueueComms {
+        time: Rc<Cell<SystemTime>>,
+        queue: VecDeque<Message>,
+    }
+
+    impl ModuleComms for QueueComms {
+        fn time(&self) -> SystemTime {
+            self.time.get()
+        }
+
+        fn receive_shared(&mut self, _: &ReadTopicHandle) -> Option<Arc<Message>> {
+            self.queue.pop_front().map(Arc::new)
+        }
+
+        fn publish(&mut self, _: &WriteTopicHandle, _: Message) {}
+
+        fn request_terminate(&mut self) {}
+    }
+
+    fn message(id: usize) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: SystemTime::UNIX_EPOCH,
+            },
+            payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                symbol: "BTCUSDT",
+                client_order_id: Arc::from(id.to_string()),
+                owner: None,
+            }),
+        }
+    }
+
+    fn id_of(message: Message) -> String {
+        match message.payload {
+            Payload::CancelOrderRequest(req) => req.client_order_id.to_string(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn faulty_comms(faults: TopicFaults, n: usize) -> (Box<dyn ModuleComms>, Rc<Cell<SystemTime>>) {
+        let time = Rc::new(Cell::new(SystemTime::UNIX_EPOCH));
+        let inner = QueueComms {
+            time: time.clone(),
+            queue: (0..n).map(message).collect(),
+        };
+        let comms = FaultInjection::new(1).with_topic(faults).wrap(
+            Box::new(inner),
+            0,
+            &["order".to_string()],
+        );
+        (comms, time)
+    }
+
+    fn receive_all(comms: &mut dyn ModuleComms) -> Vec<String> {
+        let topic = ReadTopicHandle { slot: 0 };
+        std::iter::from_fn(|| comms.receive(&topic))
+            .map(id_of)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_topic_faults() {
+        let faults: TopicFaults = "order:drop=0.1,delay=0.5,max_delay_ms=200".parse().unwrap();
+        assert_eq!(faults.topic, "order");
+        assert_eq!(faults.drop_rate, 0.1);
+        assert_eq!(faults.duplicate_rate, 0.0);
+        assert_eq!(faults.delay_rate, 0.5);
+        assert_eq!(faults.max_delay, Duration::from_millis(200));
+
+        assert!("order".parse::<TopicFaults>().is_err());
+        assert!("order:drop=2".parse::<TopicFaults>().is_err());
+        assert!("order:lose=0.1".parse::<TopicFaults>().is_err());
+    }
+
+    #[test]
+    fn test_drop_and_duplicate() {
+        let (mut comms, _) = faulty_comms(
+            TopicFaults {
+                drop_rate: 1.0,
+                ..TopicFaults::new("order")
+            },
+            5,
+        );
+        assert!(receive_all(comms.as_mut()).is_empty());
+
+        let (mut comms, _) = faulty_comms(
+            TopicFaults {
+                duplicate_rate: 1.0,
+                ..TopicFaults::new("order")
+            },
+            2,
+        );
+        assert_eq!(receive_all(comms.as_mut()), vec!["0", "0", "1", "1"]);
+
+        // topics without faults are passed through
+        let inner = QueueComms {
+            time: Rc::new(Cell::new(SystemTime::UNIX_EPOCH)),
+            queue: (0..2).map(message).collect(),
+        };
+        let mut comms = FaultInjection::new(1)
+            .with_topic(TopicFaults {
+                drop_rate: 1.0,
+                ..TopicFaults::new("order")
+            })
+            .wrap(Box::new(inner), 0, &["account".to_string()]);
+        assert_eq!(receive_all(comms.as_mut()), vec!["0", "1"]);
+    }
+
+    #[test]
+    fn test_delay_holds_messages() {
+        let (mut comms, time) = faulty_comms(
+            TopicFaults {
+                delay_rate: 1.0,
+                max_delay: Duration::from_millis(100),
+                ..TopicFaults::new("order")
+            },
+            20,
+        );
+        assert!(receive_all(comms.as_mut()).is_empty());
+        let deliver_at = comms.next_delivery_at().unwrap();
+        assert!(deliver_at <= SystemTime::UNIX_EPOCH + Duration::from_millis(100));
+
+        time.set(SystemTime::UNIX_EPOCH + Duration::from_millis(100));
+        let received = receive_all(comms.as_mut());
+        assert!(comms.next_delivery_at().is_none());
+        // all delivered, in the order of their delays
+        let mut sorted = received.clone();
+        sorted.sort_by_key(|id| id.parse::<usize>().unwrap());
+        assert_eq!(sorted, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+        assert_ne!(received, sorted);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{cell::Cell, time::SystemTime};
+
+use upstair_type::{
+    order::{OrderRequest, OrderResult},
+    Message, Payload,
+};
+
+// What a hook sees of the engine when it is called
+pub struct HookContext<'a> {
+    time: SystemTime,
+    is_world_running: &'a Cell<bool>,
+}
+
+impl<'a> HookContext<'a> {
+    pub(crate) fn new(time: SystemTime, is_world_running: &'a Cell<bool>) -> Self {
+        HookContext {
+            time,
+            is_world_running,
+        }
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    // stops the simulation like a module requesting terminate, for guards
+    pub fn request_terminate(&self) {
+        self.is_world_running.set(false);
+    }
+}
+
+type FillHook = Box<dyn FnMut(&HookContext, &OrderResult)>;
+type OrderHook = Box<dyn FnMut(&HookContext, &OrderRequest)>;
+type IterationEndHook = Box<dyn FnMut(&HookContext, &str)>;
+type TerminateHook = Box<dyn FnMut(&HookContext)>;
+
+// Callbacks for analytics or guards which don't need a full module. They see every message
+// published by the modules once the publishing module's iteration ends, topic by topic.
+#[derive(Default)]
+pub struct EngineHooks {
+    on_fill: Vec<FillHook>,
+    on_order: Vec<OrderHook>,
+    on_iteration_end: Vec<IterationEndHook>,
+    on_terminate: Vec<TerminateHook>,
+}
+
+impl EngineHooks {
+    // order results with a filled quantity, one per fill
+    pub fn on_fill(mut self, hook: impl FnMut(&HookContext, &OrderResult) + 'static) -> Self {
+        self.on_fill.push(Box::new(hook));
+        self
+    }
+
+    // new order requests, before the market sees them
+    pub fn on_order(mut self, hook: impl FnMut(&HookContext, &OrderRequest) + 'static) -> Self {
+        self.on_order.push(Box::new(hook));
+        self
+    }
+
+    // after each module iteration, with the module name
+    pub fn on_iteration_end(mut self, hook: impl FnMut(&HookContext, &str) + 'static) -> Self {
+        self.on_iteration_end.push(Box::new(hook));
+        self
+    }
+
+    // once the simulation stopped, before the modules are terminated
+    pub fn on_terminate(mut self, hook: impl FnMut(&HookContext) + 'static) -> Self {
+        self.on_terminate.push(Box::new(hook));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_fill.is_empty()
+            && self.on_order.is_empty()
+            && self.on_iteration_end.is_empty()
+            && self.on_terminate.is_empty()
+    }
+
+    pub(crate) fn message(&mut self, ctx: &HookContext, message: &Message) {
+        match &message.payload {
+            Payload::OrderRequest(req) => self.on_order.iter_mut().for_each(|hook| hook(ctx, req)),
+            Payload::BatchOrderRequest(batch) => {
+                for req in &batch.orders {
+                    self.on_order.iter_mut().for_each(|hook| hook(ctx, req))
+                }
+            }
+            payload => {
+                for result in payload.order_results() {
+                    if result.filled_quantity > 0.0 {
+                        self.on_fill.iter_mut().for_each(|hook| hook(ctx, result))
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn iteration_end(&mut self, ctx: &HookContext, module: &str) {
+        self.on_iteration_end
+            .iter_mut()
+            .for_each(|hook| hook(ctx, module));
+    }
+
+    pub(crate) fn terminate(&mut self, ctx: &HookContext) {
+        self.on_terminate.iter_mut().for_each(|hook| hook(ctx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, sync::Arc};
+
+    use upstair_type::{
+        order::{CancelOrderRequest, OrderStatus, TimeInForce, TradeSide, TradeType},
+        MessageHeader,
+    };
+
+    use super::*;
+
+    fn message(payload: Payload) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: SystemTime::UNIX_EPOCH,
+            },
+            payload,
+        }
+    }
+
+    fn order_result(filled_quantity: f64, status: OrderStatus) -> Payload {
+        Payload::OrderResult(OrderResult {
+            symbol: "BTCUSDT",
+            at: SystemTime::UNIX_EPOCH,
+            client_order_id: Arc::from("B0"),
+            filled_quantity,
+            price: 100.0,
+            is_buy: true,
+            status,
+            seq: 0,
+            owner: None,
+            reject_reason: None,
+        })
+    }
+
+    #[test]
+    fn test_hooks_dispatch_messages() {
+        let fills = Rc::new(Cell::new(0.0));
+        let orders = Rc::new(Cell::new(0));
+        let mut hooks = EngineHooks::default()
+            .on_fill({
+                let fills = fills.clone();
+                move |_, result| fills.set(fills.get() + result.filled_quantity)
+            })
+            .on_order({
+                let orders = orders.clone();
+                move |ctx, _| {
+                    orders.set(orders.get() + 1);
+                    ctx.request_terminate();
+                }
+            });
+        assert!(!hooks.is_empty());
+
+        let is_world_running = Cell::new(true);
+        let ctx = HookContext::new(SystemTime::UNIX_EPOCH, &is_world_running);
+        for payload in [
+            Payload::CancelOrderRequest(CancelOrderRequest {
+                symbol: "BTCUSDT",
+                client_order_id: Arc::from("B0"),
+                owner: None,
+            }),
+            order_result(0.0, OrderStatus::New),
+            order_result(0.25, OrderStatus::PartiallyFilled),
+            order_result(0.5, OrderStatus::Filled),
+        ] {
+            hooks.message(&ctx, &message(payload));
+        }
+        assert_eq!(fills.get(), 0.75);
+        assert_eq!(orders.get(), 0);
+        assert!(is_world_running.get());
+
+        hooks.message(
+            &ctx,
+            &message(Payload::OrderRequest(OrderRequest {
+                symbol: "BTCUSDT",
+                side: TradeSide::Buy,
+                price: 100.0,
+                quantity: 1.0,
+                trade_type: TradeType::Limit,
+                time_in_force: TimeInForce::GoodTilCancelled,
+                client_order_id: Arc::from("B1"),
+                cancel_order_id: None,
+                owner: None,
+            })),
+        );
+        assert_eq!(orders.get(), 1);
+        assert!(!is_world_running.get());
+    }
+}
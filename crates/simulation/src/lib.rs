@@ -1,2 +1,10 @@
+pub mod clock_audit;
 pub mod engine;
+pub mod fault_injection;
+pub mod hooks;
+pub mod namespace;
+mod playback;
+pub mod profile;
+mod progress;
 pub mod simulation;
+mod threaded_module;
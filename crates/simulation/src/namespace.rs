@@ -0,0 +1,306 @@
+use upstair_type::{
+    module::{
+        namespaced_topic, MessageFilter, Module, ModuleBuilder, ModuleComms, ModuleCommsBuilder,
+        ModuleId, ModulePriority, ReadTopicHandle, TopicId, WriteTopicHandle,
+    },
+    run_output::RunOutput,
+};
+
+// the topics every namespace shares
+const SHARED_TOPICS: &[&str] = &["market_data", "signals"];
+
+// Maps the topics of a module to the ones of its namespace, the shared ones are left alone
+struct NamespacedCommsBuilder<'a> {
+    inner: &'a mut dyn ModuleCommsBuilder,
+    namespace: &'a str,
+}
+
+impl ModuleCommsBuilder for NamespacedCommsBuilder<'_> {
+    fn get_module_id(&self) -> &ModuleId {
+        self.inner.get_module_id()
+    }
+
+    fn get_topic(&mut self, name: &str) -> TopicId {
+        if SHARED_TOPICS.contains(&name) {
+            self.inner.get_topic(name)
+        } else {
+            self.inner
+                .get_topic(&namespaced_topic(name, self.namespace))
+        }
+    }
+
+    fn subscribe_topic(&mut self, topic: &TopicId) -> ReadTopicHandle {
+        self.inner.subscribe_topic(topic)
+    }
+
+    fn subscribe_topic_filtered(
+        &mut self,
+        topic: &TopicId,
+        filter: MessageFilter,
+    ) -> ReadTopicHandle {
+        self.inner.subscribe_topic_filtered(topic, filter)
+    }
+
+    fn publish_topic(&mut self, topic: &TopicId) -> WriteTopicHandle {
+        self.inner.publish_topic(topic)
+    }
+
+    fn build(self) -> Box<dyn ModuleComms> {
+        unreachable!("the comms of a namespaced module are built by the engine")
+    }
+}
+
+// Runs a module on the topics of a namespace, sharing only the market data and the signals
+// derived from it with the modules outside it. A strategy and the market agent filling it in one namespace trade on their own
+// orders and account, next to other strategies fed the same market data in the same run.
+pub struct NamespacedBuilder {
+    inner: Box<dyn ModuleBuilder>,
+    namespace: String,
+    name: String,
+}
+
+impl NamespacedBuilder {
+    pub fn new(inner: Box<dyn ModuleBuilder>, namespace: &str) -> Self {
+        let name = format!("{}@{}", inner.name(), namespace);
+        NamespacedBuilder {
+            inner,
+            namespace: namespace.to_string(),
+            name,
+        }
+    }
+}
+
+impl ModuleBuilder for NamespacedBuilder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn priority(&self) -> ModulePriority {
+        self.inner.priority()
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+        self.inner.init_comm(&mut NamespacedCommsBuilder {
+            inner: comms,
+            namespace: &self.namespace,
+        })
+    }
+
+    // the files of the namespace go to a directory of its own
+    fn init_output(&mut self, output: &RunOutput) {
+        self.inner.init_output(&output.namespaced(&self.namespace))
+    }
+
+    fn build(self: Box<NamespacedBuilder>) -> Box<dyn Module> {
+        self.inner.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use indicators::order_flow::OrderFlowPublisherBuilder;
+    use upstair_type::{
+        data::market::BinanceBookTicker,
+        order::CancelOrderRequest,
+        signal::{SignalKind, SignalUpdate},
+        Message, MessageHeader, Payload,
+    };
+
+    use super::*;
+    use crate::engine::SimulationEngineBuilder;
+    use crate::simulation::SimulationCommsSystem;
+    use upstair_type::module::CommsSystem;
+
+    // publishes on its topics, order and market_data unless set, records what it receives from
+    // them by the index of the topic and publishes the messages of outbox a second into the run
+    #[derive(Default)]
+    struct EchoBuilder {
+        name: &'static str,
+        topic_names: Vec<&'static str>,
+        topics: Vec<(ReadTopicHandle, WriteTopicHandle)>,
+        outbox: Vec<(usize, Message)>,
+        received: Rc<RefCell<Vec<(usize, Message)>>>,
+    }
+
+    struct Echo {
+        topics: Vec<(ReadTopicHandle, WriteTopicHandle)>,
+        outbox: Vec<(usize, Message)>,
+        received: Rc<RefCell<Vec<(usize, Message)>>>,
+    }
+
+    impl EchoBuilder {
+        fn new(name: &'static str) -> Self {
+            EchoBuilder {
+                name,
+                topic_names: vec!["order", "market_data"],
+                ..Default::default()
+            }
+        }
+    }
+
+    impl ModuleBuilder for EchoBuilder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            for name in &self.topic_names {
+                let topic = comms.get_topic(name);
+                self.topics
+                    .push((comms.subscribe_topic(&topic), comms.publish_topic(&topic)));
+            }
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(Echo {
+                topics: self.topics,
+                outbox: self.outbox,
+                received: self.received,
+            })
+        }
+    }
+
+    impl Module for Echo {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+            for (i, (topic, _)) in self.topics.iter().enumerate() {
+                while let Some(msg) = comms.receive(topic) {
+                    self.received.borrow_mut().push((i, msg));
+                }
+            }
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            for (i, mut msg) in self.outbox.drain(..) {
+                msg.header.commit_at = comms.time();
+                comms.publish(&self.topics[i].1, msg);
+            }
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            (!self.outbox.is_empty()).then_some(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+        }
+
+        fn wake_on_message(&self) -> bool {
+            true
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: SystemTime::UNIX_EPOCH,
+            },
+            payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                symbol: "BTCUSDT",
+                client_order_id: Arc::from("B0"),
+                owner: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_namespaces_share_market_data_only() {
+        let system = SimulationCommsSystem::default();
+        let mut default = EchoBuilder::new("echo");
+        let mut default_comms = system.new_builder("echo");
+        default.init_comm(&mut default_comms);
+        let mut b = EchoBuilder::new("echo");
+        let mut b_comms = system.new_builder("echo@b");
+        b.init_comm(&mut NamespacedCommsBuilder {
+            inner: &mut b_comms,
+            namespace: "b",
+        });
+        // market_data, order and order@b
+        assert_eq!(system.num_topics(), 3);
+        let mut default_comms = default_comms.build();
+        let mut b_comms = b_comms.build();
+
+        // an order of the default namespace stays in it, the market data reaches both
+        default_comms.publish(&default.topics[0].1, message());
+        default_comms.publish(&default.topics[1].1, message());
+        assert!(default_comms.receive(&default.topics[0].0).is_some());
+        assert!(default_comms.receive(&default.topics[1].0).is_some());
+        assert!(b_comms.receive(&b.topics[0].0).is_none());
+        assert!(b_comms.receive(&b.topics[1].0).is_some());
+    }
+
+    #[test]
+    fn test_namespaced_module_in_engine() {
+        let publisher = EchoBuilder {
+            outbox: vec![(0, message()), (1, message())],
+            ..EchoBuilder::new("publisher")
+        };
+        let default = EchoBuilder::new("echo");
+        let default_received = default.received.clone();
+        let b = EchoBuilder::new("echo");
+        let b_received = b.received.clone();
+        let mut engine = SimulationEngineBuilder::default()
+            .add_module(publisher)
+            .add_module(default);
+        engine.add_module_dyn(Box::new(NamespacedBuilder::new(Box::new(b), "b")));
+        engine.build().run();
+
+        // the topics of the messages received
+        let topics = |received: &Rc<RefCell<Vec<(usize, Message)>>>| -> Vec<usize> {
+            received.borrow().iter().map(|(i, _)| *i).collect()
+        };
+        assert_eq!(topics(&default_received), vec![0, 1]);
+        assert_eq!(topics(&b_received), vec![1]);
+    }
+
+    #[test]
+    fn test_namespaced_module_receives_signals() {
+        let book = Message {
+            header: MessageHeader {
+                commit_at: SystemTime::UNIX_EPOCH,
+            },
+            payload: Payload::BinanceBookTicker(BinanceBookTicker {
+                best_bid_price: 100.0,
+                best_bid_qty: 3.0,
+                best_ask_price: 101.0,
+                best_ask_qty: 1.0,
+                symbol: "BTCUSDT",
+                ..Default::default()
+            }),
+        };
+        let publisher = EchoBuilder {
+            outbox: vec![(1, book)],
+            ..EchoBuilder::new("publisher")
+        };
+        let b = EchoBuilder {
+            topic_names: vec!["signals"],
+            ..EchoBuilder::new("echo")
+        };
+        let b_received = b.received.clone();
+        let mut engine = SimulationEngineBuilder::default()
+            .add_module(publisher)
+            .add_module(OrderFlowPublisherBuilder::new("BTCUSDT"));
+        engine.add_module_dyn(Box::new(NamespacedBuilder::new(Box::new(b), "b")));
+        engine.build().run();
+
+        // the microprice of the default namespace reaches the strategy of a variant
+        assert!(b_received.borrow().iter().any(|(_, msg)| matches!(
+            &msg.payload,
+            Payload::SignalUpdate(SignalUpdate {
+                kind: SignalKind::Microprice,
+                ..
+            })
+        )));
+    }
+
+    #[test]
+    fn test_namespaced_name() {
+        let builder = NamespacedBuilder::new(Box::new(EchoBuilder::new("echo")), "b");
+        assert_eq!(builder.name(), "echo@b");
+    }
+}
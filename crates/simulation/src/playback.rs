@@ -0,0 +1,68 @@
+use std::{
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use upstair_type::time::PlaybackControl;
+
+// Holds the simulation events back to the speed of a PlaybackControl
+pub(crate) struct Pacer {
+    control: PlaybackControl,
+    // (simulation time, wall time, speed) the pace is kept from, reset after a pause or a
+    // speed change
+    anchor: Option<(SystemTime, Instant, f64)>,
+}
+
+impl Pacer {
+    pub(crate) fn new(control: PlaybackControl) -> Self {
+        Pacer {
+            control,
+            anchor: None,
+        }
+    }
+
+    // blocks until the event at time is due, or while the playback is paused
+    pub(crate) fn wait(&mut self, time: SystemTime) {
+        let (speed, waited) = self.control.wait_turn(time);
+        let Some(speed) = speed else {
+            self.anchor = None;
+            return;
+        };
+        match self.anchor {
+            Some((anchor_time, anchor_wall, anchor_speed)) if !waited && anchor_speed == speed => {
+                let sim_elapsed = time.duration_since(anchor_time).unwrap_or_default();
+                let due = anchor_wall + sim_elapsed.div_f64(speed);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+            _ => self.anchor = Some((time, Instant::now(), speed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_pacer_keeps_speed() {
+        let control = PlaybackControl::with_speed(Some(10.0));
+        let mut pacer = Pacer::new(control.clone());
+        let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        let started_at = Instant::now();
+        for i in 0..=5 {
+            pacer.wait(at(i * 100));
+        }
+        // 500 ms of simulation at 10x
+        assert!(started_at.elapsed() >= Duration::from_millis(50));
+
+        control.set_speed(None);
+        let started_at = Instant::now();
+        pacer.wait(at(100_000));
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+    }
+}
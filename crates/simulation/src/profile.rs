@@ -0,0 +1,163 @@
+use std::{
+    cell::Cell,
+    fmt::{self, Display, Write as _},
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tracing::trace_span;
+use upstair_type::{
+    module::{Module, ModuleComms, ReadTopicHandle, WriteTopicHandle},
+    Message,
+};
+
+const PROFILE_FILE: &str = "profile.csv";
+
+// Where the time of a run goes, per module
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleProfile {
+    pub name: String,
+    pub iterations: u64,
+    // wall time spent in sync and one_iteration
+    pub busy: Duration,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    // messages published out of time, counted by a lenient clock audit
+    pub clock_violations: u64,
+}
+
+// Runs a module once in tracing spans, a run span of the module name and simulation time with
+// the sync and one_iteration spans in it, for the timeline of the engine in a trace viewer.
+// The spans are of the trace level, free unless a subscriber takes them.
+pub(crate) fn traced_run(
+    module: &mut dyn Module,
+    comms: &mut dyn ModuleComms,
+    name: &str,
+    time: SystemTime,
+) {
+    let sim_time_ms = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let _run = trace_span!("run", module = %name, sim_time_ms).entered();
+    if trace_span!("sync").in_scope(|| module.sync(comms)) {
+        trace_span!("one_iteration").in_scope(|| module.one_iteration(comms));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineProfile {
+    pub modules: Vec<ModuleProfile>,
+    // wall time of SimulationEngine::run
+    pub elapsed: Duration,
+    // module wake-ups requested, and those merged into an already pending one
+    pub wakeups: u64,
+    pub coalesced_wakeups: u64,
+}
+
+impl EngineProfile {
+    // written next to the results of a run
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        let mut s = String::from("module,iterations,busy_ms,messages_in,messages_out\n");
+        for m in &self.modules {
+            writeln!(
+                s,
+                "{},{},{:.3},{},{}",
+                m.name,
+                m.iterations,
+                m.busy.as_secs_f64() * 1000.0,
+                m.messages_in,
+                m.messages_out
+            )
+            .unwrap();
+        }
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(PROFILE_FILE), s)
+    }
+}
+
+impl Display for EngineProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>12} {:>12} {:>7} {:>12} {:>12} {:>12}",
+            "module", "iterations", "busy ms", "busy %", "us/iter", "msgs in", "msgs out"
+        )?;
+        let elapsed = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        for m in &self.modules {
+            let busy = m.busy.as_secs_f64();
+            writeln!(
+                f,
+                "{:<20} {:>12} {:>12.1} {:>7.1} {:>12.2} {:>12} {:>12}",
+                m.name,
+                m.iterations,
+                busy * 1000.0,
+                busy / elapsed * 100.0,
+                busy * 1e6 / m.iterations.max(1) as f64,
+                m.messages_in,
+                m.messages_out
+            )?;
+        }
+        write!(
+            f,
+            "Total: {:.1} ms, wake-ups: {} ({} coalesced)",
+            elapsed * 1000.0,
+            self.wakeups,
+            self.coalesced_wakeups
+        )
+    }
+}
+
+// shared between the comms counting and the engine reading
+pub(crate) type MessageCount = Rc<Cell<u64>>;
+
+// Counts the messages a module receives and publishes
+pub(crate) struct CountingModuleComms {
+    inner: Box<dyn ModuleComms>,
+    messages_in: MessageCount,
+    messages_out: MessageCount,
+}
+
+impl CountingModuleComms {
+    // returns the comms with the (messages in, messages out) counters
+    pub(crate) fn wrap(
+        inner: Box<dyn ModuleComms>,
+    ) -> (Box<dyn ModuleComms>, MessageCount, MessageCount) {
+        let (messages_in, messages_out) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let comms = CountingModuleComms {
+            inner,
+            messages_in: messages_in.clone(),
+            messages_out: messages_out.clone(),
+        };
+        (Box::new(comms), messages_in, messages_out)
+    }
+}
+
+impl ModuleComms for CountingModuleComms {
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        let message = self.inner.receive_shared(topic);
+        if message.is_some() {
+            self.messages_in.set(self.messages_in.get() + 1);
+        }
+        message
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        self.messages_out.set(self.messages_out.get() + 1);
+        self.inner.publish(topic, message)
+    }
+
+    fn request_terminate(&mut self) {
+        self.inner.request_terminate()
+    }
+
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        self.inner.next_delivery_at()
+    }
+}
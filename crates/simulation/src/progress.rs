@@ -0,0 +1,58 @@
+use std::{
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+// Shows how far a run is through the time range of its data, with the ETA and the simulation
+// time reached, redrawn once per second of simulation time
+pub(crate) struct ProgressReporter {
+    bar: ProgressBar,
+    start: SystemTime,
+    shown_secs: Option<u64>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(range: &Range<SystemTime>) -> Self {
+        let len = range
+            .end
+            .duration_since(range.start)
+            .unwrap_or_default()
+            .as_secs();
+        let bar = ProgressBar::new(len.max(1));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed}] (eta: {eta}) [{bar:40.cyan/blue}] {percent}% : {msg}")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+        ProgressReporter {
+            bar,
+            start: range.start,
+            shown_secs: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, time: SystemTime) {
+        let secs = time
+            .duration_since(self.start)
+            .unwrap_or_default()
+            .as_secs();
+        if self.shown_secs.is_some_and(|shown| shown >= secs) {
+            return;
+        }
+        self.shown_secs = Some(secs);
+        self.bar.set_position(secs);
+        let time: chrono::DateTime<chrono::Utc> = (self.start + Duration::from_secs(secs)).into();
+        self.bar.set_message(format!(
+            "simulation time {}",
+            time.format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+
+    // leaves the bar where the run ended, short of the end of the range when it stopped early
+    pub(crate) fn finish(&self) {
+        self.bar.abandon();
+    }
+}
@@ -1,24 +1,44 @@
-use std::{cell::Cell, rc::Rc, sync::Mutex, time::SystemTime};
+use std::{
+    cell::Cell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use crossbeam::channel;
 use upstair_type::{
     module::{
-        CommsSystem, ModuleComms, ModuleCommsBuilder, ModuleId, ReadTopicHandle, TopicId,
-        WriteTopicHandle,
+        CommsSystem, MessageFilter, ModuleComms, ModuleCommsBuilder, ModuleId, ReadTopicHandle,
+        TopicId, WriteTopicHandle,
     },
     time::{SimulationTime, TimeProvider},
     Message,
 };
 
+#[derive(Clone)]
+struct TopicSubscriber {
+    sender: crossbeam::channel::Sender<Arc<Message>>,
+    filter: Option<MessageFilter>,
+}
+
+impl std::fmt::Debug for TopicSubscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopicSubscriber")
+            .field("filtered", &self.filter.is_some())
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone)]
 struct SimulationTopicPublisher {
-    destination: Vec<crossbeam::channel::Sender<Message>>,
+    // every subscriber gets the same message, it is never copied on publish
+    destination: Vec<TopicSubscriber>,
     topic_updated_at: Rc<Cell<SystemTime>>,
 }
 
 pub struct SimulationModuleComms {
     time_priovider: SimulationTime,
-    topic_readers: Vec<crossbeam::channel::Receiver<Message>>,
+    topic_readers: Vec<crossbeam::channel::Receiver<Arc<Message>>>,
     topic_publisher: Vec<SimulationTopicPublisher>,
     is_world_running: Rc<Cell<bool>>,
 }
@@ -28,15 +48,23 @@ impl ModuleComms for SimulationModuleComms {
         self.time_priovider.time()
     }
 
-    fn receive(&mut self, topic: &ReadTopicHandle) -> Option<Message> {
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
         let reader = &mut self.topic_readers[topic.slot];
         reader.try_recv().ok()
     }
 
     fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
         let writer = &mut self.topic_publisher[topic.slot];
-        for writer in &writer.destination {
-            writer.send(message.clone()).unwrap();
+        let message = Arc::new(message);
+        for subscriber in &writer.destination {
+            if subscriber
+                .filter
+                .as_ref()
+                .is_some_and(|filter| !filter(&message))
+            {
+                continue;
+            }
+            subscriber.sender.send(message.clone()).unwrap();
         }
         writer.topic_updated_at.replace(message.header.commit_at);
     }
@@ -50,7 +78,7 @@ pub struct SimulationModuleCommsBuilder {
     module_id: ModuleId,
     system: Rc<Mutex<SimulationCommsSystemInner>>,
 
-    topic_readers: Vec<crossbeam::channel::Receiver<Message>>,
+    topic_readers: Vec<crossbeam::channel::Receiver<Arc<Message>>>,
 }
 
 impl ModuleCommsBuilder for SimulationModuleCommsBuilder {
@@ -59,12 +87,29 @@ impl ModuleCommsBuilder for SimulationModuleCommsBuilder {
     }
 
     fn subscribe_topic(&mut self, topic: &TopicId) -> ReadTopicHandle {
-        self.topic_readers.push(
-            self.system
-                .lock()
-                .unwrap()
-                .subscribe_topic(&self.module_id, topic),
-        );
+        self.topic_readers
+            .push(
+                self.system
+                    .lock()
+                    .unwrap()
+                    .subscribe_topic(&self.module_id, topic, None),
+            );
+        ReadTopicHandle {
+            slot: self.topic_readers.len() - 1,
+        }
+    }
+
+    fn subscribe_topic_filtered(
+        &mut self,
+        topic: &TopicId,
+        filter: MessageFilter,
+    ) -> ReadTopicHandle {
+        self.topic_readers
+            .push(self.system.lock().unwrap().subscribe_topic(
+                &self.module_id,
+                topic,
+                Some(filter),
+            ));
         ReadTopicHandle {
             slot: self.topic_readers.len() - 1,
         }
@@ -128,10 +173,16 @@ impl SimulationCommsSystem {
     pub fn get_topic_reader(
         &mut self,
         topic_id: &TopicId,
-    ) -> crossbeam::channel::Receiver<Message> {
+    ) -> crossbeam::channel::Receiver<Arc<Message>> {
         let mut inner = self.inner.lock().unwrap();
         let (tx, rx) = channel::unbounded();
-        inner.topics[topic_id.slot].publisher.destination.push(tx);
+        inner.topics[topic_id.slot]
+            .publisher
+            .destination
+            .push(TopicSubscriber {
+                sender: tx,
+                filter: None,
+            });
         rx
     }
 
@@ -255,7 +306,8 @@ impl SimulationCommsSystemInner {
         &mut self,
         module_id: &ModuleId,
         topic_id: &TopicId,
-    ) -> crossbeam::channel::Receiver<Message> {
+        filter: Option<MessageFilter>,
+    ) -> crossbeam::channel::Receiver<Arc<Message>> {
         let topic = &mut self.topics[topic_id.slot];
         topic.read_modules.push(module_id.clone());
 
@@ -263,7 +315,10 @@ impl SimulationCommsSystemInner {
         module.read_topics.push(topic_id.clone());
 
         let (tx, rx) = channel::unbounded();
-        topic.publisher.destination.push(tx);
+        topic
+            .publisher
+            .destination
+            .push(TopicSubscriber { sender: tx, filter });
 
         rx
     }
@@ -278,3 +333,120 @@ impl SimulationCommsSystemInner {
         module.write_topics.len() - 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use upstair_type::{
+        order::{CancelOrderRequest, ResyncRequest},
+        MessageHeader, Payload,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_publish_shares_message() {
+        let system = SimulationCommsSystem::default();
+        let mut publisher = system.new_builder("publisher");
+        let topic = publisher.get_topic("order");
+        let write_handle = publisher.publish_topic(&topic);
+        let mut subscribers: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let mut builder = system.new_builder(name);
+                let read_handle = builder.subscribe_topic(&topic);
+                (builder.build(), read_handle)
+            })
+            .collect();
+        let mut publisher = publisher.build();
+
+        publisher.publish(
+            &write_handle,
+            Message {
+                header: MessageHeader {
+                    commit_at: SystemTime::UNIX_EPOCH,
+                },
+                payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                    symbol: "BTCUSDT",
+                    client_order_id: Arc::from("B0"),
+                    owner: None,
+                }),
+            },
+        );
+        let (comms_a, handle_a) = &mut subscribers[0];
+        let a = comms_a.receive_shared(handle_a).unwrap();
+        assert!(comms_a.receive_shared(handle_a).is_none());
+        let (comms_b, handle_b) = &mut subscribers[1];
+        let b = comms_b.receive_shared(handle_b).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(Arc::strong_count(&a), 2);
+    }
+
+    #[test]
+    fn test_subscribe_topic_filtered() {
+        let system = SimulationCommsSystem::default();
+        let mut publisher = system.new_builder("publisher");
+        let topic = publisher.get_topic("order");
+        let write_handle = publisher.publish_topic(&topic);
+        let mut subscriber = system.new_builder("subscriber");
+        let read_handle = subscriber
+            .subscribe_topic_filtered(&topic, upstair_type::module::symbol_filter("ETHUSDT"));
+        let mut publisher = publisher.build();
+        let mut subscriber = subscriber.build();
+
+        for symbol in ["BTCUSDT", "ETHUSDT", "BTCUSDT"] {
+            publisher.publish(
+                &write_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: SystemTime::UNIX_EPOCH,
+                    },
+                    payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                        symbol,
+                        client_order_id: Arc::from("B0"),
+                        owner: None,
+                    }),
+                },
+            );
+        }
+        let message = subscriber.receive(&read_handle).unwrap();
+        assert_eq!(message.payload.symbol(), Some("ETHUSDT"));
+        assert!(subscriber.receive(&read_handle).is_none());
+    }
+    #[test]
+    fn test_subscribe_topic_owner_filtered() {
+        use upstair_type::module::{and_filter, owner_filter, symbol_filter};
+
+        let system = SimulationCommsSystem::default();
+        let mut publisher = system.new_builder("publisher");
+        let topic = publisher.get_topic("order");
+        let write_handle = publisher.publish_topic(&topic);
+        let mut subscriber = system.new_builder("subscriber");
+        let read_handle = subscriber.subscribe_topic_filtered(
+            &topic,
+            and_filter(symbol_filter("BTCUSDT"), owner_filter(Some("a"))),
+        );
+        let mut publisher = publisher.build();
+        let mut subscriber = subscriber.build();
+
+        for (symbol, owner) in [
+            ("BTCUSDT", None),
+            ("BTCUSDT", Some("b")),
+            ("ETHUSDT", Some("a")),
+            ("BTCUSDT", Some("a")),
+        ] {
+            publisher.publish(
+                &write_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: SystemTime::UNIX_EPOCH,
+                    },
+                    payload: Payload::ResyncRequest(ResyncRequest { symbol, owner }),
+                },
+            );
+        }
+        let message = subscriber.receive(&read_handle).unwrap();
+        assert_eq!(message.payload.symbol(), Some("BTCUSDT"));
+        assert_eq!(message.payload.owner(), Some("a"));
+        assert!(subscriber.receive(&read_handle).is_none());
+    }
+}
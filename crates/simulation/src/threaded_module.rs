@@ -0,0 +1,202 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use upstair_type::{
+    module::{ModuleBuilder, ModuleComms, ReadTopicHandle, WriteTopicHandle},
+    Message,
+};
+
+use crate::profile::traced_run;
+
+enum ThreadedModuleCommand {
+    Start,
+    Run {
+        time: SystemTime,
+        inbox: Vec<VecDeque<Arc<Message>>>,
+    },
+    Shutdown {
+        time: SystemTime,
+    },
+    Terminate,
+}
+
+pub(crate) struct ThreadedModuleReply {
+    pub(crate) outbox: Vec<(WriteTopicHandle, Message)>,
+    pub(crate) terminate_requested: bool,
+    // wall time of the command on the module thread
+    pub(crate) busy: Duration,
+    next_iteration_start_at: Option<SystemTime>,
+    wake_on_message: bool,
+    failure: Option<String>,
+}
+
+// ModuleComms used on the module thread.
+// Inputs are handed over when an iteration is dispatched, outputs are published by the engine
+// once the iteration is joined.
+struct ThreadedModuleComms {
+    time: SystemTime,
+    inbox: Vec<VecDeque<Arc<Message>>>,
+    outbox: Vec<(WriteTopicHandle, Message)>,
+    terminate_requested: bool,
+}
+
+impl ModuleComms for ThreadedModuleComms {
+    fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        self.inbox.get_mut(topic.slot)?.pop_front()
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        self.outbox.push((topic.clone(), message));
+    }
+
+    fn request_terminate(&mut self) {
+        self.terminate_requested = true;
+    }
+}
+
+// Engine side handle of a module running on its own thread
+pub(crate) struct ThreadedModule {
+    name: String,
+    command_tx: Sender<ThreadedModuleCommand>,
+    reply_rx: Receiver<ThreadedModuleReply>,
+    join_handle: Option<JoinHandle<()>>,
+    pending: bool,
+    next_iteration_start_at: Option<SystemTime>,
+    wake_on_message: bool,
+    failure: Option<String>,
+}
+
+impl ThreadedModule {
+    pub(crate) fn spawn(name: &str, builder: Box<dyn ModuleBuilder + Send>) -> Self {
+        let (command_tx, command_rx) = channel::unbounded::<ThreadedModuleCommand>();
+        let (reply_tx, reply_rx) = channel::unbounded();
+        let module_name = name.to_string();
+        let join_handle = thread::Builder::new()
+            .name(format!("module({})", name))
+            .spawn(move || {
+                let mut module = builder.build();
+                let mut comms = ThreadedModuleComms {
+                    time: UNIX_EPOCH,
+                    inbox: vec![],
+                    outbox: vec![],
+                    terminate_requested: false,
+                };
+                while let Ok(command) = command_rx.recv() {
+                    let started_at = Instant::now();
+                    match command {
+                        ThreadedModuleCommand::Start => module.start(),
+                        ThreadedModuleCommand::Run { time, inbox } => {
+                            comms.time = time;
+                            comms.inbox = inbox;
+                            traced_run(module.as_mut(), &mut comms, &module_name, time);
+                        }
+                        ThreadedModuleCommand::Shutdown { time } => {
+                            comms.time = time;
+                            module.on_shutdown(&mut comms);
+                        }
+                        ThreadedModuleCommand::Terminate => {
+                            module.terminate();
+                            return;
+                        }
+                    }
+                    let reply = ThreadedModuleReply {
+                        outbox: std::mem::take(&mut comms.outbox),
+                        terminate_requested: std::mem::take(&mut comms.terminate_requested),
+                        busy: started_at.elapsed(),
+                        next_iteration_start_at: module.next_iteration_start_at(),
+                        wake_on_message: module.wake_on_message(),
+                        failure: module.failure(),
+                    };
+                    if reply_tx.send(reply).is_err() {
+                        return;
+                    }
+                }
+            })
+            .expect("failed to spawn module thread");
+        ThreadedModule {
+            name: name.into(),
+            command_tx,
+            reply_rx,
+            join_handle: Some(join_handle),
+            pending: false,
+            next_iteration_start_at: None,
+            wake_on_message: false,
+            failure: None,
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        self.send(ThreadedModuleCommand::Start);
+        self.join();
+    }
+
+    // run one iteration in background, the result is collected by join
+    pub(crate) fn dispatch(&mut self, time: SystemTime, inbox: Vec<VecDeque<Arc<Message>>>) {
+        assert!(!self.pending, "module({}) is already running", self.name);
+        self.send(ThreadedModuleCommand::Run { time, inbox });
+    }
+
+    pub(crate) fn shutdown(&mut self, time: SystemTime) -> ThreadedModuleReply {
+        assert!(!self.pending, "module({}) is already running", self.name);
+        self.send(ThreadedModuleCommand::Shutdown { time });
+        self.join()
+    }
+
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    pub(crate) fn join(&mut self) -> ThreadedModuleReply {
+        let reply = self
+            .reply_rx
+            .recv()
+            .unwrap_or_else(|_| panic!("module({}) thread exited unexpectedly", self.name));
+        self.pending = false;
+        self.next_iteration_start_at = reply.next_iteration_start_at;
+        self.wake_on_message = reply.wake_on_message;
+        self.failure = reply.failure.clone();
+        reply
+    }
+
+    pub(crate) fn terminate(&mut self) {
+        if self.pending {
+            self.join();
+        }
+        self.send(ThreadedModuleCommand::Terminate);
+        self.pending = false;
+        if let Some(h) = self.join_handle.take() {
+            if h.join().is_err() {
+                panic!("module({}) thread panicked", self.name);
+            }
+        }
+    }
+
+    pub(crate) fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        self.next_iteration_start_at
+    }
+
+    pub(crate) fn wake_on_message(&self) -> bool {
+        self.wake_on_message
+    }
+
+    // as of the last joined command, the module itself is gone once terminated
+    pub(crate) fn failure(&self) -> Option<String> {
+        self.failure.clone()
+    }
+
+    fn send(&mut self, command: ThreadedModuleCommand) {
+        self.command_tx
+            .send(command)
+            .unwrap_or_else(|_| panic!("module({}) thread exited unexpectedly", self.name));
+        self.pending = true;
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Registers touch, which quotes one order on each side at the touch and requotes when the
+ * book moves, the config is the quantity of the orders, e.g. 0.01.
+ *
+ *   cc -shared -fPIC -O2 -o plugins/libexample_strategy.so example_strategy.c
+ *   sim -d 2023-12-01 --strategy touch --strategy-plugin-config 0.01
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "maker_strategy.h"
+
+typedef struct {
+    double quantity;
+    uint64_t next_id;
+} Strategy;
+
+static uint32_t abi_version(void) { return MAKER_STRATEGY_ABI_VERSION; }
+
+static void *touch_new(const char *config) {
+    double quantity = atof(config);
+    if (quantity <= 0.0) {
+        return NULL;
+    }
+    Strategy *strategy = calloc(1, sizeof(Strategy));
+    strategy->quantity = quantity;
+    return strategy;
+}
+
+static int is_live(const MakerOrder *order) {
+    return order->status == MAKER_STATUS_OPEN_REQUESTED || order->status == MAKER_STATUS_OPEN ||
+           order->status == MAKER_STATUS_PARTIALLY_FILLED;
+}
+
+static size_t touch_decide(void *handle, const MakerWorld *world, MakerAction *actions,
+                           size_t capacity) {
+    Strategy *strategy = handle;
+    size_t count = 0;
+    if (world->best_bid_price <= 0.0 || world->best_ask_price <= 0.0 || world->data_degraded) {
+        return 0;
+    }
+    int quoted[2] = {0, 0};
+    for (size_t i = 0; i < world->orders_len && count < capacity; i++) {
+        const MakerOrder *order = &world->orders[i];
+        if (!is_live(order)) {
+            continue;
+        }
+        double touch = order->side == MAKER_SIDE_BUY ? world->best_bid_price : world->best_ask_price;
+        if (order->price == touch && !quoted[order->side]) {
+            quoted[order->side] = 1;
+            continue;
+        }
+        MakerAction *cancel = &actions[count++];
+        cancel->kind = MAKER_ACTION_CANCEL;
+        snprintf(cancel->order_id, sizeof(cancel->order_id), "%s", order->order_id);
+    }
+    for (uint8_t side = MAKER_SIDE_BUY; side <= MAKER_SIDE_SELL && count < capacity; side++) {
+        if (quoted[side]) {
+            continue;
+        }
+        MakerAction *place = &actions[count++];
+        place->kind = MAKER_ACTION_PLACE;
+        place->side = side;
+        place->order_type = MAKER_ORDER_TYPE_LIMIT_MAKER;
+        snprintf(place->order_id, sizeof(place->order_id), "C%llu",
+                 (unsigned long long)strategy->next_id++);
+        place->price = side == MAKER_SIDE_BUY ? world->best_bid_price : world->best_ask_price;
+        place->quantity = strategy->quantity;
+    }
+    return count;
+}
+
+static void touch_free(void *strategy) { free(strategy); }
+
+static const MakerStrategyEntry STRATEGIES[] = {
+    {"touch", abi_version, touch_new, touch_decide, touch_free},
+};
+
+size_t maker_strategy_register(const MakerStrategyEntry **entries) {
+    *entries = STRATEGIES;
+    return sizeof(STRATEGIES) / sizeof(STRATEGIES[0]);
+}
@@ -0,0 +1,137 @@
+/*
+ * The C ABI of the strategy plugins of the stepper, see src/plugin.rs.
+ *
+ * A plugin is a shared library exporting maker_strategy_register, which registers its
+ * strategies by name for --strategy, or else the four maker_strategy_* functions below of one
+ * strategy named after the library, libgrid.so is grid. The sim discovers the plugins in
+ * --plugin-dir. The stepper creates one strategy per market from the config string of
+ * --strategy-plugin-config, calls decide on every decision with a snapshot of its world,
+ * sends the actions written back and frees the strategy at the end of the run. The calls
+ * come from one thread at a time.
+ *
+ * The stepper keeps the orders: a placed order shows up in the orders of the next
+ * snapshot, an order id must not be reused. Ids are NUL terminated, at most
+ * MAKER_STRATEGY_ORDER_ID_LEN - 1 bytes. Strategies sharing a market see their orders with
+ * @<owner> appended and cancel them by those ids.
+ */
+#ifndef MAKER_STRATEGY_H
+#define MAKER_STRATEGY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define MAKER_STRATEGY_ABI_VERSION 2
+#define MAKER_STRATEGY_ORDER_ID_LEN 64
+#define MAKER_STRATEGY_SYMBOL_LEN 32
+
+enum { MAKER_SIDE_BUY = 0, MAKER_SIDE_SELL = 1 };
+
+enum {
+    MAKER_STATUS_OPEN_REQUESTED = 0,
+    MAKER_STATUS_OPEN = 1,
+    MAKER_STATUS_PARTIALLY_FILLED = 2,
+    MAKER_STATUS_FILLED = 3,
+    MAKER_STATUS_CANCEL_REQUESTED = 4,
+    MAKER_STATUS_CANCELED = 5,
+    MAKER_STATUS_ERRORED = 6,
+};
+
+enum { MAKER_ACTION_PLACE = 0, MAKER_ACTION_CANCEL = 1 };
+
+enum {
+    MAKER_ORDER_TYPE_LIMIT = 0,
+    MAKER_ORDER_TYPE_LIMIT_MAKER = 1,
+    MAKER_ORDER_TYPE_MARKET = 2,
+};
+
+typedef struct {
+    char order_id[MAKER_STRATEGY_ORDER_ID_LEN];
+    uint8_t side;
+    uint8_t status;
+    double price;
+    double quantity;
+    double filled;
+} MakerOrder;
+
+typedef struct {
+    uint64_t time_ms;
+    double price;
+    double quantity;
+    uint8_t is_buyer_maker;
+} MakerTrade;
+
+typedef struct {
+    char order_id[MAKER_STRATEGY_ORDER_ID_LEN];
+    double filled;
+} MakerFill;
+
+/* The top of the book and the last trade of a symbol */
+typedef struct {
+    char symbol[MAKER_STRATEGY_SYMBOL_LEN];
+    double best_bid_price;
+    double best_bid_qty;
+    double best_ask_price;
+    double best_ask_qty;
+    double last_price;
+} MakerMarket;
+
+/* The world at a decision, the arrays are valid for the call only */
+typedef struct {
+    uint64_t now_ms;
+    double latest_market_price;
+    double best_bid_price;
+    double best_bid_qty;
+    double best_ask_price;
+    double best_ask_qty;
+    double base_balance;
+    double base_locked;
+    double quote_balance;
+    double quote_locked;
+    /* set while the validation of the market data flags it */
+    uint8_t data_degraded;
+    const MakerOrder *orders;
+    size_t orders_len;
+    /* the trades and fills since the previous decision */
+    const MakerTrade *trades;
+    size_t trades_len;
+    const MakerFill *fills;
+    size_t fills_len;
+    /* the books of the symbol and of the ones the stepper references */
+    const MakerMarket *markets;
+    size_t markets_len;
+} MakerWorld;
+
+/* An order to place, or the order_id to cancel */
+typedef struct {
+    uint8_t kind;
+    uint8_t side;
+    uint8_t order_type;
+    char order_id[MAKER_STRATEGY_ORDER_ID_LEN];
+    double price;
+    double quantity;
+} MakerAction;
+
+/* MAKER_STRATEGY_ABI_VERSION of the header the plugin was built with */
+uint32_t maker_strategy_abi_version(void);
+/* the strategy of the config, NULL when it is invalid */
+void *maker_strategy_new(const char *config);
+/* writes up to capacity actions and returns their count */
+size_t maker_strategy_decide(void *strategy, const MakerWorld *world, MakerAction *actions,
+                             size_t capacity);
+void maker_strategy_free(void *strategy);
+
+/* A strategy of a library registering several */
+typedef struct {
+    const char *name;
+    uint32_t (*abi_version)(void);
+    void *(*new_strategy)(const char *config);
+    size_t (*decide)(void *strategy, const MakerWorld *world, MakerAction *actions,
+                     size_t capacity);
+    void (*free_strategy)(void *strategy);
+} MakerStrategyEntry;
+
+/* points entries at the strategies of the library, kept while it is loaded, and returns
+ * their count */
+size_t maker_strategy_register(const MakerStrategyEntry **entries);
+
+#endif
@@ -0,0 +1,70 @@
+"""Serves touch over the StrategyBridge service, it quotes one order on each side at the touch
+and requotes when the book moves, like the touch of plugin/example_strategy.c.
+
+    pip install grpcio grpcio-tools
+    python -m grpc_tools.protoc -I. --python_out=. --grpc_python_out=. strategy_bridge.proto
+    python example_bridge.py --port 50051 --quantity 0.01
+    sim -d 2023-12-01 --grpc-strategy http://127.0.0.1:50051
+"""
+import argparse
+from concurrent import futures
+from itertools import count
+
+import grpc
+
+import strategy_bridge_pb2 as pb
+import strategy_bridge_pb2_grpc as pb_grpc
+
+LIVE = (pb.STATUS_OPEN_REQUESTED, pb.STATUS_OPEN, pb.STATUS_PARTIALLY_FILLED)
+
+
+class Touch(pb_grpc.StrategyBridgeServicer):
+    def __init__(self, quantity):
+        self.quantity = quantity
+        self.ids = count()
+
+    def Decide(self, request, context):
+        world = request.world
+        if world.best_bid_price <= 0.0 or world.best_ask_price <= 0.0 or world.data_degraded:
+            return pb.DecideResponse()
+        touch = {pb.SIDE_BUY: world.best_bid_price, pb.SIDE_SELL: world.best_ask_price}
+        quoted = set()
+        actions = []
+        for order in world.orders:
+            if order.status not in LIVE:
+                continue
+            if order.price == touch[order.side] and order.side not in quoted:
+                quoted.add(order.side)
+                continue
+            actions.append(pb.Action(kind=pb.ACTION_CANCEL, order_id=order.order_id))
+        for side in (pb.SIDE_BUY, pb.SIDE_SELL):
+            if side in quoted:
+                continue
+            actions.append(
+                pb.Action(
+                    kind=pb.ACTION_PLACE,
+                    side=side,
+                    order_type=pb.ORDER_TYPE_LIMIT_MAKER,
+                    order_id=f"G{next(self.ids)}",
+                    price=touch[side],
+                    quantity=self.quantity,
+                )
+            )
+        return pb.DecideResponse(actions=actions)
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--port", type=int, default=50051)
+    parser.add_argument("--quantity", type=float, default=0.01)
+    args = parser.parse_args()
+
+    server = grpc.server(futures.ThreadPoolExecutor(max_workers=1))
+    pb_grpc.add_StrategyBridgeServicer_to_server(Touch(args.quantity), server)
+    server.add_insecure_port(f"127.0.0.1:{args.port}")
+    server.start()
+    server.wait_for_termination()
+
+
+if __name__ == "__main__":
+    main()
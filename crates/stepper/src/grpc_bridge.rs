@@ -0,0 +1,640 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context};
+use pure_market_maker::{Action, CancelOrder, PlaceOrderData};
+use stepper_world::{order_tracker::OrderStatus, StepperWorld};
+use tonic::{
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+};
+use tracing::{error, warn};
+use upstair_type::order::{TimeInForce, TradeSide, TradeType};
+
+// Strategies running in another process, e.g. in Python or C++, served with the StrategyBridge
+// service of proto/strategy_bridge.proto. Like a plugin the bridge decides in place of the
+// market maker of the stepper: on every decision it sends a snapshot of the world and takes
+// back the orders to place and cancel. They reach the stepper once the round trip passed in
+// simulated time, the wall time of the call or a fixed latency, and no snapshot is sent while
+// a decision is in flight, as a strategy busy deciding would not see it.
+
+const DECIDE_PATH: &str = "/maker_simulator.bridge.v1.StrategyBridge/Decide";
+// how long a decision may take in wall time
+const DECIDE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The messages of proto/strategy_bridge.proto
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Side {
+    Buy = 0,
+    Sell = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum BridgeOrderStatus {
+    OpenRequested = 0,
+    Open = 1,
+    PartiallyFilled = 2,
+    Filled = 3,
+    CancelRequested = 4,
+    Canceled = 5,
+    Errored = 6,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ActionKind {
+    Place = 0,
+    Cancel = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum OrderType {
+    Limit = 0,
+    LimitMaker = 1,
+    Market = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeOrder {
+    #[prost(string, tag = "1")]
+    pub order_id: String,
+    #[prost(enumeration = "Side", tag = "2")]
+    pub side: i32,
+    #[prost(enumeration = "BridgeOrderStatus", tag = "3")]
+    pub status: i32,
+    #[prost(double, tag = "4")]
+    pub price: f64,
+    #[prost(double, tag = "5")]
+    pub quantity: f64,
+    #[prost(double, tag = "6")]
+    pub filled: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeTrade {
+    #[prost(uint64, tag = "1")]
+    pub time_ms: u64,
+    #[prost(double, tag = "2")]
+    pub price: f64,
+    #[prost(double, tag = "3")]
+    pub quantity: f64,
+    #[prost(bool, tag = "4")]
+    pub is_buyer_maker: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeFill {
+    #[prost(string, tag = "1")]
+    pub order_id: String,
+    #[prost(double, tag = "2")]
+    pub filled: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeMarket {
+    #[prost(string, tag = "1")]
+    pub symbol: String,
+    #[prost(double, tag = "2")]
+    pub best_bid_price: f64,
+    #[prost(double, tag = "3")]
+    pub best_bid_qty: f64,
+    #[prost(double, tag = "4")]
+    pub best_ask_price: f64,
+    #[prost(double, tag = "5")]
+    pub best_ask_qty: f64,
+    #[prost(double, tag = "6")]
+    pub last_price: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeWorld {
+    #[prost(uint64, tag = "1")]
+    pub now_ms: u64,
+    #[prost(double, tag = "2")]
+    pub latest_market_price: f64,
+    #[prost(double, tag = "3")]
+    pub best_bid_price: f64,
+    #[prost(double, tag = "4")]
+    pub best_bid_qty: f64,
+    #[prost(double, tag = "5")]
+    pub best_ask_price: f64,
+    #[prost(double, tag = "6")]
+    pub best_ask_qty: f64,
+    #[prost(double, tag = "7")]
+    pub base_balance: f64,
+    #[prost(double, tag = "8")]
+    pub base_locked: f64,
+    #[prost(double, tag = "9")]
+    pub quote_balance: f64,
+    #[prost(double, tag = "10")]
+    pub quote_locked: f64,
+    #[prost(bool, tag = "11")]
+    pub data_degraded: bool,
+    #[prost(message, repeated, tag = "12")]
+    pub orders: Vec<BridgeOrder>,
+    #[prost(message, repeated, tag = "13")]
+    pub trades: Vec<BridgeTrade>,
+    #[prost(message, repeated, tag = "14")]
+    pub fills: Vec<BridgeFill>,
+    #[prost(message, repeated, tag = "15")]
+    pub markets: Vec<BridgeMarket>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecideRequest {
+    #[prost(string, tag = "1")]
+    pub symbol: String,
+    #[prost(message, optional, tag = "2")]
+    pub world: Option<BridgeWorld>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BridgeAction {
+    #[prost(enumeration = "ActionKind", tag = "1")]
+    pub kind: i32,
+    #[prost(enumeration = "Side", tag = "2")]
+    pub side: i32,
+    #[prost(enumeration = "OrderType", tag = "3")]
+    pub order_type: i32,
+    #[prost(string, tag = "4")]
+    pub order_id: String,
+    #[prost(double, tag = "5")]
+    pub price: f64,
+    #[prost(double, tag = "6")]
+    pub quantity: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecideResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub actions: Vec<BridgeAction>,
+}
+
+fn side_to_bridge(side: &TradeSide) -> Side {
+    match side {
+        TradeSide::Buy => Side::Buy,
+        TradeSide::Sell => Side::Sell,
+    }
+}
+
+fn status_to_bridge(status: &OrderStatus) -> BridgeOrderStatus {
+    match status {
+        OrderStatus::OpenRequested => BridgeOrderStatus::OpenRequested,
+        OrderStatus::Open => BridgeOrderStatus::Open,
+        OrderStatus::PartiallyFilled => BridgeOrderStatus::PartiallyFilled,
+        OrderStatus::Filled => BridgeOrderStatus::Filled,
+        OrderStatus::CancelRequested => BridgeOrderStatus::CancelRequested,
+        OrderStatus::Canceled => BridgeOrderStatus::Canceled,
+        OrderStatus::Errored => BridgeOrderStatus::Errored,
+    }
+}
+
+// the snapshot of the world of the strategy trading symbol
+pub fn to_bridge_world(
+    world: &StepperWorld,
+    symbol: &str,
+    base_asset: &str,
+    quote_asset: &str,
+) -> BridgeWorld {
+    let balance = |asset: &str| {
+        world
+            .account
+            .asset_to_balance
+            .get(asset)
+            .map_or((0.0, 0.0), |b| (b.balance, b.locked))
+    };
+    let (base_balance, base_locked) = balance(base_asset);
+    let (quote_balance, quote_locked) = balance(quote_asset);
+    let book = world.book(symbol);
+    BridgeWorld {
+        now_ms: world
+            .now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        latest_market_price: world.latest_market_price,
+        best_bid_price: book.best_bid_price,
+        best_bid_qty: book.best_bid_qty,
+        best_ask_price: book.best_ask_price,
+        best_ask_qty: book.best_ask_qty,
+        base_balance,
+        base_locked,
+        quote_balance,
+        quote_locked,
+        data_degraded: world.data_quality.is_degraded(),
+        orders: world
+            .order_tracker
+            .iter()
+            .map(|order| BridgeOrder {
+                order_id: order.order_id.clone(),
+                side: side_to_bridge(&order.side) as i32,
+                status: status_to_bridge(&order.status) as i32,
+                price: order.price,
+                quantity: order.quantity,
+                filled: order.filled,
+            })
+            .collect(),
+        trades: world
+            .trade_buf
+            .iter()
+            .map(|trade| BridgeTrade {
+                time_ms: trade.time,
+                price: trade.price,
+                quantity: trade.qty,
+                is_buyer_maker: trade.is_buyer_maker,
+            })
+            .collect(),
+        fills: world
+            .filled_event_buf
+            .iter()
+            .map(|(order_id, filled)| BridgeFill {
+                order_id: order_id.to_string(),
+                filled: *filled,
+            })
+            .collect(),
+        markets: world
+            .markets
+            .iter()
+            .map(|(symbol, market)| BridgeMarket {
+                symbol: symbol.to_string(),
+                best_bid_price: market.best_bid_price,
+                best_bid_qty: market.best_bid_qty,
+                best_ask_price: market.best_ask_price,
+                best_ask_qty: market.best_ask_qty,
+                last_price: market.last_price,
+            })
+            .collect(),
+    }
+}
+
+// the action of the strategy for symbol, None when it is invalid
+fn to_action(action: BridgeAction, symbol: &'static str) -> Option<Action> {
+    if action.order_id.is_empty() {
+        return None;
+    }
+    match ActionKind::try_from(action.kind).ok()? {
+        ActionKind::Cancel => Some(Action::CancelOrder(CancelOrder {
+            symbol,
+            order_id: action.order_id,
+        })),
+        ActionKind::Place => {
+            let side = match Side::try_from(action.side).ok()? {
+                Side::Buy => TradeSide::Buy,
+                Side::Sell => TradeSide::Sell,
+            };
+            let trade_type = match OrderType::try_from(action.order_type).ok()? {
+                OrderType::Limit => TradeType::Limit,
+                OrderType::LimitMaker => TradeType::LimitMaker,
+                OrderType::Market => TradeType::Market,
+            };
+            if !action.price.is_finite() || !action.quantity.is_finite() || action.quantity <= 0.0 {
+                return None;
+            }
+            Some(Action::PlaceOrder(PlaceOrderData {
+                symbol,
+                order_id: action.order_id,
+                price: action.price,
+                side,
+                quantity: action.quantity,
+                trade_type,
+                time_in_force: TimeInForce::GoodTilCancelled,
+            }))
+        }
+    }
+}
+
+pub struct GrpcBridge {
+    // the calls block the stepper, the simulated time stands still while they run
+    runtime: tokio::runtime::Runtime,
+    client: tonic::client::Grpc<Channel>,
+    // a call not answered within this fails like a call the strategy refused, so a hung
+    // strategy does not stall the run
+    timeout: Duration,
+    // the latency of every decision, the wall time of its call when None
+    latency: Option<Duration>,
+    // the actions of the decision in flight and when they reach the stepper
+    in_flight: Option<(SystemTime, Vec<Action>)>,
+}
+
+impl GrpcBridge {
+    // the bridge to the strategy served at endpoint, e.g. http://127.0.0.1:50051, its actions
+    // applied after latency, or the wall time of the call when None
+    pub fn connect(endpoint: &str, latency: Option<Duration>) -> Result<GrpcBridge, anyhow::Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = Endpoint::from_shared(endpoint.to_string())
+            .with_context(|| format!("invalid strategy bridge endpoint {}", endpoint))?;
+        let channel = runtime
+            .block_on(endpoint.connect())
+            .with_context(|| format!("failed to connect to {}", endpoint.uri()))?;
+        Ok(GrpcBridge {
+            runtime,
+            client: tonic::client::Grpc::new(channel),
+            timeout: DECIDE_TIMEOUT,
+            latency,
+            in_flight: None,
+        })
+    }
+
+    // when the actions of the decision in flight reach the stepper
+    pub fn due_at(&self) -> Option<SystemTime> {
+        self.in_flight.as_ref().map(|(due_at, _)| *due_at)
+    }
+
+    // sends the world to the strategy unless a decision is in flight
+    pub fn send(
+        &mut self,
+        world: &StepperWorld,
+        symbol: &'static str,
+        base_asset: &str,
+        quote_asset: &str,
+    ) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        let request = DecideRequest {
+            symbol: symbol.to_string(),
+            world: Some(to_bridge_world(world, symbol, base_asset, quote_asset)),
+        };
+        let started_at = Instant::now();
+        let client = &mut self.client;
+        let timeout = self.timeout;
+        let response = self.runtime.block_on(async {
+            let call = async {
+                client.ready().await.context("strategy bridge not ready")?;
+                let mut request = tonic::Request::new(request);
+                request.set_timeout(timeout);
+                let response = client
+                    .unary(
+                        request,
+                        PathAndQuery::from_static(DECIDE_PATH),
+                        ProstCodec::<DecideRequest, DecideResponse>::default(),
+                    )
+                    .await?;
+                Ok(response)
+            };
+            match tokio::time::timeout(timeout, call).await {
+                Ok(response) => response,
+                Err(_) => Err(anyhow!("no decision within {:?}", timeout)),
+            }
+        });
+        let latency = self.latency.unwrap_or_else(|| started_at.elapsed());
+        let response = match response {
+            Ok(response) => response.into_inner(),
+            Err(e) => {
+                error!("strategy bridge failed to decide: {:#}", e);
+                return;
+            }
+        };
+        let actions = response
+            .actions
+            .into_iter()
+            .filter_map(|action| {
+                let (kind, order_id) = (action.kind, action.order_id.clone());
+                let converted = to_action(action, symbol);
+                if converted.is_none() {
+                    warn!(
+                        "invalid bridge action of kind {} for order {:?}",
+                        kind, order_id
+                    );
+                }
+                converted
+            })
+            .collect();
+        self.in_flight = Some((world.now + latency, actions));
+    }
+
+    // the actions of the decision in flight once they reached the stepper by now
+    pub fn receive(&mut self, now: SystemTime) -> Vec<Action> {
+        match self.in_flight.take() {
+            Some((due_at, actions)) if due_at <= now => actions,
+            in_flight => {
+                self.in_flight = in_flight;
+                vec![]
+            }
+        }
+    }
+
+    // drops the decision in flight, e.g. once trading halted
+    pub fn discard(&mut self) {
+        self.in_flight = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use stepper_world::{order_tracker::Order, MarketSnapshot};
+    use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+
+    use super::*;
+
+    // cancels the orders of the world and quotes a buy a tick under the bid
+    fn decide(request: DecideRequest) -> DecideResponse {
+        let world = request.world.unwrap_or_default();
+        let mut actions: Vec<BridgeAction> = world
+            .orders
+            .iter()
+            .map(|order| BridgeAction {
+                kind: ActionKind::Cancel as i32,
+                order_id: order.order_id.clone(),
+                ..Default::default()
+            })
+            .collect();
+        actions.push(BridgeAction {
+            kind: ActionKind::Place as i32,
+            side: Side::Buy as i32,
+            order_type: OrderType::LimitMaker as i32,
+            order_id: format!("P{}", world.now_ms),
+            price: world.best_bid_price - 0.1,
+            quantity: 0.01,
+        });
+        // an unknown kind is dropped
+        actions.push(BridgeAction {
+            kind: 9,
+            order_id: "X".to_string(),
+            ..Default::default()
+        });
+        DecideResponse { actions }
+    }
+
+    // the StrategyBridge service of decide
+    #[derive(Clone)]
+    struct TestServer;
+
+    struct DecideService;
+
+    impl tonic::server::UnaryService<DecideRequest> for DecideService {
+        type Response = DecideResponse;
+        type Future = BoxFuture<tonic::Response<DecideResponse>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<DecideRequest>) -> Self::Future {
+            Box::pin(async move { Ok(tonic::Response::new(decide(request.into_inner()))) })
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for TestServer
+    where
+        B: tonic::codegen::Body + Send + 'static,
+        B::Error: Into<tonic::codegen::StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+                Ok(grpc.unary(DecideService, request).await)
+            })
+        }
+    }
+
+    impl tonic::server::NamedService for TestServer {
+        const NAME: &'static str = "maker_simulator.bridge.v1.StrategyBridge";
+    }
+
+    // the endpoint of a TestServer serving on a thread of its own
+    fn serve() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                tonic::transport::Server::builder()
+                    .add_service(TestServer)
+                    .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                    .await
+                    .unwrap();
+            });
+        });
+        endpoint
+    }
+
+    #[test]
+    fn test_bridge_latency() {
+        let mut bridge = GrpcBridge::connect(&serve(), Some(Duration::from_millis(50))).unwrap();
+        let now = UNIX_EPOCH + Duration::from_millis(1000);
+        let mut world = StepperWorld {
+            now,
+            ..Default::default()
+        };
+        world.markets.insert(
+            "BTCUSDT",
+            MarketSnapshot {
+                best_bid_price: 100.0,
+                best_ask_price: 100.2,
+                ..Default::default()
+            },
+        );
+        world.order_tracker.upsert_order(Order {
+            order_id: "B7@wide".to_string(),
+            price: 99.0,
+            side: TradeSide::Buy,
+            quantity: 0.01,
+            filled: 0.0,
+            status: OrderStatus::Open,
+            created_at: UNIX_EPOCH,
+        });
+
+        bridge.send(&world, "BTCUSDT", "BTC", "USDT");
+        let due_at = now + Duration::from_millis(50);
+        assert_eq!(bridge.due_at(), Some(due_at));
+        // in flight until the latency passed, the next decisions are not sent
+        assert!(bridge.receive(now + Duration::from_millis(49)).is_empty());
+        world.now = now + Duration::from_millis(10);
+        bridge.send(&world, "BTCUSDT", "BTC", "USDT");
+        assert_eq!(bridge.due_at(), Some(due_at));
+
+        let actions = bridge.receive(due_at);
+        assert_eq!(bridge.due_at(), None);
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(
+            &actions[0],
+            Action::CancelOrder(CancelOrder { order_id, .. }) if order_id == "B7@wide"
+        ));
+        let Action::PlaceOrder(place) = &actions[1] else {
+            panic!("expected a place, got {:?}", actions[1]);
+        };
+        assert_eq!(place.order_id, "P1000");
+        assert_eq!(place.side, TradeSide::Buy);
+        assert!(matches!(place.trade_type, TradeType::LimitMaker));
+        assert!((place.price - 99.9).abs() < 1e-9);
+
+        // the wall time of the call without a fixed latency
+        bridge.latency = None;
+        bridge.send(&world, "BTCUSDT", "BTC", "USDT");
+        assert!(bridge.due_at().unwrap() > world.now);
+        bridge.discard();
+        assert_eq!(bridge.due_at(), None);
+    }
+
+    #[test]
+    fn test_bridge_world_markets() {
+        let mut world = StepperWorld::default();
+        for (symbol, bid, last_price) in [("ETHUSDT", 2000.0, 2001.0), ("BTCUSDT", 100.0, 100.1)] {
+            world.markets.insert(
+                symbol,
+                MarketSnapshot {
+                    best_bid_price: bid,
+                    last_price,
+                    ..Default::default()
+                },
+            );
+        }
+        let bridge_world = to_bridge_world(&world, "BTCUSDT", "BTC", "USDT");
+        // the book of the symbol traded, and every book by symbol
+        assert_eq!(bridge_world.best_bid_price, 100.0);
+        let markets: Vec<_> = bridge_world
+            .markets
+            .iter()
+            .map(|market| {
+                (
+                    market.symbol.as_str(),
+                    market.best_bid_price,
+                    market.last_price,
+                )
+            })
+            .collect();
+        assert_eq!(
+            markets,
+            vec![("BTCUSDT", 100.0, 100.1), ("ETHUSDT", 2000.0, 2001.0)]
+        );
+    }
+
+    #[test]
+    fn test_bridge_timeout() {
+        // accepts the connections and never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _connections: Vec<_> = listener.incoming().collect();
+        });
+        let mut bridge = GrpcBridge::connect(&endpoint, None).unwrap();
+        bridge.timeout = Duration::from_millis(100);
+        let world = StepperWorld {
+            now: UNIX_EPOCH + Duration::from_millis(1000),
+            ..Default::default()
+        };
+
+        let started_at = Instant::now();
+        bridge.send(&world, "BTCUSDT", "BTC", "USDT");
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        // no decision, the next one is sent
+        assert_eq!(bridge.due_at(), None);
+    }
+}
@@ -1 +1,5 @@
+pub mod grpc_bridge;
+pub mod plugin;
+pub mod session;
+pub mod state_history;
 pub mod stepper;
@@ -0,0 +1,719 @@
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    path::Path,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{bail, Context};
+use libloading::Library;
+use pure_market_maker::{Action, CancelOrder, PlaceOrderData};
+use stepper_world::{order_tracker::OrderStatus, StepperWorld};
+use tracing::warn;
+use upstair_type::order::{TimeInForce, TradeSide, TradeType};
+
+// Strategies written in other languages, loaded from a shared library with the C ABI of
+// plugin/maker_strategy.h. The plugin decides in place of the market maker of the stepper,
+// which keeps the orders, balances, reconciliation, sessions and halts: on every decision it
+// hands the plugin a snapshot of its world and takes back the orders to place and cancel.
+
+// bumped on every change of the structs and functions of the ABI
+pub const ABI_VERSION: u32 = 2;
+// bytes of an order id, NUL terminated
+pub const ORDER_ID_LEN: usize = 64;
+// bytes of a symbol, NUL terminated
+pub const SYMBOL_LEN: usize = 32;
+// the most actions taken from one decision
+const MAX_ACTIONS: usize = 256;
+
+pub const SIDE_BUY: u8 = 0;
+pub const SIDE_SELL: u8 = 1;
+
+pub const STATUS_OPEN_REQUESTED: u8 = 0;
+pub const STATUS_OPEN: u8 = 1;
+pub const STATUS_PARTIALLY_FILLED: u8 = 2;
+pub const STATUS_FILLED: u8 = 3;
+pub const STATUS_CANCEL_REQUESTED: u8 = 4;
+pub const STATUS_CANCELED: u8 = 5;
+pub const STATUS_ERRORED: u8 = 6;
+
+pub const ACTION_PLACE: u8 = 0;
+pub const ACTION_CANCEL: u8 = 1;
+
+pub const ORDER_TYPE_LIMIT: u8 = 0;
+pub const ORDER_TYPE_LIMIT_MAKER: u8 = 1;
+pub const ORDER_TYPE_MARKET: u8 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginOrder {
+    pub order_id: [c_char; ORDER_ID_LEN],
+    pub side: u8,
+    pub status: u8,
+    pub price: f64,
+    pub quantity: f64,
+    pub filled: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginTrade {
+    pub time_ms: u64,
+    pub price: f64,
+    pub quantity: f64,
+    pub is_buyer_maker: u8,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginFill {
+    pub order_id: [c_char; ORDER_ID_LEN],
+    pub filled: f64,
+}
+
+// The top of the book and the last trade of a symbol
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginMarket {
+    pub symbol: [c_char; SYMBOL_LEN],
+    pub best_bid_price: f64,
+    pub best_bid_qty: f64,
+    pub best_ask_price: f64,
+    pub best_ask_qty: f64,
+    pub last_price: f64,
+}
+
+// The world of the stepper at a decision. The arrays are valid for the call only.
+#[repr(C)]
+pub struct PluginWorld {
+    pub now_ms: u64,
+    pub latest_market_price: f64,
+    pub best_bid_price: f64,
+    pub best_bid_qty: f64,
+    pub best_ask_price: f64,
+    pub best_ask_qty: f64,
+    pub base_balance: f64,
+    pub base_locked: f64,
+    pub quote_balance: f64,
+    pub quote_locked: f64,
+    // set while the validation of the market data flags it
+    pub data_degraded: u8,
+    pub orders: *const PluginOrder,
+    pub orders_len: usize,
+    // since the previous decision
+    pub trades: *const PluginTrade,
+    pub trades_len: usize,
+    pub fills: *const PluginFill,
+    pub fills_len: usize,
+    // the books of our symbol and of the ones the stepper references
+    pub markets: *const PluginMarket,
+    pub markets_len: usize,
+}
+
+// An order to place, or the order_id to cancel
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginAction {
+    pub kind: u8,
+    pub side: u8,
+    pub order_type: u8,
+    pub order_id: [c_char; ORDER_ID_LEN],
+    pub price: f64,
+    pub quantity: f64,
+}
+
+impl Default for PluginAction {
+    fn default() -> Self {
+        PluginAction {
+            kind: ACTION_PLACE,
+            side: SIDE_BUY,
+            order_type: ORDER_TYPE_LIMIT,
+            order_id: [0; ORDER_ID_LEN],
+            price: 0.0,
+            quantity: 0.0,
+        }
+    }
+}
+
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+// the strategy of the config, null on an invalid one
+pub type NewFn = unsafe extern "C" fn(config: *const c_char) -> *mut c_void;
+// writes up to capacity actions and returns their count
+pub type DecideFn = unsafe extern "C" fn(
+    strategy: *mut c_void,
+    world: *const PluginWorld,
+    actions: *mut PluginAction,
+    capacity: usize,
+) -> usize;
+pub type FreeFn = unsafe extern "C" fn(strategy: *mut c_void);
+// points entries at the strategies of the library and returns their count
+pub type RegisterFn = unsafe extern "C" fn(entries: *mut *const PluginEntry) -> usize;
+
+// The functions of a strategy of a plugin
+#[derive(Clone, Copy)]
+pub struct PluginFns {
+    pub abi_version: AbiVersionFn,
+    pub new: NewFn,
+    pub decide: DecideFn,
+    pub free: FreeFn,
+}
+
+// A strategy registered by maker_strategy_register
+#[repr(C)]
+pub struct PluginEntry {
+    pub name: *const c_char,
+    pub abi_version: Option<AbiVersionFn>,
+    pub new: Option<NewFn>,
+    pub decide: Option<DecideFn>,
+    pub free: Option<FreeFn>,
+}
+
+pub struct PluginStrategy {
+    strategy: *mut c_void,
+    fns: PluginFns,
+    // the buffers of the snapshot and the actions, reused by every decision
+    orders: Vec<PluginOrder>,
+    trades: Vec<PluginTrade>,
+    fills: Vec<PluginFill>,
+    markets: Vec<PluginMarket>,
+    actions: Vec<PluginAction>,
+    // the functions live as long as the library, it is dropped after the strategy is freed
+    _library: Option<Arc<Library>>,
+}
+
+// the id truncated to fit, NUL terminated
+fn to_c_id<const N: usize>(id: &str) -> [c_char; N] {
+    let mut c_id = [0; N];
+    for (c, b) in c_id.iter_mut().zip(id.bytes().take(N - 1)) {
+        *c = b as c_char;
+    }
+    c_id
+}
+
+fn from_c_id<const N: usize>(c_id: &[c_char; N]) -> Option<String> {
+    let len = c_id.iter().position(|c| *c == 0)?;
+    let bytes: Vec<u8> = c_id[..len].iter().map(|c| *c as u8).collect();
+    String::from_utf8(bytes).ok().filter(|id| !id.is_empty())
+}
+
+fn side_to_c(side: &TradeSide) -> u8 {
+    match side {
+        TradeSide::Buy => SIDE_BUY,
+        TradeSide::Sell => SIDE_SELL,
+    }
+}
+
+fn status_to_c(status: &OrderStatus) -> u8 {
+    match status {
+        OrderStatus::OpenRequested => STATUS_OPEN_REQUESTED,
+        OrderStatus::Open => STATUS_OPEN,
+        OrderStatus::PartiallyFilled => STATUS_PARTIALLY_FILLED,
+        OrderStatus::Filled => STATUS_FILLED,
+        OrderStatus::CancelRequested => STATUS_CANCEL_REQUESTED,
+        OrderStatus::Canceled => STATUS_CANCELED,
+        OrderStatus::Errored => STATUS_ERRORED,
+    }
+}
+
+// the action of the plugin for symbol, None when it is invalid
+fn to_action(action: &PluginAction, symbol: &'static str) -> Option<Action> {
+    let order_id = from_c_id(&action.order_id)?;
+    match action.kind {
+        ACTION_CANCEL => Some(Action::CancelOrder(CancelOrder { symbol, order_id })),
+        ACTION_PLACE => {
+            let side = match action.side {
+                SIDE_BUY => TradeSide::Buy,
+                SIDE_SELL => TradeSide::Sell,
+                _ => return None,
+            };
+            let trade_type = match action.order_type {
+                ORDER_TYPE_LIMIT => TradeType::Limit,
+                ORDER_TYPE_LIMIT_MAKER => TradeType::LimitMaker,
+                ORDER_TYPE_MARKET => TradeType::Market,
+                _ => return None,
+            };
+            if !action.price.is_finite() || !action.quantity.is_finite() || action.quantity <= 0.0 {
+                return None;
+            }
+            Some(Action::PlaceOrder(PlaceOrderData {
+                symbol,
+                order_id,
+                price: action.price,
+                side,
+                quantity: action.quantity,
+                trade_type,
+                time_in_force: TimeInForce::GoodTilCancelled,
+            }))
+        }
+        _ => None,
+    }
+}
+
+// the name of a library without the lib prefix and the extension, libgrid.so is grid
+fn library_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    stem.strip_prefix("lib").unwrap_or(&stem).to_string()
+}
+
+// A strategy of a plugin library, the library stays loaded while one is held
+#[derive(Clone)]
+pub struct StrategyPlugin {
+    name: String,
+    fns: PluginFns,
+    library: Option<Arc<Library>>,
+}
+
+impl StrategyPlugin {
+    // the strategy of the functions of a plugin linked in, e.g. a test
+    pub fn linked(name: &str, fns: PluginFns) -> Self {
+        StrategyPlugin {
+            name: name.to_string(),
+            fns,
+            library: None,
+        }
+    }
+
+    // the strategies of the shared library at path, the ones of its maker_strategy_register,
+    // or without it the one of its maker_strategy_* functions named after the library
+    pub fn load(path: &Path) -> Result<Vec<StrategyPlugin>, anyhow::Error> {
+        // SAFETY: the library runs its initializers on load, a plugin is trusted code
+        let library = Arc::new(
+            unsafe { Library::new(path) }
+                .with_context(|| format!("failed to load {}", path.display()))?,
+        );
+        // SAFETY: the symbols have the types of maker_strategy.h
+        let register = unsafe { library.get::<RegisterFn>(b"maker_strategy_register\0") }
+            .ok()
+            .map(|register| *register);
+        let Some(register) = register else {
+            let fns = unsafe {
+                PluginFns {
+                    abi_version: *library.get(b"maker_strategy_abi_version\0")?,
+                    new: *library.get(b"maker_strategy_new\0")?,
+                    decide: *library.get(b"maker_strategy_decide\0")?,
+                    free: *library.get(b"maker_strategy_free\0")?,
+                }
+            };
+            return Ok(vec![StrategyPlugin {
+                name: library_name(path),
+                fns,
+                library: Some(library),
+            }]);
+        };
+
+        let mut entries: *const PluginEntry = std::ptr::null();
+        // SAFETY: the plugin points entries at count entries it keeps for its lifetime
+        let count = unsafe { register(&mut entries) };
+        if count == 0 || entries.is_null() {
+            bail!("{} registers no strategy", path.display());
+        }
+        let entries = unsafe { std::slice::from_raw_parts(entries, count) };
+        entries
+            .iter()
+            .map(|entry| {
+                let name = (!entry.name.is_null())
+                    .then(|| unsafe { CStr::from_ptr(entry.name) }.to_string_lossy())
+                    .filter(|name| !name.is_empty())
+                    .with_context(|| format!("a strategy of {} has no name", path.display()))?;
+                let (Some(abi_version), Some(new), Some(decide), Some(free)) =
+                    (entry.abi_version, entry.new, entry.decide, entry.free)
+                else {
+                    bail!("strategy {} of {} lacks a function", name, path.display());
+                };
+                Ok(StrategyPlugin {
+                    name: name.into_owned(),
+                    fns: PluginFns {
+                        abi_version,
+                        new,
+                        decide,
+                        free,
+                    },
+                    library: Some(library.clone()),
+                })
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // the strategy of config, a string the plugin parses as it likes
+    pub fn create(&self, config: &str) -> Result<PluginStrategy, anyhow::Error> {
+        let mut strategy = PluginStrategy::new(self.fns, config)
+            .with_context(|| format!("invalid strategy plugin {}", self.name))?;
+        strategy._library = self.library.clone();
+        Ok(strategy)
+    }
+}
+
+// The strategy plugins by name, of the libraries of a directory or given one by one
+#[derive(Clone, Default)]
+pub struct PluginRegistry {
+    plugins: Vec<StrategyPlugin>,
+}
+
+impl PluginRegistry {
+    // the strategies of the shared libraries in dir, *.so, *.dll or *.dylib by the platform,
+    // none without the directory
+    pub fn discover(dir: &Path) -> Result<PluginRegistry, anyhow::Error> {
+        let mut registry = PluginRegistry::default();
+        if !dir.is_dir() {
+            return Ok(registry);
+        }
+        let mut paths = vec![];
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION)
+            {
+                paths.push(path);
+            }
+        }
+        // the same order on every run
+        paths.sort();
+        for path in paths {
+            registry = registry.with_library(&path)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn with_library(mut self, path: &Path) -> Result<Self, anyhow::Error> {
+        for plugin in StrategyPlugin::load(path)? {
+            self.register(plugin)
+                .with_context(|| format!("failed to register {}", path.display()))?;
+        }
+        Ok(self)
+    }
+
+    pub fn register(&mut self, plugin: StrategyPlugin) -> Result<(), anyhow::Error> {
+        if self.get(plugin.name()).is_some() {
+            bail!("strategy plugin {} is registered already", plugin.name());
+        }
+        self.plugins.push(plugin);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StrategyPlugin> {
+        self.plugins.iter().find(|plugin| plugin.name == name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(StrategyPlugin::name).collect()
+    }
+}
+
+impl PluginStrategy {
+    // the strategy of the functions of a plugin
+    fn new(fns: PluginFns, config: &str) -> Result<PluginStrategy, anyhow::Error> {
+        // SAFETY: the functions are the ones of a plugin of the ABI
+        let version = unsafe { (fns.abi_version)() };
+        if version != ABI_VERSION {
+            bail!("plugin ABI version {version}, expected {ABI_VERSION}");
+        }
+        let config = CString::new(config).context("config has a NUL")?;
+        let strategy = unsafe { (fns.new)(config.as_ptr()) };
+        if strategy.is_null() {
+            bail!("plugin rejected the config {:?}", config);
+        }
+        Ok(PluginStrategy {
+            strategy,
+            fns,
+            orders: vec![],
+            trades: vec![],
+            fills: vec![],
+            markets: vec![],
+            actions: vec![PluginAction::default(); MAX_ACTIONS],
+            _library: None,
+        })
+    }
+
+    // the orders of symbol the plugin places and cancels on the world
+    pub fn decide(
+        &mut self,
+        world: &StepperWorld,
+        symbol: &'static str,
+        base_asset: &str,
+        quote_asset: &str,
+    ) -> Vec<Action> {
+        self.orders.clear();
+        self.orders
+            .extend(world.order_tracker.iter().map(|order| PluginOrder {
+                order_id: to_c_id(&order.order_id),
+                side: side_to_c(&order.side),
+                status: status_to_c(&order.status),
+                price: order.price,
+                quantity: order.quantity,
+                filled: order.filled,
+            }));
+        self.trades.clear();
+        self.trades
+            .extend(world.trade_buf.iter().map(|trade| PluginTrade {
+                time_ms: trade.time,
+                price: trade.price,
+                quantity: trade.qty,
+                is_buyer_maker: trade.is_buyer_maker as u8,
+            }));
+        self.fills.clear();
+        self.fills.extend(
+            world
+                .filled_event_buf
+                .iter()
+                .map(|(order_id, filled)| PluginFill {
+                    order_id: to_c_id(order_id),
+                    filled: *filled,
+                }),
+        );
+        let balance = |asset: &str| {
+            world
+                .account
+                .asset_to_balance
+                .get(asset)
+                .map_or((0.0, 0.0), |b| (b.balance, b.locked))
+        };
+        let (base_balance, base_locked) = balance(base_asset);
+        let (quote_balance, quote_locked) = balance(quote_asset);
+        self.markets.clear();
+        self.markets
+            .extend(world.markets.iter().map(|(symbol, market)| PluginMarket {
+                symbol: to_c_id(symbol),
+                best_bid_price: market.best_bid_price,
+                best_bid_qty: market.best_bid_qty,
+                best_ask_price: market.best_ask_price,
+                best_ask_qty: market.best_ask_qty,
+                last_price: market.last_price,
+            }));
+        let book = world.book(symbol);
+        let snapshot = PluginWorld {
+            now_ms: world
+                .now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            latest_market_price: world.latest_market_price,
+            best_bid_price: book.best_bid_price,
+            best_bid_qty: book.best_bid_qty,
+            best_ask_price: book.best_ask_price,
+            best_ask_qty: book.best_ask_qty,
+            base_balance,
+            base_locked,
+            quote_balance,
+            quote_locked,
+            data_degraded: world.data_quality.is_degraded() as u8,
+            orders: self.orders.as_ptr(),
+            orders_len: self.orders.len(),
+            trades: self.trades.as_ptr(),
+            trades_len: self.trades.len(),
+            fills: self.fills.as_ptr(),
+            fills_len: self.fills.len(),
+            markets: self.markets.as_ptr(),
+            markets_len: self.markets.len(),
+        };
+        // SAFETY: the snapshot and the actions outlive the call, the plugin writes at most
+        // capacity actions
+        let count = unsafe {
+            (self.fns.decide)(
+                self.strategy,
+                &snapshot,
+                self.actions.as_mut_ptr(),
+                self.actions.len(),
+            )
+        };
+        self.actions[..count.min(MAX_ACTIONS)]
+            .iter()
+            .filter_map(|action| {
+                let converted = to_action(action, symbol);
+                if converted.is_none() {
+                    warn!(
+                        "invalid plugin action of kind {} for order {:?}",
+                        action.kind,
+                        from_c_id(&action.order_id)
+                    );
+                }
+                converted
+            })
+            .collect()
+    }
+}
+
+impl Drop for PluginStrategy {
+    fn drop(&mut self) {
+        // SAFETY: the strategy was made by new of the same plugin and is freed once
+        unsafe { (self.fns.free)(self.strategy) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use stepper_world::{order_tracker::Order, MarketSnapshot};
+
+    use super::*;
+
+    // quotes a buy a tick under the bid and cancels the orders of the world, and echoes the
+    // symbols of the books it sees as a cancel
+    struct TestStrategy {
+        quantity: f64,
+    }
+
+    unsafe extern "C" fn abi_version() -> u32 {
+        ABI_VERSION
+    }
+
+    unsafe extern "C" fn new(config: *const c_char) -> *mut c_void {
+        match CStr::from_ptr(config)
+            .to_str()
+            .ok()
+            .and_then(|config| config.parse().ok())
+        {
+            Some(quantity) => Box::into_raw(Box::new(TestStrategy { quantity })) as *mut c_void,
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn decide(
+        strategy: *mut c_void,
+        world: *const PluginWorld,
+        actions: *mut PluginAction,
+        capacity: usize,
+    ) -> usize {
+        let strategy = &*(strategy as *const TestStrategy);
+        let world = &*world;
+        let actions = std::slice::from_raw_parts_mut(actions, capacity);
+        let orders = std::slice::from_raw_parts(world.orders, world.orders_len);
+        for (action, order) in actions.iter_mut().zip(orders) {
+            action.kind = ACTION_CANCEL;
+            action.order_id = order.order_id;
+        }
+        actions[orders.len()] = PluginAction {
+            kind: ACTION_PLACE,
+            side: SIDE_BUY,
+            order_type: ORDER_TYPE_LIMIT_MAKER,
+            order_id: to_c_id(&format!("P{}", world.now_ms)),
+            price: world.best_bid_price - 0.1,
+            quantity: strategy.quantity,
+        };
+        let markets = std::slice::from_raw_parts(world.markets, world.markets_len);
+        let symbols: Vec<_> = markets
+            .iter()
+            .map(|market| from_c_id(&market.symbol).unwrap())
+            .collect();
+        actions[orders.len() + 1] = PluginAction {
+            kind: ACTION_CANCEL,
+            order_id: to_c_id(&symbols.join(",")),
+            ..Default::default()
+        };
+        // an unknown kind is dropped
+        actions[orders.len() + 2] = PluginAction {
+            kind: 9,
+            order_id: to_c_id("X"),
+            ..Default::default()
+        };
+        orders.len() + 3
+    }
+
+    unsafe extern "C" fn free(strategy: *mut c_void) {
+        drop(Box::from_raw(strategy as *mut TestStrategy));
+    }
+
+    const FNS: PluginFns = PluginFns {
+        abi_version,
+        new,
+        decide,
+        free,
+    };
+
+    #[test]
+    fn test_plugin_decide() {
+        let plugin = StrategyPlugin::linked("test", FNS);
+        assert!(plugin.create("not a quantity").is_err());
+        let mut plugin = plugin.create("0.01").unwrap();
+
+        let mut world = StepperWorld {
+            now: UNIX_EPOCH + Duration::from_millis(1000),
+            ..Default::default()
+        };
+        for (symbol, bid, ask) in [("BTCUSDT", 100.0, 100.2), ("ETHUSDT", 2000.0, 2000.5)] {
+            world.markets.insert(
+                symbol,
+                MarketSnapshot {
+                    best_bid_price: bid,
+                    best_ask_price: ask,
+                    ..Default::default()
+                },
+            );
+        }
+        world.order_tracker.upsert_order(Order {
+            order_id: "B7@wide".to_string(),
+            price: 99.0,
+            side: TradeSide::Buy,
+            quantity: 0.01,
+            filled: 0.0,
+            status: OrderStatus::Open,
+            created_at: UNIX_EPOCH,
+        });
+        let actions = plugin.decide(&world, "BTCUSDT", "BTC", "USDT");
+        assert_eq!(actions.len(), 3);
+        assert!(matches!(
+            &actions[0],
+            Action::CancelOrder(CancelOrder { order_id, .. }) if order_id == "B7@wide"
+        ));
+        let Action::PlaceOrder(place) = &actions[1] else {
+            panic!("expected a place, got {:?}", actions[1]);
+        };
+        assert_eq!(place.order_id, "P1000");
+        assert_eq!(place.side, TradeSide::Buy);
+        assert!(matches!(place.trade_type, TradeType::LimitMaker));
+        assert!((place.price - 99.9).abs() < 1e-9);
+        assert_eq!(place.quantity, 0.01);
+        // the books of every symbol the stepper follows
+        assert!(matches!(
+            &actions[2],
+            Action::CancelOrder(CancelOrder { order_id, .. }) if order_id == "BTCUSDT,ETHUSDT"
+        ));
+    }
+
+    #[test]
+    fn test_plugin_registry() {
+        let mut registry = PluginRegistry::default();
+        registry
+            .register(StrategyPlugin::linked("quote_bid", FNS))
+            .unwrap();
+        registry
+            .register(StrategyPlugin::linked("grid", FNS))
+            .unwrap();
+        assert!(registry
+            .register(StrategyPlugin::linked("grid", FNS))
+            .is_err());
+        assert_eq!(registry.names(), vec!["quote_bid", "grid"]);
+        assert!(registry.get("grid").is_some());
+        assert!(registry.get("unknown").is_none());
+
+        assert_eq!(library_name(Path::new("plugins/libgrid.so")), "grid");
+        assert_eq!(library_name(Path::new("plugins/grid.dll")), "grid");
+        // a missing directory has no plugins
+        let dir = std::env::temp_dir().join(format!("no_plugins_{}", std::process::id()));
+        assert!(PluginRegistry::discover(&dir).unwrap().names().is_empty());
+    }
+
+    #[test]
+    fn test_order_id_round_trip() {
+        assert_eq!(
+            from_c_id(&to_c_id::<ORDER_ID_LEN>("S12@wide")).as_deref(),
+            Some("S12@wide")
+        );
+        assert_eq!(from_c_id(&to_c_id::<ORDER_ID_LEN>("")), None);
+        let long = "x".repeat(ORDER_ID_LEN * 2);
+        assert_eq!(
+            from_c_id(&to_c_id::<ORDER_ID_LEN>(&long)).unwrap().len(),
+            ORDER_ID_LEN - 1
+        );
+    }
+}
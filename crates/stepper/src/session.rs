@@ -0,0 +1,124 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// A window of the UTC day from start to end exclusive, in seconds of the day. Wraps past
+// midnight when it ends before it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionWindow {
+    start: u64,
+    end: u64,
+}
+
+impl SessionWindow {
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs() % SECS_PER_DAY;
+        if self.start <= self.end {
+            self.start <= secs && secs < self.end
+        } else {
+            secs >= self.start || secs < self.end
+        }
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Option<u64> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes): (u64, u64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    // 24:00 ends a window at midnight
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60)
+}
+
+impl FromStr for SessionWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let window = s.split_once('-').and_then(|(start, end)| {
+            Some(SessionWindow {
+                start: parse_time_of_day(start.trim())? % SECS_PER_DAY,
+                end: parse_time_of_day(end.trim())? % SECS_PER_DAY,
+            })
+        });
+        match window {
+            Some(window) if window.start != window.end => Ok(window),
+            _ => Err(format!(
+                "invalid session window {s}, expected HH:MM-HH:MM in UTC"
+            )),
+        }
+    }
+}
+
+// The UTC windows a strategy quotes in, e.g. outside the low liquidity hours, less the
+// blackouts, e.g. around the funding timestamps. Always open without windows.
+#[derive(Debug, Clone, Default)]
+pub struct SessionCalendar {
+    windows: Vec<SessionWindow>,
+    blackouts: Vec<SessionWindow>,
+}
+
+impl SessionCalendar {
+    pub fn with_window(mut self, window: SessionWindow) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    pub fn with_blackout(mut self, blackout: SessionWindow) -> Self {
+        self.blackouts.push(blackout);
+        self
+    }
+
+    pub fn is_open(&self, time: SystemTime) -> bool {
+        (self.windows.is_empty() || self.windows.iter().any(|w| w.contains(time)))
+            && !self.blackouts.iter().any(|b| b.contains(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        // a day after the epoch, the date does not matter
+        UNIX_EPOCH + Duration::from_secs(SECS_PER_DAY + hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn test_session_calendar() {
+        let calendar = SessionCalendar::default()
+            .with_window("06:00-22:00".parse().unwrap())
+            .with_blackout("07:55-08:05".parse().unwrap());
+        assert!(!calendar.is_open(at(5, 59)));
+        assert!(calendar.is_open(at(6, 0)));
+        assert!(!calendar.is_open(at(8, 0)));
+        assert!(calendar.is_open(at(8, 5)));
+        assert!(!calendar.is_open(at(22, 0)));
+
+        // wraps past midnight
+        let calendar = SessionCalendar::default().with_blackout("23:55-00:05".parse().unwrap());
+        assert!(!calendar.is_open(at(23, 58)));
+        assert!(!calendar.is_open(at(0, 2)));
+        assert!(calendar.is_open(at(12, 0)));
+        assert!(SessionCalendar::default().is_open(at(3, 0)));
+    }
+
+    #[test]
+    fn test_parse_session_window() {
+        assert_eq!(
+            "22:00-24:00".parse(),
+            Ok(SessionWindow {
+                start: 22 * 3600,
+                end: 0
+            })
+        );
+        assert!("8:00-8:00".parse::<SessionWindow>().is_err());
+        assert!("08:00".parse::<SessionWindow>().is_err());
+        assert!("08:60-09:00".parse::<SessionWindow>().is_err());
+    }
+}
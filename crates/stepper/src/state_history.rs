@@ -0,0 +1,252 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use polars::df;
+use pure_market_maker::AmmStrategy;
+use stepper_world::StepperWorld;
+use upstair_type::{order::TradeSide, strategy::StrategyDebug, time::to_ms};
+use util::parquet::write_parquet;
+
+const STATE_HISTORY_FILE: &str = "state_history.parquet";
+const STATE_ORDERS_FILE: &str = "state_orders.parquet";
+
+// What the strategy knew at a checkpoint
+#[derive(Debug, Clone, PartialEq)]
+struct StateSnapshot {
+    time_ms: u64,
+    latest_market_price: f64,
+    best_bid: f64,
+    best_ask: f64,
+    base_balance: f64,
+    quote_balance: f64,
+    initial_position: f64,
+    target_ratio: f64,
+    open_orders: u32,
+    halted: bool,
+    data_degraded: bool,
+    // of the last quote round, None before the first
+    vol: Option<f64>,
+    reservation_price: Option<f64>,
+    quoting_bid: Option<bool>,
+    quoting_ask: Option<bool>,
+}
+
+// An order tracked at a checkpoint
+#[derive(Debug, Clone, PartialEq)]
+struct OrderSnapshot {
+    time_ms: u64,
+    order_id: String,
+    is_buy: bool,
+    price: f64,
+    quantity: f64,
+    filled: f64,
+    status: String,
+    created_at_ms: u64,
+}
+
+// Snapshots of the orders, balances and strategy variables every interval of simulated
+// time, written to state_history.parquet and state_orders.parquet in dir on save
+pub struct StateHistory {
+    dir: PathBuf,
+    interval: Duration,
+    next_at: Option<SystemTime>,
+    snapshots: Vec<StateSnapshot>,
+    orders: Vec<OrderSnapshot>,
+    // the last quote round of the strategy, until the next one
+    last_round: Option<StrategyDebug>,
+}
+
+impl StateHistory {
+    pub fn new(dir: impl Into<PathBuf>, interval: Duration) -> Self {
+        StateHistory {
+            dir: dir.into(),
+            interval,
+            next_at: None,
+            snapshots: vec![],
+            orders: vec![],
+            last_round: None,
+        }
+    }
+
+    // whether a checkpoint is due at now, the first one right away and then one per
+    // interval, aligned to the interval
+    pub fn due(&mut self, now: SystemTime) -> bool {
+        if self.next_at.is_some_and(|at| now < at) {
+            return false;
+        }
+        let interval_ms = self.interval.as_millis().max(1) as u64;
+        let next_ms = (to_ms(now) / interval_ms + 1) * interval_ms;
+        self.next_at = Some(UNIX_EPOCH + Duration::from_millis(next_ms));
+        true
+    }
+
+    // a quote round of the strategy, the checkpoints from here on show it
+    pub fn on_round(&mut self, round: &StrategyDebug) {
+        self.last_round = Some(round.clone());
+    }
+
+    pub fn record(&mut self, world: &StepperWorld, strategy: &AmmStrategy, halted: bool) {
+        let last_round = self.last_round.as_ref();
+        let time_ms = to_ms(world.now);
+        let balance = |asset| {
+            world
+                .account
+                .asset_to_balance
+                .get(asset)
+                .map_or(0.0, |b| b.balance)
+        };
+        let mut open_orders = 0;
+        for order in world.order_tracker.iter() {
+            open_orders += 1;
+            self.orders.push(OrderSnapshot {
+                time_ms,
+                order_id: order.order_id.clone(),
+                is_buy: order.side == TradeSide::Buy,
+                price: order.price,
+                quantity: order.quantity,
+                filled: order.filled,
+                status: format!("{:?}", order.status),
+                created_at_ms: to_ms(order.created_at),
+            });
+        }
+        let book = world.book(strategy.symbol);
+        self.snapshots.push(StateSnapshot {
+            time_ms,
+            latest_market_price: world.latest_market_price,
+            best_bid: book.best_bid_price,
+            best_ask: book.best_ask_price,
+            base_balance: balance(strategy.base_asset),
+            quote_balance: balance(strategy.quote_asset),
+            initial_position: strategy.intial_position,
+            target_ratio: strategy.target_ratio,
+            open_orders,
+            halted,
+            data_degraded: world.data_quality.is_degraded(),
+            vol: last_round.map(|d| d.vol),
+            reservation_price: last_round.map(|d| d.reservation_price),
+            quoting_bid: last_round.map(|d| d.quoting_bid),
+            quoting_ask: last_round.map(|d| d.quoting_ask),
+        });
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let s = &self.snapshots;
+        write_parquet(
+            &self.dir,
+            STATE_HISTORY_FILE,
+            df!(
+                "time" => s.iter().map(|s| s.time_ms).collect::<Vec<_>>(),
+                "latest_market_price" => s.iter().map(|s| s.latest_market_price).collect::<Vec<_>>(),
+                "best_bid" => s.iter().map(|s| s.best_bid).collect::<Vec<_>>(),
+                "best_ask" => s.iter().map(|s| s.best_ask).collect::<Vec<_>>(),
+                "base_balance" => s.iter().map(|s| s.base_balance).collect::<Vec<_>>(),
+                "quote_balance" => s.iter().map(|s| s.quote_balance).collect::<Vec<_>>(),
+                "initial_position" => s.iter().map(|s| s.initial_position).collect::<Vec<_>>(),
+                "target_ratio" => s.iter().map(|s| s.target_ratio).collect::<Vec<_>>(),
+                "open_orders" => s.iter().map(|s| s.open_orders).collect::<Vec<_>>(),
+                "halted" => s.iter().map(|s| s.halted).collect::<Vec<_>>(),
+                "data_degraded" => s.iter().map(|s| s.data_degraded).collect::<Vec<_>>(),
+                "vol" => s.iter().map(|s| s.vol).collect::<Vec<_>>(),
+                "reservation_price" => s.iter().map(|s| s.reservation_price).collect::<Vec<_>>(),
+                "quoting_bid" => s.iter().map(|s| s.quoting_bid).collect::<Vec<_>>(),
+                "quoting_ask" => s.iter().map(|s| s.quoting_ask).collect::<Vec<_>>(),
+            )?,
+        )?;
+        let o = &self.orders;
+        write_parquet(
+            &self.dir,
+            STATE_ORDERS_FILE,
+            df!(
+                "time" => o.iter().map(|o| o.time_ms).collect::<Vec<_>>(),
+                "order_id" => o.iter().map(|o| o.order_id.as_str()).collect::<Vec<_>>(),
+                "is_buy" => o.iter().map(|o| o.is_buy).collect::<Vec<_>>(),
+                "price" => o.iter().map(|o| o.price).collect::<Vec<_>>(),
+                "quantity" => o.iter().map(|o| o.quantity).collect::<Vec<_>>(),
+                "filled" => o.iter().map(|o| o.filled).collect::<Vec<_>>(),
+                "status" => o.iter().map(|o| o.status.as_str()).collect::<Vec<_>>(),
+                "created_at" => o.iter().map(|o| o.created_at_ms).collect::<Vec<_>>(),
+            )?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::{io::SerReader, prelude::ParquetReader};
+    use stepper_world::order_tracker::{Order, OrderStatus};
+    use symbol_info::SymbolInfoManager;
+
+    use super::*;
+
+    #[test]
+    fn test_state_history_checkpoints() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let dir = std::env::temp_dir().join(format!("state_history_{}", std::process::id()));
+        let mut history = StateHistory::new(&dir, Duration::from_secs(60));
+        assert!(history.due(at(30)));
+        assert!(!history.due(at(59)));
+        assert!(history.due(at(60)));
+        // a quiet market skips checkpoints
+        assert!(history.due(at(250)));
+        assert!(!history.due(at(299)));
+
+        let strategy = AmmStrategy::new(
+            "BTCUSDT",
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+        );
+        let mut world = StepperWorld {
+            now: at(60),
+            ..Default::default()
+        };
+        world
+            .account
+            .asset_to_balance
+            .entry("BTC")
+            .or_default()
+            .balance = 1.5;
+        history.record(&world, &strategy, false);
+        world.now = at(120);
+        for (order_id, side) in [("B0", TradeSide::Buy), ("S0", TradeSide::Sell)] {
+            world.order_tracker.upsert_order(Order {
+                order_id: order_id.to_string(),
+                price: 100.0,
+                side,
+                quantity: 0.01,
+                filled: 0.0,
+                status: OrderStatus::Open,
+                created_at: at(119),
+            });
+        }
+        let round = StrategyDebug {
+            vol: 0.5,
+            quoting_bid: true,
+            ..Default::default()
+        };
+        history.on_round(&round);
+        history.record(&world, &strategy, true);
+        // no round since the last checkpoint
+        world.now = at(180);
+        history.record(&world, &strategy, true);
+        assert_eq!(history.snapshots[0].base_balance, 1.5);
+        assert_eq!(history.snapshots[0].vol, None);
+        assert_eq!(history.snapshots[1].open_orders, 2);
+        assert_eq!(history.snapshots[1].vol, Some(0.5));
+        assert_eq!(history.snapshots[1].quoting_ask, Some(false));
+        assert_eq!(history.snapshots[2].vol, Some(0.5));
+
+        history.save().unwrap();
+        let rows = |name: &str| {
+            let file = std::fs::File::open(dir.join(name)).unwrap();
+            ParquetReader::new(file).finish().unwrap().height()
+        };
+        assert_eq!(rows(STATE_HISTORY_FILE), 3);
+        assert_eq!(rows(STATE_ORDERS_FILE), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,28 +1,97 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use stepper_world::order_tracker::{self};
+use stepper_world::sequence::{SequenceTracker, Sequenced};
 use symbol_info::SymbolInfoManager;
-use upstair_type::module::{Module, ModuleBuilder, ReadTopicHandle, WriteTopicHandle};
-use upstair_type::order::{CancelOrderRequest, TimeInForce};
+use upstair_type::account::AccountUpdate;
+use upstair_type::control::StaleOrderReport;
+use upstair_type::debug_log::DebugLog;
+use upstair_type::module::{
+    and_filter, owner_filter, symbol_filter, symbols_filter, Module, ModuleBuilder,
+    ReadTopicHandle, WriteTopicHandle,
+};
+use upstair_type::order::{CancelOrderRequest, ResyncRequest, ResyncSnapshot};
+use upstair_type::run_output::RunOutput;
 use upstair_type::Payload::{self, BinanceTradeTick};
 use upstair_type::{order, Message, MessageHeader};
 
 use stepper_world;
 
+use crate::grpc_bridge::GrpcBridge;
+use crate::plugin::PluginStrategy;
+use crate::session::SessionCalendar;
+use crate::state_history::StateHistory;
+
+// a resync request without a snapshot is sent again after
+const RESYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Orders whose place or cancel request is not answered within timeout get their cancel
+// re-issued, after max_retries they are given up
+#[derive(Debug, Clone)]
+pub struct ReconcileConfig {
+    pub timeout: Duration,
+    pub max_retries: u32,
+}
+
+// When the strategy decides its quotes: at most once every interval of engine time, or after
+// every book ticker update to study how quoting frequency affects PnL
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecisionTrigger {
+    Interval(Duration),
+    BookTicker,
+}
+
+impl Default for DecisionTrigger {
+    fn default() -> Self {
+        DecisionTrigger::Interval(Duration::from_millis(100))
+    }
+}
+
 pub struct Stepper {
     // Topics
     read_market_data_handle: ReadTopicHandle,
     read_order_result_handle: ReadTopicHandle,
     write_order_handle: WriteTopicHandle,
     read_account_handle: ReadTopicHandle,
+    read_control_handle: ReadTopicHandle,
+    read_signals_handle: ReadTopicHandle,
+    write_control_handle: WriteTopicHandle,
+    write_strategy_debug_handle: WriteTopicHandle,
+    write_debug_log_handle: WriteTopicHandle,
 
     // Internal states
     world: stepper_world::StepperWorld,
 
     last_iteration_time: std::time::SystemTime,
+    decision_trigger: DecisionTrigger,
+    // a book ticker arrived since the last decision
+    book_updated: bool,
 
     mm_strategy: pure_market_maker::AmmStrategy,
+    // decides in place of the market maker when loaded
+    plugin: Option<PluginStrategy>,
+    // decides in place of the market maker in another process when connected
+    bridge: Option<GrpcBridge>,
+    // set once a risk module halts trading
+    halted: bool,
+    // set on shutdown, only cancels are sent from then on
+    shut_down: bool,
+    flatten_on_shutdown: bool,
+    // quoting is suspended outside the session
+    session: SessionCalendar,
+    session_open: bool,
+    reconcile: Option<ReconcileConfig>,
+    sequence: SequenceTracker,
+    state_history: Option<StateHistory>,
+    owner: Option<&'static str>,
+    // the requests of a decision are sent in batches of MAX_BATCH_ORDERS
+    batch_orders: bool,
+    // (due, message) of the market data and signals on their way to the strategy, in due
+    // order, see receive_feed
+    delayed_feed: VecDeque<(SystemTime, Message)>,
 
     #[allow(dead_code)]
     symbol_info: SymbolInfoManager,
@@ -31,118 +100,564 @@ pub struct Stepper {
 impl Module for Stepper {
     fn sync(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) -> bool {
         while let Some(msg) = comms.receive(&self.read_market_data_handle) {
-            self.ingest_message(msg);
+            self.receive_feed(msg);
         }
-        while let Some(msg) = comms.receive(&self.read_order_result_handle) {
-            self.ingest_message(msg);
+        // the results and balance updates of our symbol come on two topics, they are applied
+        // in the order the exchange sent them
+        let mut exchange_messages: Vec<Message> =
+            std::iter::from_fn(|| comms.receive(&self.read_order_result_handle)).collect();
+        exchange_messages.extend(std::iter::from_fn(|| {
+            comms.receive(&self.read_account_handle)
+        }));
+        exchange_messages.sort_by_key(|msg| self.sequence_number(msg).unwrap_or(u64::MAX));
+        for msg in exchange_messages {
+            self.ingest_exchange_message(msg);
         }
-        while let Some(msg) = comms.receive(&self.read_account_handle) {
+        while let Some(msg) = comms.receive(&self.read_control_handle) {
             self.ingest_message(msg);
         }
+        while let Some(msg) = comms.receive(&self.read_signals_handle) {
+            self.receive_feed(msg);
+        }
+        while self
+            .delayed_feed
+            .front()
+            .is_some_and(|(due, _)| *due <= comms.time())
+        {
+            let (_, msg) = self.delayed_feed.pop_front().unwrap();
+            self.ingest_feed(msg);
+        }
+        if self.sequence.resync_due(comms.time(), RESYNC_TIMEOUT) {
+            comms.publish(
+                &self.write_order_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: comms.time(),
+                    },
+                    payload: Payload::ResyncRequest(ResyncRequest {
+                        symbol: self.mm_strategy.symbol,
+                        owner: self.owner,
+                    }),
+                },
+            );
+        }
         true
     }
 
     fn one_iteration(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
-        // at least 100ms from last iteration
-        if comms
-            .time()
-            .duration_since(self.last_iteration_time)
-            .unwrap()
-            .as_millis()
-            < 100
-        {
+        let due = match self.decision_trigger {
+            DecisionTrigger::Interval(interval) => {
+                comms
+                    .time()
+                    .duration_since(self.last_iteration_time)
+                    .unwrap()
+                    >= interval
+            }
+            DecisionTrigger::BookTicker => self.book_updated,
+        };
+        // the actions of the bridge reached us, applied between the decisions
+        let bridged = self
+            .bridge
+            .as_ref()
+            .and_then(GrpcBridge::due_at)
+            .is_some_and(|due_at| due_at <= comms.time());
+        if !due && !bridged {
             return;
         }
-        self.last_iteration_time = comms.time();
+        if due {
+            self.last_iteration_time = comms.time();
+            self.book_updated = false;
+        }
 
         self.world.now = comms.time();
+        self.reconcile_orders(comms);
         self.world.order_tracker.remove_terminated_orders();
 
-        self.mm_strategy.run(&mut self.world);
-        self.world.trade_buf.clear();
-        self.world.wap_buf.clear();
-        self.world.filled_event_buf.clear();
+        let session_open = self.session.is_open(self.world.now);
+        if session_open != self.session_open {
+            tracing::info!(
+                "{} session {}",
+                self.mm_strategy.symbol,
+                if session_open { "opened" } else { "closed" }
+            );
+            self.session_open = session_open;
+        }
+        let quoting = !self.halted && !self.shut_down && session_open;
+        if quoting && due {
+            // the strategy reads the world from here
+            self.world.check_look_ahead();
+        }
+        if !quoting {
+            if let Some(bridge) = &mut self.bridge {
+                bridge.discard();
+            }
+            // stop quoting and cancel everything still open
+            self.mm_strategy.actions = self
+                .world
+                .order_tracker
+                .iter()
+                .filter(|order| order.status != order_tracker::OrderStatus::CancelRequested)
+                .map(|order| {
+                    pure_market_maker::Action::CancelOrder(pure_market_maker::CancelOrder {
+                        symbol: self.mm_strategy.symbol,
+                        order_id: order.order_id.clone(),
+                    })
+                })
+                .collect();
+        } else if let Some(plugin) = &mut self.plugin {
+            self.mm_strategy.actions = plugin.decide(
+                &self.world,
+                self.mm_strategy.symbol,
+                self.mm_strategy.base_asset,
+                self.mm_strategy.quote_asset,
+            );
+        } else if let Some(bridge) = &mut self.bridge {
+            if due {
+                bridge.send(
+                    &self.world,
+                    self.mm_strategy.symbol,
+                    self.mm_strategy.base_asset,
+                    self.mm_strategy.quote_asset,
+                );
+            }
+            self.mm_strategy.actions = bridge.receive(self.world.now);
+        } else {
+            self.mm_strategy.run(&mut self.world);
+        }
+        // kept for the next decision when only actions of the bridge were applied
+        if due {
+            self.world.trade_buf.clear();
+            self.world.wap_buf.clear();
+            self.world.mid_buf.clear();
+            self.world.filled_event_buf.clear();
+            self.world.reject_buf.clear();
+            self.world.cancel_reject_buf.clear();
+        }
+
+        if let Some(history) = &mut self.state_history {
+            // the round decided in this iteration, before the checkpoint
+            if let Some(round) = self.mm_strategy.debug_buf.last() {
+                history.on_round(round);
+            }
+            if history.due(self.world.now) {
+                history.record(&self.world, &self.mm_strategy, self.halted);
+            }
+        }
+        for debug in self.mm_strategy.debug_buf.drain(..) {
+            comms.publish(
+                &self.write_strategy_debug_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: self.world.now,
+                    },
+                    payload: Payload::StrategyDebug(debug),
+                },
+            );
+        }
+        for record in self.mm_strategy.debug_log_buf.drain(..) {
+            comms.publish(
+                &self.write_debug_log_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: self.world.now,
+                    },
+                    payload: Payload::DebugLog(DebugLog {
+                        symbol: self.mm_strategy.symbol,
+                        record,
+                    }),
+                },
+            );
+        }
+
+        self.publish_actions(comms);
+    }
+
+    fn start(&mut self) {}
+
+    fn next_iteration_start_at(&self) -> Option<std::time::SystemTime> {
+        // woken when the actions of the bridge arrive or the delayed market data is due
+        let bridged = self.bridge.as_ref().and_then(GrpcBridge::due_at);
+        let delayed = self.delayed_feed.front().map(|(due, _)| *due);
+        match (bridged, delayed) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
 
-        // run actions
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+
+    fn on_shutdown(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        self.sync(comms);
+        self.shut_down = true;
+        if let Some(bridge) = &mut self.bridge {
+            bridge.discard();
+        }
+        self.world.now = comms.time();
+        self.mm_strategy
+            .shutdown(&self.world, self.flatten_on_shutdown);
+        self.publish_actions(comms);
+    }
+
+    fn terminate(&mut self) {
+        self.mm_strategy.terminate();
+        if self.sequence.gaps() > 0 {
+            println!("Sequence gaps resynced: {}", self.sequence.gaps());
+        }
+        if let Some(history) = &self.state_history {
+            if let Err(e) = history.save() {
+                tracing::error!("failed to write state history: {:#}", e);
+            }
+        }
+    }
+}
+
+impl Stepper {
+    fn publish_actions(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        let mut cancels = vec![];
+        let mut orders = vec![];
         for action in self.mm_strategy.actions.iter() {
             match action {
                 pure_market_maker::Action::CancelOrder(cancel_order) => {
                     self.world
                         .order_tracker
-                        .request_cancel_order(&cancel_order.order_id);
+                        .request_cancel_order(&cancel_order.order_id, self.world.now);
+                    let req = CancelOrderRequest {
+                        symbol: cancel_order.symbol,
+                        client_order_id: Arc::from(cancel_order.order_id.as_str()),
+                        owner: self.owner,
+                    };
+                    if self.batch_orders {
+                        cancels.push(req);
+                        continue;
+                    }
                     comms.publish(
                         &self.write_order_handle,
                         Message {
                             header: MessageHeader {
                                 commit_at: self.world.now,
                             },
-                            payload: Payload::CancelOrderRequest(CancelOrderRequest {
-                                symbol: cancel_order.symbol,
-                                client_order_id: Arc::from(cancel_order.order_id.as_str()),
-                            }),
+                            payload: Payload::CancelOrderRequest(req),
                         },
                     )
                 }
                 pure_market_maker::Action::PlaceOrder(place_order) => {
+                    // the ids of the strategies sharing a market differ by their owners
+                    let order_id = match self.owner {
+                        Some(owner) => format!("{}@{}", place_order.order_id, owner),
+                        None => place_order.order_id.clone(),
+                    };
                     let tracking_order = stepper_world::order_tracker::Order {
-                        order_id: place_order.order_id.clone(),
+                        order_id: order_id.clone(),
                         price: place_order.price,
                         side: place_order.side.clone(),
                         quantity: place_order.quantity,
                         filled: 0.0,
-                        status: stepper_world::order_tracker::OrderStatus::Open,
+                        status: stepper_world::order_tracker::OrderStatus::OpenRequested,
                         created_at: self.world.now,
                     };
                     self.world.order_tracker.upsert_order(tracking_order);
+                    let req = order::OrderRequest {
+                        symbol: place_order.symbol,
+                        side: place_order.side.clone(),
+                        price: place_order.price,
+                        quantity: place_order.quantity,
+                        client_order_id: Arc::from(order_id),
+                        trade_type: place_order.trade_type.clone(),
+                        time_in_force: place_order.time_in_force.clone(),
+                        cancel_order_id: None,
+                        owner: self.owner,
+                    };
+                    if self.batch_orders {
+                        orders.push(req);
+                        continue;
+                    }
                     comms.publish(
                         &self.write_order_handle,
                         Message {
                             header: MessageHeader {
                                 commit_at: self.world.now,
                             },
-                            payload: Payload::OrderRequest(order::OrderRequest {
-                                symbol: place_order.symbol,
-                                side: place_order.side.clone(),
-                                price: place_order.price,
-                                quantity: place_order.quantity,
-                                client_order_id: Arc::from(place_order.order_id.as_str()),
-                                trade_type: order::TradeType::Limit,
-                                time_in_force: TimeInForce::GoodTilCancelled,
-                                cancel_order_id: None,
-                            }),
+                            payload: Payload::OrderRequest(req),
                         },
                     );
                 }
             }
         }
+        // the cancels first, they unlock the balance of the orders
+        let symbol = self.mm_strategy.symbol;
+        let batches = cancels
+            .chunks(order::MAX_BATCH_ORDERS)
+            .map(|cancels| {
+                Payload::BatchCancelRequest(order::BatchCancelRequest {
+                    symbol,
+                    cancels: cancels.to_vec(),
+                    owner: self.owner,
+                })
+            })
+            .chain(orders.chunks(order::MAX_BATCH_ORDERS).map(|orders| {
+                Payload::BatchOrderRequest(order::BatchOrderRequest {
+                    symbol,
+                    orders: orders.to_vec(),
+                    owner: self.owner,
+                })
+            }));
+        for payload in batches {
+            comms.publish(
+                &self.write_order_handle,
+                Message {
+                    header: MessageHeader {
+                        commit_at: self.world.now,
+                    },
+                    payload,
+                },
+            );
+        }
     }
 
-    fn start(&mut self) {}
+    fn reconcile_orders(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        let Some(reconcile) = &self.reconcile else {
+            return;
+        };
+        let now = self.world.now;
+        for stale in self
+            .world
+            .order_tracker
+            .stale_orders(now, reconcile.timeout)
+        {
+            let errored = stale.retries >= reconcile.max_retries;
+            let client_order_id: Arc<str> = Arc::from(stale.order_id.as_str());
+            if errored {
+                tracing::error!(
+                    "order {} stuck in {:?} for {:?}, giving up after {} retries",
+                    stale.order_id,
+                    stale.status,
+                    stale.pending_for,
+                    stale.retries
+                );
+                self.world.order_tracker.mark_errored(&stale.order_id);
+            } else {
+                tracing::warn!(
+                    "order {} stuck in {:?} for {:?}, re-issuing cancel",
+                    stale.order_id,
+                    stale.status,
+                    stale.pending_for
+                );
+                self.world
+                    .order_tracker
+                    .reissue_cancel_order(&stale.order_id, now);
+                comms.publish(
+                    &self.write_order_handle,
+                    Message {
+                        header: MessageHeader { commit_at: now },
+                        payload: Payload::CancelOrderRequest(CancelOrderRequest {
+                            symbol: self.mm_strategy.symbol,
+                            client_order_id: client_order_id.clone(),
+                            owner: self.owner,
+                        }),
+                    },
+                );
+            }
+            comms.publish(
+                &self.write_control_handle,
+                Message {
+                    header: MessageHeader { commit_at: now },
+                    payload: Payload::StaleOrderReport(StaleOrderReport {
+                        symbol: self.mm_strategy.symbol,
+                        client_order_id,
+                        pending_for: stale.pending_for,
+                        retries: stale.retries,
+                        errored,
+                    }),
+                },
+            );
+        }
+    }
 
-    fn next_iteration_start_at(&self) -> Option<std::time::SystemTime> {
-        None
+    // The market data and signals reach the strategy as they are published, or a feed latency
+    // later with the look-ahead check. The check then only fails on data published before it
+    // happened, which no feed could deliver.
+    fn receive_feed(&mut self, msg: Message) {
+        let Some(latency) = self.world.look_ahead_latency else {
+            self.ingest_feed(msg);
+            return;
+        };
+        let due = msg.header.commit_at + latency;
+        let i = self.delayed_feed.partition_point(|(at, _)| *at <= due);
+        self.delayed_feed.insert(i, (due, msg));
     }
 
-    fn wake_on_message(&self) -> bool {
-        true
+    fn ingest_feed(&mut self, msg: Message) {
+        if matches!(msg.payload, Payload::SignalUpdate(_)) {
+            self.ingest_message(msg);
+            return;
+        }
+        self.world.update_market(&msg.payload);
+        // the market data of the referenced symbols only updates their books
+        if msg
+            .payload
+            .symbol()
+            .is_none_or(|symbol| symbol == self.mm_strategy.symbol)
+        {
+            self.ingest_message(msg);
+        }
     }
 
-    fn terminate(&mut self) {
-        self.mm_strategy.terminate();
+    // sequence number of an exchange message of our symbol, None when not sequenced
+    fn sequence_number(&self, msg: &Message) -> Option<u64> {
+        let seq = match &msg.payload {
+            Payload::OrderResult(result) => result.seq,
+            Payload::AccountUpdate(update) if update.symbol == Some(self.mm_strategy.symbol) => {
+                update.seq
+            }
+            Payload::ResyncSnapshot(snapshot) => snapshot.seq,
+            _ => return None,
+        };
+        (seq > 0).then_some(seq)
+    }
+
+    fn ingest_exchange_message(&mut self, msg: Message) {
+        // the answers of a batch are checked and applied one by one
+        if let Payload::OrderResultBatch(batch) = msg.payload {
+            for result in batch.results {
+                self.ingest_exchange_message(Message {
+                    header: msg.header.clone(),
+                    payload: Payload::OrderResult(result),
+                });
+            }
+            for reject in batch.cancel_rejects {
+                self.ingest_message(Message {
+                    header: msg.header.clone(),
+                    payload: Payload::CancelReject(reject),
+                });
+            }
+            return;
+        }
+        let is_snapshot = matches!(msg.payload, Payload::ResyncSnapshot(_));
+        if let Some(seq) = self.sequence_number(&msg).filter(|_| !is_snapshot) {
+            match self.sequence.check(seq) {
+                Sequenced::Next => {}
+                Sequenced::Gap => {
+                    tracing::warn!(
+                        "missed messages of {} before {}, resyncing",
+                        self.mm_strategy.symbol,
+                        seq
+                    );
+                    return;
+                }
+                Sequenced::Stale | Sequenced::Resyncing => return,
+            }
+        }
+        self.ingest_message(msg);
+    }
+
+    // rebuild the orders and balances from the exchange after missed messages
+    fn apply_snapshot(&mut self, snapshot: ResyncSnapshot) {
+        if !self.sequence.on_snapshot(snapshot.seq) {
+            return;
+        }
+        tracing::info!(
+            "resynced {} after {}: {} open orders",
+            snapshot.symbol,
+            snapshot.seq,
+            snapshot.open_orders.len()
+        );
+        self.apply_account_update(&snapshot.account);
+        let tracker = &mut self.world.order_tracker;
+        for order in &snapshot.open_orders {
+            tracker.update_fill_quantity(&order.client_order_id, order.filled);
+            tracker.update_status(
+                &order.client_order_id,
+                if order.filled > 0.0 {
+                    order_tracker::OrderStatus::PartiallyFilled
+                } else {
+                    order_tracker::OrderStatus::Open
+                },
+            );
+        }
+        // gone from the exchange, they were filled or cancelled while messages were lost.
+        // Orders still requested may be on their way to it.
+        let gone: Vec<String> = tracker
+            .iter()
+            .filter(|order| {
+                matches!(
+                    order.status,
+                    order_tracker::OrderStatus::Open
+                        | order_tracker::OrderStatus::PartiallyFilled
+                        | order_tracker::OrderStatus::CancelRequested
+                ) && !snapshot
+                    .open_orders
+                    .iter()
+                    .any(|open| *open.client_order_id == *order.order_id)
+            })
+            .map(|order| order.order_id.clone())
+            .collect();
+        for order_id in gone {
+            tracker.update_status(&order_id, order_tracker::OrderStatus::Canceled);
+        }
+    }
+
+    fn apply_account_update(&mut self, update: &AccountUpdate) {
+        update.updates.iter().for_each(|(asset, updated_balance)| {
+            let entry = self
+                .world
+                .account
+                .asset_to_balance
+                .entry(asset)
+                .or_default();
+            entry.balance = updated_balance.balance;
+            entry.locked = updated_balance.locked;
+        });
     }
-}
 
-impl Stepper {
     fn ingest_message(&mut self, data: upstair_type::Message) {
         match data.payload {
             BinanceTradeTick(data) => {
                 self.world.latest_market_price = data.price;
+                self.world.data_times.trade = self.world.data_times.trade.max(data.time);
                 self.world.trade_buf.push(data);
             }
+            Payload::TradeTickBatch(ticks) => {
+                if let Some(tick) = ticks.last() {
+                    self.world.latest_market_price = tick.price;
+                    self.world.data_times.trade = self.world.data_times.trade.max(tick.time);
+                }
+                self.world.trade_buf.extend(ticks);
+            }
+            Payload::BinanceAggTrade(trade) => {
+                self.world.latest_market_price = trade.price;
+                self.world.data_times.trade = self.world.data_times.trade.max(trade.time);
+                self.world.trade_buf.push(trade.to_trade_tick());
+            }
+            Payload::BinanceKline(kline) => {
+                self.world.latest_market_price = kline.close;
+                self.world.data_times.trade = self.world.data_times.trade.max(kline.close_time);
+            }
+            Payload::MarkPrice(mark) => {
+                self.world.mark_price = Some(mark.mark_price);
+                self.world.basis = mark.basis();
+                self.world.data_times.mark_price = self.world.data_times.mark_price.max(mark.time);
+            }
             Payload::OrderRequest(_) => {}
-            Payload::CancelOrderRequest(_) => {
-                unimplemented!("cacnel rsp")
+            Payload::CancelOrderRequest(_) => {}
+            Payload::BatchOrderRequest(_) => {}
+            Payload::BatchCancelRequest(_) => {}
+            Payload::OrderResultBatch(_) => {}
+            Payload::CancelReject(reject) => {
+                tracing::debug!(
+                    "cancel of {} rejected: {:?}",
+                    reject.client_order_id,
+                    reject.reason
+                );
+                self.world
+                    .order_tracker
+                    .reject_cancel(&reject.client_order_id, reject.reason);
+                self.world
+                    .cancel_reject_buf
+                    .push((reject.client_order_id.as_ref().into(), reject.reason));
             }
+            Payload::RateLimitUsage(usage) => self.world.rate_limit_usage = Some(usage),
             Payload::OrderResult(order_result) => {
                 let order_tracking_status: order_tracker::OrderStatus = match order_result.status {
                     order::OrderStatus::New => order_tracker::OrderStatus::Open,
@@ -152,6 +667,7 @@ impl Stepper {
                     order::OrderStatus::Filled => order_tracker::OrderStatus::Filled,
                     order::OrderStatus::Canceled => order_tracker::OrderStatus::Canceled,
                     order::OrderStatus::Rejected => order_tracker::OrderStatus::Canceled,
+                    order::OrderStatus::RateLimited => order_tracker::OrderStatus::Canceled,
                     order::OrderStatus::Expired => order_tracker::OrderStatus::Canceled,
                     order::OrderStatus::ExpiredInMatch => order_tracker::OrderStatus::Canceled,
                 };
@@ -163,40 +679,60 @@ impl Stepper {
                     order_result.client_order_id.as_ref().into(),
                     order_result.filled_quantity,
                 ));
+                if let Some(reason) = order_result.reject_reason {
+                    tracing::debug!(
+                        "order {} rejected: {:?}",
+                        order_result.client_order_id,
+                        reason
+                    );
+                    self.world
+                        .reject_buf
+                        .push((order_result.client_order_id.as_ref().into(), reason));
+                }
                 self.world
                     .order_tracker
                     .update_status(&order_result.client_order_id, order_tracking_status);
             }
-            Payload::AccountUpdate(update) => {
-                update.updates.iter().for_each(|(asset, updated_balance)| {
-                    let entry = self
-                        .world
-                        .account
-                        .asset_to_balance
-                        .entry(asset)
-                        .or_default();
-                    entry.balance = updated_balance.balance;
-                    entry.locked = updated_balance.locked;
-                });
+            Payload::AccountUpdate(update) => self.apply_account_update(&update),
+            Payload::TradingHalt(halt) => {
+                tracing::warn!("trading halted: {}", halt.reason);
+                self.halted = true;
             }
+            Payload::StaleOrderReport(_) => {}
+            Payload::ResyncRequest(_) => {}
+            Payload::StrategyDebug(_) => {}
+            Payload::DayRoll(_) => self.mm_strategy.on_day_roll(),
+            Payload::EquitySnapshot(_) => {}
+            Payload::DebugLog(_) => {}
+            Payload::DataQuality(quality) => self.world.data_quality = quality.flags,
+            Payload::SignalUpdate(signal) => {
+                self.world.signals.insert(signal.kind, signal.value);
+                self.world.data_times.signal = data
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+            }
+            Payload::ResyncSnapshot(snapshot) => self.apply_snapshot(snapshot),
             Payload::BinanceBookTicker(book_ticker) => {
                 self.world.booker_tick_updated_at = self.world.now;
-                self.world.best_ask_price = book_ticker.best_ask_price;
-                self.world.best_ask_qty = book_ticker.best_ask_qty;
-                self.world.best_bid_price = book_ticker.best_bid_price;
-                self.world.best_bid_qty = book_ticker.best_bid_qty;
+                self.world.data_times.book = book_ticker.event_time;
+                self.book_updated = true;
+                // the book itself is in the markets of the world, see ingest_feed
 
                 let wap = (book_ticker.best_ask_price * book_ticker.best_bid_qty
                     + book_ticker.best_bid_price * book_ticker.best_ask_qty)
                     / (book_ticker.best_ask_qty + book_ticker.best_bid_qty);
-                self.world.wap_buf.push((
-                    data.header
-                        .commit_at
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                    wap,
-                ));
+                let mid = (book_ticker.best_ask_price + book_ticker.best_bid_price) / 2.0;
+                let time = data
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64;
+                self.world.wap_buf.push((time, wap));
+                self.world.mid_buf.push((time, mid));
             }
         }
     }
@@ -207,7 +743,40 @@ pub struct StepperBuilder {
     order_result_topic: Option<ReadTopicHandle>,
     order_topic: Option<WriteTopicHandle>,
     account_topic: Option<ReadTopicHandle>,
+    control_topic: Option<ReadTopicHandle>,
+    signals_topic: Option<ReadTopicHandle>,
+    control_write_topic: Option<WriteTopicHandle>,
+    strategy_debug_topic: Option<WriteTopicHandle>,
+    debug_log_topic: Option<WriteTopicHandle>,
     symbol_info_manager: Option<SymbolInfoManager>,
+    pricing_model: pure_market_maker::PricingModel,
+    fair_price_source: pure_market_maker::FairPriceSource,
+    vol_price_source: pure_market_maker::VolPriceSource,
+    vol_estimator: pure_market_maker::vol_estimator::VolEstimator,
+    vol_gap: (pure_market_maker::vol_estimator::VolGapHandling, Duration),
+    vol_warm_up: bool,
+    quote_anchoring: pure_market_maker::QuoteAnchoring,
+    price_tick: f64,
+    quote_sizing: pure_market_maker::quote_sizing::QuoteSizing,
+    lot_size: f64,
+    quote_tolerance: Option<pure_market_maker::QuoteTolerance>,
+    inventory_limits: Option<pure_market_maker::InventoryLimits>,
+    degraded_data: pure_market_maker::DegradedDataResponse,
+    reconcile: Option<ReconcileConfig>,
+    state_history_interval: Option<Duration>,
+    output_dir: Option<PathBuf>,
+    session: SessionCalendar,
+    flatten_on_shutdown: bool,
+    debug_log: bool,
+    decision_trigger: DecisionTrigger,
+    owner: Option<&'static str>,
+    batch_orders: bool,
+    exchange_expiry: bool,
+    // the symbols besides ours whose books the strategy reads
+    reference_symbols: Vec<&'static str>,
+    plugin: Option<PluginStrategy>,
+    bridge: Option<GrpcBridge>,
+    look_ahead_latency: Option<Duration>,
 
     symbol: &'static str,
 }
@@ -219,7 +788,42 @@ impl StepperBuilder {
             order_result_topic: None,
             order_topic: None,
             account_topic: None,
+            control_topic: None,
+            signals_topic: None,
+            control_write_topic: None,
+            strategy_debug_topic: None,
+            debug_log_topic: None,
             symbol_info_manager: None,
+            pricing_model: pure_market_maker::PricingModel::default(),
+            fair_price_source: pure_market_maker::FairPriceSource::default(),
+            vol_price_source: pure_market_maker::VolPriceSource::default(),
+            vol_estimator: pure_market_maker::vol_estimator::VolEstimator::default(),
+            vol_gap: (
+                pure_market_maker::vol_estimator::VolGapHandling::default(),
+                Duration::from_secs(60),
+            ),
+            vol_warm_up: false,
+            quote_anchoring: pure_market_maker::QuoteAnchoring::default(),
+            price_tick: 0.1,
+            quote_sizing: pure_market_maker::quote_sizing::QuoteSizing::default(),
+            lot_size: 0.001,
+            quote_tolerance: None,
+            inventory_limits: None,
+            degraded_data: pure_market_maker::DegradedDataResponse::default(),
+            reconcile: None,
+            state_history_interval: None,
+            output_dir: None,
+            session: SessionCalendar::default(),
+            flatten_on_shutdown: false,
+            debug_log: false,
+            decision_trigger: DecisionTrigger::default(),
+            owner: None,
+            batch_orders: false,
+            exchange_expiry: false,
+            reference_symbols: vec![],
+            plugin: None,
+            bridge: None,
+            look_ahead_latency: None,
             symbol,
         }
     }
@@ -228,6 +832,239 @@ impl StepperBuilder {
         self.symbol_info_manager = Some(symbol_info_manager);
         self
     }
+
+    pub fn with_pricing_model(mut self, pricing_model: pure_market_maker::PricingModel) -> Self {
+        self.pricing_model = pricing_model;
+        self
+    }
+
+    pub fn with_fair_price_source(mut self, source: pure_market_maker::FairPriceSource) -> Self {
+        self.fair_price_source = source;
+        self
+    }
+
+    pub fn with_vol_price_source(mut self, source: pure_market_maker::VolPriceSource) -> Self {
+        self.vol_price_source = source;
+        self
+    }
+
+    pub fn with_vol_estimator(
+        mut self,
+        estimator: pure_market_maker::vol_estimator::VolEstimator,
+    ) -> Self {
+        self.vol_estimator = estimator;
+        self
+    }
+
+    pub fn with_vol_gap_handling(
+        mut self,
+        handling: pure_market_maker::vol_estimator::VolGapHandling,
+        gap: Duration,
+    ) -> Self {
+        self.vol_gap = (handling, gap);
+        self
+    }
+
+    pub fn with_vol_warm_up(mut self, warm_up: bool) -> Self {
+        self.vol_warm_up = warm_up;
+        self
+    }
+
+    pub fn with_quote_anchoring(mut self, anchoring: pure_market_maker::QuoteAnchoring) -> Self {
+        self.quote_anchoring = anchoring;
+        self
+    }
+
+    pub fn with_price_tick(mut self, price_tick: f64) -> Self {
+        self.price_tick = price_tick;
+        self
+    }
+
+    pub fn with_quote_sizing(
+        mut self,
+        sizing: pure_market_maker::quote_sizing::QuoteSizing,
+    ) -> Self {
+        self.quote_sizing = sizing;
+        self
+    }
+
+    pub fn with_lot_size(mut self, lot_size: f64) -> Self {
+        self.lot_size = lot_size;
+        self
+    }
+
+    pub fn with_quote_tolerance(
+        mut self,
+        tolerance: Option<pure_market_maker::QuoteTolerance>,
+    ) -> Self {
+        self.quote_tolerance = tolerance;
+        self
+    }
+
+    // quotes expire on the exchange as good til date orders instead of being canceled
+    pub fn with_exchange_expiry(mut self, exchange_expiry: bool) -> Self {
+        self.exchange_expiry = exchange_expiry;
+        self
+    }
+
+    pub fn with_inventory_limits(
+        mut self,
+        limits: Option<pure_market_maker::InventoryLimits>,
+    ) -> Self {
+        self.inventory_limits = limits;
+        self
+    }
+
+    pub fn with_degraded_data_response(
+        mut self,
+        response: pure_market_maker::DegradedDataResponse,
+    ) -> Self {
+        self.degraded_data = response;
+        self
+    }
+
+    // snapshot the orders, balances and strategy variables every interval of simulated time,
+    // written as Parquet files to the run output on terminate
+    pub fn with_state_history(mut self, interval: Duration) -> Self {
+        self.state_history_interval = Some(interval);
+        self
+    }
+
+    // quote only inside the windows of the calendar, the orders are cancelled outside
+    pub fn with_session(mut self, session: SessionCalendar) -> Self {
+        self.session = session;
+        self
+    }
+
+    // on shutdown take the book for the inventory away from target besides cancelling
+    pub fn with_flatten_on_shutdown(mut self, flatten: bool) -> Self {
+        self.flatten_on_shutdown = flatten;
+        self
+    }
+
+    // publish the vol updates, quotes and fills of the strategy on the debug_log topic
+    pub fn with_debug_log(mut self, debug_log: bool) -> Self {
+        self.debug_log = debug_log;
+        self
+    }
+
+    // decide with the strategy of a plugin instead of the market maker, see
+    // StrategyPlugin::create
+    pub fn with_plugin(mut self, plugin: PluginStrategy) -> Self {
+        self.plugin = Some(plugin);
+        self
+    }
+
+    // decide with the strategy served over gRPC instead of the market maker, see
+    // GrpcBridge::connect
+    pub fn with_grpc_bridge(mut self, bridge: GrpcBridge) -> Self {
+        self.bridge = Some(bridge);
+        self
+    }
+
+    pub fn with_reconcile(mut self, reconcile: Option<ReconcileConfig>) -> Self {
+        self.reconcile = reconcile;
+        self
+    }
+
+    // minimum engine time between two decisions of the strategy, 100ms by default
+    pub fn with_decision_interval(mut self, interval: Duration) -> Self {
+        self.decision_trigger = DecisionTrigger::Interval(interval);
+        self
+    }
+
+    // the market data and signals reach the strategy the feed latency after they are
+    // published, and a decision reading data newer than that panics, see
+    // StepperWorld::check_look_ahead
+    pub fn with_look_ahead_check(mut self, feed_latency: Duration) -> Self {
+        self.look_ahead_latency = Some(feed_latency);
+        self
+    }
+
+    pub fn with_decision_trigger(mut self, trigger: DecisionTrigger) -> Self {
+        self.decision_trigger = trigger;
+        self
+    }
+
+    // trade the account the market agent keeps for owner instead of the shared one
+    pub fn with_owner(mut self, owner: &'static str) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    // follow the book of symbol too, see StepperWorld::markets
+    pub fn with_reference_symbol(mut self, symbol: &'static str) -> Self {
+        self.reference_symbols.push(symbol);
+        self
+    }
+
+    // send the orders and cancels of a decision in batch requests, as the batchOrders endpoint
+    pub fn with_batch_orders(mut self, batch_orders: bool) -> Self {
+        self.batch_orders = batch_orders;
+        self
+    }
+
+    // the stepper on the topics of init_comm
+    fn into_stepper(self) -> Stepper {
+        let state_history = self.state_history_interval.map(|interval| {
+            let dir = self
+                .output_dir
+                .clone()
+                .expect("the state history needs the run output of the engine");
+            StateHistory::new(dir, interval)
+        });
+        Stepper {
+            read_market_data_handle: self.market_data_topic.unwrap(),
+            read_order_result_handle: self.order_result_topic.unwrap(),
+            write_order_handle: self.order_topic.unwrap(),
+            read_account_handle: self.account_topic.unwrap(),
+            read_control_handle: self.control_topic.unwrap(),
+            read_signals_handle: self.signals_topic.unwrap(),
+            write_control_handle: self.control_write_topic.unwrap(),
+            write_strategy_debug_handle: self.strategy_debug_topic.unwrap(),
+            write_debug_log_handle: self.debug_log_topic.unwrap(),
+            world: stepper_world::StepperWorld {
+                look_ahead_latency: self.look_ahead_latency,
+                ..Default::default()
+            },
+            last_iteration_time: SystemTime::UNIX_EPOCH,
+            decision_trigger: self.decision_trigger,
+            book_updated: false,
+            mm_strategy: pure_market_maker::AmmStrategy::new(
+                self.symbol,
+                self.symbol_info_manager.clone().unwrap(),
+            )
+            .with_pricing_model(self.pricing_model)
+            .with_fair_price_source(self.fair_price_source)
+            .with_vol_price_source(self.vol_price_source)
+            .with_vol_estimator(self.vol_estimator)
+            .with_vol_gap_handling(self.vol_gap.0, self.vol_gap.1)
+            .with_vol_warm_up(self.vol_warm_up)
+            .with_quote_anchoring(self.quote_anchoring)
+            .with_price_tick(self.price_tick)
+            .with_quote_sizing(self.quote_sizing)
+            .with_lot_size(self.lot_size)
+            .with_quote_tolerance(self.quote_tolerance)
+            .with_exchange_expiry(self.exchange_expiry)
+            .with_inventory_limits(self.inventory_limits)
+            .with_degraded_data_response(self.degraded_data)
+            .with_debug_log(self.debug_log),
+            plugin: self.plugin,
+            bridge: self.bridge,
+            halted: false,
+            shut_down: false,
+            flatten_on_shutdown: self.flatten_on_shutdown,
+            session: self.session,
+            session_open: true,
+            reconcile: self.reconcile,
+            sequence: SequenceTracker::default(),
+            state_history,
+            owner: self.owner,
+            batch_orders: self.batch_orders,
+            delayed_feed: VecDeque::new(),
+            symbol_info: self.symbol_info_manager.unwrap(),
+        }
+    }
 }
 
 impl ModuleBuilder for StepperBuilder {
@@ -235,31 +1072,431 @@ impl ModuleBuilder for StepperBuilder {
         "stepper"
     }
 
+    fn init_output(&mut self, output: &RunOutput) {
+        self.output_dir = Some(output.dir().to_path_buf());
+    }
+
     fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
         let market_data_topic = comms.get_topic("market_data");
         let order_result_topic = comms.get_topic("order_result");
         let order_topic = comms.get_topic("order");
         let account_topic = comms.get_topic("account");
+        let control_topic = comms.get_topic("control");
+        let strategy_debug_topic = comms.get_topic("strategy_debug");
+        let signals_topic = comms.get_topic("signals");
+        let debug_log_topic = comms.get_topic("debug_log");
 
-        self.market_data_topic = comms.subscribe_topic(&market_data_topic).into();
-        self.order_result_topic = comms.subscribe_topic(&order_result_topic).into();
+        // only the ticks of our symbol and the referenced ones
+        let symbols = std::iter::once(self.symbol)
+            .chain(self.reference_symbols.iter().copied())
+            .collect();
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbols_filter(symbols))
+            .into();
+        // only the results and balances of our orders
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(
+                &order_result_topic,
+                and_filter(symbol_filter(self.symbol), owner_filter(self.owner)),
+            )
+            .into();
         self.order_topic = comms.publish_topic(&order_topic).into();
-        self.account_topic = comms.subscribe_topic(&account_topic).into();
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(self.owner))
+            .into();
+        self.control_topic = comms.subscribe_topic(&control_topic).into();
+        self.control_write_topic = comms.publish_topic(&control_topic).into();
+        self.signals_topic = comms
+            .subscribe_topic_filtered(&signals_topic, symbol_filter(self.symbol))
+            .into();
+        self.strategy_debug_topic = comms.publish_topic(&strategy_debug_topic).into();
+        self.debug_log_topic = comms.publish_topic(&debug_log_topic).into();
     }
 
     fn build(self: Box<StepperBuilder>) -> Box<dyn Module> {
-        Box::new(Stepper {
-            read_market_data_handle: self.market_data_topic.unwrap(),
-            read_order_result_handle: self.order_result_topic.unwrap(),
-            write_order_handle: self.order_topic.unwrap(),
-            read_account_handle: self.account_topic.unwrap(),
-            world: stepper_world::StepperWorld::default(),
-            last_iteration_time: SystemTime::UNIX_EPOCH,
-            mm_strategy: pure_market_maker::AmmStrategy::new(
-                self.symbol,
-                self.symbol_info_manager.clone().unwrap(),
-            ),
-            symbol_info: self.symbol_info_manager.unwrap(),
-        })
+        Box::new(self.into_stepper())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use simulation::engine::SimulationEngineBuilder;
+    use simulation::simulation::SimulationCommsSystem;
+    use upstair_type::account::AccountAssetUpdate;
+    use upstair_type::control::DayRoll;
+    use upstair_type::data::market::{BinanceBookTicker, BinanceTradeTick};
+    use upstair_type::module::CommsSystem;
+    use upstair_type::module::{ModuleComms, ModuleCommsBuilder};
+
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(5);
+    const END: Duration = Duration::from_secs(2);
+
+    // the balances, then a book ticker and a trade of BTCUSDT every TICK published at the time
+    // they happened, the book tickers stamped peek ahead of it
+    struct FeedBuilder {
+        peek: Duration,
+        topics: Option<(WriteTopicHandle, WriteTopicHandle)>,
+    }
+
+    struct Feed {
+        peek: Duration,
+        market_data: WriteTopicHandle,
+        account: WriteTopicHandle,
+        next: Option<SystemTime>,
+    }
+
+    impl ModuleBuilder for FeedBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let market_data = comms.get_topic("market_data");
+            let account = comms.get_topic("account");
+            self.topics = Some((
+                comms.publish_topic(&market_data),
+                comms.publish_topic(&account),
+            ));
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            let (market_data, account) = self.topics.unwrap();
+            Box::new(Feed {
+                peek: self.peek,
+                market_data,
+                account,
+                next: Some(UNIX_EPOCH + TICK),
+            })
+        }
+
+        fn name(&self) -> &str {
+            "feed"
+        }
+    }
+
+    impl Module for Feed {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, _: &mut dyn ModuleComms) -> bool {
+            true
+        }
+
+        fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+            let now = comms.time();
+            let header = MessageHeader { commit_at: now };
+            if now == UNIX_EPOCH + TICK {
+                let balance = |balance| AccountAssetUpdate {
+                    balance,
+                    locked: 0.0,
+                };
+                let update = AccountUpdate {
+                    updates: vec![("BTC", balance(1.0)), ("USDT", balance(50000.0))],
+                    symbol: None,
+                    seq: 0,
+                    owner: None,
+                };
+                comms.publish(
+                    &self.account,
+                    Message {
+                        header: header.clone(),
+                        payload: Payload::AccountUpdate(update),
+                    },
+                );
+            }
+            let now_ms = now.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+            let price = 40000.0 + (now_ms % 100) as f64 / 10.0;
+            comms.publish(
+                &self.market_data,
+                Message {
+                    header: header.clone(),
+                    payload: Payload::BinanceBookTicker(BinanceBookTicker {
+                        update_id: now_ms,
+                        best_bid_price: price,
+                        best_bid_qty: 1.0,
+                        best_ask_price: price + 0.1,
+                        best_ask_qty: 1.0,
+                        transaction_time: now_ms,
+                        event_time: now_ms + self.peek.as_millis() as u64,
+                        symbol: "BTCUSDT",
+                    }),
+                },
+            );
+            comms.publish(
+                &self.market_data,
+                Message {
+                    header,
+                    payload: Payload::BinanceTradeTick(BinanceTradeTick {
+                        id: now_ms,
+                        price,
+                        qty: 0.01,
+                        base_qty: price * 0.01,
+                        time: now_ms,
+                        is_buyer_maker: false,
+                        symbol: "BTCUSDT",
+                    }),
+                },
+            );
+            self.next = Some(now + TICK).filter(|next| *next <= UNIX_EPOCH + END);
+        }
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            self.next
+        }
+
+        fn wake_on_message(&self) -> bool {
+            false
+        }
+    }
+
+    // the times of the order requests of the stepper
+    struct OrderRecorderBuilder {
+        orders: Rc<RefCell<Vec<SystemTime>>>,
+        topic: Option<ReadTopicHandle>,
+    }
+
+    struct OrderRecorder {
+        orders: Rc<RefCell<Vec<SystemTime>>>,
+        topic: ReadTopicHandle,
+    }
+
+    impl ModuleBuilder for OrderRecorderBuilder {
+        fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder) {
+            let order = comms.get_topic("order");
+            self.topic = Some(comms.subscribe_topic(&order));
+        }
+
+        fn build(self: Box<Self>) -> Box<dyn Module> {
+            Box::new(OrderRecorder {
+                orders: self.orders,
+                topic: self.topic.unwrap(),
+            })
+        }
+
+        fn name(&self) -> &str {
+            "order_recorder"
+        }
+    }
+
+    impl Module for OrderRecorder {
+        fn start(&mut self) {}
+
+        fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+            while let Some(msg) = comms.receive(&self.topic) {
+                if matches!(msg.payload, Payload::OrderRequest(_)) {
+                    self.orders.borrow_mut().push(msg.header.commit_at);
+                }
+            }
+            false
+        }
+
+        fn one_iteration(&mut self, _: &mut dyn ModuleComms) {}
+
+        fn next_iteration_start_at(&self) -> Option<SystemTime> {
+            None
+        }
+
+        fn wake_on_message(&self) -> bool {
+            true
+        }
+    }
+
+    // the times of the orders the stepper deciding on every book ticker placed
+    fn run_with_look_ahead_check(peek: Duration) -> Vec<SystemTime> {
+        let orders = Rc::new(RefCell::new(vec![]));
+        let stepper = StepperBuilder::new("BTCUSDT")
+            .with_symbol_info_manager(
+                SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+            )
+            .with_decision_trigger(DecisionTrigger::BookTicker)
+            .with_look_ahead_check(Duration::from_millis(20));
+        let mut engine = SimulationEngineBuilder::default()
+            .add_module(FeedBuilder { peek, topics: None })
+            .add_module(stepper)
+            .add_module(OrderRecorderBuilder {
+                orders: orders.clone(),
+                topic: None,
+            })
+            .build();
+        engine.run();
+        orders.take()
+    }
+
+    // a stepper of BTCUSDT on the topics of system, driven by hand, and its comms
+    fn stepper(
+        builder: StepperBuilder,
+        system: &SimulationCommsSystem,
+    ) -> (Stepper, Box<dyn ModuleComms>) {
+        let mut builder = builder.with_symbol_info_manager(
+            SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+        );
+        let mut comms = system.new_builder("stepper");
+        builder.init_comm(&mut comms);
+        (builder.into_stepper(), comms.build())
+    }
+
+    #[test]
+    fn test_day_roll_starts_a_new_day_of_the_strategy() {
+        let system = SimulationCommsSystem::default();
+        let (mut stepper, _) = stepper(StepperBuilder::new("BTCUSDT"), &system);
+        stepper.mm_strategy.event_count.insert("vol_warming_up", 3);
+        stepper
+            .mm_strategy
+            .day_event_count
+            .insert("vol_warming_up", 3);
+
+        stepper.ingest_message(Message {
+            header: MessageHeader {
+                commit_at: UNIX_EPOCH + Duration::from_secs(86400),
+            },
+            payload: Payload::DayRoll(DayRoll {
+                day_start: UNIX_EPOCH + Duration::from_secs(86400),
+            }),
+        });
+        // the events of the run are kept
+        assert!(stepper.mm_strategy.day_event_count.is_empty());
+        assert_eq!(
+            stepper.mm_strategy.event_count.get("vol_warming_up"),
+            Some(&3)
+        );
+    }
+
+    #[test]
+    fn test_referenced_symbol_only_updates_its_book() {
+        let system = SimulationCommsSystem::default();
+        let (mut stepper, _) = stepper(
+            StepperBuilder::new("BTCUSDT").with_reference_symbol("ETHUSDT"),
+            &system,
+        );
+        let message = |payload| Message {
+            header: MessageHeader {
+                commit_at: UNIX_EPOCH + Duration::from_secs(1),
+            },
+            payload,
+        };
+        let trade = |symbol, price| {
+            Payload::BinanceTradeTick(BinanceTradeTick {
+                price,
+                qty: 1.0,
+                time: 1000,
+                symbol,
+                ..Default::default()
+            })
+        };
+        let ticker = |symbol, bid, ask| {
+            Payload::BinanceBookTicker(BinanceBookTicker {
+                best_bid_price: bid,
+                best_bid_qty: 1.0,
+                best_ask_price: ask,
+                best_ask_qty: 1.0,
+                event_time: 1000,
+                symbol,
+                ..Default::default()
+            })
+        };
+        for payload in [
+            ticker("ETHUSDT", 1999.0, 2001.0),
+            trade("ETHUSDT", 2000.0),
+            ticker("BTCUSDT", 100.0, 101.0),
+            trade("BTCUSDT", 100.5),
+        ] {
+            stepper.ingest_feed(message(payload));
+        }
+
+        // the trades and prices of the strategy are those of its symbol
+        let trades: Vec<_> = stepper
+            .world
+            .trade_buf
+            .iter()
+            .map(|trade| (trade.symbol, trade.price))
+            .collect();
+        assert_eq!(trades, vec![("BTCUSDT", 100.5)]);
+        assert_eq!(stepper.world.latest_market_price, 100.5);
+        assert_eq!(stepper.world.wap_buf, vec![(1000, 100.5)]);
+        assert_eq!(stepper.world.book("BTCUSDT").best_bid_price, 100.0);
+        let eth = stepper.world.book("ETHUSDT");
+        assert_eq!((eth.best_bid_price, eth.last_price), (1999.0, 2000.0));
+    }
+
+    #[test]
+    fn test_batch_orders_in_chunks_cancels_first() {
+        let system = SimulationCommsSystem::default();
+        let mut exchange = system.new_builder("exchange");
+        let order_topic = exchange.get_topic("order");
+        let order_topic = exchange.subscribe_topic(&order_topic);
+        let mut exchange = exchange.build();
+        let (mut stepper, mut comms) = stepper(
+            StepperBuilder::new("BTCUSDT").with_batch_orders(true),
+            &system,
+        );
+
+        // 7 places and 6 cancels, interleaved
+        for i in 0..7 {
+            stepper
+                .mm_strategy
+                .actions
+                .push(pure_market_maker::Action::PlaceOrder(
+                    pure_market_maker::PlaceOrderData {
+                        symbol: "BTCUSDT",
+                        order_id: format!("P{}", i),
+                        price: 100.0,
+                        side: order::TradeSide::Buy,
+                        quantity: 0.01,
+                        trade_type: order::TradeType::Limit,
+                        time_in_force: order::TimeInForce::GoodTilCancelled,
+                    },
+                ));
+            if i < 6 {
+                stepper
+                    .mm_strategy
+                    .actions
+                    .push(pure_market_maker::Action::CancelOrder(
+                        pure_market_maker::CancelOrder {
+                            symbol: "BTCUSDT",
+                            order_id: format!("C{}", i),
+                        },
+                    ));
+            }
+        }
+        stepper.publish_actions(comms.as_mut());
+
+        let batches: Vec<Vec<String>> = std::iter::from_fn(|| exchange.receive(&order_topic))
+            .map(|msg| match msg.payload {
+                Payload::BatchCancelRequest(batch) => batch
+                    .cancels
+                    .iter()
+                    .map(|c| c.client_order_id.to_string())
+                    .collect(),
+                Payload::BatchOrderRequest(batch) => batch
+                    .orders
+                    .iter()
+                    .map(|o| o.client_order_id.to_string())
+                    .collect(),
+                payload => panic!("not a batch: {:?}", payload),
+            })
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec!["C0", "C1", "C2", "C3", "C4"],
+                vec!["C5"],
+                vec!["P0", "P1", "P2", "P3", "P4"],
+                vec!["P5", "P6"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_look_ahead_check_on_a_dense_feed() {
+        // every decision is within the feed latency of the last tick, which it does not see yet
+        let orders = run_with_look_ahead_check(Duration::ZERO);
+        assert!(!orders.is_empty());
+        // the first book ticker at 5ms reaches the strategy 20ms later
+        assert!(orders[0] >= UNIX_EPOCH + Duration::from_millis(25));
+    }
+
+    #[test]
+    #[should_panic(expected = "look-ahead: the strategy reads a book ticker")]
+    fn test_look_ahead_check_on_data_ahead_of_its_time() {
+        run_with_look_ahead_check(Duration::from_millis(50));
     }
 }
@@ -1,4 +1,5 @@
 pub mod order_tracker;
+pub mod sequence;
 pub mod stepper_world;
 
-pub use stepper_world::StepperWorld;
+pub use stepper_world::{MarketSnapshot, StepperWorld};
@@ -1,10 +1,10 @@
 use std::{
     collections::{HashMap, HashSet},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
-use upstair_type::order::TradeSide;
+use upstair_type::order::{CancelRejectReason, TradeSide};
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum OrderStatus {
     OpenRequested,
     Open,
@@ -12,6 +12,8 @@ pub enum OrderStatus {
     Filled,
     CancelRequested,
     Canceled,
+    // gave up on a request the exchange never answered
+    Errored,
 }
 
 #[derive(Debug)]
@@ -25,10 +27,34 @@ pub struct Order {
     pub created_at: SystemTime,
 }
 
+impl Order {
+    pub fn remaining_quantity(&self) -> f64 {
+        self.quantity - self.filled
+    }
+}
+
+// A request sent to the exchange which is not answered yet
+#[derive(Debug, Clone, Copy)]
+struct PendingRequest {
+    since: SystemTime,
+    // cancels re-issued since the first request
+    retries: u32,
+}
+
+// An order stuck in OpenRequested or CancelRequested
+#[derive(Debug, PartialEq)]
+pub struct StaleOrder {
+    pub order_id: String,
+    pub status: OrderStatus,
+    pub pending_for: Duration,
+    pub retries: u32,
+}
+
 #[derive(Debug, Default)]
 pub struct OrderTracker {
     orders: HashMap<String, Order>,
     proceed_unique_fill_report_id: HashSet<String>,
+    pending_requests: HashMap<String, PendingRequest>,
 }
 
 impl OrderTracker {
@@ -42,6 +68,17 @@ impl OrderTracker {
     pub fn upsert_order(&mut self, order: Order) -> bool {
         let order_id = order.order_id.clone();
         let is_new_order = !self.orders.contains_key(&order_id);
+        if order.status == OrderStatus::OpenRequested {
+            self.pending_requests.insert(
+                order_id.clone(),
+                PendingRequest {
+                    since: order.created_at,
+                    retries: 0,
+                },
+            );
+        } else {
+            self.pending_requests.remove(&order_id);
+        }
         self.orders.insert(order_id, order);
         is_new_order
     }
@@ -73,31 +110,140 @@ impl OrderTracker {
 
     pub fn update_status(&mut self, order_id: &str, status: OrderStatus) {
         if let Some(order) = self.orders.get_mut(order_id) {
+            // a late ack or a fill does not answer the cancel request
+            if order.status == OrderStatus::CancelRequested
+                && matches!(status, OrderStatus::Open | OrderStatus::PartiallyFilled)
+            {
+                return;
+            }
             order.status = status;
+            if !matches!(
+                status,
+                OrderStatus::OpenRequested | OrderStatus::CancelRequested
+            ) {
+                self.pending_requests.remove(order_id);
+            }
         }
     }
 
     pub fn remove_terminated_orders(&mut self) {
         self.orders.retain(|_, order| {
-            order.status != OrderStatus::Canceled && order.status != OrderStatus::Filled
+            !matches!(
+                order.status,
+                OrderStatus::Canceled | OrderStatus::Filled | OrderStatus::Errored
+            )
         });
+        let orders = &self.orders;
+        self.pending_requests
+            .retain(|order_id, _| orders.contains_key(order_id));
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Order> {
         self.orders.values()
     }
 
+    // orders on the side which may still be filled and are not being cancelled
+    pub fn live_orders<'a>(&'a self, side: &'a TradeSide) -> impl Iterator<Item = &'a Order> {
+        self.orders.values().filter(move |order| {
+            order.side == *side
+                && matches!(
+                    order.status,
+                    OrderStatus::OpenRequested | OrderStatus::Open | OrderStatus::PartiallyFilled
+                )
+        })
+    }
+
     pub fn cancel_order(&mut self, order_id: &str) {
         // remove the order
         self.orders.remove(order_id);
+        self.pending_requests.remove(order_id);
     }
 
-    pub fn request_cancel_order(&mut self, order_id: &str) {
+    pub fn request_cancel_order(&mut self, order_id: &str, at: SystemTime) {
         if let Some(order) = self.orders.get_mut(order_id) {
             order.status = OrderStatus::CancelRequested;
+            self.pending_requests
+                .entry(order_id.to_string())
+                .and_modify(|pending| pending.since = at)
+                .or_insert(PendingRequest {
+                    since: at,
+                    retries: 0,
+                });
+        }
+    }
+
+    // the exchange did not cancel the order, it kept it or had none of the id
+    pub fn reject_cancel(&mut self, order_id: &str, reason: CancelRejectReason) {
+        let Some(order) = self.orders.get_mut(order_id) else {
+            return;
+        };
+        if order.status != OrderStatus::CancelRequested {
+            return;
+        }
+        order.status = match reason {
+            // still waiting for the ack of the order, a later cancel takes it
+            CancelRejectReason::NotYetPlaced => {
+                order.status = OrderStatus::OpenRequested;
+                return;
+            }
+            CancelRejectReason::UnknownOrder => OrderStatus::Canceled,
+            CancelRejectReason::UnknownSymbol | CancelRejectReason::NotOwner => {
+                OrderStatus::Errored
+            }
+            // still on the exchange, a later cancel takes it, or the fill on its way
+            // closes it
+            CancelRejectReason::RateLimited | CancelRejectReason::AlreadyFilled
+                if order.filled > 0.0 =>
+            {
+                OrderStatus::PartiallyFilled
+            }
+            CancelRejectReason::RateLimited | CancelRejectReason::AlreadyFilled => {
+                OrderStatus::Open
+            }
+        };
+        self.pending_requests.remove(order_id);
+    }
+
+    // cancel again an order whose request got no answer
+    pub fn reissue_cancel_order(&mut self, order_id: &str, at: SystemTime) {
+        self.request_cancel_order(order_id, at);
+        if let Some(pending) = self.pending_requests.get_mut(order_id) {
+            pending.retries += 1;
         }
     }
 
+    // stop waiting for an answer, the order is dropped with the terminated ones
+    pub fn mark_errored(&mut self, order_id: &str) {
+        if let Some(order) = self.orders.get_mut(order_id) {
+            order.status = OrderStatus::Errored;
+        }
+        self.pending_requests.remove(order_id);
+    }
+
+    // orders waiting longer than timeout for an answer, oldest first
+    pub fn stale_orders(&self, now: SystemTime, timeout: Duration) -> Vec<StaleOrder> {
+        let mut stale: Vec<StaleOrder> = self
+            .pending_requests
+            .iter()
+            .filter_map(|(order_id, pending)| {
+                let pending_for = now.duration_since(pending.since).unwrap_or_default();
+                let order = self.orders.get(order_id)?;
+                (pending_for > timeout).then(|| StaleOrder {
+                    order_id: order_id.clone(),
+                    status: order.status,
+                    pending_for,
+                    retries: pending.retries,
+                })
+            })
+            .collect();
+        stale.sort_by(|a, b| {
+            b.pending_for
+                .cmp(&a.pending_for)
+                .then_with(|| a.order_id.cmp(&b.order_id))
+        });
+        stale
+    }
+
     pub fn size(&self) -> usize {
         self.orders.len()
     }
@@ -191,4 +337,153 @@ mod tests {
         order_tracker.cancel_order("test");
         assert_eq!(order_tracker.orders.len(), 0);
     }
+
+    #[test]
+    fn test_live_orders() {
+        let mut order_tracker = OrderTracker::default();
+        for (order_id, side, status) in [
+            ("b1", TradeSide::Buy, OrderStatus::Open),
+            ("b2", TradeSide::Buy, OrderStatus::CancelRequested),
+            ("b3", TradeSide::Buy, OrderStatus::PartiallyFilled),
+            ("s1", TradeSide::Sell, OrderStatus::Open),
+        ] {
+            order_tracker.upsert_order(Order {
+                order_id: order_id.into(),
+                price: 0.0,
+                side,
+                quantity: 1.5,
+                filled: 0.5,
+                status,
+                created_at: SystemTime::UNIX_EPOCH,
+            });
+        }
+        let mut live = order_tracker
+            .live_orders(&TradeSide::Buy)
+            .map(|order| order.order_id.as_str())
+            .collect::<Vec<_>>();
+        live.sort();
+        assert_eq!(live, vec!["b1", "b3"]);
+        assert_eq!(
+            order_tracker.get_order("b1").unwrap().remaining_quantity(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_stale_orders() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut order_tracker = OrderTracker::default();
+        for (order_id, status) in [
+            ("b1", OrderStatus::OpenRequested),
+            ("b2", OrderStatus::OpenRequested),
+            ("b3", OrderStatus::Open),
+        ] {
+            order_tracker.upsert_order(Order {
+                order_id: order_id.into(),
+                price: 0.0,
+                side: TradeSide::Buy,
+                quantity: 1.0,
+                filled: 0.0,
+                status,
+                created_at: at(0),
+            });
+        }
+        // b1 is acknowledged, b3 cancel is requested later
+        order_tracker.update_status("b1", OrderStatus::Open);
+        order_tracker.request_cancel_order("b3", at(5));
+        assert!(order_tracker
+            .stale_orders(at(3), Duration::from_secs(4))
+            .is_empty());
+
+        let stale = order_tracker.stale_orders(at(6), Duration::from_secs(4));
+        assert_eq!(
+            stale,
+            vec![StaleOrder {
+                order_id: "b2".into(),
+                status: OrderStatus::OpenRequested,
+                pending_for: Duration::from_secs(6),
+                retries: 0,
+            }]
+        );
+
+        // a late ack does not answer the cancel
+        order_tracker.update_status("b3", OrderStatus::Open);
+        assert_eq!(
+            order_tracker.get_order("b3").unwrap().status,
+            OrderStatus::CancelRequested
+        );
+        order_tracker.reissue_cancel_order("b2", at(6));
+        let stale = order_tracker.stale_orders(at(11), Duration::from_secs(4));
+        assert_eq!(
+            stale
+                .iter()
+                .map(|s| (s.order_id.as_str(), s.status, s.retries))
+                .collect::<Vec<_>>(),
+            vec![
+                ("b3", OrderStatus::CancelRequested, 0),
+                ("b2", OrderStatus::CancelRequested, 1),
+            ]
+        );
+
+        order_tracker.mark_errored("b2");
+        order_tracker.update_status("b3", OrderStatus::Canceled);
+        order_tracker.remove_terminated_orders();
+        assert_eq!(order_tracker.size(), 1);
+        assert!(order_tracker
+            .stale_orders(at(60), Duration::from_secs(4))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_reject_cancel() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut order_tracker = OrderTracker::default();
+        for order_id in ["b1", "b2", "b3", "b4", "b5"] {
+            order_tracker.upsert_order(Order {
+                order_id: order_id.into(),
+                price: 0.0,
+                side: TradeSide::Buy,
+                quantity: 1.0,
+                filled: 0.0,
+                status: OrderStatus::OpenRequested,
+                created_at: at(0),
+            });
+            order_tracker.request_cancel_order(order_id, at(1));
+        }
+        order_tracker.reject_cancel("b1", CancelRejectReason::NotYetPlaced);
+        order_tracker.reject_cancel("b2", CancelRejectReason::AlreadyFilled);
+        order_tracker.reject_cancel("b3", CancelRejectReason::UnknownOrder);
+        order_tracker.reject_cancel("b4", CancelRejectReason::UnknownSymbol);
+        order_tracker.reject_cancel("b5", CancelRejectReason::RateLimited);
+        let status = |order_id| order_tracker.get_order(order_id).unwrap().status;
+        assert_eq!(status("b1"), OrderStatus::OpenRequested);
+        // open until its fill arrives
+        assert_eq!(status("b2"), OrderStatus::Open);
+        assert_eq!(status("b3"), OrderStatus::Canceled);
+        assert_eq!(status("b4"), OrderStatus::Errored);
+        assert_eq!(status("b5"), OrderStatus::Open);
+        // the order on its way is still waiting for its ack
+        let stale = order_tracker.stale_orders(at(10), Duration::from_secs(4));
+        assert_eq!(
+            stale
+                .iter()
+                .map(|s| s.order_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["b1"]
+        );
+
+        order_tracker.update_status("b2", OrderStatus::Filled);
+        assert_eq!(
+            order_tracker.get_order("b2").unwrap().status,
+            OrderStatus::Filled
+        );
+
+        // a reject after the cancel was answered changes nothing
+        order_tracker.update_status("b1", OrderStatus::Open);
+        order_tracker.reject_cancel("b1", CancelRejectReason::UnknownOrder);
+        assert_eq!(
+            order_tracker.get_order("b1").unwrap().status,
+            OrderStatus::Open
+        );
+    }
 }
@@ -0,0 +1,109 @@
+use std::time::{Duration, SystemTime};
+
+// What to do with a sequenced message of the exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequenced {
+    // the next message, apply it
+    Next,
+    // seen already or included in the last snapshot, drop it
+    Stale,
+    // messages before it were lost, drop it and resync
+    Gap,
+    // the snapshot being waited for includes it, drop it
+    Resyncing,
+}
+
+// Tracks the sequence numbers of the order results and balance updates of a symbol. After a
+// gap the state is rebuilt from a snapshot, which is requested again when it does not arrive.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: u64,
+    resyncing: bool,
+    // None until the pending resync is requested
+    resync_requested_at: Option<SystemTime>,
+    gaps: u64,
+}
+
+impl SequenceTracker {
+    pub fn check(&mut self, seq: u64) -> Sequenced {
+        if seq <= self.last {
+            return Sequenced::Stale;
+        }
+        if self.resyncing {
+            return Sequenced::Resyncing;
+        }
+        if seq == self.last + 1 {
+            self.last = seq;
+            return Sequenced::Next;
+        }
+        self.gaps += 1;
+        self.resyncing = true;
+        self.resync_requested_at = None;
+        Sequenced::Gap
+    }
+
+    // returns whether the snapshot after message seq should be applied, only the first one
+    // answering a resync is
+    pub fn on_snapshot(&mut self, seq: u64) -> bool {
+        if !self.resyncing || seq < self.last {
+            return false;
+        }
+        self.last = seq;
+        self.resyncing = false;
+        self.resync_requested_at = None;
+        true
+    }
+
+    // whether a resync request should be sent now, the first one right after the gap and
+    // another one each timeout without a snapshot
+    pub fn resync_due(&mut self, now: SystemTime, timeout: Duration) -> bool {
+        if !self.resyncing {
+            return false;
+        }
+        let due = self
+            .resync_requested_at
+            .is_none_or(|at| now.duration_since(at).unwrap_or_default() >= timeout);
+        if due {
+            self.resync_requested_at = Some(now);
+        }
+        due
+    }
+
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_and_resync() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let timeout = Duration::from_secs(1);
+        let mut sequence = SequenceTracker::default();
+        assert_eq!(sequence.check(1), Sequenced::Next);
+        assert_eq!(sequence.check(2), Sequenced::Next);
+        // duplicate
+        assert_eq!(sequence.check(2), Sequenced::Stale);
+        assert!(!sequence.resync_due(at(0), timeout));
+
+        // 3 is lost
+        assert_eq!(sequence.check(4), Sequenced::Gap);
+        assert_eq!(sequence.gaps(), 1);
+        assert_eq!(sequence.check(5), Sequenced::Resyncing);
+        assert!(sequence.resync_due(at(0), timeout));
+        assert!(!sequence.resync_due(at(0), timeout));
+        // the snapshot got lost too
+        assert!(sequence.resync_due(at(1), timeout));
+
+        assert!(sequence.on_snapshot(6));
+        // the answer to the first request
+        assert!(!sequence.on_snapshot(6));
+        assert_eq!(sequence.check(5), Sequenced::Stale);
+        assert_eq!(sequence.check(7), Sequenced::Next);
+        assert!(!sequence.resync_due(at(5), timeout));
+        assert_eq!(sequence.gaps(), 1);
+    }
+}
@@ -1,7 +1,16 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use account::account::Account;
-use upstair_type::data::market::BinanceTradeTick;
+use upstair_type::{
+    control::DataQualityFlags,
+    data::market::BinanceTradeTick,
+    order::{CancelRejectReason, RateLimitUsage, RejectReason},
+    signal::SignalKind,
+    Payload,
+};
 
 use crate::order_tracker::OrderTracker;
 
@@ -10,16 +19,70 @@ pub struct StepperWorld {
     pub latest_market_price: f64,
     pub order_tracker: OrderTracker,
     pub account: Account,
-    pub best_bid_price: f64,
-    pub best_bid_qty: f64,
-    pub best_ask_price: f64,
-    pub best_ask_qty: f64,
     pub booker_tick_updated_at: SystemTime,
+    // set by the validation of the market data, all clear without it
+    pub data_quality: DataQualityFlags,
+    // the latest values on the signals topic, empty without an indicator module
+    pub signals: BTreeMap<SignalKind, f64>,
+    // of the futures symbol, None without mark price data
+    pub mark_price: Option<f64>,
+    // the mark price over the index price, None without index price data
+    pub basis: Option<f64>,
+    // the books of the symbols the stepper follows, its own and the ones the strategy
+    // references, e.g. a hedge leg
+    pub markets: BTreeMap<&'static str, MarketSnapshot>,
 
     pub trade_buf: Vec<BinanceTradeTick>,
     pub wap_buf: Vec<(u64, f64)>,
+    pub mid_buf: Vec<(u64, f64)>,
     // (order_id, filled_amt)
     pub filled_event_buf: Vec<(String, f64)>,
+    // (order_id, reason) of the orders the exchange rejected
+    pub reject_buf: Vec<(String, RejectReason)>,
+    // (order_id, reason) of the cancels the exchange rejected
+    pub cancel_reject_buf: Vec<(String, CancelRejectReason)>,
+    // of the rate limits of the exchange after the last request answered, none without limits
+    pub rate_limit_usage: Option<RateLimitUsage>,
+
+    // the times of the newest market data, see check_look_ahead
+    pub data_times: MarketDataTimes,
+    // the feed latency the data is checked against at each decision, none skips the check
+    pub look_ahead_latency: Option<Duration>,
+}
+
+// The top of the book and the last trade of a symbol
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MarketSnapshot {
+    pub best_bid_price: f64,
+    pub best_bid_qty: f64,
+    pub best_ask_price: f64,
+    pub best_ask_qty: f64,
+    pub last_price: f64,
+    // of the last book ticker, in ms
+    pub book_time: u64,
+    pub mark_price: Option<f64>,
+    pub basis: Option<f64>,
+}
+
+impl MarketSnapshot {
+    // mid of the book, the last trade price until a book ticker is received
+    pub fn mid_price(&self) -> f64 {
+        if self.best_bid_price > 0.0 && self.best_ask_price > 0.0 {
+            (self.best_bid_price + self.best_ask_price) / 2.0
+        } else {
+            self.last_price
+        }
+    }
+}
+
+// The times the market data of the world happened at, in ms
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarketDataTimes {
+    pub book: u64,
+    // of the trades, and of the candles setting the latest market price
+    pub trade: u64,
+    pub signal: u64,
+    pub mark_price: u64,
 }
 
 impl Default for StepperWorld {
@@ -29,14 +92,166 @@ impl Default for StepperWorld {
             latest_market_price: 0.0,
             order_tracker: OrderTracker::default(),
             account: Account::default(),
-            best_bid_price: 0.0,
-            best_bid_qty: 0.0,
-            best_ask_price: 0.0,
-            best_ask_qty: 0.0,
             booker_tick_updated_at: UNIX_EPOCH,
+            data_quality: DataQualityFlags::default(),
+            signals: BTreeMap::new(),
+            mark_price: None,
+            basis: None,
+            markets: BTreeMap::new(),
             trade_buf: Vec::with_capacity(1024),
             wap_buf: Vec::with_capacity(1024),
+            mid_buf: Vec::with_capacity(1024),
             filled_event_buf: Vec::with_capacity(1024),
+            reject_buf: Vec::new(),
+            cancel_reject_buf: Vec::new(),
+            rate_limit_usage: None,
+            data_times: MarketDataTimes::default(),
+            look_ahead_latency: None,
+        }
+    }
+}
+
+impl StepperWorld {
+    // the book of symbol, none before its market data
+    pub fn market(&self, symbol: &str) -> Option<&MarketSnapshot> {
+        self.markets.get(symbol)
+    }
+
+    // the book of symbol, empty before its market data
+    pub fn book(&self, symbol: &str) -> MarketSnapshot {
+        self.markets.get(symbol).copied().unwrap_or_default()
+    }
+
+    // brings the book of the symbol of the market data up to date
+    pub fn update_market(&mut self, payload: &Payload) {
+        let Some(symbol) = payload.symbol() else {
+            return;
+        };
+        if let Payload::BinanceBookTicker(ticker) = payload {
+            let market = self.markets.entry(symbol).or_default();
+            market.best_bid_price = ticker.best_bid_price;
+            market.best_bid_qty = ticker.best_bid_qty;
+            market.best_ask_price = ticker.best_ask_price;
+            market.best_ask_qty = ticker.best_ask_qty;
+            market.book_time = ticker.event_time;
+        } else if let Payload::MarkPrice(mark) = payload {
+            let market = self.markets.entry(symbol).or_default();
+            market.mark_price = Some(mark.mark_price);
+            market.basis = mark.basis();
+        } else if let Some(price) = payload.trade_price() {
+            self.markets.entry(symbol).or_default().last_price = price;
+        }
+    }
+
+    // panics when the world holds market data newer than now less the feed latency, which a
+    // strategy deciding now could not have received yet and a backtest reading it peeks at
+    pub fn check_look_ahead(&self) {
+        let Some(latency) = self.look_ahead_latency else {
+            return;
+        };
+        let now_ms = self
+            .now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let visible_ms = now_ms.saturating_sub(latency.as_millis() as u64);
+        let times = [
+            ("book ticker", self.data_times.book),
+            ("trade", self.data_times.trade),
+            ("signal", self.data_times.signal),
+            ("mark price", self.data_times.mark_price),
+        ];
+        for (data, time) in times {
+            if time > visible_ms {
+                panic!(
+                    "look-ahead: the strategy reads a {} of {} ms at {} ms, newer than a feed latency of {:?} lets it see",
+                    data, time, now_ms, latency
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use upstair_type::{aggregate::MarkPrice, data::market::BinanceBookTicker};
+
+    use super::*;
+
+    fn world(latency_ms: u64) -> StepperWorld {
+        StepperWorld {
+            now: UNIX_EPOCH + Duration::from_millis(1000),
+            look_ahead_latency: Some(Duration::from_millis(latency_ms)),
+            data_times: MarketDataTimes {
+                book: 990,
+                trade: 995,
+                signal: 0,
+                mark_price: 0,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_look_ahead_within_latency() {
+        world(5).check_look_ahead();
+        StepperWorld {
+            look_ahead_latency: None,
+            ..world(100)
         }
+        .check_look_ahead();
+    }
+
+    #[test]
+    #[should_panic(expected = "look-ahead: the strategy reads a trade of 995 ms at 1000 ms")]
+    fn test_look_ahead_panics() {
+        world(10).check_look_ahead();
+    }
+
+    #[test]
+    fn test_update_market() {
+        let mut world = StepperWorld::default();
+        let ticker = |symbol, bid, ask| {
+            Payload::BinanceBookTicker(BinanceBookTicker {
+                update_id: 0,
+                best_bid_price: bid,
+                best_bid_qty: 1.0,
+                best_ask_price: ask,
+                best_ask_qty: 2.0,
+                transaction_time: 0,
+                event_time: 5,
+                symbol,
+            })
+        };
+        world.update_market(&Payload::BinanceTradeTick(BinanceTradeTick {
+            id: 0,
+            price: 2000.0,
+            qty: 1.0,
+            base_qty: 2000.0,
+            time: 0,
+            is_buyer_maker: false,
+            symbol: "ETHUSDT",
+        }));
+        assert_eq!(world.market("ETHUSDT").unwrap().mid_price(), 2000.0);
+        world.update_market(&ticker("BTCUSDT", 100.0, 101.0));
+        world.update_market(&ticker("ETHUSDT", 1999.0, 2001.0));
+        assert_eq!(world.market("BTCUSDT").unwrap().mid_price(), 100.5);
+        let eth = world.market("ETHUSDT").unwrap();
+        assert_eq!(eth.best_ask_qty, 2.0);
+        assert_eq!(eth.last_price, 2000.0);
+        assert_eq!(eth.book_time, 5);
+        assert_eq!(eth.mark_price, None);
+        assert!(world.market("BNBUSDT").is_none());
+
+        world.update_market(&Payload::MarkPrice(MarkPrice {
+            mark_price: 2002.0,
+            index_price: Some(2000.5),
+            time: 59_999,
+            symbol: "ETHUSDT",
+        }));
+        let eth = world.market("ETHUSDT").unwrap();
+        assert_eq!((eth.mark_price, eth.basis), (Some(2002.0), Some(1.5)));
+        // the mark price is no trade
+        assert_eq!(eth.last_price, 2000.0);
     }
 }
@@ -1,4 +1,4 @@
 mod symbol_info;
 mod symbol_trade;
-pub use symbol_info::SymbolInfoManager;
+pub use symbol_info::{SymbolInfo, SymbolInfoManager};
 pub use symbol_trade::calc_trade_result;
@@ -0,0 +1,3 @@
+pub mod scenario;
+pub mod synthetic_feed;
+pub mod time_compression;
@@ -0,0 +1,484 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use upstair_type::{
+    module::{
+        Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle, WriteTopicHandle,
+    },
+    Message, Payload,
+};
+
+// A stress put on the market data, at is the time after the first tick
+#[derive(Debug, Clone, PartialEq)]
+pub enum StressEvent {
+    // prices fall by the fraction drop within over, and climb back within recover unless it
+    // is zero
+    FlashCrash {
+        at: Duration,
+        drop: f64,
+        over: Duration,
+        recover: Duration,
+    },
+    // the spread of the book tickers widened around the mid by factor
+    SpreadBlowout {
+        at: Duration,
+        duration: Duration,
+        factor: f64,
+    },
+    // no market data at all
+    DataGap {
+        at: Duration,
+        duration: Duration,
+    },
+}
+
+const EVENT_FORMAT: &str = "crash:at_secs=3600,drop=0.1,over_secs=30,recover_secs=300, \
+    spread:at_secs=3600,for_secs=600,factor=5 or gap:at_secs=3600,for_secs=300";
+
+// crash:at_secs=3600,drop=0.1,over_secs=30,recover_secs=300 and the like, see EVENT_FORMAT
+impl FromStr for StressEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, params)) = s.split_once(':') else {
+            return Err(format!("invalid scenario {s}, expected {EVENT_FORMAT}"));
+        };
+        let mut values = vec![];
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            let (name, value) = param.split_once('=').ok_or_else(|| {
+                format!("invalid scenario parameter {param}, expected name=value")
+            })?;
+            let value: f64 = value
+                .parse()
+                .map_err(|_| format!("invalid value of scenario parameter {name}: {value}"))?;
+            if value < 0.0 {
+                return Err(format!("scenario parameter {name} must not be negative"));
+            }
+            values.push((name, value));
+        }
+        let mut take = |name: &str, default: Option<f64>| -> Result<f64, String> {
+            match values.iter().position(|(n, _)| *n == name) {
+                Some(i) => Ok(values.remove(i).1),
+                None => default.ok_or_else(|| format!("scenario {kind} needs {name}")),
+            }
+        };
+        let secs = |secs: f64| Duration::from_secs_f64(secs);
+        let event = match kind.to_lowercase().as_str() {
+            "crash" => StressEvent::FlashCrash {
+                at: secs(take("at_secs", None)?),
+                drop: take("drop", None)?,
+                over: secs(take("over_secs", Some(0.0))?),
+                recover: secs(take("recover_secs", Some(0.0))?),
+            },
+            "spread" => StressEvent::SpreadBlowout {
+                at: secs(take("at_secs", None)?),
+                duration: secs(take("for_secs", None)?),
+                factor: take("factor", None)?,
+            },
+            "gap" => StressEvent::DataGap {
+                at: secs(take("at_secs", None)?),
+                duration: secs(take("for_secs", None)?),
+            },
+            _ => {
+                return Err(format!(
+                    "unknown scenario {kind}, expected crash, spread or gap"
+                ))
+            }
+        };
+        if let Some((name, _)) = values.first() {
+            return Err(format!("unknown parameter {name} of scenario {kind}"));
+        }
+        match event {
+            StressEvent::FlashCrash { drop, .. } if drop >= 1.0 => {
+                Err("drop of a crash must be below 1".to_string())
+            }
+            event => Ok(event),
+        }
+    }
+}
+
+// Stress events applied to the market data a module publishes, the same for every subscriber
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    events: Vec<StressEvent>,
+    // time of the first market data tick, the events are timed from it
+    start: Option<SystemTime>,
+    blacked_out: u64,
+    stressed: u64,
+}
+
+impl Scenario {
+    pub fn with_event(mut self, event: StressEvent) -> Self {
+        self.events.push(event);
+        self
+    }
+
+    // what the prices are multiplied by
+    fn price_factor(&self, elapsed: Duration) -> f64 {
+        self.events
+            .iter()
+            .map(|event| match *event {
+                StressEvent::FlashCrash {
+                    at,
+                    drop,
+                    over,
+                    recover,
+                } if elapsed >= at => {
+                    let t = elapsed - at;
+                    let fallen = if t < over {
+                        t.as_secs_f64() / over.as_secs_f64()
+                    } else if recover.is_zero() {
+                        1.0
+                    } else if t < over + recover {
+                        1.0 - (t - over).as_secs_f64() / recover.as_secs_f64()
+                    } else {
+                        0.0
+                    };
+                    1.0 - drop * fallen
+                }
+                _ => 1.0,
+            })
+            .product()
+    }
+
+    fn spread_factor(&self, elapsed: Duration) -> f64 {
+        self.events
+            .iter()
+            .map(|event| match *event {
+                StressEvent::SpreadBlowout {
+                    at,
+                    duration,
+                    factor,
+                } if elapsed >= at && elapsed < at + duration => factor,
+                _ => 1.0,
+            })
+            .product()
+    }
+
+    fn blacked_out(&self, elapsed: Duration) -> bool {
+        self.events.iter().any(|event| match *event {
+            StressEvent::DataGap { at, duration } => elapsed >= at && elapsed < at + duration,
+            _ => false,
+        })
+    }
+
+    // the message as stressed, None when it falls in a data gap. Control messages such as
+    // day rolls pass as they are.
+    pub fn apply(&mut self, mut message: Message) -> Option<Message> {
+        if message.payload.trade_price().is_none()
+            && !matches!(
+                message.payload,
+                Payload::BinanceBookTicker(_) | Payload::MarkPrice(_)
+            )
+        {
+            return Some(message);
+        }
+        let time = message.header.commit_at;
+        let elapsed = time
+            .duration_since(*self.start.get_or_insert(time))
+            .unwrap_or_default();
+        if self.blacked_out(elapsed) {
+            self.blacked_out += 1;
+            return None;
+        }
+        let price = self.price_factor(elapsed);
+        let spread = self.spread_factor(elapsed);
+        if price == 1.0 && spread == 1.0 {
+            return Some(message);
+        }
+        self.stressed += 1;
+        match &mut message.payload {
+            Payload::BinanceBookTicker(ticker) => {
+                let mid = (ticker.best_bid_price + ticker.best_ask_price) / 2.0;
+                let half_spread = (ticker.best_ask_price - ticker.best_bid_price) / 2.0 * spread;
+                ticker.best_bid_price = (mid - half_spread) * price;
+                ticker.best_ask_price = (mid + half_spread) * price;
+            }
+            Payload::BinanceTradeTick(tick) => {
+                tick.price *= price;
+                tick.base_qty *= price;
+            }
+            Payload::TradeTickBatch(ticks) => ticks.iter_mut().for_each(|tick| {
+                tick.price *= price;
+                tick.base_qty *= price;
+            }),
+            Payload::BinanceAggTrade(trade) => trade.price *= price,
+            Payload::BinanceKline(kline) => {
+                kline.open *= price;
+                kline.high *= price;
+                kline.low *= price;
+                kline.close *= price;
+                kline.quote_volume *= price;
+                kline.taker_buy_quote_volume *= price;
+            }
+            Payload::MarkPrice(mark) => {
+                mark.mark_price *= price;
+                mark.index_price = mark.index_price.map(|index_price| index_price * price);
+            }
+            _ => {}
+        }
+        Some(message)
+    }
+}
+
+// Publishes through the inner comms what the scenario leaves of the messages
+struct ScenarioComms<'a> {
+    inner: &'a mut dyn ModuleComms,
+    scenario: &'a mut Scenario,
+}
+
+impl ModuleComms for ScenarioComms<'_> {
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        self.inner.receive_shared(topic)
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        if let Some(message) = self.scenario.apply(message) {
+            self.inner.publish(topic, message)
+        }
+    }
+
+    fn request_terminate(&mut self) {
+        self.inner.request_terminate()
+    }
+
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        self.inner.next_delivery_at()
+    }
+}
+
+struct ScenarioModule {
+    inner: Box<dyn Module>,
+    scenario: Scenario,
+}
+
+impl Module for ScenarioModule {
+    fn start(&mut self) {
+        self.inner.start()
+    }
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        self.inner.sync(&mut ScenarioComms {
+            inner: comms,
+            scenario: &mut self.scenario,
+        })
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        self.inner.one_iteration(&mut ScenarioComms {
+            inner: comms,
+            scenario: &mut self.scenario,
+        })
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        self.inner.next_iteration_start_at()
+    }
+
+    fn wake_on_message(&self) -> bool {
+        self.inner.wake_on_message()
+    }
+
+    fn on_shutdown(&mut self, comms: &mut dyn ModuleComms) {
+        self.inner.on_shutdown(&mut ScenarioComms {
+            inner: comms,
+            scenario: &mut self.scenario,
+        })
+    }
+
+    fn terminate(&mut self) {
+        self.inner.terminate();
+        println!(
+            "Scenario: {} events, {} ticks stressed, {} ticks blacked out",
+            self.scenario.events.len(),
+            self.scenario.stressed,
+            self.scenario.blacked_out
+        );
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.inner.failure()
+    }
+}
+
+// Puts a scenario on the market data of a feed such as the republisher or the synthetic feed,
+// to see how the strategy copes with a crash, a wide market or missing data
+pub struct ScenarioBuilder {
+    inner: Box<dyn ModuleBuilder>,
+    scenario: Scenario,
+}
+
+impl ScenarioBuilder {
+    pub fn new(inner: Box<dyn ModuleBuilder>, scenario: Scenario) -> Self {
+        ScenarioBuilder { inner, scenario }
+    }
+}
+
+impl ModuleBuilder for ScenarioBuilder {
+    // the feed's name, the scenario is no module of its own
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn priority(&self) -> ModulePriority {
+        self.inner.priority()
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        self.inner.init_comm(comms)
+    }
+
+    fn init_output(&mut self, output: &upstair_type::run_output::RunOutput) {
+        self.inner.init_output(output)
+    }
+
+    fn build(self: Box<ScenarioBuilder>) -> Box<dyn Module> {
+        Box::new(ScenarioModule {
+            inner: self.inner.build(),
+            scenario: self.scenario,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use upstair_type::{
+        control::DayRoll,
+        data::market::{BinanceBookTicker, BinanceTradeTick},
+        MessageHeader,
+    };
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    fn book(secs: u64, bid: f64, ask: f64) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: at(secs),
+            },
+            payload: Payload::BinanceBookTicker(BinanceBookTicker {
+                update_id: secs,
+                best_bid_price: bid,
+                best_bid_qty: 1.0,
+                best_ask_price: ask,
+                best_ask_qty: 1.0,
+                transaction_time: 0,
+                event_time: 0,
+                symbol: "BTCUSDT",
+            }),
+        }
+    }
+
+    fn trade(secs: u64, price: f64) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: at(secs),
+            },
+            payload: Payload::BinanceTradeTick(BinanceTradeTick {
+                id: secs,
+                price,
+                qty: 1.0,
+                base_qty: price,
+                time: 0,
+                is_buyer_maker: false,
+                symbol: "BTCUSDT",
+            }),
+        }
+    }
+
+    fn trade_price(message: Option<Message>) -> Option<f64> {
+        message.and_then(|message| message.payload.trade_price())
+    }
+
+    fn touch(message: Option<Message>) -> (f64, f64) {
+        match message.map(|message| message.payload) {
+            Some(Payload::BinanceBookTicker(ticker)) => {
+                (ticker.best_bid_price, ticker.best_ask_price)
+            }
+            _ => panic!("not a book ticker"),
+        }
+    }
+
+    #[test]
+    fn test_parse_events() {
+        assert_eq!(
+            "crash:at_secs=60,drop=0.1,over_secs=30".parse(),
+            Ok(StressEvent::FlashCrash {
+                at: Duration::from_secs(60),
+                drop: 0.1,
+                over: Duration::from_secs(30),
+                recover: Duration::ZERO,
+            })
+        );
+        assert_eq!(
+            "Gap:at_secs=60,for_secs=300".parse(),
+            Ok(StressEvent::DataGap {
+                at: Duration::from_secs(60),
+                duration: Duration::from_secs(300),
+            })
+        );
+        assert!("spread:at_secs=60,factor=5".parse::<StressEvent>().is_err());
+        assert!("gap:at_secs=60,for_secs=5,factor=2"
+            .parse::<StressEvent>()
+            .is_err());
+        assert!("crash:at_secs=60,drop=1".parse::<StressEvent>().is_err());
+        assert!("halt:at_secs=60".parse::<StressEvent>().is_err());
+    }
+
+    #[test]
+    fn test_flash_crash_and_recovery() {
+        let mut scenario = Scenario::default().with_event(
+            "crash:at_secs=100,drop=0.2,over_secs=10,recover_secs=20"
+                .parse()
+                .unwrap(),
+        );
+        let prices: Vec<f64> = [0, 100, 105, 110, 120, 130, 200]
+            .into_iter()
+            .map(|secs| trade_price(scenario.apply(trade(secs, 100.0))).unwrap())
+            .collect();
+        let expected = [100.0, 100.0, 90.0, 80.0, 90.0, 100.0, 100.0];
+        for (price, expected) in prices.iter().zip(expected) {
+            assert!((price - expected).abs() < 1e-9, "{prices:?}");
+        }
+        assert_eq!(scenario.stressed, 3);
+    }
+
+    #[test]
+    fn test_spread_blowout() {
+        let mut scenario = Scenario::default()
+            .with_event("spread:at_secs=10,for_secs=10,factor=5".parse().unwrap());
+        assert_eq!(touch(scenario.apply(book(0, 99.0, 101.0))), (99.0, 101.0));
+        assert_eq!(touch(scenario.apply(book(15, 99.0, 101.0))), (95.0, 105.0));
+        assert_eq!(touch(scenario.apply(book(20, 99.0, 101.0))), (99.0, 101.0));
+        // trades are left where they are
+        assert_eq!(trade_price(scenario.apply(trade(15, 100.0))), Some(100.0));
+    }
+
+    #[test]
+    fn test_data_gap_keeps_control_messages() {
+        let mut scenario =
+            Scenario::default().with_event("gap:at_secs=10,for_secs=10".parse().unwrap());
+        assert!(scenario.apply(trade(0, 100.0)).is_some());
+        assert!(scenario.apply(trade(10, 100.0)).is_none());
+        assert!(scenario.apply(book(19, 99.0, 101.0)).is_none());
+        let roll = Message {
+            header: MessageHeader { commit_at: at(15) },
+            payload: Payload::DayRoll(DayRoll { day_start: at(15) }),
+        };
+        assert!(scenario.apply(roll).is_some());
+        assert!(scenario.apply(trade(20, 100.0)).is_some());
+        assert_eq!(scenario.blacked_out, 2);
+    }
+}
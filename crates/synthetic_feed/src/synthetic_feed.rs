@@ -0,0 +1,478 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::info;
+use upstair_type::{
+    control::DayRoll,
+    data::market::{BinanceBookTicker, BinanceTradeTick},
+    module::{Module, ModuleBuilder, ModulePriority, WriteTopicHandle},
+    Message, Payload,
+};
+
+const YEAR_SECS: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+// The process the log price follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceProcess {
+    // geometric Brownian motion, a random walk with drift
+    Gbm,
+    // Ornstein-Uhlenbeck, pulled back to the initial price
+    OrnsteinUhlenbeck,
+}
+
+impl FromStr for PriceProcess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gbm" => Ok(PriceProcess::Gbm),
+            "ou" | "ornstein-uhlenbeck" => Ok(PriceProcess::OrnsteinUhlenbeck),
+            _ => Err(format!("unknown price process {s}, expected gbm or ou")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticFeedConfig {
+    pub process: PriceProcess,
+    pub initial_price: f64,
+    // annualized, of the log price
+    pub volatility: f64,
+    // annualized, of gbm
+    pub drift: f64,
+    // of ou, the time half of a deviation from the initial price takes to decay
+    pub half_life: Duration,
+    // mean trades per second, the arrivals are Poisson
+    pub trade_rate: f64,
+    pub mean_trade_qty: f64,
+    // the price moves and a book ticker is published every step
+    pub step: Duration,
+    pub price_tick: f64,
+    // ticks between the best bid and the best ask
+    pub spread_ticks: u32,
+    pub mean_book_qty: f64,
+    pub seed: u64,
+}
+
+impl Default for SyntheticFeedConfig {
+    fn default() -> Self {
+        SyntheticFeedConfig {
+            process: PriceProcess::Gbm,
+            initial_price: 40000.0,
+            volatility: 0.5,
+            drift: 0.0,
+            half_life: Duration::from_secs(60 * 60),
+            trade_rate: 5.0,
+            mean_trade_qty: 0.01,
+            step: Duration::from_millis(100),
+            price_tick: 0.1,
+            spread_ticks: 1,
+            mean_book_qty: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+// Book tickers every step around a price path and trades at the touch arriving in between,
+// in time order. The same config and seed give the same ticks.
+pub struct SyntheticMarket {
+    config: SyntheticFeedConfig,
+    symbol: &'static str,
+    rng: StdRng,
+    log_price: f64,
+    // the touch of the last book ticker, trades happen at it
+    best_bid_price: f64,
+    best_ask_price: f64,
+    next_step_ms: u64,
+    next_trade_ms: u64,
+    update_id: u64,
+    trade_id: u64,
+}
+
+impl SyntheticMarket {
+    pub fn new(symbol: &'static str, config: SyntheticFeedConfig, start_ms: u64) -> Self {
+        let mut market = SyntheticMarket {
+            symbol,
+            rng: StdRng::seed_from_u64(config.seed),
+            log_price: config.initial_price.ln(),
+            best_bid_price: config.initial_price,
+            best_ask_price: config.initial_price,
+            next_step_ms: start_ms,
+            next_trade_ms: start_ms,
+            update_id: 0,
+            trade_id: 0,
+            config,
+        };
+        market.next_trade_ms = start_ms + market.trade_interval_ms();
+        market
+    }
+
+    // standard normal by Box-Muller
+    fn normal(&mut self) -> f64 {
+        let u1: f64 = 1.0 - self.rng.gen::<f64>();
+        let u2: f64 = self.rng.gen();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    fn exponential(&mut self, mean: f64) -> f64 {
+        -(1.0 - self.rng.gen::<f64>()).ln() * mean
+    }
+
+    fn trade_interval_ms(&mut self) -> u64 {
+        if self.config.trade_rate <= 0.0 {
+            return u64::MAX / 2;
+        }
+        self.exponential(1000.0 / self.config.trade_rate).round() as u64
+    }
+
+    fn qty(&mut self, mean: f64) -> f64 {
+        (self.exponential(mean) * 1000.0).round().max(1.0) / 1000.0
+    }
+
+    fn move_price(&mut self) {
+        let dt = self.config.step.as_secs_f64() / YEAR_SECS;
+        let sigma = self.config.volatility;
+        let shock = sigma * dt.sqrt() * self.normal();
+        self.log_price += match self.config.process {
+            PriceProcess::Gbm => (self.config.drift - sigma * sigma / 2.0) * dt + shock,
+            PriceProcess::OrnsteinUhlenbeck => {
+                let half_life = self.config.half_life.as_secs_f64().max(f64::MIN_POSITIVE);
+                let theta = std::f64::consts::LN_2 / (half_life / YEAR_SECS);
+                let mean = self.config.initial_price.ln();
+                // the exact decay, stable for a half life shorter than a step
+                (mean - self.log_price) * (1.0 - (-theta * dt).exp()) + shock
+            }
+        };
+    }
+
+    // the price path, the mid of the book before it is aligned to the tick
+    pub fn price(&self) -> f64 {
+        self.log_price.exp()
+    }
+
+    fn book_ticker(&mut self, time: u64) -> BinanceBookTicker {
+        self.move_price();
+        let tick = self.config.price_tick;
+        let spread_ticks = self.config.spread_ticks.max(1) as f64;
+        let ticks = (self.price() / tick - spread_ticks / 2.0).floor().max(1.0);
+        // rounded to clear the float error, 400001 * 0.1 is 40000.100000000006
+        let on_tick = |ticks: f64| (ticks * tick * 1e8).round() / 1e8;
+        self.best_bid_price = on_tick(ticks);
+        self.best_ask_price = on_tick(ticks + spread_ticks);
+        self.update_id += 1;
+        let mean_book_qty = self.config.mean_book_qty;
+        BinanceBookTicker {
+            update_id: self.update_id,
+            best_bid_price: self.best_bid_price,
+            best_bid_qty: self.qty(mean_book_qty),
+            best_ask_price: self.best_ask_price,
+            best_ask_qty: self.qty(mean_book_qty),
+            transaction_time: time,
+            event_time: time,
+            symbol: self.symbol,
+        }
+    }
+
+    fn trade(&mut self, time: u64) -> BinanceTradeTick {
+        // a seller hits the bid as often as a buyer lifts the ask
+        let is_buyer_maker = self.rng.gen_bool(0.5);
+        let price = if is_buyer_maker {
+            self.best_bid_price
+        } else {
+            self.best_ask_price
+        };
+        let qty = self.qty(self.config.mean_trade_qty);
+        self.trade_id += 1;
+        BinanceTradeTick {
+            id: self.trade_id,
+            price,
+            qty,
+            base_qty: price * qty,
+            time,
+            is_buyer_maker,
+            symbol: self.symbol,
+        }
+    }
+
+    // the next tick and its time in milliseconds, the book ticker first at equal times
+    pub fn next_tick(&mut self) -> (u64, Payload) {
+        if self.next_trade_ms < self.next_step_ms {
+            let time = self.next_trade_ms;
+            self.next_trade_ms += self.trade_interval_ms();
+            (time, Payload::BinanceTradeTick(self.trade(time)))
+        } else {
+            let time = self.next_step_ms;
+            self.next_step_ms += (self.config.step.as_millis() as u64).max(1);
+            (time, Payload::BinanceBookTicker(self.book_ticker(time)))
+        }
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Publishes a synthetic market on market_data in place of the republisher, to test and
+// stress a strategy without data files
+pub struct SyntheticFeed {
+    write_market_data_handle: WriteTopicHandle,
+    market: SyntheticMarket,
+    peeking_tick: Option<(u64, Payload)>,
+    end_ms: u64,
+    // UTC day of the last published tick, counted from the epoch
+    day: Option<u64>,
+    published: u64,
+}
+
+impl SyntheticFeed {
+    fn next_tick(&mut self) {
+        let (time, payload) = self.market.next_tick();
+        self.peeking_tick = (time < self.end_ms).then_some((time, payload));
+    }
+}
+
+impl Module for SyntheticFeed {
+    fn sync(&mut self, _: &mut dyn upstair_type::module::ModuleComms) -> bool {
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        let now = millis(comms.time());
+        while let Some((time, _)) = &self.peeking_tick {
+            let time = *time;
+            if time > now {
+                break;
+            }
+            let (_, payload) = self.peeking_tick.take().unwrap();
+            let commit_at = UNIX_EPOCH + Duration::from_millis(time);
+            let tick_day = time / DAY_MS;
+            if self.day.replace(tick_day).is_some_and(|day| tick_day > day) {
+                comms.publish(
+                    &self.write_market_data_handle,
+                    Message {
+                        header: upstair_type::MessageHeader { commit_at },
+                        payload: Payload::DayRoll(DayRoll {
+                            day_start: UNIX_EPOCH + Duration::from_millis(tick_day * DAY_MS),
+                        }),
+                    },
+                );
+            }
+            comms.publish(
+                &self.write_market_data_handle,
+                Message {
+                    header: upstair_type::MessageHeader { commit_at },
+                    payload,
+                },
+            );
+            self.published += 1;
+            self.next_tick();
+        }
+        if self.peeking_tick.is_none() {
+            comms.request_terminate();
+        }
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        self.peeking_tick
+            .as_ref()
+            .map(|(time, _)| UNIX_EPOCH + Duration::from_millis(*time))
+    }
+
+    fn start(&mut self) {
+        let config = &self.market.config;
+        info!(
+            "synthetic feed {:?} from {} at volatility {} and seed {}",
+            config.process, config.initial_price, config.volatility, config.seed
+        );
+        self.next_tick();
+    }
+
+    fn wake_on_message(&self) -> bool {
+        false
+    }
+
+    fn terminate(&mut self) {
+        println!(
+            "Synthetic feed: {} ticks published, price ended at {:.2}",
+            self.published,
+            self.market.price()
+        );
+    }
+}
+
+pub struct SyntheticFeedBuilder {
+    symbol: &'static str,
+    write_target_topic_handle: Option<WriteTopicHandle>,
+    config: SyntheticFeedConfig,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+impl SyntheticFeedBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        SyntheticFeedBuilder {
+            symbol,
+            write_target_topic_handle: None,
+            config: SyntheticFeedConfig::default(),
+            start: UNIX_EPOCH,
+            end: UNIX_EPOCH + Duration::from_millis(DAY_MS),
+        }
+    }
+
+    pub fn with_config(mut self, config: SyntheticFeedConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // ticks are published from start until end
+    pub fn with_time_range(mut self, start: SystemTime, end: SystemTime) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+}
+
+impl ModuleBuilder for SyntheticFeedBuilder {
+    fn name(&self) -> &str {
+        "synthetic_feed"
+    }
+
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::DataFeed
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let target_topic = comms.get_topic("market_data");
+        self.write_target_topic_handle = comms.publish_topic(&target_topic).into();
+    }
+
+    fn build(self: Box<SyntheticFeedBuilder>) -> Box<dyn Module> {
+        Box::new(SyntheticFeed {
+            write_market_data_handle: self.write_target_topic_handle.clone().unwrap(),
+            market: SyntheticMarket::new(self.symbol, self.config, millis(self.start)),
+            peeking_tick: None,
+            end_ms: millis(self.end),
+            day: None,
+            published: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(config: SyntheticFeedConfig, n: usize) -> Vec<(u64, Payload)> {
+        let mut market = SyntheticMarket::new("BTCUSDT", config, 0);
+        (0..n).map(|_| market.next_tick()).collect()
+    }
+
+    fn prices(ticks: &[(u64, Payload)]) -> Vec<f64> {
+        ticks
+            .iter()
+            .filter_map(|(_, payload)| match payload {
+                Payload::BinanceBookTicker(tick) => Some(tick.best_bid_price),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_ticks() {
+        let config = SyntheticFeedConfig::default();
+        let a = ticks(config.clone(), 1000);
+        let b = ticks(config.clone(), 1000);
+        assert_eq!(prices(&a), prices(&b));
+        let times = |ticks: &[(u64, Payload)]| ticks.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(times(&a), times(&b));
+        let other = ticks(SyntheticFeedConfig { seed: 1, ..config }, 1000);
+        assert_ne!(prices(&a), prices(&other));
+    }
+
+    #[test]
+    fn test_book_and_trades() {
+        let ticks = ticks(SyntheticFeedConfig::default(), 10000);
+        let mut last_time = 0;
+        let mut book = None;
+        for (time, payload) in &ticks {
+            assert!(*time >= last_time);
+            last_time = *time;
+            match payload {
+                Payload::BinanceBookTicker(tick) => {
+                    assert!(tick.best_bid_price < tick.best_ask_price);
+                    let ticks = tick.best_bid_price / 0.1;
+                    assert!((ticks - ticks.round()).abs() < 1e-6);
+                    assert!(tick.best_bid_qty > 0.0 && tick.best_ask_qty > 0.0);
+                    book = Some((tick.best_bid_price, tick.best_ask_price));
+                }
+                Payload::BinanceTradeTick(trade) => {
+                    let (bid, ask) = book.expect("a trade before the first book ticker");
+                    let touch = if trade.is_buyer_maker { bid } else { ask };
+                    assert_eq!(trade.price, touch);
+                    assert!(trade.qty > 0.0);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_trade_arrivals_are_poisson() {
+        let config = SyntheticFeedConfig {
+            trade_rate: 20.0,
+            ..Default::default()
+        };
+        let mut market = SyntheticMarket::new("BTCUSDT", config, 0);
+        let mut trades = 0;
+        loop {
+            let (time, payload) = market.next_tick();
+            if time >= 1000 * 1000 {
+                break;
+            }
+            if matches!(payload, Payload::BinanceTradeTick(_)) {
+                trades += 1;
+            }
+        }
+        // 20000 expected in 1000 seconds, the standard deviation is about 141
+        assert!((19500..20500).contains(&trades), "{trades} trades");
+    }
+
+    #[test]
+    fn test_ou_reverts_to_initial_price() {
+        let config = SyntheticFeedConfig {
+            process: PriceProcess::OrnsteinUhlenbeck,
+            initial_price: 100.0,
+            volatility: 2.0,
+            half_life: Duration::from_secs(60),
+            trade_rate: 0.0,
+            price_tick: 0.01,
+            ..Default::default()
+        };
+        let mut market = SyntheticMarket::new("BTCUSDT", config, 0);
+        // pushed far away it decays by half within a half life
+        market.log_price = 200f64.ln();
+        while market.next_tick().0 < 60 * 1000 {}
+        let deviation = (market.price() / 100.0).ln();
+        assert!(
+            (deviation - 2f64.ln() / 2.0).abs() < 0.05,
+            "log deviation {deviation}"
+        );
+        // and stays around it
+        let prices = prices(&ticks(market.config.clone(), 100000));
+        let mean = prices.iter().sum::<f64>() / prices.len() as f64;
+        assert!((mean - 100.0).abs() < 2.0, "mean {mean}");
+    }
+
+    #[test]
+    fn test_parse_price_process() {
+        assert_eq!("GBM".parse(), Ok(PriceProcess::Gbm));
+        assert_eq!("ou".parse(), Ok(PriceProcess::OrnsteinUhlenbeck));
+        assert!("brownian".parse::<PriceProcess>().is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use upstair_type::{
+    module::{
+        Module, ModuleBuilder, ModuleComms, ModulePriority, ReadTopicHandle, WriteTopicHandle,
+    },
+    Message, Payload,
+};
+
+// Maps the time of a feed to a run factor times faster, from the feed's first tick on
+#[derive(Debug, Clone, Copy)]
+struct Compression {
+    origin: SystemTime,
+    factor: f64,
+}
+
+impl Compression {
+    fn compress(&self, time: SystemTime) -> SystemTime {
+        match time.duration_since(self.origin) {
+            Ok(elapsed) => self.origin + elapsed.div_f64(self.factor),
+            Err(_) => time,
+        }
+    }
+
+    fn expand(&self, time: SystemTime) -> SystemTime {
+        match time.duration_since(self.origin) {
+            Ok(elapsed) => self.origin + elapsed.mul_f64(self.factor),
+            Err(_) => time,
+        }
+    }
+
+    // milliseconds since the epoch, as in the payloads
+    fn compress_ms(&self, ms: u64) -> u64 {
+        self.compress(UNIX_EPOCH + Duration::from_millis(ms))
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    // the message with its time and the times in its payload compressed, the order of the
+    // messages is kept as published
+    fn message(&self, mut message: Message) -> Message {
+        message.header.commit_at = self.compress(message.header.commit_at);
+        match &mut message.payload {
+            Payload::BinanceTradeTick(tick) => tick.time = self.compress_ms(tick.time),
+            Payload::TradeTickBatch(ticks) => ticks
+                .iter_mut()
+                .for_each(|tick| tick.time = self.compress_ms(tick.time)),
+            Payload::BinanceBookTicker(ticker) => {
+                ticker.transaction_time = self.compress_ms(ticker.transaction_time);
+                ticker.event_time = self.compress_ms(ticker.event_time);
+            }
+            Payload::BinanceAggTrade(trade) => trade.time = self.compress_ms(trade.time),
+            Payload::BinanceKline(kline) => {
+                kline.open_time = self.compress_ms(kline.open_time);
+                kline.close_time = self.compress_ms(kline.close_time);
+            }
+            Payload::MarkPrice(mark) => mark.time = self.compress_ms(mark.time),
+            Payload::DayRoll(roll) => roll.day_start = self.compress(roll.day_start),
+            _ => {}
+        }
+        message
+    }
+}
+
+// The inner module sees the time of its data, the other modules the compressed time
+struct CompressedComms<'a> {
+    inner: &'a mut dyn ModuleComms,
+    compression: Compression,
+    now: SystemTime,
+}
+
+impl ModuleComms for CompressedComms<'_> {
+    fn time(&self) -> SystemTime {
+        self.now
+    }
+
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>> {
+        self.inner.receive_shared(topic)
+    }
+
+    fn publish(&mut self, topic: &WriteTopicHandle, message: Message) {
+        self.inner.publish(topic, self.compression.message(message))
+    }
+
+    fn request_terminate(&mut self) {
+        self.inner.request_terminate()
+    }
+
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        self.inner.next_delivery_at()
+    }
+}
+
+struct TimeCompressedModule {
+    inner: Box<dyn Module>,
+    factor: f64,
+    // set once the inner module knows its first tick
+    compression: Option<Compression>,
+}
+
+impl TimeCompressedModule {
+    fn comms<'a>(&mut self, comms: &'a mut dyn ModuleComms) -> CompressedComms<'a> {
+        let compression = *self.compression.get_or_insert(Compression {
+            origin: comms.time(),
+            factor: self.factor,
+        });
+        let now = comms.time();
+        let mut expanded = compression.expand(now);
+        // woken at the compressed time of its next tick, which the float rounding of expanding
+        // it again must not put off
+        if let Some(next) = self.inner.next_iteration_start_at() {
+            if compression.compress(next) <= now {
+                expanded = expanded.max(next);
+            }
+        }
+        CompressedComms {
+            inner: comms,
+            compression,
+            now: expanded,
+        }
+    }
+}
+
+impl Module for TimeCompressedModule {
+    fn start(&mut self) {
+        self.inner.start();
+        let factor = self.factor;
+        self.compression = self
+            .inner
+            .next_iteration_start_at()
+            .map(|origin| Compression { origin, factor });
+    }
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        let mut comms = self.comms(comms);
+        self.inner.sync(&mut comms)
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let mut comms = self.comms(comms);
+        self.inner.one_iteration(&mut comms)
+    }
+
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        let next = self.inner.next_iteration_start_at()?;
+        Some(match &self.compression {
+            Some(compression) => compression.compress(next),
+            None => next,
+        })
+    }
+
+    fn wake_on_message(&self) -> bool {
+        self.inner.wake_on_message()
+    }
+
+    fn on_shutdown(&mut self, comms: &mut dyn ModuleComms) {
+        let mut comms = self.comms(comms);
+        self.inner.on_shutdown(&mut comms)
+    }
+
+    fn terminate(&mut self) {
+        self.inner.terminate()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.inner.failure()
+    }
+}
+
+// Replays a feed factor times denser, e.g. 10 for ten times the ticks per second of the day,
+// to see whether the engine, the order tracking and the strategy keep up. The ticks keep their
+// order, the modules trading on them run at the compressed time.
+pub struct TimeCompressionBuilder {
+    inner: Box<dyn ModuleBuilder>,
+    factor: f64,
+}
+
+impl TimeCompressionBuilder {
+    pub fn new(inner: Box<dyn ModuleBuilder>, factor: f64) -> Self {
+        assert!(factor > 0.0, "time compression must be positive");
+        TimeCompressionBuilder { inner, factor }
+    }
+}
+
+impl ModuleBuilder for TimeCompressionBuilder {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn priority(&self) -> ModulePriority {
+        self.inner.priority()
+    }
+
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        self.inner.init_comm(comms)
+    }
+
+    fn init_output(&mut self, output: &upstair_type::run_output::RunOutput) {
+        self.inner.init_output(output)
+    }
+
+    fn build(self: Box<TimeCompressionBuilder>) -> Box<dyn Module> {
+        Box::new(TimeCompressedModule {
+            inner: self.inner.build(),
+            factor: self.factor,
+            compression: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use upstair_type::{data::market::BinanceTradeTick, MessageHeader};
+
+    use super::*;
+
+    fn at_ms(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn trade(ms: u64) -> Message {
+        Message {
+            header: MessageHeader {
+                commit_at: at_ms(ms),
+            },
+            payload: Payload::BinanceTradeTick(BinanceTradeTick {
+                id: ms,
+                price: 100.0,
+                qty: 1.0,
+                base_qty: 100.0,
+                time: ms,
+                is_buyer_maker: false,
+                symbol: "BTCUSDT",
+            }),
+        }
+    }
+
+    #[test]
+    fn test_compress_and_expand() {
+        let compression = Compression {
+            origin: at_ms(1_000_000),
+            factor: 10.0,
+        };
+        assert_eq!(compression.compress(at_ms(1_000_000)), at_ms(1_000_000));
+        assert_eq!(compression.compress(at_ms(1_010_000)), at_ms(1_001_000));
+        assert_eq!(compression.expand(at_ms(1_001_000)), at_ms(1_010_000));
+        // before the first tick time is left alone
+        assert_eq!(compression.compress(at_ms(999_000)), at_ms(999_000));
+    }
+
+    #[test]
+    fn test_message_order_kept() {
+        let compression = Compression {
+            origin: at_ms(1_000_000),
+            factor: 10.0,
+        };
+        // ticks 1 ms apart fall on the same millisecond but keep their order
+        let times: Vec<(SystemTime, u64)> = [1_000_000, 1_000_001, 1_000_005, 1_000_020]
+            .into_iter()
+            .map(|ms| {
+                let message = compression.message(trade(ms));
+                match message.payload {
+                    Payload::BinanceTradeTick(tick) => (message.header.commit_at, tick.time),
+                    _ => unreachable!(),
+                }
+            })
+            .collect();
+        assert!(times.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(
+            times.iter().map(|(_, ms)| *ms).collect::<Vec<_>>(),
+            vec![1_000_000, 1_000_000, 1_000_000, 1_000_002]
+        );
+        assert!(times[0].0 < times[1].0);
+    }
+}
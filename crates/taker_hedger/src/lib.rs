@@ -0,0 +1 @@
+pub mod taker_hedger;
@@ -0,0 +1,620 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use account::account::Account;
+use symbol_info::SymbolInfoManager;
+use tracing::{debug, error};
+use upstair_type::{
+    account::AccountUpdate,
+    module::{
+        and_filter, owner_filter, symbol_filter, Module, ModuleBuilder, ModuleComms,
+        ReadTopicHandle, WriteTopicHandle,
+    },
+    order::{OrderRequest, OrderResult, OrderStatus, TimeInForce, TradeSide, TradeType},
+    Message, MessageHeader, Payload,
+};
+
+// dust left over after a hedge is not worth an order
+const MIN_ORDER_QUANTITY: f64 = 1e-6;
+// wait before sending another order once one is rejected
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// A market order on the hedge symbol offsetting the inventory of the maker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hedge {
+    pub side: TradeSide,
+    pub quantity: f64,
+}
+
+// Tracks the inventory the maker strategy takes on against the first known base balance, and
+// the position the hedges took on the hedge symbol against it
+#[derive(Debug)]
+pub struct HedgeMonitor {
+    // base asset the inventory may move away before it is hedged
+    band: f64,
+    base_asset: &'static str,
+    // the assets of the symbol hedged on, those of the maker by default
+    hedge_base_asset: &'static str,
+    hedge_quote_asset: &'static str,
+    // hedge base asset offsetting one base asset of inventory
+    ratio: f64,
+    account: Account,
+    // the last trade price of the hedge symbol
+    last_price: f64,
+    // the first known base balance, hedged back to
+    target: Option<f64>,
+    // the hedge base asset the hedges bought, negative when they sold
+    hedged: f64,
+}
+
+impl HedgeMonitor {
+    pub fn new(band: f64, base_asset: &'static str, quote_asset: &'static str) -> Self {
+        HedgeMonitor {
+            band,
+            base_asset,
+            hedge_base_asset: base_asset,
+            hedge_quote_asset: quote_asset,
+            ratio: 1.0,
+            account: Account::default(),
+            last_price: 0.0,
+            target: None,
+            hedged: 0.0,
+        }
+    }
+
+    // hedge on a correlated symbol of these assets
+    pub fn with_hedge_assets(
+        mut self,
+        base_asset: &'static str,
+        quote_asset: &'static str,
+    ) -> Self {
+        self.hedge_base_asset = base_asset;
+        self.hedge_quote_asset = quote_asset;
+        self
+    }
+
+    pub fn with_ratio(mut self, ratio: f64) -> Self {
+        self.ratio = ratio;
+        self
+    }
+
+    pub fn on_hedge_fill(&mut self, side: &TradeSide, quantity: f64) {
+        match side {
+            TradeSide::Buy => self.hedged += quantity,
+            TradeSide::Sell => self.hedged -= quantity,
+        }
+    }
+
+    pub fn on_account_update(&mut self, update: &AccountUpdate) {
+        for (asset, updated_balance) in update.updates.iter() {
+            let entry = self.account.get_or_create(asset);
+            entry.balance = updated_balance.balance;
+            entry.locked = updated_balance.locked;
+        }
+    }
+
+    pub fn on_trade_price(&mut self, price: f64) {
+        self.last_price = price;
+    }
+
+    pub fn last_price(&self) -> f64 {
+        self.last_price
+    }
+
+    // returns the order offsetting the whole net inventory once it is out of the band, if any
+    pub fn check(&mut self) -> Option<Hedge> {
+        if self.last_price <= 0.0 || self.ratio <= 0.0 {
+            return None;
+        }
+        let base = self.account.asset_to_balance.get(self.base_asset)?.balance;
+        let hedge_base = self
+            .account
+            .asset_to_balance
+            .get(self.hedge_base_asset)?
+            .clone();
+        let hedge_quote = self
+            .account
+            .asset_to_balance
+            .get(self.hedge_quote_asset)?
+            .clone();
+        let mut inventory = base - *self.target.get_or_insert(base);
+        // the hedges of a symbol of the same base asset moved the balance too
+        if self.hedge_base_asset == self.base_asset {
+            inventory -= self.hedged;
+        }
+        // in the hedge base asset, positive when long
+        let exposure = inventory * self.ratio + self.hedged;
+        if (exposure / self.ratio).abs() <= self.band {
+            return None;
+        }
+        // only the free balance can be traded, the quotes of the maker lock the rest
+        let (side, quantity) = if exposure > 0.0 {
+            (
+                TradeSide::Sell,
+                exposure.min(hedge_base.balance - hedge_base.locked),
+            )
+        } else {
+            (
+                TradeSide::Buy,
+                (-exposure).min((hedge_quote.balance - hedge_quote.locked) / self.last_price),
+            )
+        };
+        if quantity < MIN_ORDER_QUANTITY {
+            return None;
+        }
+        Some(Hedge { side, quantity })
+    }
+}
+
+struct TakerHedger {
+    market_data_topic: ReadTopicHandle,
+    account_topic: ReadTopicHandle,
+    order_result_topic: ReadTopicHandle,
+    order_topic: WriteTopicHandle,
+
+    // the symbol hedged on
+    symbol: &'static str,
+    owner: Option<&'static str>,
+    monitor: HedgeMonitor,
+    // wait after a hedge before the next one
+    cooldown: Duration,
+    // one hedge at a time, the next is sized by the account it left
+    pending_order: Option<(Arc<str>, TradeSide)>,
+    next_hedge_at: Option<SystemTime>,
+    order_seq: u64,
+}
+
+impl TakerHedger {
+    fn on_order_result(&mut self, result: &OrderResult, now: SystemTime) {
+        let Some((order_id, side)) = &self.pending_order else {
+            return;
+        };
+        if *order_id != result.client_order_id {
+            return;
+        }
+        if result.filled_quantity > 0.0 {
+            self.monitor.on_hedge_fill(side, result.filled_quantity);
+        }
+        match result.status {
+            OrderStatus::New | OrderStatus::PartiallyFilled => {}
+            OrderStatus::Rejected | OrderStatus::RateLimited => {
+                error!("hedge order {} rejected", result.client_order_id);
+                self.pending_order = None;
+                self.next_hedge_at = Some(now + RETRY_INTERVAL);
+            }
+            _ => {
+                self.pending_order = None;
+                if !self.cooldown.is_zero() {
+                    self.next_hedge_at = Some(now + self.cooldown);
+                }
+            }
+        }
+    }
+}
+
+impl Module for TakerHedger {
+    fn start(&mut self) {}
+
+    fn sync(&mut self, comms: &mut dyn ModuleComms) -> bool {
+        while let Some(msg) = comms.receive_shared(&self.market_data_topic) {
+            if let Some(price) = msg.payload.trade_price() {
+                self.monitor.on_trade_price(price);
+            }
+        }
+        while let Some(msg) = comms.receive(&self.account_topic) {
+            if let Payload::AccountUpdate(update) = msg.payload {
+                self.monitor.on_account_update(&update);
+            }
+        }
+        let now = comms.time();
+        while let Some(msg) = comms.receive(&self.order_result_topic) {
+            for result in msg.payload.order_results() {
+                self.on_order_result(result, now);
+            }
+        }
+        true
+    }
+
+    fn one_iteration(&mut self, comms: &mut dyn ModuleComms) {
+        let now = comms.time();
+        let hedge = self.monitor.check();
+        if self.pending_order.is_some() || self.next_hedge_at.is_some_and(|t| now < t) {
+            return;
+        }
+        self.next_hedge_at = None;
+        let Some(hedge) = hedge else {
+            return;
+        };
+        self.order_seq += 1;
+        // the ids of the strategies sharing a market differ by their owners
+        let client_order_id: Arc<str> = Arc::from(match self.owner {
+            Some(owner) => format!("H{}@{}", self.order_seq, owner),
+            None => format!("H{}", self.order_seq),
+        });
+        debug!(
+            "hedge {:?} {:.5} order_id={}",
+            hedge.side, hedge.quantity, client_order_id
+        );
+        self.pending_order = Some((client_order_id.clone(), hedge.side.clone()));
+        comms.publish(
+            &self.order_topic,
+            Message {
+                header: MessageHeader { commit_at: now },
+                payload: Payload::OrderRequest(OrderRequest {
+                    symbol: self.symbol,
+                    side: hedge.side,
+                    price: self.monitor.last_price(),
+                    quantity: hedge.quantity,
+                    trade_type: TradeType::Market,
+                    time_in_force: TimeInForce::ImmediateOrCancelled,
+                    client_order_id,
+                    cancel_order_id: None,
+                    owner: self.owner,
+                }),
+            },
+        );
+    }
+
+    // the inventory may have left the band while waiting
+    fn next_iteration_start_at(&self) -> Option<SystemTime> {
+        self.next_hedge_at
+    }
+
+    fn wake_on_message(&self) -> bool {
+        true
+    }
+}
+
+// Crosses the spread to take off the inventory a maker strategy on the same account acquires
+// once it leaves the band, the maker quotes and the hedger takes. Ratio times the inventory is
+// taken, on the symbol of the maker or a correlated one, with the cooldown between hedges
+pub struct TakerHedgerBuilder {
+    market_data_topic: Option<ReadTopicHandle>,
+    account_topic: Option<ReadTopicHandle>,
+    order_result_topic: Option<ReadTopicHandle>,
+    order_topic: Option<WriteTopicHandle>,
+
+    symbol: &'static str,
+    hedge_symbol: Option<&'static str>,
+    symbol_info_manager: Option<SymbolInfoManager>,
+    band: f64,
+    ratio: f64,
+    cooldown: Duration,
+    owner: Option<&'static str>,
+}
+
+impl TakerHedgerBuilder {
+    pub fn new(symbol: &'static str) -> Self {
+        TakerHedgerBuilder {
+            market_data_topic: None,
+            account_topic: None,
+            order_result_topic: None,
+            order_topic: None,
+            symbol,
+            hedge_symbol: None,
+            symbol_info_manager: None,
+            band: 0.0,
+            ratio: 1.0,
+            cooldown: Duration::ZERO,
+            owner: None,
+        }
+    }
+
+    // hedge on symbol instead of the symbol of the maker, the engine needs its market data
+    pub fn with_hedge_symbol(mut self, symbol: &'static str) -> Self {
+        self.hedge_symbol = Some(symbol);
+        self
+    }
+
+    // base asset of the hedge symbol traded for each base asset of inventory
+    pub fn with_hedge_ratio(mut self, ratio: f64) -> Self {
+        self.ratio = ratio;
+        self
+    }
+
+    // wait this long after a hedge before sending the next one
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn with_symbol_info_manager(mut self, manager: SymbolInfoManager) -> Self {
+        self.symbol_info_manager = Some(manager);
+        self
+    }
+
+    // base asset the inventory may move away from the first known balance unhedged
+    pub fn with_band(mut self, band: f64) -> Self {
+        self.band = band;
+        self
+    }
+
+    // hedge the account the market agent keeps for owner instead of the shared one
+    pub fn with_owner(mut self, owner: &'static str) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+}
+
+impl ModuleBuilder for TakerHedgerBuilder {
+    fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
+        let market_data_topic = comms.get_topic("market_data");
+        let account_topic = comms.get_topic("account");
+        let order_result_topic = comms.get_topic("order_result");
+        let order_topic = comms.get_topic("order");
+
+        // only the ticks of the symbol hedged on
+        let hedge_symbol = self.hedge_symbol.unwrap_or(self.symbol);
+        self.market_data_topic = comms
+            .subscribe_topic_filtered(&market_data_topic, symbol_filter(hedge_symbol))
+            .into();
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(self.owner))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(
+                &order_result_topic,
+                and_filter(symbol_filter(hedge_symbol), owner_filter(self.owner)),
+            )
+            .into();
+        self.order_topic = comms.publish_topic(&order_topic).into();
+    }
+
+    fn build(self: Box<Self>) -> Box<dyn Module> {
+        let symbol_info_manager = self.symbol_info_manager.unwrap();
+        let symbol_info = symbol_info_manager
+            .get(self.symbol)
+            .expect("symbol in symbol info manager");
+        let hedge_symbol = self.hedge_symbol.unwrap_or(self.symbol);
+        let hedge_symbol_info = symbol_info_manager
+            .get(hedge_symbol)
+            .expect("hedge symbol in symbol info manager");
+        let monitor = HedgeMonitor::new(self.band, symbol_info.base_asset, symbol_info.quote_asset)
+            .with_hedge_assets(hedge_symbol_info.base_asset, hedge_symbol_info.quote_asset)
+            .with_ratio(self.ratio);
+        Box::new(TakerHedger {
+            market_data_topic: self.market_data_topic.unwrap(),
+            account_topic: self.account_topic.unwrap(),
+            order_result_topic: self.order_result_topic.unwrap(),
+            order_topic: self.order_topic.unwrap(),
+            symbol: hedge_symbol,
+            owner: self.owner,
+            monitor,
+            cooldown: self.cooldown,
+            pending_order: None,
+            next_hedge_at: None,
+            order_seq: 0,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "taker_hedger"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use simulation::simulation::SimulationCommsSystem;
+    use upstair_type::{
+        account::AccountAssetUpdate,
+        data::market::BinanceTradeTick,
+        module::{CommsSystem, ModuleBuilder, ModuleCommsBuilder},
+    };
+
+    use super::*;
+
+    fn balances(base: (f64, f64), quote: (f64, f64)) -> AccountUpdate {
+        AccountUpdate {
+            updates: vec![
+                (
+                    "BTC",
+                    AccountAssetUpdate {
+                        balance: base.0,
+                        locked: base.1,
+                    },
+                ),
+                (
+                    "USDT",
+                    AccountAssetUpdate {
+                        balance: quote.0,
+                        locked: quote.1,
+                    },
+                ),
+            ],
+            symbol: None,
+            seq: 0,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn test_hedge_out_of_band() {
+        let mut monitor = HedgeMonitor::new(0.1, "BTC", "USDT");
+        monitor.on_account_update(&balances((1.0, 0.0), (1000.0, 0.0)));
+        // no price yet
+        assert_eq!(monitor.check(), None);
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.check(), None);
+
+        monitor.on_account_update(&balances((1.05, 0.0), (950.0, 0.0)));
+        assert_eq!(monitor.check(), None);
+
+        // the maker bought 0.3, 0.2 of the base balance is locked by its asks
+        monitor.on_account_update(&balances((1.3, 1.2), (700.0, 0.0)));
+        let hedge = monitor.check().unwrap();
+        assert_eq!(hedge.side, TradeSide::Sell);
+        assert!((hedge.quantity - 0.1).abs() < 1e-9);
+        monitor.on_account_update(&balances((1.3, 0.0), (700.0, 0.0)));
+        assert!((monitor.check().unwrap().quantity - 0.3).abs() < 1e-9);
+
+        // the maker sold 0.5, the buy is sized by the free quote asset
+        monitor.on_account_update(&balances((0.5, 0.0), (1500.0, 1200.0)));
+        let hedge = monitor.check().unwrap();
+        assert_eq!(hedge.side, TradeSide::Buy);
+        assert!((hedge.quantity - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hedge_on_correlated_symbol() {
+        let mut monitor = HedgeMonitor::new(0.1, "BTC", "USDT")
+            .with_hedge_assets("ETH", "USDT")
+            .with_ratio(20.0);
+        let mut update = balances((1.0, 0.0), (1000.0, 0.0));
+        update.updates.push((
+            "ETH",
+            AccountAssetUpdate {
+                balance: 10.0,
+                locked: 0.0,
+            },
+        ));
+        monitor.on_account_update(&update);
+        monitor.on_trade_price(50.0);
+        assert_eq!(monitor.check(), None);
+
+        // the maker bought 0.3 BTC, offset by 6 ETH
+        monitor.on_account_update(&balances((1.3, 0.0), (700.0, 0.0)));
+        let hedge = monitor.check().unwrap();
+        assert_eq!(hedge.side, TradeSide::Sell);
+        assert!((hedge.quantity - 6.0).abs() < 1e-9);
+        monitor.on_hedge_fill(&TradeSide::Sell, 6.0);
+        assert_eq!(monitor.check(), None);
+
+        // the maker sold it back, the hedge is bought back
+        monitor.on_account_update(&balances((1.0, 0.0), (1000.0, 0.0)));
+        let hedge = monitor.check().unwrap();
+        assert_eq!(hedge.side, TradeSide::Buy);
+        assert!((hedge.quantity - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hedge_ratio_on_same_symbol() {
+        let mut monitor = HedgeMonitor::new(0.1, "BTC", "USDT").with_ratio(0.5);
+        monitor.on_account_update(&balances((1.0, 0.0), (1000.0, 0.0)));
+        monitor.on_trade_price(1000.0);
+        assert_eq!(monitor.check(), None);
+
+        // half the inventory is hedged, the hedge fill moves the balance too
+        monitor.on_account_update(&balances((1.4, 0.0), (600.0, 0.0)));
+        let hedge = monitor.check().unwrap();
+        assert!((hedge.quantity - 0.2).abs() < 1e-9);
+        monitor.on_hedge_fill(&TradeSide::Sell, 0.2);
+        monitor.on_account_update(&balances((1.2, 0.0), (800.0, 0.0)));
+        assert_eq!(monitor.check(), None);
+    }
+
+    #[test]
+    fn test_cooldown_between_hedges() {
+        let system = SimulationCommsSystem::default();
+        let mut builder = Box::new(
+            TakerHedgerBuilder::new("BTCUSDT")
+                .with_symbol_info_manager(
+                    SymbolInfoManager::default().with_symbol_config("BTCUSDT", "BTC", "USDT", 0.0),
+                )
+                .with_band(0.1)
+                .with_cooldown(Duration::from_secs(5)),
+        );
+        let mut hedger_comms = system.new_builder("taker_hedger");
+        builder.init_comm(&mut hedger_comms);
+        let mut comms = system.new_builder("market_agent");
+        let topics =
+            ["market_data", "account", "order_result", "order"].map(|name| comms.get_topic(name));
+        let market_data = comms.publish_topic(&topics[0]);
+        let account = comms.publish_topic(&topics[1]);
+        let order_result = comms.publish_topic(&topics[2]);
+        let order = comms.subscribe_topic(&topics[3]);
+        let mut hedger = builder.build();
+        let mut hedger_comms = hedger_comms.build();
+        let mut comms = comms.build();
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let message = |payload, time| Message {
+            header: MessageHeader { commit_at: time },
+            payload,
+        };
+        let mut step = |comms: &mut Box<dyn ModuleComms>, secs| {
+            system.time_provider.set_time(at(secs));
+            hedger.sync(hedger_comms.as_mut());
+            hedger.one_iteration(hedger_comms.as_mut());
+            let orders: Vec<_> = std::iter::from_fn(|| comms.receive(&order))
+                .filter_map(|msg| match msg.payload {
+                    Payload::OrderRequest(req) => Some(req),
+                    _ => None,
+                })
+                .collect();
+            (orders, hedger.next_iteration_start_at())
+        };
+
+        comms.publish(
+            &market_data,
+            message(
+                Payload::BinanceTradeTick(BinanceTradeTick {
+                    price: 1000.0,
+                    qty: 1.0,
+                    symbol: "BTCUSDT",
+                    ..Default::default()
+                }),
+                at(1),
+            ),
+        );
+        comms.publish(
+            &account,
+            message(
+                Payload::AccountUpdate(balances((1.0, 0.0), (1000.0, 0.0))),
+                at(1),
+            ),
+        );
+        assert!(step(&mut comms, 1).0.is_empty());
+
+        // the maker bought 0.3, hedged at once
+        comms.publish(
+            &account,
+            message(
+                Payload::AccountUpdate(balances((1.3, 0.0), (700.0, 0.0))),
+                at(2),
+            ),
+        );
+        let (orders, _) = step(&mut comms, 2);
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].side, TradeSide::Sell);
+        assert!((orders[0].quantity - 0.3).abs() < 1e-9);
+        comms.publish(
+            &order_result,
+            message(
+                Payload::OrderResult(OrderResult {
+                    symbol: "BTCUSDT",
+                    at: at(3),
+                    client_order_id: orders[0].client_order_id.clone(),
+                    filled_quantity: 0.3,
+                    price: 1000.0,
+                    is_buy: false,
+                    status: OrderStatus::Filled,
+                    seq: 1,
+                    owner: None,
+                    reject_reason: None,
+                }),
+                at(3),
+            ),
+        );
+        // the maker bought another 0.3 right after the fill
+        comms.publish(
+            &account,
+            message(
+                Payload::AccountUpdate(balances((1.3, 0.0), (1000.0, 0.0))),
+                at(3),
+            ),
+        );
+        let (orders, next_at) = step(&mut comms, 3);
+        assert!(orders.is_empty());
+        assert_eq!(next_at, Some(at(8)));
+        assert!(step(&mut comms, 7).0.is_empty());
+
+        // the next hedge waits out the cooldown
+        let (orders, next_at) = step(&mut comms, 8);
+        assert_eq!(orders.len(), 1);
+        assert!((orders[0].quantity - 0.3).abs() < 1e-9);
+        assert_eq!(next_at, None);
+    }
+}
@@ -7,4 +7,18 @@ pub struct AccountAssetUpdate {
 #[derive(Debug, Clone)]
 pub struct AccountUpdate {
     pub updates: Vec<(&'static str, AccountAssetUpdate)>,
+    // symbol whose fill changed the balances, None for account summaries
+    pub symbol: Option<&'static str>,
+    // sequence number in the messages of symbol and owner, 0 for account summaries
+    pub seq: u64,
+    // the strategy whose account it is, see OrderRequest
+    pub owner: Option<&'static str>,
+}
+
+// Value of an account in USDT at the last trade prices, sent after the account summaries when
+// they are sampled at a fixed interval
+#[derive(Debug, Clone)]
+pub struct EquitySnapshot {
+    pub equity: f64,
+    pub owner: Option<&'static str>,
 }
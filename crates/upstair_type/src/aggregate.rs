@@ -0,0 +1,68 @@
+use crate::data::market::BinanceTradeTick;
+
+// The trades of one taker order at one price, as in the aggTrades files of Binance. Far
+// smaller than the trades files, enough when the single fills do not matter.
+#[derive(Debug, Clone, Default)]
+pub struct BinanceAggTrade {
+    pub agg_trade_id: u64,
+    pub price: f64,
+    pub qty: f64,
+    pub first_trade_id: u64,
+    pub last_trade_id: u64,
+    pub time: u64,
+    pub is_buyer_maker: bool,
+    pub symbol: &'static str,
+}
+
+impl BinanceAggTrade {
+    // the aggregated trades as one trade, for the modules working on trades
+    pub fn to_trade_tick(&self) -> BinanceTradeTick {
+        BinanceTradeTick {
+            id: self.agg_trade_id,
+            price: self.price,
+            qty: self.qty,
+            base_qty: self.price * self.qty,
+            time: self.time,
+            is_buyer_maker: self.is_buyer_maker,
+            symbol: self.symbol,
+        }
+    }
+}
+
+// One candle of the klines files of Binance, published at its close time so nothing of the
+// candle is known before it ends
+#[derive(Debug, Clone, Default)]
+pub struct BinanceKline {
+    pub open_time: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub close_time: u64,
+    pub quote_volume: f64,
+    pub count: u64,
+    pub taker_buy_volume: f64,
+    pub taker_buy_quote_volume: f64,
+    pub symbol: &'static str,
+}
+
+// The mark price of a futures symbol at the close of a candle of the markPriceKlines files of
+// Binance, with the index price of the last candle of the indexPriceKlines files up to it
+// when those are replayed as well
+#[derive(Debug, Clone, Default)]
+pub struct MarkPrice {
+    pub mark_price: f64,
+    pub index_price: Option<f64>,
+    // in ms
+    pub time: u64,
+    pub symbol: &'static str,
+}
+
+impl MarkPrice {
+    // the mark price over the index price, None without an index price
+    pub fn basis(&self) -> Option<f64> {
+        self.index_price
+            .map(|index_price| self.mark_price - index_price)
+    }
+}
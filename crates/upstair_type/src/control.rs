@@ -0,0 +1,54 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+// Stops strategies from placing new orders, open orders are cancelled
+#[derive(Debug, Clone)]
+pub struct TradingHalt {
+    pub reason: String,
+}
+
+// A new UTC day starts in the market data, published before its first tick so modules can
+// reset per-session state and cut daily reports
+#[derive(Debug, Clone)]
+pub struct DayRoll {
+    // midnight the new day starts at
+    pub day_start: SystemTime,
+}
+
+// An order request the exchange did not answer within the reconcile timeout
+#[derive(Debug, Clone)]
+pub struct StaleOrderReport {
+    pub symbol: &'static str,
+    pub client_order_id: Arc<str>,
+    pub pending_for: Duration,
+    // cancels already re-issued for the order
+    pub retries: u32,
+    // the order is given up and no longer tracked
+    pub errored: bool,
+}
+
+// What the validation of the market data found wrong with the ticks of a symbol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DataQualityFlags {
+    // no book ticker within the stale timeout, the best bid/ask may be far off
+    pub stale_book: bool,
+    // trade ids were skipped, trades are missing from the data
+    pub gap_detected: bool,
+    // the last trade was too far from the price and was not published
+    pub outlier_filtered: bool,
+}
+
+impl DataQualityFlags {
+    pub fn is_degraded(&self) -> bool {
+        self.stale_book || self.gap_detected || self.outlier_filtered
+    }
+}
+
+// Published on the market data topic whenever the quality flags of a symbol change
+#[derive(Debug, Clone)]
+pub struct DataQuality {
+    pub symbol: &'static str,
+    pub flags: DataQualityFlags,
+}
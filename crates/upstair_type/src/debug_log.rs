@@ -0,0 +1,31 @@
+// A quote placed by a strategy and the book it was placed against
+#[derive(Debug, Clone, Default)]
+pub struct QuoteDebug {
+    pub time_ms: u64,
+    pub order_id: String,
+    pub is_bid: bool,
+    pub price: f64,
+    pub qty: f64,
+    pub fair_price: f64,
+    pub best_bid_price: f64,
+    pub best_bid_qty: f64,
+    pub best_ask_price: f64,
+    pub best_ask_qty: f64,
+}
+
+#[derive(Debug, Clone)]
+pub enum DebugRecord {
+    // the volatility estimate after an update
+    Vol { time_ms: u64, vol: f64 },
+    Quote(QuoteDebug),
+    // the quantity of an order filled so far when the strategy saw a fill of it
+    Fill { order_id: String, filled: f64 },
+}
+
+// A detailed record of what a strategy did, published on the debug_log topic for the sinks
+// writing them out
+#[derive(Debug, Clone)]
+pub struct DebugLog {
+    pub symbol: &'static str,
+    pub record: DebugRecord,
+}
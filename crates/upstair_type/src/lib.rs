@@ -1,19 +1,117 @@
 pub mod account;
+pub mod aggregate;
 use std::time::SystemTime;
 
+pub mod control;
 pub mod data;
+pub mod debug_log;
 pub mod module;
 pub mod order;
+pub mod run_output;
+pub mod signal;
+pub mod strategy;
 pub mod time;
 
 #[derive(Debug, Clone)]
 pub enum Payload {
     BinanceTradeTick(data::market::BinanceTradeTick),
+    // consecutive trades of a symbol published at the time of the last one, see
+    // BinanceRepublisherBuilder::with_trade_batch_window
+    TradeTickBatch(Vec<data::market::BinanceTradeTick>),
     OrderRequest(order::OrderRequest),
     CancelOrderRequest(order::CancelOrderRequest),
+    BatchOrderRequest(order::BatchOrderRequest),
+    BatchCancelRequest(order::BatchCancelRequest),
     OrderResult(order::OrderResult),
+    OrderResultBatch(order::OrderResultBatch),
+    CancelReject(order::CancelReject),
+    RateLimitUsage(order::RateLimitUsage),
     AccountUpdate(account::AccountUpdate),
     BinanceBookTicker(data::market::BinanceBookTicker),
+    TradingHalt(control::TradingHalt),
+    StaleOrderReport(control::StaleOrderReport),
+    ResyncRequest(order::ResyncRequest),
+    ResyncSnapshot(order::ResyncSnapshot),
+    DayRoll(control::DayRoll),
+    DataQuality(control::DataQuality),
+    StrategyDebug(strategy::StrategyDebug),
+    BinanceAggTrade(aggregate::BinanceAggTrade),
+    BinanceKline(aggregate::BinanceKline),
+    MarkPrice(aggregate::MarkPrice),
+    SignalUpdate(signal::SignalUpdate),
+    EquitySnapshot(account::EquitySnapshot),
+    DebugLog(debug_log::DebugLog),
+}
+
+impl Payload {
+    // the symbol the payload is about, None for account wide payloads
+    pub fn symbol(&self) -> Option<&'static str> {
+        match self {
+            Payload::BinanceTradeTick(tick) => Some(tick.symbol),
+            Payload::TradeTickBatch(ticks) => ticks.first().map(|tick| tick.symbol),
+            Payload::OrderRequest(req) => Some(req.symbol),
+            Payload::CancelOrderRequest(req) => Some(req.symbol),
+            Payload::BatchOrderRequest(batch) => Some(batch.symbol),
+            Payload::BatchCancelRequest(batch) => Some(batch.symbol),
+            Payload::OrderResult(result) => Some(result.symbol),
+            Payload::OrderResultBatch(batch) => Some(batch.symbol),
+            Payload::CancelReject(reject) => Some(reject.symbol),
+            Payload::RateLimitUsage(usage) => Some(usage.symbol),
+            Payload::BinanceBookTicker(ticker) => Some(ticker.symbol),
+            Payload::StaleOrderReport(report) => Some(report.symbol),
+            Payload::ResyncRequest(req) => Some(req.symbol),
+            Payload::ResyncSnapshot(snapshot) => Some(snapshot.symbol),
+            Payload::DataQuality(quality) => Some(quality.symbol),
+            Payload::StrategyDebug(debug) => Some(debug.symbol),
+            Payload::BinanceAggTrade(trade) => Some(trade.symbol),
+            Payload::BinanceKline(kline) => Some(kline.symbol),
+            Payload::MarkPrice(mark) => Some(mark.symbol),
+            Payload::SignalUpdate(signal) => Some(signal.symbol),
+            Payload::DebugLog(log) => Some(log.symbol),
+            Payload::AccountUpdate(update) => update.symbol,
+            Payload::TradingHalt(_) | Payload::DayRoll(_) | Payload::EquitySnapshot(_) => None,
+        }
+    }
+
+    // the strategy owning the order or account the payload is about, None for the payloads
+    // of the strategies without an owner and the ones about no order or account
+    pub fn owner(&self) -> Option<&'static str> {
+        match self {
+            Payload::OrderRequest(req) => req.owner,
+            Payload::CancelOrderRequest(req) => req.owner,
+            Payload::BatchOrderRequest(batch) => batch.owner,
+            Payload::BatchCancelRequest(batch) => batch.owner,
+            Payload::OrderResult(result) => result.owner,
+            Payload::OrderResultBatch(batch) => batch.owner,
+            Payload::CancelReject(reject) => reject.owner,
+            Payload::RateLimitUsage(usage) => usage.owner,
+            Payload::AccountUpdate(update) => update.owner,
+            Payload::EquitySnapshot(snapshot) => snapshot.owner,
+            Payload::ResyncRequest(req) => req.owner,
+            Payload::ResyncSnapshot(snapshot) => snapshot.account.owner,
+            _ => None,
+        }
+    }
+
+    // the order results of the payload, one or those of a batch
+    pub fn order_results(&self) -> &[order::OrderResult] {
+        match self {
+            Payload::OrderResult(result) => std::slice::from_ref(result),
+            Payload::OrderResultBatch(batch) => &batch.results,
+            _ => &[],
+        }
+    }
+
+    // the last traded price of the market data, None for the other payloads
+    pub fn trade_price(&self) -> Option<f64> {
+        match self {
+            Payload::BinanceTradeTick(tick) => Some(tick.price),
+            Payload::TradeTickBatch(ticks) => ticks.last().map(|tick| tick.price),
+            Payload::BinanceAggTrade(trade) => Some(trade.price),
+            Payload::BinanceKline(kline) => Some(kline.close),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
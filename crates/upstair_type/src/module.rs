@@ -1,6 +1,6 @@
-use std::time::SystemTime;
+use std::{sync::Arc, time::SystemTime};
 
-use crate::Message;
+use crate::{run_output::RunOutput, Message};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TopicId {
@@ -22,18 +22,66 @@ pub struct ReadTopicHandle {
     pub slot: usize,
 }
 
+// Decides which messages of a topic a subscriber receives
+pub type MessageFilter = Arc<dyn Fn(&Message) -> bool + Send + Sync>;
+
+// passes the messages about symbol and the account wide ones
+pub fn symbol_filter(symbol: &'static str) -> MessageFilter {
+    Arc::new(move |message| message.payload.symbol().is_none_or(|s| s == symbol))
+}
+
+// passes the messages about any of symbols and the account wide ones
+pub fn symbols_filter(symbols: Vec<&'static str>) -> MessageFilter {
+    Arc::new(move |message| {
+        message
+            .payload
+            .symbol()
+            .is_none_or(|s| symbols.contains(&s))
+    })
+}
+
+// passes the messages about the orders and account of owner, for None those of no owner
+pub fn owner_filter(owner: Option<&'static str>) -> MessageFilter {
+    Arc::new(move |message| message.payload.owner() == owner)
+}
+
+// passes the messages both filters pass
+pub fn and_filter(a: MessageFilter, b: MessageFilter) -> MessageFilter {
+    Arc::new(move |message| a(message) && b(message))
+}
+
+// the topic of a module run in a namespace, e.g. account@b for the account of strategy b
+pub fn namespaced_topic(name: &str, namespace: &str) -> String {
+    format!("{name}@{namespace}")
+}
+
 // Each module has its own ModuleComms instance for communication with other modules.
 pub trait ModuleComms {
     fn time(&self) -> SystemTime;
-    fn receive(&mut self, topic: &ReadTopicHandle) -> Option<Message>;
+    // the message as published, shared with the other subscribers of the topic
+    fn receive_shared(&mut self, topic: &ReadTopicHandle) -> Option<Arc<Message>>;
+    // moves the message out when no other subscriber holds it, copies it otherwise
+    fn receive(&mut self, topic: &ReadTopicHandle) -> Option<Message> {
+        self.receive_shared(topic).map(Arc::unwrap_or_clone)
+    }
     fn publish(&mut self, topic: &WriteTopicHandle, message: Message);
     fn request_terminate(&mut self);
+    // when a message held back by the transport becomes receivable
+    fn next_delivery_at(&self) -> Option<SystemTime> {
+        None
+    }
 }
 
 pub trait ModuleCommsBuilder {
     fn get_module_id(&self) -> &ModuleId;
     fn get_topic(&mut self, name: &str) -> TopicId;
     fn subscribe_topic(&mut self, topic: &TopicId) -> ReadTopicHandle;
+    // messages the filter rejects are not delivered to the module at all
+    fn subscribe_topic_filtered(
+        &mut self,
+        topic: &TopicId,
+        filter: MessageFilter,
+    ) -> ReadTopicHandle;
     fn publish_topic(&mut self, topic: &TopicId) -> WriteTopicHandle;
 
     fn build(self) -> Box<dyn ModuleComms>;
@@ -69,11 +117,37 @@ pub trait Module {
     fn one_iteration(&mut self, comms: &mut dyn ModuleComms);
     fn next_iteration_start_at(&self) -> Option<SystemTime>;
     fn wake_on_message(&self) -> bool;
+    // once the world stops, when the engine has a shutdown phase, e.g. to cancel the open
+    // orders and flatten before the final report. The modules keep running for its length
+    fn on_shutdown(&mut self, _comms: &mut dyn ModuleComms) {}
     fn terminate(&mut self) {}
+    // why the module ended the run early, e.g. on input it could not replay. The run fails
+    // instead of finishing on the data seen so far
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+// The order the engine runs the modules scheduled at the same time in: the market data first,
+// then the exchange filling the orders on it, then the strategies and last the modules
+// watching the others. The modules of a priority run in the order they are added.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ModulePriority {
+    DataFeed,
+    Exchange,
+    #[default]
+    Strategy,
+    Observer,
 }
 
 pub trait ModuleBuilder {
     fn init_comm(&mut self, comms: &mut dyn ModuleCommsBuilder);
+    // the directory of the run, before init_comm, for the modules writing files. Not called
+    // when the run has none
+    fn init_output(&mut self, _output: &RunOutput) {}
     fn build(self: Box<Self>) -> Box<dyn Module>;
     fn name(&self) -> &str;
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::default()
+    }
 }
@@ -1,5 +1,11 @@
 use std::sync::Arc;
 
+use crate::account::AccountUpdate;
+
+// client order id prefix of orders sent to enforce hard risk limits, their fills are tagged
+// as liquidations in the results
+pub const LIQUIDATION_ORDER_PREFIX: &str = "L";
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum TradeSide {
     Buy,
@@ -11,6 +17,12 @@ pub enum TradeType {
     Limit,
     LimitMaker,
     Market,
+    // fills at market once the last trade price reaches trigger_price,
+    // falling for sell orders and rising for buy orders
+    StopMarket { trigger_price: f64 },
+    // rests as a limit order at price once the last trade price reaches trigger_price,
+    // rising for sell orders and falling for buy orders
+    TakeProfit { trigger_price: f64 },
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +30,8 @@ pub enum TimeInForce {
     GoodTilCancelled,
     ImmediateOrCancelled,
     FillOrKill,
+    // rests until filled, canceled or the deadline, when the exchange expires it
+    GoodTilDate(std::time::SystemTime),
 }
 
 #[derive(Debug, Clone)]
@@ -30,12 +44,57 @@ pub struct OrderRequest {
     pub time_in_force: TimeInForce,
     pub client_order_id: Arc<str>,
     pub cancel_order_id: Option<Arc<str>>,
+    // the strategy whose account the order trades, None for the account of the ones without
+    // an owner. Client order ids are unique across the owners of a market.
+    pub owner: Option<&'static str>,
 }
 
 #[derive(Debug, Clone)]
 pub struct CancelOrderRequest {
     pub symbol: &'static str,
     pub client_order_id: Arc<str>,
+    // the strategy of the order, the one answered when the cancel is rejected
+    pub owner: Option<&'static str>,
+}
+
+// Why the exchange did not cancel an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelRejectReason {
+    // the order request is still on its way, the order rests once it arrives
+    NotYetPlaced,
+    // filled before the cancel arrived
+    AlreadyFilled,
+    // no order of the id is open, e.g. canceled or expired already
+    UnknownOrder,
+    UnknownSymbol,
+    // over the cancel rate limit of the exchange, the order rests
+    RateLimited,
+    // the order is of another owner, whose account it stays in
+    NotOwner,
+}
+
+impl CancelRejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelRejectReason::NotYetPlaced => "not_yet_placed",
+            CancelRejectReason::AlreadyFilled => "already_filled",
+            CancelRejectReason::UnknownOrder => "unknown_order",
+            CancelRejectReason::UnknownSymbol => "unknown_symbol",
+            CancelRejectReason::RateLimited => "rate_limited",
+            CancelRejectReason::NotOwner => "not_owner",
+        }
+    }
+}
+
+// Answer to a CancelOrderRequest the exchange did not carry out. The orders it cancels are
+// answered by an OrderResult of status Canceled.
+#[derive(Debug, Clone)]
+pub struct CancelReject {
+    pub symbol: &'static str,
+    pub at: std::time::SystemTime,
+    pub client_order_id: Arc<str>,
+    pub reason: CancelRejectReason,
+    pub owner: Option<&'static str>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -45,10 +104,48 @@ pub enum OrderStatus {
     Filled,
     Canceled,
     Rejected,
+    // rejected for going over the order rate limit of the exchange, retry once the window
+    // frees up, see RateLimitUsage
+    RateLimited,
     Expired,
     ExpiredInMatch,
 }
 
+// Why the exchange rejected an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    // the account can not lock the balance the order pays with
+    InsufficientBalance,
+    // a price or trigger price of zero or less
+    BadPrice,
+    // a quantity of zero or less
+    FilterViolation,
+    // a limit maker order which would take liquidity
+    PostOnlyCross,
+    UnknownSymbol,
+    // a market order before the exchange has a book to fill it on
+    NoBook,
+    // an order matching the resting orders of its account, see SelfTradePrevention::Reject
+    SelfTrade,
+    // over the order rate limit of the exchange
+    RateLimited,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::InsufficientBalance => "insufficient_balance",
+            RejectReason::BadPrice => "bad_price",
+            RejectReason::FilterViolation => "filter_violation",
+            RejectReason::PostOnlyCross => "post_only_cross",
+            RejectReason::UnknownSymbol => "unknown_symbol",
+            RejectReason::NoBook => "no_book",
+            RejectReason::SelfTrade => "self_trade",
+            RejectReason::RateLimited => "rate_limited",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct OrderResult {
     pub symbol: &'static str,
@@ -58,4 +155,79 @@ pub struct OrderResult {
     pub price: f64,
     pub is_buy: bool,
     pub status: OrderStatus,
+    // sequence number in the messages of symbol and owner, see ResyncRequest
+    pub seq: u64,
+    pub owner: Option<&'static str>,
+    // why the order was rejected, None for the other statuses
+    pub reject_reason: Option<RejectReason>,
+}
+
+// The most requests a batch carries, as the batchOrders endpoint of Binance
+pub const MAX_BATCH_ORDERS: usize = 5;
+
+// Orders placed in one request, the exchange places them in order at the time the batch
+// arrives and answers them with one OrderResultBatch
+#[derive(Debug, Clone)]
+pub struct BatchOrderRequest {
+    pub symbol: &'static str,
+    pub orders: Vec<OrderRequest>,
+    pub owner: Option<&'static str>,
+}
+
+// Orders canceled in one request, answered as a BatchOrderRequest
+#[derive(Debug, Clone)]
+pub struct BatchCancelRequest {
+    pub symbol: &'static str,
+    pub cancels: Vec<CancelOrderRequest>,
+    pub owner: Option<&'static str>,
+}
+
+// The answers to the requests of a batch, in their order. The results of the orders placed or
+// canceled are numbered one after the other, see ResyncRequest.
+#[derive(Debug, Clone)]
+pub struct OrderResultBatch {
+    pub symbol: &'static str,
+    pub results: Vec<OrderResult>,
+    pub cancel_rejects: Vec<CancelReject>,
+    pub owner: Option<&'static str>,
+}
+
+// The requests of an account in the rate limit windows of the exchange, sent after each of its
+// requests when the exchange limits them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitUsage {
+    // of the request answered
+    pub symbol: &'static str,
+    pub at: std::time::SystemTime,
+    // (used, limit) of the order and cancel requests, None when not limited
+    pub orders: Option<(u32, u32)>,
+    pub cancels: Option<(u32, u32)>,
+    pub owner: Option<&'static str>,
+}
+
+// Asks the exchange for its open orders and balances after a gap in the sequence numbers of
+// symbol. The order results and balance updates of a symbol are numbered from 1 in the order
+// the exchange sends them, a missing number means a message was lost.
+#[derive(Debug, Clone)]
+pub struct ResyncRequest {
+    pub symbol: &'static str,
+    pub owner: Option<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenOrder {
+    pub client_order_id: Arc<str>,
+    pub price: f64,
+    pub quantity: f64,
+    pub filled: f64,
+    pub is_buy: bool,
+}
+
+// Answer to a ResyncRequest, the state after the message numbered seq
+#[derive(Debug, Clone)]
+pub struct ResyncSnapshot {
+    pub symbol: &'static str,
+    pub seq: u64,
+    pub open_orders: Vec<OpenOrder>,
+    pub account: AccountUpdate,
 }
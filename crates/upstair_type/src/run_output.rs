@@ -0,0 +1,121 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// The directory of one run. The reports, debug logs, exports and audit logs of its modules
+// are written under it, the engine builder creates it and hands it to the module builders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutput {
+    dir: PathBuf,
+}
+
+// yyyymmddThhmmss of the UTC time
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // the civil date of the days since 1970-01-01, proleptic Gregorian
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+impl RunOutput {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        RunOutput { dir: dir.into() }
+    }
+
+    // <root>/<timestamp>-<label> of the UTC time the run started, e.g.
+    // runs/20240101T093000-wide, or without the label when it is empty. The directory is
+    // created to claim it, a run of the same second and label gets the next free suffix,
+    // e.g. runs/20240101T093000-wide-2
+    pub fn timestamped(root: &Path, label: &str, started_at: SystemTime) -> std::io::Result<Self> {
+        let timestamp = utc_timestamp(started_at);
+        let name = if label.is_empty() {
+            timestamp
+        } else {
+            format!("{}-{}", timestamp, label)
+        };
+        std::fs::create_dir_all(root)?;
+        let mut dir = root.join(&name);
+        let mut suffix = 1;
+        loop {
+            match std::fs::create_dir(&dir) {
+                Ok(()) => return Ok(RunOutput::new(dir)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    suffix += 1;
+                    dir = root.join(format!("{}-{}", name, suffix));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // a directory of the output for one kind of files, e.g. vis
+    pub fn subdir(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // the output of the modules run in a namespace, the namespaces being the variants
+    // compared in a run
+    pub fn namespaced(&self, namespace: &str) -> RunOutput {
+        RunOutput::new(self.dir.join(format!("variant-{}", namespace)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_run_output_dirs() {
+        let root = std::env::temp_dir().join(format!("run_output_{}", std::process::id()));
+        // 2024-02-29 09:30:05 UTC
+        let at = UNIX_EPOCH + Duration::from_secs(1_709_199_005);
+        let output = RunOutput::timestamped(&root, "wide", at).unwrap();
+        assert_eq!(output.dir(), root.join("20240229T093005-wide"));
+        assert!(output.dir().is_dir());
+        assert_eq!(
+            RunOutput::timestamped(&root, "", UNIX_EPOCH).unwrap().dir(),
+            root.join("19700101T000000")
+        );
+        assert_eq!(
+            output.namespaced("tight").subdir("vis"),
+            root.join("20240229T093005-wide/variant-tight/vis")
+        );
+
+        // runs of the same second and label each get a directory of their own
+        let dirs: Vec<_> = (0..2)
+            .map(|_| RunOutput::timestamped(&root, "wide", at).unwrap())
+            .collect();
+        assert_eq!(dirs[0].dir(), root.join("20240229T093005-wide-2"));
+        assert_eq!(dirs[1].dir(), root.join("20240229T093005-wide-3"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+// The indicators published on the signals topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignalKind {
+    // stdev of the sampled trade price changes
+    Volatility,
+    // (bid qty - ask qty) / (bid qty + ask qty) of the best levels, in [-1, 1]
+    Imbalance,
+    // relative trade price change over the momentum window
+    Momentum,
+    // quantity added at the best bid and taken from the best ask minus the opposite, summed
+    // over a window
+    OrderFlowImbalance,
+    // lag one autocorrelation of the taker sides of the last trades
+    TradeSignAutocorrelation,
+    // mid price adjusted by the mid price changes that followed the book imbalance
+    Microprice,
+}
+
+impl SignalKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignalKind::Volatility => "volatility",
+            SignalKind::Imbalance => "imbalance",
+            SignalKind::Momentum => "momentum",
+            SignalKind::OrderFlowImbalance => "order_flow_imbalance",
+            SignalKind::TradeSignAutocorrelation => "trade_sign_autocorrelation",
+            SignalKind::Microprice => "microprice",
+        }
+    }
+}
+
+// The latest value of an indicator of a symbol, computed by an indicator module so the
+// strategies do not each recompute it from the ticks
+#[derive(Debug, Clone)]
+pub struct SignalUpdate {
+    pub symbol: &'static str,
+    pub kind: SignalKind,
+    pub value: f64,
+}
@@ -0,0 +1,31 @@
+// What a market making strategy quoted in one round and the inputs it quoted from
+#[derive(Debug, Clone, Default)]
+pub struct StrategyDebug {
+    pub symbol: &'static str,
+    // rolling volatility of the price
+    pub vol: f64,
+    pub fair_price: f64,
+    pub reservation_price: f64,
+    pub bid: f64,
+    pub ask: f64,
+    pub best_bid: f64,
+    pub best_ask: f64,
+    // base asset balance, and the balances the inventory skew is clamped between
+    pub base_balance: f64,
+    pub initial_position: f64,
+    pub low_water_level: f64,
+    pub high_water_level: f64,
+    // false while a side is not quoted to keep the inventory within its limits
+    pub quoting_bid: bool,
+    pub quoting_ask: bool,
+}
+
+impl StrategyDebug {
+    pub fn quoted_spread(&self) -> f64 {
+        self.ask - self.bid
+    }
+
+    pub fn market_spread(&self) -> f64 {
+        self.best_ask - self.best_bid
+    }
+}
@@ -1,8 +1,15 @@
 use std::{
-    sync::{atomic::AtomicU64, Arc},
+    sync::{atomic::AtomicU64, Arc, Condvar, Mutex},
     time::{SystemTime, UNIX_EPOCH},
 };
 
+// milliseconds since the epoch, 0 before it
+pub fn to_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 pub trait TimeProvider {
     fn time(&self) -> SystemTime;
 }
@@ -38,6 +45,97 @@ impl SimulationTime {
     }
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlaybackState {
+    // simulation seconds per wall second, None runs as fast as possible
+    pub speed: Option<f64>,
+    pub paused: bool,
+    // events still to run while paused
+    pub steps: u64,
+    // runs as fast as possible until then and pauses
+    pub fast_forward_to: Option<SystemTime>,
+}
+
+// Lets a UI throttle, pause, step and fast-forward a simulation. The engine calls wait_turn
+// before each event, the UI the other methods from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct PlaybackControl {
+    inner: Arc<(Mutex<PlaybackState>, Condvar)>,
+}
+
+impl PlaybackControl {
+    pub fn with_speed(speed: Option<f64>) -> Self {
+        let control = PlaybackControl::default();
+        control.set_speed(speed);
+        control
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.inner.0.lock().unwrap().clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PlaybackState)) {
+        let (state, resumed) = &*self.inner;
+        f(&mut state.lock().unwrap());
+        resumed.notify_all();
+    }
+
+    pub fn set_speed(&self, speed: Option<f64>) {
+        self.update(|state| state.speed = speed.filter(|speed| *speed > 0.0));
+    }
+
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| {
+            state.paused = false;
+            state.steps = 0;
+        });
+    }
+
+    // run one more event while paused
+    pub fn step(&self) {
+        self.update(|state| state.steps += 1);
+    }
+
+    pub fn fast_forward_to(&self, time: SystemTime) {
+        self.update(|state| {
+            state.fast_forward_to = Some(time);
+            state.paused = false;
+        });
+    }
+
+    // stops throttling and pausing for good, e.g. when the UI is closed
+    pub fn release(&self) {
+        self.update(|state| *state = PlaybackState::default());
+    }
+
+    // Blocks while paused, until the next step or resume. Returns the speed to run the event
+    // at time with and whether it waited.
+    pub fn wait_turn(&self, time: SystemTime) -> (Option<f64>, bool) {
+        let (state, resumed) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        if let Some(to) = state.fast_forward_to {
+            if time < to {
+                return (None, false);
+            }
+            state.fast_forward_to = None;
+            state.paused = true;
+        }
+        let mut waited = false;
+        while state.paused && state.steps == 0 {
+            waited = true;
+            state = resumed.wait(state).unwrap();
+        }
+        if state.paused {
+            state.steps -= 1;
+        }
+        (state.speed, waited)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -75,4 +173,34 @@ mod tests {
         is_send::<SimulationTime>();
         is_send::<&SimulationTime>();
     }
+
+    #[test]
+    fn test_playback_control() {
+        use super::*;
+        let at = |secs| UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        let control = PlaybackControl::with_speed(Some(60.0));
+        assert_eq!(control.wait_turn(at(1)), (Some(60.0), false));
+
+        control.pause();
+        control.step();
+        control.step();
+        assert_eq!(control.wait_turn(at(2)), (Some(60.0), false));
+        assert_eq!(control.wait_turn(at(3)), (Some(60.0), false));
+        let ui = control.clone();
+        let resume = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            ui.fast_forward_to(at(10));
+        });
+        // blocks until the fast forward resumes it, and runs at full speed until 10
+        assert_eq!(control.wait_turn(at(4)), (Some(60.0), true));
+        resume.join().unwrap();
+        assert_eq!(control.wait_turn(at(5)), (None, false));
+        // paused at 10
+        control.step();
+        assert_eq!(control.wait_turn(at(10)), (Some(60.0), false));
+        assert!(control.state().paused);
+
+        control.release();
+        assert_eq!(control.wait_turn(at(11)), (None, false));
+    }
 }
@@ -0,0 +1,128 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use tracing::{debug, error};
+
+// enough for the request line and the headers of a GET
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+// (status, content type, body) of the answer to a GET of a target, e.g.
+// ("200 OK", "text/plain", body)
+pub type HttpResponse = (&'static str, &'static str, String);
+
+// Serves the GET requests on addr from a background thread, each connection on a thread of
+// its own so a slow client holds up no other, with the answer of respond to the target of
+// each. Other methods are refused. The answers allow
+// any origin, for dashboards such as Grafana reading them from the browser. Returns the
+// bound address, port 0 picks a free one.
+pub fn serve_get(
+    name: &'static str,
+    addr: SocketAddr,
+    respond: impl Fn(&str) -> HttpResponse + Send + Sync + 'static,
+) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let respond = Arc::new(respond);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let respond = respond.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, respond.as_ref()) {
+                            debug!("{} connection failed: {}", name, e);
+                        }
+                    });
+                }
+                Err(e) => error!("{} failed to accept: {}", name, e),
+            }
+        }
+    });
+    Ok(local_addr)
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    respond: &impl Fn(&str) -> HttpResponse,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = vec![];
+    let mut chunk = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => respond(target),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is served".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_get() {
+        let addr = serve_get("test", "127.0.0.1:0".parse().unwrap(), |target| {
+            ("200 OK", "text/plain", format!("got {target}"))
+        })
+        .unwrap();
+        let response = request(addr, "GET /a?b=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\ngot /a?b=1"));
+        let response = request(addr, "POST /a HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"));
+        // a request cut short is answered all the same
+        assert!(request(addr, "GET").starts_with("HTTP/1.1 405"));
+    }
+
+    #[test]
+    fn test_idle_client_holds_up_no_other() {
+        let addr = serve_get("test", "127.0.0.1:0".parse().unwrap(), |_| {
+            ("200 OK", "text/plain", String::new())
+        })
+        .unwrap();
+        // connected without sending a request, it is not timed out for 5 s
+        let idle = TcpStream::connect(addr).unwrap();
+        let start = std::time::Instant::now();
+        assert!(request(addr, "GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200 OK"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(idle);
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::Path;
+
+use anyhow::Context;
+use polars::{frame::DataFrame, io::parquet::ParquetWriter};
+
+// writes df to the file name in dir, replacing it
+pub fn write_parquet(dir: &Path, name: &str, mut df: DataFrame) -> Result<(), anyhow::Error> {
+    let path = dir.join(name);
+    let mut file = std::fs::File::create(&path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    ParquetWriter::new(&mut file)
+        .finish(&mut df)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
 pub mod candle;
 pub mod vis_app;
 pub mod vis_data;
+pub mod vis_export;
 pub mod vis_module;
+pub mod vis_web;
@@ -1,15 +1,18 @@
-use std::ops::RangeInclusive;
+use std::{ops::RangeInclusive, sync::Arc};
 
-use eframe::egui::{self, Color32, Frame, Margin, RichText, Widget};
+use eframe::egui::{self, Color32, Frame, Margin, RichText, Stroke, Widget};
 use egui_plot::{
-    BoxElem, BoxPlot, BoxSpread, GridMark, Legend, Line, Plot, PlotPoints, PlotUi, Points,
+    BoxElem, BoxPlot, BoxSpread, GridMark, Legend, Line, LineStyle, Plot, PlotBounds, PlotPoint,
+    PlotPoints, PlotUi, Points, Polygon, Text,
 };
-use time::OffsetDateTime;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use upstair_type::{strategy::StrategyDebug, time::PlaybackControl};
 
 use crate::{
     candle::OhlcvCandle,
     vis_data::{
-        compute_candles_from_market_trades, DataState, MakerOrderBrief, TimeInMs, TradeBrief,
+        book_heatmap, compute_candles_from_market_trades, price_range, quote_side_stops, DataState,
+        MakerOrderBrief, TimeInMs, TradeBrief,
     },
 };
 
@@ -18,12 +21,22 @@ pub struct VisApp {
     update_data_fn: Option<Box<UpdateFnType>>,
     state: DataState,
     ui_state: VisAppUiState,
+    playback: Option<PlaybackControl>,
 }
 
 struct VisAppUiState {
     candle_period_ms: TimeInMs,
     show_account_trade: bool,
     show_order_brief: bool,
+    show_book_heatmap: bool,
+    heatmap_price_step: f64,
+    show_fill_list: bool,
+    // the time window zoomed to around a fill picked in the fill list
+    focus_window_ms: TimeInMs,
+    // (from, to) the market plot zooms to on its next frame
+    pending_focus: Option<(TimeInMs, TimeInMs)>,
+    // 2000-01-01 00:00:00
+    fast_forward_to: String,
 }
 
 impl VisAppUiState {
@@ -44,6 +57,38 @@ impl VisAppUiState {
             .find_map(|(s, p)| if *p == t { Some(*s) } else { None })
             .unwrap_or("custom")
     }
+
+    const FOCUS_WINDOWS: [(&'static str, TimeInMs); 4] = [
+        ("1m", 60 * 1000),
+        ("5m", 5 * 60 * 1000),
+        ("15m", 15 * 60 * 1000),
+        ("1h", 60 * 60 * 1000),
+    ];
+
+    const PRICE_STEPS: [f64; 6] = [0.1, 0.5, 1.0, 5.0, 10.0, 50.0];
+
+    // simulation seconds per wall second, None is as fast as possible
+    const SPEEDS: [(&'static str, Option<f64>); 6] = [
+        ("1x", Some(1.0)),
+        ("10x", Some(10.0)),
+        ("60x", Some(60.0)),
+        ("600x", Some(600.0)),
+        ("3600x", Some(3600.0)),
+        ("max", None),
+    ];
+
+    fn speed_str(speed: Option<f64>) -> String {
+        Self::SPEEDS
+            .iter()
+            .find_map(|(s, v)| {
+                if *v == speed {
+                    Some(s.to_string())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(|| format!("{}x", speed.unwrap_or_default()))
+    }
 }
 
 impl VisApp {
@@ -54,6 +99,12 @@ impl VisApp {
         self.update_data_fn = update_fn.into();
         self
     }
+
+    // shows the pause, step, speed and fast forward controls
+    pub fn with_playback(mut self, playback: PlaybackControl) -> Self {
+        self.playback = Some(playback);
+        self
+    }
 }
 
 impl Default for VisApp {
@@ -65,7 +116,14 @@ impl Default for VisApp {
                 candle_period_ms: 15 * 60 * 1000,
                 show_account_trade: false,
                 show_order_brief: false,
+                show_book_heatmap: false,
+                heatmap_price_step: 1.0,
+                show_fill_list: false,
+                focus_window_ms: 5 * 60 * 1000,
+                pending_focus: None,
+                fast_forward_to: String::new(),
             },
+            playback: None,
         }
     }
 }
@@ -77,6 +135,60 @@ impl eframe::App for VisApp {
                 ctx.request_repaint();
             }
         }
+        if let Some(playback) = self.playback.clone() {
+            egui::TopBottomPanel::top("playback_view").show(ctx, |ui| {
+                self.playback_view(ui, &playback);
+            });
+            // keep the paused state and the simulation time shown up to date
+            ctx.request_repaint_after(std::time::Duration::from_millis(200));
+        }
+        if self.ui_state.show_fill_list {
+            egui::SidePanel::right("fill_list_view")
+                .default_width(260.0)
+                .resizable(true)
+                .show(ctx, |ui| self.fill_list_view(ui));
+        }
+        if self.ui_state.show_book_heatmap {
+            egui::TopBottomPanel::bottom("book_view")
+                .default_height(250.0)
+                .resizable(true)
+                .frame(Frame {
+                    inner_margin: Margin::symmetric(0.0, 0.0),
+                    ..Default::default()
+                })
+                .show(ctx, |ui| {
+                    let layout = egui::Layout::top_down(egui::Align::Min)
+                        .with_cross_justify(true)
+                        .with_main_align(egui::Align::TOP);
+                    ui.with_layout(layout, |ui| self.book_view(ui));
+                });
+        }
+        egui::TopBottomPanel::bottom("inventory_view")
+            .default_height(150.0)
+            .resizable(true)
+            .frame(Frame {
+                inner_margin: Margin::symmetric(0.0, 0.0),
+                ..Default::default()
+            })
+            .show(ctx, |ui| {
+                let layout = egui::Layout::top_down(egui::Align::Min)
+                    .with_cross_justify(true)
+                    .with_main_align(egui::Align::TOP);
+                ui.with_layout(layout, |ui| self.inventory_view(ui));
+            });
+        egui::TopBottomPanel::bottom("strategy_view")
+            .default_height(200.0)
+            .resizable(true)
+            .frame(Frame {
+                inner_margin: Margin::symmetric(0.0, 0.0),
+                ..Default::default()
+            })
+            .show(ctx, |ui| {
+                let layout = egui::Layout::top_down(egui::Align::Min)
+                    .with_cross_justify(true)
+                    .with_main_align(egui::Align::TOP);
+                ui.with_layout(layout, |ui| self.strategy_view(ui));
+            });
         egui::TopBottomPanel::bottom("account_view")
             .default_height(200.0)
             .resizable(true)
@@ -103,6 +215,68 @@ impl eframe::App for VisApp {
 }
 
 impl VisApp {
+    fn playback_view(&mut self, ui: &mut egui::Ui, playback: &PlaybackControl) {
+        let state = playback.state();
+        ui.horizontal(|ui| {
+            if state.paused {
+                if ui.button("Resume").clicked() {
+                    playback.resume();
+                }
+                if ui.button("Step").clicked() {
+                    playback.step();
+                }
+            } else if ui.button("Pause").clicked() {
+                playback.pause();
+            }
+
+            let mut speed = state.speed;
+            egui::ComboBox::from_id_source("playback_speed")
+                .selected_text(VisAppUiState::speed_str(speed))
+                .show_ui(ui, |ui| {
+                    for (text, value) in &VisAppUiState::SPEEDS {
+                        ui.selectable_value(&mut speed, *value, *text);
+                    }
+                });
+            if speed != state.speed {
+                playback.set_speed(speed);
+            }
+
+            let last_trade_time = self.state.market_trades.last().map(|t| t.time);
+            if let Some(time) = last_trade_time {
+                ui.label(convert_timestamp_to_string(time as f64 / 1000.0));
+            }
+            if self.ui_state.fast_forward_to.is_empty() {
+                if let Some(time) = last_trade_time {
+                    self.ui_state.fast_forward_to =
+                        convert_timestamp_to_string(time as f64 / 1000.0)
+                            .split('.')
+                            .next()
+                            .unwrap_or_default()
+                            .to_string();
+                }
+            }
+            egui::TextEdit::singleline(&mut self.ui_state.fast_forward_to)
+                .desired_width(150.0)
+                .ui(ui);
+            let fast_forward_to = parse_timestamp(&self.ui_state.fast_forward_to);
+            if let Some(to) = &state.fast_forward_to {
+                ui.label(format!(
+                    "fast forwarding to {}",
+                    convert_timestamp_to_string(
+                        to.duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs_f64()
+                    )
+                ));
+            } else if ui
+                .add_enabled(fast_forward_to.is_some(), egui::Button::new("Fast forward"))
+                .clicked()
+            {
+                playback.fast_forward_to(fast_forward_to.unwrap());
+            }
+        });
+    }
+
     fn account_view(&mut self, ui: &mut egui::Ui) {
         ui.heading("Account view");
 
@@ -127,6 +301,202 @@ impl VisApp {
         });
     }
 
+    // our fills, newest first. Clicking one zooms the plots to a window around it
+    fn fill_list_view(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Fills");
+            egui::ComboBox::from_id_source("focus_window")
+                .selected_text(
+                    VisAppUiState::FOCUS_WINDOWS
+                        .iter()
+                        .find(|(_, w)| *w == self.ui_state.focus_window_ms)
+                        .map_or("custom", |(s, _)| *s),
+                )
+                .show_ui(ui, |ui| {
+                    for (text, window) in &VisAppUiState::FOCUS_WINDOWS {
+                        ui.selectable_value(&mut self.ui_state.focus_window_ms, *window, *text);
+                    }
+                });
+        });
+        let fills = &self.state.account_trades;
+        let row_height = ui.text_style_height(&egui::TextStyle::Body);
+        egui::ScrollArea::vertical().show_rows(ui, row_height, fills.len(), |ui, rows| {
+            for i in rows {
+                let fill = &fills[fills.len() - 1 - i];
+                let text = format!(
+                    "{} {} {} @ {}",
+                    convert_timestamp_to_string(fill.time as f64 / 1000.0),
+                    if fill.is_buy { "buy" } else { "sell" },
+                    fill.qty,
+                    fill.price
+                );
+                let color = if fill.is_buy {
+                    Color32::from_rgb(255, 0, 0)
+                } else {
+                    Color32::from_rgb(0, 255, 0)
+                };
+                if ui
+                    .selectable_label(false, RichText::new(text).color(color))
+                    .clicked()
+                {
+                    let half = self.ui_state.focus_window_ms / 2;
+                    self.ui_state.pending_focus =
+                        Some((fill.time.saturating_sub(half), fill.time + half));
+                }
+            }
+        });
+    }
+
+    // liquidity resting in the book per candle period and price step, our orders on top
+    fn book_view(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::Label::new(RichText::from("Book view").heading())
+                .selectable(false)
+                .ui(ui);
+            egui::ComboBox::from_id_source("heatmap_price_step")
+                .selected_text(self.ui_state.heatmap_price_step.to_string())
+                .show_ui(ui, |ui| {
+                    for step in VisAppUiState::PRICE_STEPS {
+                        ui.selectable_value(
+                            &mut self.ui_state.heatmap_price_step,
+                            step,
+                            step.to_string(),
+                        );
+                    }
+                });
+        });
+        let period_ms = self.ui_state.candle_period_ms;
+        let price_step = self.ui_state.heatmap_price_step;
+        let cells = book_heatmap(&self.state.book_snapshots, period_ms, price_step);
+        let max_quantity = |is_bid| {
+            cells
+                .iter()
+                .filter(|cell| cell.is_bid == is_bid)
+                .map(|cell| cell.quantity)
+                .fold(f64::EPSILON, f64::max)
+        };
+        let (max_bid, max_ask) = (max_quantity(true), max_quantity(false));
+        Plot::new("book_plot")
+            .x_axis_formatter(timestamp_axis_formatter)
+            .label_formatter(market_label)
+            .show_axes([true, true])
+            .show_grid([true, true])
+            .link_axis("timeline_linkgroup", true, false)
+            .link_cursor("timeline_linkgroup", true, false)
+            .show(ui, |plot_ui| {
+                for cell in &cells {
+                    let (t0, t1) = (
+                        cell.time as f64 / 1000.0,
+                        (cell.time + period_ms) as f64 / 1000.0,
+                    );
+                    let (p0, p1) = (cell.price, cell.price + price_step);
+                    let (intensity, (r, g, b)) = if cell.is_bid {
+                        (cell.quantity / max_bid, (255, 0, 0))
+                    } else {
+                        (cell.quantity / max_ask, (0, 255, 0))
+                    };
+                    let alpha = (intensity.clamp(0.0, 1.0) * 200.0) as u8 + 20;
+                    plot_ui.polygon(
+                        Polygon::new(vec![[t0, p0], [t1, p0], [t1, p1], [t0, p1]])
+                            .stroke(Stroke::NONE)
+                            .fill_color(Color32::from_rgba_unmultiplied(r, g, b, alpha))
+                            .name(format!("{:.3} resting", cell.quantity)),
+                    );
+                }
+                Self::draw_order_briefs(plot_ui, self.state.order_briefs.iter());
+            });
+    }
+
+    fn inventory_view(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Inventory view");
+
+        let debug = &self.state.strategy_debug;
+        // relative to the initial position, 0 is on target
+        let series = |f: fn(&StrategyDebug) -> f64| {
+            debug
+                .iter()
+                .map(|(ts_ms, d)| [*ts_ms as f64 / 1000.0, f(d) - d.initial_position])
+                .collect::<Vec<_>>()
+        };
+        let stops = |quoting: fn(&StrategyDebug) -> bool| {
+            quote_side_stops(debug, quoting)
+                .map(|(ts_ms, d)| [*ts_ms as f64 / 1000.0, d.base_balance - d.initial_position])
+                .collect::<Vec<_>>()
+        };
+        Plot::new("inventory_plot")
+            .x_axis_formatter(timestamp_axis_formatter)
+            .show_axes([true, true])
+            .show_grid([true, true])
+            .legend(Legend::default())
+            .link_axis("timeline_linkgroup", true, false)
+            .link_cursor("timeline_linkgroup", true, false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(series(|d| d.base_balance)).name("inventory"));
+                plot_ui.line(
+                    Line::new(series(|d| d.low_water_level))
+                        .style(LineStyle::dashed_dense())
+                        .name("low water level"),
+                );
+                plot_ui.line(
+                    Line::new(series(|d| d.high_water_level))
+                        .style(LineStyle::dashed_dense())
+                        .name("high water level"),
+                );
+                plot_ui.points(
+                    Points::new(stops(|d| d.quoting_bid))
+                        .shape(egui_plot::MarkerShape::Up)
+                        .radius(5.0)
+                        .color(Color32::from_rgb(255, 0, 0))
+                        .name("bid stopped"),
+                );
+                plot_ui.points(
+                    Points::new(stops(|d| d.quoting_ask))
+                        .shape(egui_plot::MarkerShape::Down)
+                        .radius(5.0)
+                        .color(Color32::from_rgb(0, 255, 0))
+                        .name("ask stopped"),
+                );
+            });
+    }
+
+    fn strategy_view(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Strategy view");
+
+        let series = |f: fn(&StrategyDebug) -> f64| {
+            self.state
+                .strategy_debug
+                .iter()
+                .map(|(ts_ms, debug)| [*ts_ms as f64 / 1000.0, f(debug)])
+                .collect::<Vec<_>>()
+        };
+        // volatility and spreads are price distances, the reservation price a price level
+        let height = (ui.available_height() / 2.0).max(50.0);
+        Plot::new("spread_plot")
+            .height(height)
+            .x_axis_formatter(timestamp_axis_formatter)
+            .show_axes([true, true])
+            .show_grid([true, true])
+            .legend(Legend::default())
+            .link_axis("timeline_linkgroup", true, false)
+            .link_cursor("timeline_linkgroup", true, false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(series(|d| d.vol)).name("vol"));
+                plot_ui.line(Line::new(series(StrategyDebug::quoted_spread)).name("quoted spread"));
+                plot_ui.line(Line::new(series(StrategyDebug::market_spread)).name("market spread"));
+            });
+        Plot::new("reservation_plot")
+            .x_axis_formatter(timestamp_axis_formatter)
+            .show_axes([true, true])
+            .show_grid([true, true])
+            .legend(Legend::default())
+            .link_axis("timeline_linkgroup", true, false)
+            .link_cursor("timeline_linkgroup", true, false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(series(|d| d.reservation_price)).name("reservation price"));
+                plot_ui.line(Line::new(series(|d| d.fair_price)).name("fair price"));
+            });
+    }
+
     fn market_view(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             egui::Label::new(RichText::from("Market view").heading())
@@ -149,14 +519,27 @@ impl VisApp {
                 });
             ui.checkbox(&mut self.ui_state.show_account_trade, "TradeMarker");
             ui.checkbox(&mut self.ui_state.show_order_brief, "OrderBrief");
+            ui.checkbox(&mut self.ui_state.show_book_heatmap, "BookHeatmap");
+            ui.checkbox(&mut self.ui_state.show_fill_list, "FillList");
         });
         let plot = Plot::new("market_plot")
             .x_axis_formatter(timestamp_axis_formatter)
+            .label_formatter(market_label)
             .show_axes([true, true])
             .show_grid([true, true])
             .link_axis("timeline_linkgroup", true, false)
             .link_cursor("timeline_linkgroup", true, false);
         plot.show(ui, |plot_ui| {
+            // zoom to the fill picked in the fill list, the linked plots follow the time axis
+            if let Some((from, to)) = self.ui_state.pending_focus.take() {
+                if let Some((low, high)) = price_range(&self.state.market_trades, from, to) {
+                    let margin = ((high - low) * 0.1).max(high * 1e-4);
+                    plot_ui.set_plot_bounds(PlotBounds::from_min_max(
+                        [from as f64 / 1000.0, low - margin],
+                        [to as f64 / 1000.0, high + margin],
+                    ));
+                }
+            }
             // draw candles
             let period_ms = self.ui_state.candle_period_ms;
             let candles = compute_candles_from_market_trades(
@@ -171,7 +554,7 @@ impl VisApp {
             }
             // draw orders
             if self.ui_state.show_order_brief {
-                Self::draw_order_briefs(plot_ui, self.state.order_briefs.values());
+                Self::draw_order_briefs(plot_ui, self.state.order_briefs.iter());
             }
         });
     }
@@ -238,16 +621,31 @@ impl VisApp {
         plot_ui.points(sell_points);
     }
 
+    // filled orders are solid lines, cancelled ones dashed, with a tick and its quantity at
+    // each fill. The name of the line is the hover tooltip, see market_label
     fn draw_order_briefs<'a>(
         plot_ui: &mut PlotUi,
-        briefs: impl Iterator<Item = &'a MakerOrderBrief>,
+        briefs: impl Iterator<Item = (&'a Arc<str>, &'a MakerOrderBrief)>,
     ) {
         const BUY_ORDER_COLOR: Color32 = Color32::from_rgb(255, 100, 0);
         const SELL_ORDER_COLOR: Color32 = Color32::from_rgb(100, 255, 0);
 
         briefs
-            .filter(|brief| brief.created_at > 0 && brief.ended_at > 0)
-            .for_each(|brief| {
+            .filter(|(_, brief)| brief.created_at > 0 && brief.ended_at > 0)
+            .for_each(|(order_id, brief)| {
+                let color = if brief.is_buy {
+                    BUY_ORDER_COLOR
+                } else {
+                    SELL_ORDER_COLOR
+                };
+                let style = if brief.canceled {
+                    LineStyle::dashed_dense()
+                } else {
+                    LineStyle::Solid
+                };
+                let fill_ratio = brief
+                    .fill_ratio()
+                    .map_or("-".to_string(), |r| format!("{:.0}%", r * 100.0));
                 let l = Line::new(Into::<PlotPoints>::into(PlotPoints::new(
                     [
                         [brief.created_at as f64 / 1000.0, brief.price],
@@ -256,16 +654,56 @@ impl VisApp {
                     .into(),
                 )))
                 .width(3.0)
-                .color(if brief.is_buy {
-                    BUY_ORDER_COLOR
-                } else {
-                    SELL_ORDER_COLOR
-                });
+                .color(color)
+                .style(style)
+                .name(format!(
+                    "{} {} {} @ {}, filled {}",
+                    order_id,
+                    if brief.is_buy { "buy" } else { "sell" },
+                    brief.quantity,
+                    brief.price,
+                    fill_ratio
+                ));
                 plot_ui.line(l);
+
+                if brief.fills.is_empty() {
+                    return;
+                }
+                let ticks: Vec<[f64; 2]> = brief
+                    .fills
+                    .iter()
+                    .map(|(t, _)| [*t as f64 / 1000.0, brief.price])
+                    .collect();
+                plot_ui.points(
+                    Points::new(ticks)
+                        .color(color)
+                        .shape(egui_plot::MarkerShape::Diamond)
+                        .radius(4.0),
+                );
+                for (t, qty) in &brief.fills {
+                    plot_ui.text(
+                        Text::new(
+                            PlotPoint::new(*t as f64 / 1000.0, brief.price),
+                            qty.to_string(),
+                        )
+                        .color(color)
+                        .anchor(egui::Align2::LEFT_BOTTOM),
+                    );
+                }
             });
     }
 }
 
+// the name of the hovered item, e.g. the order of an order line, with the time and price
+fn market_label(name: &str, value: &PlotPoint) -> String {
+    let at = format!("{}\n{:.2}", convert_timestamp_to_string(value.x), value.y);
+    if name.is_empty() {
+        at
+    } else {
+        format!("{name}\n{at}")
+    }
+}
+
 fn timestamp_axis_formatter(
     mark: GridMark,
     _max_digits: usize,
@@ -295,3 +733,20 @@ fn convert_timestamp_to_string(duration_since_epoch: f64) -> String {
         dt.millisecond()
     )
 }
+
+// parses 2000-01-01 00:00:00 in UTC
+fn parse_timestamp(s: &str) -> Option<std::time::SystemTime> {
+    let (date, time) = s.trim().split_once(' ')?;
+    let mut date = date.split('-').map(|v| v.parse::<i32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.trim().split(':').map(|v| v.parse::<u8>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    let date = Date::from_calendar_date(
+        year,
+        Month::try_from(u8::try_from(month).ok()?).ok()?,
+        u8::try_from(day).ok()?,
+    )
+    .ok()?;
+    let time = Time::from_hms(hour, minute, second).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_utc().into())
+}
@@ -1,10 +1,15 @@
-use std::{collections::HashMap, sync::Arc, time::UNIX_EPOCH};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
 
 use account::account::Account;
 
 use upstair_type::{
     data::market::BinanceTradeTick,
     order::{OrderResult, OrderStatus},
+    strategy::StrategyDebug,
 };
 
 use crate::candle::OhlcvCandle;
@@ -23,6 +28,41 @@ pub struct MakerOrderBrief {
     pub created_at: TimeInMs, // 0 for TBD
     pub ended_at: TimeInMs,   // 0 for TBD
     pub is_buy: bool,
+    pub quantity: f64, // 0 when the request was not seen
+    // (time, quantity) of each fill
+    pub fills: Vec<(TimeInMs, f64)>,
+    // ended by a cancel or a reject rather than filled
+    pub canceled: bool,
+}
+
+impl MakerOrderBrief {
+    pub fn filled(&self) -> f64 {
+        self.fills.iter().map(|(_, qty)| qty).sum()
+    }
+
+    // None when the quantity is unknown
+    pub fn fill_ratio(&self) -> Option<f64> {
+        (self.quantity > 0.0).then(|| self.filled() / self.quantity)
+    }
+}
+
+// The resting liquidity of the book at a moment, (price, quantity) of each level from the
+// touch outwards. The book ticker gives the top level only.
+#[derive(Default, Debug, Clone)]
+pub struct BookSnapshot {
+    pub time: TimeInMs,
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+// The mean quantity resting in a time x price cell of the book heatmap
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatCell {
+    // start of the time bucket and bottom of the price bucket
+    pub time: TimeInMs,
+    pub price: f64,
+    pub quantity: f64,
+    pub is_bid: bool,
 }
 
 #[derive(Default, Debug)]
@@ -39,10 +79,26 @@ pub struct DataBuffer {
     pub account_trades: Vec<TradeBrief>,
 
     pub order_updates: Vec<OrderResult>,
+    // (client order id, quantity) of the order requests
+    pub order_quantities: Vec<(Arc<str>, f64)>,
+    pub strategy_debug: Vec<(TimeInMs, StrategyDebug)>,
+    pub book_snapshots: Vec<BookSnapshot>,
+
+    // the accounts of the strategies run next to this one, see VisModuleBuilder::with_variant
+    pub variants: Vec<VariantAccount>,
 
     pub commit_at: TimeInMs,
 }
 
+#[derive(Default, Debug, Clone)]
+pub struct VariantAccount {
+    // the names of its series in the account history, e.g. EquityUSDT@b
+    pub equity_series: &'static str,
+    pub profit_series: &'static str,
+    pub account: Account,
+    pub profit_account: Account,
+}
+
 impl DataBuffer {
     pub fn take(&mut self) -> Self {
         Self {
@@ -55,8 +111,12 @@ impl DataBuffer {
             commit_at: self.commit_at,
             account_trades: std::mem::take(&mut self.account_trades),
             order_updates: std::mem::take(&mut self.order_updates),
+            order_quantities: std::mem::take(&mut self.order_quantities),
+            strategy_debug: std::mem::take(&mut self.strategy_debug),
+            book_snapshots: std::mem::take(&mut self.book_snapshots),
             latest_market_price: self.latest_market_price.clone(),
             profit_account: self.profit_account.clone(),
+            variants: self.variants.clone(),
         }
     }
 }
@@ -67,48 +127,56 @@ pub struct DataState {
     pub account_trades: Vec<TradeBrief>,
     pub account_asset_history: HashMap<&'static str, Vec<(TimeInMs, f64)>>,
     pub order_briefs: HashMap<Arc<str>, MakerOrderBrief>,
+    // quote rounds of the strategy
+    pub strategy_debug: Vec<(TimeInMs, StrategyDebug)>,
+    pub book_snapshots: Vec<BookSnapshot>,
+    // commit time of the last buffer
+    pub updated_at: TimeInMs,
 }
 
 impl DataState {
-    pub fn update(&mut self, buffer: DataBuffer) {
-        let mut buffer = buffer;
-        self.market_trades.append(&mut buffer.market_trades);
-        self.account_trades.append(&mut buffer.account_trades);
-
+    // the usdt value of the account at the latest prices, appended to the series
+    fn push_account_value(&mut self, series: &'static str, account: &Account, buffer: &DataBuffer) {
         let mut total_usdt_value = 0.0;
-        for (asset, account) in buffer.account.asset_to_balance.iter() {
-            self.account_asset_history
-                .entry(asset)
-                .or_default()
-                .push((buffer.commit_at, account.balance));
+        for (asset, balance) in account.asset_to_balance.iter() {
             if let Some(asset_price) = buffer.latest_market_price.get(asset) {
-                total_usdt_value += account.balance * asset_price;
+                total_usdt_value += balance.balance * asset_price;
             } else if asset == &"USDT" {
-                total_usdt_value += account.balance;
+                total_usdt_value += balance.balance;
             }
         }
         if total_usdt_value != 0.0 {
             self.account_asset_history
-                .entry("EquityUSDT")
+                .entry(series)
                 .or_default()
                 .push((buffer.commit_at, total_usdt_value));
         }
+    }
 
-        let mut total_profit_usdt = 0.0;
-        for (asset, account) in buffer.profit_account.asset_to_balance.iter() {
-            if let Some(asset_price) = buffer.latest_market_price.get(asset) {
-                total_profit_usdt += account.balance * asset_price;
-            } else if asset == &"USDT" {
-                total_profit_usdt += account.balance;
-            }
-        }
-        if total_profit_usdt != 0.0 {
+    pub fn update(&mut self, buffer: DataBuffer) {
+        let mut buffer = buffer;
+        self.updated_at = buffer.commit_at;
+        self.market_trades.append(&mut buffer.market_trades);
+        self.account_trades.append(&mut buffer.account_trades);
+        self.strategy_debug.append(&mut buffer.strategy_debug);
+        self.book_snapshots.append(&mut buffer.book_snapshots);
+
+        for (asset, account) in buffer.account.asset_to_balance.iter() {
             self.account_asset_history
-                .entry("ProfitUSDT")
+                .entry(asset)
                 .or_default()
-                .push((buffer.commit_at, total_profit_usdt));
+                .push((buffer.commit_at, account.balance));
+        }
+        self.push_account_value("EquityUSDT", &buffer.account, &buffer);
+        self.push_account_value("ProfitUSDT", &buffer.profit_account, &buffer);
+        for variant in &buffer.variants {
+            self.push_account_value(variant.equity_series, &variant.account, &buffer);
+            self.push_account_value(variant.profit_series, &variant.profit_account, &buffer);
         }
 
+        for (order_id, quantity) in buffer.order_quantities.drain(..) {
+            self.order_briefs.entry(order_id).or_default().quantity = quantity;
+        }
         for order_result in buffer.order_updates.drain(..) {
             let brief = self
                 .order_briefs
@@ -125,15 +193,24 @@ impl DataState {
                     brief.price = order_result.price;
                     brief.created_at = order_result_t_in_ms;
                 }
-                OrderStatus::PartiallyFilled => {}
+                OrderStatus::PartiallyFilled => {
+                    brief
+                        .fills
+                        .push((order_result_t_in_ms, order_result.filled_quantity));
+                }
                 OrderStatus::Filled => {
+                    brief
+                        .fills
+                        .push((order_result_t_in_ms, order_result.filled_quantity));
                     brief.ended_at = order_result_t_in_ms;
                 }
                 OrderStatus::Canceled => {
                     brief.ended_at = order_result_t_in_ms;
+                    brief.canceled = true;
                 }
-                OrderStatus::Rejected => {
+                OrderStatus::Rejected | OrderStatus::RateLimited => {
                     brief.ended_at = order_result_t_in_ms;
+                    brief.canceled = true;
                 }
                 _ => {}
             }
@@ -142,6 +219,63 @@ impl DataState {
 }
 
 pub type TimeInMs = u64;
+
+// lowest and highest trade price within [from, to], trades are in time order
+pub fn price_range(
+    trades: &[BinanceTradeTick],
+    from: TimeInMs,
+    to: TimeInMs,
+) -> Option<(f64, f64)> {
+    let start = trades.partition_point(|t| t.time < from);
+    let end = trades.partition_point(|t| t.time <= to);
+    trades[start..end].iter().fold(None, |range, t| {
+        let (low, high) = range.unwrap_or((t.price, t.price));
+        Some((low.min(t.price), high.max(t.price)))
+    })
+}
+
+// Buckets the book snapshots into period_ms x price_step cells. The quantity of a cell is
+// averaged over all snapshots of its time bucket, a level missing from a snapshot counts as 0.
+pub fn book_heatmap(
+    snapshots: &[BookSnapshot],
+    period_ms: TimeInMs,
+    price_step: f64,
+) -> Vec<HeatCell> {
+    let mut cells: BTreeMap<(TimeInMs, i64, bool), f64> = BTreeMap::new();
+    let mut snapshots_in_bucket: HashMap<TimeInMs, u32> = HashMap::new();
+    for snapshot in snapshots {
+        let bucket = snapshot.time - snapshot.time % period_ms;
+        *snapshots_in_bucket.entry(bucket).or_default() += 1;
+        let levels = snapshot.bids.iter().map(|level| (level, true));
+        for ((price, quantity), is_bid) in levels.chain(snapshot.asks.iter().map(|l| (l, false))) {
+            let price_bucket = (price / price_step).floor() as i64;
+            *cells.entry((bucket, price_bucket, is_bid)).or_default() += quantity;
+        }
+    }
+    cells
+        .into_iter()
+        .map(|((time, price_bucket, is_bid), quantity)| HeatCell {
+            time,
+            price: price_bucket as f64 * price_step,
+            quantity: quantity / snapshots_in_bucket[&time] as f64,
+            is_bid,
+        })
+        .collect()
+}
+
+// the quote rounds where the strategy stopped quoting a side, quoting tells whether the side
+// was quoted in a round
+pub fn quote_side_stops(
+    strategy_debug: &[(TimeInMs, StrategyDebug)],
+    quoting: fn(&StrategyDebug) -> bool,
+) -> impl Iterator<Item = &(TimeInMs, StrategyDebug)> {
+    let mut was_quoting = true;
+    strategy_debug.iter().filter(move |(_, debug)| {
+        let stopped = was_quoting && !quoting(debug);
+        was_quoting = quoting(debug);
+        stopped
+    })
+}
 pub fn compute_candles_from_market_trades(
     trades: &[BinanceTradeTick],
     first_time_ms: TimeInMs,
@@ -255,4 +389,146 @@ mod tests {
         let candles: Vec<(TimeInMs, OhlcvCandle)> = candles.collect();
         assert_eq!(candles.len(), 0);
     }
+
+    #[test]
+    fn test_price_range() {
+        let trades: Vec<_> = [(0, 100.0), (10, 102.0), (20, 99.0), (30, 105.0)]
+            .into_iter()
+            .map(|(time, price)| BinanceTradeTick {
+                id: time,
+                price,
+                qty: 1.0,
+                base_qty: price,
+                time,
+                is_buyer_maker: false,
+                symbol: "",
+            })
+            .collect();
+        assert_eq!(price_range(&trades, 5, 20), Some((99.0, 102.0)));
+        assert_eq!(price_range(&trades, 0, 100), Some((99.0, 105.0)));
+        assert_eq!(price_range(&trades, 11, 19), None);
+    }
+
+    #[test]
+    fn test_order_brief_fills() {
+        let result = |status, filled_quantity, at_ms| OrderResult {
+            symbol: "BTCUSDT",
+            at: UNIX_EPOCH + std::time::Duration::from_millis(at_ms),
+            client_order_id: "B0".into(),
+            filled_quantity,
+            price: 100.0,
+            is_buy: true,
+            status,
+            seq: 0,
+            owner: None,
+            reject_reason: None,
+        };
+        let mut state = DataState::default();
+        state.update(DataBuffer {
+            order_quantities: vec![("B0".into(), 0.04)],
+            order_updates: vec![
+                result(OrderStatus::New, 0.0, 100),
+                result(OrderStatus::PartiallyFilled, 0.01, 200),
+            ],
+            ..Default::default()
+        });
+        state.update(DataBuffer {
+            order_updates: vec![result(OrderStatus::Canceled, 0.0, 300)],
+            ..Default::default()
+        });
+        let brief = &state.order_briefs[&Arc::from("B0")];
+        assert_eq!((brief.created_at, brief.ended_at), (100, 300));
+        assert_eq!(brief.fills, vec![(200, 0.01)]);
+        assert_eq!(brief.fill_ratio(), Some(0.25));
+        assert!(brief.canceled);
+    }
+
+    #[test]
+    fn test_variant_series() {
+        let account = |usdt| {
+            let mut account = Account::default();
+            account.asset_to_balance.insert(
+                "USDT",
+                account::account::AssetBalance {
+                    balance: usdt,
+                    locked: 0.0,
+                },
+            );
+            account
+        };
+        let mut state = DataState::default();
+        state.update(DataBuffer {
+            account: account(100.0),
+            profit_account: account(1.0),
+            variants: vec![VariantAccount {
+                equity_series: "EquityUSDT@b",
+                profit_series: "ProfitUSDT@b",
+                account: account(98.0),
+                profit_account: account(-2.0),
+            }],
+            commit_at: 1000,
+            ..Default::default()
+        });
+        let history = &state.account_asset_history;
+        assert_eq!(history["ProfitUSDT"], vec![(1000, 1.0)]);
+        assert_eq!(history["EquityUSDT@b"], vec![(1000, 98.0)]);
+        assert_eq!(history["ProfitUSDT@b"], vec![(1000, -2.0)]);
+    }
+
+    #[test]
+    fn test_quote_side_stops() {
+        let round = |time, quoting_bid, quoting_ask| {
+            let debug = StrategyDebug {
+                quoting_bid,
+                quoting_ask,
+                ..Default::default()
+            };
+            (time, debug)
+        };
+        let rounds = vec![
+            round(0, true, true),
+            round(100, false, true),
+            round(200, false, true),
+            round(300, true, true),
+            round(400, false, false),
+        ];
+        let times = |quoting| {
+            quote_side_stops(&rounds, quoting)
+                .map(|(time, _)| *time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(|d| d.quoting_bid), vec![100, 400]);
+        assert_eq!(times(|d| d.quoting_ask), vec![400]);
+    }
+
+    #[test]
+    fn test_book_heatmap() {
+        let snapshot = |time, bid: f64, ask: f64| BookSnapshot {
+            time,
+            bids: vec![(bid, 2.0)],
+            asks: vec![(ask, 1.0)],
+        };
+        let snapshots = vec![
+            snapshot(0, 100.0, 100.5),
+            snapshot(500, 100.2, 100.5),
+            snapshot(1000, 101.0, 101.5),
+        ];
+        let cells = book_heatmap(&snapshots, 1000, 1.0);
+        let cell = |time, price: f64, quantity, is_bid| HeatCell {
+            time,
+            price,
+            quantity,
+            is_bid,
+        };
+        assert_eq!(
+            cells,
+            vec![
+                cell(0, 100.0, 1.0, false),
+                // both bids of the first second are in the same price bucket
+                cell(0, 100.0, 2.0, true),
+                cell(1000, 101.0, 1.0, false),
+                cell(1000, 101.0, 2.0, true),
+            ]
+        );
+    }
 }
@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use anyhow::Context;
+use polars::df;
+use util::parquet::write_parquet;
+
+use crate::vis_data::{compute_candles_from_market_trades, DataState, TimeInMs};
+
+// period of the exported candles, notebooks resample them to coarser ones
+const EXPORT_CANDLE_PERIOD_MS: TimeInMs = 60 * 1000;
+
+// Writes what the vis window would plot to Parquet files in dir: trades, 1m candles, the
+// account history, the own fills, the strategy quote rounds and the order briefs
+pub fn export_data_state(state: &DataState, dir: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let trades = &state.market_trades;
+    write_parquet(
+        dir,
+        "trades.parquet",
+        df!(
+            "time" => trades.iter().map(|t| t.time).collect::<Vec<_>>(),
+            "price" => trades.iter().map(|t| t.price).collect::<Vec<_>>(),
+            "qty" => trades.iter().map(|t| t.qty).collect::<Vec<_>>(),
+            "is_buyer_maker" => trades.iter().map(|t| t.is_buyer_maker).collect::<Vec<_>>(),
+        )?,
+    )?;
+
+    let first_time = trades.first().map_or(0, |t| t.time);
+    let first_time = first_time - first_time % EXPORT_CANDLE_PERIOD_MS;
+    let candles: Vec<_> =
+        compute_candles_from_market_trades(trades, first_time, EXPORT_CANDLE_PERIOD_MS).collect();
+    write_parquet(
+        dir,
+        "candles.parquet",
+        df!(
+            "time" => candles.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+            "open" => candles.iter().map(|(_, c)| c.open).collect::<Vec<_>>(),
+            "high" => candles.iter().map(|(_, c)| c.high).collect::<Vec<_>>(),
+            "low" => candles.iter().map(|(_, c)| c.low).collect::<Vec<_>>(),
+            "close" => candles.iter().map(|(_, c)| c.close).collect::<Vec<_>>(),
+            "volume" => candles.iter().map(|(_, c)| c.volume).collect::<Vec<_>>(),
+        )?,
+    )?;
+
+    // one row per asset and time, EquityUSDT and ProfitUSDT included
+    let mut history: Vec<_> = state
+        .account_asset_history
+        .iter()
+        .flat_map(|(asset, history)| history.iter().map(move |(t, v)| (*asset, *t, *v)))
+        .collect();
+    history.sort_by_key(|(asset, t, _)| (*t, *asset));
+    write_parquet(
+        dir,
+        "account_history.parquet",
+        df!(
+            "asset" => history.iter().map(|(a, _, _)| *a).collect::<Vec<_>>(),
+            "time" => history.iter().map(|(_, t, _)| *t).collect::<Vec<_>>(),
+            "balance" => history.iter().map(|(_, _, v)| *v).collect::<Vec<_>>(),
+        )?,
+    )?;
+
+    let fills = &state.account_trades;
+    write_parquet(
+        dir,
+        "account_trades.parquet",
+        df!(
+            "time" => fills.iter().map(|t| t.time).collect::<Vec<_>>(),
+            "is_buy" => fills.iter().map(|t| t.is_buy).collect::<Vec<_>>(),
+            "price" => fills.iter().map(|t| t.price).collect::<Vec<_>>(),
+            "qty" => fills.iter().map(|t| t.qty).collect::<Vec<_>>(),
+        )?,
+    )?;
+
+    let debug = &state.strategy_debug;
+    write_parquet(
+        dir,
+        "strategy_debug.parquet",
+        df!(
+            "time" => debug.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+            "vol" => debug.iter().map(|(_, d)| d.vol).collect::<Vec<_>>(),
+            "fair_price" => debug.iter().map(|(_, d)| d.fair_price).collect::<Vec<_>>(),
+            "reservation_price" => debug.iter().map(|(_, d)| d.reservation_price).collect::<Vec<_>>(),
+            "quoted_spread" => debug.iter().map(|(_, d)| d.quoted_spread()).collect::<Vec<_>>(),
+            "market_spread" => debug.iter().map(|(_, d)| d.market_spread()).collect::<Vec<_>>(),
+            "base_balance" => debug.iter().map(|(_, d)| d.base_balance).collect::<Vec<_>>(),
+            "quoting_bid" => debug.iter().map(|(_, d)| d.quoting_bid).collect::<Vec<_>>(),
+            "quoting_ask" => debug.iter().map(|(_, d)| d.quoting_ask).collect::<Vec<_>>(),
+        )?,
+    )?;
+
+    let mut briefs: Vec<_> = state.order_briefs.iter().collect();
+    briefs.sort_by_key(|(id, brief)| (brief.created_at, id.to_string()));
+    write_parquet(
+        dir,
+        "order_briefs.parquet",
+        df!(
+            "order_id" => briefs.iter().map(|(id, _)| id.to_string()).collect::<Vec<_>>(),
+            "is_buy" => briefs.iter().map(|(_, b)| b.is_buy).collect::<Vec<_>>(),
+            "price" => briefs.iter().map(|(_, b)| b.price).collect::<Vec<_>>(),
+            "quantity" => briefs.iter().map(|(_, b)| b.quantity).collect::<Vec<_>>(),
+            "filled" => briefs.iter().map(|(_, b)| b.filled()).collect::<Vec<_>>(),
+            "created_at" => briefs.iter().map(|(_, b)| b.created_at).collect::<Vec<_>>(),
+            "ended_at" => briefs.iter().map(|(_, b)| b.ended_at).collect::<Vec<_>>(),
+            "canceled" => briefs.iter().map(|(_, b)| b.canceled).collect::<Vec<_>>(),
+        )?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::{io::SerReader, prelude::ParquetReader};
+    use upstair_type::{data::market::BinanceTradeTick, strategy::StrategyDebug};
+
+    use super::*;
+    use crate::vis_data::{MakerOrderBrief, TradeBrief};
+
+    #[test]
+    fn test_export_data_state() {
+        let mut state = DataState::default();
+        for i in 0..3 {
+            state.market_trades.push(BinanceTradeTick {
+                id: i,
+                price: 100.0 + i as f64,
+                qty: 1.0,
+                base_qty: 100.0,
+                time: i * 40 * 1000,
+                is_buyer_maker: false,
+                symbol: "BTCUSDT",
+            });
+        }
+        state.account_trades.push(TradeBrief {
+            time: 1000,
+            is_buy: true,
+            price: 100.0,
+            qty: 0.01,
+        });
+        state
+            .account_asset_history
+            .insert("BTC", vec![(0, 1.0), (1000, 1.01)]);
+        state.strategy_debug.push((
+            1000,
+            StrategyDebug {
+                vol: 0.5,
+                bid: 99.5,
+                ask: 100.5,
+                ..Default::default()
+            },
+        ));
+        state.order_briefs.insert(
+            "B0".into(),
+            MakerOrderBrief {
+                price: 100.0,
+                created_at: 500,
+                ended_at: 1000,
+                is_buy: true,
+                ..Default::default()
+            },
+        );
+
+        let dir = std::env::temp_dir().join(format!("vis_export_{}", std::process::id()));
+        export_data_state(&state, &dir).unwrap();
+        let rows = |name: &str| {
+            let file = std::fs::File::open(dir.join(name)).unwrap();
+            ParquetReader::new(file).finish().unwrap().height()
+        };
+        assert_eq!(rows("trades.parquet"), 3);
+        // trades at 0s and 40s, then 80s
+        assert_eq!(rows("candles.parquet"), 2);
+        assert_eq!(rows("account_history.parquet"), 2);
+        assert_eq!(rows("account_trades.parquet"), 1);
+        assert_eq!(rows("strategy_debug.parquet"), 1);
+        assert_eq!(rows("order_briefs.parquet"), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
 use std::{
+    net::SocketAddr,
     ops::Add,
-    sync::mpsc::{self, Sender},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
     thread::{self, JoinHandle},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
@@ -8,20 +13,124 @@ use std::{
 use account::account::{Account, AssetBalance};
 use eframe::{egui, EventLoopBuilderHook};
 use symbol_info::SymbolInfoManager;
-use upstair_type::module::{Module, ModuleBuilder, ReadTopicHandle};
+use upstair_type::account::AccountUpdate;
+use upstair_type::module::{
+    namespaced_topic, owner_filter, Module, ModuleBuilder, ModulePriority, ReadTopicHandle,
+};
+use upstair_type::run_output::RunOutput;
+use upstair_type::time::PlaybackControl;
 
-use crate::vis_data::{self, DataState, TimeInMs, TradeBrief};
+use crate::vis_data::{self, BookSnapshot, DataState, TimeInMs, TradeBrief, VariantAccount};
+use crate::vis_export::export_data_state;
+use crate::vis_web;
 use crate::{vis_app::VisApp, vis_data::DataBuffer};
 
 use tracing::{error, info};
 
-use winit::platform::windows::EventLoopBuilderExtWindows;
+const BOOK_SAMPLE_MS: TimeInMs = 1000;
+
+// Windows and X11/Wayland allow the event loop off the main thread, so the window can run
+// next to the engine
+#[cfg(target_os = "windows")]
+fn any_thread_event_loop() -> Option<EventLoopBuilderHook> {
+    use winit::platform::windows::EventLoopBuilderExtWindows;
+    Some(Box::new(|event_loop_builder| {
+        event_loop_builder.with_any_thread(true);
+    }))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn any_thread_event_loop() -> Option<EventLoopBuilderHook> {
+    use winit::platform::x11::EventLoopBuilderExtX11;
+    Some(Box::new(|event_loop_builder| {
+        event_loop_builder.with_any_thread(true);
+    }))
+}
+
+// macOS only runs an event loop on the main thread, see VisWindow
+const ANY_THREAD_EVENT_LOOP: bool = !cfg!(target_os = "macos");
+
+#[cfg(target_os = "macos")]
+fn any_thread_event_loop() -> Option<EventLoopBuilderHook> {
+    None
+}
+
+fn run_vis_app(
+    rx: Receiver<DataBuffer>,
+    playback: Option<PlaybackControl>,
+    event_loop_builder: Option<EventLoopBuilderHook>,
+) {
+    info!("Vis App Started");
+    let options = eframe::NativeOptions {
+        event_loop_builder,
+        viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
+        default_theme: eframe::Theme::Dark,
+        follow_system_theme: false,
+        centered: true,
+        ..Default::default()
+    };
+    let app_playback = playback.clone();
+
+    let result = eframe::run_native(
+        "Stepper Vis",
+        options,
+        Box::new(|cc| {
+            cc.egui_ctx.set_pixels_per_point(1.);
+            let mut app =
+                VisApp::default().with_update_data_fn(Box::new(move |state: &mut DataState| {
+                    let mut updated = false;
+                    while let Ok(buffer) = rx.try_recv() {
+                        state.update(buffer);
+                        updated = true;
+                    }
+                    updated
+                }));
+            if let Some(playback) = app_playback {
+                app = app.with_playback(playback);
+            }
+            Box::new(app)
+        }),
+    );
+    if result.is_err() {
+        error!("Error in running vis app: {:?}", result);
+    }
+    // nothing can resume the simulation once the window is closed
+    if let Some(playback) = playback {
+        playback.release();
+    }
+    info!("Vis App Terminated");
+}
+
+// The vis window run on the calling thread, which has to be the main thread on macOS. The
+// simulation runs on another thread and sends its data through the VisLink.
+pub struct VisWindow {
+    rx: Receiver<DataBuffer>,
+    playback: Option<PlaybackControl>,
+}
+
+// Connects a VisModule to a VisWindow, see VisModuleBuilder::with_window
+pub struct VisLink {
+    tx: Sender<DataBuffer>,
+}
+
+impl VisWindow {
+    pub fn new(playback: Option<PlaybackControl>) -> (VisWindow, VisLink) {
+        let (tx, rx) = mpsc::channel::<DataBuffer>();
+        (VisWindow { rx, playback }, VisLink { tx })
+    }
+
+    // blocks until the window is closed
+    pub fn run(self) {
+        run_vis_app(self.rx, self.playback, None);
+    }
+}
 
 pub struct VisModule {
     read_market_data: ReadTopicHandle,
     order_topic: ReadTopicHandle,
     order_result_topic: ReadTopicHandle,
     account_topic: ReadTopicHandle,
+    strategy_debug_topic: ReadTopicHandle,
 
     wait_for_first_message: bool,
     next_iteration_time: SystemTime,
@@ -36,55 +145,57 @@ pub struct VisModule {
     app_tx: Option<Sender<DataBuffer>>,
 
     initial_account: Account,
+    // the accounts of buffer.variants and the balances they start with
+    variant_accounts: Vec<(ReadTopicHandle, Account)>,
+
+    playback: Option<PlaybackControl>,
+    // book tickers are sampled for the heatmap, one per BOOK_SAMPLE_MS
+    next_book_sample_at: TimeInMs,
+
+    // headless, the data is written there on terminate instead of shown in a window
+    export_dir: Option<PathBuf>,
+    // headless, the data is served there instead of shown in a window
+    web_addr: Option<SocketAddr>,
+    // the data of the headless modes
+    export_state: Arc<Mutex<DataState>>,
 }
 
 impl Module for VisModule {
     fn start(&mut self) {
-        let (tx, rx) = mpsc::channel::<DataBuffer>();
-        let vis_app_join_handle = thread::spawn(move || {
-            info!("Vis App Started");
-            let event_loop_builder: Option<EventLoopBuilderHook> =
-                Some(Box::new(|event_loop_builder| {
-                    event_loop_builder.with_any_thread(true);
-                }));
-            let options = eframe::NativeOptions {
-                event_loop_builder,
-                viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
-                default_theme: eframe::Theme::Dark,
-                follow_system_theme: false,
-                centered: true,
-                ..Default::default()
-            };
-
-            let result = eframe::run_native(
-                "Stepper Vis",
-                options,
-                Box::new(|cc| {
-                    cc.egui_ctx.set_pixels_per_point(1.);
-                    let app = VisApp::default().with_update_data_fn(Box::new(
-                        move |state: &mut DataState| {
-                            let mut updated = false;
-                            while let Ok(buffer) = rx.try_recv() {
-                                state.update(buffer);
-                                updated = true;
-                            }
-                            updated
-                        },
-                    ));
-                    Box::new(app)
-                }),
-            );
-            if result.is_err() {
-                error!("Error in running vis app: {:?}", result);
+        if let Some(addr) = self.web_addr {
+            match vis_web::serve(addr, self.export_state.clone()) {
+                Ok(addr) => println!("Vis dashboard at http://{}", addr),
+                Err(e) => error!("failed to serve the vis dashboard on {}: {}", addr, e),
             }
-            info!("Vis App Terminated");
-        });
+        }
+        // headless, or the window runs on another thread already
+        if self.is_headless() || self.app_tx.is_some() {
+            return;
+        }
+        if !ANY_THREAD_EVENT_LOOP {
+            error!("the vis window can only run on the main thread here, use VisWindow");
+            return;
+        }
+        let (tx, rx) = mpsc::channel::<DataBuffer>();
+        let playback = self.playback.clone();
+        // the hook is not Send, it is made on the window thread
+        let vis_app_join_handle =
+            thread::spawn(move || run_vis_app(rx, playback, any_thread_event_loop()));
         self.vis_app_join_handle = Some(vis_app_join_handle);
         self.app_tx = tx.into();
     }
 
     fn terminate(&mut self) {
         self.vis_app_join_handle.take().map(|h| h.join());
+        if let Some(dir) = &self.export_dir {
+            let buffer = self.buffer.take();
+            let mut state = self.export_state.lock().unwrap();
+            state.update(buffer);
+            match export_data_state(&state, dir) {
+                Ok(()) => println!("Vis data exported to {}", dir.display()),
+                Err(e) => error!("failed to export vis data: {:#}", e),
+            }
+        }
     }
 
     fn sync(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) -> bool {
@@ -100,6 +211,25 @@ impl Module for VisModule {
         while let Some(msg) = comms.receive(&self.account_topic) {
             self.ingest_message(msg);
         }
+        while let Some(msg) = comms.receive(&self.strategy_debug_topic) {
+            self.ingest_message(msg);
+        }
+        for ((topic, initial_account), variant) in self
+            .variant_accounts
+            .iter()
+            .zip(self.buffer.variants.iter_mut())
+        {
+            while let Some(msg) = comms.receive(topic) {
+                if let upstair_type::Payload::AccountUpdate(update) = msg.payload {
+                    apply_account_update(
+                        &mut variant.account,
+                        &mut variant.profit_account,
+                        initial_account,
+                        &update,
+                    );
+                }
+            }
+        }
         if self.wait_for_first_message {
             self.wait_for_first_message = false;
             self.next_iteration_time = comms.time().add(Duration::from_millis(60 * 1000));
@@ -109,10 +239,13 @@ impl Module for VisModule {
     }
 
     fn one_iteration(&mut self, comms: &mut dyn upstair_type::module::ModuleComms) {
+        self.buffer.commit_at =
+            comms.time().duration_since(UNIX_EPOCH).unwrap().as_millis() as TimeInMs;
         if let Some(tx) = self.app_tx.as_ref() {
-            self.buffer.commit_at =
-                comms.time().duration_since(UNIX_EPOCH).unwrap().as_millis() as TimeInMs;
             let _ = tx.send(self.buffer.take());
+        } else if self.is_headless() {
+            let buffer = self.buffer.take();
+            self.export_state.lock().unwrap().update(buffer);
         }
         self.next_iteration_time = comms.time().add(Duration::from_millis(1000));
     }
@@ -131,23 +264,54 @@ impl Module for VisModule {
 }
 
 impl VisModule {
+    fn is_headless(&self) -> bool {
+        self.export_dir.is_some() || self.web_addr.is_some()
+    }
+
+    fn ingest_market_trade(&mut self, tick: upstair_type::data::market::BinanceTradeTick) {
+        *self
+            .buffer
+            .latest_market_price
+            .entry(
+                self.symbol_info_manager
+                    .get(tick.symbol)
+                    .unwrap()
+                    .base_asset,
+            )
+            .or_default() = tick.price;
+        self.buffer.last_price = tick.price;
+        self.buffer.market_trades.push(tick);
+    }
+
     fn ingest_message(&mut self, data: upstair_type::Message) {
         match data.payload {
-            upstair_type::Payload::BinanceTradeTick(tick) => {
-                *self
-                    .buffer
-                    .latest_market_price
-                    .entry(
-                        self.symbol_info_manager
-                            .get(tick.symbol)
-                            .unwrap()
-                            .base_asset,
-                    )
-                    .or_default() = tick.price;
-                self.buffer.last_price = tick.price;
-                self.buffer.market_trades.push(tick);
+            upstair_type::Payload::BinanceTradeTick(tick) => self.ingest_market_trade(tick),
+            upstair_type::Payload::TradeTickBatch(ticks) => {
+                ticks
+                    .into_iter()
+                    .for_each(|tick| self.ingest_market_trade(tick));
+            }
+            upstair_type::Payload::BinanceAggTrade(trade) => {
+                self.ingest_market_trade(trade.to_trade_tick())
+            }
+            // a candle is drawn from its close, the only price it has at its close time
+            upstair_type::Payload::BinanceKline(kline) => {
+                self.ingest_market_trade(upstair_type::data::market::BinanceTradeTick {
+                    id: kline.open_time,
+                    price: kline.close,
+                    qty: kline.volume,
+                    base_qty: kline.quote_volume,
+                    time: kline.close_time,
+                    is_buyer_maker: kline.close < kline.open,
+                    symbol: kline.symbol,
+                })
+            }
+            upstair_type::Payload::OrderRequest(req) => {
+                self.buffer.order_count += 1;
+                self.buffer
+                    .order_quantities
+                    .push((req.client_order_id, req.quantity));
             }
-            upstair_type::Payload::OrderRequest(_) => self.buffer.order_count += 1,
             upstair_type::Payload::OrderResult(order_result) => {
                 if order_result.status == upstair_type::order::OrderStatus::Filled
                     || order_result.status == upstair_type::order::OrderStatus::PartiallyFilled
@@ -168,37 +332,105 @@ impl VisModule {
             upstair_type::Payload::CancelOrderRequest(_) => {
                 self.buffer.order_cancel_count += 1;
             }
-            upstair_type::Payload::AccountUpdate(account) => {
-                for (asset, update) in account.updates.iter() {
-                    let b = self
-                        .buffer
-                        .account
-                        .asset_to_balance
-                        .entry(asset)
-                        .or_default();
-                    b.balance = update.balance;
-                    b.locked = update.locked;
-
-                    let profit_balance = self
-                        .buffer
-                        .profit_account
-                        .asset_to_balance
-                        .entry(&asset)
-                        .or_default();
-                    let inital_balance = self
-                        .initial_account
-                        .asset_to_balance
-                        .get(asset)
-                        .map(|b| b.balance)
-                        .unwrap_or(0.);
-                    profit_balance.balance = b.balance - inital_balance;
+            // the requests and results of a batch are drawn one by one
+            upstair_type::Payload::BatchOrderRequest(batch) => {
+                for req in batch.orders {
+                    self.ingest_message(upstair_type::Message {
+                        header: data.header.clone(),
+                        payload: upstair_type::Payload::OrderRequest(req),
+                    });
                 }
             }
-            upstair_type::Payload::BinanceBookTicker(_) => {}
+            upstair_type::Payload::BatchCancelRequest(batch) => {
+                self.buffer.order_cancel_count += batch.cancels.len() as i64;
+            }
+            upstair_type::Payload::OrderResultBatch(batch) => {
+                for result in batch.results {
+                    self.ingest_message(upstair_type::Message {
+                        header: data.header.clone(),
+                        payload: upstair_type::Payload::OrderResult(result),
+                    });
+                }
+            }
+            upstair_type::Payload::AccountUpdate(account) => apply_account_update(
+                &mut self.buffer.account,
+                &mut self.buffer.profit_account,
+                &self.initial_account,
+                &account,
+            ),
+            upstair_type::Payload::BinanceBookTicker(ticker) => {
+                let time = data
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as TimeInMs;
+                if time >= self.next_book_sample_at {
+                    self.next_book_sample_at = time - time % BOOK_SAMPLE_MS + BOOK_SAMPLE_MS;
+                    self.buffer.book_snapshots.push(BookSnapshot {
+                        time,
+                        bids: vec![(ticker.best_bid_price, ticker.best_bid_qty)],
+                        asks: vec![(ticker.best_ask_price, ticker.best_ask_qty)],
+                    });
+                }
+            }
+            upstair_type::Payload::CancelReject(_) => {}
+            upstair_type::Payload::RateLimitUsage(_) => {}
+            upstair_type::Payload::TradingHalt(_) => {}
+            upstair_type::Payload::StaleOrderReport(_) => {}
+            upstair_type::Payload::ResyncRequest(_) => {}
+            upstair_type::Payload::StrategyDebug(debug) => {
+                let time = data
+                    .header
+                    .commit_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as TimeInMs;
+                self.buffer.strategy_debug.push((time, debug));
+            }
+            upstair_type::Payload::DayRoll(_) => {}
+            upstair_type::Payload::DataQuality(_) => {}
+            upstair_type::Payload::ResyncSnapshot(_) => {}
+            upstair_type::Payload::SignalUpdate(_) => {}
+            upstair_type::Payload::MarkPrice(_) => {}
+            upstair_type::Payload::EquitySnapshot(_) => {}
+            upstair_type::Payload::DebugLog(_) => {}
         }
     }
 }
 
+fn insert_balance(account: &mut Account, asset: &'static str, balance: f64) {
+    account.asset_to_balance.insert(
+        asset,
+        AssetBalance {
+            balance,
+            locked: 0.,
+        },
+    );
+}
+
+// the balances of the update and their change from the initial ones
+fn apply_account_update(
+    account: &mut Account,
+    profit_account: &mut Account,
+    initial_account: &Account,
+    update: &AccountUpdate,
+) {
+    for (asset, update) in update.updates.iter() {
+        let b = account.asset_to_balance.entry(asset).or_default();
+        b.balance = update.balance;
+        b.locked = update.locked;
+
+        let profit_balance = profit_account.asset_to_balance.entry(asset).or_default();
+        let inital_balance = initial_account
+            .asset_to_balance
+            .get(asset)
+            .map(|b| b.balance)
+            .unwrap_or(0.);
+        profit_balance.balance = b.balance - inital_balance;
+    }
+}
+
 #[derive(Default)]
 pub struct VisModuleBuilder {
     market_data_topic: Option<ReadTopicHandle>,
@@ -206,7 +438,17 @@ pub struct VisModuleBuilder {
     order_result_topic: Option<ReadTopicHandle>,
     symbol_info_manager: Option<SymbolInfoManager>,
     account_topic: Option<ReadTopicHandle>,
+    strategy_debug_topic: Option<ReadTopicHandle>,
+    // the namespaces of the strategies compared with the default one, the balances their
+    // accounts start with, and their accounts
+    variants: Vec<(&'static str, Account)>,
+    variant_account_topics: Vec<ReadTopicHandle>,
     initial_account: Account,
+    playback: Option<PlaybackControl>,
+    export: bool,
+    export_dir: Option<PathBuf>,
+    web_addr: Option<SocketAddr>,
+    window: Option<VisLink>,
 }
 
 impl VisModuleBuilder {
@@ -216,13 +458,48 @@ impl VisModuleBuilder {
     }
 
     pub fn with_initial_balance(mut self, asset: &'static str, balance: f64) -> Self {
-        self.initial_account.asset_to_balance.insert(
-            asset,
-            AssetBalance {
-                balance,
-                locked: 0.,
-            },
-        );
+        insert_balance(&mut self.initial_account, asset, balance);
+        self
+    }
+
+    // lets the window pause, step, throttle and fast-forward the simulation
+    pub fn with_playback(mut self, playback: PlaybackControl) -> Self {
+        self.playback = Some(playback);
+        self
+    }
+
+    // sends the data to a window run elsewhere instead of opening one on start
+    pub fn with_window(mut self, link: VisLink) -> Self {
+        self.window = Some(link);
+        self
+    }
+
+    // runs without the window and writes the plotted data to Parquet files in vis of the run
+    // output on terminate
+    pub fn with_export(mut self) -> Self {
+        self.export = true;
+        self
+    }
+
+    // plots the equity and profit of the strategy run in the namespace next to the default
+    // one, as EquityUSDT@<namespace> and ProfitUSDT@<namespace>, its profit taken from the
+    // (asset, balance) its account starts with
+    pub fn with_variant(
+        mut self,
+        namespace: &'static str,
+        initial_balances: &[(&'static str, f64)],
+    ) -> Self {
+        let mut initial_account = Account::default();
+        for (asset, balance) in initial_balances {
+            insert_balance(&mut initial_account, asset, *balance);
+        }
+        self.variants.push((namespace, initial_account));
+        self
+    }
+
+    // runs without the window and serves the plotted data on addr, see vis_web
+    pub fn with_web(mut self, addr: SocketAddr) -> Self {
+        self.web_addr = Some(addr);
         self
     }
 }
@@ -232,19 +509,47 @@ impl ModuleBuilder for VisModuleBuilder {
         "vis"
     }
 
+    fn priority(&self) -> ModulePriority {
+        ModulePriority::Observer
+    }
+
+    fn init_output(&mut self, output: &RunOutput) {
+        if self.export {
+            self.export_dir = Some(output.subdir("vis"));
+        }
+    }
+
     fn init_comm(&mut self, comms: &mut dyn upstair_type::module::ModuleCommsBuilder) {
         let market_data_topic = comms.get_topic("market_data");
         let order_topic = comms.get_topic("order");
         let order_result_topic = comms.get_topic("order_result");
         let account_topic = comms.get_topic("account");
+        let strategy_debug_topic = comms.get_topic("strategy_debug");
 
         self.market_data_topic = comms.subscribe_topic(&market_data_topic).into();
-        self.order_topic = comms.subscribe_topic(&order_topic).into();
-        self.order_result_topic = comms.subscribe_topic(&order_result_topic).into();
-        self.account_topic = comms.subscribe_topic(&account_topic).into();
+        // the orders and account without an owner
+        self.order_topic = comms
+            .subscribe_topic_filtered(&order_topic, owner_filter(None))
+            .into();
+        self.order_result_topic = comms
+            .subscribe_topic_filtered(&order_result_topic, owner_filter(None))
+            .into();
+        self.account_topic = comms
+            .subscribe_topic_filtered(&account_topic, owner_filter(None))
+            .into();
+        self.strategy_debug_topic = comms.subscribe_topic(&strategy_debug_topic).into();
+        for (namespace, _) in &self.variants {
+            let topic = comms.get_topic(&namespaced_topic("account", namespace));
+            self.variant_account_topics
+                .push(comms.subscribe_topic(&topic));
+        }
     }
 
     fn build(self: Box<VisModuleBuilder>) -> Box<dyn Module> {
+        assert!(
+            !self.export || self.export_dir.is_some(),
+            "the vis export needs the run output of the engine"
+        );
         Box::new(VisModule {
             read_market_data: self.market_data_topic.unwrap(),
             order_topic: self.order_topic.unwrap(),
@@ -252,11 +557,33 @@ impl ModuleBuilder for VisModuleBuilder {
             wait_for_first_message: true,
             next_iteration_time: SystemTime::UNIX_EPOCH,
             symbol_info_manager: self.symbol_info_manager.unwrap(),
-            buffer: DataBuffer::default(),
+            buffer: DataBuffer {
+                variants: self
+                    .variants
+                    .iter()
+                    .map(|(namespace, _)| VariantAccount {
+                        equity_series: format!("EquityUSDT@{}", namespace).leak(),
+                        profit_series: format!("ProfitUSDT@{}", namespace).leak(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            variant_accounts: self
+                .variant_account_topics
+                .into_iter()
+                .zip(self.variants.into_iter().map(|(_, account)| account))
+                .collect(),
             vis_app_join_handle: None,
-            app_tx: None,
+            app_tx: self.window.map(|link| link.tx),
             account_topic: self.account_topic.unwrap(),
+            strategy_debug_topic: self.strategy_debug_topic.unwrap(),
             initial_account: self.initial_account,
+            playback: self.playback,
+            next_book_sample_at: 0,
+            export_dir: self.export_dir,
+            web_addr: self.web_addr,
+            export_state: Arc::default(),
         })
     }
 }
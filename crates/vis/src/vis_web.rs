@@ -0,0 +1,233 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+use util::http::{serve_get, HttpResponse};
+
+use crate::vis_data::{compute_candles_from_market_trades, DataState, TimeInMs};
+
+const DASHBOARD_HTML: &str = include_str!("vis_web.html");
+const DEFAULT_CANDLE_PERIOD_MS: TimeInMs = 60 * 1000;
+const MIN_CANDLE_PERIOD_MS: TimeInMs = 1000;
+
+// Serves the data state on addr from a background thread: a dashboard page on / and the
+// JSON API below /api, which Grafana reads through its JSON datasource. Returns the bound
+// address, port 0 picks a free one.
+pub fn serve(addr: SocketAddr, state: Arc<Mutex<DataState>>) -> std::io::Result<SocketAddr> {
+    serve_get("vis web", addr, move |target| {
+        respond(&state.lock().unwrap(), target)
+    })
+}
+
+// the value of name in the query string of target
+fn query_param<'a>(target: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = target.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+// the answer to a GET of target
+fn respond(state: &DataState, target: &str) -> HttpResponse {
+    let path = target.split('?').next().unwrap_or_default();
+    // the times are ms since the epoch, everything from since on is returned
+    let since = query_param(target, "since")
+        .and_then(|s| s.parse::<TimeInMs>().ok())
+        .unwrap_or(0);
+    let json = match path {
+        "/" => return ("200 OK", "text/html; charset=utf-8", DASHBOARD_HTML.into()),
+        "/api/summary" => summary(state),
+        "/api/candles" => {
+            let period_ms = query_param(target, "period_ms")
+                .and_then(|s| s.parse::<TimeInMs>().ok())
+                .unwrap_or(DEFAULT_CANDLE_PERIOD_MS)
+                .max(MIN_CANDLE_PERIOD_MS);
+            candles(state, since, period_ms)
+        }
+        "/api/account" => account_history(state, since),
+        "/api/fills" => {
+            Value::from_iter(state.account_trades.iter().filter(|t| t.time >= since).map(
+                |t| json!({"time": t.time, "is_buy": t.is_buy, "price": t.price, "qty": t.qty}),
+            ))
+        }
+        "/api/strategy" => Value::from_iter(
+            state
+                .strategy_debug
+                .iter()
+                .filter(|(t, _)| *t >= since)
+                .map(|(t, d)| {
+                    json!({
+                        "time": t,
+                        "vol": d.vol,
+                        "fair_price": d.fair_price,
+                        "reservation_price": d.reservation_price,
+                        "quoted_spread": d.quoted_spread(),
+                        "market_spread": d.market_spread(),
+                        "base_balance": d.base_balance,
+                    })
+                }),
+        ),
+        _ => {
+            return (
+                "404 Not Found",
+                "text/plain",
+                format!("no such path {path}"),
+            )
+        }
+    };
+    ("200 OK", "application/json", json.to_string())
+}
+
+fn last_value(state: &DataState, asset: &str) -> Option<f64> {
+    state
+        .account_asset_history
+        .get(asset)
+        .and_then(|history| history.last())
+        .map(|(_, v)| *v)
+}
+
+fn summary(state: &DataState) -> Value {
+    let open_orders = state
+        .order_briefs
+        .values()
+        .filter(|b| b.ended_at == 0)
+        .count();
+    let balances: serde_json::Map<_, _> = state
+        .account_asset_history
+        .keys()
+        .filter(|asset| !asset.starts_with("EquityUSDT") && !asset.starts_with("ProfitUSDT"))
+        .map(|asset| (asset.to_string(), json!(last_value(state, asset))))
+        .collect();
+    json!({
+        "updated_at": state.updated_at,
+        "last_price": state.market_trades.last().map(|t| t.price),
+        "trades": state.market_trades.len(),
+        "fills": state.account_trades.len(),
+        "orders": state.order_briefs.len(),
+        "open_orders": open_orders,
+        "equity": last_value(state, "EquityUSDT"),
+        "profit": last_value(state, "ProfitUSDT"),
+        "balances": balances,
+    })
+}
+
+fn candles(state: &DataState, since: TimeInMs, period_ms: TimeInMs) -> Value {
+    let trades = &state.market_trades;
+    let first_time = since.max(trades.first().map_or(0, |t| t.time));
+    let first_time = first_time - first_time % period_ms;
+    // trades are in time order, skip the old ones without walking them
+    let start = trades.partition_point(|t| t.time < first_time);
+    Value::from_iter(
+        compute_candles_from_market_trades(&trades[start..], first_time, period_ms).map(
+            |(t, c)| {
+                json!({
+                    "time": t,
+                    "open": c.open,
+                    "high": c.high,
+                    "low": c.low,
+                    "close": c.close,
+                    "volume": c.volume,
+                })
+            },
+        ),
+    )
+}
+
+// one row per asset and time like account_history.parquet, EquityUSDT and ProfitUSDT included
+fn account_history(state: &DataState, since: TimeInMs) -> Value {
+    let mut history: Vec<_> = state
+        .account_asset_history
+        .iter()
+        .flat_map(|(asset, history)| history.iter().map(move |(t, v)| (*asset, *t, *v)))
+        .filter(|(_, t, _)| *t >= since)
+        .collect();
+    history.sort_by_key(|(asset, t, _)| (*t, *asset));
+    Value::from_iter(
+        history
+            .into_iter()
+            .map(|(asset, t, v)| json!({"time": t, "asset": asset, "balance": v})),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use upstair_type::data::market::BinanceTradeTick;
+
+    use super::*;
+    use crate::vis_data::TradeBrief;
+
+    fn get(addr: SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn body(response: &str) -> Value {
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn test_web_api() {
+        let mut state = DataState::default();
+        for i in 0..3 {
+            state.market_trades.push(BinanceTradeTick {
+                id: i,
+                price: 100.0 + i as f64,
+                qty: 1.0,
+                base_qty: 100.0,
+                time: i * 40 * 1000,
+                is_buyer_maker: false,
+                symbol: "BTCUSDT",
+            });
+        }
+        state.account_trades.push(TradeBrief {
+            time: 70 * 1000,
+            is_buy: true,
+            price: 101.0,
+            qty: 0.01,
+        });
+        state
+            .account_asset_history
+            .insert("EquityUSDT", vec![(60 * 1000, 200.0), (120 * 1000, 201.0)]);
+        state.updated_at = 120 * 1000;
+
+        let state = Arc::new(Mutex::new(state));
+        let addr = serve("127.0.0.1:0".parse().unwrap(), state.clone()).unwrap();
+        assert!(get(addr, "/").contains("text/html"));
+        assert!(get(addr, "/nothing").starts_with("HTTP/1.1 404"));
+
+        let summary = body(&get(addr, "/api/summary"));
+        assert_eq!(summary["last_price"], 102.0);
+        assert_eq!(summary["equity"], 201.0);
+        assert_eq!(summary["fills"], 1);
+
+        // trades at 0s and 40s, then 80s
+        assert_eq!(
+            body(&get(addr, "/api/candles")).as_array().unwrap().len(),
+            2
+        );
+        let candles = body(&get(addr, "/api/candles?since=60000&period_ms=20000"));
+        assert_eq!(candles[0]["time"], 80 * 1000);
+        assert_eq!(candles.as_array().unwrap().len(), 1);
+        assert_eq!(body(&get(addr, "/api/fills?since=70001")), json!([]));
+        assert_eq!(
+            body(&get(addr, "/api/account?since=100000"))[0]["balance"],
+            201.0
+        );
+
+        // the state keeps changing while served
+        state.lock().unwrap().account_trades.clear();
+        assert_eq!(body(&get(addr, "/api/fills")), json!([]));
+    }
+}